@@ -0,0 +1,177 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use tracing::{Level, event};
+
+use super::AudioSink;
+
+// Output rate of the SPU
+const SAMPLE_RATE: u32 = 44100;
+// Stereo frames the ring holds, about a fifth of a second
+const RING_CAPACITY: usize = 8192;
+
+// Ring of stereo frames shared by the emulator and the audio callback without locking.
+// Each frame is packed into one atomic so a frame is never torn
+pub struct AudioRing {
+    frames: Box<[AtomicU32]>,
+    read: AtomicUsize,
+    write: AtomicUsize,
+}
+
+impl AudioRing {
+    pub fn new() -> Self {
+        Self {
+            frames: (0..RING_CAPACITY).map(|_| AtomicU32::new(0)).collect(),
+            read: AtomicUsize::new(0),
+            write: AtomicUsize::new(0),
+        }
+    }
+
+    // Called by the emulator. When the ring is full the oldest frame is dropped
+    pub fn push(&self, left: i16, right: i16) {
+        let write = self.write.load(Ordering::Relaxed);
+        let read = self.read.load(Ordering::Acquire);
+        if write.wrapping_sub(read) >= RING_CAPACITY {
+            // Fails if the callback took the frame first, which frees the slot just the same
+            let _ = self.read.compare_exchange(
+                read,
+                read.wrapping_add(1),
+                Ordering::AcqRel,
+                Ordering::Relaxed,
+            );
+        }
+
+        let frame = (left as u16 as u32) | ((right as u16 as u32) << 16);
+        self.frames[write % RING_CAPACITY].store(frame, Ordering::Relaxed);
+        self.write.store(write.wrapping_add(1), Ordering::Release);
+    }
+
+    // Called by the audio callback. Returns None on underrun
+    pub fn pop(&self) -> Option<(i16, i16)> {
+        loop {
+            let read = self.read.load(Ordering::Acquire);
+            if read == self.write.load(Ordering::Acquire) {
+                return None;
+            }
+
+            let frame = self.frames[read % RING_CAPACITY].load(Ordering::Relaxed);
+            // Retry if the emulator dropped this frame while it was being read
+            if self
+                .read
+                .compare_exchange(
+                    read,
+                    read.wrapping_add(1),
+                    Ordering::AcqRel,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+            {
+                return Some((frame as i16, (frame >> 16) as i16));
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        let read = self.read.load(Ordering::Acquire);
+        self.write.load(Ordering::Acquire).wrapping_sub(read)
+    }
+}
+
+// State shared with the audio callback
+struct Shared {
+    ring: AudioRing,
+    // f32 bits of the output volume
+    volume: AtomicU32,
+    paused: AtomicBool,
+}
+
+impl Shared {
+    fn new() -> Self {
+        Self {
+            ring: AudioRing::new(),
+            volume: AtomicU32::new(1.0f32.to_bits()),
+            paused: AtomicBool::new(false),
+        }
+    }
+
+    // Fills interleaved output frames. Underruns and pauses play silence
+    fn fill(&self, data: &mut [f32], channels: usize) {
+        let volume = f32::from_bits(self.volume.load(Ordering::Relaxed));
+        let paused = self.paused.load(Ordering::Relaxed);
+        for frame in data.chunks_mut(channels) {
+            let (left, right) = if paused {
+                (0, 0)
+            } else {
+                self.ring.pop().unwrap_or((0, 0))
+            };
+            let left = left as f32 / 32768.0 * volume;
+            let right = right as f32 / 32768.0 * volume;
+            for (i, sample) in frame.iter_mut().enumerate() {
+                *sample = if i % 2 == 0 { left } else { right };
+            }
+        }
+    }
+}
+
+pub struct CpalSink {
+    shared: Arc<Shared>,
+    // Output stops when the stream is dropped
+    _stream: cpal::Stream,
+}
+
+impl CpalSink {
+    pub fn new() -> Result<Self, String> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or("no output device")?;
+        let config = cpal::StreamConfig {
+            channels: 2,
+            sample_rate: SAMPLE_RATE,
+            buffer_size: cpal::BufferSize::Default,
+        };
+
+        let shared = Arc::new(Shared::new());
+        let callback_shared = shared.clone();
+        let stream = device
+            .build_output_stream(
+                &config,
+                move |data: &mut [f32], _| callback_shared.fill(data, 2),
+                |err| event!(target: "ps1_emulator::Audio", Level::WARN, "Audio stream error: {err}"),
+                None,
+            )
+            .map_err(|err| err.to_string())?;
+        stream.play().map_err(|err| err.to_string())?;
+
+        Ok(Self {
+            shared,
+            _stream: stream,
+        })
+    }
+}
+
+impl AudioSink for CpalSink {
+    fn push_samples(&mut self, samples: &[i16]) {
+        for frame in samples.chunks_exact(2) {
+            self.shared.ring.push(frame[0], frame[1]);
+        }
+    }
+
+    fn set_volume(&mut self, volume: f32) {
+        self.shared
+            .volume
+            .store(volume.to_bits(), Ordering::Relaxed);
+    }
+
+    fn set_paused(&mut self, paused: bool) {
+        self.shared.paused.store(paused, Ordering::Relaxed);
+    }
+
+    fn buffered(&self) -> Option<usize> {
+        Some(self.shared.ring.len())
+    }
+
+    fn clear(&mut self) {
+        while self.shared.ring.pop().is_some() {}
+    }
+}
@@ -0,0 +1,48 @@
+#[cfg(feature = "audio")]
+mod cpal_sink;
+
+#[cfg(feature = "audio")]
+pub use cpal_sink::CpalSink;
+#[cfg(feature = "audio")]
+use tracing::{Level, event};
+
+// Destination for the SPU output. Headless builds and machines without an audio device
+// use NullSink
+pub trait AudioSink: Send {
+    // Interleaved stereo samples at 44.1kHz
+    fn push_samples(&mut self, samples: &[i16]);
+    fn set_volume(&mut self, volume: f32);
+    // Paused output plays silence
+    fn set_paused(&mut self, paused: bool);
+    // Stereo frames waiting to be played. None without an audio device
+    fn buffered(&self) -> Option<usize>;
+    // Drops buffered samples, such as when a save state replaces the sound being played
+    fn clear(&mut self);
+}
+
+pub struct NullSink;
+
+impl AudioSink for NullSink {
+    fn push_samples(&mut self, _samples: &[i16]) {}
+
+    fn set_volume(&mut self, _volume: f32) {}
+
+    fn set_paused(&mut self, _paused: bool) {}
+
+    fn buffered(&self) -> Option<usize> {
+        None
+    }
+
+    fn clear(&mut self) {}
+}
+
+// Opens the default output device, falling back to no audio
+pub fn open_output() -> Box<dyn AudioSink> {
+    #[cfg(feature = "audio")]
+    match CpalSink::new() {
+        Ok(sink) => return Box::new(sink),
+        Err(err) => event!(target: "ps1_emulator::Audio", Level::WARN, "Audio disabled: {err}"),
+    }
+
+    Box::new(NullSink)
+}
@@ -0,0 +1,173 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const BIOS_SIZE: usize = 0x80000;
+// Kernel build date in BCD as YYYYMMDD
+const DATE_OFFSET: usize = 0x100;
+// Kernel maker string, such as "CEX-3000 KT-3  by K.S"
+const KERNEL_OFFSET: usize = 0x108;
+// Shell version string of retail BIOSes, such as "System ROM Version 4.1 12/16/97 E"
+const VERSION_OFFSET: usize = 0x7FF32;
+
+pub struct Bios {
+    pub image: Vec<u8>,
+    // Shell version, or the kernel maker on BIOSes without one
+    pub version: String,
+    // Kernel build date as YYYY-MM-DD
+    pub date: String,
+}
+
+impl Bios {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Self::new(fs::read(path)?)
+    }
+
+    // A BIOS is recognised by its size and the kernel build date every Sony BIOS carries
+    pub fn new(image: Vec<u8>) -> io::Result<Self> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        if image.len() != BIOS_SIZE {
+            return Err(invalid(format!(
+                "BIOS image is {} bytes, expected {BIOS_SIZE}",
+                image.len()
+            )));
+        }
+
+        let date = u32::from_le_bytes(image[DATE_OFFSET..DATE_OFFSET + 4].try_into().unwrap());
+        let digits: Vec<u32> = (0..8).rev().map(|i| (date >> (i * 4)) & 0xF).collect();
+        let number = |digits: &[u32]| digits.iter().fold(0, |acc, d| acc * 10 + d);
+        let (year, month, day) = (
+            number(&digits[..4]),
+            number(&digits[4..6]),
+            number(&digits[6..]),
+        );
+        if digits.iter().any(|d| *d > 9)
+            || !(1990..2100).contains(&year)
+            || !(1..=12).contains(&month)
+            || !(1..=31).contains(&day)
+        {
+            return Err(invalid(format!(
+                "no BIOS build date found, read {date:08X}"
+            )));
+        }
+
+        let version = ascii_string(&image[VERSION_OFFSET..])
+            .filter(|version| version.starts_with("System ROM Version"))
+            .or_else(|| ascii_string(&image[KERNEL_OFFSET..]))
+            .unwrap_or_else(|| "Unknown version".to_string());
+
+        Ok(Self {
+            image,
+            version,
+            date: format!("{year:04}-{month:02}-{day:02}"),
+        })
+    }
+}
+
+// The first valid BIOS in a folder, by file name
+pub fn find(dir: &Path) -> io::Result<(PathBuf, Bios)> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.is_file())
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        if let Ok(bios) = Bios::open(&path) {
+            return Ok((path, bios));
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no valid BIOS in {}", dir.display()),
+    ))
+}
+
+// Printable ASCII up to the first NUL
+fn ascii_string(bytes: &[u8]) -> Option<String> {
+    let end = bytes.iter().take(64).position(|b| *b == 0)?;
+    let text = &bytes[..end];
+    if text.is_empty() || !text.iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+        return None;
+    }
+    Some(String::from_utf8_lossy(text).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Image with a build date of 1997-12-16 and optionally the version strings
+    fn image(kernel: Option<&str>, version: Option<&str>) -> Vec<u8> {
+        let mut image = vec![0; BIOS_SIZE];
+        image[DATE_OFFSET..DATE_OFFSET + 4].copy_from_slice(&0x19971216u32.to_le_bytes());
+        if let Some(kernel) = kernel {
+            image[KERNEL_OFFSET..KERNEL_OFFSET + kernel.len()].copy_from_slice(kernel.as_bytes());
+        }
+        if let Some(version) = version {
+            image[VERSION_OFFSET..VERSION_OFFSET + version.len()]
+                .copy_from_slice(version.as_bytes());
+        }
+        image
+    }
+
+    #[test]
+    fn version_and_date_come_from_the_image() {
+        let bios = Bios::new(image(
+            Some("CEX-3000 KT-3  by K.S"),
+            Some("System ROM Version 4.1 12/16/97 E"),
+        ))
+        .unwrap();
+        assert_eq!(bios.version, "System ROM Version 4.1 12/16/97 E");
+        assert_eq!(bios.date, "1997-12-16");
+        assert_eq!(bios.image.len(), BIOS_SIZE);
+
+        // Early BIOSes only have the kernel maker
+        let bios = Bios::new(image(Some("CEX-1000 KT-3  by S.O"), None)).unwrap();
+        assert_eq!(bios.version, "CEX-1000 KT-3  by S.O");
+        let bios = Bios::new(image(None, Some("Not a version"))).unwrap();
+        assert_eq!(bios.version, "Unknown version");
+    }
+
+    #[test]
+    fn truncated_and_corrupted_images_are_rejected() {
+        let mut truncated = image(None, None);
+        truncated.pop();
+        let mut padded = image(None, None);
+        padded.push(0);
+        let mut images = vec![truncated, padded, vec![]];
+        // Dates that aren't BCD or are out of range
+        for date in [
+            0xFFFFFFFFu32,
+            0x19971316,
+            0x19971200,
+            0x19891216,
+            0x1997121A,
+        ] {
+            let mut image = image(None, None);
+            image[DATE_OFFSET..DATE_OFFSET + 4].copy_from_slice(&date.to_le_bytes());
+            images.push(image);
+        }
+        for image in images {
+            let err = Bios::new(image).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn find_skips_files_that_arent_a_bios() {
+        let dir = std::env::temp_dir().join(format!("ps1_emulator_bios_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        assert_eq!(find(&dir).err().unwrap().kind(), io::ErrorKind::NotFound);
+
+        fs::write(dir.join("a.txt"), "readme").unwrap();
+        fs::write(dir.join("b.bin"), &image(None, None)[..0x40000]).unwrap();
+        fs::write(dir.join("c.bin"), image(Some("CEX-3000"), None)).unwrap();
+        fs::write(dir.join("d.bin"), image(Some("CEX-7000"), None)).unwrap();
+        let (path, bios) = find(&dir).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(path, dir.join("c.bin"));
+        assert_eq!(bios.version, "CEX-3000");
+    }
+}
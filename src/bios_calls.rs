@@ -0,0 +1,447 @@
+use serde::{Deserialize, Serialize};
+
+use crate::bus::Bus;
+
+// Longest string read from guest memory for a log line
+const MAX_STRING: usize = 256;
+
+// How much of the BIOS call log is kept
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum BiosLog {
+    Off,
+    // Leaves out the calls made many times a frame, such as putchar and TestEvent
+    Calls,
+    All,
+}
+
+impl BiosLog {
+    pub fn label(self) -> &'static str {
+        match self {
+            BiosLog::Off => "Off",
+            BiosLog::Calls => "Calls",
+            BiosLog::All => "All calls",
+        }
+    }
+}
+
+// Names of the functions behind the A0, B0 and C0 vectors, by the number in $t1. Empty names
+// are dummies that return 0 or report a system error
+#[rustfmt::skip]
+const A_FUNCTIONS: [&str; 0xB5] = [
+    // 00
+    "FileOpen", "FileSeek", "FileRead", "FileWrite", "FileClose", "FileIoctl", "exit",
+    "FileGetDeviceFlag", "FileGetc", "FilePutc", "todigit", "atof", "strtoul", "strtol", "abs",
+    "labs",
+    // 10
+    "atoi", "atol", "atob", "SaveState", "RestoreState", "strcat", "strncat", "strcmp",
+    "strncmp", "strcpy", "strncpy", "strlen", "index", "rindex", "strchr", "strrchr",
+    // 20
+    "strpbrk", "strspn", "strcspn", "strtok", "strstr", "toupper", "tolower", "bcopy", "bzero",
+    "bcmp", "memcpy", "memset", "memmove", "memcmp", "memchr", "rand",
+    // 30
+    "srand", "qsort", "strtod", "malloc", "free", "lsearch", "bsearch", "calloc", "realloc",
+    "InitHeap", "SystemErrorExit", "std_in_getchar", "std_out_putchar", "std_in_gets",
+    "std_out_puts", "printf",
+    // 40
+    "SystemErrorUnresolvedException", "LoadExeHeader", "LoadExeFile", "DoExecute", "FlushCache",
+    "init_a0_b0_c0_vectors", "GPU_dw", "gpu_send_dma", "SendGP1Command", "GPU_cw", "GPU_cwp",
+    "send_gpu_linked_list", "gpu_abort_dma", "GetGPUStatus", "gpu_sync", "",
+    // 50
+    "", "LoadAndExecute", "GetSysSp", "", "CdInit", "_bu_init", "CdRemove", "", "", "", "",
+    "dev_tty_init", "dev_tty_open", "dev_tty_in_out", "dev_tty_ioctl", "dev_cd_open",
+    // 60
+    "dev_cd_read", "dev_cd_close", "dev_cd_firstfile", "dev_cd_nextfile", "dev_cd_chdir",
+    "dev_card_open", "dev_card_read", "dev_card_write", "dev_card_close", "dev_card_firstfile",
+    "dev_card_nextfile", "dev_card_erase", "dev_card_undelete", "dev_card_format",
+    "dev_card_rename", "card_clear_error",
+    // 70
+    "_bu_init", "CdInit", "CdRemove", "", "", "", "", "", "CdAsyncSeekL", "", "", "",
+    "CdAsyncGetStatus", "", "CdAsyncReadSector", "",
+    // 80
+    "", "CdAsyncSetMode", "", "", "", "", "", "", "", "", "", "", "", "", "", "",
+    // 90
+    "CdromIoIrqFunc1", "CdromDmaIrqFunc1", "CdromIoIrqFunc2", "CdromDmaIrqFunc2",
+    "CdromGetInt5errCode", "CdInitSubFunc", "AddCDROMDevice", "AddMemCardDevice",
+    "AddDuartTtyDevice", "AddDummyTtyDevice", "", "", "SetConf", "GetConf",
+    "SetCdromIrqAutoAbort", "SetMemSize",
+    // A0
+    "WarmBoot", "SystemErrorBootOrDiskFailure", "EnqueueCdIntr", "DequeueCdIntr", "CdGetLbn",
+    "CdReadSector", "CdGetStatus", "bu_callback_okay", "bu_callback_err_write",
+    "bu_callback_err_busy", "bu_callback_err_eject", "_card_info", "_card_async_load_directory",
+    "set_card_auto_format", "bu_callback_err_prev_write", "card_write_test",
+    // B0
+    "", "", "ioabort_raw", "", "GetSystemInfo",
+];
+
+#[rustfmt::skip]
+const B_FUNCTIONS: [&str; 0x5E] = [
+    // 00
+    "alloc_kernel_memory", "free_kernel_memory", "init_timer", "get_timer", "enable_timer_irq",
+    "disable_timer_irq", "restart_timer", "DeliverEvent", "OpenEvent", "CloseEvent",
+    "WaitEvent", "TestEvent", "EnableEvent", "DisableEvent", "OpenThread", "CloseThread",
+    // 10
+    "ChangeThread", "", "InitPad", "StartPad", "StopPad", "OutdatedPadInitAndStart",
+    "OutdatedPadGetButtons", "ReturnFromException", "SetDefaultExitFromException",
+    "SetCustomExitFromException", "", "", "", "", "", "",
+    // 20
+    "UnDeliverEvent", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "",
+    // 30
+    "", "", "FileOpen", "FileSeek", "FileRead", "FileWrite", "FileClose", "FileIoctl", "exit",
+    "FileGetDeviceFlag", "FileGetc", "FilePutc", "std_in_getchar", "std_out_putchar",
+    "std_in_gets", "std_out_puts",
+    // 40
+    "chdir", "FormatDevice", "firstfile", "nextfile", "FileRename", "FileDelete",
+    "FileUndelete", "AddDevice", "RemoveDevice", "PrintInstalledDevices", "InitCard",
+    "StartCard", "StopCard", "_card_info_subfunc", "write_card_sector", "read_card_sector",
+    // 50
+    "allow_new_card", "Krom2RawAdd", "", "Krom2Offset", "GetLastError", "GetLastFileError",
+    "GetC0Table", "GetB0Table", "get_bu_callback_port", "testdevice", "", "ChangeClearPad",
+    "get_card_status", "wait_card_status",
+];
+
+#[rustfmt::skip]
+const C_FUNCTIONS: [&str; 0x1E] = [
+    // 00
+    "EnqueueTimerAndVblankIrqs", "EnqueueSyscallHandler", "SysEnqIntRP", "SysDeqIntRP",
+    "get_free_EvCB_slot", "get_free_TCB_slot", "ExceptionHandler", "InstallExceptionHandlers",
+    "SysInitMemory", "SysInitKernelVariables", "ChangeClearRCnt", "", "InitDefInt",
+    "SetIrqAutoAck", "", "dev_sio_open",
+    // 10
+    "dev_sio_in_out", "dev_sio_ioctl", "InstallDevices", "FlushStdInOutPut", "",
+    "tty_cdevinput", "tty_cdevscan", "tty_circgetc", "tty_circputc", "ioabort",
+    "set_card_find_mode", "KernelRedirect", "AdjustA0Table", "get_card_find_mode",
+];
+
+// Called many times a frame by most games, or already shown in the TTY console
+const NOISY: [(u32, u32); 6] = [
+    (0xA0, 0x3C), // std_out_putchar
+    (0xB0, 0x3D), // std_out_putchar
+    (0xB0, 0x07), // DeliverEvent
+    (0xB0, 0x0B), // TestEvent
+    (0xB0, 0x17), // ReturnFromException
+    (0xA0, 0x4D), // GetGPUStatus
+];
+
+// Calls whose first argument, or first two for renames, are file names or text
+const STRING_ARGS: [(u32, u32, usize); 12] = [
+    (0xA0, 0x00, 1), // FileOpen
+    (0xA0, 0x3E, 1), // std_out_puts
+    (0xA0, 0x41, 1), // LoadExeHeader
+    (0xA0, 0x42, 1), // LoadExeFile
+    (0xA0, 0x51, 1), // LoadAndExecute
+    (0xB0, 0x32, 1), // FileOpen
+    (0xB0, 0x3F, 1), // std_out_puts
+    (0xB0, 0x40, 1), // chdir
+    (0xB0, 0x42, 1), // firstfile
+    (0xB0, 0x44, 2), // FileRename
+    (0xB0, 0x45, 1), // FileDelete
+    (0xB0, 0x46, 1), // FileUndelete
+];
+
+// Name of a BIOS function, given the vector jumped to and the function number in $t1
+pub fn name(vector: u32, func: u32) -> Option<&'static str> {
+    let table: &[&str] = match vector {
+        0xA0 => &A_FUNCTIONS,
+        0xB0 => &B_FUNCTIONS,
+        0xC0 => &C_FUNCTIONS,
+        _ => return None,
+    };
+    table
+        .get(func as usize)
+        .copied()
+        .filter(|name| !name.is_empty())
+}
+
+pub fn noisy(vector: u32, func: u32) -> bool {
+    NOISY.contains(&(vector, func))
+}
+
+// Log line for a call about to run, such as `A0:3F printf("%d\n", 00000007, 00000000, 00000000) "7\n"`
+pub fn describe(bus: &Bus, registers: &[u32; 32], vector: u32, func: u32) -> String {
+    let name = name(vector, func).unwrap_or("?");
+    let strings = STRING_ARGS
+        .iter()
+        .find(|&&(v, f, _)| (v, f) == (vector, func))
+        .map_or(0, |&(_, _, count)| count);
+    let printf = (vector, func) == (0xA0, 0x3F);
+
+    let args: Vec<_> = registers[4..8]
+        .iter()
+        .enumerate()
+        .map(|(i, &arg)| {
+            let string = i < strings || (printf && i == 0);
+            match string.then(|| read_string(bus, arg)).flatten() {
+                Some(text) => format!("{text:?}"),
+                None => format!("{arg:08X}"),
+            }
+        })
+        .collect();
+    let mut line = format!("{vector:02X}:{func:02X} {name}({})", args.join(", "));
+    if printf && let Some(text) = printf_text(bus, registers) {
+        line.push_str(&format!(" {text:?}"));
+    }
+    line
+}
+
+// Null terminated string from RAM, the scratchpad or the BIOS, if it's short enough. Registers
+// are left alone even where peeking them is harmless
+pub fn read_string(bus: &Bus, addr: u32) -> Option<String> {
+    let mut text = String::new();
+    for i in 0..MAX_STRING as u32 {
+        let addr = addr.wrapping_add(i);
+        if !matches!(addr & 0x1FFFFFFF, 0..=0x1FFFFF | 0x1F800000..=0x1F8003FF | 0x1FC00000..) {
+            return None;
+        }
+        match bus.peek_byte(addr)? {
+            0 => return Some(text),
+            byte @ (0x20..=0x7E | b'\n' | b'\r' | b'\t') => text.push(byte as char),
+            _ => return None,
+        }
+    }
+    None
+}
+
+// Argument n of a call, counting from $a0. Arguments past the fourth are on the stack, above
+// the space kept for the first four
+fn argument(bus: &Bus, registers: &[u32; 32], n: usize) -> Option<u32> {
+    match n {
+        0..4 => Some(registers[4 + n]),
+        _ => bus.peek_word(registers[29].wrapping_add(4 * n as u32)),
+    }
+}
+
+// What a printf call about to run prints. None when its format string or arguments can't be
+// read safely, or it uses conversions not handled here
+pub fn printf_text(bus: &Bus, registers: &[u32; 32]) -> Option<String> {
+    let format = read_string(bus, registers[4])?;
+    let mut chars = format.chars().peekable();
+    let mut out = String::new();
+    let mut n = 1;
+    while let Some(ch) = chars.next() {
+        if ch != '%' {
+            out.push(ch);
+            continue;
+        }
+
+        let mut left = false;
+        let mut zero = false;
+        let mut plus = false;
+        while let Some(&flag @ ('-' | '0' | '+' | ' ' | '#')) = chars.peek() {
+            left |= flag == '-';
+            zero |= flag == '0';
+            plus |= flag == '+';
+            chars.next();
+        }
+        let mut width = 0;
+        while let Some(digit) = chars.peek().and_then(|ch| ch.to_digit(10)) {
+            width = width * 10 + digit as usize;
+            chars.next();
+        }
+        let mut precision = None;
+        if chars.next_if_eq(&'.').is_some() {
+            let mut digits = 0;
+            while let Some(digit) = chars.peek().and_then(|ch| ch.to_digit(10)) {
+                digits = digits * 10 + digit as usize;
+                chars.next();
+            }
+            precision = Some(digits);
+        }
+        while chars.next_if(|&ch| ch == 'l' || ch == 'h').is_some() {}
+
+        let conversion = chars.next()?;
+        let text = match conversion {
+            '%' => {
+                out.push('%');
+                continue;
+            }
+            'd' | 'i' => {
+                let val = argument(bus, registers, n)? as i32;
+                if plus && val >= 0 {
+                    format!("+{val}")
+                } else {
+                    val.to_string()
+                }
+            }
+            'u' => argument(bus, registers, n)?.to_string(),
+            'x' => format!("{:x}", argument(bus, registers, n)?),
+            'X' => format!("{:X}", argument(bus, registers, n)?),
+            'o' => format!("{:o}", argument(bus, registers, n)?),
+            'p' => format!("{:08x}", argument(bus, registers, n)?),
+            'c' => (argument(bus, registers, n)? as u8 as char).to_string(),
+            's' => {
+                let text = read_string(bus, argument(bus, registers, n)?)?;
+                match precision {
+                    Some(max) => text.chars().take(max).collect(),
+                    None => text,
+                }
+            }
+            _ => return None,
+        };
+        n += 1;
+
+        let pad = width.saturating_sub(text.chars().count());
+        if left {
+            out.push_str(&text);
+            out.extend(std::iter::repeat_n(' ', pad));
+        } else if zero && conversion != 's' && conversion != 'c' {
+            // Zeros go after the sign
+            let (sign, digits) = match text.strip_prefix(['-', '+']) {
+                Some(digits) => (&text[..1], digits),
+                None => ("", text.as_str()),
+            };
+            out.push_str(sign);
+            out.extend(std::iter::repeat_n('0', pad));
+            out.push_str(digits);
+        } else {
+            out.extend(std::iter::repeat_n(' ', pad));
+            out.push_str(&text);
+        }
+        if out.len() > 2 * MAX_STRING {
+            return None;
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_bus(test: impl FnOnce(&mut Bus) + Send + 'static) {
+        std::thread::Builder::new()
+            .stack_size(64 << 20)
+            .spawn(move || test(&mut Bus::new()))
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    // Writes to RAM above the first 64 KB, which the bus keeps apart for the kernel
+    fn poke(bus: &mut Bus, addr: u32, bytes: &[u8]) {
+        let start = (addr & 0x1FFFFF) as usize - 0x10000;
+        bus.ram[start..start + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn put_string(bus: &mut Bus, addr: u32, text: &str) {
+        poke(bus, addr, text.as_bytes());
+        poke(bus, addr + text.len() as u32, &[0]);
+    }
+
+    #[test]
+    fn names_come_from_the_vector_and_function_number() {
+        assert_eq!(name(0xA0, 0x3F), Some("printf"));
+        assert_eq!(name(0xA0, 0x3C), Some("std_out_putchar"));
+        assert_eq!(name(0xB0, 0x3D), Some("std_out_putchar"));
+        assert_eq!(name(0xC0, 0x02), Some("SysEnqIntRP"));
+        // Dummies, numbers past the end of a table and other addresses have no name
+        assert_eq!(name(0xA0, 0x4F), None);
+        assert_eq!(name(0xA0, 0xB5), None);
+        assert_eq!(name(0xC0, 0x1E), None);
+        assert_eq!(name(0x80, 0x00), None);
+
+        assert!(noisy(0xB0, 0x0B));
+        assert!(!noisy(0xA0, 0x3F));
+    }
+
+    #[test]
+    fn strings_are_read_from_readable_memory_only() {
+        with_bus(|bus| {
+            put_string(bus, 0x8001_1000, "bu00:SAVE\n");
+            assert_eq!(
+                read_string(bus, 0x8001_1000).as_deref(),
+                Some("bu00:SAVE\n")
+            );
+            assert_eq!(
+                read_string(bus, 0x0001_1000).as_deref(),
+                Some("bu00:SAVE\n")
+            );
+
+            bus.scratchpad[..3].copy_from_slice(b"hi\0");
+            assert_eq!(read_string(bus, 0x1F80_0000).as_deref(), Some("hi"));
+
+            // Binary data, unterminated text and I/O registers are left alone
+            poke(bus, 0x8001_2000, &[0x01, 0]);
+            assert_eq!(read_string(bus, 0x8001_2000), None);
+            poke(bus, 0x8001_3000, &[b'a'; MAX_STRING]);
+            assert_eq!(read_string(bus, 0x8001_3000), None);
+            assert_eq!(read_string(bus, 0x1F80_1000), None);
+        });
+    }
+
+    #[test]
+    fn printf_fetches_arguments_from_registers_then_the_stack() {
+        with_bus(|bus| {
+            let mut registers = [0; 32];
+            put_string(bus, 0x8001_1000, "%d lives, %05d pts %-4s|%x %c%%\n");
+            put_string(bus, 0x8001_1100, "ab");
+            registers[4] = 0x8001_1000;
+            registers[5] = -3i32 as u32;
+            registers[6] = 42;
+            registers[7] = 0x8001_1100;
+            // The fifth and sixth arguments sit above the four words kept for $a0-$a3
+            registers[29] = 0x801F_FF00;
+            poke(bus, 0x801F_FF10, &0xBEEFu32.to_le_bytes());
+            poke(bus, 0x801F_FF14, &u32::from(b'Z').to_le_bytes());
+            assert_eq!(
+                printf_text(bus, &registers).as_deref(),
+                Some("-3 lives, 00042 pts ab  |beef Z%\n")
+            );
+
+            put_string(bus, 0x8001_1000, "[%05d] [%+d] [%6X] [%.3s] [%3c]");
+            registers[5] = -42i32 as u32;
+            registers[6] = 7;
+            registers[7] = 0xABC;
+            poke(bus, 0x801F_FF10, &0x8001_1100u32.to_le_bytes());
+            put_string(bus, 0x8001_1100, "abcdef");
+            assert_eq!(
+                printf_text(bus, &registers).as_deref(),
+                Some("[-0042] [+7] [   ABC] [abc] [  Z]")
+            );
+        });
+    }
+
+    #[test]
+    fn printf_gives_up_on_what_it_cannot_read() {
+        with_bus(|bus| {
+            let mut registers = [0; 32];
+            registers[4] = 0x8001_1000;
+
+            put_string(bus, 0x8001_1000, "%f\n");
+            assert_eq!(printf_text(bus, &registers), None);
+            put_string(bus, 0x8001_1000, "50%");
+            assert_eq!(printf_text(bus, &registers), None);
+            put_string(bus, 0x8001_1000, "%s\n");
+            registers[5] = 0x1F80_1000;
+            assert_eq!(printf_text(bus, &registers), None);
+
+            registers[4] = 0x1F80_1000;
+            assert_eq!(printf_text(bus, &registers), None);
+        });
+    }
+
+    #[test]
+    fn calls_are_described_with_their_text_arguments() {
+        with_bus(|bus| {
+            let mut registers = [0; 32];
+            put_string(bus, 0x8001_1000, "%d\n");
+            registers[4] = 0x8001_1000;
+            registers[5] = 7;
+            assert_eq!(
+                describe(bus, &registers, 0xA0, 0x3F),
+                r#"A0:3F printf("%d\n", 00000007, 00000000, 00000000) "7\n""#
+            );
+
+            put_string(bus, 0x8001_1000, "bu00:SAVE");
+            registers[5] = 1;
+            assert_eq!(
+                describe(bus, &registers, 0xB0, 0x32),
+                r#"B0:32 FileOpen("bu00:SAVE", 00000001, 00000000, 00000000)"#
+            );
+            // Only arguments known to be text are read as strings
+            assert_eq!(
+                describe(bus, &registers, 0xA0, 0x4F),
+                "A0:4F ?(80011000, 00000001, 00000000, 00000000)"
+            );
+        });
+    }
+}
@@ -0,0 +1,134 @@
+use eframe::egui::{self, Color32, RichText};
+use serde::{Deserialize, Serialize};
+
+// Execution breakpoint. Disabled ones are kept in the list but never stop the CPU
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Breakpoint {
+    pub addr: u32,
+    pub enabled: bool,
+}
+
+// Addresses the CPU stops at
+pub fn enabled(breakpoints: &[Breakpoint]) -> impl Iterator<Item = u32> + '_ {
+    breakpoints
+        .iter()
+        .filter(|breakpoint| breakpoint.enabled)
+        .map(|breakpoint| breakpoint.addr)
+}
+
+// Adds an enabled breakpoint unless the address already has one. Returns whether it was added
+pub fn add(breakpoints: &mut Vec<Breakpoint>, addr: u32) -> bool {
+    if breakpoints.iter().any(|breakpoint| breakpoint.addr == addr) {
+        return false;
+    }
+    breakpoints.push(Breakpoint {
+        addr,
+        enabled: true,
+    });
+    breakpoints.sort_by_key(|breakpoint| breakpoint.addr);
+    true
+}
+
+// Removes the breakpoint at an address, or adds one if there is none
+pub fn toggle(breakpoints: &mut Vec<Breakpoint>, addr: u32) {
+    if !add(breakpoints, addr) {
+        breakpoints.retain(|breakpoint| breakpoint.addr != addr);
+    }
+}
+
+// Debug window listing the current game's breakpoints
+pub struct BreakpointWindow {
+    pub open: bool,
+    address_text: String,
+    error: Option<String>,
+}
+
+impl BreakpointWindow {
+    pub fn new() -> Self {
+        Self {
+            open: false,
+            address_text: String::new(),
+            error: None,
+        }
+    }
+
+    // Returns whether the list changed. The breakpoint last hit is highlighted
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        breakpoints: &mut Vec<Breakpoint>,
+        hit: Option<u32>,
+    ) -> bool {
+        let mut changed = false;
+        let mut open = self.open;
+        egui::Window::new("Breakpoints")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| changed = self.contents(ui, breakpoints, hit));
+        self.open = open;
+        changed
+    }
+
+    fn contents(
+        &mut self,
+        ui: &mut egui::Ui,
+        breakpoints: &mut Vec<Breakpoint>,
+        hit: Option<u32>,
+    ) -> bool {
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            ui.label("Address");
+            let response = ui.add(
+                egui::TextEdit::singleline(&mut self.address_text)
+                    .desired_width(80.0)
+                    .font(egui::TextStyle::Monospace),
+            );
+            let entered = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+            if ui.button("Add").clicked() || entered {
+                let text = self.address_text.trim();
+                let text = text.strip_prefix("0x").unwrap_or(text);
+                match u32::from_str_radix(text, 16) {
+                    // Instructions are word aligned
+                    Ok(addr) if !addr.is_multiple_of(4) => {
+                        self.error = Some(format!("{addr:08X} is not word aligned"));
+                    }
+                    Ok(addr) => {
+                        changed |= add(breakpoints, addr);
+                        self.address_text.clear();
+                        self.error = None;
+                    }
+                    Err(_) => {
+                        self.error = Some(format!("{} is not an address", self.address_text));
+                    }
+                }
+            }
+        });
+        if let Some(error) = &self.error {
+            ui.colored_label(Color32::RED, error);
+        }
+
+        if breakpoints.is_empty() {
+            ui.label("No breakpoints");
+        }
+        let mut remove = None;
+        egui::Grid::new("breakpoints").show(ui, |ui| {
+            for (i, breakpoint) in breakpoints.iter_mut().enumerate() {
+                changed |= ui.checkbox(&mut breakpoint.enabled, "").changed();
+                let mut text = RichText::new(format!("{:08X}", breakpoint.addr)).monospace();
+                if hit == Some(breakpoint.addr) {
+                    text = text.color(Color32::YELLOW);
+                }
+                ui.label(text);
+                if ui.button("Remove").clicked() {
+                    remove = Some(i);
+                }
+                ui.end_row();
+            }
+        });
+        if let Some(i) = remove {
+            breakpoints.remove(i);
+            changed = true;
+        }
+        changed
+    }
+}
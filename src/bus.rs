@@ -1,18 +1,34 @@
+use crate::cdrom::CdRom;
+use crate::cheats::{self, Code};
 use crate::cop0::Cop0;
 use crate::cpu::ExceptionType;
 use crate::dma::{Dicr, Dma, SyncMode};
 use crate::gpu::Gpu;
 use crate::interrupts::Interrupt;
 use crate::mdec::Mdec;
+use crate::sio::Sio0;
+use crate::sio1::Sio1;
+use crate::spu::Spu;
+use crate::state;
 use crate::timer::Timer;
+use crate::tty_console::TtyOutput;
+use crate::watchpoints::Watcher;
 
+use serde::{Deserialize, Serialize};
 use tracing::{Level, event};
 
+#[derive(Serialize, Deserialize)]
 pub struct Bus {
-    pub kernel: Box<[u8; 65536]>,      // 64 KB
-    pub ram: Box<[u8; 2097152]>,       // 2 MB - Box needed due to large array size
-    pub expansion1: Box<[u8; 65536]>,  // 64 KB
-    pub scratchpad: [u8; 1024],        // 1 KB
+    #[serde(with = "state::bytes")]
+    pub kernel: Box<[u8; 65536]>, // 64 KB
+    #[serde(with = "state::bytes")]
+    pub ram: Box<[u8; 2097152]>, // 2 MB - Box needed due to large array size
+    #[serde(with = "state::bytes")]
+    pub expansion1: Box<[u8; 65536]>, // 64 KB
+    #[serde(with = "state::bytes")]
+    pub scratchpad: [u8; 1024], // 1 KB
+    // The BIOS comes from the host, not the save state
+    #[serde(skip, default = "empty_rom")]
     pub kernel_rom: Box<[u8; 524288]>, // 512 KB - Box needed due to large array size
     pub cop0: Cop0,
     pub interrupts: Interrupt,
@@ -20,11 +36,35 @@ pub struct Bus {
     pub timer1: Timer,
     pub timer2: Timer,
     pub gpu: Gpu,
+    pub cdrom: CdRom,
     pub mdec: Mdec,
+    pub spu: Spu,
+    pub sio0: Sio0,
+    pub sio1: Sio1,
+    pub dma0: Dma,
+    pub dma1: Dma,
     pub dma2: Dma,
+    pub dma3: Dma,
+    pub dma4: Dma,
     pub dma6: Dma,
     pub dpcr: u32,
+    // CPU cycles run since power on
+    pub cycles: u64,
     pub dicr: Dicr,
+    // Characters printed through the BIOS putchar calls and logged BIOS calls, each with the
+    // address the call returns to
+    #[serde(skip)]
+    tty_output: Vec<(TtyOutput, u32)>,
+    // Codes of the enabled cheats, run at the start of each vblank
+    #[serde(skip)]
+    pub cheats: Vec<Code>,
+    // Data watchpoints checked by the load, store and DMA wrappers below
+    #[serde(skip)]
+    pub watcher: Watcher,
+}
+
+fn empty_rom() -> Box<[u8; 524288]> {
+    Box::new([0; 524288])
 }
 
 impl Bus {
@@ -41,30 +81,172 @@ impl Bus {
             timer1: Timer::new(1),
             timer2: Timer::new(2),
             gpu: Gpu::new(),
+            cdrom: CdRom::new(),
             mdec: Mdec::new(),
+            spu: Spu::new(),
+            sio0: Sio0::new(),
+            sio1: Sio1::new(),
+            dma0: Dma::new(),
+            dma1: Dma::new(),
             dma2: Dma::new(),
+            dma3: Dma::new(),
+            dma4: Dma::new(),
             dma6: Dma::new(),
             dpcr: 0x07654321,
+            cycles: 0,
             dicr: Dicr::new(),
+            tty_output: Vec::new(),
+            cheats: Vec::new(),
+            watcher: Watcher::default(),
+        }
+    }
+
+    pub fn push_tty_output(&mut self, ch: char, return_addr: u32) {
+        self.tty_output.push((TtyOutput::Char(ch), return_addr));
+    }
+
+    pub fn push_bios_call(&mut self, line: String, return_addr: u32) {
+        self.tty_output
+            .push((TtyOutput::BiosCall(line), return_addr));
+    }
+
+    // TTY output since the last call
+    pub fn take_tty_output(&mut self) -> Vec<(TtyOutput, u32)> {
+        std::mem::take(&mut self.tty_output)
+    }
+
+    // A GP0 write with the FIFO full holds up the writer until the GPU has drawn enough to make
+    // room, with the rest of the machine running meanwhile
+    fn wait_for_gp0_fifo(&mut self) {
+        while self.gpu.gp0.fifo_full() {
+            self.tick(self.gpu.busy_cpu_cycles().max(1));
         }
     }
 
     pub fn tick(&mut self, cycles: u32) {
-        if self.gpu.tick(cycles) {
+        self.cycles += cycles as u64;
+        let events = self.gpu.tick(cycles);
+        if events.vblank_start {
             self.interrupts.set_vblank_irq();
+            if !self.cheats.is_empty() {
+                let cheats = std::mem::take(&mut self.cheats);
+                cheats::apply(&cheats, self);
+                self.cheats = cheats;
+            }
+        }
+        if events.irq {
+            self.interrupts.set_gpu_irq();
+        }
+        if self.cdrom.tick(cycles) {
+            self.interrupts.set_cdrom_irq();
+        }
+        self.spu.cd_input.extend(self.cdrom.audio.drain(..));
+        self.spu.run_cycles(cycles);
+        if self.spu.take_irq() {
+            self.interrupts.set_spu_irq();
         }
+        if self.sio0.tick(cycles) {
+            self.interrupts.set_controller_irq();
+        }
+        if self.sio1.tick(cycles) {
+            self.interrupts.set_sio_irq();
+        }
+
+        if self.timer0.tick(cycles, &events) {
+            self.interrupts.set_tmr0_irq();
+        }
+        if self.timer1.tick(cycles, &events) {
+            self.interrupts.set_tmr1_irq();
+        }
+        if self.timer2.tick(cycles, &events) {
+            self.interrupts.set_tmr2_irq();
+        }
+    }
 
-        let dots = self.gpu.dotclock_counter;
-        let hblanks = self.gpu.hblank_counter;
-        for _ in 0..1 {
-            if self.timer0.tick(dots, hblanks) {
-                self.interrupts.set_tmr0_irq();
+    // DMA 0 feeds the MDEC command port and DMA 1 drains decoded words, a whole block at a
+    // time while the MDEC requests it. A channel left waiting carries on after the next
+    // write to the MDEC
+    fn mdec_dma(&mut self) {
+        if self.dma0.active() && self.mdec.data_in_request() {
+            let mut address = self.dma0.madr_read();
+            let block_ctrl = self.dma0.block_control_read();
+            let dma_len = match self.dma0.sync_mode {
+                SyncMode::Burst => match block_ctrl & 0xFFFF {
+                    0 => 0x10000,
+                    words => words,
+                },
+                SyncMode::Slice => (block_ctrl & 0xFFFF) * ((block_ctrl >> 16) & 0xFFFF),
+                // Only the GPU channel follows linked lists. The channel ends without moving
+                // anything
+                SyncMode::LinkedList => {
+                    event!(target: "ps1_emulator::DMA", Level::WARN, "Ignored linked list transfer on DMA 0");
+                    0
+                }
+            };
+
+            for _ in 0..dma_len {
+                let data = self.dma_read_word(address);
+                self.mdec.command_write(data);
+
+                if self.dma0.increment_direction() {
+                    address -= 4;
+                } else {
+                    address += 4;
+                }
             }
-            if self.timer1.tick(dots, hblanks) {
-                self.interrupts.set_tmr1_irq();
+
+            self.dma0.madr_write(address);
+            self.dma0.finish_dma();
+            if self.dicr.dma0_mask_set() {
+                self.dicr.dma0_set_interrupt_flag();
+                if self.dicr.master_interrupt_set() {
+                    self.interrupts.set_dma_irq();
+                }
             }
-            if self.timer2.tick(dots, hblanks) {
-                self.interrupts.set_tmr2_irq();
+        }
+
+        if self.dma1.active() && self.mdec.data_out_request() {
+            let mut address = self.dma1.madr_read();
+            let block_ctrl = self.dma1.block_control_read();
+            let (block_size, mut blocks) = match self.dma1.sync_mode {
+                SyncMode::Burst => match block_ctrl & 0xFFFF {
+                    0 => (0x10000, 1),
+                    words => (words, 1),
+                },
+                SyncMode::Slice => (block_ctrl & 0xFFFF, (block_ctrl >> 16) & 0xFFFF),
+                SyncMode::LinkedList => {
+                    event!(target: "ps1_emulator::DMA", Level::WARN, "Ignored linked list transfer on DMA 1");
+                    (0, 0)
+                }
+            };
+
+            while blocks > 0 && self.mdec.output_words() >= block_size as usize {
+                for _ in 0..block_size {
+                    let data = self.mdec.data_read();
+                    self.dma_write_word(address, data);
+
+                    if self.dma1.increment_direction() {
+                        address -= 4;
+                    } else {
+                        address += 4;
+                    }
+                }
+                blocks -= 1;
+            }
+
+            self.dma1.madr_write(address);
+            if let SyncMode::Slice = self.dma1.sync_mode {
+                self.dma1
+                    .block_control_write((blocks << 16) | (block_ctrl & 0xFFFF));
+            }
+            if blocks == 0 {
+                self.dma1.finish_dma();
+                if self.dicr.dma1_mask_set() {
+                    self.dicr.dma1_set_interrupt_flag();
+                    if self.dicr.master_interrupt_set() {
+                        self.interrupts.set_dma_irq();
+                    }
+                }
             }
         }
     }
@@ -193,16 +375,10 @@ impl Bus {
             0x1F801021 => Ok(0x11),
             0x1F801022 => Ok(0x03),
             0x1F801023 => Ok(0x00),
-            // JOY DATA
-            0x1F801040 => Ok(0),
-            0x1F801041 => Ok(0),
-            0x1F801042 => Ok(0),
-            0x1F801043 => Ok(0),
-            // JOY CTRL
-            0x1F80104A => Ok(0),
-            0x1F80104B => Ok(0),
-            0x1F80104C => Ok(0),
-            0x1F80104D => Ok(0),
+            // SIO0 - Controllers and memory cards
+            0x1F801040..=0x1F80104F => Ok(self.sio0.read(addr - 0x1F801040) as u8),
+            // SIO1 - Serial port
+            0x1F801050..=0x1F80105F => Ok(self.sio1.read(addr - 0x1F801050) as u8),
             // RAM SIZE
             0x1F801060 => Ok(0x88),
             0x1F801061 => Ok(0x0B),
@@ -264,38 +440,13 @@ impl Bus {
             0x1F801129 => Ok((self.timer2.target_value >> 8) as u8),
             0x1F80112A => Ok(0),
             0x1F80112B => Ok(0),
-            // SPU Control Registers
-            // Voice Registers
-            0x1F801C00..=0x1F801D7F => Ok(0),
-            // Main Volume
-            0x1F801D80 => Ok(0),
-            0x1F801D81 => Ok(0),
-            0x1F801D82 => Ok(0),
-            0x1F801D83 => Ok(0),
-            // Reverb Output Volume
-            0x1F801D84 => Ok(0),
-            0x1F801D85 => Ok(0),
-            0x1F801D86 => Ok(0),
-            0x1F801D87 => Ok(0),
-            // Voice 0..23 Key ON
-            0x1F801D88 => Ok(0),
-            0x1F801D89 => Ok(0),
-            0x1F801D8A => Ok(0),
-            0x1F801D8B => Ok(0),
-            // Voice 0..23 Key OFF
-            0x1F801D8C => Ok(0),
-            0x1F801D8D => Ok(0),
-            0x1F801D8E => Ok(0),
-            0x1F801D8F => Ok(0),
-            // SPU Control Register (SPUCNT)
-            0x1F801DAA => Ok(0),
-            0x1F801DAB => Ok(0),
-            // Sound RAM Data Transfer Control
-            0x1F801DAC => Ok(0),
-            0x1F801DAD => Ok(0),
-            // SPU Status Register (SPUSTAT)
-            0x1F801DAE => Ok(0),
-            0x1F801DAF => Ok(0),
+            // CDROM
+            0x1F801800..=0x1F801803 => Ok(self.cdrom.read(addr - 0x1F801800)),
+            // SPU
+            0x1F801C00..=0x1F801FFF => {
+                let val = self.spu.read((addr - 0x1F801C00) & !1);
+                Ok((val >> (8 * (addr & 1))) as u8)
+            }
             // Expansion Region 2 Int/Dip/Post
             0x1F802041 => Ok(0),
             // CPU Control Register
@@ -315,6 +466,69 @@ impl Bus {
         }
     }
 
+    // Reads a byte for debug views without raising exceptions or touching devices. Gives None
+    // for unmapped space and for I/O registers whose reads have side effects
+    pub fn peek_byte(&self, addr: u32) -> Option<u8> {
+        let phys = match addr {
+            0x00000000..=0x1FFFFFFF => addr,
+            0x80000000..=0x9FFFFFFF => addr - 0x80000000,
+            0xA0000000..=0xBFFFFFFF => addr - 0xA0000000,
+            _ => return None,
+        };
+        let byte = |val: u32| (val >> (8 * (phys & 3))) as u8;
+
+        match phys {
+            0x00000000..=0x0000FFFF => Some(self.kernel[phys as usize]),
+            0x00010000..=0x001FFFFF => Some(self.ram[(phys - 0x00010000) as usize]),
+            0x1F000000..=0x1F00FFFF => Some(self.expansion1[(phys - 0x1F000000) as usize]),
+            // Scratchpad is not mapped in KSEG1
+            0x1F800000..=0x1F8003FF if addr < 0xA0000000 => {
+                Some(self.scratchpad[(phys - 0x1F800000) as usize])
+            }
+            0x1FC00000..=0x1FC7FFFF => Some(self.kernel_rom[(phys - 0x1FC00000) as usize]),
+            0x1F801070..=0x1F801073 => Some(byte(self.interrupts.stat & 0xFFFF)),
+            0x1F801074..=0x1F801077 => Some(byte(self.interrupts.mask & 0xFFFF)),
+            0x1F801100..=0x1F80112B => {
+                let timer = match (phys >> 4) & 3 {
+                    0 => &self.timer0,
+                    1 => &self.timer1,
+                    _ => &self.timer2,
+                };
+                let val = match (phys >> 2) & 3 {
+                    0 => timer.counter,
+                    1 => timer.read_mode(),
+                    2 => timer.target_value,
+                    _ => return None,
+                };
+                Some(byte(val as u32))
+            }
+            _ => None,
+        }
+    }
+
+    // RAM by offset for cheats, ignoring cache isolation. The first 64KB is the kernel's
+    pub fn peek_ram_byte(&self, offset: u32) -> u8 {
+        match offset {
+            0x0000..=0xFFFF => self.kernel[offset as usize],
+            _ => self.ram[(offset - 0x10000) as usize],
+        }
+    }
+
+    pub fn poke_ram_byte(&mut self, offset: u32, val: u8) {
+        match offset {
+            0x0000..=0xFFFF => self.kernel[offset as usize] = val,
+            _ => self.ram[(offset - 0x10000) as usize] = val,
+        }
+    }
+
+    pub fn peek_word(&self, addr: u32) -> Option<u32> {
+        let mut bytes = [0; 4];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = self.peek_byte(addr.wrapping_add(i as u32))?;
+        }
+        Some(u32::from_le_bytes(bytes))
+    }
+
     pub fn mem_write_byte(&mut self, addr: u32, val: u8) -> Result<(), ExceptionType> {
         let isc_set = self.cop0.sr.get_isc();
 
@@ -460,16 +674,40 @@ impl Bus {
             0x1F801021 => Ok(()),
             0x1F801022 => Ok(()),
             0x1F801023 => Ok(()),
-            // JOY_DATA
-            0x1F801040 => Ok(()),
-            0x1F801041 => Ok(()),
-            0x1F801042 => Ok(()),
-            0x1F801043 => Ok(()),
-            // JOY_CTRL
-            0x1F80104A => Ok(()),
-            0x1F80104B => Ok(()),
-            0x1F80104C => Ok(()),
-            0x1F80104D => Ok(()),
+            // SIO0. Byte writes to the 16 bit registers keep the other byte
+            0x1F801040 => {
+                self.sio0.write(0, val as u16);
+                Ok(())
+            }
+            0x1F801041..=0x1F801047 => Ok(()),
+            0x1F801048..=0x1F80104F => {
+                let offset = addr - 0x1F801040;
+                let reg = self.sio0.read(offset & !1) as u16;
+                let reg = if offset & 1 == 0 {
+                    (reg & 0xFF00) | val as u16
+                } else {
+                    (reg & 0x00FF) | ((val as u16) << 8)
+                };
+                self.sio0.write(offset & !1, reg);
+                Ok(())
+            }
+            // SIO1, laid out like SIO0
+            0x1F801050 => {
+                self.sio1.write(0, val as u16);
+                Ok(())
+            }
+            0x1F801051..=0x1F801057 => Ok(()),
+            0x1F801058..=0x1F80105F => {
+                let offset = addr - 0x1F801050;
+                let reg = self.sio1.read(offset & !1) as u16;
+                let reg = if offset & 1 == 0 {
+                    (reg & 0xFF00) | val as u16
+                } else {
+                    (reg & 0x00FF) | ((val as u16) << 8)
+                };
+                self.sio1.write(offset & !1, reg);
+                Ok(())
+            }
             // RAM SIZE
             0x1F801060 => Ok(()),
             0x1F801061 => Ok(()),
@@ -627,73 +865,18 @@ impl Bus {
             }
             0x1F80112A => Ok(()),
             0x1F80112B => Ok(()),
-            // SPU Control Registers
-            // Voice Registers
-            0x1F801C00..=0x1F801D7F => Ok(()),
-            // Main Volume
-            0x1F801D80 => Ok(()),
-            0x1F801D81 => Ok(()),
-            0x1F801D82 => Ok(()),
-            0x1F801D83 => Ok(()),
-            // Reverb Output Volume
-            0x1F801D84 => Ok(()),
-            0x1F801D85 => Ok(()),
-            0x1F801D86 => Ok(()),
-            0x1F801D87 => Ok(()),
-            // Voice 0..23 Key ON
-            0x1F801D88 => Ok(()),
-            0x1F801D89 => Ok(()),
-            0x1F801D8A => Ok(()),
-            0x1F801D8B => Ok(()),
-            // Voice 0..23 Key OFF
-            0x1F801D8C => Ok(()),
-            0x1F801D8D => Ok(()),
-            0x1F801D8E => Ok(()),
-            0x1F801D8F => Ok(()),
-            // Voice 0..23 Channel FM
-            0x1F801D90 => Ok(()),
-            0x1F801D91 => Ok(()),
-            0x1F801D92 => Ok(()),
-            0x1F801D93 => Ok(()),
-            // Voice 0..23 Channel Noise Mode
-            0x1F801D94 => Ok(()),
-            0x1F801D95 => Ok(()),
-            0x1F801D96 => Ok(()),
-            0x1F801D97 => Ok(()),
-            // Voice 0..23 Channel Reverb Mode
-            0x1F801D98 => Ok(()),
-            0x1F801D99 => Ok(()),
-            0x1F801D9A => Ok(()),
-            0x1F801D9B => Ok(()),
-            // Sound RAM Reverb Work Area
-            0x1F801DA2 => Ok(()),
-            0x1F801DA3 => Ok(()),
-            // Sound RAM Data Transfer Address
-            0x1F801DA6 => Ok(()),
-            0x1F801DA7 => Ok(()),
-            // Sound RAM Data Transfer FIFO
-            0x1F801DA8 => Ok(()),
-            0x1F801DA9 => Ok(()),
-            // SPU Control Register (SPUCNT)
-            0x1F801DAA => Ok(()),
-            0x1F801DAB => Ok(()),
-            // Sound RAM Data Transfer Control
-            0x1F801DAC => Ok(()),
-            0x1F801DAD => Ok(()),
-            // SPU Status Register (SPUSTAT)
-            0x1F801DAE => Ok(()),
-            0x1F801DAF => Ok(()),
-            // CD Volume Left/Right
-            0x1F801DB0 => Ok(()),
-            0x1F801DB1 => Ok(()),
-            0x1F801DB2 => Ok(()),
-            0x1F801DB3 => Ok(()),
-            // External Volume Left/Right
-            0x1F801DB4 => Ok(()),
-            0x1F801DB5 => Ok(()),
-            0x1F801DB6 => Ok(()),
-            0x1F801DB7 => Ok(()),
-            0x1F801DC0..=0x1F801DFF => Ok(()),
+            // CDROM
+            0x1F801800..=0x1F801803 => {
+                self.cdrom.write(addr - 0x1F801800, val);
+                Ok(())
+            }
+            // SPU. Registers are 16 bits wide, only the low byte of a byte write is used
+            0x1F801C00..=0x1F801FFF => {
+                if addr & 1 == 0 {
+                    self.spu.write(addr - 0x1F801C00, val as u16);
+                }
+                Ok(())
+            }
 
             // Expansion Region 2 Int/Dip/Post
             0x1F802041 => Ok(()),
@@ -722,10 +905,26 @@ impl Bus {
         }
 
         match addr {
+            // DMA 0 - MDEC in
+            0x1F801080 => Ok(self.dma0.madr_read()),
+            0x1F801084 => Ok(self.dma0.block_control_read()),
+            0x1F801088 => Ok(self.dma0.channel_control_read()),
+            // DMA 1 - MDEC out
+            0x1F801090 => Ok(self.dma1.madr_read()),
+            0x1F801094 => Ok(self.dma1.block_control_read()),
+            0x1F801098 => Ok(self.dma1.channel_control_read()),
             // DMA 2 - GPU
             0x1F8010A0 => Ok(self.dma2.madr_read()),
             0x1F8010A4 => Ok(self.dma2.block_control_read()),
             0x1F8010A8 => Ok(self.dma2.channel_control_read()),
+            // DMA 3 - CDROM
+            0x1F8010B0 => Ok(self.dma3.madr_read()),
+            0x1F8010B4 => Ok(self.dma3.block_control_read()),
+            0x1F8010B8 => Ok(self.dma3.channel_control_read()),
+            // DMA 4 - SPU
+            0x1F8010C0 => Ok(self.dma4.madr_read()),
+            0x1F8010C4 => Ok(self.dma4.block_control_read()),
+            0x1F8010C8 => Ok(self.dma4.channel_control_read()),
             // DMA 6 - OTC
             0x1F8010E0 => Ok(self.dma6.madr_read()),
             0x1F8010E4 => Ok(self.dma6.block_control_read()),
@@ -744,6 +943,29 @@ impl Bus {
             // GPU
             0x1F801810 => Ok(self.gpu.gpuread()),
             0x1F801814 => Ok(self.gpu.gpustat()),
+            // MDEC
+            0x1F801820 => Ok(self.mdec.data_read()),
+            0x1F801824 => Ok(self.mdec.status()),
+            // SIO0. DATA and STAT are 32 bits wide, the rest 16 bits
+            0x1F801040 | 0x1F801044 => Ok(self.sio0.read(addr - 0x1F801040)),
+            0x1F801048 | 0x1F80104C => {
+                let lo = self.sio0.read(addr - 0x1F801040) & 0xFFFF;
+                let hi = self.sio0.read(addr + 2 - 0x1F801040) & 0xFFFF;
+                Ok(lo | (hi << 16))
+            }
+            // SIO1
+            0x1F801050 | 0x1F801054 => Ok(self.sio1.read(addr - 0x1F801050)),
+            0x1F801058 | 0x1F80105C => {
+                let lo = self.sio1.read(addr - 0x1F801050) & 0xFFFF;
+                let hi = self.sio1.read(addr + 2 - 0x1F801050) & 0xFFFF;
+                Ok(lo | (hi << 16))
+            }
+            // SPU
+            0x1F801C00..=0x1F801FFF => {
+                let lo = self.spu.read(addr - 0x1F801C00) as u32;
+                let hi = self.spu.read(addr + 2 - 0x1F801C00) as u32;
+                Ok(lo | (hi << 16))
+            }
             _ => {
                 let b0 = self.mem_read_byte(addr)?;
                 let b1 = self.mem_read_byte(addr + 1)?;
@@ -765,6 +987,44 @@ impl Bus {
         }
 
         match addr {
+            // DMA 0 - MDEC in
+            0x1F801080 => {
+                event!(target: "ps1_emulator::DMA", Level::TRACE, "DMA 0 MADR write {:08X}", val);
+                self.dma0.madr_write(val);
+                Ok(())
+            }
+            0x1F801084 => {
+                event!(target: "ps1_emulator::DMA", Level::TRACE, "DMA 0 BCR write {:08X}", val);
+                self.dma0.block_control_write(val);
+                Ok(())
+            }
+            0x1F801088 => {
+                event!(target: "ps1_emulator::DMA", Level::TRACE, "DMA 0 CHCR write {:08X}", val);
+                if self.dma0.channel_control_write(val) {
+                    self.dma0.start_dma();
+                    self.mdec_dma();
+                }
+                Ok(())
+            }
+            // DMA 1 - MDEC out
+            0x1F801090 => {
+                event!(target: "ps1_emulator::DMA", Level::TRACE, "DMA 1 MADR write {:08X}", val);
+                self.dma1.madr_write(val);
+                Ok(())
+            }
+            0x1F801094 => {
+                event!(target: "ps1_emulator::DMA", Level::TRACE, "DMA 1 BCR write {:08X}", val);
+                self.dma1.block_control_write(val);
+                Ok(())
+            }
+            0x1F801098 => {
+                event!(target: "ps1_emulator::DMA", Level::TRACE, "DMA 1 CHCR write {:08X}", val);
+                if self.dma1.channel_control_write(val) {
+                    self.dma1.start_dma();
+                    self.mdec_dma();
+                }
+                Ok(())
+            }
             // DMA 2 - GPU
             0x1F8010A0 => {
                 event!(target: "ps1_emulator::DMA", Level::TRACE, "DMA 2 MADR write {:08X}", val);
@@ -793,7 +1053,8 @@ impl Bus {
 
                             for _ in 0..dma_len {
                                 if self.dma2.dma_direction() {
-                                    let val = self.mem_read_word(address).unwrap();
+                                    let val = self.dma_read_word(address);
+                                    self.wait_for_gp0_fifo();
                                     self.gpu.gp0.write(val);
                                 }
 
@@ -809,13 +1070,14 @@ impl Bus {
                         }
                         SyncMode::LinkedList => {
                             loop {
-                                let header = self.mem_read_word(address).unwrap();
+                                let header = self.dma_read_word(address);
 
                                 let data_words = header >> 24;
 
                                 for i in 0..data_words {
                                     let addr = address + 4 * (i + 1);
-                                    let data = self.mem_read_word(addr).unwrap();
+                                    let data = self.dma_read_word(addr);
+                                    self.wait_for_gp0_fifo();
                                     self.gpu.gp0.write(data);
                                 }
 
@@ -841,6 +1103,115 @@ impl Bus {
 
                 Ok(())
             }
+            // DMA 3 - CDROM
+            0x1F8010B0 => {
+                event!(target: "ps1_emulator::DMA", Level::TRACE, "DMA 3 MADR write {:08X}", val);
+                self.dma3.madr_write(val);
+                Ok(())
+            }
+            0x1F8010B4 => {
+                event!(target: "ps1_emulator::DMA", Level::TRACE, "DMA 3 BCR write {:08X}", val);
+                self.dma3.block_control_write(val);
+                Ok(())
+            }
+            0x1F8010B8 => {
+                event!(target: "ps1_emulator::DMA", Level::TRACE, "DMA 3 CHCR write {:08X}", val);
+                if self.dma3.channel_control_write(val) {
+                    let mut address = self.dma3.madr_read();
+                    self.dma3.start_dma();
+                    let block_ctrl = self.dma3.block_control_read();
+                    let dma_len = match self.dma3.sync_mode {
+                        SyncMode::Burst => match block_ctrl & 0xFFFF {
+                            0 => 0x10000,
+                            words => words,
+                        },
+                        SyncMode::Slice => (block_ctrl & 0xFFFF) * ((block_ctrl >> 16) & 0xFFFF),
+                        SyncMode::LinkedList => {
+                            event!(target: "ps1_emulator::DMA", Level::WARN, "Ignored linked list transfer on DMA 3");
+                            0
+                        }
+                    };
+
+                    for _ in 0..dma_len {
+                        let data = self.cdrom.dma_read();
+                        self.dma_write_word(address, data);
+
+                        if self.dma3.increment_direction() {
+                            address -= 4;
+                        } else {
+                            address += 4;
+                        }
+                    }
+
+                    self.dma3.madr_write(address);
+                    self.dma3.finish_dma();
+                    if self.dicr.dma3_mask_set() {
+                        self.dicr.dma3_set_interrupt_flag();
+                        if self.dicr.master_interrupt_set() {
+                            self.interrupts.set_dma_irq();
+                        }
+                    }
+                }
+
+                Ok(())
+            }
+            // DMA 4 - SPU
+            0x1F8010C0 => {
+                event!(target: "ps1_emulator::DMA", Level::TRACE, "DMA 4 MADR write {:08X}", val);
+                self.dma4.madr_write(val);
+                Ok(())
+            }
+            0x1F8010C4 => {
+                event!(target: "ps1_emulator::DMA", Level::TRACE, "DMA 4 BCR write {:08X}", val);
+                self.dma4.block_control_write(val);
+                Ok(())
+            }
+            0x1F8010C8 => {
+                event!(target: "ps1_emulator::DMA", Level::TRACE, "DMA 4 CHCR write {:08X}", val);
+                if self.dma4.channel_control_write(val) {
+                    let mut address = self.dma4.madr_read();
+                    self.dma4.start_dma();
+                    let block_ctrl = self.dma4.block_control_read();
+                    let dma_len = match self.dma4.sync_mode {
+                        SyncMode::Burst => match block_ctrl & 0xFFFF {
+                            0 => 0x10000,
+                            words => words,
+                        },
+                        SyncMode::Slice => (block_ctrl & 0xFFFF) * ((block_ctrl >> 16) & 0xFFFF),
+                        SyncMode::LinkedList => {
+                            event!(target: "ps1_emulator::DMA", Level::WARN, "Ignored linked list transfer on DMA 4");
+                            0
+                        }
+                    };
+
+                    for _ in 0..dma_len {
+                        if self.dma4.dma_direction() {
+                            let data = self.dma_read_word(address);
+                            self.spu.dma_write(data);
+                        } else {
+                            let data = self.spu.dma_read();
+                            self.dma_write_word(address, data);
+                        }
+
+                        if self.dma4.increment_direction() {
+                            address -= 4;
+                        } else {
+                            address += 4;
+                        }
+                    }
+
+                    self.dma4.madr_write(address);
+                    self.dma4.finish_dma();
+                    if self.dicr.dma4_mask_set() {
+                        self.dicr.dma4_set_interrupt_flag();
+                        if self.dicr.master_interrupt_set() {
+                            self.interrupts.set_dma_irq();
+                        }
+                    }
+                }
+
+                Ok(())
+            }
             // DMA 6 - OTC
             0x1F8010E0 => {
                 event!(target: "ps1_emulator::DMA", Level::TRACE, "DMA 6 MADR write {:08X}", val);
@@ -867,7 +1238,7 @@ impl Bus {
                                     address - 4
                                 };
 
-                                self.mem_write_word(address, header).unwrap();
+                                self.dma_write_word(address, header);
                                 address -= 4;
                             }
                         }
@@ -892,7 +1263,11 @@ impl Bus {
             // DPCR - DMA Control Register
             0x1F8010F0 => {
                 event!(target: "ps1_emulator::DMA", Level::TRACE, "DPCR DMA Write {:08X}", val);
+                self.dma0.enabled = val & 0x8 > 0;
+                self.dma1.enabled = val & 0x80 > 0;
                 self.dma2.enabled = val & 0x800 > 0;
+                self.dma3.enabled = val & 0x8000 > 0;
+                self.dma4.enabled = val & 0x80000 > 0;
                 self.dma6.enabled = val & 0x8000000 > 0;
                 self.dpcr = val;
                 Ok(())
@@ -904,6 +1279,7 @@ impl Bus {
                 Ok(())
             }
             0x1F801810 => {
+                self.wait_for_gp0_fifo();
                 self.gpu.gp0.write(val);
                 Ok(())
             }
@@ -911,6 +1287,39 @@ impl Bus {
                 self.gpu.gp1_write(val);
                 Ok(())
             }
+            // MDEC
+            0x1F801820 => {
+                self.mdec.command_write(val);
+                self.mdec_dma();
+                Ok(())
+            }
+            0x1F801824 => {
+                self.mdec.control_write(val);
+                self.mdec_dma();
+                Ok(())
+            }
+            // SIO0
+            0x1F801040..=0x1F80104F => {
+                self.sio0.write(addr - 0x1F801040, val as u16);
+                if addr >= 0x1F801048 {
+                    self.sio0.write(addr + 2 - 0x1F801040, (val >> 16) as u16);
+                }
+                Ok(())
+            }
+            // SIO1
+            0x1F801050..=0x1F80105F => {
+                self.sio1.write(addr - 0x1F801050, val as u16);
+                if addr >= 0x1F801058 {
+                    self.sio1.write(addr + 2 - 0x1F801050, (val >> 16) as u16);
+                }
+                Ok(())
+            }
+            // SPU
+            0x1F801C00..=0x1F801FFF => {
+                self.spu.write(addr - 0x1F801C00, val as u16);
+                self.spu.write(addr + 2 - 0x1F801C00, (val >> 16) as u16);
+                Ok(())
+            }
             _ => {
                 let [b0, b1, b2, b3] = val.to_le_bytes();
                 self.mem_write_byte(addr, b0)?;
@@ -927,6 +1336,19 @@ impl Bus {
             return Err(ExceptionType::AddressErrorLoad(addr));
         }
 
+        // SPU registers are 16 bits wide
+        if let 0x1F801C00..=0x1F801FFF = addr {
+            return Ok(self.spu.read(addr - 0x1F801C00));
+        }
+
+        // Reading SIO0 DATA must take a single byte from the FIFO
+        if let 0x1F801040..=0x1F80104F = addr {
+            return Ok(self.sio0.read(addr - 0x1F801040) as u16);
+        }
+        if let 0x1F801050..=0x1F80105F = addr {
+            return Ok(self.sio1.read(addr - 0x1F801050) as u16);
+        }
+
         Ok(u16::from_le_bytes([
             self.mem_read_byte(addr)?,
             self.mem_read_byte(addr + 1)?,
@@ -943,9 +1365,379 @@ impl Bus {
             return Ok(());
         }
 
+        // SPU registers are 16 bits wide. The transfer FIFO must see each halfword once
+        if let 0x1F801C00..=0x1F801FFF = addr {
+            self.spu.write(addr - 0x1F801C00, val);
+            return Ok(());
+        }
+
+        if let 0x1F801040..=0x1F80104F = addr {
+            self.sio0.write(addr - 0x1F801040, val);
+            return Ok(());
+        }
+        if let 0x1F801050..=0x1F80105F = addr {
+            self.sio1.write(addr - 0x1F801050, val);
+            return Ok(());
+        }
+
         let [lo, hi] = val.to_le_bytes();
         self.mem_write_byte(addr, lo)?;
         self.mem_write_byte(addr + 1, hi)?;
         Ok(())
     }
+
+    // CPU loads and stores. Unlike the mem_* functions they are checked against the
+    // watchpoints, once per access whatever its width
+    pub fn load_byte(&mut self, addr: u32) -> Result<u8, ExceptionType> {
+        let val = self.mem_read_byte(addr)?;
+        if !self.watcher.is_empty() {
+            self.watcher.check(addr, 1, val as u32, false, false);
+        }
+        Ok(val)
+    }
+
+    pub fn load_halfword(&mut self, addr: u32) -> Result<u16, ExceptionType> {
+        let val = self.mem_read_halfword(addr)?;
+        if !self.watcher.is_empty() {
+            self.watcher.check(addr, 2, val as u32, false, false);
+        }
+        Ok(val)
+    }
+
+    pub fn load_word(&mut self, addr: u32) -> Result<u32, ExceptionType> {
+        let val = self.mem_read_word(addr)?;
+        if !self.watcher.is_empty() {
+            self.watcher.check(addr, 4, val, false, false);
+        }
+        Ok(val)
+    }
+
+    pub fn store_byte(&mut self, addr: u32, val: u8) -> Result<(), ExceptionType> {
+        self.mem_write_byte(addr, val)?;
+        if !self.watcher.is_empty() {
+            self.watcher.check(addr, 1, val as u32, true, false);
+        }
+        Ok(())
+    }
+
+    pub fn store_halfword(&mut self, addr: u32, val: u16) -> Result<(), ExceptionType> {
+        self.mem_write_halfword(addr, val)?;
+        if !self.watcher.is_empty() {
+            self.watcher.check(addr, 2, val as u32, true, false);
+        }
+        Ok(())
+    }
+
+    pub fn store_word(&mut self, addr: u32, val: u32) -> Result<(), ExceptionType> {
+        self.mem_write_word(addr, val)?;
+        if !self.watcher.is_empty() {
+            self.watcher.check(addr, 4, val, true, false);
+        }
+        Ok(())
+    }
+
+    // DMA transfers to and from RAM. The watcher ignores these unless asked to include DMA
+    fn dma_read_word(&mut self, addr: u32) -> u32 {
+        let val = self.mem_read_word(addr).unwrap();
+        if !self.watcher.is_empty() {
+            self.watcher.check(addr, 4, val, false, true);
+        }
+        val
+    }
+
+    fn dma_write_word(&mut self, addr: u32, val: u32) {
+        self.mem_write_word(addr, val).unwrap();
+        if !self.watcher.is_empty() {
+            self.watcher.check(addr, 4, val, true, true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disc::{self, Disc};
+
+    // Bus::new builds its memories on the stack
+    fn with_bus(test: impl FnOnce(&mut Bus) + Send + 'static) {
+        std::thread::Builder::new()
+            .stack_size(64 << 20)
+            .spawn(move || test(&mut Bus::new()))
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    fn write_words(bus: &mut Bus, addr: u32, words: &[u32]) {
+        for (i, &word) in words.iter().enumerate() {
+            bus.mem_write_word(addr + 4 * i as u32, word).unwrap();
+        }
+    }
+
+    fn read_words(bus: &mut Bus, addr: u32, count: usize) -> Vec<u32> {
+        (0..count as u32)
+            .map(|i| bus.mem_read_word(addr + 4 * i).unwrap())
+            .collect()
+    }
+
+    // Sets MADR, BCR and then CHCR of the channel with registers at base
+    fn start_dma(bus: &mut Bus, base: u32, madr: u32, bcr: u32, chcr: u32) {
+        bus.mem_write_word(base, madr).unwrap();
+        bus.mem_write_word(base + 4, bcr).unwrap();
+        bus.mem_write_word(base + 8, chcr).unwrap();
+    }
+
+    // CHCR bit 24 stays set until the transfer ends
+    fn busy(bus: &mut Bus, base: u32) -> bool {
+        bus.mem_read_word(base + 8).unwrap() & 0x0100_0000 > 0
+    }
+
+    // Disc whose sector n holds the words (n << 16) | i
+    fn cd_disc(sectors: u32) -> Disc {
+        let data: Vec<u8> = (0..sectors)
+            .flat_map(|n| (0..512).flat_map(move |i| ((n << 16) | i).to_le_bytes()))
+            .collect();
+        disc::test_iso("dma3", &data)
+    }
+
+    fn sector_words(n: u32) -> Vec<u32> {
+        (0..512).map(|i| (n << 16) | i).collect()
+    }
+
+    // Runs the drive until it interrupts, then acknowledges. Returns the interrupt number
+    fn cd_wait(bus: &mut Bus) -> u8 {
+        bus.mem_write_byte(0x1F801800, 1).unwrap();
+        loop {
+            bus.cdrom.tick(1000);
+            let int = bus.mem_read_byte(0x1F801803).unwrap() & 0x7;
+            if int != 0 {
+                bus.mem_write_byte(0x1F801803, 0x1F).unwrap();
+                bus.mem_write_byte(0x1F801800, 0).unwrap();
+                return int;
+            }
+        }
+    }
+
+    // Reads from the start of the disc until the first sector arrives
+    fn cd_start_reading(bus: &mut Bus) {
+        bus.cdrom.insert_disc(cd_disc(4));
+        bus.mem_write_word(0x1F8010F0, 0x8000).unwrap();
+        for param in [0x00, 0x02, 0x00] {
+            bus.mem_write_byte(0x1F801802, param).unwrap();
+        }
+        bus.mem_write_byte(0x1F801801, 0x02).unwrap();
+        assert_eq!(cd_wait(bus), 3);
+        bus.mem_write_byte(0x1F801801, 0x06).unwrap();
+        assert_eq!(cd_wait(bus), 3);
+        assert_eq!(cd_wait(bus), 1);
+    }
+
+    // Sets the SPU transfer address and mode (2 DMA write, 3 DMA read)
+    fn spu_transfer(bus: &mut Bus, address: u16, mode: u16) {
+        bus.mem_write_halfword(0x1F801DAA, mode << 4).unwrap();
+        bus.mem_write_halfword(0x1F801DA6, address).unwrap();
+    }
+
+    // Uploads tables, then decodes one 8-bit monochrome block
+    fn mdec_commands() -> Vec<u32> {
+        let mut words = vec![0x6000_0000];
+        words.extend((0..32).map(|i| 0x5A82_5A82 - i * 0x0101_0101));
+        words.push(0x4000_0000);
+        words.extend((0..16).map(|i| 0x0403_0201 + i * 0x0404_0404));
+        // DC only block with a scale of 1, then the end of block code
+        words.extend([0x2800_0001, 0xFE00_0464]);
+        words
+    }
+
+    #[test]
+    fn mdec_is_fed_and_drained_by_dma() {
+        with_bus(|bus| {
+            let commands = mdec_commands();
+            write_words(bus, 0x1000, &commands);
+            bus.mem_write_word(0x1F8010F0, 0x88).unwrap();
+            bus.mem_write_word(0x1F801824, 0x6000_0000).unwrap();
+
+            // Output waits for the block to be decoded
+            start_dma(bus, 0x1F801090, 0x2000, 16 | (1 << 16), 0x0100_0200);
+            assert!(busy(bus, 0x1F801090));
+            start_dma(
+                bus,
+                0x1F801080,
+                0x1000,
+                commands.len() as u32 | (1 << 16),
+                0x0100_0201,
+            );
+            assert!(!busy(bus, 0x1F801080));
+            assert!(!busy(bus, 0x1F801090));
+            assert_eq!(bus.dma0.madr_read(), 0x1000 + 4 * commands.len() as u32);
+            assert_eq!(bus.dma1.madr_read(), 0x2000 + 4 * 16);
+
+            let mut mdec = Mdec::new();
+            for word in commands {
+                mdec.command_write(word);
+            }
+            let expected: Vec<_> = (0..16).map(|_| mdec.data_read()).collect();
+            assert!(expected.iter().any(|&word| word != 0));
+            assert_eq!(read_words(bus, 0x2000, 16), expected);
+            // Everything decoded went out
+            assert_eq!(bus.mdec.output_words(), 0);
+        });
+    }
+
+    #[test]
+    fn mdec_output_is_taken_a_block_at_a_time() {
+        with_bus(|bus| {
+            bus.mem_write_word(0x1F8010F0, 0x88).unwrap();
+            bus.mem_write_word(0x1F801824, 0x6000_0000).unwrap();
+            // Four blocks of 8 words, only two of which are decoded yet
+            start_dma(bus, 0x1F801090, 0x2000, 8 | (4 << 16), 0x0100_0200);
+            for word in mdec_commands() {
+                bus.mem_write_word(0x1F801820, word).unwrap();
+            }
+            assert_eq!(bus.dma1.block_control_read() >> 16, 2);
+            assert!(busy(bus, 0x1F801090));
+
+            // The rest follows once another block is decoded
+            for word in [0x2800_0001, 0xFE00_0464] {
+                bus.mem_write_word(0x1F801820, word).unwrap();
+            }
+            assert!(!busy(bus, 0x1F801090));
+            assert_eq!(bus.dma1.madr_read(), 0x2000 + 4 * 32);
+        });
+    }
+
+    #[test]
+    fn linked_list_mdec_transfers_are_ignored() {
+        with_bus(|bus| {
+            write_words(bus, 0x1000, &mdec_commands());
+            bus.mem_write_word(0x1F8010F0, 0x88).unwrap();
+            bus.mem_write_word(0x1F801824, 0x6000_0000).unwrap();
+            start_dma(bus, 0x1F801080, 0x1000, 0, 0x0100_0401);
+            assert!(!busy(bus, 0x1F801080));
+            assert_eq!(bus.dma0.madr_read(), 0x1000);
+            assert_eq!(bus.mdec.status() & 0x2000_0000, 0);
+            assert_eq!(bus.mdec.output_words(), 0);
+
+            for word in mdec_commands() {
+                bus.mem_write_word(0x1F801820, word).unwrap();
+            }
+            start_dma(bus, 0x1F801090, 0x2000, 0, 0x0100_0400);
+            assert!(!busy(bus, 0x1F801090));
+            assert_eq!(bus.mdec.output_words(), 16);
+            assert_eq!(bus.mem_read_word(0x2000).unwrap(), 0);
+        });
+    }
+
+    #[test]
+    fn cdrom_sectors_are_read_by_dma() {
+        with_bus(|bus| {
+            cd_start_reading(bus);
+            bus.mem_write_byte(0x1F801803, 0x80).unwrap();
+            start_dma(bus, 0x1F8010B0, 0x1000, 512, 0x1100_0000);
+            assert!(!busy(bus, 0x1F8010B0));
+            assert_eq!(bus.dma3.madr_read(), 0x1000 + 4 * 512);
+            assert_eq!(read_words(bus, 0x1000, 512), sector_words(0));
+
+            // The next sector is read once it arrives and is asked for
+            assert_eq!(cd_wait(bus), 1);
+            bus.mem_write_byte(0x1F801803, 0x80).unwrap();
+            start_dma(bus, 0x1F8010B0, 0x1000, 512, 0x1100_0000);
+            assert_eq!(read_words(bus, 0x1000, 512), sector_words(1));
+        });
+    }
+
+    #[test]
+    fn sector_being_read_is_not_overwritten() {
+        with_bus(|bus| {
+            cd_start_reading(bus);
+            bus.mem_write_byte(0x1F801803, 0x80).unwrap();
+            start_dma(bus, 0x1F8010B0, 0x1000, 256, 0x1100_0000);
+
+            // Sector 1 fills the other buffer, then sector 2 replaces it unread
+            assert_eq!(cd_wait(bus), 1);
+            assert_eq!(cd_wait(bus), 1);
+            // Asking again mid sector carries on where the transfer stopped
+            bus.mem_write_byte(0x1F801803, 0x80).unwrap();
+            start_dma(bus, 0x1F8010B0, 0x1400, 256, 0x1100_0000);
+            assert_eq!(read_words(bus, 0x1000, 512), sector_words(0));
+
+            bus.mem_write_byte(0x1F801803, 0x80).unwrap();
+            start_dma(bus, 0x1F8010B0, 0x1000, 512, 0x1100_0000);
+            assert_eq!(read_words(bus, 0x1000, 512), sector_words(2));
+        });
+    }
+
+    #[test]
+    fn linked_list_cdrom_transfers_are_ignored() {
+        with_bus(|bus| {
+            cd_start_reading(bus);
+            bus.mem_write_byte(0x1F801803, 0x80).unwrap();
+            start_dma(bus, 0x1F8010B0, 0x1000, 512, 0x0100_0400);
+            assert!(!busy(bus, 0x1F8010B0));
+            assert_eq!(bus.dma3.madr_read(), 0x1000);
+            assert_eq!(bus.mem_read_word(0x1000).unwrap(), 0);
+
+            // Nothing was taken from the sector
+            start_dma(bus, 0x1F8010B0, 0x1000, 512, 0x1100_0000);
+            assert_eq!(read_words(bus, 0x1000, 512), sector_words(0));
+        });
+    }
+
+    #[test]
+    fn spu_ram_round_trips_through_dma() {
+        with_bus(|bus| {
+            let words: Vec<u32> = (0..64).map(|i| 0x1234_5678 ^ (i * 0x0101_0101)).collect();
+            write_words(bus, 0x1000, &words);
+            bus.mem_write_word(0x1F8010F0, 0x80000).unwrap();
+
+            spu_transfer(bus, 0x200, 2);
+            start_dma(bus, 0x1F8010C0, 0x1000, 16 | (4 << 16), 0x0100_0201);
+            assert!(!busy(bus, 0x1F8010C0));
+            assert_eq!(bus.dma4.madr_read(), 0x1000 + 4 * 64);
+
+            spu_transfer(bus, 0x200, 3);
+            start_dma(bus, 0x1F8010C0, 0x2000, 16 | (4 << 16), 0x0100_0200);
+            assert!(!busy(bus, 0x1F8010C0));
+            assert_eq!(read_words(bus, 0x2000, 64), words);
+        });
+    }
+
+    #[test]
+    fn linked_list_spu_transfers_are_ignored() {
+        with_bus(|bus| {
+            write_words(bus, 0x1000, &[0xDEAD_BEEF; 16]);
+            bus.mem_write_word(0x1F8010F0, 0x80000).unwrap();
+
+            spu_transfer(bus, 0x200, 2);
+            start_dma(bus, 0x1F8010C0, 0x1000, 0, 0x0100_0401);
+            assert!(!busy(bus, 0x1F8010C0));
+            assert_eq!(bus.dma4.madr_read(), 0x1000);
+
+            // Sound RAM was left alone
+            spu_transfer(bus, 0x200, 3);
+            start_dma(bus, 0x1F8010C0, 0x2000, 16, 0x0100_0200);
+            assert_eq!(read_words(bus, 0x2000, 16), [0; 16]);
+        });
+    }
+
+    #[test]
+    fn full_gp0_fifo_stalls_the_writer() {
+        with_bus(|bus| {
+            for word in [0xE3000000, 0xE4000000 | (511 << 10) | 1023] {
+                bus.mem_write_word(0x1F801810, word).unwrap();
+            }
+            // A 64x64 flat rect is 4096 GPU cycles, then NOPs fill the FIFO
+            for word in [0x600000FF, 0, (64 << 16) | 64] {
+                bus.mem_write_word(0x1F801810, word).unwrap();
+            }
+            for _ in 0..16 {
+                bus.mem_write_word(0x1F801810, 0).unwrap();
+            }
+            assert_eq!(bus.cycles, 0);
+
+            bus.mem_write_word(0x1F801810, 0).unwrap();
+            assert_eq!(bus.cycles, 2607);
+            assert_eq!(bus.gpu.gp0.busy_cycles, 0);
+        });
+    }
 }
@@ -0,0 +1,1403 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+use tracing::{Level, event};
+
+use crate::disc::{self, Disc, Region, SECTOR_SIZE, TrackKind};
+use crate::spu::{ADPCM_NEG, ADPCM_POS};
+
+// CPU cycles between a command write and its first response
+const COMMAND_DELAY: u32 = 25000;
+// CPU cycles between the first and second response
+const SECOND_DELAY: u32 = 50000;
+// CPU cycles per sector at single speed (33.8688 MHz / 75)
+const SECTOR_DELAY: u32 = 451584;
+// CPU cycles for the motor to reach speed from a stop, about half a second
+const SPIN_UP_DELAY: u32 = 16934400;
+// Seeks take a fixed settle time plus a time per sector crossed. A seek across the whole
+// disc takes about a second
+const SEEK_DELAY: u32 = 100000;
+const SEEK_DELAY_PER_SECTOR: u32 = 100;
+// CD audio samples kept for the SPU, about a tenth of a second
+const AUDIO_CAPACITY: usize = 4410;
+
+// Status byte bits
+const STAT_ERROR: u8 = 0x1;
+const STAT_MOTOR: u8 = 0x2;
+const STAT_SHELL_OPEN: u8 = 0x10;
+const STAT_READ: u8 = 0x20;
+const STAT_SEEK: u8 = 0x40;
+const STAT_PLAY: u8 = 0x80;
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+enum CdEvent {
+    // First response to a command
+    Command(u8),
+    // Second response to a command
+    Finish(u8),
+    // Seek for the given command has reached the target
+    Seek(u8),
+    // Next sector of a read
+    Sector,
+    // A read was aborted by opening the lid
+    LidOpened,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CdRom {
+    index: u8,
+    parameter_fifo: VecDeque<u8>,
+    response_fifo: VecDeque<u8>,
+    interrupt_enable: u8,
+    // Bits 0-2 hold the pending response type (INT1-INT5)
+    interrupt_flag: u8,
+    stat: u8,
+    mode: u8,
+    // CPU cycles until the motor is at speed while spinning up
+    spin_up: Option<u32>,
+    // Pending events with the CPU cycles left until they fire, in the order scheduled
+    events: Vec<(u32, CdEvent)>,
+    // Double buffered sectors. A new sector never overwrites the buffer being read
+    sectors: [Vec<u8>; 2],
+    newest_sector: usize,
+    // Buffer exposed through the data FIFO and the read position within it
+    data_buffer: Option<usize>,
+    data_pos: usize,
+    seek_target: u32,
+    // Set until a read, seek or play moves to the Setloc position
+    setloc_pending: bool,
+    read_lba: u32,
+    // Sectors advanced per sector time while playing. Forward and Backward scan faster
+    play_step: i32,
+    // Header and subheader of the last data sector read, for GetlocL
+    last_header: Option<[u8; 8]>,
+    // CD audio volume (left to left, left to right, right to right, right to left)
+    volume: [u8; 4],
+    pending_volume: [u8; 4],
+    // XA-ADPCM file and channel from Setfilter, decoder history per channel and
+    // the resampling phase to 44.1kHz
+    xa_filter: (u8, u8),
+    xa_history: [[i32; 2]; 2],
+    xa_phase: u32,
+    xa_muted: bool,
+    // Stereo CD audio at 44.1kHz waiting for the SPU CD input
+    pub audio: VecDeque<(i16, i16)>,
+    lid_open: bool,
+    #[serde(skip)]
+    disc: Option<Disc>,
+}
+
+impl CdRom {
+    pub fn new() -> Self {
+        Self {
+            index: 0,
+            parameter_fifo: VecDeque::with_capacity(16),
+            response_fifo: VecDeque::with_capacity(16),
+            interrupt_enable: 0,
+            interrupt_flag: 0,
+            stat: 0,
+            mode: 0,
+            spin_up: None,
+            events: Vec::new(),
+            sectors: [Vec::new(), Vec::new()],
+            newest_sector: 0,
+            data_buffer: None,
+            data_pos: 0,
+            seek_target: 0,
+            setloc_pending: false,
+            read_lba: 0,
+            play_step: 1,
+            last_header: None,
+            volume: [0x80, 0, 0x80, 0],
+            pending_volume: [0x80, 0, 0x80, 0],
+            xa_filter: (0, 0),
+            xa_history: [[0; 2]; 2],
+            xa_phase: 0,
+            xa_muted: false,
+            audio: VecDeque::new(),
+            lid_open: false,
+            disc: None,
+        }
+    }
+
+    pub fn insert_disc(&mut self, disc: Disc) {
+        self.disc = Some(disc);
+        // Motor on
+        self.stat = STAT_MOTOR;
+    }
+
+    // Save states leave the disc out, so a restored drive keeps the one in the drive it replaces
+    pub fn take_disc_from(&mut self, old: &mut CdRom) {
+        self.disc = old.disc.take();
+    }
+
+    pub fn take_disc(&mut self) -> Option<Disc> {
+        self.disc.take()
+    }
+
+    pub fn disc_mut(&mut self) -> Option<&mut Disc> {
+        self.disc.as_mut()
+    }
+
+    pub fn disc_present(&self) -> bool {
+        self.disc.is_some()
+    }
+
+    // Stops the motor and removes the disc. A read in progress fails with a door open error
+    pub fn open_lid(&mut self) {
+        if self.stat & (STAT_READ | STAT_SEEK | STAT_PLAY) > 0 {
+            self.schedule(0, CdEvent::LidOpened);
+        }
+        self.stop_reading();
+        self.lid_open = true;
+        self.disc = None;
+        self.spin_up = None;
+        self.last_header = None;
+        self.stat = STAT_SHELL_OPEN;
+    }
+
+    // The shell open bit stays set until read by GetStat so the BIOS notices the disc change
+    pub fn close_lid(&mut self, disc: Option<Disc>) {
+        self.lid_open = false;
+        self.disc = disc;
+        if self.disc.is_some() {
+            self.start_motor();
+        }
+    }
+
+    // Advance pending events by the given CPU cycles. Returns true if IRQ
+    pub fn tick(&mut self, cycles: u32) -> bool {
+        if let Some(spin_up) = self.spin_up {
+            if spin_up > cycles {
+                self.spin_up = Some(spin_up - cycles);
+            } else {
+                self.spin_up = None;
+                self.stat |= STAT_MOTOR;
+            }
+        }
+
+        for (delay, _) in &mut self.events {
+            *delay = delay.saturating_sub(cycles);
+        }
+
+        // Responses wait until the previous interrupt has been acknowledged
+        if self.interrupt_flag & 0x7 != 0 {
+            return false;
+        }
+
+        let Some(i) = self.events.iter().position(|(delay, _)| *delay == 0) else {
+            return false;
+        };
+
+        match self.events.remove(i).1 {
+            CdEvent::Command(command) => self.execute_command(command),
+            CdEvent::Finish(command) => self.finish_command(command),
+            CdEvent::Seek(command) => self.finish_seek(command),
+            CdEvent::Sector => self.deliver_sector(),
+            CdEvent::LidOpened => self.error(0x08),
+        }
+
+        self.interrupt_flag & self.interrupt_enable & 0x1F != 0
+    }
+
+    fn schedule(&mut self, delay: u32, event: CdEvent) {
+        self.events.push((delay, event));
+    }
+
+    fn stop_reading(&mut self) {
+        self.events
+            .retain(|(_, event)| !matches!(event, CdEvent::Sector | CdEvent::Seek(_)));
+        self.stat &= !(STAT_READ | STAT_SEEK | STAT_PLAY);
+    }
+
+    // Starts the motor if it is stopped. Returns the CPU cycles until it is at speed
+    fn start_motor(&mut self) -> u32 {
+        if self.stat & STAT_MOTOR == 0 && self.spin_up.is_none() {
+            self.spin_up = Some(SPIN_UP_DELAY);
+        }
+        self.spin_up.unwrap_or(0)
+    }
+
+    // Moves the head to the target once the motor is at speed. The command continues when
+    // the seek finishes
+    fn start_seek(&mut self, target: u32, command: u8) {
+        self.seek_target = target;
+        self.setloc_pending = false;
+        self.stat |= STAT_SEEK;
+
+        let distance = self.read_lba.abs_diff(target);
+        let delay =
+            self.start_motor() + SEEK_DELAY + distance.saturating_mul(SEEK_DELAY_PER_SECTOR);
+        self.schedule(delay, CdEvent::Seek(command));
+    }
+
+    // Setloc position if one is pending, otherwise the current position
+    fn seek_position(&self) -> u32 {
+        if self.setloc_pending {
+            self.seek_target
+        } else {
+            self.read_lba
+        }
+    }
+
+    // Mode bit 7 selects double speed
+    fn sector_delay(&self) -> u32 {
+        if self.mode & 0x80 > 0 {
+            SECTOR_DELAY / 2
+        } else {
+            SECTOR_DELAY
+        }
+    }
+
+    // Reads from 0x1F801800-0x1F801803
+    pub fn read(&mut self, offset: u32) -> u8 {
+        match offset {
+            0 => self.status(),
+            1 => self.response_fifo.pop_front().unwrap_or(0),
+            2 => self.read_data(),
+            3 => match self.index {
+                0 | 2 => self.interrupt_enable | 0xE0,
+                _ => self.interrupt_flag | 0xE0,
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    // Writes to 0x1F801800-0x1F801803
+    pub fn write(&mut self, offset: u32, val: u8) {
+        match (offset, self.index) {
+            (0, _) => self.index = val & 0x3,
+            (1, 0) => self.command_write(val),
+            (2, 0) => {
+                if self.parameter_fifo.len() < 16 {
+                    self.parameter_fifo.push_back(val);
+                }
+            }
+            (1, 3) => self.pending_volume[2] = val,
+            (2, 1) => self.interrupt_enable = val & 0x1F,
+            (2, 2) => self.pending_volume[0] = val,
+            (2, 3) => self.pending_volume[3] = val,
+            (3, 0) => {
+                // Request register. Bit 7 asks for the newest sector, unless one is still being read
+                if val & 0x80 == 0 {
+                    self.data_buffer = None;
+                } else if !self.data_fifo_ready() {
+                    self.data_buffer = Some(self.newest_sector);
+                    self.data_pos = 0;
+                }
+            }
+            (3, 1) => {
+                self.interrupt_flag &= !(val & 0x1F);
+                if val & 0x40 > 0 {
+                    self.parameter_fifo.clear();
+                }
+            }
+            (3, 2) => self.pending_volume[1] = val,
+            (3, 3) => {
+                // Bit 0 mutes XA-ADPCM and bit 5 applies the volume changes
+                self.xa_muted = val & 0x1 > 0;
+                if val & 0x20 > 0 {
+                    self.volume = self.pending_volume;
+                }
+            }
+            _ => {
+                event!(
+                    target: "ps1_emulator::CDROM",
+                    Level::DEBUG,
+                    "Write {:02X} to register {} index {} ignored",
+                    val,
+                    offset,
+                    self.index
+                );
+            }
+        }
+    }
+
+    // Reads a word from the data FIFO for DMA channel 3
+    pub fn dma_read(&mut self) -> u32 {
+        u32::from_le_bytes([
+            self.read_data(),
+            self.read_data(),
+            self.read_data(),
+            self.read_data(),
+        ])
+    }
+
+    fn data_fifo_ready(&self) -> bool {
+        self.data_buffer
+            .is_some_and(|buffer| self.data_pos < self.sectors[buffer].len())
+    }
+
+    // Reading past the end of the sector returns the last byte again
+    fn read_data(&mut self) -> u8 {
+        let Some(buffer) = self.data_buffer else {
+            return 0;
+        };
+
+        let sector = &self.sectors[buffer];
+        let byte = sector
+            .get(self.data_pos)
+            .or(sector.last())
+            .copied()
+            .unwrap_or(0);
+        self.data_pos = (self.data_pos + 1).min(sector.len());
+        byte
+    }
+
+    // Index/status register
+    fn status(&self) -> u8 {
+        let mut status = self.index;
+        if self.parameter_fifo.is_empty() {
+            status |= 0x8;
+        }
+        if self.parameter_fifo.len() < 16 {
+            status |= 0x10;
+        }
+        if !self.response_fifo.is_empty() {
+            status |= 0x20;
+        }
+        if self.data_fifo_ready() {
+            status |= 0x40;
+        }
+        if self
+            .events
+            .iter()
+            .any(|(_, event)| matches!(event, CdEvent::Command(_)))
+        {
+            status |= 0x80;
+        }
+        status
+    }
+
+    fn command_write(&mut self, command: u8) {
+        event!(target: "ps1_emulator::CDROM", Level::TRACE, "Command {:02X}", command);
+        self.schedule(COMMAND_DELAY, CdEvent::Command(command));
+    }
+
+    fn execute_command(&mut self, command: u8) {
+        let params: Vec<u8> = self.parameter_fifo.drain(..).collect();
+        event!(
+            target: "ps1_emulator::CDROM",
+            Level::DEBUG,
+            "Execute command {:02X} (parameters {:02X?})",
+            command,
+            params
+        );
+
+        // Only GetStat and Test work with the lid open
+        if self.lid_open && !matches!(command, 0x01 | 0x19) {
+            return self.error(0x08);
+        }
+
+        match command {
+            // GetStat. Reading the status clears the shell open bit once the lid is closed
+            0x01 => {
+                self.push_response(3, &[self.stat]);
+                if !self.lid_open {
+                    self.stat &= !STAT_SHELL_OPEN;
+                }
+            }
+            // Setloc
+            0x02 => {
+                let [minute, second, frame] = params[..] else {
+                    return self.error(0x20);
+                };
+                self.seek_target = disc::msf_to_lba(
+                    disc::from_bcd(minute),
+                    disc::from_bcd(second),
+                    disc::from_bcd(frame),
+                );
+                self.setloc_pending = true;
+                self.push_response(3, &[self.stat]);
+            }
+            // Play. An optional parameter selects the track
+            0x03 => {
+                if self.disc.is_none() {
+                    return self.error(0x80);
+                }
+                let target = match params.first().map(|&track| disc::from_bcd(track)) {
+                    Some(number) if number > 0 => {
+                        let start = self.disc.as_ref().and_then(|disc| {
+                            disc.tracks()
+                                .iter()
+                                .find(|track| track.number == number)
+                                .map(|track| track.start)
+                        });
+                        let Some(start) = start else {
+                            return self.error(0x10);
+                        };
+                        start
+                    }
+                    _ => self.seek_position(),
+                };
+                self.push_response(3, &[self.stat]);
+                self.stop_reading();
+                self.play_step = 1;
+                self.start_seek(target, command);
+            }
+            // Forward and Backward
+            0x04 | 0x05 => {
+                if self.stat & STAT_PLAY == 0 {
+                    return self.error(0x80);
+                }
+                self.play_step = if command == 0x04 { 8 } else { -8 };
+                self.push_response(3, &[self.stat]);
+            }
+            // ReadN and ReadS
+            0x06 | 0x1B => {
+                if self.disc.is_none() {
+                    return self.error(0x80);
+                }
+                self.push_response(3, &[self.stat]);
+                self.stop_reading();
+                self.start_seek(self.seek_position(), command);
+            }
+            // Stop
+            0x08 => {
+                self.push_response(3, &[self.stat]);
+                self.stop_reading();
+                self.stat &= !STAT_MOTOR;
+                self.spin_up = None;
+                self.schedule(SECOND_DELAY, CdEvent::Finish(command));
+            }
+            // Pause
+            0x09 => {
+                self.push_response(3, &[self.stat]);
+                self.stop_reading();
+                self.schedule(SECOND_DELAY, CdEvent::Finish(command));
+            }
+            // Init
+            0x0A => {
+                self.push_response(3, &[self.stat]);
+                self.stop_reading();
+                self.mode = 0;
+                self.stat &= STAT_MOTOR;
+                let delay = self.start_motor() + SECOND_DELAY;
+                self.schedule(delay, CdEvent::Finish(command));
+            }
+            // Setfilter
+            0x0D => {
+                let [file, channel] = params[..] else {
+                    return self.error(0x20);
+                };
+                self.xa_filter = (file, channel);
+                self.push_response(3, &[self.stat]);
+            }
+            // Setmode
+            0x0E => {
+                let [mode] = params[..] else {
+                    return self.error(0x20);
+                };
+                self.mode = mode;
+                self.push_response(3, &[self.stat]);
+            }
+            // GetlocL
+            0x10 => match self.last_header {
+                Some(header) => self.push_response(3, &header),
+                None => self.error(0x80),
+            },
+            // GetlocP
+            0x11 => {
+                let response = self.position(self.read_lba);
+                self.push_response(3, &response);
+            }
+            // GetTN
+            0x13 => {
+                let Some(disc) = self.disc.as_ref() else {
+                    return self.error(0x80);
+                };
+                let tracks = disc.tracks();
+                let first = tracks.first().map_or(1, |track| track.number);
+                let last = tracks.last().map_or(1, |track| track.number);
+                self.push_response(3, &[self.stat, disc::to_bcd(first), disc::to_bcd(last)]);
+            }
+            // GetTD. Track 0 is the lead out
+            0x14 => {
+                let Some(disc) = self.disc.as_ref() else {
+                    return self.error(0x80);
+                };
+                let [track] = params[..] else {
+                    return self.error(0x20);
+                };
+                let start = match disc::from_bcd(track) {
+                    0 => Some(disc.lead_out()),
+                    number => disc
+                        .tracks()
+                        .iter()
+                        .find(|track| track.number == number)
+                        .map(|track| track.start),
+                };
+                let Some(start) = start else {
+                    return self.error(0x10);
+                };
+                let (minute, second, _) = disc::lba_to_msf(start);
+                self.push_response(3, &[self.stat, disc::to_bcd(minute), disc::to_bcd(second)]);
+            }
+            // SeekL
+            0x15 => {
+                if self.disc.is_none() {
+                    return self.error(0x80);
+                }
+                self.push_response(3, &[self.stat]);
+                self.stop_reading();
+                self.start_seek(self.seek_position(), command);
+            }
+            // Test
+            0x19 => match params.first() {
+                // BIOS date and version of the PU-7 controller
+                Some(0x20) => self.push_response(3, &[0x94, 0x09, 0x19, 0xC0]),
+                Some(_) => self.error(0x10),
+                None => self.error(0x20),
+            },
+            // GetID
+            0x1A => {
+                self.push_response(3, &[self.stat]);
+                self.schedule(SECOND_DELAY, CdEvent::Finish(command));
+            }
+            _ => {
+                event!(
+                    target: "ps1_emulator::CDROM",
+                    Level::DEBUG,
+                    "Command {:02X} not implemented",
+                    command
+                );
+                // Invalid command
+                self.error(0x40);
+            }
+        }
+    }
+
+    fn finish_command(&mut self, command: u8) {
+        match command {
+            // GetID
+            0x1A => match self.disc.as_mut().and_then(|disc| disc.region()) {
+                Some(region) => {
+                    let letter = match region {
+                        Region::Japan => b'I',
+                        Region::NorthAmerica => b'A',
+                        Region::Europe => b'E',
+                    };
+                    self.push_response(2, &[self.stat, 0x00, 0x20, 0x00, b'S', b'C', b'E', letter])
+                }
+                // Unlicensed
+                None if self.disc.is_some() => {
+                    self.push_response(5, &[self.stat | 0x8, 0x80, 0, 0, 0, 0, 0, 0])
+                }
+                // No disc
+                None => self.push_response(5, &[0x8, 0x40, 0, 0, 0, 0, 0, 0]),
+            },
+            _ => self.push_response(2, &[self.stat]),
+        }
+    }
+
+    fn finish_seek(&mut self, command: u8) {
+        self.stat &= !STAT_SEEK;
+        self.read_lba = self.seek_target;
+        match command {
+            // Play
+            0x03 => {
+                self.stat |= STAT_PLAY;
+                self.schedule(self.sector_delay(), CdEvent::Sector);
+            }
+            // SeekL
+            0x15 => self.push_response(2, &[self.stat]),
+            // ReadN and ReadS
+            _ => {
+                self.stat |= STAT_READ;
+                self.schedule(self.sector_delay(), CdEvent::Sector);
+            }
+        }
+    }
+
+    // Track, index, position within the track and position on the disc from subchannel Q
+    fn position(&self, lba: u32) -> [u8; 8] {
+        let Some(disc) = self.disc.as_ref() else {
+            return [0; 8];
+        };
+
+        let q = disc.subq(lba);
+        [q[1], q[2], q[3], q[4], q[5], q[7], q[8], q[9]]
+    }
+
+    // Loads the sector at the read position, signals INT1 and schedules the next one
+    fn deliver_sector(&mut self) {
+        let Some(disc) = self.disc.as_mut() else {
+            self.stop_reading();
+            return self.error(0x80);
+        };
+
+        match disc.read_sector(self.read_lba) {
+            Ok(sector) if self.stat & STAT_PLAY > 0 => self.play_sector(&sector),
+            Ok(sector) if self.is_xa_audio(&sector) => {
+                self.last_header = sector[12..20].try_into().ok();
+                self.read_lba += 1;
+                self.decode_xa_sector(&sector);
+                self.schedule(self.sector_delay(), CdEvent::Sector);
+            }
+            Ok(sector) => {
+                let mut target = self.newest_sector ^ 1;
+                if self.data_buffer == Some(target) && self.data_fifo_ready() {
+                    // The unread newest sector is dropped
+                    target = self.newest_sector;
+                }
+
+                let buffer = &mut self.sectors[target];
+                buffer.clear();
+                // Mode bit 5 selects the whole sector after the sync bytes
+                if self.mode & 0x20 > 0 {
+                    buffer.extend(&sector[12..]);
+                } else {
+                    buffer.extend(&sector[24..2072]);
+                }
+                self.newest_sector = target;
+                self.last_header = sector[12..20].try_into().ok();
+                self.read_lba += 1;
+                self.push_response(1, &[self.stat]);
+                self.schedule(self.sector_delay(), CdEvent::Sector);
+            }
+            Err(err) => {
+                event!(
+                    target: "ps1_emulator::CDROM",
+                    Level::WARN,
+                    "Failed to read sector {}: {}",
+                    self.read_lba,
+                    err
+                );
+                self.stop_reading();
+                self.error(0x4);
+            }
+        }
+    }
+
+    fn play_sector(&mut self, sector: &[u8; SECTOR_SIZE]) {
+        let Some(disc) = self.disc.as_ref() else {
+            return;
+        };
+        let lba = self.read_lba;
+        let track = disc.track_at(lba).copied();
+        let lead_out = disc.lead_out();
+
+        // Data tracks play as silence
+        if track.is_some_and(|track| track.kind == TrackKind::Audio) {
+            self.queue_audio(sector);
+        }
+        self.read_lba = lba.saturating_add_signed(self.play_step);
+
+        // Mode bit 1 pauses at the end of the track. Playing always stops at the lead out
+        let track_end = track.map_or(u32::MAX, |track| track.start + track.length);
+        if self.read_lba >= lead_out || (self.mode & 0x2 > 0 && self.read_lba >= track_end) {
+            self.stop_reading();
+            return self.push_response(4, &[self.stat]);
+        }
+
+        // Mode bit 2 reports the position every 10 sectors
+        if self.mode & 0x4 > 0 && lba.is_multiple_of(10) {
+            let [track, index, _, _, _, minute, second, frame] = self.position(lba);
+            self.push_response(1, &[self.stat, track, index, minute, second, frame, 0, 0]);
+        }
+        self.schedule(self.sector_delay(), CdEvent::Sector);
+    }
+
+    // Mixes the 16 bit stereo samples of an audio sector through the CD volume
+    fn queue_audio(&mut self, sector: &[u8; SECTOR_SIZE]) {
+        for frame in sector.chunks_exact(4) {
+            let left = i16::from_le_bytes([frame[0], frame[1]]);
+            let right = i16::from_le_bytes([frame[2], frame[3]]);
+            self.queue_sample(left, right);
+        }
+    }
+
+    fn queue_sample(&mut self, left: i16, right: i16) {
+        let [ll, lr, rr, rl] = self.volume.map(i32::from);
+        let (left, right) = (left as i32, right as i32);
+        let out_left = ((left * ll + right * rl) >> 7).clamp(-0x8000, 0x7FFF);
+        let out_right = ((left * lr + right * rr) >> 7).clamp(-0x8000, 0x7FFF);
+        self.audio.push_back((out_left as i16, out_right as i16));
+
+        // The SPU takes these as it plays. If it falls behind, the oldest samples are dropped
+        // so CD audio doesn't lag further and further behind the game
+        if self.audio.len() > AUDIO_CAPACITY {
+            self.audio.pop_front();
+        }
+    }
+
+    // Mode bit 6 sends audio sectors to the decoder. With mode bit 3 set only sectors
+    // matching the Setfilter file and channel are decoded, the rest go to the data path
+    fn is_xa_audio(&self, sector: &[u8; SECTOR_SIZE]) -> bool {
+        let (file, channel, submode) = (sector[16], sector[17], sector[18]);
+        self.mode & 0x40 > 0
+            && sector[15] == 2
+            && submode & 0x4 > 0
+            && (self.mode & 0x8 == 0 || (file, channel) == self.xa_filter)
+    }
+
+    // Decodes the 18 sound groups of an XA-ADPCM sector and resamples them to 44.1kHz
+    fn decode_xa_sector(&mut self, sector: &[u8; SECTOR_SIZE]) {
+        let coding = sector[19];
+        let stereo = coding & 0x3 == 1;
+        let rate = if coding & 0xC == 0 { 37800 } else { 18900 };
+        let eight_bit = coding & 0x30 == 0x10;
+        let units = if eight_bit { 4 } else { 8 };
+
+        let mut samples: [Vec<i16>; 2] = Default::default();
+        for group in sector[24..24 + 18 * 128].chunks_exact(128) {
+            for unit in 0..units {
+                let header = group[4 + unit];
+                let shift = match header & 0xF {
+                    shift @ 0..=12 => shift,
+                    _ => 9,
+                };
+                let filter = ((header >> 4) & 0x3) as usize;
+                // Stereo sectors alternate left and right units
+                let channel = if stereo { unit & 1 } else { 0 };
+                let [old, older] = &mut self.xa_history[channel];
+
+                for i in 0..28 {
+                    let raw = if eight_bit {
+                        (group[16 + i * 4 + unit] as i16) << 8
+                    } else {
+                        let byte = group[16 + i * 4 + unit / 2];
+                        (((byte >> ((unit & 1) * 4)) & 0xF) as i16) << 12
+                    };
+                    let prediction =
+                        (*old * ADPCM_POS[filter] + *older * ADPCM_NEG[filter] + 32) >> 6;
+                    let sample = ((raw >> shift) as i32 + prediction).clamp(-0x8000, 0x7FFF);
+                    *older = *old;
+                    *old = sample;
+                    samples[channel].push(sample as i16);
+                }
+            }
+        }
+
+        if self.xa_muted {
+            return;
+        }
+
+        let [left, right] = &samples;
+        for (i, &sample) in left.iter().enumerate() {
+            let frame = (sample, if stereo { right[i] } else { sample });
+            // Each decoded sample is held for as many output samples as fit at 44.1kHz
+            self.xa_phase += 44100;
+            while self.xa_phase >= rate {
+                self.xa_phase -= rate;
+                self.queue_sample(frame.0, frame.1);
+            }
+        }
+    }
+
+    fn push_response(&mut self, int: u8, response: &[u8]) {
+        self.response_fifo.clear();
+        self.response_fifo.extend(response);
+        self.interrupt_flag = (self.interrupt_flag & !0x7) | int;
+    }
+
+    // INT5 with the error bit set in the status byte
+    fn error(&mut self, code: u8) {
+        self.push_response(5, &[self.stat | STAT_ERROR, code]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::*;
+
+    // Cycles ticked at a time while waiting for a response
+    const STEP: u32 = 1000;
+
+    // Writes the parameters and then the command
+    fn send(cdrom: &mut CdRom, command: u8, params: &[u8]) {
+        cdrom.write(0, 0);
+        for &param in params {
+            cdrom.write(2, param);
+        }
+        cdrom.write(1, command);
+    }
+
+    // Ticks until an interrupt is pending and acknowledges it. Returns the interrupt, the
+    // response and the cycles waited
+    fn next_response(cdrom: &mut CdRom) -> (u8, Vec<u8>, u32) {
+        let mut cycles = 0;
+        while cdrom.interrupt_flag & 0x7 == 0 {
+            assert!(cycles < 100_000_000, "no response");
+            cdrom.tick(STEP);
+            cycles += STEP;
+        }
+
+        cdrom.write(0, 1);
+        let int = cdrom.read(3) & 0x7;
+        let mut response = Vec::new();
+        while cdrom.read(0) & 0x20 > 0 {
+            response.push(cdrom.read(1));
+        }
+        cdrom.write(3, 0x1F);
+        cdrom.write(0, 0);
+        (int, response, cycles)
+    }
+
+    fn respond(cdrom: &mut CdRom) -> (u8, Vec<u8>) {
+        let (int, response, _) = next_response(cdrom);
+        (int, response)
+    }
+
+    // Images written by testdata/cdrom/make_fixtures.py
+    fn fixture_path(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata/cdrom")
+            .join(name)
+    }
+
+    fn fixture(name: &str) -> Disc {
+        Disc::open(&fixture_path(name)).unwrap()
+    }
+
+    // Requests the newest sector and reads it through the data port
+    fn read_sector(cdrom: &mut CdRom, len: usize) -> Vec<u8> {
+        cdrom.write(0, 0);
+        cdrom.write(3, 0x80);
+        (0..len).map(|_| cdrom.read(2)).collect()
+    }
+
+    #[test]
+    fn index_selects_the_register_bank() {
+        let mut cdrom = CdRom::new();
+        for index in [1, 3, 2, 0] {
+            cdrom.write(0, index);
+            assert_eq!(cdrom.read(0) & 0x3, index);
+        }
+
+        // Register 2 is the parameter FIFO at index 0 and the interrupt enable at index 1
+        cdrom.write(0, 1);
+        cdrom.write(2, 0x1F);
+        assert_eq!(cdrom.read(3), 0xE0);
+        cdrom.write(0, 0);
+        assert_eq!(cdrom.read(3), 0xFF);
+        cdrom.write(2, 0x05);
+        assert_eq!(cdrom.read(3), 0xFF);
+        assert_eq!(cdrom.parameter_fifo, [0x05]);
+    }
+
+    #[test]
+    fn parameter_writes_update_the_fifo_status() {
+        let mut cdrom = CdRom::new();
+        // Parameter FIFO empty and not full
+        assert_eq!(cdrom.read(0), 0x18);
+
+        cdrom.write(2, 0x12);
+        assert_eq!(cdrom.read(0), 0x10);
+        for param in 1..16 {
+            cdrom.write(2, param);
+        }
+        assert_eq!(cdrom.read(0), 0x00);
+        // Writes to a full FIFO are dropped
+        cdrom.write(2, 0xFF);
+        assert_eq!(cdrom.parameter_fifo.len(), 16);
+
+        // Acknowledging with bit 6 set clears it
+        cdrom.write(0, 1);
+        cdrom.write(3, 0x40);
+        assert_eq!(cdrom.read(0), 0x19);
+
+        // The busy bit is set until the command runs, which takes the parameters
+        send(&mut cdrom, 0x01, &[0x34]);
+        assert_eq!(cdrom.read(0), 0x90);
+        cdrom.tick(COMMAND_DELAY);
+        assert_eq!(cdrom.read(0), 0x38);
+    }
+
+    #[test]
+    fn commands_respond_without_a_disc() {
+        let mut cdrom = CdRom::new();
+        send(&mut cdrom, 0x01, &[]);
+        assert_eq!(respond(&mut cdrom), (3, vec![0x00]));
+        // Unknown commands are an error
+        send(&mut cdrom, 0x55, &[]);
+        assert_eq!(respond(&mut cdrom), (5, vec![0x01, 0x40]));
+        send(&mut cdrom, 0x06, &[]);
+        assert_eq!(respond(&mut cdrom), (5, vec![0x01, 0x80]));
+    }
+
+    #[test]
+    fn iso_sectors_are_framed_as_mode2_form1() {
+        let iso = std::fs::read(fixture_path("game.iso")).unwrap();
+        let mut disc = fixture("game.iso");
+        for (lba, data) in iso.chunks_exact(2048).enumerate() {
+            let sector = disc.read_sector(lba as u32).unwrap();
+            assert_eq!(sector[24..2072], *data, "LBA {lba}");
+        }
+
+        // Whole sectors through the controller, starting at LBA 16
+        let mut cdrom = CdRom::new();
+        cdrom.insert_disc(disc);
+        for (command, params) in [
+            (0x0E, &[0x20][..]),
+            (0x02, &[0x00, 0x02, 0x16]),
+            (0x06, &[]),
+        ] {
+            send(&mut cdrom, command, params);
+            assert_eq!(respond(&mut cdrom).0, 3);
+        }
+        for lba in [16, 17] {
+            assert_eq!(respond(&mut cdrom).0, 1);
+            let sector = read_sector(&mut cdrom, 2340);
+            assert_eq!(sector[..4], [0x00, 0x02, disc::to_bcd(lba), 0x02]);
+            // Subheader with the data bit, stored twice
+            assert_eq!(sector[4..12], [0, 0, 0x08, 0, 0, 0, 0x08, 0]);
+            assert_eq!(sector[12..2060], iso[lba as usize * 2048..][..2048]);
+        }
+    }
+
+    #[test]
+    fn bios_boot_sequence() {
+        let iso = std::fs::read(fixture_path("game.iso")).unwrap();
+        let mut cdrom = CdRom::new();
+        cdrom.insert_disc(fixture("game.iso"));
+
+        send(&mut cdrom, 0x01, &[]);
+        assert_eq!(respond(&mut cdrom), (3, vec![0x02]));
+        send(&mut cdrom, 0x1A, &[]);
+        assert_eq!(respond(&mut cdrom), (3, vec![0x02]));
+        assert_eq!(respond(&mut cdrom), (2, b"\x02\x00\x20\x00SCEE".to_vec()));
+        send(&mut cdrom, 0x02, &[0x00, 0x02, 0x19]);
+        assert_eq!(respond(&mut cdrom), (3, vec![0x02]));
+        send(&mut cdrom, 0x15, &[]);
+        assert_eq!(respond(&mut cdrom), (3, vec![0x02]));
+        assert_eq!(respond(&mut cdrom), (2, vec![0x02]));
+
+        // Reading continues from the seek position with the read bit set
+        send(&mut cdrom, 0x06, &[]);
+        assert_eq!(respond(&mut cdrom), (3, vec![0x02]));
+        assert_eq!(respond(&mut cdrom), (1, vec![0x22]));
+        assert_eq!(read_sector(&mut cdrom, 2048), iso[19 * 2048..]);
+        send(&mut cdrom, 0x09, &[]);
+        assert_eq!(respond(&mut cdrom), (3, vec![0x22]));
+        assert_eq!(respond(&mut cdrom), (2, vec![0x02]));
+
+        send(&mut cdrom, 0x19, &[0x20]);
+        assert_eq!(respond(&mut cdrom), (3, vec![0x94, 0x09, 0x19, 0xC0]));
+        send(&mut cdrom, 0x0A, &[]);
+        assert_eq!(respond(&mut cdrom), (3, vec![0x02]));
+        assert_eq!(respond(&mut cdrom), (2, vec![0x02]));
+    }
+
+    #[test]
+    fn get_id_reports_unlicensed_and_missing_discs() {
+        let mut cdrom = CdRom::new();
+        cdrom.insert_disc(disc::test_iso("unlicensed", &[0; 2048 * 20]));
+        send(&mut cdrom, 0x1A, &[]);
+        assert_eq!(respond(&mut cdrom), (3, vec![0x02]));
+        assert_eq!(respond(&mut cdrom), (5, vec![0x0A, 0x80, 0, 0, 0, 0, 0, 0]));
+
+        let mut cdrom = CdRom::new();
+        send(&mut cdrom, 0x1A, &[]);
+        assert_eq!(respond(&mut cdrom), (3, vec![0x00]));
+        assert_eq!(respond(&mut cdrom), (5, vec![0x08, 0x40, 0, 0, 0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn responses_wait_for_the_previous_interrupt() {
+        let mut cdrom = CdRom::new();
+        cdrom.insert_disc(fixture("game.iso"));
+        send(&mut cdrom, 0x1A, &[]);
+        cdrom.tick(COMMAND_DELAY - 1);
+        assert_eq!(cdrom.interrupt_flag, 0);
+        cdrom.tick(1);
+        assert_eq!(cdrom.interrupt_flag, 3);
+
+        // The second response is due but held back until INT3 is acknowledged
+        cdrom.tick(SECOND_DELAY * 4);
+        assert_eq!(cdrom.interrupt_flag, 3);
+        assert_eq!(cdrom.response_fifo, [0x02]);
+        cdrom.write(0, 1);
+        cdrom.write(3, 0x07);
+        cdrom.tick(1);
+        assert_eq!(cdrom.interrupt_flag, 2);
+    }
+
+    #[test]
+    fn sectors_arrive_at_the_drive_speed() {
+        for (mode, delay) in [(0x00, SECTOR_DELAY), (0x80, SECTOR_DELAY / 2)] {
+            let mut cdrom = CdRom::new();
+            cdrom.insert_disc(fixture("game.iso"));
+            send(&mut cdrom, 0x0E, &[mode]);
+            respond(&mut cdrom);
+            send(&mut cdrom, 0x06, &[]);
+            assert_eq!(respond(&mut cdrom).0, 3);
+            assert_eq!(respond(&mut cdrom).0, 1);
+
+            for _ in 0..4 {
+                let (int, _, cycles) = next_response(&mut cdrom);
+                assert_eq!(int, 1);
+                assert!(
+                    cycles.abs_diff(delay) < STEP,
+                    "{cycles} cycles at mode {mode:02X}"
+                );
+                // The data FIFO stays empty until the sector is requested
+                assert_eq!(cdrom.read(0) & 0x40, 0);
+                cdrom.write(3, 0x80);
+                assert_eq!(cdrom.read(0) & 0x40, 0x40);
+                cdrom.write(3, 0x00);
+            }
+
+            // Pause stops the stream
+            send(&mut cdrom, 0x09, &[]);
+            assert_eq!(respond(&mut cdrom).0, 3);
+            assert_eq!(respond(&mut cdrom).0, 2);
+            cdrom.tick(SECTOR_DELAY * 4);
+            assert_eq!(cdrom.interrupt_flag, 0);
+        }
+    }
+
+    // Stereo samples of the audio sectors in mixed.bin
+    fn mixed_audio(sectors: std::ops::Range<usize>) -> Vec<(i16, i16)> {
+        let bin = std::fs::read(fixture_path("mixed.bin")).unwrap();
+        bin[sectors.start * SECTOR_SIZE..sectors.end * SECTOR_SIZE]
+            .chunks_exact(4)
+            .map(|s| {
+                (
+                    i16::from_le_bytes([s[0], s[1]]),
+                    i16::from_le_bytes([s[2], s[3]]),
+                )
+            })
+            .collect()
+    }
+
+    fn play_until(cdrom: &mut CdRom, samples: usize) {
+        while cdrom.audio.len() < samples {
+            cdrom.tick(STEP);
+        }
+    }
+
+    #[test]
+    fn audio_tracks_play_into_the_cd_input() {
+        let samples = mixed_audio(6..9);
+        let mut cdrom = CdRom::new();
+        cdrom.insert_disc(fixture("mixed.cue"));
+        // Pause at the end of the track
+        send(&mut cdrom, 0x0E, &[0x02]);
+        respond(&mut cdrom);
+        send(&mut cdrom, 0x03, &[0x02]);
+        assert_eq!(respond(&mut cdrom), (3, vec![0x02]));
+
+        for sector in 1..=2 {
+            play_until(&mut cdrom, sector as usize * 588);
+            assert_eq!(cdrom.audio, &samples[..sector as usize * 588]);
+            send(&mut cdrom, 0x11, &[]);
+            let position = [0x02, 0x01, 0x00, 0x00, sector, 0x00, 0x02, 0x06 + sector];
+            assert_eq!(respond(&mut cdrom), (3, position.to_vec()));
+        }
+
+        play_until(&mut cdrom, 3 * 588);
+        assert_eq!(cdrom.audio, samples);
+        assert_eq!(respond(&mut cdrom), (4, vec![0x02]));
+        // The pregap of track 3 counts down to its start
+        send(&mut cdrom, 0x11, &[]);
+        let position = [0x03, 0x00, 0x00, 0x02, 0x00, 0x00, 0x02, 0x09];
+        assert_eq!(respond(&mut cdrom), (3, position.to_vec()));
+    }
+
+    #[test]
+    fn cd_volume_mixes_the_channels() {
+        let samples = mixed_audio(6..7);
+        let mut cdrom = CdRom::new();
+        cdrom.insert_disc(fixture("mixed.cue"));
+        // Left at half volume to both outputs, right muted
+        cdrom.write(0, 2);
+        cdrom.write(2, 0x40);
+        cdrom.write(3, 0x40);
+        cdrom.write(0, 3);
+        cdrom.write(1, 0x00);
+        cdrom.write(2, 0x00);
+        cdrom.write(3, 0x20);
+
+        send(&mut cdrom, 0x03, &[0x02]);
+        respond(&mut cdrom);
+        play_until(&mut cdrom, 588);
+        let expected: Vec<_> = samples
+            .iter()
+            .map(|&(left, _)| (left >> 1, left >> 1))
+            .collect();
+        assert_eq!(cdrom.audio, expected);
+    }
+
+    // Starts reading xa.bin with the XA filter on
+    fn read_xa(channel: u8) -> CdRom {
+        let mut cdrom = CdRom::new();
+        cdrom.insert_disc(fixture("xa.bin"));
+        send(&mut cdrom, 0x0E, &[0x48]);
+        respond(&mut cdrom);
+        send(&mut cdrom, 0x0D, &[0x01, channel]);
+        respond(&mut cdrom);
+        send(&mut cdrom, 0x1B, &[]);
+        assert_eq!(respond(&mut cdrom).0, 3);
+        cdrom
+    }
+
+    #[test]
+    fn xa_sector_decodes_to_the_reference_pcm() {
+        let pcm = std::fs::read(fixture_path("xa.pcm")).unwrap();
+        let expected: Vec<_> = pcm
+            .chunks_exact(4)
+            .map(|s| {
+                (
+                    i16::from_le_bytes([s[0], s[1]]),
+                    i16::from_le_bytes([s[2], s[3]]),
+                )
+            })
+            .collect();
+
+        let mut cdrom = read_xa(0);
+        play_until(&mut cdrom, 1);
+        assert_eq!(cdrom.audio, expected);
+        // Audio sectors don't go to the data FIFO
+        assert_eq!(cdrom.interrupt_flag, 0);
+    }
+
+    #[test]
+    fn xa_sectors_for_other_channels_are_data() {
+        let mut cdrom = read_xa(1);
+        assert_eq!(respond(&mut cdrom), (1, vec![0x22]));
+        assert!(cdrom.audio.is_empty());
+        let sector = read_sector(&mut cdrom, 2048);
+        let xa = std::fs::read(fixture_path("xa.bin")).unwrap();
+        assert_eq!(sector, xa[24..2072]);
+    }
+
+    #[test]
+    fn table_of_contents_of_a_multi_track_cue() {
+        let disc = fixture("mixed.cue");
+        let layout: Vec<_> = disc
+            .tracks()
+            .iter()
+            .map(|track| (track.number, track.kind, track.start, track.length))
+            .collect();
+        assert_eq!(
+            layout,
+            [
+                (1, TrackKind::Data, 0, 6),
+                (2, TrackKind::Audio, 6, 3),
+                (3, TrackKind::Audio, 159, 3),
+            ]
+        );
+
+        let mut cdrom = CdRom::new();
+        cdrom.insert_disc(disc);
+        send(&mut cdrom, 0x13, &[]);
+        assert_eq!(respond(&mut cdrom), (3, vec![0x02, 0x01, 0x03]));
+        // Track 0 is the lead out at 00:04:12
+        for (track, minute, second) in [(0x00, 0x00, 0x04), (0x01, 0x00, 0x02), (0x03, 0x00, 0x04)]
+        {
+            send(&mut cdrom, 0x14, &[track]);
+            assert_eq!(respond(&mut cdrom), (3, vec![0x02, minute, second]));
+        }
+        send(&mut cdrom, 0x14, &[0x04]);
+        assert_eq!(respond(&mut cdrom), (5, vec![0x03, 0x10]));
+    }
+
+    #[test]
+    fn getlocl_returns_the_last_data_header() {
+        let mut cdrom = CdRom::new();
+        cdrom.insert_disc(fixture("mixed.cue"));
+        send(&mut cdrom, 0x10, &[]);
+        assert_eq!(respond(&mut cdrom), (5, vec![0x03, 0x80]));
+
+        send(&mut cdrom, 0x02, &[0x00, 0x02, 0x02]);
+        respond(&mut cdrom);
+        send(&mut cdrom, 0x06, &[]);
+        assert_eq!(respond(&mut cdrom).0, 3);
+        assert_eq!(respond(&mut cdrom).0, 1);
+        send(&mut cdrom, 0x10, &[]);
+        let header = vec![0x00, 0x02, 0x02, 0x02, 0x00, 0x00, 0x08, 0x00];
+        assert_eq!(respond(&mut cdrom), (3, header));
+    }
+
+    // Cycles from the SeekL command finishing to the seek reaching the target
+    fn seek_time(cdrom: &mut CdRom, target: [u8; 3]) -> u32 {
+        send(cdrom, 0x02, &target);
+        respond(cdrom);
+        send(cdrom, 0x15, &[]);
+        assert_eq!(respond(cdrom), (3, vec![0x02]));
+        // The seek bit is set while the head moves
+        assert_eq!(cdrom.stat, 0x42);
+        let (int, response, cycles) = next_response(cdrom);
+        assert_eq!((int, response), (2, vec![0x02]));
+        cycles
+    }
+
+    #[test]
+    fn long_seeks_take_longer_than_short_ones() {
+        let mut cdrom = CdRom::new();
+        cdrom.insert_disc(fixture("game.iso"));
+        let short = seek_time(&mut cdrom, [0x00, 0x02, 0x01]);
+        let long = seek_time(&mut cdrom, [0x70, 0x00, 0x00]);
+        let back = seek_time(&mut cdrom, [0x00, 0x02, 0x00]);
+        assert!(short < SEEK_DELAY + 2 * STEP, "{short} cycles");
+        assert!(long > short * 100, "{long} against {short} cycles");
+        // Seeking back the same distance takes as long
+        assert!(long.abs_diff(back) <= STEP, "{long} against {back} cycles");
+    }
+
+    #[test]
+    fn stopped_motor_spins_up_before_reading() {
+        let mut cdrom = CdRom::new();
+        cdrom.insert_disc(fixture("game.iso"));
+        send(&mut cdrom, 0x08, &[]);
+        assert_eq!(respond(&mut cdrom), (3, vec![0x02]));
+        assert_eq!(respond(&mut cdrom), (2, vec![0x00]));
+
+        send(&mut cdrom, 0x06, &[]);
+        assert_eq!(respond(&mut cdrom), (3, vec![0x00]));
+        send(&mut cdrom, 0x01, &[]);
+        assert_eq!(respond(&mut cdrom), (3, vec![0x40]));
+        let (int, response, cycles) = next_response(&mut cdrom);
+        assert_eq!((int, response), (1, vec![0x22]));
+        assert!(cycles > SPIN_UP_DELAY - COMMAND_DELAY, "{cycles} cycles");
+    }
+
+    #[test]
+    fn double_speed_halves_the_sector_interval() {
+        let interval = |mode| {
+            let mut cdrom = CdRom::new();
+            cdrom.insert_disc(fixture("game.iso"));
+            send(&mut cdrom, 0x0E, &[mode]);
+            respond(&mut cdrom);
+            send(&mut cdrom, 0x06, &[]);
+            respond(&mut cdrom);
+            respond(&mut cdrom);
+            next_response(&mut cdrom).2
+        };
+        let (single, double) = (interval(0x00), interval(0x80));
+        assert!(
+            single.abs_diff(double * 2) <= 2 * STEP,
+            "{single} and {double} cycles"
+        );
+    }
+
+    #[test]
+    fn chd_sectors_match_the_bin() {
+        let mut cue = fixture("mixed.cue");
+        let mut chd = fixture("mixed.chd");
+        let layout = |disc: &Disc| {
+            disc.tracks()
+                .iter()
+                .map(|track| (track.number, track.kind, track.start, track.length))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(layout(&chd), layout(&cue));
+        assert_eq!(chd.lead_out(), cue.lead_out());
+
+        // Out of order to go back to a hunk that was already read
+        for lba in (0..cue.lead_out() + 2).rev().chain(0..12) {
+            assert_eq!(
+                chd.read_sector(lba).unwrap(),
+                cue.read_sector(lba).unwrap(),
+                "LBA {lba}"
+            );
+        }
+
+        // Audio plays the same from either image
+        let play = |disc| {
+            let mut cdrom = CdRom::new();
+            cdrom.insert_disc(disc);
+            send(&mut cdrom, 0x03, &[0x03]);
+            respond(&mut cdrom);
+            play_until(&mut cdrom, 3 * 588);
+            cdrom.audio
+        };
+        assert_eq!(play(chd), play(cue));
+        assert_eq!(play(fixture("mixed.chd")), mixed_audio(9..12));
+    }
+
+    #[test]
+    fn sbi_replaces_subchannel_q_of_the_listed_sectors() {
+        let mut disc = fixture("mixed.cue");
+        let lbas = 0..disc.lead_out() + 4;
+        let before: Vec<_> = lbas.clone().map(|lba| disc.subq(lba)).collect();
+        disc.load_sbi(&fixture_path("libcrypt.sbi")).unwrap();
+        let changed: Vec<_> = lbas
+            .filter(|&lba| disc.subq(lba)[10..] != before[lba as usize][10..])
+            .collect();
+        assert_eq!(changed, [3, 5]);
+        assert_eq!(
+            disc.subq(5)[..10],
+            [0x41, 0x01, 0x01, 0x00, 0x00, 0x05, 0x00, 0x00, 0x02, 0x25]
+        );
+
+        // GetlocP reports the replaced position
+        let mut cdrom = CdRom::new();
+        cdrom.insert_disc(disc);
+        send(&mut cdrom, 0x02, &[0x00, 0x02, 0x03]);
+        respond(&mut cdrom);
+        send(&mut cdrom, 0x15, &[]);
+        respond(&mut cdrom);
+        respond(&mut cdrom);
+        send(&mut cdrom, 0x11, &[]);
+        let position = vec![0x01, 0x01, 0x00, 0x00, 0x13, 0x00, 0x02, 0x03];
+        assert_eq!(respond(&mut cdrom), (3, position));
+    }
+
+    #[test]
+    fn lid_swap_sequence() {
+        let mut cdrom = CdRom::new();
+        cdrom.insert_disc(fixture("game.iso"));
+        send(&mut cdrom, 0x1A, &[]);
+        assert_eq!(respond(&mut cdrom), (3, vec![0x02]));
+        assert_eq!(respond(&mut cdrom), (2, b"\x02\x00\x20\x00SCEE".to_vec()));
+
+        // Opening the lid fails the read in progress
+        send(&mut cdrom, 0x06, &[]);
+        respond(&mut cdrom);
+        assert_eq!(respond(&mut cdrom), (1, vec![0x22]));
+        cdrom.open_lid();
+        assert_eq!(respond(&mut cdrom), (5, vec![0x11, 0x08]));
+        for _ in 0..2 {
+            send(&mut cdrom, 0x01, &[]);
+            assert_eq!(respond(&mut cdrom), (3, vec![0x10]));
+        }
+        send(&mut cdrom, 0x1A, &[]);
+        assert_eq!(respond(&mut cdrom), (5, vec![0x11, 0x08]));
+        cdrom.tick(SECTOR_DELAY * 4);
+        assert_eq!(cdrom.interrupt_flag, 0);
+
+        // The shell open bit is reported once after closing, then the motor spins up
+        let mut license = vec![0; 2048 * 5];
+        license[2048 * 4..][..32].copy_from_slice(b"Sony Computer Entertainment Inc.");
+        cdrom.close_lid(Some(disc::test_iso("japan", &license)));
+        send(&mut cdrom, 0x01, &[]);
+        assert_eq!(respond(&mut cdrom), (3, vec![0x10]));
+        send(&mut cdrom, 0x01, &[]);
+        assert_eq!(respond(&mut cdrom), (3, vec![0x00]));
+        cdrom.tick(SPIN_UP_DELAY);
+        send(&mut cdrom, 0x01, &[]);
+        assert_eq!(respond(&mut cdrom), (3, vec![0x02]));
+        send(&mut cdrom, 0x1A, &[]);
+        assert_eq!(respond(&mut cdrom), (3, vec![0x02]));
+        assert_eq!(respond(&mut cdrom), (2, b"\x02\x00\x20\x00SCEI".to_vec()));
+    }
+
+    #[test]
+    fn region_and_boot_executable_of_the_fixture() {
+        let mut disc = fixture("game.iso");
+        assert_eq!(disc.region(), Some(Region::Europe));
+        assert_eq!(disc.boot_executable().unwrap(), "SLES_123.45;1");
+        let cnf = disc.read_file("system.cnf").unwrap();
+        assert!(cnf.starts_with(b"BOOT = cdrom:\\SLES_123.45;1\r\n"));
+
+        // Without SYSTEM.CNF the BIOS boots PSX.EXE
+        let mut iso = std::fs::read(fixture_path("game.iso")).unwrap();
+        iso[18 * 2048 + 68..19 * 2048].fill(0);
+        let mut disc = disc::test_iso("no_cnf", &iso);
+        assert_eq!(disc.boot_executable().unwrap(), "PSX.EXE");
+
+        // The region follows "Sony Computer Entertainment " in the license text
+        let region = 4 * 2048 + 60;
+        iso[region..region + 10].copy_from_slice(b"Amer  ica ");
+        assert_eq!(
+            disc::test_iso("america", &iso).region(),
+            Some(Region::NorthAmerica)
+        );
+        iso[region - 28..region].fill(b' ');
+        assert_eq!(disc::test_iso("no_license", &iso).region(), None);
+    }
+}
@@ -0,0 +1,551 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use flate2::read::DeflateDecoder;
+
+use crate::disc::SECTOR_SIZE;
+
+// CD frames are a raw sector followed by 96 bytes of subcode
+const FRAME_SIZE: usize = 2448;
+const SUBCODE_SIZE: usize = 96;
+// Decompressed hunks kept around for streaming reads
+const CACHE_SIZE: usize = 8;
+
+// Hunk map entry types
+const COMPRESSION_TYPE_3: u8 = 3;
+const COMPRESSION_NONE: u8 = 4;
+const COMPRESSION_SELF: u8 = 5;
+const COMPRESSION_PARENT: u8 = 6;
+const COMPRESSION_RLE_SMALL: u8 = 7;
+const COMPRESSION_RLE_LARGE: u8 = 8;
+const COMPRESSION_SELF_0: u8 = 9;
+const COMPRESSION_SELF_1: u8 = 10;
+const COMPRESSION_PARENT_SELF: u8 = 11;
+const COMPRESSION_PARENT_0: u8 = 12;
+const COMPRESSION_PARENT_1: u8 = 13;
+
+const SYNC: [u8; 12] = [
+    0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00,
+];
+
+pub struct ChdTrack {
+    pub number: u8,
+    pub audio: bool,
+    // Frames stored for the track, including a stored pregap
+    pub frames: u32,
+    pub pregap: u32,
+    pub pregap_stored: bool,
+}
+
+#[derive(Clone, Copy)]
+struct MapEntry {
+    kind: u8,
+    offset: u64,
+    length: u32,
+}
+
+// MAME compressed hunks of data (V5 only) holding a CD image
+pub struct Chd {
+    file: File,
+    hunk_bytes: usize,
+    compressors: [[u8; 4]; 4],
+    map: Vec<MapEntry>,
+    tracks: Vec<ChdTrack>,
+    // Most recently used hunk first
+    cache: Vec<(u32, Vec<u8>)>,
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes(bytes[..4].try_into().unwrap())
+}
+
+fn be_u64(bytes: &[u8]) -> u64 {
+    u64::from_be_bytes(bytes[..8].try_into().unwrap())
+}
+
+impl Chd {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let mut header = [0; 124];
+        file.read_exact(&mut header)?;
+        if &header[0..8] != b"MComprHD" {
+            return Err(invalid("not a CHD file"));
+        }
+        if be_u32(&header[12..]) != 5 {
+            return Err(invalid("only version 5 CHD files are supported"));
+        }
+
+        let mut compressors = [[0; 4]; 4];
+        for (i, compressor) in compressors.iter_mut().enumerate() {
+            compressor.copy_from_slice(&header[16 + i * 4..20 + i * 4]);
+        }
+        let logical_bytes = be_u64(&header[32..]);
+        let map_offset = be_u64(&header[40..]);
+        let meta_offset = be_u64(&header[48..]);
+        let hunk_bytes = be_u32(&header[56..]) as usize;
+        let unit_bytes = be_u32(&header[60..]) as usize;
+        if unit_bytes != FRAME_SIZE || !hunk_bytes.is_multiple_of(FRAME_SIZE) {
+            return Err(invalid("CHD file is not a CD image"));
+        }
+
+        let hunk_count = logical_bytes.div_ceil(hunk_bytes as u64) as usize;
+        let map = if compressors[0] == [0; 4] {
+            read_raw_map(&mut file, map_offset, hunk_count, hunk_bytes)?
+        } else {
+            read_compressed_map(&mut file, map_offset, hunk_count, hunk_bytes, unit_bytes)?
+        };
+        let tracks = read_tracks(&mut file, meta_offset)?;
+
+        Ok(Self {
+            file,
+            hunk_bytes,
+            compressors,
+            map,
+            tracks,
+            cache: Vec::new(),
+        })
+    }
+
+    pub fn tracks(&self) -> &[ChdTrack] {
+        &self.tracks
+    }
+
+    // Reads the sector part of the given frame
+    pub fn read_frame(&mut self, frame: u32, sector: &mut [u8; SECTOR_SIZE]) -> io::Result<()> {
+        let offset = frame as usize * FRAME_SIZE;
+        let hunk = (offset / self.hunk_bytes) as u32;
+        let start = offset % self.hunk_bytes;
+        let data = self.hunk(hunk)?;
+        sector.copy_from_slice(&data[start..start + SECTOR_SIZE]);
+        Ok(())
+    }
+
+    fn hunk(&mut self, hunk: u32) -> io::Result<&[u8]> {
+        match self.cache.iter().position(|(cached, _)| *cached == hunk) {
+            Some(i) => {
+                let entry = self.cache.remove(i);
+                self.cache.insert(0, entry);
+            }
+            None => {
+                let data = self.decompress_hunk(hunk)?;
+                self.cache.insert(0, (hunk, data));
+                self.cache.truncate(CACHE_SIZE);
+            }
+        }
+        Ok(&self.cache[0].1)
+    }
+
+    fn decompress_hunk(&mut self, hunk: u32) -> io::Result<Vec<u8>> {
+        let entry = *self
+            .map
+            .get(hunk as usize)
+            .ok_or_else(|| invalid("hunk out of range"))?;
+
+        match entry.kind {
+            0..=COMPRESSION_TYPE_3 => {
+                let mut compressed = vec![0; entry.length as usize];
+                self.file.seek(SeekFrom::Start(entry.offset))?;
+                self.file.read_exact(&mut compressed)?;
+                match &self.compressors[entry.kind as usize] {
+                    b"cdlz" => decompress_cd(&compressed, self.hunk_bytes, inflate_lzma),
+                    b"cdzl" => decompress_cd(&compressed, self.hunk_bytes, inflate),
+                    _ => Err(invalid("unsupported CHD codec")),
+                }
+            }
+            COMPRESSION_NONE => {
+                let mut data = vec![0; self.hunk_bytes];
+                self.file.seek(SeekFrom::Start(entry.offset))?;
+                self.file.read_exact(&mut data)?;
+                Ok(data)
+            }
+            COMPRESSION_SELF => self.hunk(entry.offset as u32).map(|data| data.to_vec()),
+            _ => Err(invalid("parent CHD files are not supported")),
+        }
+    }
+}
+
+fn read_raw_map(
+    file: &mut File,
+    map_offset: u64,
+    hunk_count: usize,
+    hunk_bytes: usize,
+) -> io::Result<Vec<MapEntry>> {
+    let mut raw = vec![0; hunk_count * 4];
+    file.seek(SeekFrom::Start(map_offset))?;
+    file.read_exact(&mut raw)?;
+    Ok(raw
+        .chunks_exact(4)
+        .map(|entry| MapEntry {
+            kind: COMPRESSION_NONE,
+            offset: be_u32(entry) as u64 * hunk_bytes as u64,
+            length: hunk_bytes as u32,
+        })
+        .collect())
+}
+
+// The map is a Huffman coded list of entry types followed by the offsets, lengths and CRCs
+fn read_compressed_map(
+    file: &mut File,
+    map_offset: u64,
+    hunk_count: usize,
+    hunk_bytes: usize,
+    unit_bytes: usize,
+) -> io::Result<Vec<MapEntry>> {
+    let mut header = [0; 16];
+    file.seek(SeekFrom::Start(map_offset))?;
+    file.read_exact(&mut header)?;
+    let map_bytes = be_u32(&header[0..]) as usize;
+    let first_offset = be_u64(&header[2..]) & 0xFFFF_FFFF_FFFF;
+    let (length_bits, self_bits, parent_bits) = (header[12], header[13], header[14]);
+
+    let mut compressed = vec![0; map_bytes];
+    file.read_exact(&mut compressed)?;
+    let mut bits = BitReader::new(&compressed);
+
+    let huffman = Huffman::import_rle(&mut bits)?;
+    let mut kinds = Vec::with_capacity(hunk_count);
+    let mut last = 0;
+    let mut repeat = 0;
+    while kinds.len() < hunk_count {
+        if repeat > 0 {
+            kinds.push(last);
+            repeat -= 1;
+            continue;
+        }
+        match huffman.decode(&mut bits) {
+            COMPRESSION_RLE_SMALL => {
+                kinds.push(last);
+                repeat = 2 + huffman.decode(&mut bits) as usize;
+            }
+            COMPRESSION_RLE_LARGE => {
+                kinds.push(last);
+                repeat = 2 + 16 + ((huffman.decode(&mut bits) as usize) << 4);
+                repeat += huffman.decode(&mut bits) as usize;
+            }
+            kind => {
+                kinds.push(kind);
+                last = kind;
+            }
+        }
+    }
+
+    let mut map = Vec::with_capacity(hunk_count);
+    let mut offset = first_offset;
+    let mut last_self = 0;
+    let mut last_parent = 0;
+    for (hunk, kind) in kinds.into_iter().enumerate() {
+        let entry = match kind {
+            0..=COMPRESSION_TYPE_3 => {
+                let length = bits.read(length_bits) as u32;
+                bits.read(16);
+                offset += length as u64;
+                MapEntry {
+                    kind,
+                    offset: offset - length as u64,
+                    length,
+                }
+            }
+            COMPRESSION_NONE => {
+                bits.read(16);
+                offset += hunk_bytes as u64;
+                MapEntry {
+                    kind,
+                    offset: offset - hunk_bytes as u64,
+                    length: hunk_bytes as u32,
+                }
+            }
+            COMPRESSION_SELF | COMPRESSION_SELF_0 | COMPRESSION_SELF_1 => {
+                match kind {
+                    COMPRESSION_SELF => last_self = bits.read(self_bits),
+                    COMPRESSION_SELF_1 => last_self += 1,
+                    _ => {}
+                }
+                MapEntry {
+                    kind: COMPRESSION_SELF,
+                    offset: last_self,
+                    length: 0,
+                }
+            }
+            COMPRESSION_PARENT
+            | COMPRESSION_PARENT_SELF
+            | COMPRESSION_PARENT_0
+            | COMPRESSION_PARENT_1 => {
+                match kind {
+                    COMPRESSION_PARENT => last_parent = bits.read(parent_bits),
+                    COMPRESSION_PARENT_SELF => {
+                        last_parent = (hunk * hunk_bytes / unit_bytes) as u64;
+                    }
+                    COMPRESSION_PARENT_1 => last_parent += (hunk_bytes / unit_bytes) as u64,
+                    _ => {}
+                }
+                MapEntry {
+                    kind: COMPRESSION_PARENT,
+                    offset: last_parent,
+                    length: 0,
+                }
+            }
+            _ => return Err(invalid("bad CHD map entry")),
+        };
+        map.push(entry);
+    }
+
+    Ok(map)
+}
+
+// Track layout from the CHT2 (or older CHTR) metadata entries
+fn read_tracks(file: &mut File, mut offset: u64) -> io::Result<Vec<ChdTrack>> {
+    let mut tracks = Vec::new();
+    while offset != 0 {
+        let mut header = [0; 16];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut header)?;
+        let length = (be_u32(&header[4..]) & 0xFFFFFF) as usize;
+        offset = be_u64(&header[8..]);
+        if &header[0..4] != b"CHT2" && &header[0..4] != b"CHTR" {
+            continue;
+        }
+
+        let mut data = vec![0; length];
+        file.read_exact(&mut data)?;
+        let text = String::from_utf8_lossy(&data);
+        let field = |name: &str| {
+            text.split_whitespace()
+                .find_map(|pair| pair.strip_prefix(name)?.strip_prefix(':'))
+                .map(|value| value.trim_end_matches('\0'))
+        };
+        let number = |name: &str| field(name).and_then(|value| value.parse::<u32>().ok());
+
+        tracks.push(ChdTrack {
+            number: number("TRACK").ok_or_else(|| invalid("bad CHD track metadata"))? as u8,
+            audio: field("TYPE") == Some("AUDIO"),
+            frames: number("FRAMES").ok_or_else(|| invalid("bad CHD track metadata"))?,
+            pregap: number("PREGAP").unwrap_or(0),
+            pregap_stored: field("PGTYPE").is_some_and(|kind| kind.starts_with('V')),
+        });
+    }
+
+    if tracks.is_empty() {
+        return Err(invalid("CHD file has no CD track metadata"));
+    }
+    tracks.sort_by_key(|track| track.number);
+    Ok(tracks)
+}
+
+// CD codecs compress the sector data with the base codec and the subcode with deflate.
+// A bitmap marks frames whose sync and ECC were stripped and must be regenerated
+fn decompress_cd(
+    src: &[u8],
+    hunk_bytes: usize,
+    base: fn(&[u8], usize) -> io::Result<Vec<u8>>,
+) -> io::Result<Vec<u8>> {
+    let frames = hunk_bytes / FRAME_SIZE;
+    let ecc_bytes = frames.div_ceil(8);
+    let length_bytes = if hunk_bytes < 65536 { 2 } else { 3 };
+    let header_bytes = ecc_bytes + length_bytes;
+    if src.len() < header_bytes {
+        return Err(invalid("truncated CHD hunk"));
+    }
+
+    let base_length = src[ecc_bytes..header_bytes]
+        .iter()
+        .fold(0, |length, &byte| (length << 8) | byte as usize);
+    let base_end = header_bytes + base_length;
+    if src.len() < base_end {
+        return Err(invalid("truncated CHD hunk"));
+    }
+    let sectors = base(&src[header_bytes..base_end], frames * SECTOR_SIZE)?;
+    let subcode = inflate(&src[base_end..], frames * SUBCODE_SIZE)?;
+
+    let mut data = vec![0; hunk_bytes];
+    for (i, frame) in data.chunks_exact_mut(FRAME_SIZE).enumerate() {
+        frame[..SECTOR_SIZE].copy_from_slice(&sectors[i * SECTOR_SIZE..(i + 1) * SECTOR_SIZE]);
+        frame[SECTOR_SIZE..].copy_from_slice(&subcode[i * SUBCODE_SIZE..(i + 1) * SUBCODE_SIZE]);
+        if src[i / 8] & (1 << (i % 8)) > 0 {
+            frame[..12].copy_from_slice(&SYNC);
+            ecc_generate(&mut frame[..SECTOR_SIZE]);
+        }
+    }
+    Ok(data)
+}
+
+// Raw deflate stream without a zlib header
+fn inflate(src: &[u8], length: usize) -> io::Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(length);
+    DeflateDecoder::new(src)
+        .take(length as u64)
+        .read_to_end(&mut data)?;
+    data.resize(length, 0);
+    Ok(data)
+}
+
+// Raw LZMA stream. The properties are fixed by the CHD encoder (lc 3, lp 0, pb 2) and the
+// dictionary never needs to be larger than a hunk
+fn inflate_lzma(src: &[u8], length: usize) -> io::Result<Vec<u8>> {
+    let dict_size = (length as u32).next_power_of_two().max(4096);
+    let mut stream = vec![0x5D];
+    stream.extend(dict_size.to_le_bytes());
+    stream.extend(src);
+
+    let options = lzma_rs::decompress::Options {
+        unpacked_size: lzma_rs::decompress::UnpackedSize::UseProvided(Some(length as u64)),
+        ..Default::default()
+    };
+    let mut data = Vec::with_capacity(length);
+    lzma_rs::lzma_decompress_with_options(&mut stream.as_slice(), &mut data, &options)
+        .map_err(|err| invalid(&format!("LZMA error: {err}")))?;
+    data.resize(length, 0);
+    Ok(data)
+}
+
+// Mode 1 P and Q parity over the header and data. P covers 86 columns of 24 bytes and Q
+// covers 52 diagonals of 43 bytes, including the P parity
+fn ecc_generate(sector: &mut [u8]) {
+    ecc_block(sector, 86, 24, 2, 86, 0x81C);
+    ecc_block(sector, 52, 43, 86, 88, 0x8C8);
+}
+
+const ECC_TABLES: ([u8; 256], [u8; 256]) = {
+    let mut f = [0; 256];
+    let mut b = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let j = ((i << 1) ^ if i & 0x80 > 0 { 0x11D } else { 0 }) as u8;
+        f[i] = j;
+        b[i ^ j as usize] = i as u8;
+        i += 1;
+    }
+    (f, b)
+};
+
+fn ecc_block(
+    sector: &mut [u8],
+    major_count: usize,
+    minor_count: usize,
+    major_mult: usize,
+    minor_inc: usize,
+    dest: usize,
+) {
+    let (ecc_f, ecc_b) = &ECC_TABLES;
+    let size = major_count * minor_count;
+    for major in 0..major_count {
+        let mut index = (major >> 1) * major_mult + (major & 1);
+        let mut ecc_a = 0;
+        let mut ecc_b_val = 0;
+        for _ in 0..minor_count {
+            let byte = sector[0xC + index];
+            index += minor_inc;
+            if index >= size {
+                index -= size;
+            }
+            ecc_a ^= byte;
+            ecc_b_val ^= byte;
+            ecc_a = ecc_f[ecc_a as usize];
+        }
+        ecc_a = ecc_b[(ecc_f[ecc_a as usize] ^ ecc_b_val) as usize];
+        sector[dest + major] = ecc_a;
+        sector[dest + major + major_count] = ecc_a ^ ecc_b_val;
+    }
+}
+
+// Reads bits most significant first. Reads past the end return zeros
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn peek(&self, bits: u8) -> u64 {
+        let mut val = 0;
+        for pos in self.pos..self.pos + bits as usize {
+            let byte = self.data.get(pos / 8).copied().unwrap_or(0);
+            val = (val << 1) | ((byte >> (7 - pos % 8)) & 1) as u64;
+        }
+        val
+    }
+
+    fn read(&mut self, bits: u8) -> u64 {
+        let val = self.peek(bits);
+        self.pos += bits as usize;
+        val
+    }
+}
+
+// 16 symbol Huffman decoder with codes of up to 8 bits, as used by the map
+struct Huffman {
+    // Symbol and code length for every 8 bit prefix
+    lookup: [(u8, u8); 256],
+}
+
+impl Huffman {
+    fn import_rle(bits: &mut BitReader) -> io::Result<Self> {
+        let mut lengths = [0u8; 16];
+        let mut symbol = 0;
+        while symbol < lengths.len() {
+            let length = bits.read(4) as u8;
+            if length != 1 {
+                lengths[symbol] = length;
+                symbol += 1;
+                continue;
+            }
+
+            let length = bits.read(4) as u8;
+            if length == 1 {
+                lengths[symbol] = length;
+                symbol += 1;
+            } else {
+                let repeat = bits.read(4) as usize + 3;
+                for _ in 0..repeat.min(lengths.len() - symbol) {
+                    lengths[symbol] = length;
+                    symbol += 1;
+                }
+            }
+        }
+
+        // Canonical codes, assigned from the longest length down
+        let mut starts = [0u32; 33];
+        for &length in &lengths {
+            if length > 8 {
+                return Err(invalid("bad CHD map Huffman tree"));
+            }
+            starts[length as usize] += 1;
+        }
+        let mut start = 0;
+        for length in (1..=32).rev() {
+            let next = (start + starts[length]) >> 1;
+            if length != 1 && next * 2 != start + starts[length] {
+                return Err(invalid("bad CHD map Huffman tree"));
+            }
+            starts[length] = start;
+            start = next;
+        }
+
+        let mut lookup = [(0, 8); 256];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length == 0 {
+                continue;
+            }
+            let code = starts[length as usize] as usize;
+            starts[length as usize] += 1;
+            let shift = 8 - length;
+            let first = code << shift;
+            lookup[first..first + (1 << shift)].fill((symbol as u8, length));
+        }
+
+        Ok(Self { lookup })
+    }
+
+    fn decode(&self, bits: &mut BitReader) -> u8 {
+        let (symbol, length) = self.lookup[bits.peek(8) as usize];
+        bits.read(length);
+        symbol
+    }
+}
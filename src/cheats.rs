@@ -0,0 +1,576 @@
+use eframe::egui::{self, Color32};
+use serde::{Deserialize, Serialize};
+use tracing::{Level, event};
+
+use crate::bus::Bus;
+
+// GameShark cheat as the user entered it. The codes are kept as text and parsed when the game
+// boots
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Cheat {
+    pub name: String,
+    // One "XXXXXXXX YYYY" code per line
+    pub codes: String,
+    pub enabled: bool,
+}
+
+// One line of a cheat, or two for slides. Addresses are offsets into RAM
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Code {
+    Write8 {
+        addr: u32,
+        val: u8,
+    },
+    Write16 {
+        addr: u32,
+        val: u16,
+    },
+    Increment8 {
+        addr: u32,
+        val: u8,
+    },
+    Decrement8 {
+        addr: u32,
+        val: u8,
+    },
+    Increment16 {
+        addr: u32,
+        val: u16,
+    },
+    Decrement16 {
+        addr: u32,
+        val: u16,
+    },
+    // The next code only runs when the halfword at the address is, or isn't, the value
+    IfEqual {
+        addr: u32,
+        val: u16,
+    },
+    IfNotEqual {
+        addr: u32,
+        val: u16,
+    },
+    // Repeats a write, stepping the address and value each time
+    Slide {
+        count: u8,
+        addr_step: u32,
+        val_step: u16,
+        addr: u32,
+        val: u16,
+        wide: bool,
+    },
+}
+
+// Codes address RAM through its KSEG0 mirror, with the top byte taken by the code type
+const ADDR_MASK: u32 = 0x1FFFFF;
+
+// Reads codes, one per line. Blank lines are skipped and errors name the line
+pub fn parse(text: &str) -> Result<Vec<Code>, String> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty());
+    let mut codes = Vec::new();
+    // Whether the last code was a conditional that still needs a code to guard
+    let mut guarding = false;
+    while let Some((i, line)) = lines.next() {
+        let (kind, addr, val) = split(line).map_err(|err| format!("line {}: {err}", i + 1))?;
+        let err = |msg: &str| format!("line {}: {msg}", i + 1);
+        let byte = |val: u16| u8::try_from(val).map_err(|_| err("value must fit in a byte"));
+        let code = match kind {
+            0x30 => Code::Write8 {
+                addr,
+                val: byte(val)?,
+            },
+            0x80 => Code::Write16 { addr, val },
+            0x10 => Code::Increment16 { addr, val },
+            0x11 => Code::Decrement16 { addr, val },
+            0x20 => Code::Increment8 {
+                addr,
+                val: byte(val)?,
+            },
+            0x21 => Code::Decrement8 {
+                addr,
+                val: byte(val)?,
+            },
+            0xD0 => Code::IfEqual { addr, val },
+            0xD1 => Code::IfNotEqual { addr, val },
+            0x50 if addr >> 16 != 0 => return Err(err("slide codes start with 5000")),
+            0x50 => {
+                let Some((j, line)) = lines.next() else {
+                    return Err(err("slide code needs a write after it"));
+                };
+                let (kind, write_addr, write_val) =
+                    split(line).map_err(|err| format!("line {}: {err}", j + 1))?;
+                if kind != 0x30 && kind != 0x80 {
+                    return Err(format!(
+                        "line {}: slide code must be followed by a 30 or 80 write",
+                        j + 1
+                    ));
+                }
+                if kind == 0x30 && write_val > 0xFF {
+                    return Err(format!("line {}: value must fit in a byte", j + 1));
+                }
+                Code::Slide {
+                    count: (addr >> 8) as u8,
+                    addr_step: addr & 0xFF,
+                    val_step: val,
+                    addr: write_addr,
+                    val: write_val,
+                    wide: kind == 0x80,
+                }
+            }
+            _ => return Err(err(&format!("{kind:02X} is not a supported code type"))),
+        };
+        guarding = matches!(code, Code::IfEqual { .. } | Code::IfNotEqual { .. });
+        codes.push(code);
+    }
+    if guarding {
+        return Err("the last code is a conditional with nothing after it".to_string());
+    }
+    if codes.is_empty() {
+        return Err("no codes".to_string());
+    }
+    Ok(codes)
+}
+
+// Splits "XXXXXXXX YYYY" into the code type, RAM offset and value
+fn split(line: &str) -> Result<(u8, u32, u16), String> {
+    let mut parts = line.split_whitespace();
+    let (Some(addr), Some(val), None) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(format!("\"{}\" is not an XXXXXXXX YYYY code", line.trim()));
+    };
+    let hex = addr.len() == 8 && val.len() == 4;
+    match (u32::from_str_radix(addr, 16), u16::from_str_radix(val, 16)) {
+        (Ok(addr), Ok(val)) if hex => Ok(((addr >> 24) as u8, addr & 0xFFFFFF, val)),
+        _ => Err(format!("\"{}\" is not an XXXXXXXX YYYY code", line.trim())),
+    }
+}
+
+// Runs the codes against RAM, once per frame
+pub fn apply(codes: &[Code], bus: &mut Bus) {
+    let mut skip = false;
+    for code in codes {
+        if std::mem::take(&mut skip) {
+            continue;
+        }
+        match *code {
+            Code::Write8 { addr, val } => bus.poke_ram_byte(addr & ADDR_MASK, val),
+            Code::Write16 { addr, val } => write16(bus, addr, val),
+            Code::Increment8 { addr, val } => {
+                let old = bus.peek_ram_byte(addr & ADDR_MASK);
+                bus.poke_ram_byte(addr & ADDR_MASK, old.wrapping_add(val));
+            }
+            Code::Decrement8 { addr, val } => {
+                let old = bus.peek_ram_byte(addr & ADDR_MASK);
+                bus.poke_ram_byte(addr & ADDR_MASK, old.wrapping_sub(val));
+            }
+            Code::Increment16 { addr, val } => {
+                write16(bus, addr, read16(bus, addr).wrapping_add(val))
+            }
+            Code::Decrement16 { addr, val } => {
+                write16(bus, addr, read16(bus, addr).wrapping_sub(val))
+            }
+            Code::IfEqual { addr, val } => skip = read16(bus, addr) != val,
+            Code::IfNotEqual { addr, val } => skip = read16(bus, addr) == val,
+            Code::Slide {
+                count,
+                addr_step,
+                val_step,
+                addr,
+                val,
+                wide,
+            } => {
+                for i in 0..count as u32 {
+                    let addr = addr.wrapping_add(i * addr_step);
+                    let val = val.wrapping_add((i as u16).wrapping_mul(val_step));
+                    if wide {
+                        write16(bus, addr, val);
+                    } else {
+                        bus.poke_ram_byte(addr & ADDR_MASK, val as u8);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn read16(bus: &Bus, addr: u32) -> u16 {
+    let lo = bus.peek_ram_byte(addr & ADDR_MASK);
+    let hi = bus.peek_ram_byte(addr.wrapping_add(1) & ADDR_MASK);
+    u16::from_le_bytes([lo, hi])
+}
+
+fn write16(bus: &mut Bus, addr: u32, val: u16) {
+    let [lo, hi] = val.to_le_bytes();
+    bus.poke_ram_byte(addr & ADDR_MASK, lo);
+    bus.poke_ram_byte(addr.wrapping_add(1) & ADDR_MASK, hi);
+}
+
+// Codes of the enabled cheats, in order. Cheats that no longer parse are left out
+pub fn enabled_codes(cheats: &[Cheat]) -> Vec<Code> {
+    cheats
+        .iter()
+        .filter(|cheat| cheat.enabled)
+        .filter_map(|cheat| match parse(&cheat.codes) {
+            Ok(codes) => Some(codes),
+            Err(err) => {
+                event!(
+                    target: "ps1_emulator::Cheats",
+                    Level::WARN,
+                    "Skipping cheat {}: {err}",
+                    cheat.name
+                );
+                None
+            }
+        })
+        .flatten()
+        .collect()
+}
+
+// Window listing the current game's cheats, with a form to paste new ones
+pub struct CheatWindow {
+    pub open: bool,
+    name_text: String,
+    codes_text: String,
+    error: Option<String>,
+}
+
+impl CheatWindow {
+    pub fn new() -> Self {
+        Self {
+            open: false,
+            name_text: String::new(),
+            codes_text: String::new(),
+            error: None,
+        }
+    }
+
+    // Returns whether the list changed
+    pub fn show(&mut self, ctx: &egui::Context, cheats: &mut Vec<Cheat>) -> bool {
+        let mut changed = false;
+        let mut open = self.open;
+        egui::Window::new("Cheats")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| changed = self.contents(ui, cheats));
+        self.open = open;
+        changed
+    }
+
+    fn contents(&mut self, ui: &mut egui::Ui, cheats: &mut Vec<Cheat>) -> bool {
+        let mut changed = false;
+        if cheats.is_empty() {
+            ui.label("No cheats");
+        }
+        let mut remove = None;
+        egui::Grid::new("cheats").show(ui, |ui| {
+            for (i, cheat) in cheats.iter_mut().enumerate() {
+                changed |= ui.checkbox(&mut cheat.enabled, "").changed();
+                ui.label(&cheat.name)
+                    .on_hover_text(egui::RichText::new(&cheat.codes).monospace());
+                if ui.button("Remove").clicked() {
+                    remove = Some(i);
+                }
+                ui.end_row();
+            }
+        });
+        if let Some(i) = remove {
+            cheats.remove(i);
+            changed = true;
+        }
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.label("Name");
+            ui.text_edit_singleline(&mut self.name_text);
+        });
+        ui.add(
+            egui::TextEdit::multiline(&mut self.codes_text)
+                .hint_text("80012345 0063")
+                .desired_rows(4)
+                .font(egui::TextStyle::Monospace),
+        );
+        if ui.button("Add").clicked() {
+            match parse(&self.codes_text) {
+                Ok(_) => {
+                    let name = match self.name_text.trim() {
+                        "" => format!("Cheat {}", cheats.len() + 1),
+                        name => name.to_string(),
+                    };
+                    let codes = self
+                        .codes_text
+                        .lines()
+                        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+                        .filter(|line| !line.is_empty())
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                        .to_uppercase();
+                    cheats.push(Cheat {
+                        name,
+                        codes,
+                        enabled: true,
+                    });
+                    self.name_text.clear();
+                    self.codes_text.clear();
+                    self.error = None;
+                    changed = true;
+                }
+                Err(err) => self.error = Some(err),
+            }
+        }
+        if let Some(error) = &self.error {
+            ui.colored_label(Color32::RED, error);
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Bus::new builds its memories on the stack
+    fn with_bus(test: impl FnOnce(&mut Bus) + Send + 'static) {
+        std::thread::Builder::new()
+            .stack_size(64 << 20)
+            .spawn(move || test(&mut Bus::new()))
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    fn cheat(codes: &str, enabled: bool) -> Cheat {
+        Cheat {
+            name: "Infinite lives".to_string(),
+            codes: codes.to_string(),
+            enabled,
+        }
+    }
+
+    #[test]
+    fn codes_of_each_type_are_parsed() {
+        let text = "800B1234 0063\n\
+                    300B1236 00FF\n\
+                    \n\
+                    100B2000 0001\n\
+                    110B2000 0002\n\
+                    200B2002 0003\n\
+                    210b2002 0004\n\
+                    D00B3000 ABCD\n\
+                    800B3002 0001\n\
+                    D10B3000 ABCD\n\
+                    300B3004 0001\n\
+                    50000A04 0002\n\
+                    800B4000 0100\n\
+                      50000301 0000  \n\
+                    300B5000 0007";
+        assert_eq!(
+            parse(text).unwrap(),
+            [
+                Code::Write16 {
+                    addr: 0x0B1234,
+                    val: 0x0063
+                },
+                Code::Write8 {
+                    addr: 0x0B1236,
+                    val: 0xFF
+                },
+                Code::Increment16 {
+                    addr: 0x0B2000,
+                    val: 1
+                },
+                Code::Decrement16 {
+                    addr: 0x0B2000,
+                    val: 2
+                },
+                Code::Increment8 {
+                    addr: 0x0B2002,
+                    val: 3
+                },
+                Code::Decrement8 {
+                    addr: 0x0B2002,
+                    val: 4
+                },
+                Code::IfEqual {
+                    addr: 0x0B3000,
+                    val: 0xABCD
+                },
+                Code::Write16 {
+                    addr: 0x0B3002,
+                    val: 1
+                },
+                Code::IfNotEqual {
+                    addr: 0x0B3000,
+                    val: 0xABCD
+                },
+                Code::Write8 {
+                    addr: 0x0B3004,
+                    val: 1
+                },
+                Code::Slide {
+                    count: 10,
+                    addr_step: 4,
+                    val_step: 2,
+                    addr: 0x0B4000,
+                    val: 0x0100,
+                    wide: true,
+                },
+                Code::Slide {
+                    count: 3,
+                    addr_step: 1,
+                    val_step: 0,
+                    addr: 0x0B5000,
+                    val: 7,
+                    wide: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_codes_are_rejected_with_their_line() {
+        for (text, err) in [
+            ("", "no codes"),
+            (
+                "800B1234",
+                "line 1: \"800B1234\" is not an XXXXXXXX YYYY code",
+            ),
+            (
+                "800B1234 0063 0000",
+                "line 1: \"800B1234 0063 0000\" is not an XXXXXXXX YYYY code",
+            ),
+            (
+                "800B1234 063",
+                "line 1: \"800B1234 063\" is not an XXXXXXXX YYYY code",
+            ),
+            (
+                "800B12G4 0063",
+                "line 1: \"800B12G4 0063\" is not an XXXXXXXX YYYY code",
+            ),
+            (
+                "800B1234 0063\n\n300B1236 0100",
+                "line 3: value must fit in a byte",
+            ),
+            ("C20B1234 0063", "line 1: C2 is not a supported code type"),
+            (
+                "50010A04 0002\n800B4000 0100",
+                "line 1: slide codes start with 5000",
+            ),
+            ("50000A04 0002", "line 1: slide code needs a write after it"),
+            (
+                "50000A04 0002\nD00B4000 0100",
+                "line 2: slide code must be followed by a 30 or 80 write",
+            ),
+            (
+                "50000A04 0002\n300B4000 0100",
+                "line 2: value must fit in a byte",
+            ),
+            (
+                "800B1234 0063\nD00B3000 ABCD",
+                "the last code is a conditional with nothing after it",
+            ),
+        ] {
+            assert_eq!(parse(text).unwrap_err(), err, "{text:?}");
+        }
+    }
+
+    #[test]
+    fn constant_writes_happen_every_frame() {
+        with_bus(|bus| {
+            let codes = parse("800B1234 BEEF\n300B1237 0042\n30001000 0001").unwrap();
+            for _ in 0..2 {
+                apply(&codes, bus);
+                assert_eq!(read16(bus, 0x0B1234), 0xBEEF);
+                assert_eq!(bus.peek_ram_byte(0x0B1237), 0x42);
+                // Addresses under 64KB are in the kernel area of RAM
+                assert_eq!(bus.kernel[0x1000], 1);
+                // The game changing the values doesn't stop the next frame's writes
+                bus.poke_ram_byte(0x0B1234, 0);
+                bus.poke_ram_byte(0x0B1237, 0);
+            }
+        });
+    }
+
+    #[test]
+    fn increments_and_decrements_wrap() {
+        with_bus(|bus| {
+            write16(bus, 0x0B2000, 0xFFFE);
+            bus.poke_ram_byte(0x0B2002, 0x02);
+            let codes = parse("100B2000 0003\n210B2002 0003").unwrap();
+            apply(&codes, bus);
+            assert_eq!(read16(bus, 0x0B2000), 0x0001);
+            assert_eq!(bus.peek_ram_byte(0x0B2002), 0xFF);
+            apply(&codes, bus);
+            assert_eq!(read16(bus, 0x0B2000), 0x0004);
+            assert_eq!(bus.peek_ram_byte(0x0B2002), 0xFC);
+
+            let codes = parse("110B2000 0005\n200B2002 0005").unwrap();
+            apply(&codes, bus);
+            assert_eq!(read16(bus, 0x0B2000), 0xFFFF);
+            assert_eq!(bus.peek_ram_byte(0x0B2002), 0x01);
+        });
+    }
+
+    #[test]
+    fn conditionals_guard_the_next_code_only() {
+        with_bus(|bus| {
+            let codes = parse(
+                "D00B3000 ABCD\n800B3002 0001\n\
+                 D10B3000 ABCD\n800B3004 0002\n\
+                 800B3006 0003",
+            )
+            .unwrap();
+            apply(&codes, bus);
+            assert_eq!(read16(bus, 0x0B3002), 0);
+            assert_eq!(read16(bus, 0x0B3004), 2);
+            assert_eq!(read16(bus, 0x0B3006), 3);
+
+            write16(bus, 0x0B3000, 0xABCD);
+            write16(bus, 0x0B3004, 0);
+            apply(&codes, bus);
+            assert_eq!(read16(bus, 0x0B3002), 1);
+            assert_eq!(read16(bus, 0x0B3004), 0);
+        });
+    }
+
+    #[test]
+    fn slides_step_the_address_and_value() {
+        with_bus(|bus| {
+            let codes =
+                parse("50000304 0010\n800B4000 0100\n50000402 FFFF\n300B5000 0003").unwrap();
+            apply(&codes, bus);
+            let words: Vec<_> = (0..4).map(|i| read16(bus, 0x0B4000 + 4 * i)).collect();
+            assert_eq!(words, [0x0100, 0x0110, 0x0120, 0]);
+            let bytes: Vec<_> = (0..10).map(|i| bus.peek_ram_byte(0x0B5000 + i)).collect();
+            assert_eq!(bytes, [3, 0, 2, 0, 1, 0, 0, 0, 0, 0]);
+        });
+    }
+
+    #[test]
+    fn disabled_and_broken_cheats_stop_writing() {
+        with_bus(|bus| {
+            let mut cheats = vec![
+                cheat("800B1234 0063", true),
+                cheat("300B1236 0100", true),
+                cheat("300B1238 0001", false),
+            ];
+            assert_eq!(
+                enabled_codes(&cheats),
+                [Code::Write16 {
+                    addr: 0x0B1234,
+                    val: 0x63
+                }]
+            );
+            apply(&enabled_codes(&cheats), bus);
+            assert_eq!(read16(bus, 0x0B1234), 0x63);
+            assert_eq!(bus.peek_ram_byte(0x0B1238), 0);
+
+            // Whatever the game writes next is left alone, nothing is put back
+            cheats[0].enabled = false;
+            write16(bus, 0x0B1234, 0x02);
+            apply(&enabled_codes(&cheats), bus);
+            assert_eq!(read16(bus, 0x0B1234), 0x02);
+        });
+    }
+}
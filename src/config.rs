@@ -0,0 +1,311 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tracing::{Level, event};
+
+use crate::bios_calls::BiosLog;
+use crate::breakpoints::Breakpoint;
+use crate::cheats::Cheat;
+use crate::frontend::{Aspect, Background, Filter};
+use crate::gpu::DisplayOptions;
+use crate::hotkey::Hotkeys;
+use crate::watchpoints::Watchpoint;
+
+pub const CONFIG_PATH: &str = "config.toml";
+// Bumped when a setting changes meaning, with older files brought up to date by migrate
+const CONFIG_VERSION: u32 = 2;
+// Games kept in the recent list
+const MAX_RECENT_GAMES: usize = 10;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RecentGame {
+    pub path: PathBuf,
+    // Seconds since the Unix epoch
+    pub last_played: u64,
+}
+
+// Frontend settings kept between runs in config.toml
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    version: u32,
+    // BIOS image picked by the user
+    pub bios_path: Option<PathBuf>,
+    // Searched for a BIOS when none has been picked, or the picked one is gone
+    pub bios_dir: PathBuf,
+    pub hotkeys: Hotkeys,
+    // Fast forward speed as a percentage of a real console. 0 runs as fast as possible
+    pub fast_forward_cap: u32,
+    // Frames shown ahead of the game to hide its input lag, 0 for off
+    pub run_ahead: u32,
+    // Debugger breakpoints of each game by name, with "bios" used when no game is loaded
+    pub breakpoints: BTreeMap<String, Vec<Breakpoint>>,
+    // Data watchpoints of each game, keyed like breakpoints
+    pub watchpoints: BTreeMap<String, Vec<Watchpoint>>,
+    // Whether watchpoints also stop at DMA transfers
+    pub watch_dma: bool,
+    // Calls into the BIOS shown in the TTY console
+    pub bios_log: BiosLog,
+    // GameShark cheats of each game, keyed like breakpoints
+    pub cheats: BTreeMap<String, Vec<Cheat>>,
+    // Listed in the game selection
+    pub rom_dir: PathBuf,
+    // Inner size of the window when it was last closed
+    pub window_size: Option<[f32; 2]>,
+    pub volume: f32,
+    pub muted: bool,
+    // Run as many cycles as the audio output needs instead of one frame per update
+    pub audio_sync: bool,
+    pub aspect: Aspect,
+    pub filter: Filter,
+    pub display: DisplayOptions,
+    // Display options of games set apart from the rest, by name
+    pub game_display: BTreeMap<String, DisplayOptions>,
+    pub resolution_scale: usize,
+    // Most recently played first
+    pub recent_games: Vec<RecentGame>,
+    // What a running game does while the window is unfocused
+    pub background: Background,
+    pub mute_in_background: bool,
+    // Saves the running game to its auto slot when it is quit, for Continue in the recent list
+    pub auto_save: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            version: CONFIG_VERSION,
+            bios_path: None,
+            bios_dir: PathBuf::from("bios/"),
+            hotkeys: Hotkeys::default(),
+            fast_forward_cap: 300,
+            run_ahead: 0,
+            breakpoints: BTreeMap::new(),
+            watchpoints: BTreeMap::new(),
+            watch_dma: false,
+            bios_log: BiosLog::Off,
+            cheats: BTreeMap::new(),
+            rom_dir: PathBuf::from("roms/"),
+            window_size: None,
+            volume: 1.0,
+            muted: false,
+            audio_sync: true,
+            aspect: Aspect::FourThree,
+            filter: Filter::Bilinear,
+            display: DisplayOptions::default(),
+            game_display: BTreeMap::new(),
+            resolution_scale: 1,
+            recent_games: Vec::new(),
+            background: Background::Run,
+            mute_in_background: false,
+            auto_save: true,
+        }
+    }
+}
+
+impl Config {
+    // Missing or unreadable files give the default settings
+    pub fn load(path: &Path) -> Self {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Self::default(),
+            Err(err) => {
+                event!(
+                    target: "ps1_emulator::Config",
+                    Level::WARN,
+                    "Failed to read {}: {err}",
+                    path.display()
+                );
+                return Self::default();
+            }
+        };
+        let config = toml::from_str(&text).and_then(|mut table: toml::Table| {
+            migrate(&mut table);
+            toml::Value::Table(table).try_into()
+        });
+        config.unwrap_or_else(|err| {
+            event!(
+                target: "ps1_emulator::Config",
+                Level::WARN,
+                "Failed to parse {}, using default settings: {err}",
+                path.display()
+            );
+            Self::default()
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let text = toml::to_string(self).map_err(io::Error::other)?;
+        fs::write(path, text)
+    }
+
+    // Moves a game to the top of the recent list
+    pub fn played(&mut self, path: &Path) {
+        let last_played = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.recent_games.retain(|game| game.path != path);
+        self.recent_games.insert(
+            0,
+            RecentGame {
+                path: path.to_path_buf(),
+                last_played,
+            },
+        );
+        self.recent_games.truncate(MAX_RECENT_GAMES);
+    }
+}
+
+// Brings a file written by an older version up to date before it is read. Settings added
+// since then need nothing here, they are filled in with defaults
+fn migrate(table: &mut toml::Table) {
+    let version = table
+        .get("version")
+        .and_then(toml::Value::as_integer)
+        .unwrap_or(0);
+
+    // Version 0 bound these to the keys the save state hotkeys now default to. Bindings left
+    // at the old defaults move out of the way
+    if version < 1
+        && let Some(toml::Value::Table(hotkeys)) = table.get_mut("hotkeys")
+    {
+        for (hotkey, old, new) in [
+            ("DumpVram", "F5", "Shift+F5"),
+            ("LoadVram", "F6", "Shift+F6"),
+            ("ReleaseMouse", "F8", "F10"),
+        ] {
+            if hotkeys.get(hotkey).and_then(toml::Value::as_str) == Some(old) {
+                hotkeys.insert(hotkey.to_string(), new.into());
+            }
+        }
+    }
+
+    // Version 1 had a switch for smoothing pictures scaled by a fraction
+    if version < 2
+        && let Some(smooth) = table.remove("smooth_scaling")
+    {
+        let filter = match smooth.as_bool() {
+            Some(false) => "Integer",
+            _ => "Bilinear",
+        };
+        table.insert("filter".to_string(), filter.into());
+    }
+
+    table.insert("version".to_string(), i64::from(CONFIG_VERSION).into());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hotkey::Hotkey;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("ps1_emulator_{name}_{}.toml", std::process::id()))
+    }
+
+    #[test]
+    fn settings_survive_a_save_and_load() {
+        let mut config = Config {
+            bios_path: Some(PathBuf::from("bios/SCPH1001.BIN")),
+            rom_dir: PathBuf::from("/games/psx"),
+            window_size: Some([1280.0, 960.0]),
+            volume: 0.25,
+            muted: true,
+            run_ahead: 2,
+            aspect: Aspect::Stretch,
+            filter: Filter::Integer,
+            background: Background::Pause,
+            resolution_scale: 4,
+            auto_save: false,
+            ..Config::default()
+        };
+        config
+            .hotkeys
+            .keys
+            .insert(Hotkey::Pause, "Ctrl+P".to_string());
+        config.played(Path::new("roms/Crash.cue"));
+
+        let path = temp_path("config_round_trip");
+        config.save(&path).unwrap();
+        let loaded = Config::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            toml::to_string(&loaded).unwrap(),
+            toml::to_string(&config).unwrap()
+        );
+        assert_eq!(loaded.version, CONFIG_VERSION);
+        assert_eq!(loaded.recent_games[0].path, Path::new("roms/Crash.cue"));
+        assert_eq!(loaded.hotkeys.keys[&Hotkey::Pause], "Ctrl+P");
+    }
+
+    #[test]
+    fn missing_or_broken_files_give_the_defaults() {
+        let defaults = toml::to_string(&Config::default()).unwrap();
+
+        let missing = Config::load(&temp_path("config_missing"));
+        assert_eq!(toml::to_string(&missing).unwrap(), defaults);
+
+        let path = temp_path("config_broken");
+        for text in ["volume = [", "volume = \"loud\"", "\u{0}\u{1}"] {
+            fs::write(&path, text).unwrap();
+            let broken = Config::load(&path);
+            assert_eq!(toml::to_string(&broken).unwrap(), defaults, "{text:?}");
+        }
+        fs::remove_file(&path).unwrap();
+
+        // Settings left out are filled in, the rest are kept
+        fs::write(&path, "volume = 0.5\n").unwrap();
+        let partial = Config::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(partial.volume, 0.5);
+        assert_eq!(partial.rom_dir, Path::new("roms/"));
+    }
+
+    #[test]
+    fn old_files_are_migrated() {
+        let path = temp_path("config_migrate");
+        fs::write(
+            &path,
+            "smooth_scaling = false\n[hotkeys]\nDumpVram = \"F5\"\nLoadVram = \"Ctrl+L\"\n",
+        )
+        .unwrap();
+        let config = Config::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert!(config.filter == Filter::Integer);
+        // Only bindings left at the old defaults move
+        assert_eq!(config.hotkeys.keys[&Hotkey::DumpVram], "Shift+F5");
+        assert_eq!(config.hotkeys.keys[&Hotkey::LoadVram], "Ctrl+L");
+        assert!(config.hotkeys.conflicts().is_empty());
+    }
+
+    #[test]
+    fn recent_games_keep_the_latest_once_each() {
+        let mut config = Config::default();
+        for i in 0..MAX_RECENT_GAMES + 2 {
+            config.played(Path::new(&format!("roms/{i}.cue")));
+        }
+        config.played(Path::new("roms/5.cue"));
+
+        let recent: Vec<_> = config.recent_games.iter().map(|game| &game.path).collect();
+        assert_eq!(recent.len(), MAX_RECENT_GAMES);
+        assert_eq!(recent[0], Path::new("roms/5.cue"));
+        assert_eq!(recent[1], Path::new("roms/11.cue"));
+        assert_eq!(
+            recent
+                .iter()
+                .filter(|path| **path == Path::new("roms/5.cue"))
+                .count(),
+            1
+        );
+        // The oldest games fell off the end, and moving one up dropped nothing
+        assert_eq!(recent[MAX_RECENT_GAMES - 1], Path::new("roms/2.cue"));
+    }
+}
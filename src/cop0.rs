@@ -1,5 +1,8 @@
+use serde::{Deserialize, Serialize};
+
 use crate::cpu::ExceptionType;
 
+#[derive(Serialize, Deserialize)]
 pub struct Cop0 {
     pub sr: StatusRegister,
     pub cause: CauseRegister,
@@ -83,6 +86,7 @@ impl Cop0 {
     }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct CauseRegister(u32);
 
 impl CauseRegister {
@@ -127,8 +131,21 @@ impl CauseRegister {
     pub fn interrupt_pending(&self) -> u32 {
         self.0 & 0x0000FF00
     }
+
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+
+    pub fn exception_code(&self) -> u32 {
+        (self.0 >> 2) & 0x1F
+    }
+
+    pub fn branch_delay(&self) -> bool {
+        self.0 & 0x80000000 > 0
+    }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct StatusRegister(u32);
 
 impl StatusRegister {
@@ -176,4 +193,8 @@ impl StatusRegister {
     pub fn get_isc(&self) -> bool {
         self.0 & 0x10000 > 0
     }
+
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
 }
@@ -1,10 +1,19 @@
 use core::fmt;
+use std::collections::HashSet;
+use std::{io, mem};
 
+use crate::bios_calls::{self, BiosLog};
 use crate::bus::Bus;
+use crate::exe::{self, Exe};
 use crate::gte::Gte;
+use crate::state::{self, HostHashes, StateError, StateHeader};
+use crate::watchpoints::{WatchHit, Watcher, Watchpoint};
 
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
 use tracing::{Level, event, span};
 
+#[derive(Serialize, Deserialize)]
 pub struct Registers {
     pub registers: [u32; 32],
     pub program_counter: u32,
@@ -106,10 +115,21 @@ pub enum ExceptionType {
     ArithmeticOverflow,  // Arithmetic Overflow
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Cpu {
     pub registers: Registers,
     pub bus: Bus,
     pub gte: Gte,
+    // Addresses the frontend stops at before executing them
+    #[serde(skip)]
+    breakpoints: HashSet<u32>,
+    // Which calls into the BIOS are logged to the TTY console and the BIOS trace target
+    #[serde(skip, default = "bios_log_off")]
+    bios_log: BiosLog,
+}
+
+fn bios_log_off() -> BiosLog {
+    BiosLog::Off
 }
 
 impl Cpu {
@@ -122,14 +142,116 @@ impl Cpu {
             registers,
             bus,
             gte,
+            breakpoints: HashSet::new(),
+            bios_log: BiosLog::Off,
         }
     }
 
+    pub fn set_breakpoints(&mut self, addrs: impl IntoIterator<Item = u32>) {
+        self.breakpoints = addrs.into_iter().collect();
+    }
+
+    // Whether the next instruction to execute has a breakpoint. Cheap when there are none
+    pub fn at_breakpoint(&self) -> bool {
+        !self.breakpoints.is_empty() && self.breakpoints.contains(&self.registers.program_counter)
+    }
+
+    pub fn set_bios_log(&mut self, bios_log: BiosLog) {
+        self.bios_log = bios_log;
+    }
+
+    // Replaces the data watchpoints. dma decides whether DMA transfers are checked too
+    pub fn set_watchpoints(&mut self, watchpoints: &[Watchpoint], dma: bool) {
+        self.bus.watcher = Watcher::new(watchpoints, dma);
+    }
+
+    // The first access to hit a watchpoint since the last call
+    pub fn take_watch_hit(&mut self) -> Option<WatchHit> {
+        self.bus.watcher.take_hit()
+    }
+
+    // CPU cycles since power on
+    pub fn cycles_executed(&self) -> u64 {
+        self.bus.cycles
+    }
+
+    // Replaces the contents of out with the machine, and the thumbnail PNG shown with it
+    pub fn save_state(&mut self, out: &mut Vec<u8>, thumbnail: Option<&[u8]>) {
+        out.clear();
+        let header = StateHeader::new(self.host_hashes());
+        state::write_header(out, &header, thumbnail);
+        state::encode(self, out);
+        state::seal(out);
+    }
+
+    // Replaces the whole machine. The BIOS, disc, controllers, memory cards, link cable, display
+    // settings, breakpoints and watchpoints belong to the host and are kept. States saved with
+    // another BIOS or disc are refused unless forced, since the game may crash after loading them
+    pub fn load_state(&mut self, state: &[u8], force: bool) -> Result<(), StateError> {
+        let (header, payload) = state::read_header(state)?;
+        if !force {
+            header.hashes.check(&self.host_hashes())?;
+        }
+        self.restore(&payload)
+    }
+
+    // Replaces the contents of out with the machine alone, without the header save states
+    // have. Cheap enough to take every frame, since nothing is hashed or checked
+    pub fn snapshot(&self, out: &mut Vec<u8>) {
+        out.clear();
+        state::encode(self, out);
+    }
+
+    // Goes back to a snapshot, keeping the same host parts as load_state
+    pub fn restore(&mut self, snapshot: &[u8]) -> Result<(), StateError> {
+        let mut restored: Cpu = state::decode(snapshot)?;
+        restored.take_host_state(self);
+        *self = restored;
+        Ok(())
+    }
+
+    // Tells apart the BIOS and disc, which save states and movies don't include
+    pub fn host_hashes(&mut self) -> HostHashes {
+        HostHashes {
+            bios: Sha1::digest(&self.bus.kernel_rom[..]).into(),
+            disc: self.bus.cdrom.disc_mut().map(|disc| disc.hash()),
+        }
+    }
+
+    // Power cycles the machine, keeping the same host parts as load_state
+    pub fn reset(&mut self) {
+        let mut fresh = Cpu::new();
+        fresh.take_host_state(self);
+        // Reinserted so the drive spins the disc up as it does at power on
+        if let Some(disc) = fresh.bus.cdrom.take_disc() {
+            fresh.bus.cdrom.insert_disc(disc);
+        }
+        *self = fresh;
+    }
+
+    fn take_host_state(&mut self, old: &mut Cpu) {
+        mem::swap(&mut self.bus.kernel_rom, &mut old.bus.kernel_rom);
+        self.bus.sio0.take_ports_from(&mut old.bus.sio0);
+        self.bus.cdrom.take_disc_from(&mut old.bus.cdrom);
+        self.bus.sio1.connect(old.bus.sio1.take_link());
+        self.breakpoints = mem::take(&mut old.breakpoints);
+        self.bios_log = old.bios_log;
+        self.bus.watcher = mem::take(&mut old.bus.watcher);
+        self.bus.cheats = mem::take(&mut old.bus.cheats);
+        let gp0 = &mut self.bus.gpu.gp0;
+        gp0.set_resolution_scale(old.bus.gpu.gp0.resolution_scale());
+        gp0.overlay.set_view(old.bus.gpu.gp0.overlay.view);
+        self.bus.gpu.display_options = old.bus.gpu.display_options;
+    }
+
     pub fn load_bios(&mut self, bios: &[u8]) {
         self.bus.kernel_rom[0..0x80000].clone_from_slice(bios);
     }
 
-    pub fn sideload_exe(&mut self, exe: &[u8], tty_check: bool) {
+    // Runs the BIOS until its shell would start, then replaces it with the exe
+    pub fn sideload_exe(&mut self, exe: &[u8], tty_check: bool) -> io::Result<()> {
+        let exe = Exe::parse(exe)?;
+
         let bios_span = span!(target: "ps1_emulator::BIOS", Level::DEBUG, "BIOS").entered();
         bios_span.in_scope(|| {
             while self.registers.program_counter != 0x80030000 {
@@ -139,42 +261,61 @@ impl Cpu {
 
         bios_span.exit();
 
-        let initial_pc = u32::from_le_bytes(exe[0x10..0x14].try_into().unwrap());
-        let initial_r28 = u32::from_le_bytes(exe[0x14..0x18].try_into().unwrap());
-        let exe_ram_addr = u32::from_le_bytes(exe[0x18..0x1C].try_into().unwrap()) & 0x1FFFFF;
-        let exe_size = u32::from_le_bytes(exe[0x1C..0x20].try_into().unwrap());
-        let initial_sp = u32::from_le_bytes(exe[0x30..0x34].try_into().unwrap());
-
         println!(
             "Initial PC: 0x{:08X}, Initial r28: 0x{:08X}, Initial SP: 0x{:08X}, EXE RAM ADDR: 0x{:08X}, EXE Size: 0x{:08X}",
-            initial_pc, initial_r28, initial_sp, exe_ram_addr, exe_size
+            exe.pc,
+            exe.gp,
+            exe.sp,
+            exe.dest,
+            exe.body.len()
         );
 
-        let ram_start_addr = exe_ram_addr - 0x10000;
-        let ram_end_addr = exe_ram_addr + exe_size - 0x10000;
-        self.bus.ram[ram_start_addr as usize..ram_end_addr as usize]
-            .copy_from_slice(&exe[2048..2048 + exe_size as usize]);
+        // Both ranges were checked when parsing
+        let body = exe::ram_range(exe.dest, exe.body.len()).unwrap();
+        self.bus.ram[body].copy_from_slice(exe.body);
+        let fill = exe::ram_range(exe.fill_start, exe.fill_size as usize).unwrap();
+        self.bus.ram[fill].fill(0);
 
-        self.registers.registers[28] = initial_r28;
-        if initial_sp != 0 {
-            self.registers.registers[29] = initial_sp;
-            self.registers.registers[30] = initial_sp;
+        self.registers.registers[28] = exe.gp;
+        if exe.sp != 0 {
+            self.registers.registers[29] = exe.sp;
+            self.registers.registers[30] = exe.sp;
         }
 
-        self.registers.program_counter = initial_pc;
+        self.registers.program_counter = exe.pc;
+        Ok(())
     }
 
-    pub fn check_for_tty_output(&self) {
+    // Hands characters given to the BIOS putchar calls to the bus, and prints them when asked
+    pub fn check_for_tty_output(&mut self, print: bool) {
         let pc = self.registers.program_counter & 0x1FFFFFFF;
         if (pc == 0xA0 && self.registers.registers[9] == 0x3C)
             || (pc == 0xB0 && self.registers.registers[9] == 0x3D)
         {
             let ch = self.registers.registers[4] as u8 as char;
             event!(target: "ps1_emulator::CPU", Level::TRACE, "TTY Output: {ch}");
-            print!("{ch}");
+            self.bus.push_tty_output(ch, self.registers.registers[31]);
+            if print {
+                print!("{ch}");
+            }
         }
     }
 
+    // Logs the call when the PC has just reached one of the BIOS function vectors
+    fn log_bios_call(&mut self) {
+        let vector = self.registers.program_counter & 0x1FFFFFFF;
+        if !matches!(vector, 0xA0 | 0xB0 | 0xC0) {
+            return;
+        }
+        let func = self.registers.registers[9];
+        if self.bios_log == BiosLog::Calls && bios_calls::noisy(vector, func) {
+            return;
+        }
+        let line = bios_calls::describe(&self.bus, &self.registers.registers, vector, func);
+        event!(target: "ps1_emulator::BIOS", Level::INFO, "{line}");
+        self.bus.push_bios_call(line, self.registers.registers[31]);
+    }
+
     fn handle_exception(&mut self, exception: ExceptionType, in_delay_slot: bool) {
         event!(target: "ps1_emulator::CPU", Level::TRACE, "Exception Occured: {:?}", exception);
         // Store PC in EPC register (unless currently in Branch Delay in which case store PC - 4)
@@ -225,8 +366,9 @@ impl Cpu {
             .cause
             .set_interrupt_pending(self.bus.interrupts.stat & self.bus.interrupts.mask > 0);
 
-        if tty_check {
-            self.check_for_tty_output();
+        self.check_for_tty_output(tty_check);
+        if self.bios_log != BiosLog::Off {
+            self.log_bios_call();
         }
 
         // Execute interrupt if SR allows
@@ -263,6 +405,11 @@ impl Cpu {
 
         self.registers.process_loads();
 
+        // Watchpoint hits report the instruction making the access
+        if !self.bus.watcher.is_empty() {
+            self.bus.watcher.pc = self.registers.program_counter;
+        }
+
         // Let each instruction take two ticks
         // Perform before exception handler bc instruction was already executed
         self.bus.tick(2);
@@ -470,7 +617,7 @@ impl Cpu {
                 event!(target: "ps1_emulator::CPU", Level::DEBUG, "{:<20}  {}", format!("LB ${rt}, {:04X}(${:02})", offset, base), self.registers);
 
                 let addr = self.registers.read(base).wrapping_add_signed(offset as i32);
-                let data = self.bus.mem_read_byte(addr)? as i8;
+                let data = self.bus.load_byte(addr)? as i8;
                 self.registers.write_delayed(rt, data as i32 as u32);
 
                 Ok(())
@@ -484,7 +631,7 @@ impl Cpu {
                 event!(target: "ps1_emulator::CPU", Level::DEBUG, "{:<20}  {}", format!("LBU ${rt}, {:04X}(${:02X})", offset, base), self.registers);
 
                 let addr = self.registers.read(base).wrapping_add_signed(offset as i32);
-                let data = self.bus.mem_read_byte(addr)?;
+                let data = self.bus.load_byte(addr)?;
                 self.registers.write_delayed(rt, data as u32);
 
                 Ok(())
@@ -499,7 +646,7 @@ impl Cpu {
 
                 let addr = self.registers.read(base).wrapping_add_signed(offset as i32);
 
-                let halfword = self.bus.mem_read_halfword(addr)? as i16;
+                let halfword = self.bus.load_halfword(addr)? as i16;
                 self.registers.write_delayed(rt, halfword as i32 as u32);
 
                 Ok(())
@@ -514,7 +661,7 @@ impl Cpu {
 
                 let addr = self.registers.read(base).wrapping_add_signed(offset as i32);
                 self.registers
-                    .write_delayed(rt, self.bus.mem_read_halfword(addr)? as u32);
+                    .write_delayed(rt, self.bus.load_halfword(addr)? as u32);
 
                 Ok(())
             }
@@ -538,8 +685,7 @@ impl Cpu {
                 event!(target: "ps1_emulator::CPU", Level::DEBUG, "{:<20}  {}", format!("LW ${rt}, {:04X}(${base})", offset), self.registers);
 
                 let addr = self.registers.read(base).wrapping_add_signed(offset as i32);
-                self.registers
-                    .write_delayed(rt, self.bus.mem_read_word(addr)?);
+                self.registers.write_delayed(rt, self.bus.load_word(addr)?);
 
                 Ok(())
             }
@@ -555,10 +701,7 @@ impl Cpu {
                     .registers
                     .read_lwl_lwr(base)
                     .wrapping_add_signed(offset as i32) as usize;
-                let [b0, b1, b2, b3] = self
-                    .bus
-                    .mem_read_word(addr as u32 & 0xFFFFFFFC)?
-                    .to_le_bytes();
+                let [b0, b1, b2, b3] = self.bus.load_word(addr as u32 & 0xFFFFFFFC)?.to_le_bytes();
                 let [r0, r1, r2, _] = self.registers.read_lwl_lwr(rt).to_le_bytes();
                 let reg_value = match addr % 4 {
                     0 => u32::from_le_bytes([r0, r1, r2, b0]),
@@ -584,10 +727,7 @@ impl Cpu {
                     .registers
                     .read_lwl_lwr(base)
                     .wrapping_add_signed(offset as i32) as usize;
-                let [b0, b1, b2, b3] = self
-                    .bus
-                    .mem_read_word(addr as u32 & 0xFFFFFFFC)?
-                    .to_le_bytes();
+                let [b0, b1, b2, b3] = self.bus.load_word(addr as u32 & 0xFFFFFFFC)?.to_le_bytes();
                 let [_, r1, r2, r3] = self.registers.read_lwl_lwr(rt).to_le_bytes();
                 let reg_value = match addr % 4 {
                     0 => u32::from_le_bytes([b0, b1, b2, b3]),
@@ -623,7 +763,7 @@ impl Cpu {
 
                 let addr = self.registers.read(base).wrapping_add_signed(offset as i32);
                 let byte = (self.registers.read(rt) & 0x000000FF) as u8;
-                self.bus.mem_write_byte(addr, byte)?;
+                self.bus.store_byte(addr, byte)?;
 
                 Ok(())
             }
@@ -638,7 +778,7 @@ impl Cpu {
                 let addr = self.registers.read(base).wrapping_add_signed(offset as i32);
                 if addr.is_multiple_of(2) {
                     let halfbyte = (self.registers.read(rt) & 0x0000FFFF) as u16;
-                    self.bus.mem_write_halfword(addr, halfbyte)?;
+                    self.bus.store_halfword(addr, halfbyte)?;
                     Ok(())
                 } else {
                     Err(ExceptionType::AddressErrorStore(addr))
@@ -686,7 +826,7 @@ impl Cpu {
 
                 let addr = self.registers.read(base).wrapping_add_signed(offset as i32);
                 if addr.is_multiple_of(4) {
-                    self.bus.mem_write_word(addr, self.registers.read(rt))?;
+                    self.bus.store_word(addr, self.registers.read(rt))?;
                     Ok(())
                 } else {
                     Err(ExceptionType::AddressErrorStore(addr))
@@ -704,22 +844,22 @@ impl Cpu {
                 let [b0, b1, b2, b3] = self.registers.read(rt).to_le_bytes();
                 match addr % 4 {
                     0 => {
-                        self.bus.mem_write_byte(addr, b3)?;
+                        self.bus.store_byte(addr, b3)?;
                     }
                     1 => {
-                        self.bus.mem_write_byte(addr, b3)?;
-                        self.bus.mem_write_byte(addr - 1, b2)?;
+                        self.bus.store_byte(addr, b3)?;
+                        self.bus.store_byte(addr - 1, b2)?;
                     }
                     2 => {
-                        self.bus.mem_write_byte(addr, b3)?;
-                        self.bus.mem_write_byte(addr - 1, b2)?;
-                        self.bus.mem_write_byte(addr - 2, b1)?;
+                        self.bus.store_byte(addr, b3)?;
+                        self.bus.store_byte(addr - 1, b2)?;
+                        self.bus.store_byte(addr - 2, b1)?;
                     }
                     3 => {
-                        self.bus.mem_write_byte(addr, b3)?;
-                        self.bus.mem_write_byte(addr - 1, b2)?;
-                        self.bus.mem_write_byte(addr - 2, b1)?;
-                        self.bus.mem_write_byte(addr - 3, b0)?;
+                        self.bus.store_byte(addr, b3)?;
+                        self.bus.store_byte(addr - 1, b2)?;
+                        self.bus.store_byte(addr - 2, b1)?;
+                        self.bus.store_byte(addr - 3, b0)?;
                     }
                     _ => panic!("Impossible"),
                 };
@@ -738,31 +878,31 @@ impl Cpu {
                 let [b0, b1, b2, b3] = self.registers.read(rt).to_le_bytes();
                 match addr % 4 {
                     0 => {
-                        self.bus.mem_write_byte(addr, b0)?;
-                        self.bus.mem_write_byte(addr + 1, b1)?;
-                        self.bus.mem_write_byte(addr + 2, b2)?;
-                        self.bus.mem_write_byte(addr + 3, b3)?;
+                        self.bus.store_byte(addr, b0)?;
+                        self.bus.store_byte(addr + 1, b1)?;
+                        self.bus.store_byte(addr + 2, b2)?;
+                        self.bus.store_byte(addr + 3, b3)?;
                     }
                     1 => {
                         // self.bus.mem_write_byte(addr, b3)?;
                         // self.bus.mem_write_byte(addr - 1, b2)?;
-                        self.bus.mem_write_byte(addr, b0)?;
-                        self.bus.mem_write_byte(addr + 1, b1)?;
-                        self.bus.mem_write_byte(addr + 2, b2)?;
+                        self.bus.store_byte(addr, b0)?;
+                        self.bus.store_byte(addr + 1, b1)?;
+                        self.bus.store_byte(addr + 2, b2)?;
                     }
                     2 => {
                         // self.bus.mem_write_byte(addr, b3)?;
                         // self.bus.mem_write_byte(addr - 1, b2)?;
                         // self.bus.mem_write_byte(addr - 2, b1)?;
-                        self.bus.mem_write_byte(addr, b0)?;
-                        self.bus.mem_write_byte(addr + 1, b1)?;
+                        self.bus.store_byte(addr, b0)?;
+                        self.bus.store_byte(addr + 1, b1)?;
                     }
                     3 => {
                         // self.bus.mem_write_byte(addr, b3)?;
                         // self.bus.mem_write_byte(addr - 1, b2)?;
                         // self.bus.mem_write_byte(addr - 2, b1)?;
                         // self.bus.mem_write_byte(addr - 3, b0)?;
-                        self.bus.mem_write_byte(addr, b0)?;
+                        self.bus.store_byte(addr, b0)?;
                     }
                     _ => panic!("Impossible"),
                 };
@@ -872,7 +1012,7 @@ impl Cpu {
                 event!(target: "ps1_emulator::CPU", Level::DEBUG, "{:<20}  {}", format!("LWC2 ${rt}, {:04X}({:02X})", offset, base), self.registers);
 
                 let addr = self.registers.read(base).wrapping_add_signed(offset as i32);
-                self.gte.data_reg_write(rt, self.bus.mem_read_word(addr)?);
+                self.gte.data_reg_write(rt, self.bus.load_word(addr)?);
                 Ok(())
             }
             // LWC3 - Load Word to Coprocessor 3
@@ -961,7 +1101,7 @@ impl Cpu {
 
                 let addr = self.registers.read(base).wrapping_add_signed(offset as i32);
                 let val = self.gte.data_reg_read(rt);
-                self.bus.mem_write_word(addr, val)?;
+                self.bus.store_word(addr, val)?;
                 Ok(())
             }
             // SWC3 - Store Word from Coprocessor 3
@@ -1363,3 +1503,218 @@ impl Cpu {
         arg1.wrapping_add(arg2)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::watchpoints::Access;
+
+    // Loops forever adding timer 0 and I_STAT into a hash, which it stores across the first 4 KB
+    // of RAM after the kernel
+    const PROGRAM: [u32; 14] = [
+        0x3C081F80, // lui t0, 0x1F80
+        0x3C10A001, // lui s0, 0xA001
+        0x8D091100, // lw t1, 0x1100(t0)
+        0x950A1070, // lhu t2, 0x1070(t0)
+        0x00115940, // sll t3, s1, 5
+        0x01718823, // subu s1, t3, s1
+        0x02298821, // addu s1, s1, t1
+        0x022A8821, // addu s1, s1, t2
+        0x324B0FFC, // andi t3, s2, 0xFFC
+        0x020B6021, // addu t4, s0, t3
+        0xAD910000, // sw s1, 0(t4)
+        0x0BF00002, // j 0xBFC00008
+        0x26520004, // addiu s2, s2, 4
+        0x00000000,
+    ];
+
+    fn machine() -> Cpu {
+        let mut cpu = Cpu::new();
+        for (i, word) in PROGRAM.into_iter().enumerate() {
+            cpu.bus.kernel_rom[4 * i..4 * i + 4].copy_from_slice(&word.to_le_bytes());
+        }
+        cpu
+    }
+
+    // PC, registers and cycle count after each instruction
+    fn trace(cpu: &mut Cpu, steps: usize) -> Vec<(u32, [u32; 32], u64)> {
+        (0..steps)
+            .map(|_| {
+                cpu.step_instruction(false);
+                let pc = cpu.registers.program_counter;
+                (pc, cpu.registers.registers, cpu.bus.cycles)
+            })
+            .collect()
+    }
+
+    // The machine is too big for the default test stack
+    fn with_big_stack(test: impl FnOnce() + Send + 'static) {
+        std::thread::Builder::new()
+            .stack_size(64 << 20)
+            .spawn(test)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn loaded_state_runs_the_same_as_the_machine_saved() {
+        with_big_stack(|| {
+            let mut cpu = machine();
+            trace(&mut cpu, 50_000);
+            let mut state = Vec::new();
+            cpu.save_state(&mut state, None);
+            let expected = trace(&mut cpu, 10_000);
+            let ram = cpu.bus.ram.clone();
+            assert!(ram[..0x1000].iter().any(|&byte| byte != 0));
+
+            cpu.load_state(&state, false).unwrap();
+            assert_eq!(trace(&mut cpu, 10_000), expected);
+            assert!(cpu.bus.ram == ram);
+
+            let mut fresh = machine();
+            fresh.load_state(&state, false).unwrap();
+            assert_eq!(trace(&mut fresh, 10_000), expected);
+            assert!(fresh.bus.ram == ram);
+        });
+    }
+
+    #[test]
+    fn watchpoints_stop_at_loads_stores_and_dma() {
+        with_big_stack(|| {
+            let mut cpu = Cpu::new();
+            let program: [u32; 5] = [
+                0x3C08A001, // lui t0, 0xA001
+                0x3409002A, // ori t1, zero, 0x2A
+                0xA1090011, // sb t1, 0x11(t0)
+                0x8D0A0010, // lw t2, 0x10(t0)
+                0x00000000,
+            ];
+            for (i, word) in program.into_iter().enumerate() {
+                cpu.bus.kernel_rom[4 * i..4 * i + 4].copy_from_slice(&word.to_le_bytes());
+            }
+            let watchpoint = |access| Watchpoint {
+                start: 0x80010010,
+                end: 0x80010013,
+                access,
+                enabled: true,
+            };
+
+            cpu.set_watchpoints(&[watchpoint(Access::Write)], false);
+            trace(&mut cpu, 2);
+            assert_eq!(cpu.take_watch_hit(), None);
+            trace(&mut cpu, 1);
+            let hit = cpu.take_watch_hit().unwrap();
+            assert_eq!(
+                (hit.pc, hit.addr, hit.size, hit.val),
+                (0xBFC00008, 0xA0010011, 1, 0x2A)
+            );
+            assert!(hit.write && !hit.dma);
+            trace(&mut cpu, 1);
+            assert_eq!(cpu.take_watch_hit(), None);
+
+            cpu.registers.program_counter = 0xBFC0000C;
+            cpu.set_watchpoints(&[watchpoint(Access::Read)], false);
+            trace(&mut cpu, 1);
+            let hit = cpu.take_watch_hit().unwrap();
+            assert_eq!(
+                (hit.pc, hit.addr, hit.val),
+                (0xBFC0000C, 0xA0010010, 0x2A00)
+            );
+            assert!(!hit.write);
+
+            // OTC DMA clears an ordering table of four entries ending at 80010018
+            let otc = |cpu: &mut Cpu| {
+                cpu.bus.store_word(0x1F8010F0, 0x08000000).unwrap();
+                cpu.bus.store_word(0x1F8010E0, 0x00010018).unwrap();
+                cpu.bus.store_word(0x1F8010E4, 4).unwrap();
+                cpu.bus.store_word(0x1F8010E8, 0x11000002).unwrap();
+            };
+            cpu.set_watchpoints(&[watchpoint(Access::Write)], false);
+            otc(&mut cpu);
+            assert_eq!(cpu.take_watch_hit(), None);
+            cpu.set_watchpoints(&[watchpoint(Access::Write)], true);
+            otc(&mut cpu);
+            let hit = cpu.take_watch_hit().unwrap();
+            assert_eq!((hit.addr, hit.val), (0x00010010, 0x0001000C));
+            assert!(hit.write && hit.dma);
+        });
+    }
+
+    #[test]
+    fn state_from_another_bios_is_refused_unless_forced() {
+        with_big_stack(|| {
+            let mut cpu = machine();
+            trace(&mut cpu, 1000);
+            let mut state = Vec::new();
+            cpu.save_state(&mut state, None);
+
+            let mut other = machine();
+            other.bus.kernel_rom[0x1000] = 0xFF;
+            assert!(matches!(
+                other.load_state(&state, false),
+                Err(StateError::WrongBios)
+            ));
+            assert_eq!(other.registers.program_counter, 0xBFC00000);
+            other.load_state(&state, true).unwrap();
+            assert_eq!(
+                other.registers.program_counter,
+                cpu.registers.program_counter
+            );
+            // The BIOS stays the host's own
+            assert_eq!(other.bus.kernel_rom[0x1000], 0xFF);
+        });
+    }
+
+    #[test]
+    fn sideloaded_exe_runs_once_the_bios_reaches_the_shell() {
+        with_big_stack(|| {
+            let mut cpu = Cpu::new();
+            // The BIOS jumps straight to the shell
+            for (i, word) in [0x3C088003u32, 0x01000008, 0].into_iter().enumerate() {
+                cpu.bus.kernel_rom[4 * i..4 * i + 4].copy_from_slice(&word.to_le_bytes());
+            }
+            // Stores a magic value, GP and SP at 0x80020000, then loops
+            let program = [
+                0x3C0900C0u32, // lui t1, 0x00C0
+                0x3529FFEE,    // ori t1, t1, 0xFFEE
+                0x3C088002,    // lui t0, 0x8002
+                0xAD090000,    // sw t1, 0(t0)
+                0xAD1C0004,    // sw gp, 4(t0)
+                0xAD1D0008,    // sw sp, 8(t0)
+                0x08004006,    // j 0x80010018
+                0x00000000,
+            ];
+            let mut exe = vec![0; exe::HEADER_SIZE];
+            exe[..8].copy_from_slice(b"PS-X EXE");
+            for (offset, val) in [
+                (0x10, 0x80010000),
+                (0x14, 0x8001C000),
+                (0x18, 0x80010000),
+                (0x1C, 4 * program.len() as u32),
+                (0x28, 0x80018000),
+                (0x2C, 0x100),
+                (0x30, 0x801FFF00),
+            ] {
+                exe[offset..offset + 4].copy_from_slice(&u32::to_le_bytes(val));
+            }
+            exe.extend(program.iter().flat_map(|word| word.to_le_bytes()));
+            cpu.bus.ram[0x8000..0x8200].fill(0xFF);
+
+            cpu.sideload_exe(&exe, false).unwrap();
+            assert_eq!(cpu.registers.program_counter, 0x80010000);
+            // Only the BSS is cleared
+            assert!(cpu.bus.ram[0x8000..0x8100].iter().all(|&byte| byte == 0));
+            assert!(cpu.bus.ram[0x8100..0x8200].iter().all(|&byte| byte == 0xFF));
+
+            trace(&mut cpu, 20);
+            let word = |offset: usize| {
+                u32::from_le_bytes(cpu.bus.ram[offset..offset + 4].try_into().unwrap())
+            };
+            assert_eq!(
+                [word(0x10000), word(0x10004), word(0x10008)],
+                [0x00C0FFEE, 0x8001C000, 0x801FFF00]
+            );
+        });
+    }
+}
@@ -0,0 +1,214 @@
+// Turns instruction words into text for the debugger, decoding them the same way the CPU does
+
+pub const REGISTER_NAMES: [&str; 32] = [
+    "zero", "at", "v0", "v1", "a0", "a1", "a2", "a3", "t0", "t1", "t2", "t3", "t4", "t5", "t6",
+    "t7", "s0", "s1", "s2", "s3", "s4", "s5", "s6", "s7", "t8", "t9", "k0", "k1", "gp", "sp", "fp",
+    "ra",
+];
+
+fn reg(opcode: u32, shift: u32) -> &'static str {
+    REGISTER_NAMES[((opcode >> shift) & 0x1F) as usize]
+}
+
+// Signed immediates are written as hex with a sign, such as -0x18
+fn signed(imm: i16) -> String {
+    if imm < 0 {
+        format!("-0x{:X}", -(imm as i32))
+    } else {
+        format!("0x{imm:X}")
+    }
+}
+
+// Address a branch or jump at an address goes to. Register jumps have no fixed target
+pub fn branch_target(opcode: u32, pc: u32) -> Option<u32> {
+    let offset = ((opcode & 0xFFFF) as i16 as i32) << 2;
+    match opcode >> 26 {
+        0x01 | 0x04..=0x07 => Some(pc.wrapping_add(4).wrapping_add(offset as u32)),
+        0x02 | 0x03 => Some((pc & 0xF0000000) | ((opcode & 0x03FFFFFF) << 2)),
+        _ => None,
+    }
+}
+
+// Whether the instruction after this one runs in its delay slot
+pub fn has_delay_slot(opcode: u32) -> bool {
+    let register_jump = opcode >> 26 == 0 && matches!(opcode & 0x3F, 0x08 | 0x09);
+    register_jump || branch_target(opcode, 0).is_some()
+}
+
+// Assembly for an instruction at an address, with ABI register names and branch targets
+// resolved, such as "ADDIU sp, sp, -0x18"
+pub fn disassemble(opcode: u32, pc: u32) -> String {
+    let (rs, rt) = (reg(opcode, 21), reg(opcode, 16));
+    let imm = (opcode & 0xFFFF) as i16;
+    let uimm = opcode & 0xFFFF;
+    let target = branch_target(opcode, pc).unwrap_or_default();
+
+    let (name, operands) = match opcode >> 26 {
+        0x00 => return special(opcode),
+        0x01 => {
+            let name = match (opcode >> 16) & 0x1F {
+                0x10 => "BLTZAL",
+                0x11 => "BGEZAL",
+                rt if rt & 1 == 1 => "BGEZ",
+                _ => "BLTZ",
+            };
+            (name, format!("{rs}, 0x{target:08X}"))
+        }
+        0x02 => ("J", format!("0x{target:08X}")),
+        0x03 => ("JAL", format!("0x{target:08X}")),
+        0x04 if rs == "zero" && rt == "zero" => ("B", format!("0x{target:08X}")),
+        0x04 => ("BEQ", format!("{rs}, {rt}, 0x{target:08X}")),
+        0x05 => ("BNE", format!("{rs}, {rt}, 0x{target:08X}")),
+        0x06 => ("BLEZ", format!("{rs}, 0x{target:08X}")),
+        0x07 => ("BGTZ", format!("{rs}, 0x{target:08X}")),
+        0x08 => ("ADDI", format!("{rt}, {rs}, {}", signed(imm))),
+        0x09 => ("ADDIU", format!("{rt}, {rs}, {}", signed(imm))),
+        0x0A => ("SLTI", format!("{rt}, {rs}, {}", signed(imm))),
+        0x0B => ("SLTIU", format!("{rt}, {rs}, {}", signed(imm))),
+        0x0C => ("ANDI", format!("{rt}, {rs}, 0x{uimm:X}")),
+        0x0D => ("ORI", format!("{rt}, {rs}, 0x{uimm:X}")),
+        0x0E => ("XORI", format!("{rt}, {rs}, 0x{uimm:X}")),
+        0x0F => ("LUI", format!("{rt}, 0x{uimm:X}")),
+        0x10..=0x13 => return coprocessor(opcode),
+        primary @ (0x20..=0x26 | 0x28..=0x2B | 0x2E) => {
+            let name = match primary {
+                0x20 => "LB",
+                0x21 => "LH",
+                0x22 => "LWL",
+                0x23 => "LW",
+                0x24 => "LBU",
+                0x25 => "LHU",
+                0x26 => "LWR",
+                0x28 => "SB",
+                0x29 => "SH",
+                0x2A => "SWL",
+                0x2B => "SW",
+                _ => "SWR",
+            };
+            (name, format!("{rt}, {}({rs})", signed(imm)))
+        }
+        primary @ (0x30..=0x33 | 0x38..=0x3B) => {
+            let name = if primary < 0x38 { "LWC" } else { "SWC" };
+            let cop = primary & 3;
+            let rt = (opcode >> 16) & 0x1F;
+            return format!("{name}{cop} ${rt}, {}({rs})", signed(imm));
+        }
+        _ => return unknown(opcode),
+    };
+    format!("{name} {operands}")
+}
+
+fn special(opcode: u32) -> String {
+    let (rs, rt, rd) = (reg(opcode, 21), reg(opcode, 16), reg(opcode, 11));
+    let sa = (opcode >> 6) & 0x1F;
+    if opcode == 0 {
+        return "NOP".to_string();
+    }
+
+    let (name, operands) = match opcode & 0x3F {
+        0x00 => ("SLL", format!("{rd}, {rt}, {sa}")),
+        0x02 => ("SRL", format!("{rd}, {rt}, {sa}")),
+        0x03 => ("SRA", format!("{rd}, {rt}, {sa}")),
+        0x04 => ("SLLV", format!("{rd}, {rt}, {rs}")),
+        0x06 => ("SRLV", format!("{rd}, {rt}, {rs}")),
+        0x07 => ("SRAV", format!("{rd}, {rt}, {rs}")),
+        0x08 => ("JR", rs.to_string()),
+        0x09 if rd == "ra" => ("JALR", rs.to_string()),
+        0x09 => ("JALR", format!("{rd}, {rs}")),
+        0x0C => return "SYSCALL".to_string(),
+        0x0D => return "BREAK".to_string(),
+        0x10 => ("MFHI", rd.to_string()),
+        0x11 => ("MTHI", rs.to_string()),
+        0x12 => ("MFLO", rd.to_string()),
+        0x13 => ("MTLO", rs.to_string()),
+        0x18 => ("MULT", format!("{rs}, {rt}")),
+        0x19 => ("MULTU", format!("{rs}, {rt}")),
+        0x1A => ("DIV", format!("{rs}, {rt}")),
+        0x1B => ("DIVU", format!("{rs}, {rt}")),
+        0x20 => ("ADD", format!("{rd}, {rs}, {rt}")),
+        0x21 if rt == "zero" => ("MOVE", format!("{rd}, {rs}")),
+        0x21 => ("ADDU", format!("{rd}, {rs}, {rt}")),
+        0x22 => ("SUB", format!("{rd}, {rs}, {rt}")),
+        0x23 => ("SUBU", format!("{rd}, {rs}, {rt}")),
+        0x24 => ("AND", format!("{rd}, {rs}, {rt}")),
+        0x25 => ("OR", format!("{rd}, {rs}, {rt}")),
+        0x26 => ("XOR", format!("{rd}, {rs}, {rt}")),
+        0x27 => ("NOR", format!("{rd}, {rs}, {rt}")),
+        0x2A => ("SLT", format!("{rd}, {rs}, {rt}")),
+        0x2B => ("SLTU", format!("{rd}, {rs}, {rt}")),
+        _ => return unknown(opcode),
+    };
+    format!("{name} {operands}")
+}
+
+// Coprocessor registers are written by number, such as "MTC0 t0, $12"
+fn coprocessor(opcode: u32) -> String {
+    let cop = (opcode >> 26) & 3;
+    let rt = reg(opcode, 16);
+    let rd = (opcode >> 11) & 0x1F;
+    match (opcode >> 21) & 0x1F {
+        0x00 => format!("MFC{cop} {rt}, ${rd}"),
+        0x02 => format!("CFC{cop} {rt}, ${rd}"),
+        0x04 => format!("MTC{cop} {rt}, ${rd}"),
+        0x06 => format!("CTC{cop} {rt}, ${rd}"),
+        0x10..=0x1F if cop == 0 && opcode & 0x3F == 0x10 => "RFE".to_string(),
+        0x10..=0x1F => format!("COP{cop} 0x{:07X}", opcode & 0x01FFFFFF),
+        _ => unknown(opcode),
+    }
+}
+
+fn unknown(opcode: u32) -> String {
+    format!(".word 0x{opcode:08X}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instructions_are_written_with_abi_names_and_targets() {
+        let pc = 0x80010000;
+        for (opcode, expected) in [
+            (0x00000000, "NOP"),
+            (0x27BDFFE8, "ADDIU sp, sp, -0x18"),
+            (0xAFBF0014, "SW ra, 0x14(sp)"),
+            (0x8C82FFFC, "LW v0, -0x4(a0)"),
+            (0x3C08BFC0, "LUI t0, 0xBFC0"),
+            (0x35080123, "ORI t0, t0, 0x123"),
+            (0x00081080, "SLL v0, t0, 2"),
+            (0x00801021, "MOVE v0, a0"),
+            (0x00851021, "ADDU v0, a0, a1"),
+            (0x03E00008, "JR ra"),
+            (0x0040F809, "JALR v0"),
+            (0x0040C809, "JALR t9, v0"),
+            (0x0000000C, "SYSCALL"),
+            (0x1000FFFF, "B 0x80010000"),
+            (0x1440FFFE, "BNE v0, zero, 0x8000FFFC"),
+            (0x04110003, "BGEZAL zero, 0x80010010"),
+            (0x0C000400, "JAL 0x80001000"),
+            (0x40806000, "MTC0 zero, $12"),
+            (0x42000010, "RFE"),
+            (0x4A180001, "COP2 0x0180001"),
+            (0xC8A10000, "LWC2 $1, 0x0(a1)"),
+            (0xFC000000, ".word 0xFC000000"),
+            (0x0000003F, ".word 0x0000003F"),
+        ] {
+            assert_eq!(disassemble(opcode, pc), expected, "{opcode:08X}");
+        }
+    }
+
+    #[test]
+    fn branches_and_jumps_have_delay_slots() {
+        // Jumps keep the top bits of the address of their delay slot
+        assert_eq!(branch_target(0x0C000400, 0xBFC00000), Some(0xB0001000));
+        assert_eq!(branch_target(0x1000FFFF, 0x80010000), Some(0x80010000));
+        assert_eq!(branch_target(0x03E00008, 0x80010000), None);
+
+        for opcode in [0x03E00008, 0x0040F809, 0x1000FFFF, 0x04110003, 0x0C000400] {
+            assert!(has_delay_slot(opcode), "{opcode:08X}");
+        }
+        for opcode in [0x00000000, 0x27BDFFE8, 0x0000000C, 0x42000010] {
+            assert!(!has_delay_slot(opcode), "{opcode:08X}");
+        }
+    }
+}
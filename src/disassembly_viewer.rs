@@ -0,0 +1,209 @@
+use eframe::egui::{self, Color32, RichText};
+
+use crate::breakpoints::Breakpoint;
+use crate::cpu::Cpu;
+use crate::disasm::{self, REGISTER_NAMES};
+
+// The listing scrolls through 64KB of instructions around the address it was centered on
+const ROWS: u32 = 0x4000;
+// Recentered once the PC comes this close to either end
+const EDGE_ROWS: u32 = 64;
+
+// Requests from the listing that the frontend carries out
+pub enum DisassemblyAction {
+    ToggleBreakpoint(u32),
+    RunTo(u32),
+}
+
+// Debug window with a disassembly listing that follows the program counter
+pub struct DisassemblyViewer {
+    pub open: bool,
+    // Recenter on the PC whenever it changes
+    pub follow_pc: bool,
+    address_text: String,
+    start: u32,
+    last_pc: Option<u32>,
+    // Address jumped to, highlighted in the listing
+    target: Option<u32>,
+    scroll_to: Option<u32>,
+}
+
+impl DisassemblyViewer {
+    pub fn new() -> Self {
+        Self {
+            open: false,
+            follow_pc: true,
+            address_text: String::new(),
+            start: 0,
+            last_pc: None,
+            target: None,
+            scroll_to: None,
+        }
+    }
+
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        cpu: &Cpu,
+        breakpoints: &[Breakpoint],
+    ) -> Option<DisassemblyAction> {
+        let pc = cpu.registers.program_counter;
+        if self.follow_pc && self.last_pc != Some(pc) {
+            self.center(pc);
+        }
+        self.last_pc = Some(pc);
+
+        let mut action = None;
+        let mut open = self.open;
+        egui::Window::new("Disassembly")
+            .open(&mut open)
+            .default_size([460.0, 500.0])
+            .show(ctx, |ui| action = self.contents(ui, cpu, breakpoints));
+        self.open = open;
+        action
+    }
+
+    fn center(&mut self, addr: u32) {
+        let addr = addr & !3;
+        let row = addr.wrapping_sub(self.start) / 4;
+        if !(EDGE_ROWS..ROWS - EDGE_ROWS).contains(&row) {
+            self.start = addr.wrapping_sub(ROWS / 2 * 4);
+        }
+        self.scroll_to = Some(addr);
+    }
+
+    fn goto(&mut self, addr: u32) {
+        self.follow_pc = false;
+        self.target = Some(addr & !3);
+        self.address_text = format!("{addr:08X}");
+        self.center(addr);
+    }
+
+    fn contents(
+        &mut self,
+        ui: &mut egui::Ui,
+        cpu: &Cpu,
+        breakpoints: &[Breakpoint],
+    ) -> Option<DisassemblyAction> {
+        ui.horizontal(|ui| {
+            ui.label("Address");
+            let response = ui.add(
+                egui::TextEdit::singleline(&mut self.address_text)
+                    .desired_width(80.0)
+                    .font(egui::TextStyle::Monospace),
+            );
+            if response.lost_focus()
+                && ui.input(|i| i.key_pressed(egui::Key::Enter))
+                && let Ok(addr) = u32::from_str_radix(self.address_text.trim(), 16)
+            {
+                self.goto(addr);
+            }
+            if ui.checkbox(&mut self.follow_pc, "Follow PC").changed() && self.follow_pc {
+                self.target = None;
+                self.center(cpu.registers.program_counter);
+            }
+        });
+        ui.label("Click the gutter to toggle a breakpoint, right click for more");
+        ui.separator();
+
+        let pc = cpu.registers.program_counter;
+        // The instruction after a branch at the PC runs before the branch is taken
+        let delay_slot = cpu
+            .bus
+            .peek_word(pc)
+            .filter(|opcode| disasm::has_delay_slot(*opcode))
+            .map(|_| pc.wrapping_add(4));
+
+        let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+        let mut scroll = egui::ScrollArea::vertical().auto_shrink(false);
+        if let Some(addr) = self.scroll_to.take() {
+            let row = addr.wrapping_sub(self.start) / 4;
+            let offset = row as f32 * (row_height + ui.spacing().item_spacing.y);
+            scroll = scroll.vertical_scroll_offset(offset - ui.available_height() / 2.0);
+        }
+
+        let mut action = None;
+        scroll.show_rows(ui, row_height, ROWS as usize, |ui, rows| {
+            for row in rows {
+                let addr = self.start.wrapping_add(row as u32 * 4);
+                let breakpoint = breakpoints
+                    .iter()
+                    .find(|breakpoint| breakpoint.addr == addr);
+                ui.horizontal(|ui| {
+                    let gutter = match breakpoint {
+                        Some(breakpoint) if breakpoint.enabled => {
+                            RichText::new("●").color(Color32::RED)
+                        }
+                        Some(_) => RichText::new("○").color(Color32::RED),
+                        None => RichText::new("●").color(Color32::from_gray(60)),
+                    };
+                    if ui
+                        .add(egui::Label::new(gutter).sense(egui::Sense::click()))
+                        .clicked()
+                    {
+                        action = Some(DisassemblyAction::ToggleBreakpoint(addr));
+                    }
+
+                    // Unmapped space and registers that can't be read safely
+                    let opcode = cpu.bus.peek_word(addr);
+                    let mut text = RichText::new(listing_line(addr, opcode)).monospace();
+                    if addr == pc {
+                        text = text.color(Color32::YELLOW);
+                    } else if Some(addr) == delay_slot {
+                        text = text.color(Color32::LIGHT_BLUE);
+                    } else if Some(addr) == self.target {
+                        text = text.color(Color32::WHITE);
+                    }
+
+                    let response = ui.add(egui::Label::new(text).sense(egui::Sense::click()));
+                    response.context_menu(|ui| {
+                        if ui.button("Run to here").clicked() {
+                            action = Some(DisassemblyAction::RunTo(addr));
+                        }
+                        let branch = opcode.and_then(|opcode| disasm::branch_target(opcode, addr));
+                        if let Some(branch) = branch
+                            && ui
+                                .button(format!("Follow branch to {branch:08X}"))
+                                .clicked()
+                        {
+                            self.goto(branch);
+                        }
+                        ui.menu_button("Follow register", |ui| {
+                            for (name, val) in REGISTER_NAMES.iter().zip(cpu.registers.registers) {
+                                if ui.button(format!("{name} {val:08X}")).clicked() {
+                                    self.goto(val);
+                                }
+                            }
+                        });
+                    });
+                });
+            }
+        });
+        action
+    }
+}
+
+// Address, instruction word and assembly, or question marks where nothing can be read
+fn listing_line(addr: u32, opcode: Option<u32>) -> String {
+    match opcode {
+        Some(opcode) => format!(
+            "{addr:08X}  {opcode:08X}  {}",
+            disasm::disassemble(opcode, addr)
+        ),
+        None => format!("{addr:08X}  ????????  ??"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listing_lines_show_the_word_and_assembly() {
+        assert_eq!(
+            listing_line(0x80010000, Some(0x27BDFFE8)),
+            "80010000  27BDFFE8  ADDIU sp, sp, -0x18"
+        );
+        assert_eq!(listing_line(0x1F800400, None), "1F800400  ????????  ??");
+    }
+}
@@ -0,0 +1,632 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::chd::Chd;
+
+pub const SECTOR_SIZE: usize = 2352;
+const ISO_SECTOR_SIZE: usize = 2048;
+// Sectors in the 2 second pregap before LBA 0
+const PREGAP: u32 = 150;
+// Sectors read to tell discs apart
+const HASHED_SECTORS: u32 = 32;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TrackKind {
+    Data,
+    Audio,
+}
+
+// Region named in the license text. Each BIOS only boots its own region
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Region {
+    Japan,
+    NorthAmerica,
+    Europe,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Track {
+    pub number: u8,
+    pub kind: TrackKind,
+    // First sector and length in sectors
+    pub start: u32,
+    pub length: u32,
+    // Image file and the sector within it holding the track start
+    file: usize,
+    file_start: u32,
+}
+
+enum Image {
+    // 2048 byte user data sectors. Sync, header and subheader are synthesized on read
+    Iso(File),
+    // Raw 2352 byte sectors from the files listed in a cue sheet
+    Bin(Vec<File>),
+    // Compressed CD image. Track file starts are frame numbers
+    Chd(Chd),
+}
+
+pub struct Disc {
+    image: Image,
+    tracks: Vec<Track>,
+    // Subchannel Q replaced by an .sbi file, keyed by LBA. Used by libcrypt protected discs
+    subq_overrides: HashMap<u32, [u8; 10]>,
+    // Worked out the first time it is asked for
+    hash: Option<u32>,
+}
+
+pub fn is_disc_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            ["iso", "bin", "cue", "chd"]
+                .iter()
+                .any(|kind| ext.eq_ignore_ascii_case(kind))
+        })
+}
+
+impl Disc {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut disc = match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("iso") => Self::from_iso(path),
+            Some(ext) if ext.eq_ignore_ascii_case("bin") => Self::from_bin(path),
+            Some(ext) if ext.eq_ignore_ascii_case("cue") => Self::from_cue(path),
+            Some(ext) if ext.eq_ignore_ascii_case("chd") => Self::from_chd(path),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "unsupported disc image format",
+            )),
+        }?;
+
+        // Libcrypt subchannel data is kept in an .sbi file next to the image
+        let sbi = path.with_extension("sbi");
+        if sbi.exists() {
+            disc.load_sbi(&sbi)?;
+        }
+        Ok(disc)
+    }
+
+    pub fn from_iso(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let length = file.metadata()?.len().div_ceil(ISO_SECTOR_SIZE as u64) as u32;
+        Ok(Self {
+            image: Image::Iso(file),
+            tracks: vec![Track {
+                number: 1,
+                kind: TrackKind::Data,
+                start: 0,
+                length,
+                file: 0,
+                file_start: 0,
+            }],
+            subq_overrides: HashMap::new(),
+            hash: None,
+        })
+    }
+
+    // Raw 2352 byte sectors without a cue sheet, read as a single data track
+    pub fn from_bin(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let length = (file.metadata()?.len() / SECTOR_SIZE as u64) as u32;
+        Ok(Self {
+            image: Image::Bin(vec![file]),
+            tracks: vec![Track {
+                number: 1,
+                kind: TrackKind::Data,
+                start: 0,
+                length,
+                file: 0,
+                file_start: 0,
+            }],
+            subq_overrides: HashMap::new(),
+            hash: None,
+        })
+    }
+
+    // Supports FILE, TRACK, PREGAP and INDEX 01 entries. Other lines are ignored
+    pub fn from_cue(path: &Path) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        let cue = fs::read_to_string(path)?;
+        let folder = path.parent().unwrap_or(Path::new(""));
+
+        let mut files = Vec::new();
+        let mut file_sectors = Vec::new();
+        let mut tracks: Vec<Track> = Vec::new();
+        // Disc LBA of the first sector of the current file
+        let mut file_lba = 0;
+
+        for line in cue.lines() {
+            let line = line.trim();
+            let (keyword, args) = line.split_once(' ').unwrap_or((line, ""));
+            match keyword.to_ascii_uppercase().as_str() {
+                "FILE" => {
+                    let file = File::open(folder.join(cue_file_name(args)))?;
+                    let sectors = file.metadata()?.len().div_ceil(SECTOR_SIZE as u64) as u32;
+
+                    file_lba += file_sectors.last().copied().unwrap_or(0);
+                    files.push(file);
+                    file_sectors.push(sectors);
+                }
+                "TRACK" => {
+                    let mut args = args.split_whitespace();
+                    let number = args
+                        .next()
+                        .and_then(|number| number.parse().ok())
+                        .ok_or_else(|| invalid("bad TRACK entry"))?;
+                    let kind = match args.next() {
+                        Some(kind) if kind.eq_ignore_ascii_case("AUDIO") => TrackKind::Audio,
+                        _ => TrackKind::Data,
+                    };
+                    if files.is_empty() {
+                        return Err(invalid("TRACK before FILE"));
+                    }
+                    tracks.push(Track {
+                        number,
+                        kind,
+                        start: 0,
+                        length: 0,
+                        file: files.len() - 1,
+                        file_start: 0,
+                    });
+                }
+                // Silence that is not stored in the file
+                "PREGAP" => file_lba += parse_msf(args).ok_or_else(|| invalid("bad PREGAP"))?,
+                "INDEX" => {
+                    let mut args = args.split_whitespace();
+                    if args.next() != Some("01") {
+                        continue;
+                    }
+                    let offset = args
+                        .next()
+                        .and_then(parse_msf)
+                        .ok_or_else(|| invalid("bad INDEX entry"))?;
+                    let track = tracks
+                        .last_mut()
+                        .ok_or_else(|| invalid("INDEX before TRACK"))?;
+                    track.file_start = offset;
+                    track.start = file_lba + offset;
+                }
+                _ => {}
+            }
+        }
+
+        if tracks.is_empty() {
+            return Err(invalid("cue sheet has no tracks"));
+        }
+
+        // Tracks end where the next one in the same file starts, or at the end of the file
+        for i in 0..tracks.len() {
+            let end = match tracks.get(i + 1) {
+                Some(next) if next.file == tracks[i].file => next.file_start,
+                _ => file_sectors[tracks[i].file],
+            };
+            tracks[i].length = end.saturating_sub(tracks[i].file_start);
+        }
+
+        Ok(Self {
+            image: Image::Bin(files),
+            tracks,
+            subq_overrides: HashMap::new(),
+            hash: None,
+        })
+    }
+
+    pub fn from_chd(path: &Path) -> io::Result<Self> {
+        let chd = Chd::open(path)?;
+        let mut tracks = Vec::new();
+        let mut lba = 0;
+        let mut frame = 0;
+        for (i, chd_track) in chd.tracks().iter().enumerate() {
+            // The pregap of the first track lies before LBA 0
+            let pregap = if i == 0 { 0 } else { chd_track.pregap };
+            let stored_pregap = if chd_track.pregap_stored {
+                chd_track.pregap
+            } else {
+                0
+            };
+            let length = chd_track.frames.saturating_sub(stored_pregap);
+
+            tracks.push(Track {
+                number: chd_track.number,
+                kind: if chd_track.audio {
+                    TrackKind::Audio
+                } else {
+                    TrackKind::Data
+                },
+                start: lba + pregap,
+                length,
+                file: 0,
+                file_start: frame + stored_pregap,
+            });
+
+            lba += pregap + length;
+            // Tracks are padded to a multiple of 4 frames
+            frame += chd_track.frames.next_multiple_of(4);
+        }
+
+        Ok(Self {
+            image: Image::Chd(chd),
+            tracks,
+            subq_overrides: HashMap::new(),
+            hash: None,
+        })
+    }
+
+    pub fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    // Track holding the given LBA, including the pregap before the next track
+    pub fn track_at(&self, lba: u32) -> Option<&Track> {
+        self.tracks.iter().rev().find(|track| track.start <= lba)
+    }
+
+    // First sector after the last track
+    pub fn lead_out(&self) -> u32 {
+        self.tracks
+            .last()
+            .map(|track| track.start + track.length)
+            .unwrap_or(0)
+    }
+
+    // Tells discs apart without reading all of them. Covers the length and the first sectors,
+    // which hold the volume descriptor, root directory and usually SYSTEM.CNF
+    pub fn hash(&mut self) -> u32 {
+        if let Some(hash) = self.hash {
+            return hash;
+        }
+        let mut crc = flate2::Crc::new();
+        crc.update(&self.lead_out().to_le_bytes());
+        for lba in 0..HASHED_SECTORS {
+            if let Ok(sector) = self.read_sector(lba) {
+                crc.update(&sector);
+            }
+        }
+        *self.hash.insert(crc.sum())
+    }
+
+    // Entries are a BCD MSF and a type byte. Type 1 replaces the Q data, types 2 and 3 replace
+    // only the relative or absolute position
+    pub fn load_sbi(&mut self, path: &Path) -> io::Result<()> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        let sbi = fs::read(path)?;
+        let Some(mut entries) = sbi.strip_prefix(b"SBI\0") else {
+            return Err(invalid("missing SBI header"));
+        };
+
+        while let [minute, second, frame, kind, rest @ ..] = entries {
+            let lba = msf_to_lba(from_bcd(*minute), from_bcd(*second), from_bcd(*frame));
+            let (range, len) = match kind {
+                1 => (0..10, 10),
+                2 => (3..6, 3),
+                3 => (7..10, 3),
+                _ => return Err(invalid("unknown SBI entry type")),
+            };
+            if rest.len() < len {
+                return Err(invalid("truncated SBI entry"));
+            }
+
+            let mut data: [u8; 10] = self.subq(lba)[..10].try_into().unwrap();
+            data[range].copy_from_slice(&rest[..len]);
+            self.subq_overrides.insert(lba, data);
+            entries = &rest[len..];
+        }
+        Ok(())
+    }
+
+    // Subchannel Q for the sector: control/ADR, track, index, relative and absolute position
+    // in BCD, followed by the CRC
+    pub fn subq(&self, lba: u32) -> [u8; 12] {
+        let mut q = [0; 12];
+        if let Some(data) = self.subq_overrides.get(&lba) {
+            q[..10].copy_from_slice(data);
+        } else {
+            let track = self
+                .tracks
+                .iter()
+                .find(|track| lba < track.start + track.length);
+            let (control, number, index, relative) = match track {
+                // Pregap sectors are index 0 and count down to the track start
+                Some(track) if lba < track.start => {
+                    (control(track), to_bcd(track.number), 0, track.start - lba)
+                }
+                Some(track) => (control(track), to_bcd(track.number), 1, lba - track.start),
+                None => (0x01, 0xAA, 1, lba.saturating_sub(self.lead_out())),
+            };
+
+            let (rm, rs, rf) = sectors_to_msf(relative);
+            let (am, as_, af) = lba_to_msf(lba);
+            q[0] = control;
+            q[1] = number;
+            q[2] = index;
+            q[3..6].copy_from_slice(&[rm, rs, rf].map(to_bcd));
+            q[7..10].copy_from_slice(&[am, as_, af].map(to_bcd));
+        }
+
+        let crc = subq_crc(&q[..10]);
+        q[10..12].copy_from_slice(&crc.to_be_bytes());
+        q
+    }
+
+    // Region from the license text in sector 4. None if the disc is unlicensed
+    pub fn region(&mut self) -> Option<Region> {
+        let data = self.read_user_data(4).ok()?;
+        let text = String::from_utf8_lossy(&data);
+        if !text.contains("Sony Computer Entertainment") {
+            return None;
+        }
+
+        if text.contains("Euro") {
+            Some(Region::Europe)
+        } else if text.contains("Inc.") {
+            Some(Region::Japan)
+        } else {
+            Some(Region::NorthAmerica)
+        }
+    }
+
+    // Executable named by the BOOT line of SYSTEM.CNF, without the cdrom: prefix. The BIOS
+    // falls back to PSX.EXE when there is no SYSTEM.CNF
+    pub fn boot_executable(&mut self) -> io::Result<String> {
+        let cnf = match self.read_file("SYSTEM.CNF") {
+            Ok(cnf) => cnf,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok("PSX.EXE".into()),
+            Err(err) => return Err(err),
+        };
+
+        String::from_utf8_lossy(&cnf)
+            .lines()
+            .find_map(|line| {
+                let (key, value) = line.split_once('=')?;
+                (key.trim() == "BOOT").then(|| value.trim())
+            })
+            .map(|boot| {
+                let path = boot.split_whitespace().next().unwrap_or("");
+                let path = path.strip_prefix("cdrom:").unwrap_or(path);
+                path.trim_start_matches('\\').to_string()
+            })
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no BOOT line"))
+    }
+
+    // Reads a file from the ISO9660 filesystem. Directories are separated by backslashes and
+    // names are matched without case or the ;1 version suffix
+    pub fn read_file(&mut self, path: &str) -> io::Result<Vec<u8>> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        let descriptor = self.read_user_data(16)?;
+        if &descriptor[1..6] != b"CD001" {
+            return Err(invalid("no ISO9660 volume descriptor"));
+        }
+
+        // The root directory record is embedded in the primary volume descriptor
+        let mut extent = u32::from_le_bytes(descriptor[158..162].try_into().unwrap());
+        let mut size = u32::from_le_bytes(descriptor[166..170].try_into().unwrap());
+        for name in path.split(['\\', '/']).filter(|name| !name.is_empty()) {
+            (extent, size) = self
+                .find_entry(extent, size, name)?
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, path.to_string()))?;
+        }
+
+        let mut file = Vec::with_capacity(size as usize);
+        for lba in extent..extent + (size as usize).div_ceil(ISO_SECTOR_SIZE) as u32 {
+            file.extend_from_slice(&self.read_user_data(lba)?);
+        }
+        file.truncate(size as usize);
+        Ok(file)
+    }
+
+    // Extent and size of the named entry in a directory
+    fn find_entry(&mut self, extent: u32, size: u32, name: &str) -> io::Result<Option<(u32, u32)>> {
+        for lba in extent..extent + (size as usize).div_ceil(ISO_SECTOR_SIZE) as u32 {
+            let data = self.read_user_data(lba)?;
+            let mut pos = 0;
+            // Records never cross a sector. A zero length pads to the next one
+            while pos < ISO_SECTOR_SIZE && data[pos] != 0 {
+                let record = &data[pos..(pos + data[pos] as usize).min(ISO_SECTOR_SIZE)];
+                pos += data[pos] as usize;
+                let Some(&name_len) = record.get(32) else {
+                    continue;
+                };
+                let Some(entry_name) = record.get(33..33 + name_len as usize) else {
+                    continue;
+                };
+
+                let entry_name = String::from_utf8_lossy(entry_name);
+                let entry_name = entry_name.split(';').next().unwrap_or("");
+                if entry_name.eq_ignore_ascii_case(name) {
+                    let extent = u32::from_le_bytes(record[2..6].try_into().unwrap());
+                    let size = u32::from_le_bytes(record[10..14].try_into().unwrap());
+                    return Ok(Some((extent, size)));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    // 2048 bytes of user data. Mode 1 sectors have no subheader
+    fn read_user_data(&mut self, lba: u32) -> io::Result<[u8; ISO_SECTOR_SIZE]> {
+        let sector = self.read_sector(lba)?;
+        let start = if sector[15] == 1 { 16 } else { 24 };
+        Ok(sector[start..start + ISO_SECTOR_SIZE].try_into().unwrap())
+    }
+
+    // Returns the full 2352 byte sector at the given LBA
+    pub fn read_sector(&mut self, lba: u32) -> io::Result<[u8; SECTOR_SIZE]> {
+        match &mut self.image {
+            Image::Iso(file) => {
+                let mut data = [0; ISO_SECTOR_SIZE];
+                file.seek(SeekFrom::Start(lba as u64 * ISO_SECTOR_SIZE as u64))?;
+                read_up_to(file, &mut data)?;
+                Ok(mode2_form1_sector(lba, &data))
+            }
+            Image::Bin(files) => {
+                let mut sector = [0; SECTOR_SIZE];
+                // Pregaps not stored in the image and the lead out read as silence
+                let Some(track) = self.tracks.iter().rev().find(|track| track.start <= lba) else {
+                    return Ok(sector);
+                };
+                let offset = lba - track.start;
+                if offset < track.length {
+                    let file = &mut files[track.file];
+                    let pos = (track.file_start + offset) as u64 * SECTOR_SIZE as u64;
+                    file.seek(SeekFrom::Start(pos))?;
+                    read_up_to(file, &mut sector)?;
+                }
+                Ok(sector)
+            }
+            Image::Chd(chd) => {
+                let mut sector = [0; SECTOR_SIZE];
+                let Some(track) = self.tracks.iter().rev().find(|track| track.start <= lba) else {
+                    return Ok(sector);
+                };
+                let offset = lba - track.start;
+                if offset < track.length {
+                    chd.read_frame(track.file_start + offset, &mut sector)?;
+                    // Audio is stored with big endian samples
+                    if track.kind == TrackKind::Audio {
+                        for sample in sector.chunks_exact_mut(2) {
+                            sample.swap(0, 1);
+                        }
+                    }
+                }
+                Ok(sector)
+            }
+        }
+    }
+}
+
+// Opens user data as an ISO image, written to a temporary file. Each call gets its own file as
+// tests run in parallel
+#[cfg(test)]
+pub fn test_iso(name: &str, data: &[u8]) -> Disc {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    static COUNT: AtomicU32 = AtomicU32::new(0);
+    let count = COUNT.fetch_add(1, Ordering::Relaxed);
+    let file = format!("ps1_emulator_{name}_{}_{count}.iso", std::process::id());
+    let path = std::env::temp_dir().join(file);
+    fs::write(&path, data).unwrap();
+    let disc = Disc::from_iso(&path).unwrap();
+    // The open file keeps the data on Unix. Elsewhere it is left behind
+    let _ = fs::remove_file(&path);
+    disc
+}
+
+// Data tracks set the data bit in the control nibble. ADR 1 marks position data
+fn control(track: &Track) -> u8 {
+    match track.kind {
+        TrackKind::Data => 0x41,
+        TrackKind::Audio => 0x01,
+    }
+}
+
+// CRC-16-CCITT over the Q data, stored inverted
+fn subq_crc(bytes: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in bytes {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = (crc << 1) ^ (0x1021 * (crc >> 15));
+        }
+    }
+    !crc
+}
+
+// The name of a cue sheet FILE entry is quoted and followed by the file type
+fn cue_file_name(args: &str) -> &str {
+    match args.strip_prefix('"') {
+        Some(quoted) => quoted.split('"').next().unwrap_or(""),
+        None => args.split_whitespace().next().unwrap_or(""),
+    }
+}
+
+// Paths of the track files a cue sheet refers to
+pub fn cue_files(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let cue = fs::read_to_string(path)?;
+    let folder = path.parent().unwrap_or(Path::new(""));
+    Ok(cue
+        .lines()
+        .filter_map(|line| {
+            let (keyword, args) = line.trim().split_once(' ')?;
+            keyword
+                .eq_ignore_ascii_case("FILE")
+                .then(|| folder.join(cue_file_name(args)))
+        })
+        .collect())
+}
+
+// Fills as much of the buffer as the file allows. The last sector of an image may be short
+fn read_up_to(file: &mut File, buf: &mut [u8]) -> io::Result<()> {
+    let mut read = 0;
+    while read < buf.len() {
+        match file.read(&mut buf[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    Ok(())
+}
+
+// Parses an mm:ss:ff cue sheet time into sectors
+fn parse_msf(msf: &str) -> Option<u32> {
+    let mut parts = msf.trim().split(':').map(|part| part.parse::<u32>().ok());
+    let (minute, second, frame) = (parts.next()??, parts.next()??, parts.next()??);
+    Some((minute * 60 + second) * 75 + frame)
+}
+
+// Wraps 2048 bytes of user data in MODE2 FORM1 framing. ECC is left zeroed
+fn mode2_form1_sector(lba: u32, data: &[u8; ISO_SECTOR_SIZE]) -> [u8; SECTOR_SIZE] {
+    let mut sector = [0; SECTOR_SIZE];
+    sector[1..11].fill(0xFF);
+
+    let (m, s, f) = lba_to_msf(lba);
+    sector[12] = to_bcd(m);
+    sector[13] = to_bcd(s);
+    sector[14] = to_bcd(f);
+    sector[15] = 2;
+
+    // Subheader is stored twice. Submode has only the data bit set
+    sector[18] = 0x08;
+    sector[22] = 0x08;
+
+    sector[24..2072].copy_from_slice(data);
+    let edc = edc(&sector[16..2072]);
+    sector[2072..2076].copy_from_slice(&edc.to_le_bytes());
+    sector
+}
+
+// CRC32 over the subheader and data using the CD-ROM EDC polynomial
+fn edc(bytes: &[u8]) -> u32 {
+    let mut edc = 0u32;
+    for &byte in bytes {
+        edc ^= byte as u32;
+        for _ in 0..8 {
+            edc = (edc >> 1) ^ (0xD8018001 * (edc & 1));
+        }
+    }
+    edc
+}
+
+pub fn lba_to_msf(lba: u32) -> (u8, u8, u8) {
+    sectors_to_msf(lba + PREGAP)
+}
+
+pub fn sectors_to_msf(sectors: u32) -> (u8, u8, u8) {
+    let minute = sectors / (60 * 75);
+    let second = (sectors / 75) % 60;
+    let frame = sectors % 75;
+    (minute as u8, second as u8, frame as u8)
+}
+
+pub fn to_bcd(val: u8) -> u8 {
+    ((val / 10) << 4) | (val % 10)
+}
+
+pub fn from_bcd(val: u8) -> u8 {
+    (val >> 4) * 10 + (val & 0xF)
+}
+
+// Sectors before 00:02:00 are part of the pregap and clamp to LBA 0
+pub fn msf_to_lba(minute: u8, second: u8, frame: u8) -> u32 {
+    let sector = (minute as u32 * 60 + second as u32) * 75 + frame as u32;
+    sector.saturating_sub(PREGAP)
+}
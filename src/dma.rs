@@ -1,11 +1,14 @@
+use serde::{Deserialize, Serialize};
 use tracing::{Level, event};
 
+#[derive(Serialize, Deserialize)]
 pub enum SyncMode {
     Burst,
     Slice,
     LinkedList,
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Dma {
     pub enabled: bool,
     pub madr: u32,
@@ -80,8 +83,14 @@ impl Dma {
     pub fn finish_dma(&mut self) {
         self.channel_control &= 0xFEFFFFFF;
     }
+
+    // A started transfer waiting on its device
+    pub fn active(&self) -> bool {
+        self.enabled && self.channel_control & 0x1000000 > 0
+    }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Dicr(u32);
 
 impl Dicr {
@@ -123,6 +132,24 @@ impl Dicr {
         self.0 & 0x80000000 > 0
     }
 
+    pub fn dma0_mask_set(&self) -> bool {
+        self.0 & 0x10000 > 0
+    }
+
+    pub fn dma0_set_interrupt_flag(&mut self) {
+        self.0 |= 0x1000000;
+        self.master_interrupt_calc();
+    }
+
+    pub fn dma1_mask_set(&self) -> bool {
+        self.0 & 0x20000 > 0
+    }
+
+    pub fn dma1_set_interrupt_flag(&mut self) {
+        self.0 |= 0x2000000;
+        self.master_interrupt_calc();
+    }
+
     pub fn dma2_mask_set(&self) -> bool {
         self.0 & 0x40000 > 0
     }
@@ -132,6 +159,24 @@ impl Dicr {
         self.master_interrupt_calc();
     }
 
+    pub fn dma3_mask_set(&self) -> bool {
+        self.0 & 0x80000 > 0
+    }
+
+    pub fn dma3_set_interrupt_flag(&mut self) {
+        self.0 |= 0x8000000;
+        self.master_interrupt_calc();
+    }
+
+    pub fn dma4_mask_set(&self) -> bool {
+        self.0 & 0x100000 > 0
+    }
+
+    pub fn dma4_set_interrupt_flag(&mut self) {
+        self.0 |= 0x10000000;
+        self.master_interrupt_calc();
+    }
+
     pub fn dma6_mask_set(&self) -> bool {
         self.0 & 0x400000 > 0
     }
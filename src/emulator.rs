@@ -0,0 +1,1509 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use eframe::egui::{self, Color32};
+use serde::{Deserialize, Serialize};
+
+use crate::audio::AudioSink;
+use crate::cpu::Cpu;
+use crate::gpu::DebugView;
+use crate::movie::{Movie, MovieFrame};
+use crate::pad::Buttons;
+use crate::sio::Rumble;
+use crate::state::StateError;
+use crate::tracing_setup;
+use crate::tty_console::TtyOutput;
+use crate::watchpoints::WatchHit;
+
+pub const CPU_CLOCK: f32 = 33_868_800.0;
+// CPU cycles in a 60Hz frame, the most one pass of the loop runs without audio sync
+const FRAME_CYCLES: u64 = 564_480;
+// Stereo frames audio sync keeps queued, three 60Hz frames at 44.1kHz
+const AUDIO_TARGET_FRAMES: usize = 3 * 735;
+// How often audio sync checks whether the output needs topping up
+const AUDIO_POLL: Duration = Duration::from_millis(4);
+// Frames the limiter may fall behind before it gives up catching up
+const MAX_LATE_FRAMES: u32 = 4;
+// Host time spent emulating per pass when fast forward is uncapped
+const FAST_FORWARD_SLICE: Duration = Duration::from_millis(15);
+// Speed of a throttled machine as a fraction of a real console's
+const THROTTLED_SPEED: f64 = 0.25;
+// How often a paused machine checks whether the debug windows changed it
+const PAUSED_REDRAW: Duration = Duration::from_millis(50);
+// How long closing waits for the worker to finish its frame
+const STOP_TIMEOUT: Duration = Duration::from_secs(2);
+// Most frames run-ahead can be set to run past the one emulated for real
+pub const MAX_RUN_AHEAD: u32 = 2;
+
+// Whether the emulation loop runs, and how far a step goes when paused
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RunState {
+    Running,
+    Paused,
+    StepOne,
+    StepFrame,
+}
+
+impl RunState {
+    pub fn toggle_pause(self) -> Self {
+        match self {
+            RunState::Running => RunState::Paused,
+            _ => RunState::Running,
+        }
+    }
+
+    // Frame advance pauses a running game and runs one frame of a paused one
+    pub fn frame_advance(self) -> Self {
+        match self {
+            RunState::Running => RunState::Paused,
+            _ => RunState::StepFrame,
+        }
+    }
+
+    // State after executing one instruction. Steps end paused
+    fn after_step(self, frame_ready: bool) -> Self {
+        match self {
+            RunState::StepOne => RunState::Paused,
+            RunState::StepFrame if frame_ready => RunState::Paused,
+            state => state,
+        }
+    }
+}
+
+// Host controls read by the UI, polled once per frame after vblank starts
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Input {
+    // Port, slot and held buttons of each keyboard player
+    pub buttons: Vec<(usize, usize, Buttons)>,
+    // Port, motion and held buttons of each mouse
+    pub mice: Vec<(usize, i32, i32, bool, bool)>,
+    // Port, slot and stick positions of each analog pad, as AnalogPad takes them
+    pub sticks: Vec<(usize, usize, [u8; 4])>,
+}
+
+// How fast the loop runs, and how far ahead of the game it shows
+#[derive(Clone, Copy, PartialEq)]
+pub struct Pacing {
+    pub fast_forward: bool,
+    // Percentage of full speed, 0 for uncapped
+    pub fast_forward_cap: u32,
+    pub audio_sync: bool,
+    // Slowed down while the window is in the background. Overrides fast forward
+    pub throttle: bool,
+    // Frames run ahead of the one emulated for real at full speed, up to MAX_RUN_AHEAD
+    pub run_ahead: u32,
+}
+
+impl Pacing {
+    // Fast forward and throttling leave the audio behind
+    fn full_speed(&self) -> bool {
+        !self.fast_forward && !self.throttle
+    }
+
+    // Time between frames for the frame limiter at a display refresh rate. None when fast
+    // forward is uncapped
+    fn frame_period(&self, refresh_rate: f64) -> Option<Duration> {
+        let speed = if self.throttle {
+            THROTTLED_SPEED
+        } else if self.fast_forward {
+            match self.fast_forward_cap {
+                0 => return None,
+                cap => cap as f64 / 100.0,
+            }
+        } else {
+            1.0
+        };
+        Some(Duration::from_secs_f64(1.0 / (refresh_rate * speed)))
+    }
+
+    // Stereo frames needed to top the audio output back up to the target depth, given the
+    // depth now. None when there's no audio device or the frame limiter paces emulation
+    // instead
+    fn audio_deficit(&self, buffered: Option<usize>) -> Option<usize> {
+        match buffered {
+            Some(depth) if self.audio_sync && self.full_speed() => {
+                Some(AUDIO_TARGET_FRAMES.saturating_sub(depth))
+            }
+            _ => None,
+        }
+    }
+}
+
+pub enum Command {
+    SetRunState(RunState),
+    // One shot breakpoint from "Run to here"
+    RunTo(u32),
+    Input(Input),
+    Pacing(Pacing),
+    Volume(f32),
+    // Named in the reply, such as "slot 3". Forced loads skip the BIOS and disc checks
+    LoadState {
+        state: Vec<u8>,
+        name: String,
+        force: bool,
+    },
+    // Starts recording or playing back a movie from its start state. Movies from power on are
+    // sent between PowerOff and PowerOn
+    StartMovie {
+        movie: Movie,
+        record: bool,
+    },
+    StopMovie,
+    // The UI is about to boot or replace the machine. Ends any movie
+    PowerOff,
+    // The machine is ready to run. Sound and frame timing from before start over
+    PowerOn,
+    Shutdown,
+}
+
+pub enum Event {
+    // The loop changed its own run state, or took one from a command
+    RunState(RunState),
+    BreakpointHit(u32),
+    WatchpointHit(WatchHit),
+    StateLoaded {
+        name: String,
+        result: Result<(), StateError>,
+    },
+    // Characters printed through the BIOS and logged BIOS calls, with the emulated time in
+    // seconds
+    Tty(Vec<(TtyOutput, u32)>, f64),
+    // The movie ended or was stopped, with the movie when it was being recorded
+    MovieStopped(Option<Movie>),
+    Stats(Stats),
+    // Small and large rumble motor speeds of the controller on each port, sent when they change
+    Rumble([(u8, u8); 2]),
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct Stats {
+    // Frames the GPU has finished since the loop started
+    pub frames: u64,
+    pub cycles: u64,
+    pub refresh_rate: f64,
+    // Stereo frames waiting in the audio output
+    pub audio_buffered: Option<usize>,
+    pub movie: Option<MovieProgress>,
+}
+
+#[derive(Clone, Copy)]
+pub struct MovieProgress {
+    pub recording: bool,
+    // Polls recorded or played so far, and in the whole movie
+    pub frame: usize,
+    pub length: usize,
+    pub rerecords: u32,
+}
+
+// Picture the worker finished, or the debug overlay in its place
+#[derive(Default)]
+pub struct Frame {
+    pub pixels: Vec<Color32>,
+    pub width: usize,
+    pub height: usize,
+}
+
+// Latest frame. The worker swaps its finished frame in, so pixels are never copied under the
+// lock
+struct FrontBuffer {
+    frame: Frame,
+    fresh: bool,
+}
+
+struct Shared {
+    cpu: Mutex<Cpu>,
+    front: Mutex<FrontBuffer>,
+    // UI locks waiting on the machine. The worker lets them in before starting another frame
+    waiting: AtomicUsize,
+    // Set whenever the UI locks the machine, which may have changed it while paused
+    touched: AtomicBool,
+}
+
+impl Shared {
+    // Used by the worker, which gives way to the UI
+    fn lock_cpu(&self) -> MutexGuard<'_, Cpu> {
+        while self.waiting.load(Ordering::SeqCst) > 0 {
+            thread::yield_now();
+        }
+        self.cpu.lock().unwrap()
+    }
+}
+
+// Runs the machine on its own thread so a slow UI frame doesn't stall the game. The UI steers it
+// with commands, and can lock the machine between frames for the menus and debug windows
+pub struct Emulator {
+    shared: Arc<Shared>,
+    commands: Sender<Command>,
+    events: Receiver<Event>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Emulator {
+    // Starts powered off. Repaints are requested from ctx as frames finish
+    pub fn new(
+        cpu: Cpu,
+        audio: Box<dyn AudioSink>,
+        ctx: egui::Context,
+        tty_output: bool,
+        tracing_start_pc: Option<u32>,
+    ) -> Self {
+        let shared = Arc::new(Shared {
+            cpu: Mutex::new(cpu),
+            front: Mutex::new(FrontBuffer {
+                frame: Frame::default(),
+                fresh: false,
+            }),
+            waiting: AtomicUsize::new(0),
+            touched: AtomicBool::new(false),
+        });
+        let (commands, command_rx) = mpsc::channel();
+        let (event_tx, events) = mpsc::channel();
+        let worker = Worker::new(
+            shared.clone(),
+            command_rx,
+            event_tx,
+            ctx,
+            audio,
+            tty_output,
+            tracing_start_pc,
+        );
+        let worker = thread::Builder::new()
+            .name("emulator".to_string())
+            .spawn(move || worker.run())
+            .expect("emulator thread starts");
+
+        Self {
+            shared,
+            commands,
+            events,
+            worker: Some(worker),
+        }
+    }
+
+    // Holds the worker off until the guard is dropped
+    pub fn cpu(&self) -> MutexGuard<'_, Cpu> {
+        self.shared.waiting.fetch_add(1, Ordering::SeqCst);
+        let cpu = self.shared.cpu.lock().unwrap();
+        self.shared.waiting.fetch_sub(1, Ordering::SeqCst);
+        self.shared.touched.store(true, Ordering::SeqCst);
+        cpu
+    }
+
+    pub fn send(&self, command: Command) {
+        // Only fails once the worker has stopped
+        let _ = self.commands.send(command);
+    }
+
+    pub fn events(&self) -> Vec<Event> {
+        self.events.try_iter().collect()
+    }
+
+    // Swaps the latest frame into out. Returns false when there is no new one
+    pub fn take_frame(&self, out: &mut Frame) -> bool {
+        let mut front = self.shared.front.lock().unwrap();
+        if !front.fresh {
+            return false;
+        }
+        mem::swap(&mut front.frame, out);
+        front.fresh = false;
+        true
+    }
+
+    // Waits for the worker to finish its frame and exit, which also stops the audio. The machine
+    // can still be locked after. Returns false if the worker was left running after STOP_TIMEOUT
+    pub fn stop(&mut self) -> bool {
+        let Some(worker) = self.worker.take() else {
+            return true;
+        };
+        self.send(Command::Shutdown);
+        let start = Instant::now();
+        while !worker.is_finished() {
+            if start.elapsed() >= STOP_TIMEOUT {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        let _ = worker.join();
+        true
+    }
+}
+
+impl Drop for Emulator {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+struct Worker {
+    shared: Arc<Shared>,
+    commands: Receiver<Command>,
+    events: Sender<Event>,
+    ctx: egui::Context,
+    audio: Box<dyn AudioSink>,
+    powered: bool,
+    run_state: RunState,
+    run_to: Option<u32>,
+    pacing: Pacing,
+    // Latest controls from the UI. Polled as late as possible, so input given while paused is
+    // what a frame advance runs with
+    input: Input,
+    // Machine as it was before the frames run ahead, kept to save allocating it every frame
+    snapshot: Vec<u8>,
+    // Input is polled before the first instruction after vblank starts, always at the same
+    // point in emulated time so movies play back the same
+    input_due: bool,
+    movie: Option<Movie>,
+    recording: bool,
+    // Next frame of the movie played back
+    movie_frame: usize,
+    // When the frame limiter runs the next frame
+    next_frame: Option<Instant>,
+    // Cycles the last pass ran past its budget, taken from the next one
+    cycle_overshoot: u64,
+    frames: u64,
+    tty_output: bool,
+    tracing_start_pc: Option<u32>,
+    logging_enabled: bool,
+    // Frame being drawn, swapped with the front buffer once finished
+    back: Frame,
+    // When the picture was last drawn. None draws it on the next pass
+    redrawn: Option<Instant>,
+    // Hash of the picture last handed to the UI while paused, so redraws that change nothing
+    // don't wake it
+    paused_picture: Option<u64>,
+    // Motor speeds last sent to the UI
+    rumble: [(u8, u8); 2],
+}
+
+// Motor speeds by port
+impl Rumble for [(u8, u8); 2] {
+    fn set_motors(&mut self, port: usize, small: u8, large: u8) {
+        self[port] = (small, large);
+    }
+}
+
+impl Worker {
+    fn new(
+        shared: Arc<Shared>,
+        commands: Receiver<Command>,
+        events: Sender<Event>,
+        ctx: egui::Context,
+        audio: Box<dyn AudioSink>,
+        tty_output: bool,
+        tracing_start_pc: Option<u32>,
+    ) -> Self {
+        Self {
+            shared,
+            commands,
+            events,
+            ctx,
+            audio,
+            powered: false,
+            run_state: RunState::Running,
+            run_to: None,
+            pacing: Pacing {
+                fast_forward: false,
+                fast_forward_cap: 0,
+                audio_sync: false,
+                throttle: false,
+                run_ahead: 0,
+            },
+            input: Input::default(),
+            snapshot: Vec::new(),
+            input_due: true,
+            movie: None,
+            recording: false,
+            movie_frame: 0,
+            next_frame: None,
+            cycle_overshoot: 0,
+            frames: 0,
+            tty_output,
+            tracing_start_pc,
+            logging_enabled: false,
+            back: Frame::default(),
+            redrawn: None,
+            paused_picture: None,
+            rumble: [(0, 0); 2],
+        }
+    }
+
+    fn run(mut self) {
+        // How long to wait for commands before running again. None waits until one arrives
+        let mut wait = Some(Duration::ZERO);
+        loop {
+            let first = match wait {
+                Some(wait) => self.commands.recv_timeout(wait),
+                None => self
+                    .commands
+                    .recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            let first = match first {
+                Ok(command) => Some(command),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => return,
+            };
+            let commands: Vec<Command> =
+                first.into_iter().chain(self.commands.try_iter()).collect();
+            let run_state = self.run_state;
+            for command in commands {
+                if !self.command(command) {
+                    return;
+                }
+            }
+            if self.run_state != run_state {
+                self.send(Event::RunState(self.run_state));
+            }
+
+            wait = if self.powered {
+                Some(self.pass())
+            } else {
+                None
+            };
+        }
+    }
+
+    // Returns false once the worker should exit
+    fn command(&mut self, command: Command) -> bool {
+        match command {
+            Command::SetRunState(run_state) => self.run_state = run_state,
+            Command::RunTo(addr) => {
+                self.run_to = Some(addr);
+                self.run_state = RunState::Running;
+            }
+            Command::Input(input) => {
+                self.input.buttons = input.buttons;
+                self.input.sticks = input.sticks;
+                // Mouse motion adds up until a frame takes it
+                for (port, dx, dy, left, right) in input.mice {
+                    match self.input.mice.iter_mut().find(|mouse| mouse.0 == port) {
+                        Some(mouse) => *mouse = (port, mouse.1 + dx, mouse.2 + dy, left, right),
+                        None => self.input.mice.push((port, dx, dy, left, right)),
+                    }
+                }
+            }
+            Command::Pacing(pacing) => self.pacing = pacing,
+            Command::Volume(volume) => self.audio.set_volume(volume),
+            Command::LoadState { state, name, force } => {
+                let result = self.shared.lock_cpu().load_state(&state, force);
+                // Sound and timing from before the load would otherwise carry on
+                if result.is_ok() {
+                    self.restart();
+                    if self.recording
+                        && let Some(movie) = &mut self.movie
+                    {
+                        movie.frames.push(MovieFrame::LoadState(state));
+                        movie.header.rerecords += 1;
+                    } else {
+                        // Playback can't follow the movie from another state
+                        self.stop_movie();
+                    }
+                }
+                self.send(Event::StateLoaded { name, result });
+            }
+            Command::StartMovie { movie, record } => {
+                self.stop_movie();
+                if let Some(state) = &movie.header.start_state {
+                    // The movie was checked against the machine before it was sent
+                    let result = self.shared.lock_cpu().load_state(state, true);
+                    if result.is_err() {
+                        let name = "movie".to_string();
+                        self.send(Event::StateLoaded { name, result });
+                        return true;
+                    }
+                    self.restart();
+                }
+                self.movie = Some(movie);
+                self.recording = record;
+                self.movie_frame = 0;
+            }
+            Command::StopMovie => self.stop_movie(),
+            Command::PowerOff => {
+                self.stop_movie();
+                self.powered = false;
+                self.audio.clear();
+                self.audio.set_paused(true);
+                if self.rumble != [(0, 0); 2] {
+                    self.rumble = [(0, 0); 2];
+                    self.send(Event::Rumble(self.rumble));
+                }
+            }
+            Command::PowerOn => {
+                self.powered = true;
+                self.restart();
+            }
+            Command::Shutdown => return false,
+        }
+        true
+    }
+
+    fn restart(&mut self) {
+        self.audio.clear();
+        self.next_frame = None;
+        self.cycle_overshoot = 0;
+        self.run_to = None;
+        self.redrawn = None;
+        self.input_due = true;
+    }
+
+    fn stop_movie(&mut self) {
+        if let Some(movie) = self.movie.take() {
+            let recorded = self.recording.then_some(movie);
+            self.send(Event::MovieStopped(recorded));
+        }
+        self.recording = false;
+    }
+
+    fn send(&self, event: Event) {
+        // Only fails once the UI has gone
+        let _ = self.events.send(event);
+    }
+
+    // Runs whatever is due, then hands the results to the UI. Returns how long until the next
+    // pass is due
+    fn pass(&mut self) -> Duration {
+        let full_speed = self.pacing.full_speed();
+        let audio_deficit = self.pacing.audio_deficit(self.audio.buffered());
+        let frames = self.frames;
+        let run_state = self.run_state;
+
+        let shared = self.shared.clone();
+        let mut cpu = shared.lock_cpu();
+        // Without audio sync the frame limiter paces emulation. Steps run at once
+        let mut wait = match audio_deficit {
+            _ if self.run_state == RunState::Paused => PAUSED_REDRAW,
+            _ if self.run_state != RunState::Running => {
+                self.run_frame(&mut cpu, None);
+                Duration::ZERO
+            }
+            Some(deficit) => {
+                self.next_frame = None;
+                if deficit > 0 {
+                    self.run_frame(&mut cpu, Some(deficit));
+                }
+                AUDIO_POLL
+            }
+            None => self.run_limited(&mut cpu),
+        };
+
+        // A paused machine is redrawn after the UI has had it, in case the debug windows
+        // changed it
+        let paused_redraw = self.run_state == RunState::Paused
+            && self
+                .redrawn
+                .is_none_or(|redrawn| redrawn.elapsed() >= PAUSED_REDRAW)
+            && self.shared.touched.swap(false, Ordering::SeqCst);
+        let tty_output = cpu.bus.take_tty_output();
+        if !tty_output.is_empty() {
+            let time = cpu.cycles_executed() as f64 / CPU_CLOCK as f64;
+            self.send(Event::Tty(tty_output, time));
+        }
+
+        let mut redraw = self.frames != frames || self.run_state != run_state || paused_redraw;
+        if redraw {
+            self.redrawn = Some(Instant::now());
+            // Restoring ends any transfer the controllers and memory cards are part way
+            // through, so run-ahead waits until no port is selected
+            let run_ahead = self.frames != frames
+                && self.run_state == RunState::Running
+                && full_speed
+                && !cpu.bus.sio0.selected();
+            if run_ahead && self.pacing.run_ahead > 0 {
+                self.run_ahead(&mut cpu);
+            } else {
+                self.draw(&mut cpu);
+            }
+
+            // Only pictures that differ from the one on screen are handed over while paused
+            if self.run_state == RunState::Paused {
+                let mut hasher = DefaultHasher::new();
+                (self.back.width, self.back.height, &self.back.pixels).hash(&mut hasher);
+                let picture = Some(hasher.finish());
+                redraw = picture != self.paused_picture || self.run_state != run_state;
+                self.paused_picture = picture;
+            } else {
+                self.paused_picture = None;
+            }
+        }
+
+        // Paused output is silent, and sound away from full speed is dropped
+        if full_speed {
+            self.audio.push_samples(&cpu.bus.spu.output);
+        }
+        cpu.bus.spu.output.clear();
+        self.audio.set_paused(self.run_state != RunState::Running);
+
+        // The motors stop while the game isn't running
+        let mut rumble = [(0, 0); 2];
+        if self.run_state == RunState::Running {
+            cpu.bus.sio0.forward_rumble(&mut rumble);
+        }
+
+        let stats = Stats {
+            frames: self.frames,
+            cycles: cpu.cycles_executed(),
+            refresh_rate: cpu.bus.gpu.refresh_rate(),
+            audio_buffered: self.audio.buffered(),
+            movie: self.movie.as_ref().map(|movie| MovieProgress {
+                recording: self.recording,
+                frame: self.movie_frame,
+                length: movie.frames.len(),
+                rerecords: movie.header.rerecords,
+            }),
+        };
+        drop(cpu);
+
+        // Paused passes only come round to look for changes from the UI
+        if self.run_state == RunState::Paused {
+            wait = PAUSED_REDRAW;
+        }
+
+        if redraw {
+            let mut front = self.shared.front.lock().unwrap();
+            mem::swap(&mut front.frame, &mut self.back);
+            front.fresh = true;
+        }
+        if self.run_state != run_state {
+            self.send(Event::RunState(self.run_state));
+        }
+        self.send(Event::Stats(stats));
+        if rumble != self.rumble {
+            self.rumble = rumble;
+            self.send(Event::Rumble(rumble));
+        }
+        if redraw {
+            self.ctx.request_repaint();
+        }
+        wait
+    }
+
+    fn draw(&mut self, cpu: &mut Cpu) {
+        let gpu = &mut cpu.bus.gpu;
+        let (width, height) = if gpu.gp0.overlay.view != DebugView::Off {
+            gpu.render_debug_overlay(&mut self.back.pixels)
+        } else {
+            gpu.render_display(&mut self.back.pixels)
+        };
+        (self.back.width, self.back.height) = (width, height);
+    }
+
+    // Runs the next frames with the latest input and draws the last of them, then puts the
+    // machine back as it was. The game answers input on screen a frame or two sooner, while
+    // the frames run for real, their sound and any movie are the same as without run-ahead
+    fn run_ahead(&mut self, cpu: &mut Cpu) {
+        cpu.snapshot(&mut self.snapshot);
+        // The controllers and memory cards belong to the host, so snapshots leave them out.
+        // Copied too, so cards written and mouse motion read ahead are taken back
+        let ports = cpu.bus.sio0.ports.clone();
+        // Kept out of the way, the frames run ahead are silent and their picture is drawn
+        let output = mem::take(&mut cpu.bus.spu.output);
+        let hires = cpu.bus.gpu.gp0.hires.clone();
+        // A movie played back decides the input itself
+        if self.movie.is_none() || self.recording {
+            for &(port, slot, buttons) in &self.input.buttons {
+                cpu.bus.sio0.set_buttons(port, slot, buttons);
+            }
+            for &(port, slot, axes) in &self.input.sticks {
+                cpu.bus.sio0.set_axes(port, slot, axes);
+            }
+        }
+        for _ in 0..self.pacing.run_ahead.min(MAX_RUN_AHEAD) {
+            let start = cpu.bus.cycles;
+            // Bounded in case the display never finishes a frame
+            while !cpu.bus.gpu.take_frame_ready() && cpu.bus.cycles - start < 2 * FRAME_CYCLES {
+                cpu.step_instruction(self.tty_output);
+            }
+        }
+        self.draw(cpu);
+        // The frames run for real reach the same accesses again
+        cpu.take_watch_hit();
+
+        // Left out so restoring doesn't build one from native VRAM only to be replaced
+        cpu.bus.gpu.gp0.hires = None;
+        cpu.restore(&self.snapshot).expect("snapshot restores");
+        cpu.bus.gpu.gp0.hires = hires;
+        cpu.bus.spu.output = output;
+        cpu.bus.sio0.ports = ports;
+    }
+
+    // Runs until the next frame, or for as long as audio sync needs. Steps stop early
+    fn run_frame(&mut self, cpu: &mut Cpu, audio_deficit: Option<usize>) {
+        // Audio sync needs up to the three frames it keeps queued, otherwise one frame is
+        // run at a time
+        let start_cycles = cpu.bus.cycles;
+        let budget = match audio_deficit {
+            Some(_) => 3 * FRAME_CYCLES,
+            None => FRAME_CYCLES.saturating_sub(self.cycle_overshoot),
+        };
+        while self.run_state != RunState::Paused {
+            if let Some(tracing_pc) = self.tracing_start_pc
+                && !self.logging_enabled
+                && tracing_pc == cpu.registers.program_counter
+            {
+                println!("Begin logging...");
+                self.logging_enabled = true;
+                tracing_setup::init_tracing();
+            }
+
+            if self.input_due {
+                self.input_due = false;
+                self.latch_input(cpu);
+            }
+            cpu.step_instruction(self.tty_output);
+            let frame_ready = cpu.bus.gpu.take_frame_ready();
+            if frame_ready {
+                self.frames += 1;
+                self.input_due = true;
+            }
+            self.run_state = self.run_state.after_step(frame_ready);
+            // Checked after each step so the instruction at a breakpoint runs once emulation
+            // resumes
+            let pc = cpu.registers.program_counter;
+            if self.run_to == Some(pc) {
+                self.run_to = None;
+                self.run_state = RunState::Paused;
+            } else if cpu.at_breakpoint() {
+                self.run_state = RunState::Paused;
+                self.send(Event::BreakpointHit(pc));
+            }
+            // The access has already been made, so the instruction after it is next to run
+            if let Some(hit) = cpu.take_watch_hit() {
+                self.run_state = RunState::Paused;
+                self.send(Event::WatchpointHit(hit));
+            }
+            match audio_deficit {
+                // A frame step runs past the budget to reach the frame
+                _ if self.run_state != RunState::Running => {}
+                Some(deficit) if cpu.bus.spu.output.len() / 2 >= deficit => break,
+                None if frame_ready => break,
+                // A movie may have loaded a state with an earlier cycle count
+                _ if cpu.bus.cycles.saturating_sub(start_cycles) >= budget => break,
+                _ => {}
+            }
+        }
+        self.cycle_overshoot = cpu
+            .bus
+            .cycles
+            .saturating_sub(start_cycles)
+            .saturating_sub(budget);
+    }
+
+    // Hands the controllers the movie's input when one is played back, otherwise the UI's
+    fn latch_input(&mut self, cpu: &mut Cpu) {
+        let live = Input {
+            buttons: self.input.buttons.clone(),
+            mice: mem::take(&mut self.input.mice),
+            sticks: self.input.sticks.clone(),
+        };
+        let input = match self.playback_input(cpu) {
+            Some(input) => input,
+            None => {
+                if self.recording
+                    && let Some(movie) = &mut self.movie
+                {
+                    movie.frames.push(MovieFrame::Input(live.clone()));
+                }
+                live
+            }
+        };
+        for (port, slot, buttons) in input.buttons {
+            cpu.bus.sio0.set_buttons(port, slot, buttons);
+        }
+        for (port, dx, dy, left, right) in input.mice {
+            cpu.bus.sio0.move_mouse(port, dx, dy, left, right);
+        }
+        for (port, slot, axes) in input.sticks {
+            cpu.bus.sio0.set_axes(port, slot, axes);
+        }
+    }
+
+    // Next input of the movie played back, loading the states recorded before it. Playback
+    // stops once the movie runs out
+    fn playback_input(&mut self, cpu: &mut Cpu) -> Option<Input> {
+        if self.recording {
+            return None;
+        }
+        let movie = self.movie.as_ref()?;
+        while let Some(frame) = movie.frames.get(self.movie_frame) {
+            self.movie_frame += 1;
+            match frame {
+                MovieFrame::Input(input) => return Some(input.clone()),
+                MovieFrame::LoadState(state) => {
+                    if cpu.load_state(state, true).is_err() {
+                        break;
+                    }
+                    self.audio.clear();
+                }
+            }
+        }
+        self.stop_movie();
+        None
+    }
+
+    // Runs the frames due by now at the display's refresh rate, or at the fast forward cap.
+    // Returns how long until the next frame is due
+    fn run_limited(&mut self, cpu: &mut Cpu) -> Duration {
+        let Some(period) = self.pacing.frame_period(cpu.bus.gpu.refresh_rate()) else {
+            let start = Instant::now();
+            while start.elapsed() < FAST_FORWARD_SLICE && self.run_state == RunState::Running {
+                self.run_frame(cpu, None);
+            }
+            self.next_frame = None;
+            return Duration::ZERO;
+        };
+
+        let now = Instant::now();
+        let (due, next) = frame_schedule(self.next_frame, now, period);
+        for _ in 0..due {
+            self.run_frame(cpu, None);
+        }
+        self.next_frame = Some(next);
+        next - now
+    }
+}
+
+// Frames due by now given the deadline of the next one, and the deadline after them. Each
+// deadline follows the last, so time lost waking up late is made up next frame. After a stall
+// the schedule starts over rather than rushing to catch up
+fn frame_schedule(next: Option<Instant>, now: Instant, period: Duration) -> (u32, Instant) {
+    let mut next = next.unwrap_or(now);
+    if now.saturating_duration_since(next) > period * MAX_LATE_FRAMES {
+        next = now;
+    }
+    let mut due = 0;
+    while next <= now {
+        due += 1;
+        next += period;
+    }
+    (due, next)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::NullSink;
+    use crate::memcard::MemoryCard;
+    use crate::movie::MovieHeader;
+
+    const ZERO: u32 = 0;
+    const A0: u32 = 4;
+    const T0: u32 = 8;
+    const T1: u32 = 9;
+    const T2: u32 = 10;
+    const T3: u32 = 11;
+    const T4: u32 = 12;
+    const S0: u32 = 16;
+    const S1: u32 = 17;
+    const S2: u32 = 18;
+    const S3: u32 = 19;
+    const RA: u32 = 31;
+
+    // Just enough of an assembler for the test program, which runs from the BIOS ROM
+    #[derive(Default)]
+    struct Asm {
+        words: Vec<u32>,
+        // Start of the routine exchanging a byte through SIO0
+        exchange: usize,
+    }
+
+    impl Asm {
+        fn here(&self) -> usize {
+            self.words.len()
+        }
+
+        fn imm(&mut self, op: u32, rs: u32, rt: u32, imm: u16) {
+            self.words
+                .push((op << 26) | (rs << 21) | (rt << 16) | imm as u32);
+        }
+
+        fn reg(&mut self, rs: u32, rt: u32, rd: u32, shamt: u32, funct: u32) {
+            self.words
+                .push((rs << 21) | (rt << 16) | (rd << 11) | (shamt << 6) | funct);
+        }
+
+        fn branch(&mut self, op: u32, rs: u32, rt: u32, target: usize) {
+            let offset = target as i32 - self.here() as i32 - 1;
+            self.imm(op, rs, rt, offset as u16);
+            self.nop();
+        }
+
+        fn jump(&mut self, op: u32, target: usize) {
+            self.words.push(Self::jump_word(op, target));
+            self.nop();
+        }
+
+        fn jump_word(op: u32, target: usize) -> u32 {
+            let addr = 0xBFC00000 + 4 * target as u32;
+            (op << 26) | ((addr >> 2) & 0x3FFFFFF)
+        }
+
+        fn nop(&mut self) {
+            self.words.push(0);
+        }
+
+        fn send(&mut self, byte: u16) {
+            self.imm(0x0D, ZERO, A0, byte);
+            self.jump(0x03, self.exchange);
+        }
+
+        // Sends the byte in a register the same number of times
+        fn send_repeated(&mut self, reg: u32, count: u16) {
+            self.imm(0x0D, ZERO, S2, count);
+            let top = self.here();
+            self.reg(reg, ZERO, A0, 0, 0x21);
+            self.jump(0x03, self.exchange);
+            self.imm(0x09, S2, S2, 0xFFFF);
+            self.branch(0x05, S2, ZERO, top);
+        }
+
+        fn select(&mut self) {
+            self.imm(0x0D, ZERO, T1, 0x0003);
+            self.imm(0x29, T0, T1, 0xA);
+        }
+
+        fn deselect(&mut self) {
+            self.imm(0x29, T0, ZERO, 0xA);
+        }
+    }
+
+    // Each frame reads the pad, reads sector 1 of the memory card, then writes the sector
+    // back filled with a hash of every byte received, which it also keeps in RAM
+    fn program() -> Vec<u32> {
+        let mut asm = Asm::default();
+        // Jumps over the routine, filled in once its end is known
+        asm.jump(0x02, 0);
+
+        // Exchanges a0, adding the byte received to the hash in s1
+        asm.exchange = asm.here();
+        asm.imm(0x28, T0, A0, 0);
+        let poll = asm.here();
+        asm.imm(0x24, T0, T2, 4);
+        asm.nop();
+        asm.imm(0x0C, T2, T2, 2);
+        asm.branch(0x04, T2, ZERO, poll);
+        asm.imm(0x24, T0, T3, 0);
+        asm.nop();
+        asm.reg(ZERO, S1, T4, 5, 0x00);
+        asm.reg(T4, S1, S1, 0, 0x23);
+        asm.reg(S1, T3, S1, 0, 0x21);
+        asm.reg(RA, ZERO, ZERO, 0, 0x08);
+        asm.nop();
+
+        asm.words[0] = Asm::jump_word(0x02, asm.here());
+        asm.imm(0x0F, ZERO, T0, 0x1F80);
+        asm.imm(0x0D, T0, T0, 0x1040);
+        asm.imm(0x0F, ZERO, S0, 0xA001);
+        asm.imm(0x0D, ZERO, T1, 0x88);
+        asm.imm(0x29, T0, T1, 0xE);
+        asm.imm(0x0D, ZERO, T1, 0x0D);
+        asm.imm(0x29, T0, T1, 0x8);
+
+        // Waits for vblank in I_STAT, then acknowledges it
+        let frame = asm.here();
+        asm.imm(0x24, T0, T2, 0x30);
+        asm.nop();
+        asm.imm(0x0C, T2, T2, 1);
+        asm.branch(0x04, T2, ZERO, frame);
+        asm.imm(0x0D, ZERO, T2, 0xFE);
+        asm.imm(0x28, T0, T2, 0x30);
+
+        asm.select();
+        for byte in [0x01, 0x42, 0x00, 0x00, 0x00] {
+            asm.send(byte);
+        }
+        asm.deselect();
+
+        asm.select();
+        for byte in [0x81, 0x52, 0x00, 0x00, 0x00, 0x01] {
+            asm.send(byte);
+        }
+        asm.send_repeated(ZERO, 134);
+        asm.deselect();
+
+        asm.imm(0x0C, S1, S3, 0xFF);
+        asm.select();
+        for byte in [0x81, 0x57, 0x00, 0x00, 0x00, 0x01] {
+            asm.send(byte);
+        }
+        asm.send_repeated(S3, 128);
+        // The data XORs to zero, leaving the sector number as the checksum
+        for byte in [0x01, 0x00, 0x00, 0x00] {
+            asm.send(byte);
+        }
+        asm.deselect();
+
+        asm.imm(0x2B, S0, S1, 0);
+        asm.jump(0x02, frame);
+        asm.words
+    }
+
+    // Worker for the machine, without a UI or audio
+    fn worker(cpu: Cpu) -> (Arc<Shared>, Worker) {
+        let shared = Arc::new(Shared {
+            cpu: Mutex::new(cpu),
+            front: Mutex::new(FrontBuffer {
+                frame: Frame::default(),
+                fresh: false,
+            }),
+            waiting: AtomicUsize::new(0),
+            touched: AtomicBool::new(false),
+        });
+        let (_commands, command_rx) = mpsc::channel();
+        let (event_tx, _events) = mpsc::channel();
+        let worker = Worker::new(
+            shared.clone(),
+            command_rx,
+            event_tx,
+            egui::Context::default(),
+            Box::new(NullSink),
+            false,
+            None,
+        );
+        (shared, worker)
+    }
+
+    // Machine running the program, with a blank memory card kept at the path
+    fn program_machine(card_path: &std::path::Path) -> Cpu {
+        let _ = std::fs::remove_file(card_path);
+        let mut cpu = Cpu::new();
+        for (i, word) in program().into_iter().enumerate() {
+            cpu.bus.kernel_rom[4 * i..4 * i + 4].copy_from_slice(&word.to_le_bytes());
+        }
+        let card = MemoryCard::open(card_path).unwrap();
+        cpu.bus
+            .sio0
+            .insert_memcard(0, Some(Box::new(card)))
+            .unwrap();
+        cpu
+    }
+
+    fn ram_hash(cpu: &Cpu) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        cpu.bus.kernel.hash(&mut hasher);
+        cpu.bus.ram.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    // Hash of RAM after running the program with input that changes part way through
+    fn run(run_ahead: u32) -> u64 {
+        let card_path = std::env::temp_dir().join(format!(
+            "ps1_emulator_run_ahead_{}_{run_ahead}.mcd",
+            std::process::id()
+        ));
+        let (shared, mut worker) = worker(program_machine(&card_path));
+        worker.pacing.run_ahead = run_ahead;
+
+        let mut cpu = shared.cpu.lock().unwrap();
+        for frame in 0..12 {
+            let mut buttons = Buttons::default();
+            buttons.set(Buttons::CROSS, (4..8).contains(&frame));
+            worker.input.buttons = vec![(0, 0, buttons)];
+            worker.run_frame(&mut cpu, None);
+            if run_ahead > 0 {
+                worker.run_ahead(&mut cpu);
+            }
+        }
+        assert_ne!(cpu.bus.ram[..4], [0; 4]);
+        let _ = std::fs::remove_file(&card_path);
+        ram_hash(&cpu)
+    }
+
+    #[test]
+    fn run_ahead_leaves_the_game_as_it_was() {
+        // The machine is too big for the default test stack
+        let hashes = thread::Builder::new()
+            .stack_size(64 << 20)
+            .spawn(|| [run(0), run(MAX_RUN_AHEAD)])
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(hashes[0], hashes[1]);
+    }
+
+    #[test]
+    fn audio_sync_tops_the_output_up_to_three_frames() {
+        let pacing = Pacing {
+            fast_forward: false,
+            fast_forward_cap: 0,
+            audio_sync: true,
+            throttle: false,
+            run_ahead: 0,
+        };
+        assert_eq!(pacing.audio_deficit(Some(0)), Some(AUDIO_TARGET_FRAMES));
+        assert_eq!(
+            pacing.audio_deficit(Some(1000)),
+            Some(AUDIO_TARGET_FRAMES - 1000)
+        );
+        assert_eq!(
+            pacing.audio_deficit(Some(AUDIO_TARGET_FRAMES + 500)),
+            Some(0)
+        );
+        // Without an audio device, with sync off or away from full speed the frame limiter
+        // takes over
+        assert_eq!(pacing.audio_deficit(None), None);
+        for other in [
+            Pacing {
+                audio_sync: false,
+                ..pacing
+            },
+            Pacing {
+                fast_forward: true,
+                ..pacing
+            },
+            Pacing {
+                throttle: true,
+                ..pacing
+            },
+        ] {
+            assert_eq!(other.audio_deficit(Some(0)), None);
+        }
+    }
+
+    #[test]
+    fn audio_sync_runs_until_the_deficit_is_made_up() {
+        let samples = thread::Builder::new()
+            .stack_size(64 << 20)
+            .spawn(|| {
+                // The BIOS jumps to itself forever
+                let mut cpu = Cpu::new();
+                cpu.bus.kernel_rom[..4].copy_from_slice(&0x0BF0_0000u32.to_le_bytes());
+                let (shared, mut worker) = worker(cpu);
+                let mut cpu = shared.cpu.lock().unwrap();
+                let mut samples = Vec::new();
+                for deficit in [100, 735, 2000] {
+                    cpu.bus.spu.output.clear();
+                    worker.run_frame(&mut cpu, Some(deficit));
+                    samples.push(cpu.bus.spu.output.len() / 2);
+                }
+                samples
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(samples, [100, 735, 2000]);
+    }
+
+    #[test]
+    fn run_states_step_and_pause() {
+        use RunState::*;
+        assert_eq!(Running.toggle_pause(), Paused);
+        for state in [Paused, StepOne, StepFrame] {
+            assert_eq!(state.toggle_pause(), Running);
+            assert_eq!(state.frame_advance(), StepFrame);
+        }
+        assert_eq!(Running.frame_advance(), Paused);
+
+        // A step of one instruction always ends paused, a frame step only once a frame is ready
+        for frame_ready in [false, true] {
+            assert_eq!(StepOne.after_step(frame_ready), Paused);
+            assert_eq!(Running.after_step(frame_ready), Running);
+            assert_eq!(Paused.after_step(frame_ready), Paused);
+        }
+        assert_eq!(StepFrame.after_step(false), StepFrame);
+        assert_eq!(StepFrame.after_step(true), Paused);
+    }
+
+    #[test]
+    fn steps_run_one_instruction_or_one_frame() {
+        thread::Builder::new()
+            .stack_size(64 << 20)
+            .spawn(|| {
+                // The BIOS counts up in t0 forever
+                let mut cpu = Cpu::new();
+                for (i, word) in [0x25080001u32, 0x0BF00000, 0].into_iter().enumerate() {
+                    cpu.bus.kernel_rom[4 * i..4 * i + 4].copy_from_slice(&word.to_le_bytes());
+                }
+                let (shared, mut worker) = worker(cpu);
+                let mut cpu = shared.cpu.lock().unwrap();
+
+                worker.run_state = RunState::StepOne;
+                worker.run_frame(&mut cpu, None);
+                assert_eq!(worker.run_state, RunState::Paused);
+                assert_eq!(cpu.registers.program_counter, 0xBFC00004);
+                assert_eq!(cpu.registers.registers[T0 as usize], 1);
+
+                // Paused, nothing runs
+                worker.run_frame(&mut cpu, None);
+                assert_eq!(cpu.registers.program_counter, 0xBFC00004);
+
+                let frames = worker.frames;
+                worker.run_state = RunState::StepFrame;
+                worker.run_frame(&mut cpu, None);
+                assert_eq!(worker.run_state, RunState::Paused);
+                assert_eq!(worker.frames, frames + 1);
+                assert!(cpu.registers.registers[T0 as usize] > 1000);
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    // Runs until the GPU finishes the next frame
+    fn next_frame(worker: &mut Worker, cpu: &mut Cpu) {
+        let frames = worker.frames;
+        while worker.frames == frames {
+            worker.run_frame(cpu, None);
+        }
+    }
+
+    // Buttons pressed on each frame of the recording
+    fn recorded_buttons(frame: u16) -> Buttons {
+        let mut buttons = Buttons::default();
+        buttons.set(Buttons::CROSS, frame.is_multiple_of(3));
+        buttons.set(Buttons::START, (5..9).contains(&frame));
+        buttons.set(Buttons::LEFT, frame % 4 == 1);
+        buttons
+    }
+
+    // RAM hashes of each frame while recording 16 frames, with a state saved at frame 4 loaded
+    // again after frame 10, and the movie recorded
+    fn record(card_path: &std::path::Path) -> (Vec<u64>, Movie) {
+        let mut cpu = program_machine(card_path);
+        let header = MovieHeader::new(&mut cpu, None);
+        let (shared, mut worker) = worker(cpu);
+        worker.movie = Some(Movie::new(header));
+        worker.recording = true;
+
+        let mut hashes = Vec::new();
+        let mut state = Vec::new();
+        for frame in 0..16 {
+            worker.input.buttons = vec![(0, 0, recorded_buttons(frame))];
+            let mut cpu = shared.lock_cpu();
+            next_frame(&mut worker, &mut cpu);
+            hashes.push(ram_hash(&cpu));
+            if frame == 4 {
+                cpu.save_state(&mut state, None);
+            }
+            drop(cpu);
+            if frame == 10 {
+                let name = "slot 1".to_string();
+                let state = state.clone();
+                worker.command(Command::LoadState {
+                    state,
+                    name,
+                    force: false,
+                });
+            }
+        }
+        (hashes, worker.movie.take().unwrap())
+    }
+
+    // RAM hashes of each frame from power on, with live input different from the recording
+    fn play(card_path: &std::path::Path, movie: Option<Movie>, frames: usize) -> Vec<u64> {
+        let (shared, mut worker) = worker(program_machine(card_path));
+        worker.movie = movie;
+        let mut live = Buttons::default();
+        live.set(Buttons::SQUARE, true);
+        live.set(Buttons::CROSS, true);
+        worker.input.buttons = vec![(0, 0, live)];
+
+        let mut cpu = shared.lock_cpu();
+        (0..frames)
+            .map(|_| {
+                next_frame(&mut worker, &mut cpu);
+                ram_hash(&cpu)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn movies_play_back_the_same_ram_each_frame() {
+        thread::Builder::new()
+            .stack_size(64 << 20)
+            .spawn(|| {
+                let card_path = std::env::temp_dir()
+                    .join(format!("ps1_emulator_movie_{}.mcd", std::process::id()));
+                let (recorded, movie) = record(&card_path);
+                assert_eq!(movie.frames.len(), 17);
+                assert_eq!(movie.header.rerecords, 1);
+                assert!(matches!(movie.frames[11], MovieFrame::LoadState(_)));
+                // The frames after the load repeat the game from frame 4 with new input
+                assert_ne!(recorded[11], recorded[5]);
+
+                let movie = Movie::decode(&movie.encode()).unwrap();
+                let played = play(&card_path, Some(movie), recorded.len());
+                assert_eq!(played, recorded);
+
+                // The live input would have played a different game
+                let live = play(&card_path, None, recorded.len());
+                assert_ne!(live, recorded);
+                let _ = std::fs::remove_file(&card_path);
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    // Audio output and memory card that log what the shutdown does to them
+    struct LoggedSink(Arc<Mutex<Vec<&'static str>>>);
+
+    impl AudioSink for LoggedSink {
+        fn push_samples(&mut self, _samples: &[i16]) {}
+
+        fn set_volume(&mut self, _volume: f32) {}
+
+        fn set_paused(&mut self, _paused: bool) {}
+
+        fn buffered(&self) -> Option<usize> {
+            None
+        }
+
+        fn clear(&mut self) {}
+    }
+
+    impl Drop for LoggedSink {
+        fn drop(&mut self) {
+            self.0.lock().unwrap().push("audio stopped");
+        }
+    }
+
+    #[derive(Clone)]
+    struct LoggedCard(Arc<Mutex<Vec<&'static str>>>);
+
+    impl crate::sio::SioDevice for LoggedCard {
+        fn exchange(&mut self, _byte: u8) -> (u8, bool) {
+            (0xFF, false)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.0.lock().unwrap().push("card saved");
+            Ok(())
+        }
+
+        fn unsaved(&self) -> bool {
+            true
+        }
+
+        fn clone_box(&self) -> Box<dyn crate::sio::SioDevice> {
+            Box::new(self.clone())
+        }
+    }
+
+    // Running emulator with a BIOS that jumps to itself forever, and the log of its sinks
+    fn running_emulator() -> (Emulator, Arc<Mutex<Vec<&'static str>>>) {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut cpu = Cpu::new();
+        cpu.bus.kernel_rom[..4].copy_from_slice(&0x0BF0_0000u32.to_le_bytes());
+        cpu.bus
+            .sio0
+            .insert_memcard(0, Some(Box::new(LoggedCard(log.clone()))))
+            .unwrap();
+        let audio = Box::new(LoggedSink(log.clone()));
+        let emulator = Emulator::new(cpu, audio, egui::Context::default(), false, None);
+        emulator.send(Command::PowerOn);
+        while emulator.cpu().cycles_executed() == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        (emulator, log)
+    }
+
+    #[test]
+    fn cards_are_saved_once_the_worker_has_stopped() {
+        thread::Builder::new()
+            .stack_size(64 << 20)
+            .spawn(|| {
+                let (mut emulator, log) = running_emulator();
+                assert!(emulator.stop());
+                assert_eq!(*log.lock().unwrap(), ["audio stopped"]);
+
+                // Nothing runs after the stop, so nothing reaches the cards after they are saved
+                let cycles = emulator.cpu().cycles_executed();
+                thread::sleep(Duration::from_millis(20));
+                assert_eq!(emulator.cpu().cycles_executed(), cycles);
+                emulator.cpu().bus.sio0.flush().unwrap();
+                assert_eq!(*log.lock().unwrap(), ["audio stopped", "card saved"]);
+
+                // Stopping again has nothing to wait for
+                assert!(emulator.stop());
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn a_worker_stuck_in_its_frame_is_left_running() {
+        thread::Builder::new()
+            .stack_size(64 << 20)
+            .spawn(|| {
+                let (mut emulator, log) = running_emulator();
+                // Held by the test, the worker's next pass waits for it like a frame that never
+                // finishes
+                let shared = emulator.shared.clone();
+                let cpu = shared.cpu.lock().unwrap();
+                thread::sleep(PAUSED_REDRAW * 2);
+                let start = Instant::now();
+                assert!(!emulator.stop());
+                assert!(start.elapsed() >= STOP_TIMEOUT);
+                assert!(log.lock().unwrap().is_empty());
+
+                // The worker exits once it gets the machine back
+                drop(cpu);
+                while log.lock().unwrap().is_empty() {
+                    thread::sleep(Duration::from_millis(1));
+                }
+                assert_eq!(*log.lock().unwrap(), ["audio stopped"]);
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn frame_limiter_paces_by_speed() {
+        let pacing = Pacing {
+            fast_forward: false,
+            fast_forward_cap: 300,
+            audio_sync: false,
+            throttle: false,
+            run_ahead: 0,
+        };
+        let us = |period: Option<Duration>| period.map(|period| period.as_micros());
+        assert_eq!(us(pacing.frame_period(50.0)), Some(20000));
+        let fast_forward = Pacing {
+            fast_forward: true,
+            ..pacing
+        };
+        assert_eq!(us(fast_forward.frame_period(50.0)), Some(6666));
+        let uncapped = Pacing {
+            fast_forward_cap: 0,
+            ..fast_forward
+        };
+        assert_eq!(uncapped.frame_period(50.0), None);
+        // Throttling overrides fast forward, even uncapped
+        let throttled = Pacing {
+            throttle: true,
+            ..uncapped
+        };
+        assert_eq!(us(throttled.frame_period(50.0)), Some(80000));
+    }
+
+    #[test]
+    fn frame_deadlines_follow_each_other() {
+        let period = Duration::from_millis(20);
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+
+        // The first frame runs at once
+        assert_eq!(frame_schedule(None, at(0), period), (1, at(20)));
+        // Woken early, nothing is due until the deadline
+        assert_eq!(frame_schedule(Some(at(20)), at(15), period), (0, at(20)));
+        assert_eq!(frame_schedule(Some(at(20)), at(20), period), (1, at(40)));
+        // Woken late, the next deadline keeps to the schedule rather than the wake up
+        assert_eq!(frame_schedule(Some(at(20)), at(27), period), (1, at(40)));
+        // Frames missed are made up
+        assert_eq!(frame_schedule(Some(at(20)), at(75), period), (3, at(80)));
+        assert_eq!(frame_schedule(Some(at(20)), at(100), period), (5, at(120)));
+        // Past MAX_LATE_FRAMES behind the schedule starts over from now
+        assert_eq!(frame_schedule(Some(at(20)), at(101), period), (1, at(121)));
+        assert_eq!(
+            frame_schedule(Some(at(20)), at(5000), period),
+            (1, at(5020))
+        );
+    }
+}
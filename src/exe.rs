@@ -0,0 +1,149 @@
+use std::io;
+use std::ops::Range;
+
+// The program follows a 2KB header
+pub const HEADER_SIZE: usize = 0x800;
+const MAGIC: &[u8] = b"PS-X EXE";
+const RAM_SIZE: usize = 0x200000;
+// The bus keeps the 64KB the kernel uses apart from the rest of RAM
+const USER_RAM_START: usize = 0x10000;
+
+pub struct Exe<'a> {
+    pub pc: u32,
+    pub gp: u32,
+    // RAM address the program is copied to
+    pub dest: u32,
+    // Area cleared before the program starts, usually its BSS
+    pub fill_start: u32,
+    pub fill_size: u32,
+    // Zero keeps the stack the BIOS set up
+    pub sp: u32,
+    pub body: &'a [u8],
+}
+
+impl<'a> Exe<'a> {
+    pub fn parse(exe: &'a [u8]) -> io::Result<Self> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        if exe.len() < HEADER_SIZE || !exe.starts_with(MAGIC) {
+            return Err(invalid("not a PS-X EXE".to_string()));
+        }
+
+        let word = |offset: usize| u32::from_le_bytes(exe[offset..offset + 4].try_into().unwrap());
+        let size = word(0x1C) as usize;
+        let body = exe.get(HEADER_SIZE..HEADER_SIZE + size).ok_or_else(|| {
+            invalid(format!(
+                "header gives {size:X} bytes of program but the file has {:X}",
+                exe.len() - HEADER_SIZE
+            ))
+        })?;
+
+        let parsed = Self {
+            pc: word(0x10),
+            gp: word(0x14),
+            dest: word(0x18),
+            fill_start: word(0x28),
+            fill_size: word(0x2C),
+            sp: word(0x30).wrapping_add(word(0x34)),
+            body,
+        };
+        if ram_range(parsed.dest, size).is_none()
+            || ram_range(parsed.fill_start, parsed.fill_size as usize).is_none()
+        {
+            return Err(invalid(format!(
+                "program at {:08X} does not fit in RAM",
+                parsed.dest
+            )));
+        }
+        Ok(parsed)
+    }
+}
+
+// Offsets into the bus's RAM past the kernel area covered by a block at a KUSEG, KSEG0 or
+// KSEG1 address
+pub fn ram_range(addr: u32, size: usize) -> Option<Range<usize>> {
+    if size == 0 {
+        return Some(0..0);
+    }
+    let start = ((addr & 0x1FFFFFFF) as usize).checked_sub(USER_RAM_START)?;
+    let end = start.checked_add(size)?;
+    (end <= RAM_SIZE - USER_RAM_START).then_some(start..end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // EXE loaded at 0x80010000 starting at 0x80010008, with a BSS after the program and the
+    // stack at 0x801FFF00
+    fn exe(body: &[u8]) -> Vec<u8> {
+        let mut exe = vec![0; HEADER_SIZE];
+        exe[..8].copy_from_slice(MAGIC);
+        for (offset, val) in [
+            (0x10, 0x80010008),
+            (0x14, 0x8001C000),
+            (0x18, 0x80010000),
+            (0x1C, body.len() as u32),
+            (0x28, 0x80018000),
+            (0x2C, 0x100),
+            (0x30, 0x801FFF00),
+            (0x34, 0),
+        ] {
+            exe[offset..offset + 4].copy_from_slice(&u32::to_le_bytes(val));
+        }
+        exe.extend(body);
+        exe
+    }
+
+    #[test]
+    fn header_gives_the_registers_and_load_address() {
+        let body: Vec<u8> = (0..0x800).map(|i| i as u8).collect();
+        let file = exe(&body);
+        let parsed = Exe::parse(&file).unwrap();
+        assert_eq!(parsed.pc, 0x80010008);
+        assert_eq!(parsed.gp, 0x8001C000);
+        assert_eq!(parsed.dest, 0x80010000);
+        assert_eq!((parsed.fill_start, parsed.fill_size), (0x80018000, 0x100));
+        assert_eq!(parsed.sp, 0x801FFF00);
+        assert_eq!(parsed.body, body);
+
+        // The stack is the base plus the offset
+        let mut file = exe(&body);
+        file[0x34..0x38].copy_from_slice(&0xF0u32.to_le_bytes());
+        assert_eq!(Exe::parse(&file).unwrap().sp, 0x801FFFF0);
+        // Anything after the size in the header isn't part of the program
+        let mut file = exe(&body);
+        file.extend([0xFF; 0x100]);
+        assert_eq!(Exe::parse(&file).unwrap().body, body);
+    }
+
+    #[test]
+    fn broken_files_are_rejected() {
+        let file = exe(&[0; 0x800]);
+        assert!(Exe::parse(&file[..HEADER_SIZE - 1]).is_err());
+        // The program is cut short
+        assert!(Exe::parse(&file[..HEADER_SIZE + 0x7FF]).is_err());
+        let mut bad_magic = file.clone();
+        bad_magic[3] = b'Y';
+        assert!(Exe::parse(&bad_magic).is_err());
+
+        // The program and BSS must land in RAM past the kernel
+        for (offset, val) in [(0x18, 0x8000F000), (0x18, 0x801FFC00), (0x2C, 0x200000)] {
+            let mut file = file.clone();
+            file[offset..offset + 4].copy_from_slice(&u32::to_le_bytes(val));
+            let err = Exe::parse(&file).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn ram_ranges_skip_the_kernel_area() {
+        // The same RAM through KUSEG, KSEG0 and KSEG1
+        for addr in [0x00010000, 0x80010000, 0xA0010000] {
+            assert_eq!(ram_range(addr, 0x10), Some(0..0x10));
+        }
+        assert_eq!(ram_range(0x801FFFF0, 0x10), Some(0x1EFFF0..0x1F0000));
+        assert_eq!(ram_range(0x801FFFF0, 0x11), None);
+        assert_eq!(ram_range(0x8000FFFF, 1), None);
+        assert_eq!(ram_range(0x8000FFFF, 0), Some(0..0));
+    }
+}
@@ -1,215 +1,3363 @@
-use std::{fs, path::PathBuf, time::Instant};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{fs, path::Path, path::PathBuf, time::Instant};
 
+use crate::audio;
+use crate::bios::{self, BIOS_SIZE, Bios};
+use crate::bios_calls::BiosLog;
+use crate::breakpoints::{self, BreakpointWindow};
+use crate::cheats::{self, CheatWindow};
+use crate::config::{CONFIG_PATH, Config};
 use crate::cpu::Cpu;
-use crate::tracing_setup;
-use eframe::egui::{self, Event, RichText};
+use crate::disassembly_viewer::{DisassemblyAction, DisassemblyViewer};
+use crate::disc::{self, Disc};
+use crate::emulator::{
+    self, CPU_CLOCK, Command, Emulator, Frame, Input, MAX_RUN_AHEAD, MovieProgress, Pacing,
+    RunState,
+};
+use crate::exe::Exe;
+use crate::gamepad::{Gamepads, PadEvent};
+use crate::gpu::{DebugView, DisplayOptions, DisplayRange};
+use crate::hotkey::{self, Hotkey};
+use crate::input::{self, BUTTON_NAMES, InputConfig};
+use crate::memcard::MemoryCard;
+use crate::memory_viewer::MemoryViewer;
+use crate::mouse::PsMouse;
+use crate::movie::{Movie, MovieHeader};
+use crate::multitap::Multitap;
+use crate::notifications::Notifications;
+use crate::pad::{AnalogPad, Buttons, DigitalPad};
+use crate::register_viewer::RegisterViewer;
+use crate::screenshot;
+use crate::sio::SioDevice;
+use crate::sio1::{Loopback, SerialLink, TcpLink};
+use crate::state::{self, StateError};
+use crate::tty_console::TtyConsole;
+use crate::vram_viewer::VramViewer;
+use crate::watchpoints::{WatchHit, WatchpointWindow};
+use eframe::egui::{self, Event, RichText, emath::GuiRounding};
+use serde::{Deserialize, Serialize};
+use tracing::{Level, event};
 
-//use tracing::{Level, event};
+const VRAM_DUMP_PATH: &str = "vram_dump.bin";
+const VRAM_PNG_PATH: &str = "vram_dump.png";
+const MEMCARD_DIR: &str = "memcards/";
+const SCREENSHOT_DIR: &str = "screenshots/";
+const SAVESTATE_DIR: &str = "savestates/";
+const MOVIE_DIR: &str = "movies/";
+const SAVE_SLOTS: usize = 10;
+// Size of the slot thumbnails in the save states menu, which show full size when hovered
+const THUMBNAIL_SHOWN: egui::Vec2 = egui::vec2(64.0, 48.0);
+// Frame advance repeats after being held this long, then at the slower rate
+const FRAME_ADVANCE_DELAY: Duration = Duration::from_millis(400);
+const FRAME_ADVANCE_REPEAT: Duration = Duration::from_millis(100);
+// Darkens the gap between lines with the scanline filter
+const SCANLINE_SHADE: egui::Color32 = egui::Color32::from_black_alpha(96);
+// Seconds between saves of written memory cards
+const MEMCARD_FLUSH_SECS: u64 = 1;
+// Address the link cable hosts on and joins, for two instances on the same machine
+const LINK_ADDR: &str = "127.0.0.1:5441";
+// Players a multitap in each port allows for
+const MAX_PLAYERS: usize = 8;
+// Keyboard layouts players can be bound to
+const KEYBOARD_LAYOUTS: usize = 2;
+const INPUT_CONFIG_PATH: &str = "input.toml";
+
+// Controller plugged into a port
+#[derive(Clone, Copy, PartialEq)]
+enum PortDevice {
+    None,
+    Digital,
+    Analog,
+    Multitap,
+    Mouse,
+}
+
+impl PortDevice {
+    fn label(self) -> &'static str {
+        match self {
+            PortDevice::None => "None",
+            PortDevice::Digital => "Digital pad",
+            PortDevice::Analog => "Analog pad",
+            PortDevice::Multitap => "Multitap",
+            PortDevice::Mouse => "Mouse",
+        }
+    }
+
+    fn connect(self) -> Option<Box<dyn SioDevice>> {
+        match self {
+            PortDevice::None => None,
+            PortDevice::Digital => Some(Box::new(DigitalPad::new())),
+            PortDevice::Analog => Some(Box::new(AnalogPad::new())),
+            PortDevice::Multitap => Some(Box::new(Multitap::new())),
+            PortDevice::Mouse => Some(Box::new(PsMouse::new())),
+        }
+    }
+
+    // Controller slots played from the keyboard
+    fn slots(self) -> usize {
+        match self {
+            PortDevice::None | PortDevice::Mouse => 0,
+            PortDevice::Digital | PortDevice::Analog => 1,
+            PortDevice::Multitap => 4,
+        }
+    }
+}
+
+// Which card image goes in a memory card slot
+#[derive(Clone, Copy, PartialEq)]
+enum CardSlot {
+    Empty,
+    // memcards/global_N.mcd
+    Global,
+    // memcards/<game>_N.mcd
+    PerGame,
+}
+
+impl CardSlot {
+    fn label(self) -> &'static str {
+        match self {
+            CardSlot::Empty => "Empty",
+            CardSlot::Global => "Shared card",
+            CardSlot::PerGame => "Per game card",
+        }
+    }
+}
+
+// Host updates, emulated frames and cycles over the last second
+struct SpeedMeter {
+    // Time of each update with the frames and cycles emulated by then
+    samples: VecDeque<(Instant, u64, u64)>,
+}
+
+impl SpeedMeter {
+    fn new() -> Self {
+        Self {
+            samples: VecDeque::new(),
+        }
+    }
+
+    fn push(&mut self, frames: u64, cycles: u64) {
+        self.push_at(frames, cycles, Instant::now());
+    }
+
+    fn push_at(&mut self, frames: u64, cycles: u64, now: Instant) {
+        // Resets and loaded states move the cycle count back, so the window starts over
+        if self
+            .samples
+            .back()
+            .is_some_and(|&(_, last_frames, last_cycles)| {
+                frames < last_frames || cycles < last_cycles
+            })
+        {
+            self.samples.clear();
+        }
+        self.samples.push_back((now, frames, cycles));
+        // The newest sample over a second old stays as the start of the window
+        while self
+            .samples
+            .get(1)
+            .is_some_and(|(time, _, _)| now.duration_since(*time).as_secs_f32() >= 1.0)
+        {
+            self.samples.pop_front();
+        }
+    }
+
+    // Updates per second, emulated frames per second and the percentage of a real console's
+    // speed
+    fn rates(&self) -> (f32, f32, f32) {
+        let (Some(first), Some(last)) = (self.samples.front(), self.samples.back()) else {
+            return (0.0, 0.0, 0.0);
+        };
+        let elapsed = last.0.duration_since(first.0).as_secs_f32();
+        if elapsed == 0.0 {
+            return (0.0, 0.0, 0.0);
+        }
+        (
+            (self.samples.len() - 1) as f32 / elapsed,
+            (last.1 - first.1) as f32 / elapsed,
+            (last.2 - first.2) as f32 / elapsed / CPU_CLOCK * 100.0,
+        )
+    }
+}
+
+// How the picture is fitted to the window
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum Aspect {
+    Stretch,
+    FourThree,
+    // Largest whole multiple of the picture with square pixels
+    Native,
+}
+
+impl Aspect {
+    fn label(self) -> &'static str {
+        match self {
+            Aspect::Stretch => "Stretch",
+            Aspect::FourThree => "4:3",
+            Aspect::Native => "Native",
+        }
+    }
+}
+
+// How the picture's pixels are drawn
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum Filter {
+    // Sharp pixels at a whole multiple of the picture's height
+    Integer,
+    Bilinear,
+    // Bilinear with every other row of screen pixels darkened
+    Scanlines,
+}
+
+impl Filter {
+    fn label(self) -> &'static str {
+        match self {
+            Filter::Integer => "Integer scaling",
+            Filter::Bilinear => "Bilinear",
+            Filter::Scanlines => "Scanlines",
+        }
+    }
+}
+
+// Size the picture is drawn at in the space available, before any integer scaling
+fn display_size(
+    aspect: Aspect,
+    available: egui::Vec2,
+    picture: egui::Vec2,
+    square_pixels: bool,
+) -> egui::Vec2 {
+    let fit = |ratio: f32| {
+        let fit_width = available.x.min(available.y * ratio);
+        egui::vec2(fit_width, fit_width / ratio)
+    };
+    match aspect {
+        Aspect::Stretch => available,
+        Aspect::FourThree if !square_pixels => fit(4.0 / 3.0),
+        Aspect::FourThree => fit(picture.x / picture.y),
+        Aspect::Native => {
+            let scale = (available.x / picture.x)
+                .min(available.y / picture.y)
+                .floor();
+            if scale >= 1.0 {
+                picture * scale
+            } else {
+                fit(picture.x / picture.y)
+            }
+        }
+    }
+}
+
+// Window title while a game is on
+fn running_title(name: &str, paused: bool, fps: f32, speed: f32) -> String {
+    if paused {
+        format!("{name} — PS1 Emulator — Paused")
+    } else {
+        format!("{name} — PS1 Emulator — {fps:.0} fps / {speed:.0}%")
+    }
+}
+
+// Where the picture goes when scaled by a whole number. The largest multiple of its height that
+// fits the fitted size is used, keeping that size's shape, and the result is centered on whole
+// screen pixels so none are stretched. Pictures too big for one multiple keep the fitted size
+fn integer_rect(
+    available: egui::Rect,
+    fitted: egui::Vec2,
+    picture: egui::Vec2,
+    pixels_per_point: f32,
+) -> egui::Rect {
+    let scale = (fitted.y * pixels_per_point / picture.y).floor();
+    let size = if scale >= 1.0 {
+        fitted * (scale * picture.y / pixels_per_point / fitted.y)
+    } else {
+        fitted
+    };
+    let size = size.round_to_pixels(pixels_per_point);
+    let min = (available.center() - size / 2.0).round_to_pixels(pixels_per_point);
+    egui::Rect::from_min_size(min, size)
+}
+
+// What a running game does while the window is unfocused
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum Background {
+    Run,
+    // Resumes when focus comes back, unless the game was paused some other way since
+    Pause,
+    Throttle,
+}
+
+impl Background {
+    fn label(self) -> &'static str {
+        match self {
+            Background::Run => "Keep running",
+            Background::Pause => "Pause",
+            Background::Throttle => "Run slowly",
+        }
+    }
+}
+
+// Ways of leaving the running game
+#[derive(Clone, Copy)]
+enum EndGame {
+    Reset,
+    QuitToList,
+    Exit,
+}
+
+impl EndGame {
+    fn label(self) -> &'static str {
+        match self {
+            EndGame::Reset => "Reset",
+            EndGame::QuitToList => "Quit to game list",
+            EndGame::Exit => "Exit",
+        }
+    }
+}
+
+// What the serial port is plugged into
+#[derive(Clone, Copy, PartialEq)]
+enum LinkCable {
+    None,
+    Loopback,
+    // Waits on LINK_ADDR for the other emulator
+    Host,
+    Join,
+}
+
+impl LinkCable {
+    fn label(self) -> &'static str {
+        match self {
+            LinkCable::None => "None",
+            LinkCable::Loopback => "Loopback plug",
+            LinkCable::Host => "Host link",
+            LinkCable::Join => "Join link",
+        }
+    }
+
+    fn connect(self) -> std::io::Result<Option<Box<dyn SerialLink>>> {
+        Ok(match self {
+            LinkCable::None => None,
+            LinkCable::Loopback => Some(Box::new(Loopback::default())),
+            LinkCable::Host => Some(Box::new(TcpLink::host(LINK_ADDR)?)),
+            LinkCable::Join => Some(Box::new(TcpLink::join(LINK_ADDR)?)),
+        })
+    }
+}
 
 pub struct GameSelect {
-    pub filepaths: Vec<PathBuf>,
+    folder: PathBuf,
+    // Sorted by name
+    games: Vec<GameEntry>,
+    // Why the folder could not be listed
+    error: Option<String>,
+    filter: String,
+    // Hover text of each game, filled in as they are hovered since discs have to be opened
+    details: HashMap<PathBuf, String>,
     pub selected_game: Option<PathBuf>,
 }
 
-impl GameSelect {
-    pub fn new(folder: PathBuf) -> Self {
-        let mut filepaths = Vec::new();
-        for filepath in folder.read_dir().unwrap().flatten() {
-            filepaths.push(filepath.path());
+struct GameEntry {
+    path: PathBuf,
+    // File stem shown in the list
+    name: String,
+}
+
+impl GameSelect {
+    pub fn new(folder: PathBuf) -> Self {
+        let mut game_select = Self {
+            folder,
+            games: Vec::new(),
+            error: None,
+            filter: String::new(),
+            details: HashMap::new(),
+            selected_game: None,
+        };
+        game_select.refresh();
+        game_select
+    }
+
+    pub fn refresh(&mut self) {
+        self.details.clear();
+        match scan_games(&self.folder) {
+            Ok(games) => {
+                self.games = games;
+                self.error = None;
+            }
+            Err(err) => {
+                self.games.clear();
+                self.error = Some(format!("{}: {err}", self.folder.display()));
+            }
+        }
+    }
+
+    fn disc_images(&self) -> impl Iterator<Item = &GameEntry> {
+        self.games
+            .iter()
+            .filter(|game| disc::is_disc_image(&game.path))
+    }
+}
+
+// Games in a folder and its subfolders. Only disc images and executables are listed, leaving
+// out the track files of listed cue sheets
+fn scan_games(folder: &Path) -> io::Result<Vec<GameEntry>> {
+    let mut paths = Vec::new();
+    collect_files(folder, &mut paths)?;
+
+    let tracks: HashSet<PathBuf> = paths
+        .iter()
+        .filter(|path| has_extension(path, "cue"))
+        .filter_map(|cue| disc::cue_files(cue).ok())
+        .flatten()
+        .collect();
+    let mut games: Vec<GameEntry> = paths
+        .into_iter()
+        .filter(|path| (disc::is_disc_image(path) || is_exe(path)) && !tracks.contains(path))
+        .map(|path| GameEntry {
+            name: path
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            path,
+        })
+        .collect();
+    games.sort_by_cached_key(|game| (game.name.to_lowercase(), game.path.clone()));
+    Ok(games)
+}
+
+// Games with the text typed in the filter box anywhere in their name, ignoring case
+fn filter_games<'a>(games: &'a [GameEntry], filter: &str) -> impl Iterator<Item = &'a GameEntry> {
+    let filter = filter.to_lowercase();
+    games
+        .iter()
+        .filter(move |game| game.name.to_lowercase().contains(&filter))
+}
+
+// Subfolders that can't be read are skipped. Symbolic links to folders aren't followed so a
+// loop can't recurse forever
+fn collect_files(dir: &Path, out: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)?.flatten() {
+        let path = entry.path();
+        if entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+            let _ = collect_files(&path, out);
+        } else {
+            out.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case(extension))
+}
+
+// What a selected file boots as
+enum Game {
+    Disc(Disc),
+    Exe(Vec<u8>),
+}
+
+fn is_exe(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("exe") || ext.eq_ignore_ascii_case("psexe"))
+}
+
+// What a file dropped on the window is loaded as
+enum DroppedFile {
+    Game,
+    Bios(Bios),
+    State,
+}
+
+// Known by extension, except that a .bin the size of a BIOS is checked for being one
+fn sniff_dropped(path: &Path) -> Result<DroppedFile, String> {
+    let extension = path.extension().and_then(|ext| ext.to_str());
+    if extension.is_some_and(|ext| ext.eq_ignore_ascii_case("sav")) {
+        return Ok(DroppedFile::State);
+    }
+    if extension.is_some_and(|ext| ext.eq_ignore_ascii_case("bin"))
+        && fs::metadata(path).is_ok_and(|meta| meta.len() == BIOS_SIZE as u64)
+        && let Ok(bios) = Bios::open(path)
+    {
+        return Ok(DroppedFile::Bios(bios));
+    }
+    if disc::is_disc_image(path) || is_exe(path) {
+        return Ok(DroppedFile::Game);
+    }
+    Err(format!(
+        "{} is not a disc image, executable, BIOS or save state",
+        file_name(path)
+    ))
+}
+
+// Written when a game is quit and loaded by Continue
+fn auto_state_path(game: &Path) -> Option<PathBuf> {
+    let name = game.file_stem()?.to_string_lossy();
+    Some(Path::new(SAVESTATE_DIR).join(&*name).join("auto.sav"))
+}
+
+// Save state slots of a game, such as "savestates/Crash/slot3.sav"
+fn slot_state_path(game: &str, slot: usize) -> PathBuf {
+    Path::new(SAVESTATE_DIR)
+        .join(game)
+        .join(format!("slot{slot}.sav"))
+}
+
+// Slot the previous and next slot hotkeys go to, wrapping around at either end
+fn cycle_slot(slot: usize, forward: bool) -> usize {
+    if forward {
+        slot % SAVE_SLOTS + 1
+    } else {
+        (slot + SAVE_SLOTS - 2) % SAVE_SLOTS + 1
+    }
+}
+
+fn write_state(path: &Path, state: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, state)
+}
+
+// Saves the machine to a slot or the auto slot
+fn save_machine(cpu: &mut Cpu, path: &Path, thumbnail: Option<&[u8]>) -> io::Result<()> {
+    let mut state = Vec::new();
+    cpu.save_state(&mut state, thumbnail);
+    write_state(path, &state)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn open_game(path: &Path) -> Result<Game, String> {
+    let game = if disc::is_disc_image(path) {
+        Disc::open(path).map(Game::Disc)
+    } else if is_exe(path) {
+        fs::read(path).and_then(|exe| {
+            // Checked here so a bad header is reported before the BIOS runs
+            Exe::parse(&exe)?;
+            Ok(Game::Exe(exe))
+        })
+    } else {
+        return Err(format!("{}: unknown file type", path.display()));
+    };
+    game.map_err(|err| format!("{}: {err}", path.display()))
+}
+
+fn movie_status(movie: MovieProgress) -> String {
+    if movie.recording {
+        format!(
+            "Recording frame {}, {} rerecords",
+            movie.length, movie.rerecords
+        )
+    } else {
+        format!("Playing frame {} of {}", movie.frame, movie.length)
+    }
+}
+
+// Text on a dark box, placed by one of its corners
+fn paint_label(ui: &egui::Ui, pos: egui::Pos2, align: egui::Align2, text: String) {
+    let painter = ui.painter();
+    let galley = painter.layout_no_wrap(text, egui::FontId::monospace(14.0), egui::Color32::WHITE);
+    let rect = align.anchor_size(pos, galley.size());
+    painter.rect_filled(rect.expand(4.0), 4.0, egui::Color32::from_black_alpha(160));
+    painter.galley(rect.min, galley, egui::Color32::WHITE);
+}
+
+// Path of a game, with the region and boot executable of discs
+fn game_details(path: &Path) -> String {
+    let name = path.to_string_lossy();
+    if !disc::is_disc_image(path) {
+        return name.into_owned();
+    }
+
+    match Disc::open(path) {
+        Ok(mut disc) => {
+            let region = match disc.region() {
+                Some(region) => format!("{region:?}"),
+                None => "Unlicensed".to_string(),
+            };
+            let boot = disc
+                .boot_executable()
+                .unwrap_or_else(|_| "no executable".to_string());
+            format!("{name} [{region}, {boot}]")
+        }
+        Err(err) => format!("{name} [{err}]"),
+    }
+}
+
+pub struct MyApp {
+    emulator: Emulator,
+    cpu_rom_loaded: bool,
+    play_bios: bool,
+    // Last run state the worker reported, or the one just asked for
+    run_state: RunState,
+    focused: bool,
+    // Paused by losing focus rather than by the user
+    background_paused: bool,
+    // When a held frame advance hotkey runs the next frame
+    frame_advance_repeat: Option<Instant>,
+    // Buttons each keyboard player holds, sent for the next frame
+    held_buttons: Vec<Buttons>,
+    tty_output: bool,
+    game_select: GameSelect,
+    screen_texture: egui::TextureHandle,
+    // Drawn over the picture by the scanline filter, two rows for each line
+    scanlines: Option<egui::TextureHandle>,
+    speed_meter: SpeedMeter,
+    stats: emulator::Stats,
+    show_speed_overlay: bool,
+    // Latched fast forward
+    turbo: bool,
+    fast_forward: bool,
+    notifications: Notifications,
+    // Last title given to the window, which is only sent again once it changes
+    title: String,
+    // Where the movie being recorded is saved once it stops
+    movie_path: Option<PathBuf>,
+    show_full_vram: bool,
+    fullscreen: bool,
+    // Latest frame from the worker
+    frame: Frame,
+    vram_buffer: Vec<u8>,
+    debug_view: DebugView,
+    show_gpu_stats: bool,
+    vram_viewer: VramViewer,
+    register_viewer: RegisterViewer,
+    memory_viewer: MemoryViewer,
+    breakpoint_window: BreakpointWindow,
+    cheat_window: CheatWindow,
+    // Breakpoint the CPU last stopped at, until emulation goes on
+    breakpoint_hit: Option<u32>,
+    watchpoint_window: WatchpointWindow,
+    // Access that last stopped the CPU at a watchpoint, until emulation goes on
+    watch_hit: Option<WatchHit>,
+    disassembly_viewer: DisassemblyViewer,
+    tty_console: TtyConsole,
+    // Save state slot the hotkeys use, from 1 to SAVE_SLOTS
+    save_slot: usize,
+    // Game started with Continue, whose auto state is loaded once it boots
+    continue_game: Option<PathBuf>,
+    // Thumbnails of the save state slots, with when the state they came from was saved
+    thumbnails: HashMap<PathBuf, (SystemTime, Option<egui::TextureHandle>)>,
+    // Save state dropped on the window, waiting to be confirmed
+    dropped_state: Option<PathBuf>,
+    // State last sent to the worker, by name, so one refused for its BIOS or disc can be forced
+    loading_state: Option<(String, PathBuf)>,
+    // Refused state with the reason, waiting for the user to load it anyway or not
+    mismatched_state: Option<(String, PathBuf, String)>,
+    // Reset or quit waiting on a memory card write to be confirmed
+    confirm_end_game: Option<EndGame>,
+    // Closing was allowed, so the next close request shuts down without asking
+    exit_confirmed: bool,
+    tray_open: bool,
+    // Disc inserted when the tray is closed
+    next_disc: Option<PathBuf>,
+    memcard_slots: [CardSlot; 2],
+    port_devices: [PortDevice; 2],
+    link_cable: LinkCable,
+    // Keyboard layout each player is bound to
+    player_keys: [Option<usize>; MAX_PLAYERS],
+    // Gamepad each player is bound to, by the number it was plugged in as
+    player_pads: [Option<usize>; MAX_PLAYERS],
+    gamepads: Gamepads,
+    // Motor speeds of the pad on each port
+    rumble: [(u8, u8); 2],
+    input_config: InputConfig,
+    // Layout and button waiting for a key press in the bindings menu
+    rebinding: Option<(usize, &'static str)>,
+    // Button waiting for a gamepad button press in the bindings menu
+    rebinding_pad: Option<&'static str>,
+    // Hotkey waiting for a key combination in the hotkeys menu
+    rebinding_hotkey: Option<Hotkey>,
+    // Host mouse motion goes to the emulated mouse while captured
+    mouse_captured: bool,
+    last_memcard_flush: Instant,
+    config: Config,
+    bios: Option<Bios>,
+    // Why no BIOS could be loaded
+    bios_error: Option<String>,
+    // Why the selected game could not be started
+    game_error: Option<String>,
+}
+
+impl MyApp {
+    pub fn new(
+        cc: &eframe::CreationContext<'_>,
+        config: Config,
+        tty_output: bool,
+        tracing_start_pc: Option<u32>,
+    ) -> Self {
+        let mut app = Self {
+            emulator: Emulator::new(
+                Cpu::new(),
+                audio::open_output(),
+                cc.egui_ctx.clone(),
+                tty_output,
+                tracing_start_pc,
+            ),
+            cpu_rom_loaded: false,
+            play_bios: false,
+            run_state: RunState::Running,
+            focused: true,
+            background_paused: false,
+            frame_advance_repeat: None,
+            held_buttons: Vec::new(),
+            tty_output,
+            game_select: GameSelect::new(config.rom_dir.clone()),
+            screen_texture: cc.egui_ctx.load_texture(
+                "Noise",
+                egui::ColorImage::example(),
+                egui::TextureOptions::NEAREST,
+            ),
+            scanlines: None,
+            speed_meter: SpeedMeter::new(),
+            stats: emulator::Stats::default(),
+            show_speed_overlay: false,
+            turbo: false,
+            fast_forward: false,
+            notifications: Notifications::new(),
+            title: String::new(),
+            movie_path: None,
+            show_full_vram: false,
+            fullscreen: false,
+            frame: Frame::default(),
+            vram_buffer: Vec::new(),
+            debug_view: DebugView::Off,
+            show_gpu_stats: false,
+            vram_viewer: VramViewer::new(),
+            register_viewer: RegisterViewer::new(),
+            memory_viewer: MemoryViewer::new(),
+            breakpoint_window: BreakpointWindow::new(),
+            cheat_window: CheatWindow::new(),
+            breakpoint_hit: None,
+            watchpoint_window: WatchpointWindow::new(),
+            watch_hit: None,
+            disassembly_viewer: DisassemblyViewer::new(),
+            tty_console: TtyConsole::new(),
+            save_slot: 1,
+            continue_game: None,
+            thumbnails: HashMap::new(),
+            dropped_state: None,
+            loading_state: None,
+            mismatched_state: None,
+            confirm_end_game: None,
+            exit_confirmed: false,
+            tray_open: false,
+            next_disc: None,
+            memcard_slots: [CardSlot::PerGame, CardSlot::Empty],
+            port_devices: [PortDevice::Digital, PortDevice::None],
+            link_cable: LinkCable::None,
+            player_keys: [Some(0), Some(1), None, None, None, None, None, None],
+            player_pads: [Some(0), Some(1), None, None, None, None, None, None],
+            rumble: [(0, 0); 2],
+            gamepads: Gamepads::new(),
+            input_config: InputConfig::load(Path::new(INPUT_CONFIG_PATH)),
+            rebinding: None,
+            rebinding_pad: None,
+            rebinding_hotkey: None,
+            mouse_captured: false,
+            last_memcard_flush: Instant::now(),
+            config,
+            bios: None,
+            bios_error: None,
+            game_error: None,
+        };
+        app.find_bios();
+        app
+    }
+}
+
+impl MyApp {
+    fn dump_vram(&mut self) {
+        let result = {
+            let cpu = self.emulator.cpu();
+            let gpu = &cpu.bus.gpu;
+            gpu.dump_vram(Path::new(VRAM_DUMP_PATH))
+                .and_then(|_| gpu.dump_vram_png(Path::new(VRAM_PNG_PATH)))
+        };
+        match result {
+            Ok(()) => self.notify(format!(
+                "VRAM dumped to {VRAM_DUMP_PATH} and {VRAM_PNG_PATH}"
+            )),
+            Err(err) => self.notify(format!("Failed to dump VRAM: {err}")),
+        }
+    }
+
+    fn load_vram(&mut self) {
+        let result = self
+            .emulator
+            .cpu()
+            .bus
+            .gpu
+            .load_vram(Path::new(VRAM_DUMP_PATH));
+        match result {
+            Ok(()) => self.notify(format!("VRAM loaded from {VRAM_DUMP_PATH}")),
+            Err(err) => self.notify(format!("Failed to load VRAM: {err}")),
+        }
+    }
+
+    // Port and slot of each player. Players are numbered across port 1 then port 2
+    fn players(&self) -> Vec<(usize, usize)> {
+        (0..2)
+            .flat_map(|port| (0..self.port_devices[port].slots()).map(move |slot| (port, slot)))
+            .collect()
+    }
+
+    // A captured cursor is hidden and kept inside the window
+    fn capture_mouse(&mut self, ctx: &egui::Context, captured: bool) {
+        self.mouse_captured = captured;
+        let grab = if captured {
+            egui::CursorGrab::Confined
+        } else {
+            egui::CursorGrab::None
+        };
+        ctx.send_viewport_cmd(egui::ViewportCommand::CursorGrab(grab));
+        ctx.send_viewport_cmd(egui::ViewportCommand::CursorVisible(!captured));
+    }
+
+    fn set_fullscreen(&mut self, ctx: &egui::Context, fullscreen: bool) {
+        self.fullscreen = fullscreen;
+        ctx.send_viewport_cmd(egui::ViewportCommand::Fullscreen(fullscreen));
+    }
+
+    // The picked BIOS, falling back on the first valid one in the BIOS folder
+    fn find_bios(&mut self) {
+        let picked = self
+            .config
+            .bios_path
+            .as_ref()
+            .map(|path| Bios::open(path).map_err(|err| format!("{}: {err}", path.display())));
+        let result = match picked {
+            Some(Ok(bios)) => Ok(bios),
+            Some(Err(picked_err)) => bios::find(&self.config.bios_dir)
+                .map(|(_, bios)| bios)
+                .map_err(|_| picked_err),
+            None => bios::find(&self.config.bios_dir)
+                .map(|(_, bios)| bios)
+                .map_err(|err| err.to_string()),
+        };
+        (self.bios, self.bios_error) = match result {
+            Ok(bios) => (Some(bios), None),
+            Err(err) => {
+                self.notify(format!("BIOS not found: {err}"));
+                (None, Some(err))
+            }
+        };
+    }
+
+    fn save_config(&self) {
+        if let Err(err) = self.config.save(Path::new(CONFIG_PATH)) {
+            event!(
+                target: "ps1_emulator::Frontend",
+                Level::WARN,
+                "Failed to save {CONFIG_PATH}: {err}"
+            );
+        }
+    }
+
+    // Recently played games, newest first. Clicking one starts it again, and Continue starts
+    // it from where it was last quit
+    fn recent_games(&mut self, ui: &mut egui::Ui) {
+        if self.config.recent_games.is_empty() {
+            return;
+        }
+        ui.label("Recent games");
+        let mut launch = None;
+        let mut resume = None;
+        egui::Grid::new("recent_games").show(ui, |ui| {
+            for game in &self.config.recent_games {
+                let name = match game.path.file_stem() {
+                    Some(stem) => stem.to_string_lossy(),
+                    None => game.path.to_string_lossy(),
+                };
+                let button = ui
+                    .add_enabled(game.path.exists(), egui::Button::new(name))
+                    .on_hover_text(game.path.display().to_string())
+                    .on_disabled_hover_text(format!("{} is missing", game.path.display()));
+                if button.clicked() {
+                    launch = Some(game.path.clone());
+                }
+                let played = UNIX_EPOCH + Duration::from_secs(game.last_played);
+                ui.label(screenshot::format_time(played));
+                let saved = auto_state_path(&game.path)
+                    .and_then(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok());
+                match saved {
+                    Some(time) => {
+                        let button = ui
+                            .add_enabled(game.path.exists(), egui::Button::new("Continue"))
+                            .on_hover_text(format!("Saved {}", screenshot::format_time(time)));
+                        if button.clicked() {
+                            resume = Some(game.path.clone());
+                        }
+                    }
+                    None => {
+                        ui.label("");
+                    }
+                }
+                ui.end_row();
+            }
+        });
+        if resume.is_some() {
+            launch = resume.clone();
+        }
+        if launch.is_some() {
+            self.continue_game = resume;
+            self.game_select.selected_game = launch;
+            ui.ctx().request_repaint();
+        }
+        ui.separator();
+    }
+
+    fn pick_rom_dir(&mut self) {
+        let Some(path) = rfd::FileDialog::new()
+            .set_directory(&self.config.rom_dir)
+            .pick_folder()
+        else {
+            return;
+        };
+        self.game_select = GameSelect::new(path.clone());
+        self.config.rom_dir = path;
+        self.save_config();
+    }
+
+    // Games in the ROM folder whose names match the filter. Clicking one starts it
+    fn game_list(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label(format!("Games in {}", self.config.rom_dir.display()));
+            if ui.button("Refresh").clicked() {
+                self.game_select.refresh();
+            }
+            if ui.button("Choose folder...").clicked() {
+                self.pick_rom_dir();
+            }
+        });
+
+        let GameSelect {
+            games,
+            error,
+            filter,
+            details,
+            selected_game,
+            ..
+        } = &mut self.game_select;
+        if let Some(error) = error {
+            ui.label(RichText::new(error.as_str()).color(egui::Color32::RED));
+            ui.label("Choose the folder your games are in");
+            return;
+        }
+        if games.is_empty() {
+            ui.label("No disc images or executables found");
+            return;
+        }
+
+        ui.add(egui::TextEdit::singleline(filter).hint_text("Filter"));
+        egui::ScrollArea::vertical()
+            .max_height(320.0)
+            .show(ui, |ui| {
+                for game in filter_games(games, filter) {
+                    let response = ui.selectable_label(false, &game.name);
+                    if response.hovered() {
+                        let text = details
+                            .entry(game.path.clone())
+                            .or_insert_with(|| game_details(&game.path));
+                        response.clone().on_hover_text(text.as_str());
+                    }
+                    if response.clicked() {
+                        *selected_game = Some(game.path.clone());
+                    }
+                }
+            });
+    }
+
+    fn pick_bios(&mut self) {
+        let Some(path) = rfd::FileDialog::new()
+            .set_directory(&self.config.bios_dir)
+            .pick_file()
+        else {
+            return;
+        };
+        match Bios::open(&path) {
+            Ok(bios) => self.set_bios(path, bios),
+            Err(err) => self.bios_error = Some(format!("{}: {err}", path.display())),
+        }
+    }
+
+    // Used from the next boot on
+    fn set_bios(&mut self, path: PathBuf, bios: Bios) {
+        self.bios = Some(bios);
+        self.bios_error = None;
+        self.config.bios_path = Some(path);
+        self.save_config();
+    }
+
+    // Boots dropped games, or swaps the disc of a running one. Save states wait for the
+    // user to confirm
+    fn drop_files(&mut self, ctx: &egui::Context) {
+        let dropped: Vec<PathBuf> = ctx.input(|i| {
+            i.raw
+                .dropped_files
+                .iter()
+                .filter_map(|file| file.path.clone())
+                .collect()
+        });
+        for path in dropped {
+            let name = file_name(&path);
+            match sniff_dropped(&path) {
+                Ok(DroppedFile::Bios(bios)) => {
+                    self.notify(format!("BIOS set to {} ({})", bios.version, bios.date));
+                    self.set_bios(path, bios);
+                }
+                Ok(DroppedFile::Game) if !self.cpu_rom_loaded => {
+                    self.game_select.selected_game = Some(path);
+                }
+                Ok(DroppedFile::Game) if disc::is_disc_image(&path) => {
+                    self.next_disc = Some(path);
+                    self.open_tray();
+                    self.close_tray();
+                    self.notify(format!("Swapped disc to {name}"));
+                }
+                // An executable can't be swapped in, so the console starts over with it
+                Ok(DroppedFile::Game) => {
+                    self.power_off();
+                    self.game_select.selected_game = Some(path);
+                }
+                Ok(DroppedFile::State) if self.cpu_rom_loaded => self.dropped_state = Some(path),
+                Ok(DroppedFile::State) => {
+                    self.notify(format!("Start a game before loading {name}"));
+                }
+                Err(err) => self.notify(err),
+            }
+        }
+    }
+
+    // Asks before a dropped save state replaces the running game
+    fn dropped_state_window(&mut self, ctx: &egui::Context) {
+        let Some(path) = self.dropped_state.clone() else {
+            return;
+        };
+        let name = file_name(&path);
+        let mut load = None;
+        egui::Window::new("Load save state")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.label(format!("Load {name}? The running game will be replaced."));
+                ui.horizontal(|ui| {
+                    if ui.button("Load").clicked() {
+                        load = Some(true);
+                    }
+                    if ui.button("Cancel").clicked() {
+                        load = Some(false);
+                    }
+                });
+            });
+        if let Some(load) = load {
+            self.dropped_state = None;
+            if load {
+                self.load_state_file(&path, &name, false);
+            }
+        }
+    }
+
+    fn mismatched_state_window(&mut self, ctx: &egui::Context) {
+        let Some((name, path, reason)) = self.mismatched_state.clone() else {
+            return;
+        };
+        let mut load = None;
+        egui::Window::new("Load save state")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.label(format!(
+                    "The {reason}. The game may crash after loading {name}."
+                ));
+                ui.horizontal(|ui| {
+                    if ui.button("Load anyway").clicked() {
+                        load = Some(true);
+                    }
+                    if ui.button("Cancel").clicked() {
+                        load = Some(false);
+                    }
+                });
+            });
+        if let Some(load) = load {
+            self.mismatched_state = None;
+            if load {
+                self.load_state_file(&path, &name, true);
+            }
+        }
+    }
+
+    fn bios_panel(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            match (&self.bios, &self.bios_error) {
+                (Some(bios), _) => ui.label(format!("BIOS: {} ({})", bios.version, bios.date)),
+                (None, error) => ui.label(
+                    RichText::new(format!(
+                        "No valid BIOS: {}",
+                        error.as_deref().unwrap_or("none found")
+                    ))
+                    .color(egui::Color32::RED),
+                ),
+            };
+            if ui.button("Choose BIOS...").clicked() {
+                self.pick_bios();
+            }
+        });
+    }
+
+    // Translucent overlay for a picture with this many lines, remade when the count changes
+    fn scanline_texture(&mut self, ctx: &egui::Context, lines: usize) -> egui::TextureId {
+        if let Some(texture) = &self.scanlines
+            && texture.size()[1] != lines * 2
+        {
+            self.scanlines = None;
+        }
+        self.scanlines
+            .get_or_insert_with(|| {
+                let pixels = (0..lines * 2)
+                    .map(|row| {
+                        if row % 2 == 1 {
+                            SCANLINE_SHADE
+                        } else {
+                            egui::Color32::TRANSPARENT
+                        }
+                    })
+                    .collect();
+                let image = egui::ColorImage::new([1, lines * 2], pixels);
+                ctx.load_texture("Scanlines", image, egui::TextureOptions::LINEAR)
+            })
+            .id()
+    }
+
+    // Draws the picture centered in the rest of the panel, letterboxed to keep its shape. The
+    // texture keeps its last picture when there is no new one
+    fn draw_display(&mut self, ui: &mut egui::Ui, image: Option<egui::ColorImage>) {
+        let [width, height] = image
+            .as_ref()
+            .map_or(self.screen_texture.size(), |image| image.size);
+        let (width, height) = (width as f32, height as f32);
+        let (rect, _) = ui.allocate_exact_size(ui.available_size(), egui::Sense::hover());
+        // VRAM has no display shape, so it is always shown with square pixels
+        let size = display_size(
+            self.config.aspect,
+            rect.size(),
+            egui::vec2(width, height),
+            self.show_full_vram,
+        );
+
+        let (picture, options) = match self.config.filter {
+            Filter::Integer => {
+                let pixels_per_point = ui.ctx().pixels_per_point();
+                let picture = egui::vec2(width, height);
+                let picture = integer_rect(rect, size, picture, pixels_per_point);
+                (picture, egui::TextureOptions::NEAREST)
+            }
+            Filter::Bilinear | Filter::Scanlines => (
+                egui::Rect::from_center_size(rect.center(), size),
+                egui::TextureOptions::LINEAR,
+            ),
+        };
+        if let Some(image) = image {
+            self.screen_texture.set(image, options);
+        }
+
+        ui.painter().rect_filled(rect, 0.0, egui::Color32::BLACK);
+        let texture = egui::load::SizedTexture::new(self.screen_texture.id(), picture.size());
+        egui::Image::new(texture).paint_at(ui, picture);
+        if self.config.filter == Filter::Scanlines {
+            // Lines the console drew, before the internal resolution scaled them up
+            let lines = (height as usize / self.config.resolution_scale).max(1);
+            let texture = self.scanline_texture(ui.ctx(), lines);
+            let texture = egui::load::SizedTexture::new(texture, picture.size());
+            egui::Image::new(texture).paint_at(ui, picture);
+        }
+
+        if self.show_speed_overlay {
+            self.speed_overlay(ui, picture);
+        }
+        if let Some(movie) = self.stats.movie {
+            let pos = picture.right_top() + egui::vec2(-8.0, 8.0);
+            paint_label(ui, pos, egui::Align2::RIGHT_TOP, movie_status(movie));
+        }
+    }
+
+    // Speed counters in the top left corner of the picture
+    fn speed_overlay(&self, ui: &egui::Ui, picture: egui::Rect) {
+        let (host_fps, emulated_fps, speed) = self.speed_meter.rates();
+        let mut text =
+            format!("Host {host_fps:.1} FPS\nEmulated {emulated_fps:.1} FPS\nSpeed {speed:.0}%");
+        if let Some(depth) = self.stats.audio_buffered {
+            text += &format!("\nAudio {:.1} ms", depth as f32 * 1000.0 / 44100.0);
+        }
+        if self.throttled() {
+            text += "\nThrottled in background";
+        } else if self.fast_forward {
+            text += "\nFast forward";
+        }
+
+        let pos = picture.left_top() + egui::vec2(8.0, 8.0);
+        paint_label(ui, pos, egui::Align2::LEFT_TOP, text);
+    }
+
+    // The game and how fast it runs while it is on, the plain name in the game list
+    fn update_title(&mut self, ctx: &egui::Context) {
+        let title = if !self.cpu_rom_loaded {
+            "PS1 Emulator".to_string()
+        } else {
+            let name = self.game_name().unwrap_or_else(|| "BIOS".to_string());
+            let (_, fps, speed) = self.speed_meter.rates();
+            running_title(&name, self.run_state == RunState::Paused, fps, speed)
+        };
+        if title != self.title {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Title(title.clone()));
+            self.title = title;
+        }
+    }
+
+    // Shown in the corner of the window for a moment
+    fn notify(&mut self, message: String) {
+        event!(target: "ps1_emulator::Frontend", Level::INFO, "{message}");
+        self.notifications.push(message);
+    }
+
+    // The picture as shown on a TV, without any debug overlay
+    fn display_image(&self) -> egui::ColorImage {
+        let mut pixels = Vec::new();
+        let (width, height) = self.emulator.cpu().bus.gpu.render_display(&mut pixels);
+        egui::ColorImage::new([width, height], pixels)
+    }
+
+    fn screenshot(&mut self, vram: bool) {
+        let name = screenshot::file_name(self.game_name().as_deref(), SystemTime::now());
+        let (image, name) = if vram {
+            let image = screenshot::vram_image(&self.emulator.cpu().bus.gpu);
+            (image, format!("vram_{name}"))
+        } else {
+            (self.display_image(), name)
+        };
+        let path = Path::new(SCREENSHOT_DIR).join(name);
+        match screenshot::save_png(&path, &image) {
+            Ok(()) => self.notify(format!("Saved {}", path.display())),
+            Err(err) => self.notify(format!("Failed to save screenshot: {err}")),
+        }
+    }
+
+    fn copy_screenshot(&mut self, ctx: &egui::Context) {
+        ctx.copy_image(self.display_image());
+        self.notify("Screenshot copied to the clipboard".to_string());
+    }
+
+    fn game_name(&self) -> Option<String> {
+        let game = self.game_select.selected_game.as_ref()?;
+        Some(game.file_stem()?.to_string_lossy().into_owned())
+    }
+
+    // Breakpoints and save states are kept under this, "bios" when no game is loaded
+    fn game_key(&self) -> String {
+        self.game_name().unwrap_or_else(|| "bios".to_string())
+    }
+
+    // Hands the enabled breakpoints of the running game to the CPU
+    fn apply_breakpoints(&mut self) {
+        let breakpoints = self
+            .config
+            .breakpoints
+            .get(&self.game_key())
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        self.emulator
+            .cpu()
+            .set_breakpoints(breakpoints::enabled(breakpoints));
+    }
+
+    // Hands the enabled watchpoints of the running game to the bus
+    fn apply_watchpoints(&mut self) {
+        let watchpoints = self
+            .config
+            .watchpoints
+            .get(&self.game_key())
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        self.emulator
+            .cpu()
+            .set_watchpoints(watchpoints, self.config.watch_dma);
+    }
+
+    // Hands the codes of the running game's enabled cheats to the bus
+    fn apply_cheats(&mut self) {
+        let cheats = self
+            .config
+            .cheats
+            .get(&self.game_key())
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        self.emulator.cpu().bus.cheats = cheats::enabled_codes(cheats);
+    }
+
+    // Cropping and display range of the running game, from its override or the defaults
+    fn display_options(&self) -> DisplayOptions {
+        self.game_name()
+            .and_then(|name| self.config.game_display.get(&name).copied())
+            .unwrap_or(self.config.display)
+    }
+
+    fn apply_display_options(&mut self) {
+        let options = self.display_options();
+        self.emulator.cpu().bus.gpu.display_options = options;
+    }
+
+    fn display_options_menu(&mut self, ui: &mut egui::Ui) {
+        let name = self.game_name();
+        let overridden = name
+            .as_ref()
+            .is_some_and(|name| self.config.game_display.contains_key(name));
+        let mut per_game = overridden;
+        let mut options = self.display_options();
+        let mut changed = false;
+
+        ui.label("Display range");
+        for (range, label) in [
+            (DisplayRange::Precise, "As the game sets it"),
+            (DisplayRange::Standard, "Standard 4:3"),
+        ] {
+            changed |= ui.radio_value(&mut options.range, range, label).changed();
+        }
+        ui.label("Crop");
+        egui::Grid::new("Crop").show(ui, |ui| {
+            for (edge, crop) in [
+                ("Left", &mut options.crop_left),
+                ("Right", &mut options.crop_right),
+                ("Top", &mut options.crop_top),
+                ("Bottom", &mut options.crop_bottom),
+            ] {
+                ui.label(edge);
+                changed |= ui
+                    .add(egui::DragValue::new(crop).range(0..=64).suffix(" px"))
+                    .changed();
+                ui.end_row();
+            }
+        });
+        if let Some(name) = &name {
+            changed |= ui
+                .checkbox(&mut per_game, format!("Only for {name}"))
+                .changed();
+        }
+
+        if changed {
+            match name {
+                Some(name) if per_game => {
+                    self.config.game_display.insert(name, options);
+                }
+                // Unticked, so the game goes back to the defaults
+                Some(name) if overridden => {
+                    self.config.game_display.remove(&name);
+                }
+                _ => self.config.display = options,
+            }
+            self.apply_display_options();
+            self.save_config();
+        }
+    }
+
+    fn breakpoint_window(&mut self, ctx: &egui::Context) {
+        let key = self.game_key();
+        let list = self.config.breakpoints.entry(key.clone()).or_default();
+        let changed = self.breakpoint_window.show(ctx, list, self.breakpoint_hit);
+        if list.is_empty() {
+            self.config.breakpoints.remove(&key);
+        }
+        if changed {
+            self.apply_breakpoints();
+            self.save_config();
+        }
+    }
+
+    fn watchpoint_window(&mut self, ctx: &egui::Context) {
+        let key = self.game_key();
+        let list = self.config.watchpoints.entry(key.clone()).or_default();
+        let changed =
+            self.watchpoint_window
+                .show(ctx, list, &mut self.config.watch_dma, self.watch_hit);
+        if list.is_empty() {
+            self.config.watchpoints.remove(&key);
+        }
+        if changed {
+            self.apply_watchpoints();
+            self.save_config();
+        }
+    }
+
+    fn cheat_window(&mut self, ctx: &egui::Context) {
+        let key = self.game_key();
+        let list = self.config.cheats.entry(key.clone()).or_default();
+        let changed = self.cheat_window.show(ctx, list);
+        if list.is_empty() {
+            self.config.cheats.remove(&key);
+        }
+        if changed {
+            self.apply_cheats();
+            self.save_config();
+        }
+    }
+
+    fn disassembly_viewer(&mut self, ctx: &egui::Context) {
+        let key = self.game_key();
+        let list = self
+            .config
+            .breakpoints
+            .get(&key)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let action = self
+            .disassembly_viewer
+            .show(ctx, &self.emulator.cpu(), list);
+        match action {
+            Some(DisassemblyAction::ToggleBreakpoint(addr)) => {
+                let list = self.config.breakpoints.entry(key.clone()).or_default();
+                breakpoints::toggle(list, addr);
+                if list.is_empty() {
+                    self.config.breakpoints.remove(&key);
+                }
+                self.apply_breakpoints();
+                self.save_config();
+            }
+            Some(DisassemblyAction::RunTo(addr)) => {
+                self.emulator.send(Command::RunTo(addr));
+                self.run_state = RunState::Running;
+            }
+            None => {}
+        }
+    }
+
+    fn state_path(&self, slot: usize) -> PathBuf {
+        slot_state_path(&self.game_key(), slot)
+    }
+
+    fn select_slot(&mut self, slot: usize) {
+        self.save_slot = slot;
+        self.notify(format!("Save slot {slot}"));
+    }
+
+    // The latest frame shrunk to be shown with a save state
+    fn thumbnail(&self) -> Option<Vec<u8>> {
+        let Frame {
+            pixels,
+            width,
+            height,
+        } = &self.frame;
+        (*width > 0).then(|| screenshot::thumbnail_png([*width, *height], pixels))
+    }
+
+    // Read again whenever the slot is saved over
+    fn slot_thumbnail(
+        &mut self,
+        ctx: &egui::Context,
+        path: &Path,
+        saved: SystemTime,
+    ) -> Option<egui::TextureHandle> {
+        if let Some((time, texture)) = self.thumbnails.get(path)
+            && *time == saved
+        {
+            return texture.clone();
+        }
+        let texture = fs::read(path).ok().and_then(|state| {
+            let image = screenshot::read_png(state::thumbnail(&state)?)?;
+            let name = format!("thumbnail {}", path.display());
+            Some(ctx.load_texture(name, image, egui::TextureOptions::LINEAR))
+        });
+        self.thumbnails
+            .insert(path.to_path_buf(), (saved, texture.clone()));
+        texture
+    }
+
+    fn save_state(&mut self, slot: usize) {
+        let path = self.state_path(slot);
+        let thumbnail = self.thumbnail();
+        let result = save_machine(&mut self.emulator.cpu(), &path, thumbnail.as_deref());
+        match result {
+            Ok(()) => self.notify(format!("Saved state to slot {slot}")),
+            Err(err) => self.notify(format!("Failed to save state to slot {slot}: {err}")),
+        }
+    }
+
+    fn load_state(&mut self, slot: usize) {
+        let path = self.state_path(slot);
+        self.load_state_file(&path, &format!("slot {slot}"), false);
+    }
+
+    // Named in the notifications, such as "slot 3". The worker replies once it is loaded
+    fn load_state_file(&mut self, path: &Path, name: &str, force: bool) {
+        match fs::read(path) {
+            Ok(state) => {
+                self.loading_state = Some((name.to_string(), path.to_path_buf()));
+                self.emulator.send(Command::LoadState {
+                    state,
+                    name: name.to_string(),
+                    force,
+                });
+            }
+            Err(err) => self.notify(format!("Failed to load {name}: {err}")),
+        }
+    }
+
+    // Saves the running game to its auto slot, as the memory cards are flushed when it ends
+    fn auto_save(&mut self) {
+        if !self.config.auto_save || !self.cpu_rom_loaded {
+            return;
+        }
+        let Some(path) = self
+            .game_select
+            .selected_game
+            .as_deref()
+            .and_then(auto_state_path)
+        else {
+            return;
+        };
+        let thumbnail = self.thumbnail();
+        let result = save_machine(&mut self.emulator.cpu(), &path, thumbnail.as_deref());
+        if let Err(err) = result {
+            event!(
+                target: "ps1_emulator::Frontend",
+                Level::WARN,
+                "Failed to write {}: {err}",
+                path.display()
+            );
+            self.notify(format!("Failed to auto save: {err}"));
+        }
+    }
+
+    fn set_run_state(&mut self, run_state: RunState) {
+        self.run_state = run_state;
+        self.background_paused = false;
+        self.emulator.send(Command::SetRunState(run_state));
+    }
+
+    // Pauses or resumes as the window loses or regains focus
+    fn follow_focus(&mut self, ctx: &egui::Context) {
+        let focused = ctx.input(|i| i.viewport().focused.unwrap_or(true));
+        if focused == self.focused {
+            return;
+        }
+        self.focused = focused;
+        if !focused
+            && self.config.background == Background::Pause
+            && self.run_state == RunState::Running
+        {
+            self.set_run_state(RunState::Paused);
+            self.background_paused = true;
+        } else if focused && self.background_paused {
+            self.set_run_state(RunState::Running);
+        }
+    }
+
+    // Runs one frame when paused, or pauses a running game
+    fn frame_advance(&mut self) {
+        self.set_run_state(self.run_state.frame_advance());
+        self.frame_advance_repeat = Some(Instant::now() + FRAME_ADVANCE_DELAY);
+    }
+
+    // Holding the frame advance hotkey runs a frame at a time, each once the last is shown
+    fn repeat_frame_advance(&mut self, ctx: &egui::Context) {
+        if !ctx.input(|i| self.config.hotkeys.held(Hotkey::FrameAdvance, i)) {
+            self.frame_advance_repeat = None;
+            return;
+        }
+        let Some(due) = self.frame_advance_repeat else {
+            return;
+        };
+        let now = Instant::now();
+        if now < due {
+            ctx.request_repaint_after(due - now);
+        } else if self.run_state == RunState::Paused {
+            self.set_run_state(RunState::StepFrame);
+            self.frame_advance_repeat = Some(now + FRAME_ADVANCE_REPEAT);
+        }
+    }
+
+    // Mouse motion and link cable traffic can't be taken back with the machine
+    fn run_ahead_available(&self) -> bool {
+        !self.port_devices.contains(&PortDevice::Mouse) && self.link_cable == LinkCable::None
+    }
+
+    fn run_ahead(&self) -> u32 {
+        if self.run_ahead_available() {
+            self.config.run_ahead
+        } else {
+            0
+        }
+    }
+
+    fn throttled(&self) -> bool {
+        !self.focused && self.config.background == Background::Throttle
+    }
+
+    // Results sent back by the worker since the last update
+    fn handle_events(&mut self) {
+        for event in self.emulator.events() {
+            match event {
+                emulator::Event::RunState(run_state) => {
+                    self.run_state = run_state;
+                    if run_state != RunState::Paused {
+                        self.breakpoint_hit = None;
+                        self.watch_hit = None;
+                    }
+                }
+                emulator::Event::BreakpointHit(pc) => {
+                    self.breakpoint_hit = Some(pc);
+                    self.register_viewer.open = true;
+                    self.disassembly_viewer.open = true;
+                    self.disassembly_viewer.follow_pc = true;
+                    self.notify(format!("Breakpoint hit at {pc:08X}"));
+                }
+                emulator::Event::WatchpointHit(hit) => {
+                    self.watch_hit = Some(hit);
+                    self.register_viewer.open = true;
+                    self.disassembly_viewer.open = true;
+                    self.disassembly_viewer.follow_pc = true;
+                    self.notify(format!("Watchpoint hit: {}", hit.describe()));
+                }
+                emulator::Event::StateLoaded {
+                    name,
+                    result: Ok(()),
+                } => {
+                    self.breakpoint_hit = None;
+                    self.watch_hit = None;
+                    self.notify(format!("Loaded state from {name}"));
+                }
+                emulator::Event::StateLoaded {
+                    name,
+                    result: Err(err @ (StateError::WrongBios | StateError::WrongDisc)),
+                } if let Some((loading, path)) = self.loading_state.take()
+                    && loading == name =>
+                {
+                    self.mismatched_state = Some((name, path, err.to_string()));
+                }
+                emulator::Event::StateLoaded {
+                    name,
+                    result: Err(err),
+                } => self.notify(format!("Failed to load {name}: {err}")),
+                emulator::Event::Tty(output, time) => self.tty_console.push(&output, time),
+                emulator::Event::MovieStopped(Some(movie)) => self.save_movie(&movie),
+                emulator::Event::MovieStopped(None) => {
+                    self.notify("Movie playback ended".to_string());
+                }
+                emulator::Event::Stats(stats) => self.stats = stats,
+                emulator::Event::Rumble(rumble) => self.rumble = rumble,
+            }
+        }
+    }
+
+    // Movies are kept one per game
+    fn default_movie_path(&self) -> PathBuf {
+        Path::new(MOVIE_DIR).join(format!("{}.ps1m", self.game_key()))
+    }
+
+    // Starts from power on, or from the machine as it is now
+    fn record_movie(&mut self, from_power_on: bool) {
+        self.movie_path = Some(self.default_movie_path());
+        let mut cpu = self.emulator.cpu();
+        let state = (!from_power_on).then(|| {
+            let mut state = Vec::new();
+            cpu.save_state(&mut state, None);
+            state
+        });
+        let header = MovieHeader::new(&mut cpu, state);
+        drop(cpu);
+        let command = Command::StartMovie {
+            movie: Movie::new(header),
+            record: true,
+        };
+        if from_power_on {
+            self.reset_with(Some(command));
+        } else {
+            self.emulator.send(command);
+        }
+        self.notify("Recording movie".to_string());
+    }
+
+    fn play_movie(&mut self) {
+        let path = self.default_movie_path();
+        let movie = match fs::read(&path).and_then(|bytes| Movie::decode(&bytes)) {
+            Ok(movie) => movie,
+            Err(err) => {
+                self.notify(format!("Failed to load movie {}: {err}", path.display()));
+                return;
+            }
+        };
+        let current = MovieHeader::new(&mut self.emulator.cpu(), None);
+        match movie.header.mismatch(&current) {
+            Some(reason) => self.notify(format!("Playing movie {reason}, it may not match")),
+            None => self.notify("Playing movie".to_string()),
+        }
+        let from_power_on = movie.header.start_state.is_none();
+        let command = Command::StartMovie {
+            movie,
+            record: false,
+        };
+        if from_power_on {
+            self.reset_with(Some(command));
+        } else {
+            self.emulator.send(command);
+        }
+    }
+
+    fn save_movie(&mut self, movie: &Movie) {
+        let path = self
+            .movie_path
+            .take()
+            .unwrap_or_else(|| self.default_movie_path());
+        let result = fs::create_dir_all(MOVIE_DIR).and_then(|_| fs::write(&path, movie.encode()));
+        match result {
+            Ok(()) => self.notify(format!(
+                "Saved movie to {} with {} rerecords",
+                path.display(),
+                movie.header.rerecords
+            )),
+            Err(err) => self.notify(format!("Failed to save movie: {err}")),
+        }
+    }
+
+    fn movie_menu(&mut self, ui: &mut egui::Ui) {
+        if let Some(movie) = self.stats.movie {
+            ui.label(movie_status(movie));
+            if ui.button("Stop").clicked() {
+                self.emulator.send(Command::StopMovie);
+            }
+            return;
+        }
+        if ui.button("Record from power on").clicked() {
+            self.record_movie(true);
+        }
+        if ui.button("Record from here").clicked() {
+            self.record_movie(false);
+        }
+        let saved = self.default_movie_path().exists();
+        if ui.add_enabled(saved, egui::Button::new("Play")).clicked() {
+            self.play_movie();
+        }
+    }
+
+    // Each slot with its picture and the time it was saved. The selected slot is the one the
+    // hotkeys use
+    fn save_states_menu(&mut self, ui: &mut egui::Ui) {
+        let mut save = None;
+        let mut load = None;
+        egui::Grid::new("save_states").show(ui, |ui| {
+            for slot in 1..=SAVE_SLOTS {
+                ui.radio_value(&mut self.save_slot, slot, format!("Slot {slot}"));
+                let path = self.state_path(slot);
+                let saved = fs::metadata(&path).and_then(|meta| meta.modified());
+                let thumbnail = match saved {
+                    Ok(time) => self.slot_thumbnail(ui.ctx(), &path, time),
+                    Err(_) => None,
+                };
+                match thumbnail {
+                    Some(texture) => {
+                        ui.add(egui::Image::new((texture.id(), THUMBNAIL_SHOWN)))
+                            .on_hover_ui(|ui| {
+                                ui.image((texture.id(), texture.size_vec2()));
+                            });
+                    }
+                    None => {
+                        ui.allocate_exact_size(THUMBNAIL_SHOWN, egui::Sense::hover());
+                    }
+                }
+                match saved {
+                    Ok(time) => ui.label(screenshot::format_time(time)),
+                    Err(_) => ui.label("Empty"),
+                };
+                if ui.button("Save").clicked() {
+                    save = Some(slot);
+                }
+                if ui
+                    .add_enabled(saved.is_ok(), egui::Button::new("Load"))
+                    .clicked()
+                {
+                    load = Some(slot);
+                }
+                ui.end_row();
+            }
+        });
+        if let Some(slot) = save {
+            self.save_state(slot);
+        }
+        if let Some(slot) = load {
+            self.load_state(slot);
+        }
+        ui.separator();
+        if ui
+            .checkbox(&mut self.config.auto_save, "Auto save on quit")
+            .changed()
+        {
+            self.save_config();
+        }
+    }
+
+    fn save_input_config(&self) {
+        if let Err(err) = self.input_config.save(Path::new(INPUT_CONFIG_PATH)) {
+            event!(
+                target: "ps1_emulator::Frontend",
+                Level::WARN,
+                "Failed to save {INPUT_CONFIG_PATH}: {err}"
+            );
+        }
+    }
+
+    // Reports gamepads plugged in and out, and hands the bindings menu the next button pressed
+    fn poll_gamepads(&mut self) {
+        for event in self.gamepads.poll() {
+            match event {
+                PadEvent::Connected(pad, name) => {
+                    self.notify(format!("Gamepad {} connected: {name}", pad + 1))
+                }
+                PadEvent::Disconnected(pad, name) => {
+                    self.notify(format!("Gamepad {} disconnected: {name}", pad + 1))
+                }
+                PadEvent::Pressed(pad_button) => {
+                    if let Some(button) = self.rebinding_pad.take() {
+                        self.input_config
+                            .gamepad
+                            .keys
+                            .insert(button.to_string(), vec![pad_button]);
+                        self.save_input_config();
+                    }
+                }
+            }
+        }
+
+        // Gamepads rumble with the pad of the player they are given to
+        let mut speeds = Vec::new();
+        for (player, (port, _)) in self.players().into_iter().enumerate() {
+            if let Some(pad) = self.player_pads[player] {
+                if speeds.len() <= pad {
+                    speeds.resize(pad + 1, (0, 0));
+                }
+                let (small, large) = self.rumble[port];
+                speeds[pad] = (speeds[pad].0.max(small), speeds[pad].1.max(large));
+            }
+        }
+        self.gamepads.rumble(&speeds);
+    }
+
+    // Keys bound to each layout, and the gamepad buttons when gamepads can be used. Keys bound
+    // more than once are shown in red
+    fn bindings_menu(&mut self, ui: &mut egui::Ui) {
+        let game = self.game_name();
+        if let Some(game) = &game {
+            let mut per_game = self.input_config.games.contains_key(game);
+            if ui
+                .checkbox(&mut per_game, format!("Bindings for {game} only"))
+                .changed()
+            {
+                if per_game {
+                    let layouts = [
+                        self.input_config.port1.clone(),
+                        self.input_config.port2.clone(),
+                    ];
+                    self.input_config.games.insert(game.clone(), layouts);
+                } else {
+                    self.input_config.games.remove(game);
+                }
+                self.save_input_config();
+            }
+        }
+
+        let layouts = self.input_config.layouts(game.as_deref());
+        let conflicts = input::conflicts(layouts);
+        let gamepads = self.gamepads.available();
+        let mut rebind = None;
+        let mut rebind_pad = None;
+        egui::Grid::new("key_bindings").show(ui, |ui| {
+            ui.label("");
+            for layout in 0..KEYBOARD_LAYOUTS {
+                ui.label(format!("Keyboard {}", layout + 1));
+            }
+            if gamepads {
+                ui.label("Gamepad");
+            }
+            ui.end_row();
+            for (button, _) in BUTTON_NAMES {
+                ui.label(button);
+                for (layout, binding) in layouts.into_iter().enumerate() {
+                    let keys = binding.keys.get(button).cloned().unwrap_or_default();
+                    let mut text = if self.rebinding == Some((layout, button)) {
+                        RichText::new("Press a key")
+                    } else if keys.is_empty() {
+                        RichText::new("-")
+                    } else {
+                        RichText::new(keys.join(", "))
+                    };
+                    if keys.iter().any(|key| conflicts.contains(key)) {
+                        text = text.color(egui::Color32::RED);
+                    }
+                    if ui.button(text).clicked() {
+                        rebind = Some((layout, button));
+                    }
+                }
+                if gamepads {
+                    let pad_buttons = self.input_config.gamepad.keys.get(button);
+                    let text = if self.rebinding_pad == Some(button) {
+                        "Press a button".to_string()
+                    } else {
+                        match pad_buttons {
+                            Some(pad_buttons) if !pad_buttons.is_empty() => pad_buttons.join(", "),
+                            _ => "-".to_string(),
+                        }
+                    };
+                    if ui.button(text).clicked() {
+                        rebind_pad = Some(button);
+                    }
+                }
+                ui.end_row();
+            }
+        });
+        // One binding waits at a time
+        if rebind.is_some() {
+            self.rebinding = rebind;
+            self.rebinding_pad = None;
+        }
+        if rebind_pad.is_some() {
+            self.rebinding_pad = rebind_pad;
+            self.rebinding = None;
+        }
+        if gamepads
+            && ui
+                .add(
+                    egui::Slider::new(&mut self.input_config.stick_dead_zone, 0.1..=0.9)
+                        .text("Stick dead zone"),
+                )
+                .changed()
+        {
+            self.save_input_config();
+        }
+
+        if ui.button("Reset to defaults").clicked() {
+            let defaults = InputConfig::default();
+            let [port1, port2] = self.input_config.layouts_mut(game.as_deref());
+            *port1 = defaults.port1;
+            *port2 = defaults.port2;
+            self.input_config.gamepad = defaults.gamepad;
+            self.input_config.stick_dead_zone = defaults.stick_dead_zone;
+            self.rebinding = None;
+            self.rebinding_pad = None;
+            self.save_input_config();
+        }
+    }
+
+    // Key combination of each hotkey. Combinations bound more than once are shown in red
+    fn hotkeys_menu(&mut self, ui: &mut egui::Ui) {
+        let conflicts = self.config.hotkeys.conflicts();
+        let mut rebind = None;
+        egui::Grid::new("hotkeys").show(ui, |ui| {
+            for hotkey in Hotkey::ALL {
+                ui.label(hotkey.label());
+                let combo = self.config.hotkeys.keys.get(&hotkey);
+                let mut text = if self.rebinding_hotkey == Some(hotkey) {
+                    RichText::new("Press a key")
+                } else {
+                    RichText::new(combo.map_or("-", |combo| combo.as_str()))
+                };
+                if combo.is_some_and(|combo| conflicts.contains(combo)) {
+                    text = text.color(egui::Color32::RED);
+                }
+                if ui.button(text).clicked() {
+                    rebind = Some(hotkey);
+                }
+                ui.end_row();
+            }
+        });
+        if rebind.is_some() {
+            self.rebinding_hotkey = rebind;
+        }
+
+        if ui.button("Reset to defaults").clicked() {
+            self.config.hotkeys = Default::default();
+            self.rebinding_hotkey = None;
+            self.save_config();
+        }
+    }
+
+    // Shown over the display while paused, with the PC so steps can be followed
+    fn pause_window(&mut self, ctx: &egui::Context) {
+        egui::Window::new("Paused")
+            .anchor(egui::Align2::RIGHT_TOP, [-10.0, 40.0])
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    if ui.button("Resume").clicked() {
+                        self.set_run_state(RunState::Running);
+                    }
+                    if ui.button("Step instruction").clicked() {
+                        self.set_run_state(RunState::StepOne);
+                    }
+                    if ui.button("Step frame").clicked() {
+                        self.set_run_state(RunState::StepFrame);
+                    }
+                    if ui.button("Reset").clicked() {
+                        self.end_game(ctx, EndGame::Reset);
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    let pc = self.emulator.cpu().registers.program_counter;
+                    ui.monospace(format!("PC {pc:08X}"));
+                    ui.checkbox(&mut self.register_viewer.open, "Registers");
+                });
+
+                // What the next frame advance runs with
+                for (player, buttons) in self.held_buttons.iter().enumerate() {
+                    let held: Vec<&str> = BUTTON_NAMES
+                        .iter()
+                        .filter(|(_, button)| buttons.0 & button != 0)
+                        .map(|(name, _)| *name)
+                        .collect();
+                    let held = if held.is_empty() {
+                        "-".to_string()
+                    } else {
+                        held.join(" ")
+                    };
+                    ui.monospace(format!("P{} {held}", player + 1));
+                }
+            });
+    }
+
+    fn run_hotkey(&mut self, ctx: &egui::Context, hotkey: Hotkey) {
+        match hotkey {
+            Hotkey::Pause => self.set_run_state(self.run_state.toggle_pause()),
+            Hotkey::FrameAdvance => self.frame_advance(),
+            Hotkey::StopMovie => self.emulator.send(Command::StopMovie),
+            Hotkey::Reset => self.end_game(ctx, EndGame::Reset),
+            Hotkey::QuitToList => self.end_game(ctx, EndGame::QuitToList),
+            // Saving happens once the window agrees to close
+            Hotkey::Quit => ctx.send_viewport_cmd(egui::ViewportCommand::Close),
+            Hotkey::Fullscreen => self.set_fullscreen(ctx, !self.fullscreen),
+            Hotkey::SpeedOverlay => self.show_speed_overlay = !self.show_speed_overlay,
+            Hotkey::Turbo => self.turbo = !self.turbo,
+            Hotkey::Screenshot => self.screenshot(false),
+            Hotkey::CopyScreenshot => self.copy_screenshot(ctx),
+            Hotkey::VramScreenshot => self.screenshot(true),
+            Hotkey::SaveState => self.save_state(self.save_slot),
+            Hotkey::LoadState => self.load_state(self.save_slot),
+            Hotkey::PrevSlot => self.select_slot(cycle_slot(self.save_slot, false)),
+            Hotkey::NextSlot => self.select_slot(cycle_slot(self.save_slot, true)),
+            Hotkey::ReleaseMouse if self.mouse_captured => self.capture_mouse(ctx, false),
+            Hotkey::DumpVram => self.dump_vram(),
+            Hotkey::LoadVram => self.load_vram(),
+            Hotkey::PrintPc if self.run_state == RunState::Paused => {
+                let pc = self.emulator.cpu().registers.program_counter;
+                self.notify(format!("PC is 0x{pc:08X}"));
+            }
+            Hotkey::ReleaseMouse | Hotkey::PrintPc | Hotkey::FastForward => {}
+        }
+    }
+
+    // Asks first while a memory card is being written, since the save would be cut short
+    fn end_game(&mut self, ctx: &egui::Context, action: EndGame) {
+        if self.emulator.cpu().bus.sio0.unsaved_writes() {
+            self.confirm_end_game = Some(action);
+            return;
+        }
+        self.finish_end_game(ctx, action);
+    }
+
+    fn finish_end_game(&mut self, ctx: &egui::Context, action: EndGame) {
+        match action {
+            EndGame::Reset => self.reset(),
+            EndGame::QuitToList => self.quit_to_list(),
+            // The next update finds the close allowed and shuts down
+            EndGame::Exit => {
+                self.exit_confirmed = true;
+                ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+            }
+        }
+    }
+
+    fn confirm_end_game_window(&mut self, ctx: &egui::Context) {
+        let Some(action) = self.confirm_end_game else {
+            return;
+        };
+        let mut confirmed = None;
+        egui::Window::new(action.label())
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.label("A memory card is being written. Its save may be left incomplete.");
+                ui.horizontal(|ui| {
+                    if ui.button(action.label()).clicked() {
+                        confirmed = Some(true);
+                    }
+                    if ui.button("Cancel").clicked() {
+                        confirmed = Some(false);
+                    }
+                });
+            });
+        if let Some(confirmed) = confirmed {
+            self.confirm_end_game = None;
+            if confirmed {
+                self.finish_end_game(ctx, action);
+            }
+        }
+    }
+
+    // Powers the console off and on again with the same BIOS, disc and settings
+    fn reset(&mut self) {
+        self.reset_with(None);
+    }
+
+    // A movie from power on is started while the machine is off, so it starts with the boot
+    fn reset_with(&mut self, movie: Option<Command>) {
+        self.flush_memcards();
+        self.emulator.send(Command::PowerOff);
+        self.emulator.cpu().reset();
+        if let Some(movie) = movie {
+            self.emulator.send(movie);
         }
-        filepaths.sort();
-        Self {
-            filepaths,
-            selected_game: None,
+        self.tray_open = false;
+        // Executables are sideloaded while booting, so the boot runs again
+        if self
+            .game_select
+            .selected_game
+            .as_ref()
+            .is_some_and(|path| is_exe(path))
+        {
+            self.cpu_rom_loaded = false;
+        } else {
+            self.emulator.send(Command::PowerOn);
         }
     }
-}
 
-pub struct MyApp {
-    cpu: Cpu,
-    cpu_rom_loaded: bool,
-    play_bios: bool,
-    paused: bool,
-    tty_output: bool,
-    game_select: GameSelect,
-    screen_texture: egui::TextureHandle,
-    tracing_start_pc: Option<u32>,
-    logging_enabled: bool,
-    timing_baseline: Instant,
-    frame_count: usize,
-    fps: f32,
-}
+    // Back to the game list, with nothing selected so any game can be picked again
+    fn quit_to_list(&mut self) {
+        self.power_off();
+        self.game_select.selected_game = None;
+        self.play_bios = false;
+        self.set_run_state(RunState::Running);
+        self.breakpoint_hit = None;
+        self.watch_hit = None;
+        self.screen_texture
+            .set(egui::ColorImage::example(), egui::TextureOptions::NEAREST);
+    }
 
-impl MyApp {
-    pub fn new(
-        cc: &eframe::CreationContext<'_>,
-        folder: PathBuf,
-        tty_output: bool,
-        tracing_start_pc: Option<u32>,
-    ) -> Self {
-        Self {
-            cpu: Cpu::new(),
-            cpu_rom_loaded: false,
-            play_bios: false,
-            paused: false,
-            tty_output,
-            game_select: GameSelect::new(folder),
-            screen_texture: cc.egui_ctx.load_texture(
-                "Noise",
-                egui::ColorImage::example(),
-                egui::TextureOptions::NEAREST,
-            ),
-            tracing_start_pc,
-            logging_enabled: false,
-            timing_baseline: Instant::now(),
-            frame_count: 0,
-            fps: 0.0,
+    // Replaces the console with a new one that is off. The next update boots the selected game
+    fn power_off(&mut self) {
+        self.flush_memcards();
+        self.auto_save();
+        self.emulator.send(Command::PowerOff);
+        let mut cpu = self.emulator.cpu();
+        *cpu = Cpu::new();
+        for port in 0..2 {
+            cpu.bus
+                .sio0
+                .connect_controller(port, self.port_devices[port].connect());
+        }
+        // Reconnected after the old console let go of the address
+        match self.link_cable.connect() {
+            Ok(link) => cpu.bus.sio1.connect(link),
+            Err(err) => {
+                event!(
+                    target: "ps1_emulator::Frontend",
+                    Level::WARN,
+                    "Failed to connect link cable on {LINK_ADDR}: {err}"
+                );
+                self.link_cable = LinkCable::None;
+            }
+        }
+        cpu.bus
+            .gpu
+            .gp0
+            .set_resolution_scale(self.config.resolution_scale);
+        cpu.bus.gpu.gp0.overlay.set_view(self.debug_view);
+        drop(cpu);
+        self.tray_open = false;
+        self.cpu_rom_loaded = false;
+    }
+
+    // Runs once when the window closes
+    fn shutdown(&mut self, ctx: &egui::Context) {
+        // Stopped first so nothing is written to the memory cards after they are saved. A
+        // worker stuck in a frame still holds them, so they are left as they are
+        let stopped = self.emulator.stop();
+        // A fullscreen window would open at the size of the screen
+        if !self.fullscreen
+            && let Some(rect) = ctx.input(|i| i.viewport().inner_rect)
+        {
+            self.config.window_size = Some([rect.width(), rect.height()]);
+        }
+        if stopped {
+            self.flush_memcards();
+            self.auto_save();
+        } else {
+            event!(
+                target: "ps1_emulator::Frontend",
+                Level::WARN,
+                "The emulator did not stop in time, memory cards and the auto state were not saved"
+            );
+        }
+        self.save_config();
+        self.save_input_config();
+    }
+
+    fn memcard_path(&self, slot: usize) -> Option<PathBuf> {
+        let name = match self.memcard_slots[slot] {
+            CardSlot::Empty => return None,
+            CardSlot::Global => "global".to_string(),
+            CardSlot::PerGame => match &self.game_select.selected_game {
+                Some(game) => game.file_stem()?.to_string_lossy().into_owned(),
+                None => "bios".to_string(),
+            },
+        };
+        Some(Path::new(MEMCARD_DIR).join(format!("{name}_{}.mcd", slot + 1)))
+    }
+
+    fn insert_memcard(&mut self, slot: usize) {
+        let card = self
+            .memcard_path(slot)
+            .and_then(|path| match MemoryCard::open(&path) {
+                Ok(card) => Some(Box::new(card) as _),
+                Err(err) => {
+                    self.notify(format!(
+                        "Failed to load memory card {}: {err}",
+                        path.display()
+                    ));
+                    None
+                }
+            });
+        let result = self.emulator.cpu().bus.sio0.insert_memcard(slot, card);
+        if let Err(err) = result {
+            self.notify(format!("Failed to save memory card: {err}"));
+        }
+    }
+
+    fn flush_memcards(&mut self) {
+        let (written, result) = {
+            let sio0 = &mut self.emulator.cpu().bus.sio0;
+            (sio0.unsaved_writes(), sio0.flush())
+        };
+        match result {
+            Ok(()) if written => self.notify("Memory card written".to_string()),
+            Ok(()) => {}
+            Err(err) => self.notify(format!("Failed to save memory card: {err}")),
         }
+        self.last_memcard_flush = Instant::now();
+    }
+
+    fn open_tray(&mut self) {
+        self.emulator.cpu().bus.cdrom.open_lid();
+        self.tray_open = true;
+    }
+
+    fn close_tray(&mut self) {
+        let disc = self
+            .next_disc
+            .clone()
+            .and_then(|path| match Disc::open(&path) {
+                Ok(disc) => Some(disc),
+                Err(err) => {
+                    self.notify(format!("Failed to load disc: {err}"));
+                    None
+                }
+            });
+        self.emulator.cpu().bus.cdrom.close_lid(disc);
+        self.tray_open = false;
     }
 }
 
 impl eframe::App for MyApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        // Run CPU and associated steps
+        if ctx.input(|i| i.viewport().close_requested()) {
+            if self.exit_confirmed {
+                self.shutdown(ctx);
+            } else {
+                ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
+                self.end_game(ctx, EndGame::Exit);
+            }
+        }
+        self.drop_files(ctx);
+        self.handle_events();
+        self.poll_gamepads();
+
+        // Steer the worker and show what it sent back
         if self.cpu_rom_loaded {
-            while !self.paused && !self.cpu.bus.gpu.frame_is_ready {
-                if let Some(tracing_pc) = self.tracing_start_pc
-                    && !self.logging_enabled
-                    && tracing_pc == self.cpu.registers.program_counter
-                {
-                    println!("Begin logging...");
-                    self.logging_enabled = true;
-                    tracing_setup::init_tracing();
+            let game = self.game_name();
+            let layouts = ctx.input(|i| {
+                self.input_config
+                    .layouts(game.as_deref())
+                    .map(|layout| layout.buttons(i))
+            });
+            let mut input = Input::default();
+            self.held_buttons.clear();
+            for (player, (port, slot)) in self.players().into_iter().enumerate() {
+                let keys = self.player_keys[player]
+                    .map(|layout| layouts[layout])
+                    .unwrap_or_default();
+                // An analog pad takes the sticks as they are, otherwise the left stick
+                // presses the d-pad
+                let analog = self.port_devices[port] == PortDevice::Analog;
+                let pad = self.player_pads[player]
+                    .map(|pad| {
+                        let binding = &self.input_config.gamepad;
+                        let dead_zone = (!analog).then_some(self.input_config.stick_dead_zone);
+                        self.gamepads.buttons(pad, binding, dead_zone)
+                    })
+                    .unwrap_or_default();
+                // Sticks are centred without a gamepad
+                if analog {
+                    let axes = self.player_pads[player].and_then(|pad| self.gamepads.sticks(pad));
+                    input.sticks.push((port, slot, axes.unwrap_or([0x80; 4])));
+                }
+                let buttons = Buttons(keys.0 | pad.0);
+                input.buttons.push((port, slot, buttons));
+                self.held_buttons.push(buttons);
+            }
+
+            // Raw motion is summed over the frame. The mouse holds on to whatever a read
+            // cannot carry
+            let (dx, dy, left, right) = ctx.input(|i| {
+                if !self.mouse_captured {
+                    return (0.0, 0.0, false, false);
+                }
+                let (dx, dy) = i
+                    .events
+                    .iter()
+                    .fold((0.0, 0.0), |(dx, dy), event| match event {
+                        Event::MouseMoved(delta) => (dx + delta.x, dy + delta.y),
+                        _ => (dx, dy),
+                    });
+                (dx, dy, i.pointer.primary_down(), i.pointer.secondary_down())
+            });
+            for port in 0..2 {
+                if self.port_devices[port] == PortDevice::Mouse {
+                    input.mice.push((port, dx as i32, dy as i32, left, right));
                 }
+            }
+            self.emulator.send(Command::Input(input));
+
+            self.follow_focus(ctx);
+            self.fast_forward =
+                self.turbo || ctx.input(|i| self.config.hotkeys.held(Hotkey::FastForward, i));
+            self.emulator.send(Command::Pacing(Pacing {
+                fast_forward: self.fast_forward,
+                fast_forward_cap: self.config.fast_forward_cap,
+                // Muted output has nothing to keep in time with
+                audio_sync: self.config.audio_sync && !self.config.muted,
+                throttle: self.throttled(),
+                run_ahead: self.run_ahead(),
+            }));
 
-                self.cpu.step_instruction(self.tty_output);
+            // Saves are written out shortly after the game writes them
+            if self.last_memcard_flush.elapsed().as_secs() >= MEMCARD_FLUSH_SECS {
+                self.flush_memcards();
             }
 
             //user input
+            let mut hotkeys = Vec::new();
+            let mut rebound = None;
+            let mut rebound_hotkey = None;
+            let mut cancel_pad = false;
             ctx.input(|i| {
                 for event in &i.events {
                     match event {
+                        // The bindings menus take the next key press
                         Event::Key {
-                            key: egui::Key::Escape,
-                            ..
-                        } => std::process::exit(0),
+                            key, pressed: true, ..
+                        } if self.rebinding.is_some() => rebound = Some(*key),
                         Event::Key {
-                            key: egui::Key::P,
+                            key,
                             pressed: true,
+                            modifiers,
                             ..
-                        } => {
-                            self.paused = !self.paused;
+                        } if self.rebinding_hotkey.is_some() => {
+                            rebound_hotkey = Some((*key, *modifiers))
                         }
                         Event::Key {
-                            key: egui::Key::L,
+                            key: egui::Key::Escape,
                             pressed: true,
                             ..
-                        } if self.paused => {
-                            println!("PC is 0x{:08X}", self.cpu.registers.program_counter);
-                        }
-                        _ => {}
+                        } if self.rebinding_pad.is_some() => cancel_pad = true,
+                        _ => hotkeys.extend(self.config.hotkeys.triggered(event)),
                     }
                 }
             });
-
-            // Frame Timings
-            if self.frame_count == 0 {
-                // let frame_time = self.timing_baseline.elapsed().as_secs_f32();
-                // self.fps = frame_time;
-                self.frame_count = 1;
-                self.timing_baseline = Instant::now();
-            } else if self.frame_count == 5 {
-                let five_frame_time = self.timing_baseline.elapsed().as_secs_f32();
-                self.frame_count = 1;
-                self.timing_baseline = Instant::now();
-                if !self.paused {
-                    self.fps = 5.0 / five_frame_time;
-                }
+            for hotkey in hotkeys {
+                self.run_hotkey(ctx, hotkey);
+            }
+            self.repeat_frame_advance(ctx);
+            if let Some((key, modifiers)) = rebound_hotkey
+                && let Some(hotkey) = self.rebinding_hotkey.take()
+                && key != egui::Key::Escape
+            {
+                self.config
+                    .hotkeys
+                    .keys
+                    .insert(hotkey, hotkey::combo_name(key, modifiers));
+                self.save_config();
+            }
+            // Escape cancels rebinding
+            if cancel_pad {
+                self.rebinding_pad = None;
+            }
+            if let Some(key) = rebound
+                && let Some((layout, button)) = self.rebinding.take()
+                && key != egui::Key::Escape
+            {
+                self.input_config.layouts_mut(game.as_deref())[layout]
+                    .keys
+                    .insert(button.to_string(), vec![key.name().to_string()]);
+                self.save_input_config();
             }
 
-            self.frame_count += 1;
+            // Frame Timings
+            self.speed_meter.push(self.stats.frames, self.stats.cycles);
 
-            let vram_bytes = &self.cpu.bus.gpu.render_vram()[..];
-            let sized_texture = if self.cpu.bus.gpu.gp1.color_depth {
+            self.emulator.take_frame(&mut self.frame);
+            let image = if !self.show_full_vram {
+                // Nothing to show until the worker finishes its first frame
+                (self.frame.width > 0).then(|| {
+                    egui::ColorImage::new(
+                        [self.frame.width, self.frame.height],
+                        self.frame.pixels.clone(),
+                    )
+                })
+            } else {
+                let mut cpu = self.emulator.cpu();
+                cpu.bus.gpu.render_vram(&mut self.vram_buffer);
                 // VRAM in 24 bit mode.
-                self.screen_texture.set(
-                    egui::ColorImage::from_rgb([682, 512], vram_bytes),
-                    egui::TextureOptions::NEAREST,
-                );
+                let width = if cpu.bus.gpu.gp1.color_depth {
+                    682
+                } else {
+                    1024
+                };
+                Some(egui::ColorImage::from_rgb([width, 512], &self.vram_buffer))
+            };
 
-                egui::load::SizedTexture::new(self.screen_texture.id(), [682.0, 512.0])
-            } else {
-                self.screen_texture.set(
-                    egui::ColorImage::from_rgb([1024, 512], vram_bytes),
-                    egui::TextureOptions::NEAREST,
-                );
+            egui::CentralPanel::default().show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    let (fps, _, speed) = self.speed_meter.rates();
+                    ui.heading(RichText::new(format!(
+                        "FPS is {fps:.0} ({:.2} Hz), {speed:.0}% speed",
+                        self.stats.refresh_rate
+                    )));
+                    ui.checkbox(&mut self.show_full_vram, "Show full VRAM");
 
-                egui::load::SizedTexture::new(self.screen_texture.id(), [1024.0, 512.0])
-            };
+                    let prev_scale = self.config.resolution_scale;
+                    egui::ComboBox::from_label("Internal Resolution")
+                        .selected_text(format!("{}x", self.config.resolution_scale))
+                        .show_ui(ui, |ui| {
+                            for scale in [1, 2, 4] {
+                                ui.selectable_value(
+                                    &mut self.config.resolution_scale,
+                                    scale,
+                                    format!("{scale}x"),
+                                );
+                            }
+                        });
+                    if self.config.resolution_scale != prev_scale {
+                        self.emulator
+                            .cpu()
+                            .bus
+                            .gpu
+                            .gp0
+                            .set_resolution_scale(self.config.resolution_scale);
+                        self.save_config();
+                    }
 
-            self.cpu.bus.gpu.frame_is_ready = false;
+                    let prev_view = self.debug_view;
+                    egui::ComboBox::from_label("Debug View")
+                        .selected_text(format!("{:?}", self.debug_view))
+                        .show_ui(ui, |ui| {
+                            for view in [DebugView::Off, DebugView::Wireframe, DebugView::Overdraw]
+                            {
+                                ui.selectable_value(
+                                    &mut self.debug_view,
+                                    view,
+                                    format!("{view:?}"),
+                                );
+                            }
+                        });
+                    if self.debug_view != prev_view {
+                        self.emulator.cpu().bus.gpu.gp0.overlay.set_view(self.debug_view);
+                    }
 
-            egui::CentralPanel::default().show(ctx, |ui| {
-                ui.heading(RichText::new(format!("FPS is {}", self.fps)));
+                    ui.checkbox(&mut self.show_gpu_stats, "GPU Stats");
 
-                ui.add(
-                    egui::Image::new(sized_texture).fit_to_exact_size(egui::vec2(1024.0, 512.0)),
-                );
-            });
+                    let mut audio_changed = ui.checkbox(&mut self.config.muted, "Mute").changed();
+                    audio_changed |= ui
+                        .checkbox(&mut self.config.audio_sync, "Audio sync")
+                        .changed();
+                    audio_changed |= ui
+                        .add(egui::Slider::new(&mut self.config.volume, 0.0..=1.0).text("Volume"))
+                        .changed();
+                    if audio_changed {
+                        self.save_config();
+                    }
+                    let background_muted = !self.focused && self.config.mute_in_background;
+                    let volume = if self.config.muted || background_muted {
+                        0.0
+                    } else {
+                        self.config.volume
+                    };
+                    self.emulator.send(Command::Volume(volume));
 
-            ctx.request_repaint();
-        } else {
-            egui::CentralPanel::default().show(ctx, |ui| {
-                ctx.input(|i| {
-                    for event in &i.events {
-                        if let Event::Key {
-                            key: egui::Key::Escape,
-                            ..
-                        } = event
+                    ui.menu_button("Video", |ui| {
+                        let mut video_changed = false;
+                        ui.label("Aspect ratio");
+                        for aspect in [Aspect::Stretch, Aspect::FourThree, Aspect::Native] {
+                            video_changed |= ui
+                                .radio_value(&mut self.config.aspect, aspect, aspect.label())
+                                .changed();
+                        }
+                        ui.separator();
+                        ui.label("Filter");
+                        for filter in [Filter::Integer, Filter::Bilinear, Filter::Scanlines] {
+                            video_changed |= ui
+                                .radio_value(&mut self.config.filter, filter, filter.label())
+                                .changed();
+                        }
+                        if video_changed {
+                            self.save_config();
+                        }
+                        ui.separator();
+                        self.display_options_menu(ui);
+                    });
+
+                    ui.menu_button("View", |ui| {
+                        ui.checkbox(&mut self.show_speed_overlay, "Speed overlay");
+                        ui.checkbox(&mut self.turbo, "Turbo");
+                        let slider = egui::Slider::new(&mut self.config.fast_forward_cap, 0..=1000)
+                            .step_by(50.0)
+                            .text("Fast forward cap")
+                            .custom_formatter(|cap, _| match cap as u32 {
+                                0 => "Uncapped".to_string(),
+                                cap => format!("{cap}%"),
+                            });
+                        if ui.add(slider).changed() {
+                            self.save_config();
+                        }
+                        let available = self.run_ahead_available();
+                        let slider = egui::Slider::new(&mut self.config.run_ahead, 0..=MAX_RUN_AHEAD)
+                            .text("Run-ahead")
+                            .custom_formatter(|frames, _| match frames as u32 {
+                                0 => "Off".to_string(),
+                                1 => "1 frame".to_string(),
+                                frames => format!("{frames} frames"),
+                            });
+                        let response = ui
+                            .add_enabled(available, slider)
+                            .on_disabled_hover_text("Not available with a mouse or link cable");
+                        if response.changed() {
+                            self.save_config();
+                        }
+                        let mut fullscreen = self.fullscreen;
+                        if ui.checkbox(&mut fullscreen, "Fullscreen").changed() {
+                            self.set_fullscreen(ctx, fullscreen);
+                        }
+
+                        ui.separator();
+                        ui.label("In the background");
+                        let mut background_changed = false;
+                        for background in
+                            [Background::Run, Background::Pause, Background::Throttle]
                         {
-                            std::process::exit(0)
+                            background_changed |= ui
+                                .radio_value(
+                                    &mut self.config.background,
+                                    background,
+                                    background.label(),
+                                )
+                                .changed();
                         }
-                    }
+                        background_changed |= ui
+                            .checkbox(&mut self.config.mute_in_background, "Mute")
+                            .changed();
+                        if background_changed {
+                            self.save_config();
+                        }
+                    });
+
+                    ui.menu_button("Controllers", |ui| {
+                        for port in 0..2 {
+                            ui.label(format!("Port {}", port + 1));
+                            let prev_device = self.port_devices[port];
+                            for device in [
+                                PortDevice::None,
+                                PortDevice::Digital,
+                                PortDevice::Analog,
+                                PortDevice::Multitap,
+                                PortDevice::Mouse,
+                            ] {
+                                ui.radio_value(
+                                    &mut self.port_devices[port],
+                                    device,
+                                    device.label(),
+                                );
+                            }
+                            if self.port_devices[port] != prev_device {
+                                self.emulator
+                                    .cpu()
+                                    .bus
+                                    .sio0
+                                    .connect_controller(port, self.port_devices[port].connect());
+                            }
+                            // Switches the pad between analog and digital mode
+                            if self.port_devices[port] == PortDevice::Analog
+                                && ui.button("Analog button").clicked()
+                            {
+                                self.emulator.cpu().bus.sio0.press_analog(port);
+                            }
+                        }
+
+                        let mut captured = self.mouse_captured;
+                        if ui.checkbox(&mut captured, "Capture mouse").changed() {
+                            self.capture_mouse(ctx, captured);
+                        }
+
+                        ui.separator();
+                        let pad_names = self.gamepads.names();
+                        for (player, (port, slot)) in self.players().into_iter().enumerate() {
+                            let name = match self.port_devices[port] {
+                                PortDevice::Multitap => {
+                                    format!("Player {} (port {}{})", player + 1, port + 1, (b'A' + slot as u8) as char)
+                                }
+                                _ => format!("Player {} (port {})", player + 1, port + 1),
+                            };
+                            ui.menu_button(name, |ui| {
+                                ui.radio_value(&mut self.player_keys[player], None, "Unbound");
+                                for layout in 0..KEYBOARD_LAYOUTS {
+                                    ui.radio_value(
+                                        &mut self.player_keys[player],
+                                        Some(layout),
+                                        format!("Keyboard {}", layout + 1),
+                                    );
+                                }
+                                if self.gamepads.available() {
+                                    ui.separator();
+                                    ui.radio_value(&mut self.player_pads[player], None, "No gamepad");
+                                    // Numbers bound but not plugged in stay listed
+                                    let bound = self.player_pads[player].map_or(0, |pad| pad + 1);
+                                    for pad in 0..pad_names.len().max(bound) {
+                                        let label = match pad_names.get(pad) {
+                                            Some(Some(name)) => format!("Gamepad {}: {name}", pad + 1),
+                                            _ => format!("Gamepad {} (unplugged)", pad + 1),
+                                        };
+                                        ui.radio_value(&mut self.player_pads[player], Some(pad), label);
+                                    }
+                                }
+                            });
+                        }
+
+                        ui.separator();
+                        ui.menu_button("Key bindings", |ui| self.bindings_menu(ui));
+                    });
+
+                    ui.menu_button("Hotkeys", |ui| self.hotkeys_menu(ui));
+
+                    ui.menu_button("Memory cards", |ui| {
+                        for slot in 0..2 {
+                            ui.label(format!("Slot {}", slot + 1));
+                            let prev_slot = self.memcard_slots[slot];
+                            for setting in [CardSlot::Empty, CardSlot::Global, CardSlot::PerGame] {
+                                ui.radio_value(
+                                    &mut self.memcard_slots[slot],
+                                    setting,
+                                    setting.label(),
+                                );
+                            }
+                            if self.memcard_slots[slot] != prev_slot {
+                                self.insert_memcard(slot);
+                            }
+                        }
+                    });
+
+                    ui.menu_button("Link cable", |ui| {
+                        let prev_cable = self.link_cable;
+                        for cable in [LinkCable::None, LinkCable::Loopback, LinkCable::Host, LinkCable::Join] {
+                            ui.radio_value(&mut self.link_cable, cable, cable.label());
+                        }
+                        if self.link_cable != prev_cable {
+                            let link = match self.link_cable.connect() {
+                                Ok(link) => link,
+                                Err(err) => {
+                                    self.notify(format!(
+                                        "Failed to connect link cable on {LINK_ADDR}: {err}"
+                                    ));
+                                    self.link_cable = LinkCable::None;
+                                    None
+                                }
+                            };
+                            self.emulator.cpu().bus.sio1.connect(link);
+                        }
+                    });
+
+                    ui.menu_button("Disc", |ui| {
+                        if !self.tray_open {
+                            ui.label(if self.emulator.cpu().bus.cdrom.disc_present() {
+                                "Disc inserted"
+                            } else {
+                                "No disc"
+                            });
+                            if ui.button("Open tray").clicked() {
+                                self.open_tray();
+                            }
+                            return;
+                        }
+
+                        for game in self.game_select.disc_images() {
+                            ui.radio_value(&mut self.next_disc, Some(game.path.clone()), &game.name);
+                        }
+                        ui.radio_value(&mut self.next_disc, None, "No disc");
+                        if ui.button("Close tray").clicked() {
+                            self.close_tray();
+                        }
+                    });
+
+                    ui.menu_button("Game", |ui| {
+                        if ui.button("Reset").clicked() {
+                            self.end_game(ctx, EndGame::Reset);
+                        }
+                        if ui.button("Quit to game list").clicked() {
+                            self.end_game(ctx, EndGame::QuitToList);
+                        }
+                        ui.checkbox(&mut self.cheat_window.open, "Cheats");
+                    });
+
+                    ui.menu_button("Save states", |ui| self.save_states_menu(ui));
+                    ui.menu_button("Movie", |ui| self.movie_menu(ui));
+
+                    ui.menu_button("Screenshot", |ui| {
+                        if ui.button("Save").clicked() {
+                            self.screenshot(false);
+                        }
+                        if ui.button("Copy to clipboard").clicked() {
+                            self.copy_screenshot(ctx);
+                        }
+                        if ui.button("Save full VRAM").clicked() {
+                            self.screenshot(true);
+                        }
+                    });
+
+                    ui.menu_button("Debug", |ui| {
+                        ui.checkbox(&mut self.vram_viewer.open, "VRAM viewer");
+                        ui.checkbox(&mut self.register_viewer.open, "Register viewer");
+                        ui.checkbox(&mut self.memory_viewer.open, "Memory viewer");
+                        ui.checkbox(&mut self.breakpoint_window.open, "Breakpoints");
+                        ui.checkbox(&mut self.watchpoint_window.open, "Watchpoints");
+                        ui.checkbox(&mut self.disassembly_viewer.open, "Disassembly");
+                        ui.checkbox(&mut self.tty_console.open, "TTY console");
+                        ui.menu_button("Log BIOS calls", |ui| {
+                            let mut changed = false;
+                            for level in [BiosLog::Off, BiosLog::Calls, BiosLog::All] {
+                                changed |= ui
+                                    .radio_value(&mut self.config.bios_log, level, level.label())
+                                    .changed();
+                            }
+                            if changed {
+                                self.emulator.cpu().set_bios_log(self.config.bios_log);
+                                self.save_config();
+                            }
+                        });
+                        if ui.button("Dump VRAM").clicked() {
+                            self.dump_vram();
+                        }
+                        if ui.button("Load VRAM").clicked() {
+                            self.load_vram();
+                        }
+                    });
                 });
 
-                if self.play_bios || self.game_select.selected_game.is_some() {
-                    // Load BIOS from folder
-                    let bios_path = match fs::read_dir("bios/").unwrap().next() {
-                        Some(Ok(path)) => path.path(),
-                        _ => panic!("BIOS not found"),
-                    };
+                if self.show_gpu_stats {
+                    let stats = self.emulator.cpu().bus.gpu.frame_stats();
+                    ui.label(format!(
+                        "GP0 words: {} | Triangles: {} | Quads: {} | Lines: {} | Rects: {} | Fills: {} | Blits: {} | Pixels: {}",
+                        stats.words,
+                        stats.triangles,
+                        stats.quads,
+                        stats.lines,
+                        stats.rects,
+                        stats.fills,
+                        stats.blits,
+                        stats.pixels
+                    ));
+                    if let Some(depth) = self.stats.audio_buffered {
+                        ui.label(format!(
+                            "Audio buffer: {} frames ({:.1} ms)",
+                            depth,
+                            depth as f32 * 1000.0 / 44100.0
+                        ));
+                    }
+                }
+
+                self.draw_display(ui, image);
+            });
+
+            if self.run_state == RunState::Paused {
+                self.pause_window(ctx);
+            }
+            if self.vram_viewer.open {
+                self.vram_viewer.show(ctx, &self.emulator.cpu().bus.gpu);
+            }
+            if self.register_viewer.open {
+                let paused = self.run_state == RunState::Paused;
+                self.register_viewer
+                    .show(ctx, &mut self.emulator.cpu(), paused);
+            }
+            if self.memory_viewer.open {
+                let paused = self.run_state == RunState::Paused;
+                self.memory_viewer
+                    .show(ctx, &mut self.emulator.cpu(), paused);
+            }
+            if self.breakpoint_window.open {
+                self.breakpoint_window(ctx);
+            }
+            if self.watchpoint_window.open {
+                self.watchpoint_window(ctx);
+            }
+            if self.cheat_window.open {
+                self.cheat_window(ctx);
+            }
+            if self.disassembly_viewer.open {
+                self.disassembly_viewer(ctx);
+            }
+            if self.tty_console.open {
+                self.tty_console.show(ctx);
+            }
+            self.dropped_state_window(ctx);
+            self.mismatched_state_window(ctx);
+            self.confirm_end_game_window(ctx);
+        } else {
+            egui::CentralPanel::default().show(ctx, |ui| {
+                // Only the hotkeys that don't need a running console
+                let hotkeys: Vec<Hotkey> = ctx.input(|i| {
+                    i.events
+                        .iter()
+                        .filter_map(|event| self.config.hotkeys.triggered(event))
+                        .collect()
+                });
+                for hotkey in hotkeys {
+                    if matches!(hotkey, Hotkey::Quit | Hotkey::Fullscreen) {
+                        self.run_hotkey(ctx, hotkey);
+                    }
+                }
 
-                    let bios = fs::read(bios_path).unwrap();
+                let start = self.play_bios || self.game_select.selected_game.is_some();
+                // The BIOS alone is played when no game is selected
+                let game = match &self.game_select.selected_game {
+                    Some(path) if start && self.bios.is_some() => match open_game(path) {
+                        Ok(game) => Some(game),
+                        Err(err) => {
+                            self.game_error = Some(err);
+                            self.game_select.selected_game = None;
+                            self.continue_game = None;
+                            return;
+                        }
+                    },
+                    _ => None,
+                };
 
-                    // Load BIOS
-                    println!("BIOS size is {:08X}", bios.len());
-                    self.cpu.load_bios(&bios);
+                if start && let Some(bios) = &self.bios {
+                    event!(
+                        target: "ps1_emulator::Frontend",
+                        Level::INFO,
+                        "BIOS: {} ({})",
+                        bios.version,
+                        bios.date
+                    );
+                    let mut cpu = self.emulator.cpu();
+                    cpu.load_bios(&bios.image);
+                    // Before an EXE is sideloaded, so the BIOS boot is logged too
+                    cpu.set_bios_log(self.config.bios_log);
 
-                    if let Some(game) = &self.game_select.selected_game {
-                        // Load exe
-                        let exe = fs::read(game).unwrap();
-                        println!("Exe size (including header): {:08X}", exe.len());
+                    match game {
+                        // Insert disc and let the BIOS boot it
+                        Some(Game::Disc(disc)) => {
+                            event!(
+                                target: "ps1_emulator::Frontend",
+                                Level::INFO,
+                                "Disc loaded with {} track(s)",
+                                disc.tracks().len()
+                            );
+                            cpu.bus.cdrom.insert_disc(disc);
+                        }
+                        Some(Game::Exe(exe)) => {
+                            event!(
+                                target: "ps1_emulator::Frontend",
+                                Level::INFO,
+                                "Exe size (including header): {:08X}",
+                                exe.len()
+                            );
 
-                        // Runs CPU until exe can be loaded
-                        self.cpu.sideload_exe(&exe, self.tty_output);
+                            // Runs CPU until exe can be loaded
+                            if let Err(err) = cpu.sideload_exe(&exe, self.tty_output) {
+                                event!(
+                                    target: "ps1_emulator::Frontend",
+                                    Level::WARN,
+                                    "Failed to sideload exe: {err}"
+                                );
+                            }
+                        }
+                        None => {}
                     }
+                    drop(cpu);
 
+                    self.game_error = None;
+                    self.insert_memcard(0);
+                    self.insert_memcard(1);
+                    self.apply_breakpoints();
+                    self.apply_watchpoints();
+                    self.apply_display_options();
+                    self.apply_cheats();
+                    if let Some(path) = self.game_select.selected_game.clone() {
+                        self.config.played(&path);
+                        self.save_config();
+                    }
+                    // Frames left over from the last game are dropped
+                    self.emulator.take_frame(&mut self.frame);
+                    self.frame = Frame::default();
+                    self.emulator.send(Command::PowerOn);
                     self.cpu_rom_loaded = true;
+                    // Loaded over the boot, refused like any state if the disc has changed
+                    if let Some(game) = self.continue_game.take()
+                        && self.game_select.selected_game.as_ref() == Some(&game)
+                        && let Some(path) = auto_state_path(&game)
+                    {
+                        self.load_state_file(&path, "auto save", false);
+                    }
                 } else {
-                    // Offer game selection option
-                    egui::ComboBox::from_label("Select a Game: ").show_ui(ui, |ui| {
-                        for file in &self.game_select.filepaths {
-                            ui.selectable_value(
-                                &mut self.game_select.selected_game,
-                                Some(file.clone()),
-                                file.to_string_lossy(),
-                            );
-                        }
-                    });
+                    if start {
+                        ui.heading(
+                            RichText::new("A valid BIOS is needed to start")
+                                .color(egui::Color32::RED),
+                        );
+                    }
+                    self.bios_panel(ui);
+                    if let Some(err) = &self.game_error {
+                        ui.label(RichText::new(err).color(egui::Color32::RED));
+                    }
+
+                    self.recent_games(ui);
+
+                    self.game_list(ui);
 
                     ui.checkbox(&mut self.play_bios, "Play BIOS");
                 }
             });
         };
+
+        self.update_title(ctx);
+        self.notifications.show(ctx);
+
+        // Files dragged over the window dim it until they are dropped
+        if ctx.input(|i| !i.raw.hovered_files.is_empty()) {
+            let layer = egui::LayerId::new(egui::Order::Foreground, egui::Id::new("drop_target"));
+            let painter = ctx.layer_painter(layer);
+            let rect = ctx.content_rect();
+            painter.rect_filled(rect, 0.0, egui::Color32::from_black_alpha(160));
+            painter.text(
+                rect.center(),
+                egui::Align2::CENTER_CENTER,
+                "Drop a game, BIOS or save state to load it",
+                egui::FontId::proportional(24.0),
+                egui::Color32::WHITE,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    // Cpu is too big for the default test thread stack
+    fn with_big_stack(f: impl FnOnce() + Send + 'static) {
+        thread::Builder::new()
+            .stack_size(64 << 20)
+            .spawn(f)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn slots_are_named_per_game_and_cycle() {
+        assert_eq!(
+            slot_state_path("Crash Bandicoot", 3),
+            Path::new("savestates/Crash Bandicoot/slot3.sav")
+        );
+        assert_eq!(
+            auto_state_path(Path::new("games/Crash Bandicoot.cue")).unwrap(),
+            Path::new("savestates/Crash Bandicoot/auto.sav")
+        );
+
+        let forward: Vec<_> = (1..=SAVE_SLOTS)
+            .map(|slot| cycle_slot(slot, true))
+            .collect();
+        assert_eq!(forward, [2, 3, 4, 5, 6, 7, 8, 9, 10, 1]);
+        let back: Vec<_> = (1..=SAVE_SLOTS)
+            .map(|slot| cycle_slot(slot, false))
+            .collect();
+        assert_eq!(back, [10, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn slot_files_load_back_the_saved_machine() {
+        with_big_stack(|| {
+            let dir =
+                std::env::temp_dir().join(format!("ps1_emulator_slots_{}", std::process::id()));
+            let path = dir.join(slot_state_path("bios", 4));
+
+            let mut cpu = Cpu::new();
+            cpu.bus.ram[..4].copy_from_slice(&0x1234_5678u32.to_le_bytes());
+            cpu.registers.registers[8] = 0xDEAD_BEEF;
+            let mut state = Vec::new();
+            cpu.save_state(&mut state, None);
+            write_state(&path, &state).unwrap();
+
+            let mut loaded = Cpu::new();
+            loaded.load_state(&fs::read(&path).unwrap(), false).unwrap();
+            let (mut before, mut after) = (Vec::new(), Vec::new());
+            cpu.snapshot(&mut before);
+            loaded.snapshot(&mut after);
+            assert!(before == after);
+
+            // Broken files are errors the frontend shows, and leave the machine alone
+            state.truncate(state.len() / 2);
+            write_state(&path, &state).unwrap();
+            assert!(loaded.load_state(&fs::read(&path).unwrap(), false).is_err());
+            loaded.snapshot(&mut after);
+            assert!(before == after);
+
+            fs::remove_dir_all(&dir).unwrap();
+        });
+    }
+
+    #[test]
+    fn dropped_files_are_known_by_extension_and_contents() {
+        let dir = std::env::temp_dir().join(format!("ps1_emulator_drop_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut bios = vec![0; BIOS_SIZE];
+        bios[0x100..0x104].copy_from_slice(&0x19971216u32.to_le_bytes());
+        for (name, contents) in [
+            ("SCPH1001.BIN", bios),
+            // The size of a BIOS without being one, such as a short data track
+            ("blank.bin", vec![0; BIOS_SIZE]),
+            ("track.bin", vec![0; 2352]),
+        ] {
+            fs::write(dir.join(name), contents).unwrap();
+        }
+
+        let kind = |name: &str| match sniff_dropped(&dir.join(name)) {
+            Ok(DroppedFile::Game) => "game".to_string(),
+            Ok(DroppedFile::Bios(_)) => "bios".to_string(),
+            Ok(DroppedFile::State) => "state".to_string(),
+            Err(err) => err,
+        };
+        assert_eq!(kind("SCPH1001.BIN"), "bios");
+        assert_eq!(kind("blank.bin"), "game");
+        assert_eq!(kind("track.bin"), "game");
+        // Only .bin files are opened, the rest are known by name even if missing
+        for name in [
+            "Crash.cue",
+            "Crash.ISO",
+            "Crash.chd",
+            "psxtest.exe",
+            "demo.PSEXE",
+        ] {
+            assert_eq!(kind(name), "game", "{name}");
+        }
+        assert_eq!(kind("slot1.sav"), "state");
+        assert_eq!(kind("SLOT1.SAV"), "state");
+        assert_eq!(
+            kind("readme.txt"),
+            "readme.txt is not a disc image, executable, BIOS or save state"
+        );
+        assert_eq!(
+            kind("noextension"),
+            "noextension is not a disc image, executable, BIOS or save state"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn game_folders_list_games_without_their_tracks() {
+        let dir = std::env::temp_dir().join(format!("ps1_emulator_games_{}", std::process::id()));
+        for (name, contents) in [
+            (
+                "Crash.cue",
+                "FILE \"Crash (Track 1).bin\" BINARY\n  TRACK 01 MODE2/2352\n\
+                 FILE \"Crash (Track 2).bin\" BINARY\n  TRACK 02 AUDIO\n",
+            ),
+            ("Crash (Track 1).bin", ""),
+            ("Crash (Track 2).bin", ""),
+            // A .bin without a cue sheet is a game of its own
+            ("ape escape.bin", ""),
+            ("readme.txt", ""),
+            ("cover.png", ""),
+            ("japan/Spyro.ISO", ""),
+            ("japan/Tekken.cue", "FILE \"Tekken.bin\" BINARY\n"),
+            ("japan/Tekken.bin", ""),
+            ("homebrew/tests/psxtest.exe", ""),
+            ("homebrew/Bandicoot.chd", ""),
+        ] {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+
+        let games = scan_games(&dir).unwrap();
+        let listed: Vec<_> = games
+            .iter()
+            .map(|game| {
+                let path = game.path.strip_prefix(&dir).unwrap();
+                (game.name.as_str(), path.to_str().unwrap())
+            })
+            .collect();
+        assert_eq!(
+            listed,
+            [
+                ("ape escape", "ape escape.bin"),
+                ("Bandicoot", "homebrew/Bandicoot.chd"),
+                ("Crash", "Crash.cue"),
+                ("psxtest", "homebrew/tests/psxtest.exe"),
+                ("Spyro", "japan/Spyro.ISO"),
+                ("Tekken", "japan/Tekken.cue"),
+            ]
+        );
+
+        let filtered: Vec<_> = filter_games(&games, "CRA").map(|game| &game.name).collect();
+        assert_eq!(filtered, ["Crash"]);
+        let filtered: Vec<_> = filter_games(&games, "e").map(|game| &game.name).collect();
+        assert_eq!(filtered, ["ape escape", "psxtest", "Tekken"]);
+        assert_eq!(filter_games(&games, "").count(), games.len());
+
+        fs::remove_dir_all(&dir).unwrap();
+        // A missing folder is an error to show, not a panic
+        assert!(scan_games(&dir).is_err());
+    }
+
+    #[test]
+    fn pictures_fit_the_window_in_each_aspect() {
+        let picture = egui::vec2(320.0, 240.0);
+        let size = |aspect, available: [f32; 2], square_pixels| {
+            display_size(aspect, available.into(), picture, square_pixels)
+        };
+        assert_eq!(
+            size(Aspect::Stretch, [900.0, 500.0], false),
+            egui::vec2(900.0, 500.0)
+        );
+        // Letterboxed or pillarboxed to 4:3, or to the picture's own shape for VRAM
+        assert_eq!(
+            size(Aspect::FourThree, [900.0, 600.0], false),
+            egui::vec2(800.0, 600.0)
+        );
+        assert_eq!(
+            size(Aspect::FourThree, [400.0, 600.0], false),
+            egui::vec2(400.0, 300.0)
+        );
+        assert_eq!(
+            display_size(
+                Aspect::FourThree,
+                egui::vec2(1024.0, 1024.0),
+                egui::vec2(1024.0, 512.0),
+                true
+            ),
+            egui::vec2(1024.0, 512.0)
+        );
+        // Whole multiples of the picture, fitted to the window once it is too small for one
+        assert_eq!(
+            size(Aspect::Native, [1000.0, 999.0], false),
+            egui::vec2(960.0, 720.0)
+        );
+        assert_eq!(
+            size(Aspect::Native, [319.0, 999.0], false),
+            egui::vec2(319.0, 239.25)
+        );
+    }
+
+    #[test]
+    fn integer_scaling_uses_whole_screen_pixels() {
+        let picture = egui::vec2(320.0, 240.0);
+        for pixels_per_point in [1.0, 1.25, 1.5, 2.0] {
+            for (width, height) in [
+                (801.0, 601.0),
+                (1023.0, 767.0),
+                (333.0, 999.0),
+                (1921.0, 241.0),
+                (641.0, 479.0),
+            ] {
+                let available =
+                    egui::Rect::from_min_size(egui::pos2(7.0, 23.0), egui::vec2(width, height));
+                let fitted = display_size(Aspect::FourThree, available.size(), picture, false);
+                let rect = integer_rect(available, fitted, picture, pixels_per_point);
+                let case = format!("{width}x{height} at {pixels_per_point}");
+
+                // A whole multiple of the picture's height in screen pixels, when one fits
+                let lines = rect.height() * pixels_per_point;
+                let scale = (fitted.y * pixels_per_point / 240.0).floor().max(1.0);
+                assert!(
+                    (lines - scale * 240.0).abs() < 0.01,
+                    "{case}: {lines} lines"
+                );
+                assert!((rect.aspect_ratio() - 4.0 / 3.0).abs() < 0.01, "{case}");
+
+                // On whole screen pixels, centered and inside the window
+                for edge in [rect.min.x, rect.min.y, rect.max.x, rect.max.y] {
+                    let pixels = edge * pixels_per_point;
+                    assert!(
+                        (pixels - pixels.round()).abs() < 0.01,
+                        "{case}: edge {edge}"
+                    );
+                }
+                assert!(
+                    (rect.center() - available.center()).length() <= 1.0,
+                    "{case}"
+                );
+                assert!(available.expand(0.5).contains_rect(rect), "{case}");
+            }
+        }
+
+        // Windows too small for the picture show it at the fitted size
+        let available = egui::Rect::from_min_size(egui::Pos2::ZERO, egui::vec2(201.0, 151.0));
+        let fitted = display_size(Aspect::FourThree, available.size(), picture, false);
+        let rect = integer_rect(available, fitted, picture, 1.0);
+        assert_eq!(rect.size(), egui::vec2(201.0, 151.0));
+    }
+
+    #[test]
+    fn title_shows_the_speed_measured_over_the_last_second() {
+        let start = Instant::now();
+        let mut meter = SpeedMeter::new();
+        assert_eq!(meter.rates(), (0.0, 0.0, 0.0));
+        // Updated 120 times a second with a frame every other update, at 90% of full speed
+        let cycles_per_update = (CPU_CLOCK as f64 * 0.9 / 120.0) as u64;
+        for i in 0..240u64 {
+            let now = start + Duration::from_secs_f64(i as f64 / 120.0);
+            meter.push_at(i / 2, i * cycles_per_update, now);
+        }
+        let (updates, fps, speed) = meter.rates();
+        assert!((updates - 120.0).abs() < 0.5, "{updates}");
+        assert!((fps - 60.0).abs() < 0.5, "{fps}");
+        assert!((speed - 90.0).abs() < 0.5, "{speed}");
+        assert_eq!(
+            running_title("Crash", false, fps, speed),
+            "Crash — PS1 Emulator — 60 fps / 90%"
+        );
+        assert_eq!(
+            running_title("Crash", true, fps, speed),
+            "Crash — PS1 Emulator — Paused"
+        );
+
+        // A reset starts the window over rather than counting backwards
+        meter.push_at(0, 0, start + Duration::from_secs(3));
+        assert_eq!(meter.rates(), (0.0, 0.0, 0.0));
+    }
+
+    // Machine with a BIOS that jumps to itself forever and a disc from testdata/cdrom
+    fn machine_with_disc(disc: &str) -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.bus.kernel_rom[..4].copy_from_slice(&0x0BF0_0000u32.to_le_bytes());
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata/cdrom")
+            .join(disc);
+        cpu.bus.cdrom.insert_disc(Disc::open(&path).unwrap());
+        cpu
+    }
+
+    // Loads the auto state the way Continue does, through the worker. Returns whether it loaded
+    fn continue_from(cpu: Cpu, path: &Path) -> (bool, Cpu) {
+        let mut emulator = Emulator::new(
+            cpu,
+            Box::new(audio::NullSink),
+            egui::Context::default(),
+            false,
+            None,
+        );
+        emulator.send(Command::LoadState {
+            state: fs::read(path).unwrap(),
+            name: "auto save".to_string(),
+            force: false,
+        });
+        let loaded = loop {
+            let loaded = emulator.events().into_iter().find_map(|event| match event {
+                emulator::Event::StateLoaded { result, .. } => Some(result.is_ok()),
+                _ => None,
+            });
+            if let Some(loaded) = loaded {
+                break loaded;
+            }
+            thread::sleep(Duration::from_millis(1));
+        };
+        assert!(emulator.stop());
+        let cpu = std::mem::replace(&mut *emulator.cpu(), Cpu::new());
+        (loaded, cpu)
+    }
+
+    #[test]
+    fn quitting_refreshes_the_auto_state_that_continue_loads() {
+        with_big_stack(|| {
+            let dir =
+                std::env::temp_dir().join(format!("ps1_emulator_auto_{}", std::process::id()));
+            let path = dir.join(auto_state_path(Path::new("roms/game.iso")).unwrap());
+
+            let mut cpu = machine_with_disc("game.iso");
+            cpu.registers.program_counter = 0x8001_0000;
+            cpu.bus.ram[0x1000..0x1004].copy_from_slice(b"QUIT");
+            save_machine(&mut cpu, &path, None).unwrap();
+            // Played again and quit later, the auto state is replaced
+            cpu.registers.program_counter = 0x8002_0000;
+            cpu.bus.ram[0x1000..0x1004].copy_from_slice(b"LAST");
+            save_machine(&mut cpu, &path, None).unwrap();
+
+            let (loaded, cpu) = continue_from(machine_with_disc("game.iso"), &path);
+            assert!(loaded);
+            assert_eq!(cpu.registers.program_counter, 0x8002_0000);
+            assert_eq!(&cpu.bus.ram[0x1000..0x1004], b"LAST");
+
+            // Continuing with another disc in the drive is refused and leaves the machine alone
+            let (loaded, cpu) = continue_from(machine_with_disc("mixed.cue"), &path);
+            assert!(!loaded);
+            assert_eq!(cpu.registers.program_counter, 0xBFC0_0000);
+            assert_eq!(&cpu.bus.ram[0x1000..0x1004], [0; 4]);
+
+            fs::remove_dir_all(&dir).unwrap();
+        });
+    }
+
+    #[test]
+    fn states_load_with_or_without_a_thumbnail() {
+        with_big_stack(|| {
+            let dir =
+                std::env::temp_dir().join(format!("ps1_emulator_thumbs_{}", std::process::id()));
+            let mut cpu = Cpu::new();
+            cpu.bus.ram[..4].copy_from_slice(b"SAVE");
+            let picture = vec![egui::Color32::from_rgb(200, 100, 50); 320 * 240];
+            let thumbnail = screenshot::thumbnail_png([320, 240], &picture);
+
+            for (slot, thumbnail) in [(1, Some(&thumbnail[..])), (2, None)] {
+                let path = dir.join(slot_state_path("bios", slot));
+                save_machine(&mut cpu, &path, thumbnail).unwrap();
+                let state = fs::read(&path).unwrap();
+                assert_eq!(state::thumbnail(&state), thumbnail);
+
+                let mut loaded = Cpu::new();
+                loaded.load_state(&state, false).unwrap();
+                assert_eq!(&loaded.bus.ram[..4], b"SAVE");
+            }
+            fs::remove_dir_all(&dir).unwrap();
+        });
     }
 }
@@ -0,0 +1,290 @@
+#[cfg(feature = "gamepad")]
+use gilrs::ff::{BaseEffect, BaseEffectType, Effect, EffectBuilder};
+#[cfg(feature = "gamepad")]
+use gilrs::{Axis, Button, EventType, GamepadId, Gilrs};
+#[cfg(feature = "gamepad")]
+use tracing::{Level, event};
+
+#[cfg(feature = "gamepad")]
+use crate::input::BUTTON_NAMES;
+use crate::input::PadBinding;
+use crate::pad::Buttons;
+
+// Host gamepad buttons by the name bindings use
+#[cfg(feature = "gamepad")]
+const BUTTONS: [(&str, Button); 19] = [
+    ("South", Button::South),
+    ("East", Button::East),
+    ("North", Button::North),
+    ("West", Button::West),
+    ("C", Button::C),
+    ("Z", Button::Z),
+    ("LeftTrigger", Button::LeftTrigger),
+    ("LeftTrigger2", Button::LeftTrigger2),
+    ("RightTrigger", Button::RightTrigger),
+    ("RightTrigger2", Button::RightTrigger2),
+    ("Select", Button::Select),
+    ("Start", Button::Start),
+    ("Mode", Button::Mode),
+    ("LeftThumb", Button::LeftThumb),
+    ("RightThumb", Button::RightThumb),
+    ("DPadUp", Button::DPadUp),
+    ("DPadDown", Button::DPadDown),
+    ("DPadLeft", Button::DPadLeft),
+    ("DPadRight", Button::DPadRight),
+];
+
+// What happened to the gamepads since the last poll. Gamepads are numbered from 0 in the
+// order they were plugged in
+#[cfg_attr(not(feature = "gamepad"), allow(dead_code))]
+pub enum PadEvent {
+    Connected(usize, String),
+    Disconnected(usize, String),
+    // Name of a button pressed, for the bindings menu
+    Pressed(String),
+}
+
+// Host gamepads, read through gilrs when built with the gamepad feature. Without it there are
+// never any
+pub struct Gamepads {
+    #[cfg(feature = "gamepad")]
+    gilrs: Option<Gilrs>,
+    // Gamepad plugged in as each number. An unplugged gamepad leaves a gap, so the others keep
+    // their numbers and the next one plugged in fills it
+    #[cfg(feature = "gamepad")]
+    slots: Vec<Option<GamepadId>>,
+    // Motor speeds each gamepad rumbles at, with the effect playing them
+    #[cfg(feature = "gamepad")]
+    rumble: Vec<((u8, u8), Option<Effect>)>,
+}
+
+impl Gamepads {
+    // Gamepads already plugged in are numbered in the order the host lists them
+    pub fn new() -> Self {
+        #[cfg(feature = "gamepad")]
+        {
+            let gilrs = match Gilrs::new() {
+                Ok(gilrs) => Some(gilrs),
+                Err(err) => {
+                    event!(target: "ps1_emulator::Input", Level::WARN, "Gamepads disabled: {err}");
+                    None
+                }
+            };
+            let slots = gilrs
+                .iter()
+                .flat_map(|gilrs| gilrs.gamepads())
+                .map(|(id, _)| Some(id))
+                .collect();
+            Self {
+                gilrs,
+                slots,
+                rumble: Vec::new(),
+            }
+        }
+        #[cfg(not(feature = "gamepad"))]
+        Self {}
+    }
+
+    // Whether gamepads can be used at all
+    pub fn available(&self) -> bool {
+        #[cfg(feature = "gamepad")]
+        return self.gilrs.is_some();
+        #[cfg(not(feature = "gamepad"))]
+        false
+    }
+
+    // Takes the events gilrs has queued, which also brings the state buttons reads up to date
+    pub fn poll(&mut self) -> Vec<PadEvent> {
+        #[cfg(feature = "gamepad")]
+        {
+            let mut events = Vec::new();
+            let Some(gilrs) = &mut self.gilrs else {
+                return events;
+            };
+            while let Some(event) = gilrs.next_event() {
+                let name = || gilrs.gamepad(event.id).name().to_string();
+                match event.event {
+                    EventType::Connected if !self.slots.contains(&Some(event.id)) => {
+                        let slot = match self.slots.iter().position(Option::is_none) {
+                            Some(slot) => slot,
+                            None => {
+                                self.slots.push(None);
+                                self.slots.len() - 1
+                            }
+                        };
+                        self.slots[slot] = Some(event.id);
+                        events.push(PadEvent::Connected(slot, name()));
+                    }
+                    EventType::Disconnected => {
+                        if let Some(slot) = self.slots.iter().position(|&id| id == Some(event.id)) {
+                            self.slots[slot] = None;
+                            if let Some(rumble) = self.rumble.get_mut(slot) {
+                                *rumble = Default::default();
+                            }
+                            events.push(PadEvent::Disconnected(slot, name()));
+                        }
+                    }
+                    EventType::ButtonPressed(button, _) => {
+                        if let Some((name, _)) = BUTTONS.iter().find(|(_, b)| *b == button) {
+                            events.push(PadEvent::Pressed(name.to_string()));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            events
+        }
+        #[cfg(not(feature = "gamepad"))]
+        Vec::new()
+    }
+
+    // Rumbles the gamepads by number with the small and large motor speeds of a pad. Gamepads
+    // left out stop
+    #[cfg_attr(not(feature = "gamepad"), allow(unused_variables))]
+    pub fn rumble(&mut self, speeds: &[(u8, u8)]) {
+        #[cfg(feature = "gamepad")]
+        {
+            let Some(gilrs) = &mut self.gilrs else {
+                return;
+            };
+            self.rumble.resize_with(self.slots.len(), Default::default);
+            for (slot, (playing, effect)) in self.rumble.iter_mut().enumerate() {
+                let speed = speeds.get(slot).copied().unwrap_or_default();
+                if speed == *playing {
+                    continue;
+                }
+                *playing = speed;
+                // Dropping the effect stops it
+                *effect = None;
+                let Some(id) = self.slots[slot] else {
+                    continue;
+                };
+                if speed == (0, 0) || !gilrs.gamepad(id).is_ff_supported() {
+                    continue;
+                }
+                // The small motor of a pad is the weak one, running flat out when on
+                let (small, large) = speed;
+                let result = EffectBuilder::new()
+                    .add_effect(BaseEffect {
+                        kind: BaseEffectType::Weak {
+                            magnitude: small as u16 * 0x101,
+                        },
+                        ..Default::default()
+                    })
+                    .add_effect(BaseEffect {
+                        kind: BaseEffectType::Strong {
+                            magnitude: large as u16 * 0x101,
+                        },
+                        ..Default::default()
+                    })
+                    .gamepads(&[id])
+                    .finish(gilrs)
+                    .and_then(|new| new.play().map(|()| new));
+                match result {
+                    Ok(new) => *effect = Some(new),
+                    Err(err) => {
+                        event!(target: "ps1_emulator::Input", Level::WARN, "Gamepad {} can't rumble: {err}", slot + 1)
+                    }
+                }
+            }
+        }
+    }
+
+    // Names of the gamepads by number, None where one was unplugged
+    pub fn names(&self) -> Vec<Option<String>> {
+        #[cfg(feature = "gamepad")]
+        if let Some(gilrs) = &self.gilrs {
+            return self
+                .slots
+                .iter()
+                .map(|id| id.map(|id| gilrs.gamepad(id).name().to_string()))
+                .collect();
+        }
+        Vec::new()
+    }
+
+    // Buttons held on a gamepad given the bindings. With a dead zone, pushing the left stick
+    // past it presses the d-pad. Nothing is held on a gamepad that isn't plugged in
+    #[cfg_attr(not(feature = "gamepad"), allow(unused_variables))]
+    pub fn buttons(&self, slot: usize, binding: &PadBinding, dead_zone: Option<f32>) -> Buttons {
+        #[cfg(feature = "gamepad")]
+        if let Some(gilrs) = &self.gilrs
+            && let Some(&Some(id)) = self.slots.get(slot)
+        {
+            let mut buttons = Buttons::default();
+            let gamepad = gilrs.gamepad(id);
+            for (name, button) in BUTTON_NAMES {
+                let held = binding.keys.get(name).is_some_and(|names| {
+                    names.iter().any(|name| {
+                        BUTTONS
+                            .iter()
+                            .any(|&(bound, b)| bound == name && gamepad.is_pressed(b))
+                    })
+                });
+                buttons.set(button, held);
+            }
+            let Some(dead_zone) = dead_zone else {
+                return buttons;
+            };
+            let (x, y) = (
+                gamepad.value(Axis::LeftStickX),
+                gamepad.value(Axis::LeftStickY),
+            );
+            // Up is positive
+            for (held, button) in [
+                (x < -dead_zone, Buttons::LEFT),
+                (x > dead_zone, Buttons::RIGHT),
+                (y > dead_zone, Buttons::UP),
+                (y < -dead_zone, Buttons::DOWN),
+            ] {
+                if held {
+                    buttons.set(button, true);
+                }
+            }
+            return buttons;
+        }
+        Buttons::default()
+    }
+
+    // Stick positions of a gamepad as an analog pad sends them. None for a gamepad that isn't
+    // plugged in
+    #[cfg_attr(not(feature = "gamepad"), allow(unused_variables))]
+    pub fn sticks(&self, slot: usize) -> Option<[u8; 4]> {
+        #[cfg(feature = "gamepad")]
+        if let Some(gilrs) = &self.gilrs
+            && let Some(&Some(id)) = self.slots.get(slot)
+        {
+            let gamepad = gilrs.gamepad(id);
+            // Up is positive for gilrs but 0x00 for the pad
+            return Some([
+                stick_byte(gamepad.value(Axis::RightStickX)),
+                stick_byte(-gamepad.value(Axis::RightStickY)),
+                stick_byte(gamepad.value(Axis::LeftStickX)),
+                stick_byte(-gamepad.value(Axis::LeftStickY)),
+            ]);
+        }
+        None
+    }
+}
+
+// Stick position from -1.0 to 1.0 as 0x00 to 0xFF, centred on 0x80
+#[cfg_attr(not(feature = "gamepad"), allow(dead_code))]
+fn stick_byte(value: f32) -> u8 {
+    ((value + 1.0) * 127.5).round().clamp(0.0, 255.0) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stick_positions_span_the_pad_range() {
+        assert_eq!(stick_byte(-1.0), 0x00);
+        assert_eq!(stick_byte(0.0), 0x80);
+        assert_eq!(stick_byte(1.0), 0xFF);
+        assert_eq!(stick_byte(-0.5), 0x40);
+        // Past the ends gilrs may report
+        assert_eq!(stick_byte(1.5), 0xFF);
+        assert_eq!(stick_byte(-1.5), 0x00);
+    }
+}
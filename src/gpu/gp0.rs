@@ -1,33 +1,17 @@
-use std::{cmp, mem};
+use std::collections::VecDeque;
 
+use serde::{Deserialize, Serialize};
 use tracing::{Level, event};
 
-use super::convert_5bit_to_8bit;
-use crate::gpu::rasterize;
-
-const DITHER_TABLE: [[i8; 4]; 4] = [
-    [-4, 0, -3, 1],
-    [2, -2, 3, -1],
-    [-3, 1, -4, 0],
-    [3, -1, 2, -2],
-];
-
-enum SemiTransparency {
-    Blend,
-    Add,
-    Subtract,
-    QuarterBlend,
-}
+use crate::gpu::overlay::DebugOverlay;
+use crate::gpu::rasterizer::{
+    self, DrawSettings, HiresTarget, HiresVram, Line, NearestTarget, Rect, SemiTransparency,
+    TextureBits, Triangle, Vertex, Vram,
+};
 
-#[derive(Clone, Copy)]
-enum TextureBits {
-    Four,
-    Eight,
-    Fifteen,
-    Reserved,
-}
+const FIFO_SIZE: usize = 16;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 enum Commands {
     Rectangle,
     TexturedRectangle,
@@ -39,7 +23,7 @@ enum Commands {
     VramFill,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Serialize, Deserialize)]
 struct VramCopyFields {
     vram_x: u16,
     vram_y: u16,
@@ -49,6 +33,40 @@ struct VramCopyFields {
     current_col: u16,
 }
 
+impl VramCopyFields {
+    // Columns advance along x (wrapping at 1024) and rows along y (wrapping at 512)
+    fn current_addr(&self) -> usize {
+        let vram_row = ((self.vram_y + self.current_row) & 0x1FF) as usize;
+        let vram_col = ((self.vram_x + self.current_col) & 0x3FF) as usize;
+        1024 * vram_row + vram_col
+    }
+
+    // Move to the next pixel. Returns true once all width * height pixels have been visited
+    fn advance(&mut self) -> bool {
+        self.current_col += 1;
+        if self.current_col == self.width {
+            self.current_col = 0;
+            self.current_row += 1;
+        }
+
+        self.current_row == self.height
+    }
+}
+
+// Work submitted to GP0 during one frame
+#[derive(Default, Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct FrameStats {
+    pub words: u32,
+    pub triangles: u32,
+    pub quads: u32,
+    pub lines: u32,
+    pub rects: u32,
+    pub fills: u32,
+    pub blits: u32,
+    pub pixels: u32,
+}
+
+#[derive(Serialize, Deserialize)]
 enum Gp0State {
     WaitingForCommand,
     ReceivingParams {
@@ -70,11 +88,12 @@ enum Gp0State {
     },
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Gp0 {
     state: Gp0State,
-    pub vram: Box<[u8; 1048576]>, // 1024 x 512 grid of pixels (lo, hi)
+    pub vram: Vram,
     pub params: [u32; 16],
-    //pub command_buffer: VecDeque<u32>, // Holds at most 16 words (i.e 16 u32s)
+    pub command_buffer: VecDeque<u32>, // Holds at most 16 words (i.e 16 u32s)
     pub tex_page_x: u8,
     pub tex_page_y: bool,
     semitransparency: SemiTransparency,
@@ -91,15 +110,25 @@ pub struct Gp0 {
     pub mask_while_draw: bool,
     pub mask_before_draw: bool,
     pub vram_size_set: bool,
+    pub busy_cycles: u32, // GPU cycles until the current command finishes drawing
+    pub pixels_drawn: u32, // Running count of pixels written by draw commands
+    pub stats: FrameStats,
+    pub display_field: Option<usize>, // Parity of lines being displayed in 480i
+    // Both are host settings, carried over when a save state is loaded
+    #[serde(skip)]
+    pub hires: Option<HiresVram>, // Set when rendering at an increased internal resolution
+    #[serde(skip, default = "DebugOverlay::new")]
+    pub overlay: DebugOverlay,
+    pub irq_request: bool, // Set by GP0(0x1F) until the GPU picks it up
 }
 
 impl Gp0 {
     pub fn new() -> Self {
         Self {
             state: Gp0State::WaitingForCommand,
-            vram: Box::new([0; 1048576]),
+            vram: Vram::new(),
             params: [0; 16],
-            //command_buffer: VecDeque::with_capacity(16),
+            command_buffer: VecDeque::with_capacity(FIFO_SIZE),
             tex_page_x: 0,
             tex_page_y: false,
             semitransparency: SemiTransparency::Blend,
@@ -116,9 +145,43 @@ impl Gp0 {
             mask_while_draw: false,
             mask_before_draw: false,
             vram_size_set: false,
+            busy_cycles: 0,
+            pixels_drawn: 0,
+            stats: FrameStats::default(),
+            display_field: None,
+            hires: None,
+            overlay: DebugOverlay::new(),
+            irq_request: false,
         }
     }
 
+    // GP1(0x00) resets the GP0(0xE1..=0xE6) settings along with the command buffer
+    pub fn reset(&mut self) {
+        self.reset_command_buffer();
+        self.tex_page_x = 0;
+        self.tex_page_y = false;
+        self.semitransparency = SemiTransparency::Blend;
+        self.tex_page_colors = TextureBits::Four;
+        self.dither_enabled = false;
+        self.draw_to_display = false;
+        self.two_mb_mem = false;
+        self.rect_x_flip = false;
+        self.rect_y_flip = false;
+        self.texture_window = 0;
+        self.draw_area_top_left = (0, 0);
+        self.draw_area_bot_right = (0, 0);
+        self.draw_offset = (0, 0);
+        self.mask_while_draw = false;
+        self.mask_before_draw = false;
+    }
+
+    // Abandons any command or transfer in progress and empties the FIFO
+    pub fn reset_command_buffer(&mut self) {
+        self.command_buffer.clear();
+        self.busy_cycles = 0;
+        self.state = Gp0State::WaitingForCommand;
+    }
+
     pub fn vram_fill(&mut self, width: u32, height: u32, vram_x: u32, vram_y: u32, val: u16) {
         // if !self.draw_to_display && self.in_draw_area(vram_x, vram_y) {
         //     return
@@ -128,114 +191,81 @@ impl Gp0 {
             for x in 0..width {
                 let col = (vram_x + x) as usize % 1024;
                 let row = (vram_y + y) as usize % 512;
-                let vram_addr = 2 * (1024 * row + col);
-                self.vram[vram_addr] = val as u8;
-                self.vram[vram_addr + 1] = (val >> 8) as u8;
+                self.vram.write(1024 * row + col, val);
+                self.sync_hires(1024 * row + col);
             }
         }
     }
 
-    fn _in_draw_area(&mut self, vram_x: u32, vram_y: u32) -> bool {
-        (self.draw_area_top_left.0..self.draw_area_bot_right.0).contains(&vram_x)
-            && (self.draw_area_top_left.1..self.draw_area_bot_right.1).contains(&vram_y)
+    // Decode a vertex parameter (signed 11 bit x and y) and apply the drawing offset
+    fn vertex(&self, word: u32) -> (i32, i32) {
+        let x = sign_extend_11bit(word) as i32 + self.draw_offset.0 as i32;
+        let y = sign_extend_11bit(word >> 16) as i32 + self.draw_offset.1 as i32;
+        (x, y)
     }
 
-    fn write_5bit_color(&mut self, addr: usize, val: u16) {
-        if self.mask_before_draw && self.read_vram(addr) & 0x8000 > 0 {
-            return;
+    fn draw_settings(&self) -> DrawSettings {
+        DrawSettings {
+            semitransparency: self.semitransparency,
+            tex_page: (64 * self.tex_page_x as u16, 256 * self.tex_page_y as u16),
+            tex_page_colors: self.tex_page_colors,
+            texture_window: self.texture_window,
+            dither_enabled: self.dither_enabled,
+            draw_to_display: self.draw_to_display,
+            rect_x_flip: self.rect_x_flip,
+            rect_y_flip: self.rect_y_flip,
+            draw_area_top_left: self.draw_area_top_left,
+            draw_area_bot_right: self.draw_area_bot_right,
+            mask_while_draw: self.mask_while_draw,
+            mask_before_draw: self.mask_before_draw,
+            display_field: self.display_field,
         }
+    }
 
-        // if !self.draw_to_display && self.in_draw_area(addr as u32 % 1024, addr as u32 / 1024) {
-        //     return
-        // }
-
-        // If Mask While Draw is set, then mask_field is forced to true. Otherwise set to bit 15
-        let mask_bit = self.mask_while_draw || (val & 0x8000 > 0);
-
-        self.vram[2 * addr] = val as u8;
-        self.vram[2 * addr + 1] = ((mask_bit as u8) << 7) | (val >> 8) as u8;
+    fn copy_vram(&mut self, settings: &DrawSettings, source_addr: usize, dest_addr: usize) {
+        let pixel = self.vram.read(source_addr);
+        rasterizer::write_pixel(&mut self.vram, settings, dest_addr, pixel);
+        self.sync_hires(dest_addr);
     }
 
-    fn write_5bit_color_alpha(&mut self, addr: usize, val: u16) {
-        if self.mask_before_draw && self.read_vram(addr) & 0x8000 > 0 {
-            return;
+    // Replace all of VRAM with the given (lo, hi) pixel bytes
+    pub fn restore_vram(&mut self, data: &[u8]) {
+        for (addr, pixel) in data.chunks_exact(2).enumerate() {
+            self.vram
+                .write(addr, u16::from_le_bytes([pixel[0], pixel[1]]));
+            self.sync_hires(addr);
         }
-
-        // if !self.draw_to_display && self.in_draw_area(addr as u32 % 1024, addr as u32 / 1024) {
-        //     return
-        // }
-
-        // If Mask While Draw is set, then mask_field is forced to true. Otherwise set to bit 15
-        let mask_bit = self.mask_while_draw || (val & 0x8000 > 0);
-
-        let r = convert_5bit_to_8bit(val & 0x1F);
-        let g = convert_5bit_to_8bit((val >> 5) & 0x1F);
-        let b = convert_5bit_to_8bit((val >> 10) & 0x1F);
-
-        let prev_color = u16::from_le_bytes([self.vram[2 * addr], self.vram[2 * addr + 1]]);
-        let prev_color_r = convert_5bit_to_8bit(prev_color & 0x1F);
-        let prev_color_g = convert_5bit_to_8bit((prev_color >> 5) & 0x1F);
-        let prev_color_b = convert_5bit_to_8bit((prev_color >> 10) & 0x1F);
-
-        let (new_r, new_g, new_b) = match self.semitransparency {
-            SemiTransparency::Blend => (
-                r / 2 + prev_color_r / 2,
-                g / 2 + prev_color_g / 2,
-                b / 2 + prev_color_b / 2,
-            ),
-            SemiTransparency::Add => (
-                (r + prev_color_r).clamp(0, 255),
-                (g + prev_color_g).clamp(0, 255),
-                (b + prev_color_b).clamp(0, 255),
-            ),
-            SemiTransparency::Subtract => (
-                (prev_color_r - r).clamp(0, 255),
-                (prev_color_g - g).clamp(0, 255),
-                (prev_color_b - b).clamp(0, 255),
-            ),
-            SemiTransparency::QuarterBlend => (
-                r / 4 + prev_color_r,
-                g / 4 + prev_color_g,
-                b / 4 + prev_color_b,
-            ),
-        };
-
-        let new_r = new_r >> 3;
-        let new_g = new_g >> 3;
-        let new_b = new_b >> 3;
-
-        let new_color = (new_r as u16) | ((new_g as u16) << 5) | ((new_b as u16) << 10);
-        self.vram[2 * addr] = new_color as u8;
-        self.vram[2 * addr + 1] = ((mask_bit as u8) << 7) | (new_color >> 8) as u8;
     }
 
-    pub fn read_vram(&self, addr: usize) -> u16 {
-        let lo = self.vram[2 * addr];
-        let hi = self.vram[2 * addr + 1];
-
-        u16::from_le_bytes([lo, hi])
+    // Internal resolution multiplier for polygons. A scale of 1 renders at native resolution only
+    pub fn set_resolution_scale(&mut self, scale: usize) {
+        self.hires = (scale > 1).then(|| HiresVram::new(&self.vram, scale));
     }
 
-    fn modulate_5bit_color(&self, col1: u16, col2: u32) -> u16 {
-        let mask = col1 & 0x8000;
-        let r1 = convert_5bit_to_8bit(col1 & 0x1F) as f32;
-        let g1 = convert_5bit_to_8bit((col1 >> 5) & 0x1F) as f32;
-        let b1 = convert_5bit_to_8bit((col1 >> 10) & 0x1F) as f32;
+    pub fn resolution_scale(&self) -> usize {
+        self.hires.as_ref().map_or(1, HiresVram::scale)
+    }
 
-        let r2 = (col2 & 0xFF) as f32;
-        let g2 = ((col2 >> 8) & 0xFF) as f32;
-        let b2 = ((col2 >> 16) & 0xFF) as f32;
+    // Fills and blits always fall back to native resolution in the high resolution buffer
+    fn sync_hires(&mut self, addr: usize) {
+        if let Some(hires) = &mut self.hires {
+            hires.sync(&self.vram, addr);
+        }
+    }
 
-        let new_r = (((r1 * r2) / 128.0).round() as u8) >> 3;
-        let new_g = (((g1 * g2) / 128.0).round() as u8) >> 3;
-        let new_b = (((b1 * b2) / 128.0).round() as u8) >> 3;
+    // The high resolution copy and debug overlay are drawn first so all see the same texture data
+    fn draw_triangle(&mut self, settings: &DrawSettings, triangle: &Triangle) -> u32 {
+        self.overlay.draw_triangle(&self.vram, settings, triangle);
 
-        new_r as u16 | (new_g as u16) << 5 | (new_b as u16) << 10 | mask
-    }
+        if let Some(hires) = &mut self.hires {
+            let mut target = HiresTarget {
+                vram: &self.vram,
+                hires,
+            };
+            rasterizer::draw_triangle(&mut target, settings, triangle);
+        }
 
-    fn copy_vram(&mut self, source_addr: usize, dest_addr: usize) {
-        self.vram[2 * dest_addr] = self.vram[2 * source_addr];
-        self.vram[2 * dest_addr + 1] = self.vram[2 * source_addr + 1];
+        rasterizer::draw_triangle(&mut self.vram, settings, triangle)
     }
 
     pub fn transparency_mode(&self) -> u32 {
@@ -256,10 +286,77 @@ impl Gp0 {
         }
     }
 
+    // GP0 words are queued in the command FIFO and then processed in order
     pub fn write(&mut self, val: u32) {
+        event!(target: "ps1_emulator::GPU", Level::DEBUG, "Write to GP0: {:08X}", val);
+
+        // The writer stalls until the GPU has finished drawing and made room in the FIFO. The
+        // bus runs the machine through the stall first, this covers writers that don't
+        while self.fifo_full() {
+            event!(target: "ps1_emulator::GPU", Level::TRACE, "GP0 FIFO full, stalling");
+            self.run(self.busy_cycles);
+        }
+
+        self.command_buffer.push_back(val);
+        self.process_fifo();
+    }
+
+    // Retire drawing time then continue with any queued words
+    pub fn run(&mut self, gpu_cycles: u32) {
+        self.busy_cycles = self.busy_cycles.saturating_sub(gpu_cycles);
+        self.process_fifo();
+    }
+
+    // Words are only taken from the FIFO while the GPU is not busy drawing
+    fn process_fifo(&mut self) {
+        while self.busy_cycles == 0 {
+            let Some(val) = self.command_buffer.pop_front() else {
+                break;
+            };
+            self.process_word(val);
+        }
+    }
+
+    // Approximate time taken to draw a primitive. Each pixel costs one GPU cycle, doubled for
+    // each of textured, shaded and semi-transparent
+    fn charge_draw_time(&mut self, pixels: u32) {
+        let command = self.params[0];
+        let mut cost = pixels;
+        if command >> 29 != 2 && command & 0x4000000 > 0 {
+            cost *= 2;
+        }
+        if command >> 29 != 3 && command & 0x10000000 > 0 {
+            cost *= 2;
+        }
+        if command & 0x2000000 > 0 {
+            cost *= 2;
+        }
+        self.busy_cycles += cost;
+        self.stats.pixels += pixels;
+    }
+
+    pub fn fifo_full(&self) -> bool {
+        self.command_buffer.len() >= FIFO_SIZE
+    }
+
+    fn process_word(&mut self, val: u32) {
         // let span = span!(target: "ps1_emulator::GPU", Level::DEBUG, "GP0");
         // let _ = span.enter();
-        event!(target: "ps1_emulator::GPU", Level::DEBUG, "Write to GP0: {:08X}", val);
+
+        self.stats.words += 1;
+
+        let drawing = match self.state {
+            Gp0State::ReceivingPolyVert { .. } | Gp0State::ReceivingLineVert { .. } => true,
+            Gp0State::ReceivingParams { command, .. } => matches!(
+                command,
+                Commands::Rectangle
+                    | Commands::TexturedRectangle
+                    | Commands::SizeRectangle
+                    | Commands::TexturedSizeRectangle
+            ),
+            _ => false,
+        };
+        let pixels_before = self.pixels_drawn;
 
         self.state = match self.state {
             Gp0State::WaitingForCommand => {
@@ -364,8 +461,8 @@ impl Gp0 {
                         match val >> 24 {
                             0x00 => Gp0State::WaitingForCommand, // no op
                             0x01 => {
-                                // Flush Texture Cache
-                                // TODO
+                                // Clear Texture Cache. Textures are always read straight from VRAM
+                                // so there is nothing to clear
                                 Gp0State::WaitingForCommand
                             }
                             0x02 => {
@@ -379,8 +476,10 @@ impl Gp0 {
                                     idx: 1,
                                 }
                             }
-                            0x03 => {
-                                // Unknown?
+                            0x1F => {
+                                // Interrupt Request. Acknowledged with GP1(0x02)
+                                event!(target: "ps1_emulator::GPU", Level::TRACE, "GP0 IRQ requested");
+                                self.irq_request = true;
                                 Gp0State::WaitingForCommand
                             }
                             0xE1 => {
@@ -417,32 +516,32 @@ impl Gp0 {
                             }
                             0xE2 => {
                                 // Texture Window Setting
-                                self.texture_window = val;
+                                self.texture_window = val & 0xFFFFF;
 
                                 Gp0State::WaitingForCommand
                             }
                             0xE3 => {
-                                // Set Drawing Area Top Left (X1, Y1)
+                                // Set Drawing Area Top Left (X1, Y1). VRAM is only 512 rows tall
                                 self.draw_area_top_left.0 = val & 0x3FF;
-                                self.draw_area_top_left.1 = (val >> 10) & 0x3FF;
+                                self.draw_area_top_left.1 = ((val >> 10) & 0x3FF).min(511);
 
                                 event!(target: "ps1_emulator::GPU", Level::TRACE, "Set Draw Area Top Left to {:?}", self.draw_area_top_left);
 
                                 Gp0State::WaitingForCommand
                             }
                             0xE4 => {
-                                // Set Drawing Area Bottom Right (X2, Y2)
+                                // Set Drawing Area Bottom Right (X2, Y2). VRAM is only 512 rows tall
                                 self.draw_area_bot_right.0 = val & 0x3FF;
-                                self.draw_area_bot_right.1 = (val >> 10) & 0x3FF;
+                                self.draw_area_bot_right.1 = ((val >> 10) & 0x3FF).min(511);
 
                                 event!(target: "ps1_emulator::GPU", Level::TRACE, "Set Draw Area Bottom Right to {:?}", self.draw_area_bot_right);
 
                                 Gp0State::WaitingForCommand
                             }
                             0xE5 => {
-                                // Set Drawing Offset (X, Y)
-                                self.draw_offset.0 = (val & 0x3FF) as i16;
-                                self.draw_offset.1 = ((val >> 11) & 0x3FF) as i16;
+                                // Set Drawing Offset (X, Y). Both are signed 11 bit values
+                                self.draw_offset.0 = sign_extend_11bit(val);
+                                self.draw_offset.1 = sign_extend_11bit(val >> 11);
 
                                 event!(target: "ps1_emulator::GPU", Level::TRACE, "Set Draw Offset to ({}, {})", self.draw_offset.0, self.draw_offset.1);
 
@@ -455,7 +554,11 @@ impl Gp0 {
 
                                 Gp0State::WaitingForCommand
                             }
-                            _ => panic!("Impossible GP0 Environment Command {:02X}", val >> 24),
+                            _ => {
+                                // Remaining misc and environment commands behave as NOPs
+                                event!(target: "ps1_emulator::GPU", Level::DEBUG, "Unknown GP0 command {:08X} treated as NOP", val);
+                                Gp0State::WaitingForCommand
+                            }
                         }
                     }
                     _ => {
@@ -483,13 +586,13 @@ impl Gp0 {
                                 3 => 16,
                                 _ => panic!("Impossible"),
                             };
-                            self.draw_untextured_rectangle(dimension, dimension);
+                            self.draw_rectangle(dimension, dimension, false);
                             Gp0State::WaitingForCommand
                         }
                         Commands::SizeRectangle => {
                             let width = val & 0x3FF;
                             let height = (val >> 16) & 0x1FF;
-                            self.draw_untextured_rectangle(width, height);
+                            self.draw_rectangle(width, height, false);
                             Gp0State::WaitingForCommand
                         }
                         Commands::TexturedRectangle => {
@@ -499,25 +602,28 @@ impl Gp0 {
                                 3 => 16,
                                 _ => panic!("Impossible"),
                             };
-                            self.draw_textured_rectangle(dimension, dimension);
+                            self.draw_rectangle(dimension, dimension, true);
                             Gp0State::WaitingForCommand
                         }
                         Commands::TexturedSizeRectangle => {
                             let width = val & 0x3FF;
                             let height = (val >> 16) & 0x1FF;
-                            self.draw_textured_rectangle(width, height);
+                            self.draw_rectangle(width, height, true);
                             Gp0State::WaitingForCommand
                         }
                         Commands::VramToVram => {
+                            self.stats.blits += 1;
                             self.vram_copy();
                             Gp0State::WaitingForCommand
                         }
                         Commands::CpuToVram => {
                             // CPU to VRAM blit
+                            self.stats.blits += 1;
                             self.cpu_to_vram_init()
                         }
                         Commands::VramToCpu => {
                             // VRAM to CPU blit
+                            self.stats.blits += 1;
                             self.vram_to_cpu_init()
                         }
                         Commands::VramFill => {
@@ -533,6 +639,9 @@ impl Gp0 {
 
                             let pixel = (r | (g << 5) | (b << 10)) as u16;
                             self.vram_fill(width, height, vram_x, vram_y, pixel);
+                            self.stats.fills += 1;
+                            // Fills write 2 pixels per cycle
+                            self.busy_cycles += width * height / 2;
                             Gp0State::WaitingForCommand
                         }
                     }
@@ -543,10 +652,10 @@ impl Gp0 {
                     }
                 }
             }
-            Gp0State::ReceivingData(mut fields) => {
+            Gp0State::ReceivingData(fields) => {
                 event!(target: "ps1_emulator::GPU", Level::TRACE, "Received Data: {:08X}", val);
 
-                self.cpu_to_vram_process(val, &mut fields)
+                self.cpu_to_vram_process(val, fields)
             }
             Gp0State::SendingData(fields) => {
                 // GPU is busy sending data. Do not change state until final data has been sent via GPUREAD
@@ -563,187 +672,7 @@ impl Gp0 {
                 let limit = size * (1 + shaded as u8 + textured as u8);
 
                 if idx >= limit {
-                    let mut index = 1 + shaded as usize;
-                    let v0 = (
-                        self.params[index] & 0x3FF,
-                        ((self.params[index] >> 16) & 0x3FF),
-                    );
-                    index += 1 + textured as usize + shaded as usize;
-                    let v1 = (
-                        self.params[index] & 0x3FF,
-                        ((self.params[index] >> 16) & 0x3FF),
-                    );
-                    index += 1 + textured as usize + shaded as usize;
-                    let v2 = (
-                        self.params[index] & 0x3FF,
-                        ((self.params[index] >> 16) & 0x3FF),
-                    );
-
-                    let (min, max) = self.get_bounds(v0, v1, v2);
-
-                    match (shaded, textured) {
-                        (false, false) => {
-                            self.rasterize_triangle(v0, v1, v2, min, max);
-                        }
-                        (false, true) => {
-                            let t0 = self.params[2];
-                            let t1 = self.params[4];
-                            let t2 = self.params[6];
-
-                            let uv0 = (t0 & 0xFF, (t0 >> 8) & 0xFF);
-                            let uv1 = (t1 & 0xFF, (t1 >> 8) & 0xFF);
-                            let uv2 = (t2 & 0xFF, (t2 >> 8) & 0xFF);
-
-                            let clut_x = 16 * ((t0 >> 16) & 0x3F);
-                            let clut_y = (t0 >> 22) & 0x1FF;
-                            let clut = (clut_x as u16, clut_y as u16);
-
-                            let tex_page = (t1 >> 16) & 0xFFFF;
-                            self.tex_page_x = (tex_page & 0xF) as u8;
-                            self.tex_page_y = tex_page & 0x10 > 0;
-
-                            match (tex_page >> 7) & 0b11 {
-                                0 => self.tex_page_colors = TextureBits::Four,
-                                1 => self.tex_page_colors = TextureBits::Eight,
-                                2 => self.tex_page_colors = TextureBits::Fifteen,
-                                3 => self.tex_page_colors = TextureBits::Reserved,
-                                _ => {
-                                    event!(target: "ps1_emulator::GPU", Level::WARN, "Texture size outside of Four, Eight and Fifteen");
-                                }
-                            }
-                            match (tex_page >> 5) & 0b11 {
-                                0 => self.semitransparency = SemiTransparency::Blend,
-                                1 => self.semitransparency = SemiTransparency::Add,
-                                2 => self.semitransparency = SemiTransparency::Subtract,
-                                3 => self.semitransparency = SemiTransparency::QuarterBlend,
-                                _ => panic!("Impossible"),
-                            }
-
-                            if self.vram_size_set {
-                                self.two_mb_mem = tex_page & 0x800 > 0;
-                            } else {
-                                self.two_mb_mem = false;
-                            }
-
-                            self.rasterize_triangle_textured(
-                                v0, v1, v2, uv0, uv1, uv2, clut, min, max,
-                            );
-                        }
-                        (true, false) => {
-                            let c0 = self.params[1];
-                            let c1 = self.params[3];
-                            let c2 = self.params[5];
-
-                            self.rasterize_triangle_shaded(v0, v1, v2, c0, c1, c2, min, max);
-                        }
-                        (true, true) => {
-                            let c0 = self.params[1];
-                            let t0 = self.params[3];
-                            let c1 = self.params[4];
-                            let t1 = self.params[6];
-                            let c2 = self.params[7];
-                            let t2 = self.params[9];
-
-                            let uv0 = (t0 & 0xFF, (t0 >> 8) & 0xFF);
-                            let uv1 = (t1 & 0xFF, (t1 >> 8) & 0xFF);
-                            let uv2 = (t2 & 0xFF, (t2 >> 8) & 0xFF);
-
-                            let clut_x = 16 * ((t0 >> 16) & 0x3F);
-                            let clut_y = (t0 >> 22) & 0x1FF;
-                            let clut = (clut_x as u16, clut_y as u16);
-
-                            let tex_page = (t1 >> 16) & 0xFFFF;
-                            self.tex_page_x = (tex_page & 0xF) as u8;
-                            self.tex_page_y = tex_page & 0x10 > 0;
-
-                            match (tex_page >> 7) & 0b11 {
-                                0 => self.tex_page_colors = TextureBits::Four,
-                                1 => self.tex_page_colors = TextureBits::Eight,
-                                2 => self.tex_page_colors = TextureBits::Fifteen,
-                                3 => self.tex_page_colors = TextureBits::Reserved,
-                                _ => {
-                                    event!(target: "ps1_emulator::GPU", Level::WARN, "Texture size outside of Four, Eight and Fifteen");
-                                }
-                            }
-                            match (tex_page >> 5) & 0b11 {
-                                0 => self.semitransparency = SemiTransparency::Blend,
-                                1 => self.semitransparency = SemiTransparency::Add,
-                                2 => self.semitransparency = SemiTransparency::Subtract,
-                                3 => self.semitransparency = SemiTransparency::QuarterBlend,
-                                _ => panic!("Impossible"),
-                            }
-
-                            if self.vram_size_set {
-                                self.two_mb_mem = tex_page & 0x800 > 0;
-                            } else {
-                                self.two_mb_mem = false;
-                            }
-
-                            self.rasterize_triangle_textured_and_shaded(
-                                v0, v1, v2, uv0, uv1, uv2, c0, c1, c2, clut, min, max,
-                            );
-                        }
-                    }
-
-                    if size == 4 {
-                        index += 1 + textured as usize + shaded as usize;
-                        let v3 = (
-                            self.params[index] & 0x3FF,
-                            ((self.params[index] >> 16) & 0x3FF),
-                        );
-
-                        let (min, max) = self.get_bounds(v1, v2, v3);
-
-                        match (shaded, textured) {
-                            (false, false) => {
-                                self.rasterize_triangle(v1, v2, v3, min, max);
-                            }
-                            (false, true) => {
-                                let t1 = self.params[4];
-                                let t2 = self.params[6];
-                                let t3 = self.params[8];
-
-                                let uv1 = (t1 & 0xFF, (t1 >> 8) & 0xFF);
-                                let uv2 = (t2 & 0xFF, (t2 >> 8) & 0xFF);
-                                let uv3 = (t3 & 0xFF, (t3 >> 8) & 0xFF);
-
-                                let clut_x = 16 * ((self.params[2] >> 16) & 0x3F);
-                                let clut_y = (self.params[2] >> 22) & 0x1FF;
-                                let clut = (clut_x as u16, clut_y as u16);
-
-                                self.rasterize_triangle_textured(
-                                    v1, v2, v3, uv1, uv2, uv3, clut, min, max,
-                                );
-                            }
-                            (true, false) => {
-                                let c1 = self.params[3];
-                                let c2 = self.params[5];
-                                let c3 = self.params[7];
-
-                                self.rasterize_triangle_shaded(v1, v2, v3, c1, c2, c3, min, max);
-                            }
-                            (true, true) => {
-                                let c1 = self.params[4];
-                                let t1 = self.params[6];
-                                let c2 = self.params[7];
-                                let t2 = self.params[9];
-                                let c3 = self.params[10];
-                                let t3 = self.params[12];
-
-                                let uv1 = (t1 & 0xFF, (t1 >> 8) & 0xFF);
-                                let uv2 = (t2 & 0xFF, (t2 >> 8) & 0xFF);
-                                let uv3 = (t3 & 0xFF, (t3 >> 8) & 0xFF);
-
-                                let clut_x = 16 * ((self.params[3] >> 16) & 0x3F);
-                                let clut_y = (self.params[3] >> 22) & 0x1FF;
-                                let clut = (clut_x as u16, clut_y as u16);
-
-                                self.rasterize_triangle_textured_and_shaded(
-                                    v1, v2, v3, uv1, uv2, uv3, c1, c2, c3, clut, min, max,
-                                );
-                            }
-                        }
-                    }
+                    self.draw_polygon(size, shaded, textured);
 
                     Gp0State::WaitingForCommand
                 } else {
@@ -772,12 +701,7 @@ impl Gp0 {
                     (true, false) => {
                         // Polyline but no stop signal. Continue to draw
                         if idx == 2 && !shaded {
-                            let x1 = self.params[1] & 0x3FF;
-                            let y1 = (self.params[1] >> 16) & 0x1FF;
-                            let x2 = self.params[2] & 0x3FF;
-                            let y2 = (self.params[2] >> 16) & 0x1FF;
-
-                            self.draw_line(x1, y1, x2, y2);
+                            self.draw_line(false);
                             self.params[1] = self.params[2];
                             Gp0State::ReceivingLineVert {
                                 polyline,
@@ -785,14 +709,7 @@ impl Gp0 {
                                 idx: 2,
                             }
                         } else if idx == 4 {
-                            let color1 = self.params[1];
-                            let x1 = self.params[2] & 0x3FF;
-                            let y1 = (self.params[2] >> 16) & 0x1FF;
-                            let color2 = self.params[3];
-                            let x2 = self.params[4] & 0x3FF;
-                            let y2 = (self.params[4] >> 16) & 0x1FF;
-
-                            self.draw_line_shaded(x1, y1, color1, x2, y2, color2);
+                            self.draw_line(true);
                             self.params[1] = self.params[3];
                             self.params[2] = self.params[4];
                             Gp0State::ReceivingLineVert {
@@ -811,22 +728,10 @@ impl Gp0 {
                     (false, _) => {
                         // Single line. Draw one line then end
                         if idx == 2 && !shaded {
-                            let x1 = self.params[1] & 0x3FF;
-                            let y1 = (self.params[1] >> 16) & 0x1FF;
-                            let x2 = self.params[2] & 0x3FF;
-                            let y2 = (self.params[2] >> 16) & 0x1FF;
-
-                            self.draw_line(x1, y1, x2, y2);
+                            self.draw_line(false);
                             Gp0State::WaitingForCommand
                         } else if idx == 4 {
-                            let color1 = self.params[1];
-                            let x1 = self.params[2] & 0x3FF;
-                            let y1 = (self.params[2] >> 16) & 0x1FF;
-                            let color2 = self.params[3];
-                            let x2 = self.params[4] & 0x3FF;
-                            let y2 = (self.params[4] >> 16) & 0x1FF;
-
-                            self.draw_line_shaded(x1, y1, color1, x2, y2, color2);
+                            self.draw_line(true);
                             Gp0State::WaitingForCommand
                         } else {
                             Gp0State::ReceivingLineVert {
@@ -839,14 +744,22 @@ impl Gp0 {
                 }
             }
         };
+
+        if drawing {
+            self.charge_draw_time(self.pixels_drawn.wrapping_sub(pixels_before));
+        }
     }
 
+    // Ready for a new command once nothing is queued and no command is part way received
     pub fn ready_for_cmd(&self) -> bool {
-        matches!(self.state, Gp0State::WaitingForCommand)
+        self.busy_cycles == 0
+            && self.command_buffer.is_empty()
+            && matches!(self.state, Gp0State::WaitingForCommand)
     }
 
+    // DMA blocks can be sent while there is space in the FIFO
     pub fn dma_ready(&self) -> bool {
-        true
+        !self.fifo_full()
     }
 
     fn cpu_to_vram_init(&mut self) -> Gp0State {
@@ -875,29 +788,23 @@ impl Gp0 {
         })
     }
 
-    fn cpu_to_vram_process(&mut self, word: u32, fields: &mut VramCopyFields) -> Gp0State {
+    // Each word holds two pixels. When the rectangle has an odd number of pixels, the upper
+    // halfword of the final word is padding and is ignored
+    fn cpu_to_vram_process(&mut self, word: u32, mut fields: VramCopyFields) -> Gp0State {
         event!(target: "ps1_emulator::GPU", Level::TRACE, "CPU to VRAM Data");
 
+        let settings = self.draw_settings();
         for i in 0..2 {
             let halfword = (word >> (16 * i)) as u16;
-            let vram_row = ((fields.vram_y + fields.current_row) & 0x1FF) as usize;
-            let vram_col = ((fields.vram_x + fields.current_col) & 0x3FF) as usize;
-
-            let vram_addr = 1024 * vram_row + vram_col;
-            self.write_5bit_color(vram_addr, halfword);
+            rasterizer::write_pixel(&mut self.vram, &settings, fields.current_addr(), halfword);
+            self.sync_hires(fields.current_addr());
 
-            fields.current_col += 1;
-            if fields.current_col == fields.width {
-                fields.current_col = 0;
-                fields.current_row += 1;
-
-                if fields.current_row == fields.height {
-                    return Gp0State::WaitingForCommand;
-                }
+            if fields.advance() {
+                return Gp0State::WaitingForCommand;
             }
         }
 
-        Gp0State::ReceivingData(*fields)
+        Gp0State::ReceivingData(fields)
     }
 
     fn vram_to_cpu_init(&mut self) -> Gp0State {
@@ -936,22 +843,14 @@ impl Gp0 {
 
         let mut out = [0u8; 4];
         for i in 0..2 {
-            let vram_row = ((fields.vram_y + fields.current_row) & 0x1FF) as usize;
-            let vram_col = ((fields.vram_x + fields.current_col) & 0x3FF) as usize;
-            let vram_addr = 1024 * vram_row + vram_col;
-            let [vram_lo, vram_hi] = self.read_vram(vram_addr).to_le_bytes();
+            let [vram_lo, vram_hi] = self.vram.read(fields.current_addr()).to_le_bytes();
 
             out[2 * i] = vram_lo;
             out[2 * i + 1] = vram_hi;
 
-            fields.current_col += 1;
-            if fields.current_col == fields.width {
-                fields.current_col = 0;
-                fields.current_row += 1;
-
-                if fields.current_row == fields.height {
-                    self.state = Gp0State::WaitingForCommand;
-                }
+            if fields.advance() {
+                self.state = Gp0State::WaitingForCommand;
+                break;
             }
         }
 
@@ -972,6 +871,7 @@ impl Gp0 {
         let width = self.params[2] & 0x3FF;
         let height = (self.params[2] >> 16) & 0x1FF;
 
+        let settings = self.draw_settings();
         for y in 0..height {
             for x in 0..width {
                 let source_row = ((source_y + y) & 0x1FF) as usize;
@@ -980,555 +880,427 @@ impl Gp0 {
                 let dest_col = ((dest_x + x) & 0x3FF) as usize;
                 let source_addr = 1024 * source_row + source_col;
                 let dest_addr = 1024 * dest_row + dest_col;
-                self.copy_vram(source_addr, dest_addr);
+                self.copy_vram(&settings, source_addr, dest_addr);
             }
         }
-    }
 
-    // returns (min_x, min_y) and (max_x, max_y) of bounding box
-    fn get_bounds(
-        &mut self,
-        v0: (u32, u32),
-        v1: (u32, u32),
-        v2: (u32, u32),
-    ) -> ((u32, u32), (u32, u32)) {
-        let min_x = cmp::max(
-            self.draw_area_top_left.0,
-            cmp::min(v0.0, cmp::min(v1.0, v2.0)),
-        );
-        let min_y = cmp::max(
-            self.draw_area_top_left.1,
-            cmp::min(v0.1, cmp::min(v1.1, v2.1)),
-        );
-        let max_x = cmp::min(
-            self.draw_area_bot_right.0,
-            cmp::max(v0.0, cmp::max(v1.0, v2.0)),
-        );
-        let max_y = cmp::min(
-            self.draw_area_bot_right.1,
-            cmp::max(v0.1, cmp::max(v1.1, v2.1)),
-        );
-
-        ((min_x, min_y), (max_x, max_y))
+        // Each pixel needs a read and a write
+        self.busy_cycles += 2 * width * height;
     }
 
-    fn rasterize_triangle(
-        &mut self,
-        mut v0: (u32, u32),
-        mut v1: (u32, u32),
-        v2: (u32, u32),
-        min: (u32, u32),
-        max: (u32, u32),
-    ) {
-        if min.0 > max.0 || min.1 > max.1 {
-            return;
-        }
-
-        if rasterize::cross_product(v0, v1, v2) < 0 {
-            mem::swap(&mut v0, &mut v1);
+    // GP0(0x20..=0x3F) polygon words are read per vertex as [color], position, [texcoord]. The
+    // first texcoord holds the CLUT and the second holds the texture page
+    fn draw_polygon(&mut self, size: u8, shaded: bool, textured: bool) {
+        if size == 4 {
+            self.stats.quads += 1;
+        } else {
+            self.stats.triangles += 1;
         }
 
         let command = self.params[0];
-
-        let r = (command & 0xFF) >> 3;
-        let g = ((command >> 8) & 0xFF) >> 3;
-        let b = ((command >> 16) & 0xFF) >> 3;
-
-        let pixel = (r | (g << 5) | (b << 10)) as u16;
-
-        let use_alpha = (self.params[0] >> 25) & 0x1 > 0;
-
-        for y in min.1..=max.1 {
-            for x in min.0..=max.0 {
-                if rasterize::inside_triange((x, y), v0, v1, v2).is_some() {
-                    let vram_addr = 1024 * (y as usize) + x as usize;
-                    if use_alpha {
-                        self.write_5bit_color_alpha(vram_addr, pixel);
-                    } else {
-                        self.write_5bit_color(vram_addr, pixel);
-                    }
-                }
-            }
-        }
-    }
-
-    fn rasterize_triangle_textured(
-        &mut self,
-        mut v0: (u32, u32),
-        mut v1: (u32, u32),
-        v2: (u32, u32),
-        mut uv0: (u32, u32),
-        mut uv1: (u32, u32),
-        uv2: (u32, u32),
-        clut: (u16, u16),
-        min: (u32, u32),
-        max: (u32, u32),
-    ) {
-        if min.0 > max.0 || min.1 > max.1 {
-            return;
+        let stride = 1 + shaded as usize + textured as usize;
+
+        let mut vertices = [Vertex::default(); 4];
+        for (i, vertex) in vertices.iter_mut().take(size as usize).enumerate() {
+            let base = 1 + stride * i;
+            let pos_idx = base + shaded as usize;
+            let color = if shaded { self.params[base] } else { command };
+            let uv = if textured {
+                let t = self.params[pos_idx + 1];
+                (t & 0xFF, (t >> 8) & 0xFF)
+            } else {
+                (0, 0)
+            };
+
+            *vertex = Vertex {
+                pos: self.vertex(self.params[pos_idx]),
+                color: color & 0xFFFFFF,
+                uv,
+            };
         }
 
-        if rasterize::cross_product(v0, v1, v2) < 0 {
-            mem::swap(&mut v0, &mut v1);
-            mem::swap(&mut uv0, &mut uv1);
+        let mut clut = (0, 0);
+        if textured {
+            let t0 = self.params[2 + shaded as usize];
+            let t1 = self.params[2 + shaded as usize + stride];
+            clut = (
+                (16 * ((t0 >> 16) & 0x3F)) as u16,
+                ((t0 >> 22) & 0x1FF) as u16,
+            );
+            self.set_texpage((t1 >> 16) & 0xFFFF);
         }
 
-        let use_alpha = (self.params[0] >> 25) & 0x1 > 0;
-        let use_modulation = self.params[0] & 0x1000000 == 0;
-        let tex_page = (64 * self.tex_page_x as u16, 256 * self.tex_page_y as u16);
-
-        for y in min.1..=max.1 {
-            for x in min.0..=max.0 {
-                if let Some([a, b, c]) = rasterize::inside_triange((x, y), v0, v1, v2) {
-                    let u = (a * uv0.0 as f32 + b * uv1.0 as f32 + c * uv2.0 as f32).round() as u32;
-                    let v = (a * uv0.1 as f32 + b * uv1.1 as f32 + c * uv2.1 as f32).round() as u32;
-                    let pixel = self.get_color_from_uv(u, v, clut, tex_page, self.tex_page_colors);
-
-                    if pixel == 0 {
-                        continue;
-                    }
+        let mut triangle = Triangle {
+            vertices: [vertices[0], vertices[1], vertices[2]],
+            shaded,
+            textured,
+            modulate: shaded || command & 0x1000000 == 0,
+            semi_transparent: command & 0x2000000 > 0,
+            clut,
+        };
 
-                    let pixel = if use_modulation {
-                        let color = self.params[0] & 0xFFFFFF;
-                        self.modulate_5bit_color(pixel, color)
-                    } else {
-                        pixel
-                    };
-
-                    let vram_addr = 1024 * (y as usize) + x as usize;
-                    if use_alpha {
-                        self.write_5bit_color_alpha(vram_addr, pixel);
-                    } else {
-                        self.write_5bit_color(vram_addr, pixel);
-                    }
-                }
-            }
+        let settings = self.draw_settings();
+        let mut pixels = self.draw_triangle(&settings, &triangle);
+        if size == 4 {
+            triangle.vertices = [vertices[1], vertices[2], vertices[3]];
+            pixels += self.draw_triangle(&settings, &triangle);
         }
+        self.pixels_drawn = self.pixels_drawn.wrapping_add(pixels);
     }
 
-    fn rasterize_triangle_shaded(
-        &mut self,
-        mut v0: (u32, u32),
-        mut v1: (u32, u32),
-        v2: (u32, u32),
-        mut c0: u32,
-        mut c1: u32,
-        c2: u32,
-        min: (u32, u32),
-        max: (u32, u32),
-    ) {
-        if min.0 > max.0 || min.1 > max.1 {
-            return;
-        }
-
-        if rasterize::cross_product(v0, v1, v2) < 0 {
-            mem::swap(&mut v0, &mut v1);
-            mem::swap(&mut c0, &mut c1);
-        }
-
-        let r0 = c0 & 0xFF;
-        let g0 = (c0 >> 8) & 0xFF;
-        let b0 = (c0 >> 16) & 0xFF;
-        let r1 = c1 & 0xFF;
-        let g1 = (c1 >> 8) & 0xFF;
-        let b1 = (c1 >> 16) & 0xFF;
-        let r2 = c2 & 0xFF;
-        let g2 = (c2 >> 8) & 0xFF;
-        let b2 = (c2 >> 16) & 0xFF;
-
-        let use_alpha = (self.params[0] >> 25) & 0x1 > 0;
-
-        for y in min.1..=max.1 {
-            for x in min.0..=max.0 {
-                if let Some([a, b, c]) = rasterize::inside_triange((x, y), v0, v1, v2) {
-                    let r = (a * r0 as f32 + b * r1 as f32 + c * r2 as f32).round() as u8;
-                    let g = (a * g0 as f32 + b * g1 as f32 + c * g2 as f32).round() as u8;
-                    let b = (a * b0 as f32 + b * b1 as f32 + c * b2 as f32).round() as u8;
-                    let (r, g, b) = if self.dither_enabled {
-                        dither((r, g, b), (x, y))
-                    } else {
-                        (r, g, b)
-                    };
-                    let r = (r >> 3) as u16;
-                    let g = (g >> 3) as u16;
-                    let b = (b >> 3) as u16;
-                    let pixel = r | (g << 5) | (b << 10);
-
-                    let vram_addr = 1024 * (y as usize) + x as usize;
-                    if use_alpha {
-                        self.write_5bit_color_alpha(vram_addr, pixel);
-                    } else {
-                        self.write_5bit_color(vram_addr, pixel);
-                    }
-                }
+    // Textured polygons replace the GP0(0xE1) texture page settings
+    fn set_texpage(&mut self, tex_page: u32) {
+        self.tex_page_x = (tex_page & 0xF) as u8;
+        self.tex_page_y = tex_page & 0x10 > 0;
+
+        match (tex_page >> 7) & 0b11 {
+            0 => self.tex_page_colors = TextureBits::Four,
+            1 => self.tex_page_colors = TextureBits::Eight,
+            2 => self.tex_page_colors = TextureBits::Fifteen,
+            3 => self.tex_page_colors = TextureBits::Reserved,
+            _ => {
+                event!(target: "ps1_emulator::GPU", Level::WARN, "Texture size outside of Four, Eight and Fifteen");
             }
         }
-    }
-
-    fn rasterize_triangle_textured_and_shaded(
-        &mut self,
-        mut v0: (u32, u32),
-        mut v1: (u32, u32),
-        v2: (u32, u32),
-        mut uv0: (u32, u32),
-        mut uv1: (u32, u32),
-        uv2: (u32, u32),
-        mut c0: u32,
-        mut c1: u32,
-        c2: u32,
-        clut: (u16, u16),
-        min: (u32, u32),
-        max: (u32, u32),
-    ) {
-        if min.0 > max.0 || min.1 > max.1 {
-            return;
+        match (tex_page >> 5) & 0b11 {
+            0 => self.semitransparency = SemiTransparency::Blend,
+            1 => self.semitransparency = SemiTransparency::Add,
+            2 => self.semitransparency = SemiTransparency::Subtract,
+            3 => self.semitransparency = SemiTransparency::QuarterBlend,
+            _ => panic!("Impossible"),
         }
 
-        if rasterize::cross_product(v0, v1, v2) < 0 {
-            mem::swap(&mut v0, &mut v1);
-            mem::swap(&mut uv0, &mut uv1);
-            mem::swap(&mut c0, &mut c1);
-        }
-
-        let r0 = c0 & 0xFF;
-        let g0 = (c0 >> 8) & 0xFF;
-        let b0 = (c0 >> 16) & 0xFF;
-        let r1 = c1 & 0xFF;
-        let g1 = (c1 >> 8) & 0xFF;
-        let b1 = (c1 >> 16) & 0xFF;
-        let r2 = c2 & 0xFF;
-        let g2 = (c2 >> 8) & 0xFF;
-        let b2 = (c2 >> 16) & 0xFF;
-
-        let use_alpha = (self.params[0] >> 25) & 0x1 > 0;
-        let tex_page = (64 * self.tex_page_x as u16, 256 * self.tex_page_y as u16);
-
-        for y in min.1..=max.1 {
-            for x in min.0..=max.0 {
-                if let Some([a, b, c]) = rasterize::inside_triange((x, y), v0, v1, v2) {
-                    let u = (a * uv0.0 as f32 + b * uv1.0 as f32 + c * uv2.0 as f32).round() as u32;
-                    let v = (a * uv0.1 as f32 + b * uv1.1 as f32 + c * uv2.1 as f32).round() as u32;
-                    let pixel = self.get_color_from_uv(u, v, clut, tex_page, self.tex_page_colors);
-
-                    if pixel == 0 {
-                        continue;
-                    }
-
-                    let r = (a * r0 as f32 + b * r1 as f32 + c * r2 as f32).round() as u8;
-                    let g = (a * g0 as f32 + b * g1 as f32 + c * g2 as f32).round() as u8;
-                    let b = (a * b0 as f32 + b * b1 as f32 + c * b2 as f32).round() as u8;
-
-                    let (r, g, b) = if self.dither_enabled {
-                        dither((r, g, b), (x, y))
-                    } else {
-                        (r, g, b)
-                    };
-                    let color = (r as u32) | ((g as u32) << 8) | ((b as u32) << 16);
-
-                    let pixel = self.modulate_5bit_color(pixel, color);
-
-                    let vram_addr = 1024 * (y as usize) + x as usize;
-                    if use_alpha {
-                        self.write_5bit_color_alpha(vram_addr, pixel);
-                    } else {
-                        self.write_5bit_color(vram_addr, pixel);
-                    }
-                }
-            }
+        if self.vram_size_set {
+            self.two_mb_mem = tex_page & 0x800 > 0;
+        } else {
+            self.two_mb_mem = false;
         }
     }
 
-    fn draw_line(&mut self, x1: u32, y1: u32, x2: u32, y2: u32) {
+    // Shaded lines are sent as color, vertex, color, vertex. Flat lines use the command color
+    fn draw_line(&mut self, shaded: bool) {
+        self.stats.lines += 1;
         let command = self.params[0];
 
-        let r = (command & 0xFF) >> 3;
-        let g = ((command >> 8) & 0xFF) >> 3;
-        let b = ((command >> 16) & 0xFF) >> 3;
-
-        let pixel = (r | (g << 5) | (b << 10)) as u16;
-
-        let use_alpha = command & 0x2000000 > 0;
-
-        if x1.abs_diff(x2) >= y1.abs_diff(y2) {
-            let slope = (y2 as f32 - y1 as f32) / (x2 as f32 - x1 as f32);
-            let range = if x1 <= x2 { x1..=x2 } else { x2..=x1 };
-            for x in range {
-                let y = (slope * (x as f32 - x1 as f32) + y1 as f32).floor();
-                if (0.0..=512.0).contains(&y) {
-                    let vram_addr = 1024 * (y as usize) + x as usize;
-                    if use_alpha {
-                        self.write_5bit_color_alpha(vram_addr, pixel);
-                    } else {
-                        self.write_5bit_color(vram_addr, pixel);
-                    }
-                }
-            }
+        let (start, end) = if shaded {
+            (
+                Vertex {
+                    pos: self.vertex(self.params[2]),
+                    color: self.params[1] & 0xFFFFFF,
+                    uv: (0, 0),
+                },
+                Vertex {
+                    pos: self.vertex(self.params[4]),
+                    color: self.params[3] & 0xFFFFFF,
+                    uv: (0, 0),
+                },
+            )
         } else {
-            let slope = (x2 as f32 - x1 as f32) / (y2 as f32 - y1 as f32);
-            let range = if y1 <= y2 { y1..=y2 } else { y2..=y1 };
-            for y in range {
-                let x = (slope * (y as f32 - y1 as f32) + x1 as f32).floor();
-                if (0.0..=1024.0).contains(&x) {
-                    let vram_addr = 1024 * (y as usize) + x as usize;
-                    if use_alpha {
-                        self.write_5bit_color_alpha(vram_addr, pixel);
-                    } else {
-                        self.write_5bit_color(vram_addr, pixel);
-                    }
-                }
-            }
+            (
+                Vertex {
+                    pos: self.vertex(self.params[1]),
+                    color: command & 0xFFFFFF,
+                    uv: (0, 0),
+                },
+                Vertex {
+                    pos: self.vertex(self.params[2]),
+                    color: command & 0xFFFFFF,
+                    uv: (0, 0),
+                },
+            )
         };
-    }
 
-    fn draw_line_shaded(&mut self, x1: u32, y1: u32, c1: u32, x2: u32, y2: u32, c2: u32) {
-        let command = self.params[0];
+        let line = Line {
+            start,
+            end,
+            shaded,
+            semi_transparent: command & 0x2000000 > 0,
+        };
 
-        let r1 = c1 & 0xFF;
-        let g1 = (c1 >> 8) & 0xFF;
-        let b1 = (c1 >> 16) & 0xFF;
-        let r2 = c2 & 0xFF;
-        let g2 = (c2 >> 8) & 0xFF;
-        let b2 = (c2 >> 16) & 0xFF;
-
-        let use_alpha = command & 0x2000000 > 0;
-
-        let total_dist =
-            f32::sqrt((x1 as f32 - x2 as f32).powi(2) + (y1 as f32 - y2 as f32).powi(2));
-        if x1.abs_diff(x2) >= y1.abs_diff(y2) {
-            let slope = (y2 as f32 - y1 as f32) / (x2 as f32 - x1 as f32);
-            let range = if x1 <= x2 { x1..=x2 } else { x2..=x1 };
-            for x in range {
-                let y_raw = slope * (x as f32 - x1 as f32) + y1 as f32;
-                let y = y_raw.floor();
-                let dist = f32::sqrt((x as f32 - x2 as f32).powi(2) + (y_raw - y2 as f32).powi(2));
-                let pct = dist / total_dist;
-
-                let r = (pct * (r1 as f32) + (1.0 - pct) * (r2 as f32)).round() as u16;
-                let g = (pct * (g1 as f32) + (1.0 - pct) * (g2 as f32)).round() as u16;
-                let b = (pct * (b1 as f32) + (1.0 - pct) * (b2 as f32)).round() as u16;
-                let r = (r & 0xFF) >> 3;
-                let g = (g & 0xFF) >> 3;
-                let b = (b & 0xFF) >> 3;
-                let pixel = r | (g << 5) | (b << 10);
-
-                if (0.0..=512.0).contains(&y) {
-                    let vram_addr = 1024 * (y as usize) + x as usize;
-                    if use_alpha {
-                        self.write_5bit_color_alpha(vram_addr, pixel);
-                    } else {
-                        self.write_5bit_color(vram_addr, pixel);
-                    }
-                }
-            }
-        } else {
-            let slope = (x2 as f32 - x1 as f32) / (y2 as f32 - y1 as f32);
-            let range = if y1 <= y2 { y1..=y2 } else { y2..=y1 };
-            for y in range {
-                let x_raw = slope * (y as f32 - y1 as f32) + x1 as f32;
-                let x = x_raw.floor();
-                let dist = f32::sqrt((x_raw - x2 as f32).powi(2) + (y as f32 - y2 as f32).powi(2));
-                let pct = dist / total_dist;
-
-                let r = (pct * (r1 as f32) + (1.0 - pct) * (r2 as f32)).round() as u16;
-                let g = (pct * (g1 as f32) + (1.0 - pct) * (g2 as f32)).round() as u16;
-                let b = (pct * (b1 as f32) + (1.0 - pct) * (b2 as f32)).round() as u16;
-                let r = (r & 0xFF) >> 3;
-                let g = (g & 0xFF) >> 3;
-                let b = (b & 0xFF) >> 3;
-                let pixel = r | (g << 5) | (b << 10);
-
-                if (0.0..1024.0).contains(&x) {
-                    let vram_addr = 1024 * (y as usize) + x as usize;
-                    if use_alpha {
-                        self.write_5bit_color_alpha(vram_addr, pixel);
-                    } else {
-                        self.write_5bit_color(vram_addr, pixel);
-                    }
-                }
+        let settings = self.draw_settings();
+        self.overlay.draw_line(&self.vram, &settings, &line);
+        let pixels = match &mut self.hires {
+            Some(hires) => {
+                let mut target = NearestTarget {
+                    vram: &mut self.vram,
+                    hires,
+                };
+                rasterizer::draw_line(&mut target, &settings, &line)
             }
+            None => rasterizer::draw_line(&mut self.vram, &settings, &line),
         };
+        self.pixels_drawn = self.pixels_drawn.wrapping_add(pixels);
     }
 
-    fn draw_textured_rectangle(&mut self, width: u32, height: u32) {
+    fn draw_rectangle(&mut self, width: u32, height: u32, textured: bool) {
+        self.stats.rects += 1;
         let command = self.params[0];
+        let tex = if textured { self.params[2] } else { 0 };
 
-        let use_alpha = command & 0x2000000 > 0;
-        let use_modulation = command & 0x1000000 == 0;
-
-        let tex_page_base_x = ((self.tex_page_x as u32) * 64) as u16;
-        let tex_page_base_y = (256 * (self.tex_page_y as u32)) as u16;
-        let u_offset = self.params[2] & 0xFF;
-        let v_offset = (self.params[2] >> 8) & 0xFF;
-        let clut = (self.params[2] >> 16) as u16;
-        let clut_x = 16 * (clut & 0x3F);
-        let clut_y = (clut >> 6) & 0x1FF;
-
-        let vram_x = self.params[1] & 0x3FF;
-        let vram_y = (self.params[1] >> 16) & 0x1FF;
-        for y in 0..height {
-            for x in 0..width {
-                let vram_row = (vram_y + y) & 0x1FF;
-                let vram_col = (vram_x + x) & 0x3FF;
-                if (self.draw_area_top_left.0..self.draw_area_bot_right.0).contains(&vram_col)
-                    && (self.draw_area_top_left.1..self.draw_area_bot_right.1).contains(&vram_row)
-                {
-                    let u = if self.rect_x_flip {
-                        u_offset.wrapping_sub(x) + 1
-                    } else {
-                        u_offset.wrapping_add(x)
-                    } % 256;
-                    let v = if self.rect_y_flip {
-                        v_offset.wrapping_sub(y) + 1
-                    } else {
-                        v_offset.wrapping_add(y)
-                    } % 256;
-
-                    let pixel = self.get_color_from_uv(
-                        u,
-                        v,
-                        (clut_x, clut_y),
-                        (tex_page_base_x, tex_page_base_y),
-                        self.tex_page_colors,
-                    );
-
-                    let vram_addr = 1024 * vram_row as usize + vram_col as usize;
-
-                    if pixel == 0 {
-                        continue;
-                    }
+        let rect = Rect {
+            pos: self.vertex(self.params[1]),
+            width,
+            height,
+            color: command & 0xFFFFFF,
+            uv: (tex & 0xFF, (tex >> 8) & 0xFF),
+            clut: (
+                (16 * ((tex >> 16) & 0x3F)) as u16,
+                ((tex >> 22) & 0x1FF) as u16,
+            ),
+            textured,
+            modulate: command & 0x1000000 == 0,
+            semi_transparent: command & 0x2000000 > 0,
+        };
 
-                    let pixel = if use_modulation {
-                        let color = command & 0xFFFFFF;
-                        self.modulate_5bit_color(pixel, color)
-                    } else {
-                        pixel
-                    };
-
-                    if use_alpha {
-                        self.write_5bit_color_alpha(vram_addr, pixel);
-                    } else {
-                        self.write_5bit_color(vram_addr, pixel);
-                    }
-                }
+        let settings = self.draw_settings();
+        self.overlay.draw_rect(&self.vram, &settings, &rect);
+
+        // Sprites are scaled with nearest filtering so they stay seamless
+        let pixels = match &mut self.hires {
+            Some(hires) => {
+                let mut target = NearestTarget {
+                    vram: &mut self.vram,
+                    hires,
+                };
+                rasterizer::draw_rect(&mut target, &settings, &rect)
             }
-        }
+            None => rasterizer::draw_rect(&mut self.vram, &settings, &rect),
+        };
+        self.pixels_drawn = self.pixels_drawn.wrapping_add(pixels);
     }
 
-    fn draw_untextured_rectangle(&mut self, width: u32, height: u32) {
-        let command = self.params[0];
-
-        let use_alpha = command & 0x2000000 > 0;
+    pub fn is_sending_data(&self) -> bool {
+        matches!(self.state, Gp0State::SendingData(_))
+    }
+}
 
-        let r = (command & 0xFF) >> 3;
-        let g = ((command >> 8) & 0xFF) >> 3;
-        let b = ((command >> 16) & 0xFF) >> 3;
+// Sign extend the lower 11 bits of val
+fn sign_extend_11bit(val: u32) -> i16 {
+    (((val & 0x7FF) as i16) << 5) >> 5
+}
 
-        let pixel = (r | (g << 5) | (b << 10)) as u16;
+fn param_limits(command: Commands) -> u8 {
+    match command {
+        Commands::Rectangle => 1,
+        Commands::SizeRectangle => 2,
+        Commands::TexturedRectangle => 2,
+        Commands::TexturedSizeRectangle => 3,
+        Commands::VramToVram => 2,
+        Commands::CpuToVram => 1,
+        Commands::VramToCpu => 1,
+        Commands::VramFill => 2,
+    }
+}
 
-        let vram_x = self.params[1] & 0x3FF;
-        let vram_y = (self.params[1] >> 16) & 0x1FF;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpu::overlay::DebugView;
+
+    // Drawing area reaching Y = 0x3FF, with a red rect running past the bottom right corner of
+    // VRAM and a green line running past its bottom row
+    fn draw_past_vram(scale: usize) -> Gp0 {
+        let mut gp0 = Gp0::new();
+        gp0.set_resolution_scale(scale);
+        gp0.write(0xE3000000);
+        gp0.write(0xE4000000 | (0x3FF << 10) | 0x3FF);
+        for word in [0x600000FF, (500 << 16) | 1000, (40 << 16) | 40] {
+            gp0.write(word);
+        }
+        for word in [0x4000FF00, (490 << 16) | 990, (700 << 16) | 1020] {
+            gp0.write(word);
+        }
+        gp0.run(u32::MAX);
+        gp0
+    }
 
-        for y in 0..height {
-            for x in 0..width {
-                let vram_row = (vram_y + y) & 0x1FF;
-                let vram_col = (vram_x + x) & 0x3FF;
-                if (self.draw_area_top_left.0..self.draw_area_bot_right.0).contains(&vram_col)
-                    && (self.draw_area_top_left.1..self.draw_area_bot_right.1).contains(&vram_row)
-                {
-                    let vram_addr = 1024 * vram_row as usize + vram_col as usize;
-                    if use_alpha {
-                        self.write_5bit_color_alpha(vram_addr, pixel);
-                    } else {
-                        self.write_5bit_color(vram_addr, pixel);
-                    }
-                }
+    #[test]
+    fn increased_resolution_leaves_native_vram_unchanged() {
+        let scene = [
+            0xE3000000,
+            0xE4000000 | (511 << 10) | 1023,
+            // Upload a 4x4 texture and CLUT, then copy it
+            0xA0000000,
+            (256 << 16) | 640,
+            (4 << 16) | 4,
+            0x7C1F_03E0,
+            0x001F_7FFF,
+            0x1234_4321,
+            0x0F0F_7070,
+            0x5555_2AAA,
+            0x6666_1111,
+            0x0001_0002,
+            0x7FFE_4000,
+            0x80000000,
+            (256 << 16) | 640,
+            (300 << 16) | 700,
+            (4 << 16) | 4,
+            // Shaded triangle, textured quad, semi-transparent rect and a line
+            0x300000FF,
+            (10 << 16) | 10,
+            0x0000FF00,
+            (90 << 16) | 40,
+            0x00FF0000,
+            (30 << 16) | 120,
+            0x2C808080,
+            (100 << 16) | 100,
+            0,
+            (100 << 16) | 164,
+            (0x11A << 16) | 4,
+            (164 << 16) | 100,
+            4 << 8,
+            (164 << 16) | 164,
+            (4 << 8) | 4,
+            0x62404040,
+            (50 << 16) | 50,
+            (80 << 16) | 80,
+            0x4000FFFF,
+            (5 << 16) | 200,
+            (230 << 16) | 20,
+        ];
+        let mut native = Gp0::new();
+        for word in scene {
+            native.write(word);
+        }
+        native.run(u32::MAX);
+        for scale in [2, 4] {
+            let mut gp0 = Gp0::new();
+            gp0.set_resolution_scale(scale);
+            for word in scene {
+                gp0.write(word);
             }
+            gp0.run(u32::MAX);
+            assert!(
+                (0..1024 * 512).all(|addr| gp0.vram.read(addr) == native.vram.read(addr)),
+                "scale {scale}"
+            );
         }
+        assert_ne!(native.vram.read(1024 * 300 + 700), 0);
+        assert_ne!(native.vram.read(1024 * 130 + 130), 0);
     }
 
-    fn get_texel_4bit(&self, x: u32, y: u32, clut: (u16, u16), tex_page: (u16, u16)) -> u16 {
-        // Get texel at (x,y) relative to to the texture page
-        let tex_addr =
-            x as usize / 4 + tex_page.0 as usize + 1024 * (y as usize + tex_page.1 as usize);
-        let texel = self.read_vram(tex_addr);
-        // Get the index offset for current pixel to be used in the clut
-        let index = (texel >> (4 * (x % 4))) & 0xF;
-        let clut_addr = 1024 * (clut.1 as usize) + clut.0 as usize + index as usize;
-        self.read_vram(clut_addr)
+    // Two 8x8 rects overlapping in the 4x4 square at (4, 4), viewed through the overlay
+    fn overlapping_rects(view: DebugView) -> Gp0 {
+        let mut gp0 = Gp0::new();
+        gp0.overlay.set_view(view);
+        let red = [0x600000FF, 0, (8 << 16) | 8];
+        let green = [0x6000FF00, (4 << 16) | 4, (8 << 16) | 8];
+        for word in [0xE3000000, 0xE4000000 | (511 << 10) | 1023] {
+            gp0.write(word);
+        }
+        for word in red.into_iter().chain(green) {
+            gp0.write(word);
+        }
+        gp0.run(u32::MAX);
+        gp0
     }
 
-    fn get_texel_8bit(&self, x: u32, y: u32, clut: (u16, u16), tex_page: (u16, u16)) -> u16 {
-        // Get texel at (x,y) relative to to the texture page
-        let tex_addr =
-            x as usize / 2 + tex_page.0 as usize + 1024 * (y as usize + tex_page.1 as usize);
-        let texel = self.read_vram(tex_addr);
-        // Get the index offset for current pixel to be used in the clut
-        let index = (texel >> (8 * (x % 2))) & 0xFF;
-        let clut_addr = 1024 * (clut.1 as usize) + clut.0 as usize + index as usize;
-        self.read_vram(clut_addr)
+    #[test]
+    fn overdraw_counts_overlapping_rects() {
+        let gp0 = overlapping_rects(DebugView::Overdraw);
+        assert_eq!(gp0.overlay.overdraw_count(0, 0), 1);
+        assert_eq!(gp0.overlay.overdraw_count(4, 4), 2);
+        assert_eq!(gp0.overlay.overdraw_count(7, 7), 2);
+        assert_eq!(gp0.overlay.overdraw_count(11, 11), 1);
+        assert_eq!(gp0.overlay.overdraw_count(12, 12), 0);
+
+        // VRAM is the same as without the overlay
+        let plain = overlapping_rects(DebugView::Off);
+        assert!((0..1024 * 16).all(|addr| gp0.vram.read(addr) == plain.vram.read(addr)));
     }
 
-    fn get_texel_15bit(&self, x: u32, y: u32, tex_page: (u16, u16)) -> u16 {
-        let vram_x = (x as usize + tex_page.0 as usize) % 1024;
-        let vram_y = (y as usize + tex_page.1 as usize) % 512;
-        let tex_addr = vram_x + 1024 * vram_y;
-        self.read_vram(tex_addr)
+    #[test]
+    fn wireframe_draws_only_the_edges() {
+        let gp0 = overlapping_rects(DebugView::Wireframe);
+        for (x, y) in [(0, 0), (7, 0), (0, 7), (4, 4), (11, 11), (7, 3)] {
+            assert_ne!(gp0.overlay.overdraw_count(x, y), 0, "({x}, {y})");
+        }
+        for (x, y) in [(2, 2), (9, 9), (12, 12)] {
+            assert_eq!(gp0.overlay.overdraw_count(x, y), 0, "({x}, {y})");
+        }
     }
 
-    fn get_color_from_uv(
-        &self,
-        u: u32,
-        v: u32,
-        clut: (u16, u16),
-        tex_page: (u16, u16),
-        tex_page_color: TextureBits,
-    ) -> u16 {
-        let mask_x = self.texture_window & 0x1F;
-        let mask_y = (self.texture_window >> 5) & 0x1F;
-        let offset_x = (self.texture_window >> 10) & 0x1F;
-        let offset_y = (self.texture_window >> 15) & 0x1F;
-
-        let u = (u & !(mask_x * 8)) | (8 * (mask_x & offset_x));
-        let v = (v & !(mask_y * 8)) | (8 * (mask_y & offset_y));
-
-        match tex_page_color {
-            TextureBits::Four => {
-                self.get_texel_4bit(u, v, (clut.0, clut.1), (tex_page.0, tex_page.1))
-            }
-            TextureBits::Eight => {
-                self.get_texel_8bit(u, v, (clut.0, clut.1), (tex_page.0, tex_page.1))
-            }
-            TextureBits::Fifteen | TextureBits::Reserved => {
-                self.get_texel_15bit(u, v, (tex_page.0, tex_page.1))
+    #[test]
+    fn nops_between_commands_change_nothing() {
+        let run = |words: &[u32]| {
+            let mut gp0 = Gp0::new();
+            for &word in words {
+                gp0.write(word);
             }
-        }
+            gp0.run(u32::MAX);
+            gp0
+        };
+        let rect = [
+            0xE4000000 | (63 << 10) | 63,
+            0x60123456,
+            (2 << 16) | 3,
+            (5 << 16) | 7,
+        ];
+        let plain = run(&rect);
+        // NOPs, unknown misc opcodes and a texture cache clear, all without parameters
+        let padded = run(&[
+            0x00000000, rect[0], 0x00FFFFFF, 0x01000000, 0x03000000, rect[1], rect[2], rect[3],
+            0x00000000, 0x1E000000,
+        ]);
+        assert!(matches!(padded.state, Gp0State::WaitingForCommand));
+        assert!((0..1024 * 64).all(|addr| padded.vram.read(addr) == plain.vram.read(addr)));
+        assert_ne!(plain.vram.read(1024 * 2 + 3), 0);
+        assert!(!padded.irq_request);
+
+        let irq = run(&[0x1F000000]);
+        assert!(irq.irq_request);
     }
 
-    pub fn is_sending_data(&self) -> bool {
-        matches!(self.state, Gp0State::SendingData(_))
+    #[test]
+    fn odd_width_upload_ends_on_the_last_pixel() {
+        let mut gp0 = Gp0::new();
+        gp0.write(0xA0000000);
+        gp0.write((20 << 16) | 10);
+        gp0.write((3 << 16) | 7);
+        // 21 pixels take 11 words, the upper half of the last one is padding
+        for i in 0..11 {
+            assert!(matches!(gp0.state, Gp0State::ReceivingData(_)));
+            gp0.write(((2 * i + 2) << 16) | (2 * i + 1));
+        }
+        assert!(matches!(gp0.state, Gp0State::WaitingForCommand));
+
+        for i in 0..21 {
+            let (x, y) = (10 + i % 7, 20 + i / 7);
+            assert_eq!(gp0.vram.read(1024 * y + x), i as u16 + 1);
+        }
+        assert_eq!(gp0.vram.read(1024 * 22 + 17), 0);
+        assert_eq!(gp0.vram.read(1024 * 23 + 10), 0);
     }
-}
 
-// Color is in rgb
-fn dither(color: (u8, u8, u8), pixel: (u32, u32)) -> (u8, u8, u8) {
-    let offset = DITHER_TABLE[(pixel.0 & 0b11) as usize][(pixel.1 & 0b11) as usize];
+    #[test]
+    fn upload_rows_run_along_x() {
+        let mut gp0 = Gp0::new();
+        for word in [0xA0000000, (100 << 16) | 300, (2 << 16) | 4] {
+            gp0.write(word);
+        }
+        for word in [0x2222_1111, 0x4444_3333, 0x6666_5555, 0x8888_7777] {
+            gp0.write(word);
+        }
+        assert!(matches!(gp0.state, Gp0State::WaitingForCommand));
 
-    (
-        color.0.saturating_add_signed(offset),
-        color.1.saturating_add_signed(offset),
-        color.2.saturating_add_signed(offset),
-    )
-}
+        let row = |gp0: &Gp0, y: usize| -> Vec<u16> {
+            (299..305).map(|x| gp0.vram.read(1024 * y + x)).collect()
+        };
+        assert_eq!(row(&gp0, 99), [0; 6]);
+        assert_eq!(row(&gp0, 100), [0, 0x1111, 0x2222, 0x3333, 0x4444, 0]);
+        assert_eq!(row(&gp0, 101), [0, 0x5555, 0x6666, 0x7777, 0x8888, 0]);
+        assert_eq!(row(&gp0, 102), [0; 6]);
+    }
 
-fn param_limits(command: Commands) -> u8 {
-    match command {
-        Commands::Rectangle => 1,
-        Commands::SizeRectangle => 2,
-        Commands::TexturedRectangle => 2,
-        Commands::TexturedSizeRectangle => 3,
-        Commands::VramToVram => 2,
-        Commands::CpuToVram => 1,
-        Commands::VramToCpu => 1,
-        Commands::VramFill => 2,
+    #[test]
+    fn draw_area_y_is_clamped_to_vram() {
+        for scale in [1, 2] {
+            let gp0 = draw_past_vram(scale);
+            assert_eq!(gp0.draw_area_bot_right, (1023, 511));
+            assert_eq!(gp0.vram.read(1024 * 500 + 1000), 0x001F);
+            assert_eq!(gp0.vram.read(1024 * 511 + 1023), 0x001F);
+            assert_eq!(gp0.vram.read(1024 * 490 + 990), 0x03E0);
+            assert_eq!(gp0.vram.read(1024 * 511 + 993), 0x03E0);
+        }
     }
 }
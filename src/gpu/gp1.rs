@@ -1,7 +1,9 @@
+use serde::{Deserialize, Serialize};
 use tracing::{Level, event, span};
 
+#[derive(Serialize, Deserialize)]
 pub struct Gp1 {
-    pub display_enable: bool,
+    pub display_disabled: bool,
     pub irq: bool,
     pub dma_direction: u8,
     pub display_x: u16,             // 0-1023, 10 bits
@@ -10,23 +12,21 @@ pub struct Gp1 {
     pub vertical_range: (u16, u16), // 10 bits each
     pub display_mode: u8,
     pub color_depth: bool,
-    pub gpu_read_register: u8,
     pub vram_size: bool,
 }
 
 impl Gp1 {
     pub fn new() -> Self {
         Self {
-            display_enable: false,
+            display_disabled: true,
             irq: false,
             dma_direction: 0,
             display_x: 0,
             display_y: 0,
-            horizon_range: (0, 0),
-            vertical_range: (0, 0),
+            horizon_range: (0x200, 0xC00),
+            vertical_range: (0x10, 0x100),
             display_mode: 0,
             color_depth: false,
-            gpu_read_register: 0,
             vram_size: false,
         }
     }
@@ -36,10 +36,11 @@ impl Gp1 {
         let _ = span.enter();
         event!(target: "ps1_emulator::GPU", Level::DEBUG, "Write to GP1: {:08X}", val);
 
-        match val >> 24 {
+        // Commands 0x40..0xFF are mirrors of 0x00..0x3F
+        match (val >> 24) & 0x3F {
             0x00 => {
                 // Reset GPU
-                self.display_enable = false;
+                self.display_disabled = true;
                 self.irq = false;
                 self.dma_direction = 0;
                 self.display_x = 0;
@@ -47,6 +48,7 @@ impl Gp1 {
                 self.horizon_range = (0x200, 0xC00);
                 self.vertical_range = (0x10, 0x100);
                 self.display_mode = 0;
+                self.color_depth = false;
             }
             0x01 => {
                 // Reset Command Buffer
@@ -56,8 +58,8 @@ impl Gp1 {
                 self.irq = false;
             }
             0x03 => {
-                // Display enable
-                self.display_enable = val & 0x1 > 0;
+                // Display enable (0 = on, 1 = off)
+                self.display_disabled = val & 0x1 > 0;
             }
             0x04 => {
                 // DMA Direction/Data Request
@@ -88,19 +90,83 @@ impl Gp1 {
                 self.vram_size = val & 0x1 > 0;
             }
             0x10..=0x1F => {
-                // Read GPU Internal Register
-                let register = (val & 0x0F) as u8;
-                match register {
-                    0x00 | 0x01 | 0x06 => {
-                        // do nothing
-                    }
-                    _ => self.gpu_read_register = register,
-                }
+                // Read GPU Internal Register. The value is latched by the GPU for GPUREAD
             }
             0x20 => {
                 // VRAM Size v1 -- Probably not used but check to confirm
             }
-            _ => panic!("Invalid GP1 command"),
+            _ => {
+                event!(target: "ps1_emulator::GPU", Level::WARN, "Unknown GP1 command {:08X}", val);
+            }
+        }
+    }
+
+    // Horizontal resolution 2 (368 pixels) overrides horizontal resolution 1
+    pub fn horizontal_res(&self) -> u16 {
+        if self.display_mode & 0x40 > 0 {
+            return 368;
+        }
+
+        match self.display_mode & 0b11 {
+            0 => 256,
+            1 => 320,
+            2 => 512,
+            3 => 640,
+            _ => panic!("Impossible"),
         }
     }
+
+    // GPU cycles per dot at the horizontal resolution
+    pub fn dot_divider(&self) -> u32 {
+        match self.horizontal_res() {
+            256 => 10,
+            320 => 8,
+            368 => 7,
+            512 => 5,
+            640 => 4,
+            _ => panic!("Impossible"),
+        }
+    }
+
+    // Vertical resolution is only 480 when interlace is also enabled
+    pub fn vertical_res(&self) -> u16 {
+        if self.display_mode & 0x4 > 0 && self.interlaced() {
+            480
+        } else {
+            240
+        }
+    }
+
+    pub fn is_pal(&self) -> bool {
+        self.display_mode & 0x8 > 0
+    }
+
+    pub fn interlaced(&self) -> bool {
+        self.display_mode & 0x20 > 0
+    }
+
+    // GPUSTAT bits 14 and 16-24 and 29-30 which are set through GP1
+    pub fn status_bits(&self) -> u32 {
+        let reverse_flag = ((self.display_mode as u32 >> 7) & 1) << 14;
+        let hres2 = ((self.display_mode as u32 >> 6) & 1) << 16;
+        let hres1 = (self.display_mode as u32 & 0b11) << 17;
+        let vres = ((self.display_mode as u32 >> 2) & 1) << 19;
+        let video_mode = ((self.display_mode as u32 >> 3) & 1) << 20;
+        let color_depth = (self.color_depth as u32) << 21;
+        let interlace = ((self.display_mode as u32 >> 5) & 1) << 22;
+        let display_disabled = (self.display_disabled as u32) << 23;
+        let irq = (self.irq as u32) << 24;
+        let dma_direction = (self.dma_direction as u32) << 29;
+
+        reverse_flag
+            | hres2
+            | hres1
+            | vres
+            | video_mode
+            | color_depth
+            | interlace
+            | display_disabled
+            | irq
+            | dma_direction
+    }
 }
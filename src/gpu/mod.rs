@@ -1,19 +1,66 @@
 mod gp0;
 mod gp1;
-mod rasterize;
+mod overlay;
+mod rasterizer;
 
-use gp0::Gp0;
+use gp0::{FrameStats, Gp0};
 use gp1::Gp1;
+pub use overlay::DebugView;
 
+use std::path::Path;
+use std::{fs, io, mem};
+
+use eframe::egui::Color32;
+use serde::{Deserialize, Serialize};
 use tracing::{Level, event};
 
+#[derive(Serialize, Deserialize)]
 pub struct Gpu {
     pub gp0: Gp0,
     pub gp1: Gp1,
-    pub frame_is_ready: bool,
-    pub counter: u64,
-    pub hblank_counter: u16,
-    pub dotclock_counter: u16,
+    frame_is_ready: bool,      // Latched at vblank until taken by the frontend
+    pub interlace_field: bool, // true when the odd field is being displayed
+    pub gpuread_latch: u32,
+    pub line_cycles: u32,
+    cycle_remainder: u32,
+    dot_remainder: u32,
+    pub scanline: u16,
+    last_frame_stats: FrameStats,
+    #[serde(skip)]
+    pub display_options: DisplayOptions, // How the frontend wants the picture taken from VRAM
+}
+
+// How much of the display area is shown, picked by the user
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(default)]
+pub struct DisplayOptions {
+    pub range: DisplayRange,
+    // Picture pixels cut from each edge
+    pub crop_left: u16,
+    pub crop_right: u16,
+    pub crop_top: u16,
+    pub crop_bottom: u16,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum DisplayRange {
+    // Sized by the horizontal and vertical display ranges, as a TV shows it
+    #[default]
+    Precise,
+    // The whole 4:3 picture of the display mode, for games that program unusual ranges
+    Standard,
+}
+
+// Video timing events produced by a call to Gpu::tick
+#[derive(Default)]
+pub struct TickEvents {
+    pub dotclocks: u32,
+    pub hblanks: u32,
+    pub vblank_start: bool,
+    pub vblank_end: bool,
+    pub irq: bool,
+    pub in_hblank: bool,
+    pub in_vblank: bool,
 }
 
 impl Gpu {
@@ -22,15 +69,49 @@ impl Gpu {
             gp0: Gp0::new(),
             gp1: Gp1::new(),
             frame_is_ready: false,
-            counter: 0,
-            hblank_counter: 0,
-            dotclock_counter: 0,
+            interlace_field: true,
+            gpuread_latch: 0,
+            line_cycles: 0,
+            cycle_remainder: 0,
+            dot_remainder: 0,
+            scanline: 0,
+            last_frame_stats: FrameStats::default(),
+            display_options: DisplayOptions::default(),
         }
     }
 
     pub fn gp1_write(&mut self, val: u32) {
         self.gp1.write(val);
         self.gp0.vram_size_set = self.gp1.vram_size;
+        self.update_display_field();
+
+        match (val >> 24) & 0x3F {
+            0x00 => self.gp0.reset(),
+            0x01 => self.gp0.reset_command_buffer(),
+            0x10..=0x1F => self.latch_internal_register(val),
+            _ => {}
+        }
+    }
+
+    // GP1(0x10) copies a GPU internal register into GPUREAD
+    fn latch_internal_register(&mut self, val: u32) {
+        let gp0 = &self.gp0;
+        self.gpuread_latch = match val & 0x7 {
+            // Texture Window
+            0x02 => gp0.texture_window,
+            // Draw Area Top Left
+            0x03 => gp0.draw_area_top_left.0 | (gp0.draw_area_top_left.1 << 10),
+            // Draw Area Bottom Right
+            0x04 => gp0.draw_area_bot_right.0 | (gp0.draw_area_bot_right.1 << 10),
+            // Draw Offset
+            0x05 => {
+                (gp0.draw_offset.0 as u32 & 0x7FF) | ((gp0.draw_offset.1 as u32 & 0x7FF) << 11)
+            }
+            // GPU Version
+            0x07 => 0x2,
+            // 0x00, 0x01 and 0x06 leave GPUREAD unchanged
+            _ => self.gpuread_latch,
+        };
     }
 
     pub fn gpuread(&mut self) -> u32 {
@@ -41,11 +122,7 @@ impl Gpu {
             return self.gp0.vram_to_cpu_process();
         }
 
-        match self.gp1.gpu_read_register {
-            0x00 | 0x01 | 0x06 => 0,
-            0x07 => 0x2,
-            _ => panic!("Impossible state for GP1 Read Register"),
-        }
+        self.gpuread_latch
     }
 
     pub fn gpustat(&mut self) -> u32 {
@@ -61,7 +138,18 @@ impl Gpu {
         let display_draw = (self.gp0.draw_to_display as u32) << 10;
         let force_mask_bit = (self.gp0.mask_while_draw as u32) << 11;
         let texture_mask = (self.gp0.mask_before_draw as u32) << 12;
+        let interlace_field = (self.interlace_field as u32) << 13;
         let two_mb = (self.gp0.two_mb_mem as u32) << 15;
+        let display_bits = self.gp1.status_bits();
+
+        // DMA / Data Request depends on the DMA direction
+        let dma_request = match self.gp1.dma_direction {
+            0 => 0,
+            1 => 1,
+            2 => self.gp0.dma_ready() as u32,
+            3 => self.gp0.is_sending_data() as u32,
+            _ => panic!("Impossible"),
+        } << 25;
 
         let output = dma_ready
             + vram_data_ready
@@ -74,87 +162,390 @@ impl Gpu {
             + semitransparency
             + tex_page_y
             + tex_page_x
-            + two_mb;
+            + interlace_field
+            + two_mb
+            + dma_request
+            + display_bits
+            + ((self.drawing_odd_line() as u32) << 31);
 
         event!(target: "ps1_emulator::GPU", Level::DEBUG, "Reading GPUSTAT: {:08X}", output);
 
         output
     }
 
-    pub fn tick(&mut self, cycles: u32) -> bool {
-        self.counter += cycles as u64;
-
-        // dots counter
-        let dot_wrap_value = match self.gp1.display_mode & 0b11 {
-            // 256 pix horizontal
-            0 => 2146 / 10,
-            // 320 p
-            1 => 2146 / 8,
-            // 512 p
-            2 => 2146 / 5,
-            // 640 p
-            3 => 2146 / 4,
-            _ => panic!("Impossible")
-        };
+    // CPU cycles until the GPU finishes drawing the current command
+    pub fn busy_cpu_cycles(&self) -> u32 {
+        (self.gp0.busy_cycles * 7).div_ceil(11)
+    }
+
+    // The GPU clock runs at 11/7 of the CPU clock. Reports the video timing events that
+    // happened during the given number of CPU cycles
+    pub fn tick(&mut self, cycles: u32) -> TickEvents {
+        let mut events = TickEvents::default();
+
+        self.cycle_remainder += cycles * 11;
+        let gpu_cycles = self.cycle_remainder / 7;
+        self.cycle_remainder %= 7;
 
-        if self.counter % dot_wrap_value == 0 {
-            self.dotclock_counter += 1;
-            self.dotclock_counter %= dot_wrap_value as u16;
+        self.gp0.run(gpu_cycles);
+
+        // GP0(0x1F) only interrupts on the rising edge of the GPUSTAT IRQ flag
+        if mem::take(&mut self.gp0.irq_request) && !self.gp1.irq {
+            self.gp1.irq = true;
+            events.irq = true;
         }
 
+        // dots counter, the divider depends on the horizontal resolution
+        let dot_divider = self.gp1.dot_divider();
+
+        self.dot_remainder += gpu_cycles;
+        events.dotclocks = self.dot_remainder / dot_divider;
+        self.dot_remainder %= dot_divider;
+
         // hblank counter
-        if self.counter % 2146 == 0 {
-            self.hblank_counter += 1;
-            self.hblank_counter %= 263;
+        self.line_cycles += gpu_cycles;
+        while self.line_cycles >= self.cycles_per_line() {
+            self.line_cycles -= self.cycles_per_line();
+            self.scanline += 1;
+            events.hblanks += 1;
+
+            if self.scanline == self.vblank_start() {
+                events.vblank_start = true;
+            }
+
+            if self.scanline >= self.lines_per_frame() {
+                self.scanline = 0;
+                events.vblank_end = true;
+            }
         }
 
+        let (x1, x2) = self.gp1.horizon_range;
+        events.in_hblank = !(x1 as u32..x2 as u32).contains(&self.line_cycles);
+        events.in_vblank = self.scanline >= self.vblank_start();
+
         // Frame counter
-        if self.counter >= 564480 {
+        if events.vblank_start {
             event!(target: "ps1_emulator::GPU", Level::DEBUG, "Render Frame");
-            self.counter -= 564480;
             self.frame_is_ready = true;
+            self.last_frame_stats = mem::take(&mut self.gp0.stats);
+
+            // Interlaced modes alternate fields every frame. Otherwise bit 13 is always set
+            if self.gp1.interlaced() {
+                self.interlace_field = !self.interlace_field;
+            } else {
+                self.interlace_field = true;
+            }
+            self.update_display_field();
+        }
+
+        events
+    }
+
+    // GP0 statistics for the last completed frame
+    pub fn frame_stats(&self) -> FrameStats {
+        self.last_frame_stats
+    }
+
+    // Returns true once for each frame completed since the last call
+    pub fn take_frame_ready(&mut self) -> bool {
+        mem::take(&mut self.frame_is_ready)
+    }
+
+    // GPU cycles per scanline. PAL is selected with GP1(0x08) bit 3
+    pub fn cycles_per_line(&self) -> u32 {
+        if self.gp1.is_pal() { 3406 } else { 3413 }
+    }
+
+    pub fn lines_per_frame(&self) -> u16 {
+        if self.gp1.is_pal() { 314 } else { 263 }
+    }
+
+    // Vblank begins at the end of the vertical display range
+    fn vblank_start(&self) -> u16 {
+        self.gp1.vertical_range.1.clamp(1, self.lines_per_frame() - 1)
+    }
+
+    // Nominal frames per second for the current video mode
+    pub fn refresh_rate(&self) -> f64 {
+        let gpu_clock = 33_868_800.0 * 11.0 / 7.0;
+        gpu_clock / (self.cycles_per_line() as f64 * self.lines_per_frame() as f64)
+    }
+
+    // GPUSTAT bit 31. In interlaced modes this follows the field, otherwise it toggles every
+    // scanline. Always 0 during vblank
+    pub fn drawing_odd_line(&self) -> bool {
+        let (y1, y2) = self.gp1.vertical_range;
+        if !(y1..y2).contains(&self.scanline) {
+            return false;
+        }
+
+        if self.gp1.interlaced() {
+            self.interlace_field
         } else {
-            self.frame_is_ready = false;
+            self.scanline & 1 > 0
         }
-        self.frame_is_ready
     }
 
-    pub fn render_vram(&self) -> Vec<u8> {
-        if self.gp1.color_depth {
-            let mut output = Vec::with_capacity(349184);
-            for y in 0..512 {
-                for x in 0..682 {
-                    let base_addr = 2048 * y + 3 * x;
-                    let r = self.gp0.vram[base_addr];
-                    let g = self.gp0.vram[base_addr + 1];
-                    let b = self.gp0.vram[base_addr + 2];
+    // In 480i the GPU does not draw to the lines of the field currently being displayed
+    // (unless GP0(0xE1) allows drawing to the display area)
+    fn update_display_field(&mut self) {
+        self.gp0.display_field = if self.gp1.interlaced() && self.gp1.vertical_res() == 480 {
+            Some(self.interlace_field as usize)
+        } else {
+            None
+        };
+    }
+
+    // (width, height) of the picture output by the display ranges, in VRAM pixels
+    fn display_size(&self) -> (usize, usize) {
+        let max_width = self.gp1.horizontal_res() as usize;
+        let max_height = self.gp1.vertical_res() as usize;
+        if self.display_options.range == DisplayRange::Standard {
+            return (max_width, max_height);
+        }
 
-                    output.push(r);
-                    output.push(g);
-                    output.push(b);
+        // Pixels output are the dots in the horizontal display range, rounded to 4 pixels
+        let (x1, x2) = self.gp1.horizon_range;
+        let dots = (x2.saturating_sub(x1) as u32 / self.gp1.dot_divider()) as usize;
+        let width = if dots == 0 {
+            max_width
+        } else {
+            ((dots + 2) & !3).min(max_width)
+        };
+
+        // Number of scanlines output is given by the vertical display range, doubled for 480 lines
+        let (y1, y2) = self.gp1.vertical_range;
+        let lines = (y2.saturating_sub(y1) as usize) * (max_height / 240);
+        let height = if lines == 0 { max_height } else { lines.min(max_height) };
+
+        (width, height)
+    }
+
+    // (left, top, width, height) of the shown part of the picture after cropping. Left and top
+    // are offsets from the display area start. At least one pixel is always left
+    fn visible_area(&self) -> (usize, usize, usize, usize) {
+        let (width, height) = self.display_size();
+        let options = &self.display_options;
+        let left = (options.crop_left as usize).min(width - 1);
+        let top = (options.crop_top as usize).min(height - 1);
+        let width = (width - left)
+            .saturating_sub(options.crop_right as usize)
+            .max(1);
+        let height = (height - top)
+            .saturating_sub(options.crop_bottom as usize)
+            .max(1);
+        (left, top, width, height)
+    }
+
+    // Extracts the visible picture from VRAM using the display area start, display range,
+    // display mode and cropping. Returns the (width, height) of the picture written to out,
+    // which is scaled up when rendering at an increased internal resolution
+    pub fn render_display(&self, out: &mut Vec<Color32>) -> (usize, usize) {
+        let (left, top, width, height) = self.visible_area();
+
+        let start_x = self.gp1.display_x as usize;
+        let start_y = self.gp1.display_y as usize + top;
+
+        out.clear();
+
+        // The increased internal resolution is only used for 15 bit output
+        if let Some(hires) = &self.gp0.hires
+            && !self.gp1.color_depth
+        {
+            let scale = hires.scale();
+            out.reserve(width * height * scale * scale);
+            for y in 0..height * scale {
+                let row = (scale * start_y + y) % (512 * scale);
+                for x in 0..width * scale {
+                    let x = scale * (start_x + left) + x;
+                    let pixel = hires.read(x % (1024 * scale), row);
+                    out.push(Color32::from_rgb(
+                        convert_5bit_to_8bit(pixel & 0x1F),
+                        convert_5bit_to_8bit((pixel >> 5) & 0x1F),
+                        convert_5bit_to_8bit((pixel >> 10) & 0x1F),
+                    ));
                 }
             }
 
-            output
+            return (width * scale, height * scale);
+        }
+
+        out.reserve(width * height);
+        for y in 0..height {
+            let row = (start_y + y) % 512;
+            for x in left..left + width {
+                let color = if self.gp1.color_depth {
+                    // 24 bit mode. Display start x is still in 16 bit pixel units
+                    let byte_addr = 2048 * row + (2 * start_x + 3 * x) % 2048;
+                    Color32::from_rgb(
+                        self.gp0.vram[byte_addr],
+                        self.gp0.vram[(byte_addr + 1) % 1048576],
+                        self.gp0.vram[(byte_addr + 2) % 1048576],
+                    )
+                } else {
+                    let pixel = self.gp0.vram.read(1024 * row + (start_x + x) % 1024);
+                    Color32::from_rgb(
+                        convert_5bit_to_8bit(pixel & 0x1F),
+                        convert_5bit_to_8bit((pixel >> 5) & 0x1F),
+                        convert_5bit_to_8bit((pixel >> 10) & 0x1F),
+                    )
+                };
+                out.push(color);
+            }
+        }
+
+        (width, height)
+    }
+
+    // (x, y, width, height) of the shown part of the display area in VRAM pixels
+    pub fn display_area(&self) -> (usize, usize, usize, usize) {
+        let (left, top, width, height) = self.visible_area();
+        (
+            self.gp1.display_x as usize + left,
+            self.gp1.display_y as usize + top,
+            width,
+            height,
+        )
+    }
+
+    pub fn vram_pixel(&self, x: usize, y: usize) -> u16 {
+        self.gp0.vram.read(1024 * (y % 512) + x % 1024)
+    }
+
+    // A 256x256 texture page at (x, y) in VRAM read through the CLUT at clut, as textured
+    // primitives see it with 4 or 8 bit texels
+    pub fn render_texture_page(
+        &self,
+        (x, y): (usize, usize),
+        clut: (usize, usize),
+        eight_bit: bool,
+        out: &mut Vec<Color32>,
+    ) {
+        out.clear();
+        for v in 0..256 {
+            for u in 0..256 {
+                let index = if eight_bit {
+                    (self.vram_pixel(x + u / 2, y + v) >> ((u % 2) * 8)) & 0xFF
+                } else {
+                    (self.vram_pixel(x + u / 4, y + v) >> ((u % 4) * 4)) & 0xF
+                };
+                let pixel = self.vram_pixel(clut.0 + index as usize, clut.1);
+                out.push(Color32::from_rgb(
+                    convert_5bit_to_8bit(pixel & 0x1F),
+                    convert_5bit_to_8bit((pixel >> 5) & 0x1F),
+                    convert_5bit_to_8bit((pixel >> 10) & 0x1F),
+                ));
+            }
+        }
+    }
+
+    // Renders the debug view over the display area and starts collecting the next frame
+    pub fn render_debug_overlay(&mut self, out: &mut Vec<Color32>) -> (usize, usize) {
+        let (x, y, width, height) = self.display_area();
+        let (start, size) = ((x, y), (width, height));
+
+        self.gp0.overlay.render(start, size, out);
+        self.gp0.overlay.clear();
+
+        size
+    }
+
+    // VRAM rows written since the last call
+    pub fn take_dirty_rows(&mut self) -> Vec<usize> {
+        self.gp0.vram.take_dirty_rows()
+    }
+
+    // Converts VRAM to rgb bytes in out, which is kept between frames so only rows written since
+    // the previous call are converted again. Returns the number of rows converted
+    pub fn render_vram(&mut self, out: &mut Vec<u8>) -> usize {
+        // 24 bit mode shows 682 pixels per row, 15 bit mode shows 1024
+        let width = if self.gp1.color_depth { 682 } else { 1024 };
+
+        let rows = if out.len() != 3 * width * 512 {
+            out.clear();
+            out.resize(3 * width * 512, 0);
+            self.take_dirty_rows();
+            (0..512).collect()
         } else {
-            let mut output = Vec::with_capacity(1572864);
-            for addr in 0..524288 {
-                let pixel =
-                    u16::from_le_bytes([self.gp0.vram[2 * addr], self.gp0.vram[2 * addr + 1]]);
-                let r = convert_5bit_to_8bit(pixel & 0x1F);
-                let g = convert_5bit_to_8bit((pixel >> 5) & 0x1F);
-                let b = convert_5bit_to_8bit((pixel >> 10) & 0x1F);
+            self.take_dirty_rows()
+        };
 
-                output.push(r);
-                output.push(g);
-                output.push(b);
+        for &y in &rows {
+            let row = &mut out[3 * width * y..3 * width * (y + 1)];
+            if self.gp1.color_depth {
+                row.copy_from_slice(&self.gp0.vram[2048 * y..2048 * y + 3 * width]);
+            } else {
+                self.convert_row_15bit(y, row);
             }
+        }
+
+        rows.len()
+    }
+
+    fn convert_row_15bit(&self, y: usize, row: &mut [u8]) {
+        for (x, rgb) in row.chunks_exact_mut(3).enumerate() {
+            let pixel = self.gp0.vram.read(1024 * y + x);
+            rgb[0] = convert_5bit_to_8bit(pixel & 0x1F);
+            rgb[1] = convert_5bit_to_8bit((pixel >> 5) & 0x1F);
+            rgb[2] = convert_5bit_to_8bit((pixel >> 10) & 0x1F);
+        }
+    }
 
-            output
+    // Raw VRAM dump: the VRAM_DUMP_MAGIC header, width and height as u16 LE, then the 1MB of VRAM
+    pub fn dump_vram(&self, path: &Path) -> io::Result<()> {
+        let mut data = Vec::with_capacity(VRAM_DUMP_HEADER + 1048576);
+        data.extend_from_slice(VRAM_DUMP_MAGIC);
+        data.extend_from_slice(&1024u16.to_le_bytes());
+        data.extend_from_slice(&512u16.to_le_bytes());
+        data.extend_from_slice(&self.gp0.vram[..]);
+
+        fs::write(path, data)
+    }
+
+    pub fn load_vram(&mut self, path: &Path) -> io::Result<()> {
+        let data = fs::read(path)?;
+
+        let valid = data.len() == VRAM_DUMP_HEADER + 1048576
+            && data.starts_with(VRAM_DUMP_MAGIC)
+            && u16::from_le_bytes([data[8], data[9]]) == 1024
+            && u16::from_le_bytes([data[10], data[11]]) == 512;
+        if !valid {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Not a 1024x512 VRAM dump",
+            ));
         }
+
+        self.gp0.restore_vram(&data[VRAM_DUMP_HEADER..]);
+        Ok(())
+    }
+
+    // The whole of VRAM interpreted as 15 bit color, as 1024x512 rgb bytes
+    pub fn vram_rgb(&self) -> Vec<u8> {
+        let mut rgb = vec![0; 3 * 1024 * 512];
+        for (y, row) in rgb.chunks_exact_mut(3 * 1024).enumerate() {
+            self.convert_row_15bit(y, row);
+        }
+        rgb
+    }
+
+    // Saves the whole of VRAM interpreted as 15 bit color
+    pub fn dump_vram_png(&self, path: &Path) -> io::Result<()> {
+        let rgb = self.vram_rgb();
+
+        let mut encoder = png::Encoder::new(io::BufWriter::new(fs::File::create(path)?), 1024, 512);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&rgb))
+            .map_err(io::Error::other)
     }
 }
 
+const VRAM_DUMP_MAGIC: &[u8; 8] = b"PS1VRAM\0";
+const VRAM_DUMP_HEADER: usize = 12;
+
 #[repr(C)]
 #[derive(Default, Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 struct Color {
@@ -163,40 +554,579 @@ struct Color {
     b: u8,
 }
 
+// 5 bit color channels scaled to 8 bits, rounded to nearest
+const FIVE_TO_EIGHT_BIT: [u8; 32] = [
+    0, 8, 16, 25, 33, 41, 49, 58, 66, 74, 82, 90, 99, 107, 115, 123, 132, 140, 148, 156, 165, 173,
+    181, 189, 197, 206, 214, 222, 230, 239, 247, 255,
+];
+
 fn convert_5bit_to_8bit(color: u16) -> u8 {
-    match color {
-        0 => 0,
-        1 => 8,
-        2 => 16,
-        3 => 25,
-        4 => 33,
-        5 => 41,
-        6 => 49,
-        7 => 58,
-        8 => 66,
-        9 => 74,
-        10 => 82,
-        11 => 90,
-        12 => 99,
-        13 => 107,
-        14 => 115,
-        15 => 123,
-        16 => 132,
-        17 => 140,
-        18 => 148,
-        19 => 156,
-        20 => 165,
-        21 => 173,
-        22 => 181,
-        23 => 189,
-        24 => 197,
-        25 => 206,
-        26 => 214,
-        27 => 222,
-        28 => 230,
-        29 => 239,
-        30 => 247,
-        31 => 255,
-        _ => panic!("Impossible"),
+    FIVE_TO_EIGHT_BIT[(color & 0x1F) as usize]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timer::Timer;
+
+    fn gp0_commands(gpu: &mut Gpu, words: &[u32]) {
+        for &word in words {
+            gpu.gp0.write(word);
+        }
+        gpu.gp0.run(u32::MAX);
+    }
+
+    #[test]
+    fn gp1_reset_restores_gpustat() {
+        let mut gpu = Gpu::new();
+        gp0_commands(&mut gpu, &[0xE100063F, 0xE6000003]);
+        for command in [0x03000000, 0x04000002, 0x0800007F] {
+            gpu.gp1_write(command);
+        }
+        assert_ne!(gpu.gpustat(), 0x14802000);
+
+        gpu.gp1_write(0x00000000);
+        assert_eq!(gpu.gpustat(), 0x14802000);
+    }
+
+    #[test]
+    fn display_mode_round_trips_through_gpustat() {
+        let mut gpu = Gpu::new();
+        for mode in 0..0x80 {
+            gpu.gp1_write(0x08000000 | mode);
+            let expected = ((mode & 0x3) << 17)
+                | ((mode >> 6 & 1) << 16)
+                | ((mode >> 2 & 1) << 19)
+                | ((mode >> 3 & 1) << 20)
+                | ((mode >> 4 & 1) << 21)
+                | ((mode >> 5 & 1) << 22);
+            assert_eq!(gpu.gpustat() & 0x007F_0000, expected, "mode {mode:02X}");
+        }
+        // Display enable, DMA direction and the IRQ acknowledge have their own bits
+        gpu.gp1_write(0x03000000);
+        assert_eq!(gpu.gpustat() & (1 << 23), 0);
+        gpu.gp1_write(0x04000003);
+        assert_eq!(gpu.gpustat() >> 29 & 0x3, 3);
+        gp0_commands(&mut gpu, &[0x1F000000]);
+        gpu.tick(1);
+        assert_ne!(gpu.gpustat() & (1 << 24), 0);
+        gpu.gp1_write(0x02000000);
+        assert_eq!(gpu.gpustat() & (1 << 24), 0);
+    }
+
+    #[test]
+    fn display_area_is_taken_from_vram() {
+        let mut gpu = Gpu::new();
+        let pixel = |x: usize, y: usize| ((x * 7 + y * 31) & 0x7FFF) as u16;
+        for y in 0..512 {
+            for x in 0..1024 {
+                gpu.gp0.vram.write(1024 * y + x, pixel(x, y));
+            }
+        }
+        // 320x240 starting at (64, 16)
+        gpu.gp1_write(0x05000000 | (16 << 10) | 64);
+        gpu.gp1_write(0x06000000 | ((0x260 + 320 * 8) << 12) | 0x260);
+        gpu.gp1_write(0x07000000 | (0x100 << 10) | 0x10);
+        gpu.gp1_write(0x08000001);
+
+        let mut out = Vec::new();
+        assert_eq!(gpu.render_display(&mut out), (320, 240));
+        assert_eq!(gpu.display_area(), (64, 16, 320, 240));
+        for y in 0..240 {
+            for x in 0..320 {
+                let color = pixel(64 + x, 16 + y);
+                let expected = Color32::from_rgb(
+                    convert_5bit_to_8bit(color & 0x1F),
+                    convert_5bit_to_8bit((color >> 5) & 0x1F),
+                    convert_5bit_to_8bit((color >> 10) & 0x1F),
+                );
+                assert_eq!(out[320 * y + x], expected, "pixel ({x}, {y})");
+            }
+        }
+    }
+
+    // Runs until the next scanline starts, a few CPU cycles at a time
+    fn next_scanline(gpu: &mut Gpu) {
+        let scanline = gpu.scanline;
+        while gpu.scanline == scanline {
+            gpu.tick(64);
+        }
+    }
+
+    // GPUSTAT bit 31 for each scanline of the next frame
+    fn odd_line_bits(gpu: &mut Gpu) -> Vec<bool> {
+        while gpu.scanline != 0 {
+            next_scanline(gpu);
+        }
+        let mut bits = Vec::new();
+        loop {
+            bits.push(gpu.gpustat() >> 31 > 0);
+            next_scanline(gpu);
+            if gpu.scanline == 0 {
+                return bits;
+            }
+        }
+    }
+
+    #[test]
+    fn odd_line_bit_toggles_every_scanline_when_progressive() {
+        let mut gpu = Gpu::new();
+        let bits = odd_line_bits(&mut gpu);
+        assert_eq!(bits.len(), 263);
+        for (line, bit) in bits.into_iter().enumerate() {
+            // Always clear outside the vertical display range
+            let expected = (0x10..0x100).contains(&line) && line & 1 > 0;
+            assert_eq!(bit, expected, "line {line}");
+        }
+    }
+
+    #[test]
+    fn odd_line_bit_follows_the_field_when_interlaced() {
+        let mut gpu = Gpu::new();
+        gpu.gp1_write(0x08000024);
+        let mut fields = Vec::new();
+        for _ in 0..4 {
+            let bits = odd_line_bits(&mut gpu);
+            let field = bits[0x10];
+            for (line, bit) in bits.into_iter().enumerate() {
+                let expected = (0x10..0x100).contains(&line) && field;
+                assert_eq!(bit, expected, "line {line}");
+            }
+            // Bit 13 reports the same field
+            assert_eq!(gpu.gpustat() >> 13 & 1 > 0, gpu.interlace_field);
+            fields.push(field);
+        }
+        assert_eq!(fields, [fields[0], !fields[0], fields[0], !fields[0]]);
+    }
+
+    // CPU cycles and timer 1 hblank counts from one vblank to the next
+    fn frame_timing(gpu: &mut Gpu) -> (u32, u16) {
+        let mut timer = Timer::new(1);
+        timer.write_mode(0x100);
+        while !gpu.tick(16).vblank_start {}
+        let mut cycles = 0;
+        loop {
+            let events = gpu.tick(16);
+            timer.tick(16, &events);
+            cycles += 16;
+            if events.vblank_start {
+                return (cycles, timer.counter);
+            }
+        }
+    }
+
+    #[test]
+    fn pal_frames_are_longer() {
+        let mut gpu = Gpu::new();
+        let (cycles, hblanks) = frame_timing(&mut gpu);
+        assert!(cycles.abs_diff(3413 * 263 * 7 / 11) < 16, "{cycles} cycles");
+        assert_eq!(hblanks, 263);
+        assert!((gpu.refresh_rate() - 59.29).abs() < 0.01);
+
+        gpu.gp1_write(0x08000008);
+        frame_timing(&mut gpu);
+        let (cycles, hblanks) = frame_timing(&mut gpu);
+        assert!(cycles.abs_diff(3406 * 314 * 7 / 11) < 16, "{cycles} cycles");
+        assert_eq!(hblanks, 314);
+        assert!((gpu.refresh_rate() - 49.76).abs() < 0.01);
+    }
+
+    #[test]
+    fn ntsc_frame_has_263_hblanks() {
+        let mut gpu = Gpu::new();
+        while !gpu.tick(100).vblank_end {}
+        let (mut hblanks, mut vblanks) = (0, 0);
+        loop {
+            let events = gpu.tick(100);
+            hblanks += events.hblanks;
+            vblanks += events.vblank_start as u32;
+            if events.vblank_end {
+                break;
+            }
+        }
+        assert_eq!((hblanks, vblanks), (263, 1));
+    }
+
+    #[test]
+    fn dotclock_follows_the_horizontal_resolution() {
+        // 7000 CPU cycles are 11000 GPU cycles
+        for (mode, dots) in [
+            (0x00, 1100),
+            (0x01, 1375),
+            (0x40, 1571),
+            (0x02, 2200),
+            (0x03, 2750),
+        ] {
+            let mut gpu = Gpu::new();
+            gpu.gp1_write(0x08000000 | mode);
+            assert_eq!(gpu.tick(7000).dotclocks, dots, "mode {mode:02X}");
+        }
+    }
+
+    #[test]
+    fn flooded_fifo_reports_dma_ready_by_its_space() {
+        let mut gpu = Gpu::new();
+        gp0_commands(&mut gpu, &[0xE3000000, 0xE4000000 | (511 << 10) | 1023]);
+        // Large triangles keep the GPU drawing while more are queued
+        let triangle = [0x200000FF, 0, 200, 200 << 16];
+        for _ in 0..20 {
+            for word in triangle {
+                gpu.gp0.write(word);
+                let dma_ready = gpu.gpustat() & (1 << 28) > 0;
+                assert_eq!(dma_ready, !gpu.gp0.fifo_full());
+                assert_eq!(gpu.gpustat() & (1 << 26), 0);
+            }
+        }
+        assert!(gpu.gp0.fifo_full());
+
+        gpu.gp1_write(0x01000000);
+        assert!(!gpu.gp0.fifo_full());
+        assert_eq!(gpu.gpustat() & (0x5 << 26), 0x5 << 26);
+        // A half received command was dropped too
+        gpu.gp0.write(0x200000FF);
+        gpu.gp1_write(0x01000000);
+        gp0_commands(&mut gpu, &[0xE1000000 | 0x200]);
+        assert_ne!(gpu.gpustat() & (1 << 9), 0);
+    }
+
+    #[test]
+    fn mask_bit_protects_earlier_draws() {
+        let mut gpu = Gpu::new();
+        gp0_commands(&mut gpu, &[0xE3000000, 0xE4000000 | (511 << 10) | 1023]);
+        gp0_commands(&mut gpu, &[0xE6000001, 0x600000FF, 0, (4 << 16) | 4]);
+        assert_eq!(gpu.gpustat() >> 11 & 0x3, 1);
+        gp0_commands(
+            &mut gpu,
+            &[0xE6000002, 0x60FF0000, (2 << 16) | 2, (4 << 16) | 4],
+        );
+        assert_eq!(gpu.gpustat() >> 11 & 0x3, 2);
+
+        let vram = &gpu.gp0.vram;
+        assert_eq!(vram.read(1024 + 1), 0x801F);
+        assert_eq!(vram.read(1024 * 3 + 3), 0x801F);
+        assert_eq!(vram.read(1024 * 3 + 5), 0x7C00);
+        assert_eq!(vram.read(1024 * 5 + 2), 0x7C00);
+
+        // Uploads honor the mask too, while fills ignore it
+        gp0_commands(
+            &mut gpu,
+            &[0xA0000000, (3 << 16) | 3, (1 << 16) | 2, 0x1234_1234],
+        );
+        assert_eq!(gpu.gp0.vram.read(1024 * 3 + 3), 0x801F);
+        assert_eq!(gpu.gp0.vram.read(1024 * 3 + 4), 0x1234);
+        gp0_commands(&mut gpu, &[0x02000000, 0, (16 << 16) | 16]);
+        assert_eq!(gpu.gp0.vram.read(1024 * 3 + 3), 0);
+    }
+
+    #[test]
+    fn color_table_matches_the_rounded_conversion() {
+        for color in 0..32 {
+            let expected = (color as f64 * 255.0 / 31.0).round() as u8;
+            assert_eq!(convert_5bit_to_8bit(color), expected);
+        }
+        // Higher bits are ignored
+        assert_eq!(convert_5bit_to_8bit(0xFFE1), 8);
+    }
+
+    #[test]
+    fn render_vram_only_converts_written_rows() {
+        let mut gpu = Gpu::new();
+        let mut out = Vec::new();
+        assert_eq!(gpu.render_vram(&mut out), 512);
+        assert_eq!(gpu.render_vram(&mut out), 0);
+
+        gp0_commands(&mut gpu, &[0xE4000000 | (511 << 10) | 1023]);
+        gp0_commands(&mut gpu, &[0x6000FF1F, (10 << 16) | 4, (3 << 16) | 2]);
+        assert_eq!(gpu.render_vram(&mut out), 3);
+        let pixel = 3 * (1024 * 11 + 5);
+        assert_eq!(out[pixel..pixel + 3], [25, 255, 0]);
+        assert_eq!(gpu.render_vram(&mut out), 0);
+    }
+
+    #[test]
+    fn frame_ready_stays_latched_until_taken() {
+        let mut gpu = Gpu::new();
+        let mut frames = 0;
+        // Vblank starts once in the first 700000 cycles. Polling rarely still sees it
+        for i in 1..=700 {
+            gpu.tick(997 + i % 3);
+            if i % 50 == 0 && gpu.take_frame_ready() {
+                frames += 1;
+            }
+        }
+        assert_eq!(frames, 1);
+        assert!(!gpu.take_frame_ready());
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("ps1_emulator_{name}_{}", std::process::id()))
+    }
+
+    #[test]
+    fn vram_dump_round_trips() {
+        let path = temp_path("vram_dump.bin");
+        let mut gpu = Gpu::new();
+        for addr in 0..1024 * 512 {
+            gpu.gp0.vram.write(addr, (addr * 2654435761) as u16);
+        }
+        gpu.dump_vram(&path).unwrap();
+
+        let mut loaded = Gpu::new();
+        loaded.load_vram(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(loaded.gp0.vram[..] == gpu.gp0.vram[..]);
+        // The whole of VRAM is shown again
+        assert_eq!(loaded.take_dirty_rows().len(), 512);
+    }
+
+    #[test]
+    fn vram_dump_header_is_checked() {
+        let path = temp_path("bad_vram_dump.bin");
+        let mut gpu = Gpu::new();
+        gpu.dump_vram(&path).unwrap();
+        let dump = fs::read(&path).unwrap();
+
+        let mut short = dump.clone();
+        short.pop();
+        let mut magic = dump.clone();
+        magic[0] = b'X';
+        let mut size = dump;
+        size[8] = 1;
+        for data in [short, magic, size] {
+            fs::write(&path, data).unwrap();
+            let err = gpu.load_vram(&path).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn frame_stats_count_the_command_stream() {
+        let mut gpu = Gpu::new();
+        let stream = [
+            0xE4000000 | (511 << 10) | 1023,
+            // 10 pixel triangle and 16 pixel quad
+            0x200000FF,
+            0,
+            4,
+            4 << 16,
+            0x280000FF,
+            100,
+            104,
+            (4 << 16) | 100,
+            (4 << 16) | 104,
+            // 4 pixel line and 6 pixel rect
+            0x400000FF,
+            200,
+            203,
+            0x600000FF,
+            300,
+            (3 << 16) | 2,
+            // Fill, copy and upload
+            0x02000000,
+            400,
+            (16 << 16) | 16,
+            0x80000000,
+            0,
+            500,
+            (1 << 16) | 1,
+            0xA0000000,
+            600,
+            (1 << 16) | 2,
+            0xFFFF_FFFF,
+        ];
+        gp0_commands(&mut gpu, &stream);
+        while !gpu.tick(100).vblank_start {}
+
+        let stats = gpu.frame_stats();
+        assert_eq!(stats.words, stream.len() as u32);
+        assert_eq!(
+            (stats.triangles, stats.quads, stats.lines, stats.rects),
+            (1, 1, 1, 1)
+        );
+        assert_eq!((stats.fills, stats.blits), (1, 2));
+        assert_eq!(stats.pixels, 10 + 16 + 4 + 6);
+
+        // The next frame starts from nothing
+        while !gpu.tick(100).vblank_start {}
+        assert_eq!(gpu.frame_stats().words, 0);
+    }
+
+    #[test]
+    fn gp1_10_latches_internal_registers() {
+        let mut gpu = Gpu::new();
+        let top_left = 0xE3000000 | (20 << 10) | 10;
+        let bot_right = 0xE4000000 | (200 << 10) | 300;
+        // Offset of (16, -8)
+        let offset = 0xE5000000 | (0x7F8 << 11) | 16;
+        gp0_commands(&mut gpu, &[0xE2012345, top_left, bot_right, offset]);
+
+        gpu.gp1_write(0x10000002);
+        assert_eq!(gpu.gpuread(), 0x12345);
+        gpu.gp1_write(0x10000003);
+        assert_eq!(gpu.gpuread(), (20 << 10) | 10);
+        gpu.gp1_write(0x10000004);
+        assert_eq!(gpu.gpuread(), (200 << 10) | 300);
+        gpu.gp1_write(0x10000005);
+        assert_eq!(gpu.gpuread(), (0x7F8 << 11) | 16);
+        gpu.gp1_write(0x10000007);
+        assert_eq!(gpu.gpuread(), 2);
+        // Only the low three bits select the register
+        gpu.gp1_write(0x1000000B);
+        assert_eq!(gpu.gpuread(), (20 << 10) | 10);
+    }
+
+    #[test]
+    fn gpuread_keeps_the_latched_value() {
+        let mut gpu = Gpu::new();
+        gp0_commands(&mut gpu, &[0xE3000000 | (4 << 10) | 2]);
+        gpu.gp1_write(0x10000003);
+        // Changing the register after it was latched doesn't change GPUREAD
+        gp0_commands(&mut gpu, &[0xE3000000 | (9 << 10) | 7]);
+        assert_eq!(gpu.gpuread(), (4 << 10) | 2);
+        assert_eq!(gpu.gpuread(), (4 << 10) | 2);
+        // Nor do the indexes with nothing to latch
+        for index in [0, 1, 6] {
+            gpu.gp1_write(0x10000000 | index);
+            assert_eq!(gpu.gpuread(), (4 << 10) | 2);
+        }
+    }
+
+    #[test]
+    fn vram_to_cpu_transfer_comes_before_the_latch() {
+        let mut gpu = Gpu::new();
+        gpu.gp1_write(0x10000007);
+        gpu.gp0.vram.write(0, 0x1234);
+        gpu.gp0.vram.write(1, 0x5678);
+        gp0_commands(&mut gpu, &[0xC0000000, 0, (1 << 16) | 2]);
+        assert_eq!(gpu.gpuread(), 0x5678_1234);
+        assert_eq!(gpu.gpuread(), 2);
+    }
+
+    #[test]
+    fn gpustat_is_busy_for_the_draw_time() {
+        let mut gpu = Gpu::new();
+        gp0_commands(&mut gpu, &[0xE3000000, 0xE4000000 | (511 << 10) | 1023]);
+        // A 16x16 flat rect is 256 GPU cycles
+        for word in [0x600000FF, 0, (16 << 16) | 16] {
+            gpu.gp0.write(word);
+        }
+        assert_eq!(gpu.busy_cpu_cycles(), 163);
+        assert_eq!(gpu.gpustat() & (1 << 26), 0);
+
+        gpu.tick(162);
+        assert_eq!(gpu.gpustat() & (1 << 26), 0);
+        // Words sent meanwhile wait in the FIFO
+        gpu.gp0.write(0);
+        assert_eq!(gpu.gpustat() & (1 << 26), 0);
+        assert_ne!(gpu.gpustat() & (1 << 28), 0);
+
+        gpu.tick(1);
+        assert_ne!(gpu.gpustat() & (1 << 26), 0);
+    }
+
+    #[test]
+    fn full_fifo_clears_dma_ready() {
+        let mut gpu = Gpu::new();
+        gp0_commands(&mut gpu, &[0xE3000000, 0xE4000000 | (511 << 10) | 1023]);
+        for word in [0x600000FF, 0, (16 << 16) | 16] {
+            gpu.gp0.write(word);
+        }
+        for _ in 0..16 {
+            gpu.gp0.write(0);
+        }
+        assert_eq!(gpu.gpustat() & (1 << 28), 0);
+        gpu.tick(gpu.busy_cpu_cycles());
+        assert_eq!(gpu.gpustat() & (0x5 << 26), 0x5 << 26);
+    }
+
+    // Display area (x, y, width, height) and picture size for a display mode and ranges
+    fn extract(
+        gpu: &mut Gpu,
+        mode: u32,
+        (x1, x2): (u32, u32),
+        (y1, y2): (u32, u32),
+    ) -> ((usize, usize, usize, usize), (usize, usize)) {
+        gpu.gp1_write(0x05000000 | (16 << 10) | 64);
+        gpu.gp1_write(0x06000000 | (x2 << 12) | x1);
+        gpu.gp1_write(0x07000000 | (y2 << 10) | y1);
+        gpu.gp1_write(0x08000000 | mode);
+        let mut out = Vec::new();
+        let size = gpu.render_display(&mut out);
+        assert_eq!(out.len(), size.0 * size.1);
+        (gpu.display_area(), size)
+    }
+
+    #[test]
+    fn display_ranges_give_the_picture_size() {
+        let mut gpu = Gpu::new();
+        // 320x240 with a full range, and a range narrower and shorter than the mode
+        assert_eq!(
+            extract(&mut gpu, 1, (0x260, 0xC60), (0x10, 0x100)),
+            ((64, 16, 320, 240), (320, 240))
+        );
+        assert_eq!(
+            extract(&mut gpu, 1, (0x260, 0xBC0), (0x20, 0xF0)),
+            ((64, 16, 300, 208), (300, 208))
+        );
+        // Dots are rounded to 4 pixels, and 480 line modes count each line of the range twice
+        assert_eq!(
+            extract(&mut gpu, 0, (0x1F4, 0xC00), (0x10, 0x100)),
+            ((64, 16, 256, 240), (256, 240))
+        );
+        assert_eq!(
+            extract(&mut gpu, 0x27, (0x260, 0xC60), (0x10, 0x100)),
+            ((64, 16, 640, 480), (640, 480))
+        );
+        // Ranges wider than the mode or empty give the whole mode
+        assert_eq!(
+            extract(&mut gpu, 1, (0x100, 0xF00), (0x10, 0x10)),
+            ((64, 16, 320, 240), (320, 240))
+        );
+
+        // Standard framing ignores the ranges
+        gpu.display_options.range = DisplayRange::Standard;
+        assert_eq!(
+            extract(&mut gpu, 1, (0x300, 0x500), (0x80, 0x90)),
+            ((64, 16, 320, 240), (320, 240))
+        );
+        assert_eq!(
+            extract(&mut gpu, 0x27, (0x300, 0x500), (0x80, 0x90)),
+            ((64, 16, 640, 480), (640, 480))
+        );
+    }
+
+    #[test]
+    fn cropping_cuts_the_edges_of_the_picture() {
+        let mut gpu = Gpu::new();
+        gpu.display_options = DisplayOptions {
+            crop_left: 8,
+            crop_right: 4,
+            crop_top: 8,
+            crop_bottom: 16,
+            ..DisplayOptions::default()
+        };
+        assert_eq!(
+            extract(&mut gpu, 1, (0x260, 0xC60), (0x10, 0x100)),
+            ((72, 24, 308, 216), (308, 216))
+        );
+        assert_eq!(
+            extract(&mut gpu, 1, (0x260, 0xBC0), (0x20, 0xF0)),
+            ((72, 24, 288, 184), (288, 184))
+        );
+
+        // The picture starts at the cropped corner
+        gpu.gp0.vram.write(1024 * 24 + 72, 0x001F);
+        let mut out = Vec::new();
+        gpu.render_display(&mut out);
+        assert_eq!(out[0], Color32::from_rgb(255, 0, 0));
+
+        // Cropping more than the whole picture still leaves a pixel
+        gpu.display_options.crop_left = 1000;
+        gpu.display_options.crop_bottom = 1000;
+        assert_eq!(
+            extract(&mut gpu, 1, (0x260, 0xC60), (0x10, 0x100)),
+            ((64 + 319, 24, 1, 1), (1, 1))
+        );
     }
 }
@@ -0,0 +1,187 @@
+use eframe::egui::Color32;
+
+use crate::gpu::rasterizer::{self, DrawSettings, Line, Rect, Target, Triangle, Vertex, Vram};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DebugView {
+    Off,
+    Wireframe,
+    Overdraw,
+}
+
+// Side buffer for the debug views. Primitives are drawn into it alongside VRAM so game data is
+// never touched. Holds a per pixel write count for overdraw, or non zero on edges for wireframe
+pub struct DebugOverlay {
+    pub view: DebugView,
+    counts: Vec<u16>,
+}
+
+// Counts pixel writes. VRAM is only read so mask bits still apply
+struct CountTarget<'a> {
+    vram: &'a Vram,
+    counts: &'a mut [u16],
+}
+
+impl Target for CountTarget<'_> {
+    fn scale(&self) -> usize {
+        1
+    }
+
+    fn native(&self) -> &Vram {
+        self.vram
+    }
+
+    fn read(&self, x: usize, y: usize) -> u16 {
+        self.vram.read(1024 * y + x)
+    }
+
+    fn write(&mut self, x: usize, y: usize, _val: u16) {
+        let count = &mut self.counts[1024 * y + x];
+        *count = count.saturating_add(1);
+    }
+}
+
+impl DebugOverlay {
+    pub fn new() -> Self {
+        Self {
+            view: DebugView::Off,
+            counts: Vec::new(),
+        }
+    }
+
+    pub fn set_view(&mut self, view: DebugView) {
+        self.view = view;
+        self.counts.clear();
+        if view != DebugView::Off {
+            self.counts.resize(524288, 0);
+        }
+    }
+
+    // Start a new frame
+    pub fn clear(&mut self) {
+        self.counts.fill(0);
+    }
+
+    pub fn draw_triangle(&mut self, vram: &Vram, settings: &DrawSettings, triangle: &Triangle) {
+        match self.view {
+            DebugView::Off => {}
+            DebugView::Wireframe => {
+                let [v0, v1, v2] = triangle.vertices.map(|v| v.pos);
+                for (start, end) in [(v0, v1), (v1, v2), (v2, v0)] {
+                    self.draw_edge(vram, settings, start, end);
+                }
+            }
+            DebugView::Overdraw => {
+                let mut target = CountTarget {
+                    vram,
+                    counts: &mut self.counts,
+                };
+                rasterizer::draw_triangle(&mut target, settings, triangle);
+            }
+        }
+    }
+
+    pub fn draw_line(&mut self, vram: &Vram, settings: &DrawSettings, line: &Line) {
+        match self.view {
+            DebugView::Off => {}
+            DebugView::Wireframe => {
+                self.draw_edge(vram, settings, line.start.pos, line.end.pos);
+            }
+            DebugView::Overdraw => {
+                let mut target = CountTarget {
+                    vram,
+                    counts: &mut self.counts,
+                };
+                rasterizer::draw_line(&mut target, settings, line);
+            }
+        }
+    }
+
+    pub fn draw_rect(&mut self, vram: &Vram, settings: &DrawSettings, rect: &Rect) {
+        match self.view {
+            DebugView::Off => {}
+            DebugView::Wireframe => {
+                if rect.width == 0 || rect.height == 0 {
+                    return;
+                }
+
+                let (x0, y0) = rect.pos;
+                let x1 = x0 + rect.width as i32 - 1;
+                let y1 = y0 + rect.height as i32 - 1;
+                for (start, end) in [
+                    ((x0, y0), (x1, y0)),
+                    ((x1, y0), (x1, y1)),
+                    ((x1, y1), (x0, y1)),
+                    ((x0, y1), (x0, y0)),
+                ] {
+                    self.draw_edge(vram, settings, start, end);
+                }
+            }
+            DebugView::Overdraw => {
+                let mut target = CountTarget {
+                    vram,
+                    counts: &mut self.counts,
+                };
+                rasterizer::draw_rect(&mut target, settings, rect);
+            }
+        }
+    }
+
+    // Edges are only clipped to the drawing area. Mask bits and the displayed field are ignored
+    fn draw_edge(
+        &mut self,
+        vram: &Vram,
+        settings: &DrawSettings,
+        start: (i32, i32),
+        end: (i32, i32),
+    ) {
+        let settings = DrawSettings {
+            mask_before_draw: false,
+            display_field: None,
+            ..*settings
+        };
+        let line = Line {
+            start: Vertex {
+                pos: start,
+                ..Default::default()
+            },
+            end: Vertex {
+                pos: end,
+                ..Default::default()
+            },
+            shaded: false,
+            semi_transparent: false,
+        };
+
+        let mut target = CountTarget {
+            vram,
+            counts: &mut self.counts,
+        };
+        rasterizer::draw_line(&mut target, &settings, &line);
+    }
+
+    pub fn overdraw_count(&self, x: usize, y: usize) -> u16 {
+        self.counts.get(1024 * y + x).copied().unwrap_or(0)
+    }
+
+    // Wireframe edges are drawn in green. Overdraw is shown as intensity, saturating at 8 writes
+    pub fn render(&self, start: (usize, usize), size: (usize, usize), out: &mut Vec<Color32>) {
+        out.clear();
+        out.reserve(size.0 * size.1);
+        for y in 0..size.1 {
+            let row = (start.1 + y) % 512;
+            for x in 0..size.0 {
+                let count = self.overdraw_count((start.0 + x) % 1024, row);
+                let color = match self.view {
+                    DebugView::Wireframe if count > 0 => Color32::GREEN,
+                    DebugView::Overdraw => {
+                        let intensity = (count as u32 * 32).min(255) as u8;
+                        Color32::from_gray(intensity)
+                    }
+                    _ => Color32::BLACK,
+                };
+                out.push(color);
+            }
+        }
+    }
+}
@@ -0,0 +1,1311 @@
+use std::ops::Deref;
+use std::{cmp, mem};
+
+use serde::{Deserialize, Serialize};
+use tracing::{Level, event};
+
+use super::convert_5bit_to_8bit;
+
+const DITHER_TABLE: [[i8; 4]; 4] = [
+    [-4, 0, -3, 1],
+    [2, -2, 3, -1],
+    [-3, 1, -4, 0],
+    [3, -1, 2, -2],
+];
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub enum SemiTransparency {
+    Blend,
+    Add,
+    Subtract,
+    QuarterBlend,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub enum TextureBits {
+    Four,
+    Eight,
+    Fifteen,
+    Reserved,
+}
+
+// 1024 x 512 grid of pixels (lo, hi)
+#[derive(Serialize, Deserialize)]
+pub struct Vram {
+    #[serde(with = "crate::state::bytes")]
+    data: Box<[u8; 1048576]>,
+    #[serde(skip, default = "all_dirty")]
+    dirty_rows: Vec<bool>, // Rows written since the last call to take_dirty_rows
+}
+
+// A restored VRAM has to be uploaded again in full
+fn all_dirty() -> Vec<bool> {
+    vec![true; 512]
+}
+
+impl Vram {
+    pub fn new() -> Self {
+        Self {
+            data: Box::new([0; 1048576]),
+            dirty_rows: vec![true; 512],
+        }
+    }
+
+    pub fn read(&self, addr: usize) -> u16 {
+        u16::from_le_bytes([self.data[2 * addr], self.data[2 * addr + 1]])
+    }
+
+    // Raw write that ignores the mask settings
+    pub fn write(&mut self, addr: usize, val: u16) {
+        let [lo, hi] = val.to_le_bytes();
+        self.data[2 * addr] = lo;
+        self.data[2 * addr + 1] = hi;
+        self.dirty_rows[addr / 1024] = true;
+    }
+
+    pub fn take_dirty_rows(&mut self) -> Vec<usize> {
+        let mut rows = Vec::new();
+        for (row, dirty) in self.dirty_rows.iter_mut().enumerate() {
+            if mem::take(dirty) {
+                rows.push(row);
+            }
+        }
+        rows
+    }
+}
+
+impl Deref for Vram {
+    type Target = [u8; 1048576];
+
+    fn deref(&self) -> &Self::Target {
+        &self.data
+    }
+}
+
+// Copy of VRAM at an increased internal resolution. Only polygons are rendered at the higher
+// resolution, everything else is copied from native VRAM with nearest filtering
+#[derive(Clone)]
+pub struct HiresVram {
+    scale: usize,
+    pixels: Vec<u16>,
+}
+
+impl HiresVram {
+    pub fn new(vram: &Vram, scale: usize) -> Self {
+        let mut hires = Self {
+            scale,
+            pixels: vec![0; 1024 * 512 * scale * scale],
+        };
+        for addr in 0..524288 {
+            hires.sync(vram, addr);
+        }
+        hires
+    }
+
+    pub fn scale(&self) -> usize {
+        self.scale
+    }
+
+    pub fn read(&self, x: usize, y: usize) -> u16 {
+        self.pixels[1024 * self.scale * y + x]
+    }
+
+    fn write(&mut self, x: usize, y: usize, val: u16) {
+        self.pixels[1024 * self.scale * y + x] = val;
+    }
+
+    // Replace the block covering a native pixel with the native value
+    pub fn sync(&mut self, vram: &Vram, addr: usize) {
+        let val = vram.read(addr);
+        let (x, y) = (addr % 1024, addr / 1024);
+        for sub_y in 0..self.scale {
+            for sub_x in 0..self.scale {
+                self.write(self.scale * x + sub_x, self.scale * y + sub_y, val);
+            }
+        }
+    }
+}
+
+// Destination of rasterized pixels. Coordinates are in units of the target resolution while
+// textures are always read from native VRAM
+pub trait Target {
+    fn scale(&self) -> usize;
+    fn native(&self) -> &Vram;
+    fn read(&self, x: usize, y: usize) -> u16;
+    fn write(&mut self, x: usize, y: usize, val: u16);
+}
+
+impl Target for Vram {
+    fn scale(&self) -> usize {
+        1
+    }
+
+    fn native(&self) -> &Vram {
+        self
+    }
+
+    fn read(&self, x: usize, y: usize) -> u16 {
+        Vram::read(self, 1024 * y + x)
+    }
+
+    fn write(&mut self, x: usize, y: usize, val: u16) {
+        Vram::write(self, 1024 * y + x, val);
+    }
+}
+
+// Renders into the high resolution buffer only
+pub struct HiresTarget<'a> {
+    pub vram: &'a Vram,
+    pub hires: &'a mut HiresVram,
+}
+
+impl Target for HiresTarget<'_> {
+    fn scale(&self) -> usize {
+        self.hires.scale
+    }
+
+    fn native(&self) -> &Vram {
+        self.vram
+    }
+
+    fn read(&self, x: usize, y: usize) -> u16 {
+        self.hires.read(x, y)
+    }
+
+    fn write(&mut self, x: usize, y: usize, val: u16) {
+        self.hires.write(x, y, val);
+    }
+}
+
+// Renders at native resolution and copies every pixel written into the high resolution buffer
+pub struct NearestTarget<'a> {
+    pub vram: &'a mut Vram,
+    pub hires: &'a mut HiresVram,
+}
+
+impl Target for NearestTarget<'_> {
+    fn scale(&self) -> usize {
+        1
+    }
+
+    fn native(&self) -> &Vram {
+        self.vram
+    }
+
+    fn read(&self, x: usize, y: usize) -> u16 {
+        self.vram.read(1024 * y + x)
+    }
+
+    fn write(&mut self, x: usize, y: usize, val: u16) {
+        self.vram.write(1024 * y + x, val);
+        self.hires.sync(self.vram, 1024 * y + x);
+    }
+}
+
+// Snapshot of the GP0 drawing environment used while rasterizing a primitive
+#[derive(Clone, Copy)]
+pub struct DrawSettings {
+    pub semitransparency: SemiTransparency,
+    pub tex_page: (u16, u16), // VRAM coordinates of the texture page
+    pub tex_page_colors: TextureBits,
+    pub texture_window: u32,
+    pub dither_enabled: bool,
+    pub draw_to_display: bool,
+    pub rect_x_flip: bool,
+    pub rect_y_flip: bool,
+    pub draw_area_top_left: (u32, u32),
+    pub draw_area_bot_right: (u32, u32),
+    pub mask_while_draw: bool,
+    pub mask_before_draw: bool,
+    pub display_field: Option<usize>, // Parity of lines being displayed in 480i
+}
+
+impl DrawSettings {
+    // Drawing area is inclusive of both the top left and bottom right corners
+    pub fn in_draw_area(&self, x: i32, y: i32) -> bool {
+        (self.draw_area_top_left.0 as i32..=self.draw_area_bot_right.0 as i32).contains(&x)
+            && (self.draw_area_top_left.1 as i32..=self.draw_area_bot_right.1 as i32).contains(&y)
+    }
+
+    // True if the VRAM row is a line of the interlaced field currently on screen and drawing to the
+    // display area is not allowed
+    fn skip_display_line(&self, row: usize) -> bool {
+        match self.display_field {
+            Some(field) => !self.draw_to_display && row & 1 == field,
+            None => false,
+        }
+    }
+}
+
+// Colors are 24 bit (r, g, b) as sent in GP0 commands. Texcoords are only used by textured
+// primitives
+#[derive(Clone, Copy, Default)]
+pub struct Vertex {
+    pub pos: (i32, i32),
+    pub color: u32,
+    pub uv: (u32, u32),
+}
+
+// Flat shaded triangles use the color of the first vertex
+pub struct Triangle {
+    pub vertices: [Vertex; 3],
+    pub shaded: bool,
+    pub textured: bool,
+    pub modulate: bool,
+    pub semi_transparent: bool,
+    pub clut: (u16, u16),
+}
+
+pub struct Line {
+    pub start: Vertex,
+    pub end: Vertex,
+    pub shaded: bool,
+    pub semi_transparent: bool,
+}
+
+pub struct Rect {
+    pub pos: (i32, i32),
+    pub width: u32,
+    pub height: u32,
+    pub color: u32,
+    pub uv: (u32, u32),
+    pub clut: (u16, u16),
+    pub textured: bool,
+    pub modulate: bool,
+    pub semi_transparent: bool,
+}
+
+// Each of the draw functions returns the number of pixels written to VRAM
+
+// Triangles are rasterized at the resolution of the target. Dithering and the displayed field
+// still follow native pixels
+pub fn draw_triangle<T: Target>(
+    target: &mut T,
+    settings: &DrawSettings,
+    triangle: &Triangle,
+) -> u32 {
+    let scale = target.scale() as i32;
+    let [mut v0, mut v1, mut v2] = triangle.vertices;
+
+    let Some((min, max)) = get_bounds(settings, v0.pos, v1.pos, v2.pos, scale) else {
+        return 0;
+    };
+
+    for vertex in [&mut v0, &mut v1, &mut v2] {
+        vertex.pos = (scale * vertex.pos.0, scale * vertex.pos.1);
+    }
+
+    if cross_product(v0.pos, v1.pos, v2.pos) < 0 {
+        mem::swap(&mut v0, &mut v1);
+    }
+
+    let mut pixels = 0;
+    for y in min.1..=max.1 {
+        for x in min.0..=max.0 {
+            let Some([a, b, c]) = inside_triange((x, y), v0.pos, v1.pos, v2.pos) else {
+                continue;
+            };
+
+            let color = if triangle.shaded {
+                let (r0, g0, b0) = split_rgb(v0.color);
+                let (r1, g1, b1) = split_rgb(v1.color);
+                let (r2, g2, b2) = split_rgb(v2.color);
+
+                let r = (a * r0 as f32 + b * r1 as f32 + c * r2 as f32).round() as u8;
+                let g = (a * g0 as f32 + b * g1 as f32 + c * g2 as f32).round() as u8;
+                let b = (a * b0 as f32 + b * b1 as f32 + c * b2 as f32).round() as u8;
+
+                if settings.dither_enabled {
+                    dither((r, g, b), (x / scale, y / scale))
+                } else {
+                    (r, g, b)
+                }
+            } else {
+                split_rgb(v0.color)
+            };
+
+            let pixel = if triangle.textured {
+                let u =
+                    (a * v0.uv.0 as f32 + b * v1.uv.0 as f32 + c * v2.uv.0 as f32).round() as u32;
+                let v =
+                    (a * v0.uv.1 as f32 + b * v1.uv.1 as f32 + c * v2.uv.1 as f32).round() as u32;
+                let texel = get_color_from_uv(target.native(), settings, u, v, triangle.clut);
+
+                if texel == 0 {
+                    continue;
+                }
+
+                if triangle.modulate {
+                    modulate_5bit_color(texel, color)
+                } else {
+                    texel
+                }
+            } else {
+                rgb_to_5bit(color)
+            };
+
+            let pos = (x as usize, y as usize);
+            if put_pixel(target, settings, pos, pixel, triangle.semi_transparent) {
+                pixels += 1;
+            }
+        }
+    }
+
+    pixels
+}
+
+// Lines and rectangles are always drawn at native resolution
+pub fn draw_line<T: Target>(target: &mut T, settings: &DrawSettings, line: &Line) -> u32 {
+    let (x1, y1) = line.start.pos;
+    let (x2, y2) = line.end.pos;
+
+    // A line with both ends the same is just its start, which the slopes below would divide by
+    // zero for
+    if (x1, y1) == (x2, y2) {
+        return draw_line_pixel(target, settings, line, (x1, y1), 1.0) as u32;
+    }
+
+    let total_dist = f32::sqrt((x1 as f32 - x2 as f32).powi(2) + (y1 as f32 - y2 as f32).powi(2));
+
+    let mut pixels = 0;
+    if x1.abs_diff(x2) >= y1.abs_diff(y2) {
+        let slope = (y2 as f32 - y1 as f32) / (x2 as f32 - x1 as f32);
+        let range = if x1 <= x2 { x1..=x2 } else { x2..=x1 };
+        for x in range {
+            let y_raw = slope * (x as f32 - x1 as f32) + y1 as f32;
+            let dist = f32::sqrt((x as f32 - x2 as f32).powi(2) + (y_raw - y2 as f32).powi(2));
+
+            if draw_line_pixel(
+                target,
+                settings,
+                line,
+                (x, y_raw.floor() as i32),
+                dist / total_dist,
+            ) {
+                pixels += 1;
+            }
+        }
+    } else {
+        let slope = (x2 as f32 - x1 as f32) / (y2 as f32 - y1 as f32);
+        let range = if y1 <= y2 { y1..=y2 } else { y2..=y1 };
+        for y in range {
+            let x_raw = slope * (y as f32 - y1 as f32) + x1 as f32;
+            let dist = f32::sqrt((x_raw - x2 as f32).powi(2) + (y as f32 - y2 as f32).powi(2));
+
+            if draw_line_pixel(
+                target,
+                settings,
+                line,
+                (x_raw.floor() as i32, y),
+                dist / total_dist,
+            ) {
+                pixels += 1;
+            }
+        }
+    };
+
+    pixels
+}
+
+// pct is the fraction of the line left until the end point
+fn draw_line_pixel<T: Target>(
+    target: &mut T,
+    settings: &DrawSettings,
+    line: &Line,
+    (x, y): (i32, i32),
+    pct: f32,
+) -> bool {
+    if !settings.in_draw_area(x, y) {
+        return false;
+    }
+
+    let pixel = if line.shaded {
+        let (r1, g1, b1) = split_rgb(line.start.color);
+        let (r2, g2, b2) = split_rgb(line.end.color);
+
+        let r = (pct * (r1 as f32) + (1.0 - pct) * (r2 as f32)).round() as u16;
+        let g = (pct * (g1 as f32) + (1.0 - pct) * (g2 as f32)).round() as u16;
+        let b = (pct * (b1 as f32) + (1.0 - pct) * (b2 as f32)).round() as u16;
+        let r = (r & 0xFF) >> 3;
+        let g = (g & 0xFF) >> 3;
+        let b = (b & 0xFF) >> 3;
+        r | (g << 5) | (b << 10)
+    } else {
+        rgb_to_5bit(split_rgb(line.start.color))
+    };
+
+    put_pixel(
+        target,
+        settings,
+        (x as usize, y as usize),
+        pixel,
+        line.semi_transparent,
+    )
+}
+
+pub fn draw_rect<T: Target>(target: &mut T, settings: &DrawSettings, rect: &Rect) -> u32 {
+    let color = split_rgb(rect.color);
+
+    let mut pixels = 0;
+    for y in 0..rect.height {
+        for x in 0..rect.width {
+            let vram_row = rect.pos.1 + y as i32;
+            let vram_col = rect.pos.0 + x as i32;
+            if !settings.in_draw_area(vram_col, vram_row) {
+                continue;
+            }
+
+            let pixel = if rect.textured {
+                let u = if settings.rect_x_flip {
+                    rect.uv.0.wrapping_sub(x).wrapping_add(1)
+                } else {
+                    rect.uv.0.wrapping_add(x)
+                } % 256;
+                let v = if settings.rect_y_flip {
+                    rect.uv.1.wrapping_sub(y).wrapping_add(1)
+                } else {
+                    rect.uv.1.wrapping_add(y)
+                } % 256;
+
+                let texel = get_color_from_uv(target.native(), settings, u, v, rect.clut);
+
+                if texel == 0 {
+                    continue;
+                }
+
+                if rect.modulate {
+                    modulate_5bit_color(texel, color)
+                } else {
+                    texel
+                }
+            } else {
+                rgb_to_5bit(color)
+            };
+
+            let pos = (vram_col as usize, vram_row as usize);
+            if put_pixel(target, settings, pos, pixel, rect.semi_transparent) {
+                pixels += 1;
+            }
+        }
+    }
+
+    pixels
+}
+
+// All VRAM writes other than GP0(0x02) fills go through here so the GP0(0xE6) mask settings
+// are honored. Returns false if the pixel was protected by its mask bit
+pub fn write_pixel(vram: &mut Vram, settings: &DrawSettings, addr: usize, val: u16) -> bool {
+    write_masked(vram, settings, (addr % 1024, addr / 1024), val)
+}
+
+fn write_masked<T: Target>(
+    target: &mut T,
+    settings: &DrawSettings,
+    (x, y): (usize, usize),
+    val: u16,
+) -> bool {
+    if settings.mask_before_draw && target.read(x, y) & 0x8000 > 0 {
+        return false;
+    }
+
+    // If Mask While Draw is set, then mask_field is forced to true. Otherwise set to bit 15
+    let mask_bit = if settings.mask_while_draw {
+        0x8000
+    } else {
+        val & 0x8000
+    };
+
+    target.write(x, y, (val & 0x7FFF) | mask_bit);
+    true
+}
+
+// Drawn pixels are blended with VRAM when semi-transparent and skip the displayed field in 480i
+fn put_pixel<T: Target>(
+    target: &mut T,
+    settings: &DrawSettings,
+    (x, y): (usize, usize),
+    val: u16,
+    semi_transparent: bool,
+) -> bool {
+    if settings.skip_display_line(y / target.scale()) {
+        return false;
+    }
+
+    let val = if semi_transparent {
+        blend(settings.semitransparency, target.read(x, y), val)
+    } else {
+        val
+    };
+
+    write_masked(target, settings, (x, y), val)
+}
+
+fn blend(mode: SemiTransparency, prev_color: u16, val: u16) -> u16 {
+    let r = convert_5bit_to_8bit(val & 0x1F);
+    let g = convert_5bit_to_8bit((val >> 5) & 0x1F);
+    let b = convert_5bit_to_8bit((val >> 10) & 0x1F);
+
+    let prev_color_r = convert_5bit_to_8bit(prev_color & 0x1F);
+    let prev_color_g = convert_5bit_to_8bit((prev_color >> 5) & 0x1F);
+    let prev_color_b = convert_5bit_to_8bit((prev_color >> 10) & 0x1F);
+
+    let (new_r, new_g, new_b) = match mode {
+        SemiTransparency::Blend => (
+            r / 2 + prev_color_r / 2,
+            g / 2 + prev_color_g / 2,
+            b / 2 + prev_color_b / 2,
+        ),
+        SemiTransparency::Add => (
+            prev_color_r.saturating_add(r),
+            prev_color_g.saturating_add(g),
+            prev_color_b.saturating_add(b),
+        ),
+        SemiTransparency::Subtract => (
+            prev_color_r.saturating_sub(r),
+            prev_color_g.saturating_sub(g),
+            prev_color_b.saturating_sub(b),
+        ),
+        SemiTransparency::QuarterBlend => (
+            prev_color_r.saturating_add(r / 4),
+            prev_color_g.saturating_add(g / 4),
+            prev_color_b.saturating_add(b / 4),
+        ),
+    };
+
+    rgb_to_5bit((new_r, new_g, new_b)) | (val & 0x8000)
+}
+
+// returns (min_x, min_y) and (max_x, max_y) of bounding box clipped to the drawing area, in
+// units of the target resolution. Polygons wider than 1023 or taller than 511 are not drawn at all
+fn get_bounds(
+    settings: &DrawSettings,
+    v0: (i32, i32),
+    v1: (i32, i32),
+    v2: (i32, i32),
+    scale: i32,
+) -> Option<((i32, i32), (i32, i32))> {
+    for (a, b) in [(v0, v1), (v1, v2), (v2, v0)] {
+        if a.0.abs_diff(b.0) > 1023 || a.1.abs_diff(b.1) > 511 {
+            event!(target: "ps1_emulator::GPU", Level::TRACE, "Polygon too large, skipping");
+            return None;
+        }
+    }
+
+    let min_x = cmp::max(
+        scale * settings.draw_area_top_left.0 as i32,
+        scale * cmp::min(v0.0, cmp::min(v1.0, v2.0)),
+    );
+    let min_y = cmp::max(
+        scale * settings.draw_area_top_left.1 as i32,
+        scale * cmp::min(v0.1, cmp::min(v1.1, v2.1)),
+    );
+    let max_x = cmp::min(
+        scale * (settings.draw_area_bot_right.0 as i32 + 1) - 1,
+        scale * cmp::max(v0.0, cmp::max(v1.0, v2.0)),
+    );
+    let max_y = cmp::min(
+        scale * (settings.draw_area_bot_right.1 as i32 + 1) - 1,
+        scale * cmp::max(v0.1, cmp::max(v1.1, v2.1)),
+    );
+
+    Some(((min_x, min_y), (max_x, max_y)))
+}
+
+pub fn inside_triange(
+    p: (i32, i32),
+    v0: (i32, i32),
+    v1: (i32, i32),
+    v2: (i32, i32),
+) -> Option<[f32; 3]> {
+    let mut barycentric_coords = [0.0; 3];
+
+    let denominator = cross_product(v0, v1, v2) as f32;
+    if denominator == 0.0 {
+        return Some([1.0 / 3.0, 1.0 / 3.0, 1.0 / 3.0]);
+    }
+
+    for (i, (a, b)) in [(v1, v2), (v2, v0), (v0, v1)].iter().enumerate() {
+        let cross_product = cross_product(*a, *b, p);
+        barycentric_coords[i] = (cross_product as f32) / denominator;
+
+        if cross_product < 0 {
+            return None;
+        }
+
+        if cross_product == 0 {
+            if b.1 > a.1 {
+                return None;
+            }
+
+            if b.1 == a.1 && b.0 < a.0 {
+                return None;
+            }
+        }
+    }
+
+    Some(barycentric_coords)
+}
+
+// Cross product of (v1 - v0) and (v2 - v0)
+pub fn cross_product(v0: (i32, i32), v1: (i32, i32), v2: (i32, i32)) -> i32 {
+    (v1.0 - v0.0) * (v2.1 - v0.1) - (v1.1 - v0.1) * (v2.0 - v0.0)
+}
+
+// Texture pages and CLUTs running off the right or bottom of VRAM wrap around to the other side
+fn wrapped_addr(x: usize, y: usize) -> usize {
+    (x & 1023) + 1024 * (y & 511)
+}
+
+fn get_texel_4bit(vram: &Vram, x: u32, y: u32, clut: (u16, u16), tex_page: (u16, u16)) -> u16 {
+    // Get texel at (x,y) relative to to the texture page
+    let texel = vram.read(wrapped_addr(
+        x as usize / 4 + tex_page.0 as usize,
+        y as usize + tex_page.1 as usize,
+    ));
+    // Get the index offset for current pixel to be used in the clut
+    let index = (texel >> (4 * (x % 4))) & 0xF;
+    vram.read(wrapped_addr(
+        clut.0 as usize + index as usize,
+        clut.1 as usize,
+    ))
+}
+
+fn get_texel_8bit(vram: &Vram, x: u32, y: u32, clut: (u16, u16), tex_page: (u16, u16)) -> u16 {
+    // Get texel at (x,y) relative to to the texture page
+    let texel = vram.read(wrapped_addr(
+        x as usize / 2 + tex_page.0 as usize,
+        y as usize + tex_page.1 as usize,
+    ));
+    // Get the index offset for current pixel to be used in the clut
+    let index = (texel >> (8 * (x % 2))) & 0xFF;
+    vram.read(wrapped_addr(
+        clut.0 as usize + index as usize,
+        clut.1 as usize,
+    ))
+}
+
+fn get_texel_15bit(vram: &Vram, x: u32, y: u32, tex_page: (u16, u16)) -> u16 {
+    vram.read(wrapped_addr(
+        x as usize + tex_page.0 as usize,
+        y as usize + tex_page.1 as usize,
+    ))
+}
+
+fn get_color_from_uv(
+    vram: &Vram,
+    settings: &DrawSettings,
+    u: u32,
+    v: u32,
+    clut: (u16, u16),
+) -> u16 {
+    let mask_x = settings.texture_window & 0x1F;
+    let mask_y = (settings.texture_window >> 5) & 0x1F;
+    let offset_x = (settings.texture_window >> 10) & 0x1F;
+    let offset_y = (settings.texture_window >> 15) & 0x1F;
+
+    let u = (u & !(mask_x * 8)) | (8 * (mask_x & offset_x));
+    let v = (v & !(mask_y * 8)) | (8 * (mask_y & offset_y));
+
+    match settings.tex_page_colors {
+        TextureBits::Four => get_texel_4bit(vram, u, v, clut, settings.tex_page),
+        TextureBits::Eight => get_texel_8bit(vram, u, v, clut, settings.tex_page),
+        TextureBits::Fifteen | TextureBits::Reserved => {
+            get_texel_15bit(vram, u, v, settings.tex_page)
+        }
+    }
+}
+
+fn modulate_5bit_color(texel: u16, color: (u8, u8, u8)) -> u16 {
+    let mask = texel & 0x8000;
+    let r1 = convert_5bit_to_8bit(texel & 0x1F) as f32;
+    let g1 = convert_5bit_to_8bit((texel >> 5) & 0x1F) as f32;
+    let b1 = convert_5bit_to_8bit((texel >> 10) & 0x1F) as f32;
+
+    let new_r = (((r1 * color.0 as f32) / 128.0).round() as u8) >> 3;
+    let new_g = (((g1 * color.1 as f32) / 128.0).round() as u8) >> 3;
+    let new_b = (((b1 * color.2 as f32) / 128.0).round() as u8) >> 3;
+
+    new_r as u16 | (new_g as u16) << 5 | (new_b as u16) << 10 | mask
+}
+
+// Color is in rgb
+fn dither(color: (u8, u8, u8), pixel: (i32, i32)) -> (u8, u8, u8) {
+    let offset = DITHER_TABLE[(pixel.0 & 0b11) as usize][(pixel.1 & 0b11) as usize];
+
+    (
+        color.0.saturating_add_signed(offset),
+        color.1.saturating_add_signed(offset),
+        color.2.saturating_add_signed(offset),
+    )
+}
+
+fn split_rgb(color: u32) -> (u8, u8, u8) {
+    (color as u8, (color >> 8) as u8, (color >> 16) as u8)
+}
+
+fn rgb_to_5bit(color: (u8, u8, u8)) -> u16 {
+    let r = (color.0 >> 3) as u16;
+    let g = (color.1 >> 3) as u16;
+    let b = (color.2 >> 3) as u16;
+    r | (g << 5) | (b << 10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: u32 = 0x0000FF;
+    const BLUE: u32 = 0xFF0000;
+
+    fn settings() -> DrawSettings {
+        DrawSettings {
+            semitransparency: SemiTransparency::Blend,
+            tex_page: (0, 0),
+            tex_page_colors: TextureBits::Fifteen,
+            texture_window: 0,
+            dither_enabled: false,
+            draw_to_display: true,
+            rect_x_flip: false,
+            rect_y_flip: false,
+            draw_area_top_left: (0, 0),
+            draw_area_bot_right: (1023, 511),
+            mask_while_draw: false,
+            mask_before_draw: false,
+            display_field: None,
+        }
+    }
+
+    fn vertex(x: i32, y: i32, color: u32) -> Vertex {
+        Vertex {
+            pos: (x, y),
+            color,
+            uv: (0, 0),
+        }
+    }
+
+    fn triangle(vertices: [Vertex; 3], shaded: bool) -> Triangle {
+        Triangle {
+            vertices,
+            shaded,
+            textured: false,
+            modulate: false,
+            semi_transparent: false,
+            clut: (0, 0),
+        }
+    }
+
+    fn rect(pos: (i32, i32), width: u32, height: u32, color: u32) -> Rect {
+        Rect {
+            pos,
+            width,
+            height,
+            color,
+            uv: (0, 0),
+            clut: (0, 0),
+            textured: false,
+            modulate: false,
+            semi_transparent: false,
+        }
+    }
+
+    fn line(start: Vertex, end: Vertex, shaded: bool) -> Line {
+        Line {
+            start,
+            end,
+            shaded,
+            semi_transparent: false,
+        }
+    }
+
+    // Coordinates of the pixels set in the top left corner of a target
+    fn drawn<T: Target>(target: &T, size: usize) -> Vec<(usize, usize)> {
+        let mut pixels = Vec::new();
+        for y in 0..size {
+            for x in 0..size {
+                if target.read(x, y) != 0 {
+                    pixels.push((x, y));
+                }
+            }
+        }
+        pixels
+    }
+
+    #[test]
+    fn flat_triangle_leaves_out_its_right_and_bottom_edges() {
+        let mut vram = Vram::new();
+        let red = triangle(
+            [vertex(0, 0, RED), vertex(4, 0, RED), vertex(0, 4, RED)],
+            false,
+        );
+        assert_eq!(draw_triangle(&mut vram, &settings(), &red), 10);
+        let expected: Vec<_> = (0..4)
+            .flat_map(|y| (0..4 - y).map(move |x| (x, y)))
+            .collect();
+        assert_eq!(drawn(&vram, 8), expected);
+        assert!(
+            expected
+                .iter()
+                .all(|&(x, y)| Target::read(&vram, x, y) == 0x001F)
+        );
+    }
+
+    #[test]
+    fn vertex_order_does_not_change_a_triangle() {
+        let vertices = [vertex(1, 1, RED), vertex(6, 2, RED), vertex(3, 7, RED)];
+        let mut clockwise = Vram::new();
+        let mut anticlockwise = Vram::new();
+        draw_triangle(&mut clockwise, &settings(), &triangle(vertices, false));
+        let [a, b, c] = vertices;
+        draw_triangle(&mut anticlockwise, &settings(), &triangle([b, a, c], false));
+        assert!(!drawn(&clockwise, 8).is_empty());
+        assert_eq!(drawn(&clockwise, 8), drawn(&anticlockwise, 8));
+    }
+
+    #[test]
+    fn shaded_triangle_takes_the_color_of_the_nearest_vertex() {
+        let mut vram = Vram::new();
+        let vertices = [vertex(0, 0, RED), vertex(64, 0, BLUE), vertex(0, 64, BLUE)];
+        draw_triangle(&mut vram, &settings(), &triangle(vertices, true));
+        assert_eq!(Target::read(&vram, 0, 0), 0x001F);
+        assert_eq!(Target::read(&vram, 63, 0), 0x7C00);
+        assert_eq!(Target::read(&vram, 32, 0), 0x4010);
+    }
+
+    #[test]
+    fn triangle_is_rasterized_at_the_target_resolution() {
+        let vram = Vram::new();
+        let mut hires = HiresVram::new(&vram, 2);
+        let mut target = HiresTarget {
+            vram: &vram,
+            hires: &mut hires,
+        };
+        let red = triangle(
+            [vertex(0, 0, RED), vertex(4, 0, RED), vertex(0, 4, RED)],
+            false,
+        );
+        assert_eq!(draw_triangle(&mut target, &settings(), &red), 36);
+        let expected: Vec<_> = (0..8)
+            .flat_map(|y| (0..8 - y).map(move |x| (x, y)))
+            .collect();
+        assert_eq!(drawn(&target, 16), expected);
+        assert!(drawn(&vram, 8).is_empty());
+    }
+
+    #[test]
+    fn triangle_is_clipped_to_the_draw_area() {
+        let mut vram = Vram::new();
+        let settings = DrawSettings {
+            draw_area_top_left: (2, 2),
+            draw_area_bot_right: (5, 5),
+            ..settings()
+        };
+        let red = triangle(
+            [vertex(0, 0, RED), vertex(16, 0, RED), vertex(0, 16, RED)],
+            false,
+        );
+        assert_eq!(draw_triangle(&mut vram, &settings, &red), 16);
+        let expected: Vec<_> = (2..6).flat_map(|y| (2..6).map(move |x| (x, y))).collect();
+        assert_eq!(drawn(&vram, 16), expected);
+    }
+
+    #[test]
+    fn rect_covers_its_width_and_height() {
+        let mut vram = Vram::new();
+        assert_eq!(
+            draw_rect(&mut vram, &settings(), &rect((2, 3), 4, 2, BLUE)),
+            8
+        );
+        let expected: Vec<_> = (3..5).flat_map(|y| (2..6).map(move |x| (x, y))).collect();
+        assert_eq!(drawn(&vram, 8), expected);
+        assert_eq!(Target::read(&vram, 2, 3), 0x7C00);
+    }
+
+    #[test]
+    fn single_pixel_rect_reaches_the_vram_corner() {
+        let mut vram = Vram::new();
+        assert_eq!(
+            draw_rect(&mut vram, &settings(), &rect((1023, 511), 1, 1, RED)),
+            1
+        );
+        assert_eq!(vram.read(1024 * 511 + 1023), 0x001F);
+        // Nothing wrapped around to the other corner
+        assert_eq!(vram.read(0), 0);
+        assert_eq!(
+            draw_rect(&mut vram, &settings(), &rect((1024, 512), 1, 1, RED)),
+            0
+        );
+    }
+
+    #[test]
+    fn single_pixel_rect_at_the_draw_area_bounds() {
+        let mut vram = Vram::new();
+        let settings = DrawSettings {
+            draw_area_top_left: (2, 2),
+            draw_area_bot_right: (5, 5),
+            ..settings()
+        };
+        for pos in [(2, 2), (5, 2), (2, 5), (5, 5)] {
+            assert_eq!(draw_rect(&mut vram, &settings, &rect(pos, 1, 1, RED)), 1);
+        }
+        for pos in [(1, 2), (2, 1), (6, 5), (5, 6)] {
+            assert_eq!(draw_rect(&mut vram, &settings, &rect(pos, 1, 1, RED)), 0);
+        }
+        assert_eq!(drawn(&vram, 8), [(2, 2), (5, 2), (2, 5), (5, 5)]);
+    }
+
+    #[test]
+    fn rect_honors_the_mask_bit() {
+        let mut vram = Vram::new();
+        vram.write(1024 + 1, 0x8001);
+        let settings = DrawSettings {
+            mask_before_draw: true,
+            ..settings()
+        };
+        assert_eq!(draw_rect(&mut vram, &settings, &rect((0, 0), 2, 2, RED)), 3);
+        assert_eq!(Target::read(&vram, 1, 1), 0x8001);
+
+        let settings = DrawSettings {
+            mask_while_draw: true,
+            ..settings
+        };
+        draw_rect(&mut vram, &settings, &rect((4, 0), 1, 1, RED));
+        assert_eq!(Target::read(&vram, 4, 0), 0x801F);
+    }
+
+    #[test]
+    fn semi_transparent_rect_adds_to_vram() {
+        let mut vram = Vram::new();
+        vram.write(0, 0x0010);
+        let settings = DrawSettings {
+            semitransparency: SemiTransparency::Add,
+            ..settings()
+        };
+        let blend = Rect {
+            semi_transparent: true,
+            ..rect((0, 0), 1, 1, 0x000040)
+        };
+        draw_rect(&mut vram, &settings, &blend);
+        assert_eq!(Target::read(&vram, 0, 0), 0x0018);
+    }
+
+    #[test]
+    fn line_includes_both_ends() {
+        let mut vram = Vram::new();
+        let red = line(vertex(1, 1, RED), vertex(5, 1, RED), false);
+        assert_eq!(draw_line(&mut vram, &settings(), &red), 5);
+        let diagonal = line(vertex(6, 6, RED), vertex(3, 3, RED), false);
+        assert_eq!(draw_line(&mut vram, &settings(), &diagonal), 4);
+        let expected = vec![
+            (1, 1),
+            (2, 1),
+            (3, 1),
+            (4, 1),
+            (5, 1),
+            (3, 3),
+            (4, 4),
+            (5, 5),
+            (6, 6),
+        ];
+        assert_eq!(drawn(&vram, 8), expected);
+    }
+
+    #[test]
+    fn zero_length_line_is_its_start_point() {
+        let mut vram = Vram::new();
+        let point = line(vertex(5, 3, RED), vertex(5, 3, BLUE), true);
+        assert_eq!(draw_line(&mut vram, &settings(), &point), 1);
+        assert_eq!(drawn(&vram, 8), vec![(5, 3)]);
+        assert_eq!(Target::read(&vram, 5, 3), 0x001F);
+
+        // Outside the draw area nothing is drawn
+        let settings = DrawSettings {
+            draw_area_top_left: (6, 0),
+            ..settings()
+        };
+        assert_eq!(draw_line(&mut vram, &settings, &point), 0);
+    }
+
+    #[test]
+    fn shaded_line_fades_between_its_ends() {
+        let mut vram = Vram::new();
+        let fade = line(vertex(0, 0, RED), vertex(0, 31, BLUE), true);
+        draw_line(&mut vram, &settings(), &fade);
+        assert_eq!(Target::read(&vram, 0, 0), 0x001F);
+        assert_eq!(Target::read(&vram, 0, 31), 0x7C00);
+        let middle = Target::read(&vram, 0, 16);
+        assert!((middle & 0x1F) > 0 && (middle >> 10) > 0);
+    }
+
+    #[test]
+    fn line_and_rect_keep_native_resolution_in_the_hires_copy() {
+        let mut vram = Vram::new();
+        let mut hires = HiresVram::new(&vram, 2);
+        let mut target = NearestTarget {
+            vram: &mut vram,
+            hires: &mut hires,
+        };
+        draw_rect(&mut target, &settings(), &rect((1, 1), 1, 1, RED));
+        draw_line(
+            &mut target,
+            &settings(),
+            &line(vertex(3, 0, BLUE), vertex(3, 1, BLUE), false),
+        );
+        assert_eq!(drawn(&vram, 4), vec![(3, 0), (1, 1), (3, 1)]);
+        let expected = vec![
+            (6, 0),
+            (7, 0),
+            (6, 1),
+            (7, 1),
+            (2, 2),
+            (3, 2),
+            (6, 2),
+            (7, 2),
+            (2, 3),
+            (3, 3),
+            (6, 3),
+            (7, 3),
+        ];
+        let pixels: Vec<_> = (0..8)
+            .flat_map(|y| (0..8).map(move |x| (x, y)))
+            .filter(|&(x, y)| hires.read(x, y) != 0)
+            .collect();
+        assert_eq!(pixels, expected);
+    }
+
+    #[test]
+    fn texture_pages_and_cluts_wrap_at_the_vram_edges() {
+        let mut vram = Vram::new();
+        let settings = DrawSettings {
+            tex_page: (960, 256),
+            tex_page_colors: TextureBits::Eight,
+            ..settings()
+        };
+        // u = 254 of an 8-bit page at x = 960 is VRAM x 1087, which wraps to 63. Index 0x20 of
+        // a CLUT at x = 1008 wraps to x = 16
+        vram.write(63 + 1024 * 256, 0x0020);
+        vram.write(16 + 1024 * 511, 0x1234);
+        let texel = Rect {
+            textured: true,
+            uv: (254, 0),
+            clut: (1008, 511),
+            ..rect((0, 0), 1, 1, 0)
+        };
+        assert_eq!(draw_rect(&mut vram, &settings, &texel), 1);
+        assert_eq!(Target::read(&vram, 0, 0), 0x1234);
+
+        // A 15-bit page wraps the same way. u = 200 is VRAM x 1160, which wraps to 136
+        let settings = DrawSettings {
+            tex_page: (960, 256),
+            tex_page_colors: TextureBits::Fifteen,
+            ..settings
+        };
+        vram.write(136 + 1024 * 511, 0x4321);
+        let texel = Rect {
+            textured: true,
+            uv: (200, 255),
+            ..rect((1, 0), 1, 1, 0)
+        };
+        draw_rect(&mut vram, &settings, &texel);
+        assert_eq!(Target::read(&vram, 1, 0), 0x4321);
+    }
+
+    // 64-bit FNV-1a of all of VRAM
+    fn checksum(vram: &Vram) -> u64 {
+        vram.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01B3)
+        })
+    }
+
+    // Texture and CLUTs drawn from by the textured scenes. A 15-bit texture at (512, 0), 4 and
+    // 8-bit textures at (640, 0) and CLUTs on row 480
+    fn texture_vram() -> Vram {
+        let mut vram = Vram::new();
+        for y in 0..256 {
+            for x in 512..768 {
+                let val = (x * 7 + y * 13) as u16 ^ ((y as u16) << 9);
+                vram.write(x + 1024 * y, val);
+            }
+        }
+        for index in 0..256 {
+            let color =
+                (index as u16).wrapping_mul(0x0421) | if index % 5 == 0 { 0x8000 } else { 0 };
+            vram.write(index + 1024 * 480, color);
+        }
+        vram
+    }
+
+    // Scenes covering each kind of primitive and drawing setting, each into its own VRAM
+    fn golden_scenes() -> Vec<(&'static str, Vram)> {
+        let mut scenes = Vec::new();
+
+        let mut vram = Vram::new();
+        let colors = [RED, 0x00FF00, BLUE];
+        for (i, &color) in colors.iter().enumerate() {
+            let x = 40 * i as i32;
+            let flat = [
+                vertex(x, 0, color),
+                vertex(x + 37, 5, color),
+                vertex(x + 3, 29, color),
+            ];
+            draw_triangle(&mut vram, &settings(), &triangle(flat, false));
+        }
+        let shaded = [
+            vertex(0, 40, RED),
+            vertex(120, 50, 0x00FF00),
+            vertex(30, 130, BLUE),
+        ];
+        draw_triangle(&mut vram, &settings(), &triangle(shaded, true));
+        let dithered = DrawSettings {
+            dither_enabled: true,
+            ..settings()
+        };
+        let shaded = [
+            vertex(130, 40, 0x102030),
+            vertex(250, 60, 0xF0E0D0),
+            vertex(140, 140, 0x808080),
+        ];
+        draw_triangle(&mut vram, &dithered, &triangle(shaded, true));
+        scenes.push(("triangles", vram));
+
+        let mut vram = texture_vram();
+        for (i, bits) in [TextureBits::Four, TextureBits::Eight, TextureBits::Fifteen]
+            .into_iter()
+            .enumerate()
+        {
+            let settings = DrawSettings {
+                tex_page: (if i == 2 { 512 } else { 640 }, 0),
+                tex_page_colors: bits,
+                texture_window: if i == 2 { 0x000C6 } else { 0 },
+                rect_x_flip: i == 1,
+                ..settings()
+            };
+            let y = 70 * i as i32;
+            let mut vertices = [
+                vertex(0, y, 0x808080),
+                vertex(63, y, 0x4080C0),
+                vertex(0, y + 63, 0xC08040),
+            ];
+            for (vertex, uv) in vertices.iter_mut().zip([(0, 0), (255, 0), (0, 255)]) {
+                vertex.uv = uv;
+            }
+            let textured = Triangle {
+                textured: true,
+                modulate: true,
+                clut: (0, 480),
+                ..triangle(vertices, true)
+            };
+            draw_triangle(&mut vram, &settings, &textured);
+            let sprite = Rect {
+                textured: true,
+                modulate: i != 0,
+                uv: (16, 32),
+                clut: (0, 480),
+                ..rect((80, y), 48, 40, 0x606060)
+            };
+            draw_rect(&mut vram, &settings, &sprite);
+        }
+        scenes.push(("textured", vram));
+
+        let mut vram = Vram::new();
+        draw_rect(&mut vram, &settings(), &rect((0, 0), 200, 40, 0x8040C0));
+        for (i, mode) in [
+            SemiTransparency::Blend,
+            SemiTransparency::Add,
+            SemiTransparency::Subtract,
+            SemiTransparency::QuarterBlend,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let settings = DrawSettings {
+                semitransparency: mode,
+                mask_while_draw: i == 3,
+                ..settings()
+            };
+            let x = 50 * i as i32;
+            let blend = Rect {
+                semi_transparent: true,
+                ..rect((x, 10), 40, 40, 0x40C060)
+            };
+            draw_rect(&mut vram, &settings, &blend);
+            let over = [
+                vertex(x, 60, 0xFF8000),
+                vertex(x + 40, 30, 0x0080FF),
+                vertex(x + 20, 90, 0x20FF20),
+            ];
+            let over = Triangle {
+                semi_transparent: true,
+                ..triangle(over, true)
+            };
+            draw_triangle(&mut vram, &settings, &over);
+        }
+        scenes.push(("blending", vram));
+
+        let mut vram = Vram::new();
+        let clipped = DrawSettings {
+            draw_area_top_left: (10, 10),
+            draw_area_bot_right: (200, 100),
+            ..settings()
+        };
+        for i in 0..16 {
+            let angle = i as f32 * std::f32::consts::PI / 8.0;
+            let end = (
+                100 + (angle.cos() * 120.0) as i32,
+                60 + (angle.sin() * 80.0) as i32,
+            );
+            let spoke = line(
+                vertex(100, 60, RED),
+                vertex(end.0, end.1, 0x00FF00 + i * 16),
+                i % 2 == 0,
+            );
+            draw_line(&mut vram, &clipped, &spoke);
+        }
+        scenes.push(("lines", vram));
+
+        let mut vram = texture_vram();
+        let settings = DrawSettings {
+            tex_page: (960, 256),
+            tex_page_colors: TextureBits::Eight,
+            ..settings()
+        };
+        for y in 256..512 {
+            for x in 960..1024 {
+                vram.write(x + 1024 * y, (x * 3 + y) as u16);
+            }
+        }
+        let sprite = Rect {
+            textured: true,
+            uv: (128, 0),
+            clut: (1008, 480),
+            ..rect((0, 0), 128, 64, 0)
+        };
+        draw_rect(&mut vram, &settings, &sprite);
+        scenes.push(("vram_edge", vram));
+
+        scenes
+    }
+
+    const GOLDEN_PATH: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/testdata/rasterizer_golden.txt"
+    );
+
+    // Run with UPDATE_GOLDEN=1 to rewrite the checksums after a change meant to alter the output
+    #[test]
+    fn scenes_match_the_golden_checksums() {
+        let checksums: Vec<_> = golden_scenes()
+            .iter()
+            .map(|(name, vram)| format!("{name} {:016X}", checksum(vram)))
+            .collect();
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::write(GOLDEN_PATH, checksums.join("\n") + "\n").unwrap();
+            return;
+        }
+        let golden = std::fs::read_to_string(GOLDEN_PATH).unwrap();
+        let golden: Vec<_> = golden.lines().collect();
+        assert_eq!(
+            checksums, golden,
+            "rendering changed, rerun with UPDATE_GOLDEN=1 if that was intended"
+        );
+    }
+}
@@ -1,5 +1,7 @@
+use serde::{Deserialize, Serialize};
 use tracing::{Level, event};
 
+#[derive(Serialize, Deserialize)]
 pub struct Gte {
     pub enabled: bool,
     /* Data Registers */
@@ -0,0 +1,274 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use eframe::egui;
+use serde::{Deserialize, Deserializer, Serialize};
+
+// Frontend actions bound to a key combination
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Hotkey {
+    Pause,
+    // Runs one frame while paused, repeating while held
+    FrameAdvance,
+    // Ends a recording or takes back control from a movie
+    StopMovie,
+    Reset,
+    QuitToList,
+    Quit,
+    Fullscreen,
+    SpeedOverlay,
+    // Lasts while held
+    FastForward,
+    Turbo,
+    Screenshot,
+    CopyScreenshot,
+    VramScreenshot,
+    // Save states go to the selected slot
+    SaveState,
+    LoadState,
+    PrevSlot,
+    NextSlot,
+    ReleaseMouse,
+    DumpVram,
+    LoadVram,
+    // Only while paused
+    PrintPc,
+}
+
+impl Hotkey {
+    // In the order the hotkeys window lists them
+    pub const ALL: [Hotkey; 21] = [
+        Hotkey::Pause,
+        Hotkey::FrameAdvance,
+        Hotkey::StopMovie,
+        Hotkey::Reset,
+        Hotkey::QuitToList,
+        Hotkey::Quit,
+        Hotkey::Fullscreen,
+        Hotkey::SpeedOverlay,
+        Hotkey::FastForward,
+        Hotkey::Turbo,
+        Hotkey::Screenshot,
+        Hotkey::CopyScreenshot,
+        Hotkey::VramScreenshot,
+        Hotkey::SaveState,
+        Hotkey::LoadState,
+        Hotkey::PrevSlot,
+        Hotkey::NextSlot,
+        Hotkey::ReleaseMouse,
+        Hotkey::DumpVram,
+        Hotkey::LoadVram,
+        Hotkey::PrintPc,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Hotkey::Pause => "Pause",
+            Hotkey::FrameAdvance => "Frame advance",
+            Hotkey::StopMovie => "Stop movie",
+            Hotkey::Reset => "Reset",
+            Hotkey::QuitToList => "Quit to game list",
+            Hotkey::Quit => "Quit",
+            Hotkey::Fullscreen => "Fullscreen",
+            Hotkey::SpeedOverlay => "Speed overlay",
+            Hotkey::FastForward => "Fast forward (hold)",
+            Hotkey::Turbo => "Turbo",
+            Hotkey::Screenshot => "Screenshot",
+            Hotkey::CopyScreenshot => "Copy screenshot",
+            Hotkey::VramScreenshot => "VRAM screenshot",
+            Hotkey::SaveState => "Save state",
+            Hotkey::LoadState => "Load state",
+            Hotkey::PrevSlot => "Previous save slot",
+            Hotkey::NextSlot => "Next save slot",
+            Hotkey::ReleaseMouse => "Release mouse",
+            Hotkey::DumpVram => "Dump VRAM",
+            Hotkey::LoadVram => "Load VRAM",
+            Hotkey::PrintPc => "Print PC",
+        }
+    }
+}
+
+// Key combination of each hotkey, written like "Ctrl+Shift+R". Unknown names never trigger
+#[derive(Serialize, Clone, PartialEq)]
+#[serde(transparent)]
+pub struct Hotkeys {
+    pub keys: BTreeMap<Hotkey, String>,
+}
+
+// Hotkeys added since the config was written get their default combination
+impl<'de> Deserialize<'de> for Hotkeys {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut keys = BTreeMap::deserialize(deserializer)?;
+        for (hotkey, combo) in Hotkeys::default().keys {
+            keys.entry(hotkey).or_insert(combo);
+        }
+        Ok(Self { keys })
+    }
+}
+
+impl Default for Hotkeys {
+    fn default() -> Self {
+        let keys = [
+            (Hotkey::Pause, "P"),
+            (Hotkey::FrameAdvance, "Backslash"),
+            (Hotkey::StopMovie, "Ctrl+M"),
+            (Hotkey::Reset, "Ctrl+R"),
+            (Hotkey::QuitToList, "Ctrl+W"),
+            (Hotkey::Quit, "Escape"),
+            (Hotkey::Fullscreen, "F11"),
+            (Hotkey::SpeedOverlay, "F3"),
+            (Hotkey::FastForward, "Backtick"),
+            (Hotkey::Turbo, "F9"),
+            (Hotkey::Screenshot, "F12"),
+            (Hotkey::CopyScreenshot, "Ctrl+F12"),
+            (Hotkey::VramScreenshot, "Shift+F12"),
+            (Hotkey::SaveState, "F5"),
+            (Hotkey::LoadState, "F7"),
+            (Hotkey::PrevSlot, "F6"),
+            (Hotkey::NextSlot, "F8"),
+            (Hotkey::ReleaseMouse, "F10"),
+            (Hotkey::DumpVram, "Shift+F5"),
+            (Hotkey::LoadVram, "Shift+F6"),
+            (Hotkey::PrintPc, "L"),
+        ];
+        Self {
+            keys: keys
+                .into_iter()
+                .map(|(hotkey, combo)| (hotkey, combo.to_string()))
+                .collect(),
+        }
+    }
+}
+
+impl Hotkeys {
+    // Hotkey started by an event. Only the first press counts, so holding a key triggers once
+    pub fn triggered(&self, event: &egui::Event) -> Option<Hotkey> {
+        let egui::Event::Key {
+            key,
+            pressed: true,
+            repeat: false,
+            modifiers,
+            ..
+        } = event
+        else {
+            return None;
+        };
+        let combo = combo_name(*key, *modifiers);
+        self.keys
+            .iter()
+            .find(|(_, keys)| **keys == combo)
+            .map(|(hotkey, _)| *hotkey)
+    }
+
+    // Whether a hotkey's combination is down, for hotkeys that last while held
+    pub fn held(&self, hotkey: Hotkey, input: &egui::InputState) -> bool {
+        let Some(combo) = self.keys.get(&hotkey) else {
+            return false;
+        };
+        // Key names never contain a '+'
+        match combo.rsplit('+').next().and_then(egui::Key::from_name) {
+            Some(key) => input.key_down(key) && combo_name(key, input.modifiers) == *combo,
+            None => false,
+        }
+    }
+
+    // Combinations bound to more than one hotkey
+    pub fn conflicts(&self) -> BTreeSet<String> {
+        let mut seen = BTreeSet::new();
+        self.keys
+            .values()
+            .filter(|combo| !seen.insert(*combo))
+            .cloned()
+            .collect()
+    }
+}
+
+// Modifiers come first in a fixed order, so each combination has one name
+pub fn combo_name(key: egui::Key, modifiers: egui::Modifiers) -> String {
+    let mut name = String::new();
+    for (held, modifier) in [
+        (modifiers.ctrl, "Ctrl+"),
+        (modifiers.alt, "Alt+"),
+        (modifiers.shift, "Shift+"),
+    ] {
+        if held {
+            name.push_str(modifier);
+        }
+    }
+    name.push_str(key.name());
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(key: egui::Key, modifiers: egui::Modifiers, pressed: bool, repeat: bool) -> egui::Event {
+        egui::Event::Key {
+            key,
+            physical_key: None,
+            pressed,
+            repeat,
+            modifiers,
+        }
+    }
+
+    #[test]
+    fn hotkeys_trigger_on_the_first_press_only() {
+        let hotkeys = Hotkeys::default();
+        let none = egui::Modifiers::NONE;
+        // Holding P sends repeats and then a release
+        let events = [
+            key(egui::Key::P, none, true, false),
+            key(egui::Key::P, none, true, true),
+            key(egui::Key::P, none, true, true),
+            key(egui::Key::P, none, false, false),
+            key(egui::Key::P, none, true, false),
+            egui::Event::Text("p".to_string()),
+        ];
+        let triggered: Vec<_> = events
+            .iter()
+            .map(|event| hotkeys.triggered(event))
+            .collect();
+        assert_eq!(
+            triggered,
+            [
+                Some(Hotkey::Pause),
+                None,
+                None,
+                None,
+                Some(Hotkey::Pause),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn modifiers_pick_the_hotkey() {
+        let hotkeys = Hotkeys::default();
+        let press = |modifiers| hotkeys.triggered(&key(egui::Key::F12, modifiers, true, false));
+        assert_eq!(press(egui::Modifiers::NONE), Some(Hotkey::Screenshot));
+        assert_eq!(press(egui::Modifiers::CTRL), Some(Hotkey::CopyScreenshot));
+        assert_eq!(press(egui::Modifiers::SHIFT), Some(Hotkey::VramScreenshot));
+        assert_eq!(press(egui::Modifiers::ALT), None);
+        // Modifiers are always named in the same order
+        assert_eq!(
+            combo_name(egui::Key::R, egui::Modifiers::SHIFT | egui::Modifiers::CTRL),
+            "Ctrl+Shift+R"
+        );
+    }
+
+    #[test]
+    fn saved_bindings_gain_new_hotkeys_and_show_conflicts() {
+        // A config from before most hotkeys existed, with Pause moved
+        let hotkeys: Hotkeys = toml::from_str("Pause = \"Space\"\nReset = \"F5\"\n").unwrap();
+        assert_eq!(hotkeys.keys.len(), Hotkey::ALL.len());
+        assert_eq!(hotkeys.keys[&Hotkey::Pause], "Space");
+        assert_eq!(hotkeys.keys[&Hotkey::Quit], "Escape");
+        // Reset took the save state key
+        assert_eq!(hotkeys.conflicts(), BTreeSet::from(["F5".to_string()]));
+        assert!(Hotkeys::default().conflicts().is_empty());
+
+        let text = toml::to_string(&hotkeys).unwrap();
+        assert!(toml::from_str::<Hotkeys>(&text).unwrap() == hotkeys);
+    }
+}
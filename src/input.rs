@@ -0,0 +1,276 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use eframe::egui;
+use serde::{Deserialize, Serialize};
+use tracing::{Level, event};
+
+use crate::pad::Buttons;
+
+// Pad buttons by the name bindings use, in the order the bindings UI lists them
+pub const BUTTON_NAMES: [(&str, u16); 14] = [
+    ("up", Buttons::UP),
+    ("down", Buttons::DOWN),
+    ("left", Buttons::LEFT),
+    ("right", Buttons::RIGHT),
+    ("cross", Buttons::CROSS),
+    ("circle", Buttons::CIRCLE),
+    ("square", Buttons::SQUARE),
+    ("triangle", Buttons::TRIANGLE),
+    ("l1", Buttons::L1),
+    ("r1", Buttons::R1),
+    ("l2", Buttons::L2),
+    ("r2", Buttons::R2),
+    ("start", Buttons::START),
+    ("select", Buttons::SELECT),
+];
+
+// Shift is a modifier rather than a key in egui, so it is bound by this name
+const SHIFT: &str = "Shift";
+
+// Keys bound to each button of a pad, by egui key name. Unknown names are ignored
+#[derive(Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(transparent)]
+pub struct PadBinding {
+    pub keys: BTreeMap<String, Vec<String>>,
+}
+
+impl PadBinding {
+    fn new(keys: [&[&str]; 14]) -> Self {
+        Self {
+            keys: BUTTON_NAMES
+                .iter()
+                .zip(keys)
+                .map(|((button, _), keys)| {
+                    (
+                        button.to_string(),
+                        keys.iter().map(|key| key.to_string()).collect(),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    // Buttons held given the keyboard state
+    pub fn buttons(&self, input: &egui::InputState) -> Buttons {
+        let mut buttons = Buttons::default();
+        for (name, button) in BUTTON_NAMES {
+            let held = self.keys.get(name).is_some_and(|keys| {
+                keys.iter().any(|key| match egui::Key::from_name(key) {
+                    Some(key) => input.key_down(key),
+                    None => key == SHIFT && input.modifiers.shift,
+                })
+            });
+            buttons.set(button, held);
+        }
+        buttons
+    }
+}
+
+// Keyboard bindings of the two layouts players choose from and the gamepad bindings, saved
+// to input.toml
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct InputConfig {
+    pub port1: PadBinding,
+    pub port2: PadBinding,
+    // Replaces both layouts while the game with this name is running
+    pub games: BTreeMap<String, [PadBinding; 2]>,
+    // Gamepad buttons by gilrs name, such as "South". Every gamepad uses the same bindings
+    pub gamepad: PadBinding,
+    // How far the left stick is pushed before it presses the d-pad, from 0 to 1
+    pub stick_dead_zone: f32,
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        Self {
+            port1: PadBinding::new([
+                &["ArrowUp"],
+                &["ArrowDown"],
+                &["ArrowLeft"],
+                &["ArrowRight"],
+                &["X"],
+                &["Z"],
+                &["A"],
+                &["S"],
+                &["Q"],
+                &["W"],
+                &["E"],
+                &["R"],
+                &["Enter"],
+                &["Backslash", SHIFT],
+            ]),
+            port2: PadBinding::new([
+                &["I"],
+                &["K"],
+                &["J"],
+                &["L"],
+                &["N"],
+                &["M"],
+                &["B"],
+                &["H"],
+                &["U"],
+                &["O"],
+                &["7"],
+                &["9"],
+                &["Space"],
+                &["Backspace"],
+            ]),
+            games: BTreeMap::new(),
+            gamepad: PadBinding::new([
+                &["DPadUp"],
+                &["DPadDown"],
+                &["DPadLeft"],
+                &["DPadRight"],
+                &["South"],
+                &["East"],
+                &["West"],
+                &["North"],
+                &["LeftTrigger"],
+                &["RightTrigger"],
+                &["LeftTrigger2"],
+                &["RightTrigger2"],
+                &["Start"],
+                &["Select"],
+            ]),
+            stick_dead_zone: 0.5,
+        }
+    }
+}
+
+impl InputConfig {
+    // Missing or unreadable files give the default bindings
+    pub fn load(path: &Path) -> Self {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Self::default(),
+            Err(err) => {
+                event!(target: "ps1_emulator::Input", Level::WARN, "Failed to read {}: {err}", path.display());
+                return Self::default();
+            }
+        };
+        toml::from_str(&text).unwrap_or_else(|err| {
+            event!(
+                target: "ps1_emulator::Input",
+                Level::WARN,
+                "Failed to parse {}, using default bindings: {err}",
+                path.display()
+            );
+            Self::default()
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let text = toml::to_string(self).map_err(io::Error::other)?;
+        fs::write(path, text)
+    }
+
+    // Layouts in use, taking a game's overrides into account
+    pub fn layouts(&self, game: Option<&str>) -> [&PadBinding; 2] {
+        match game.and_then(|game| self.games.get(game)) {
+            Some([port1, port2]) => [port1, port2],
+            None => [&self.port1, &self.port2],
+        }
+    }
+
+    pub fn layouts_mut(&mut self, game: Option<&str>) -> [&mut PadBinding; 2] {
+        match game.and_then(|game| self.games.get_mut(game)) {
+            Some([port1, port2]) => [port1, port2],
+            None => [&mut self.port1, &mut self.port2],
+        }
+    }
+}
+
+// Keys bound to more than one button across both layouts
+pub fn conflicts(layouts: [&PadBinding; 2]) -> BTreeSet<String> {
+    let mut seen = BTreeSet::new();
+    let mut conflicts = BTreeSet::new();
+    for layout in layouts {
+        for key in layout.keys.values().flatten() {
+            if !seen.insert(key) {
+                conflicts.insert(key.clone());
+            }
+        }
+    }
+    conflicts
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn input_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("ps1_emulator_{name}_{}.toml", std::process::id()))
+    }
+
+    #[test]
+    fn bindings_survive_a_save_and_load() {
+        let mut config = InputConfig::default();
+        config.port1.keys.insert(
+            "cross".to_string(),
+            vec!["C".to_string(), SHIFT.to_string()],
+        );
+        let mut game = [config.port2.clone(), config.port1.clone()];
+        game[0]
+            .keys
+            .insert("start".to_string(), vec!["F".to_string()]);
+        config.games.insert("SLUS_123.45".to_string(), game);
+        config.gamepad.keys.remove("select");
+        config.stick_dead_zone = 0.25;
+
+        let path = input_path("input_round_trip");
+        config.save(&path).unwrap();
+        let loaded = InputConfig::load(&path);
+        let _ = fs::remove_file(&path);
+        assert!(loaded == config);
+
+        let [port1, port2] = loaded.layouts(Some("SLUS_123.45"));
+        assert_eq!(port1.keys["start"], ["F"]);
+        assert!(port2 == &config.port1);
+        assert!(loaded.layouts(Some("SLES_000.00"))[0] == &config.port1);
+        assert!(loaded.layouts(None)[1] == &config.port2);
+    }
+
+    #[test]
+    fn missing_or_broken_files_give_the_defaults() {
+        let path = input_path("input_missing");
+        let _ = fs::remove_file(&path);
+        assert!(InputConfig::load(&path) == InputConfig::default());
+
+        let path = input_path("input_broken");
+        fs::write(&path, "port1 = [").unwrap();
+        assert!(InputConfig::load(&path) == InputConfig::default());
+
+        // Settings left out keep their defaults
+        fs::write(&path, "stick_dead_zone = 0.75\n").unwrap();
+        let config = InputConfig::load(&path);
+        let _ = fs::remove_file(&path);
+        assert_eq!(config.stick_dead_zone, 0.75);
+        assert!(config.port1 == InputConfig::default().port1);
+    }
+
+    #[test]
+    fn keys_bound_twice_conflict() {
+        let mut config = InputConfig::default();
+        assert!(conflicts(config.layouts(None)).is_empty());
+
+        // Within a layout and across the two
+        config
+            .port1
+            .keys
+            .insert("circle".to_string(), vec!["X".to_string()]);
+        config
+            .port2
+            .keys
+            .insert("start".to_string(), vec!["Enter".to_string()]);
+        assert_eq!(
+            conflicts(config.layouts(None)),
+            BTreeSet::from(["Enter".to_string(), "X".to_string()])
+        );
+    }
+}
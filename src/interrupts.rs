@@ -1,6 +1,7 @@
+use serde::{Deserialize, Serialize};
 use tracing::{Level, event};
 
-#[derive(Default)]
+#[derive(Default, Serialize, Deserialize)]
 pub struct Interrupt {
     pub stat: u32,
     pub mask: u32,
@@ -24,11 +25,16 @@ impl Interrupt {
         self.stat |= 0x1;
     }
 
-    pub fn _set_gpu_irq(&mut self) {
+    pub fn set_gpu_irq(&mut self) {
         event!(target: "ps1_emulator::INT", Level::TRACE, "GPU Interrupt Set");
         self.stat |= 0x2;
     }
 
+    pub fn set_cdrom_irq(&mut self) {
+        event!(target: "ps1_emulator::INT", Level::TRACE, "CDROM Interrupt Set");
+        self.stat |= 0x4;
+    }
+
     pub fn set_dma_irq(&mut self) {
         event!(target: "ps1_emulator::INT", Level::TRACE, "DMA Interrupt Set");
         self.stat |= 0x8;
@@ -48,4 +54,19 @@ impl Interrupt {
         event!(target: "ps1_emulator::INT", Level::TRACE, "Timer 2 Interrupt Set");
         self.stat |= 0x40;
     }
+
+    pub fn set_controller_irq(&mut self) {
+        event!(target: "ps1_emulator::INT", Level::TRACE, "Controller Interrupt Set");
+        self.stat |= 0x80;
+    }
+
+    pub fn set_sio_irq(&mut self) {
+        event!(target: "ps1_emulator::INT", Level::TRACE, "SIO Interrupt Set");
+        self.stat |= 0x100;
+    }
+
+    pub fn set_spu_irq(&mut self) {
+        event!(target: "ps1_emulator::INT", Level::TRACE, "SPU Interrupt Set");
+        self.stat |= 0x200;
+    }
 }
@@ -1,36 +1,68 @@
+mod audio;
+mod bios;
+mod bios_calls;
+mod breakpoints;
 mod bus;
+mod cdrom;
+mod chd;
+mod cheats;
+mod config;
 mod cop0;
 mod cpu;
+mod disasm;
+mod disassembly_viewer;
+mod disc;
 mod dma;
+mod emulator;
+mod exe;
 mod frontend;
+mod gamepad;
 mod gpu;
 mod gte;
+mod hotkey;
+mod input;
 mod interrupts;
 mod mdec;
+mod memcard;
+mod memory_viewer;
+mod mouse;
+mod movie;
+mod multitap;
+mod notifications;
+mod pad;
+mod register_viewer;
+mod screenshot;
+mod sio;
+mod sio1;
+mod spu;
+mod state;
 mod timer;
 mod tracing_setup;
+mod tty_console;
+mod vram_viewer;
+mod watchpoints;
 
+use config::{CONFIG_PATH, Config};
 use eframe::egui;
 use frontend::MyApp;
-use std::path::PathBuf;
+use std::path::Path;
 
 fn main() {
+    // Loaded first so the window opens at the size it was closed at
+    let config = Config::load(Path::new(CONFIG_PATH));
     let options = eframe::NativeOptions {
-        viewport: egui::ViewportBuilder::default().with_inner_size([1040.0, 560.0]),
+        viewport: egui::ViewportBuilder::default()
+            .with_inner_size(config.window_size.unwrap_or([1040.0, 560.0])),
         ..Default::default()
     };
 
-    //let folder: PathBuf = PathBuf::from("roms/tests/tests-Jaczekanski/gpu/animated-triangle/");
-    let folder: PathBuf = PathBuf::from("roms/tests/tests-Jaczekanski/timers/");
-    //let folder: PathBuf = PathBuf::from("roms/tests/tests/");
-
     let _ = eframe::run_native(
         "PS1 Emulator",
         options,
         Box::new(|cc| {
             Ok(Box::<MyApp>::new(MyApp::new(
                 cc,
-                folder,
+                config,
                 true,
                 Some(/*0x800507B8*/ 0x80011998),
             )))
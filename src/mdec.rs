@@ -1,39 +1,589 @@
-#![allow(unused)]
+use std::collections::VecDeque;
 
+use serde::{Deserialize, Serialize};
+use tracing::{Level, event};
+
+use crate::state;
+
+// Position in the 8x8 block of each coefficient, in the order they are sent
+const ZIGZAG: [usize; 64] = [
+    0, 1, 5, 6, 14, 15, 27, 28, 2, 4, 7, 13, 16, 26, 29, 42, 3, 8, 12, 17, 25, 30, 41, 43, 9, 11,
+    18, 24, 31, 40, 44, 53, 10, 19, 23, 32, 39, 45, 52, 54, 20, 22, 33, 38, 46, 51, 55, 60, 21, 34,
+    37, 47, 50, 56, 59, 61, 35, 36, 48, 49, 57, 58, 62, 63,
+];
+const ZAGZIG: [usize; 64] = {
+    let mut table = [0; 64];
+    let mut i = 0;
+    while i < 64 {
+        table[ZIGZAG[i]] = i;
+        i += 1;
+    }
+    table
+};
+// Halfword padding the compressed stream between blocks
+const PADDING: u16 = 0xFE00;
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum Command {
+    None,
+    Decode,
+    SetQuant,
+    SetScale,
+}
+
+// Output depths selected by command bits 27-28
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum Depth {
+    Four,
+    Eight,
+    TwentyFour,
+    Fifteen,
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct Mdec {
-    command: u32,
-    data: u32,
-    status: u32,
-    control: u32,
+    command: Command,
+    // Parameter words the current command still expects
+    remaining: u32,
+    // Parameter words the current command has received
+    received: usize,
+    depth: Depth,
+    signed: bool,
+    // Bit 15 of every 15 bit pixel
+    set_bit15: bool,
+    // Command bits 25-28, shown in status bits 23-26
+    command_bits: u32,
+    // Compressed halfwords waiting for the rest of their macroblock
+    input: Vec<u16>,
+    output: VecDeque<u32>,
+    #[serde(with = "state::bytes")]
+    quant_y: [u8; 64],
+    #[serde(with = "state::bytes")]
+    quant_uv: [u8; 64],
+    #[serde(with = "state::array")]
+    scale: [i16; 64],
+    in_enabled: bool,
+    out_enabled: bool,
 }
 
 impl Mdec {
     pub fn new() -> Self {
         Self {
-            command: 0,
-            data: 0,
-            status: 0,
-            control: 0,
+            command: Command::None,
+            remaining: 0,
+            received: 0,
+            depth: Depth::Four,
+            signed: false,
+            set_bit15: false,
+            command_bits: 0,
+            input: Vec::new(),
+            output: VecDeque::new(),
+            quant_y: [0; 64],
+            quant_uv: [0; 64],
+            scale: [0; 64],
+            in_enabled: false,
+            out_enabled: false,
         }
     }
 
+    // Command and parameter port at 0x1F801820
     pub fn command_write(&mut self, val: u32) {
+        // Words past the count a command asked for start the next command
+        if self.remaining > 0 {
+            self.parameter_write(val);
+            return;
+        }
+
+        self.command_bits = (val >> 25) & 0xF;
+        self.received = 0;
         match val >> 29 {
             1 => {
-                // decode macroblock
-                todo!()
+                self.command = Command::Decode;
+                self.remaining = val & 0xFFFF;
+                self.depth = match (val >> 27) & 0x3 {
+                    0 => Depth::Four,
+                    1 => Depth::Eight,
+                    2 => Depth::TwentyFour,
+                    _ => Depth::Fifteen,
+                };
+                self.signed = val & 0x4000000 > 0;
+                self.set_bit15 = val & 0x2000000 > 0;
+                self.input.clear();
             }
             2 => {
-                // Set Quant Tables
-                todo!()
+                // Bit 0 adds the color table after the luminance table
+                self.command = Command::SetQuant;
+                self.remaining = if val & 0x1 > 0 { 32 } else { 16 };
             }
             3 => {
-                // Set Scale Table
-                todo!()
+                self.command = Command::SetScale;
+                self.remaining = 32;
             }
             _ => {
-                // do nothing
+                event!(target: "ps1_emulator::MDEC", Level::DEBUG, "Ignored command {:08X}", val);
+                self.command = Command::None;
             }
         }
     }
+
+    fn parameter_write(&mut self, val: u32) {
+        self.remaining -= 1;
+        let index = self.received;
+        self.received += 1;
+        match self.command {
+            Command::Decode => {
+                self.input.push(val as u16);
+                self.input.push((val >> 16) as u16);
+                self.decode_input();
+                // Anything left over once the command ends is padding
+                if self.remaining == 0 {
+                    self.input.clear();
+                }
+            }
+            // Four 8 bit entries per word, luminance then color
+            Command::SetQuant => {
+                let bytes = val.to_le_bytes();
+                match index {
+                    0..16 => self.quant_y[index * 4..index * 4 + 4].copy_from_slice(&bytes),
+                    _ => self.quant_uv[(index - 16) * 4..(index - 16) * 4 + 4]
+                        .copy_from_slice(&bytes),
+                }
+            }
+            // Two signed 16 bit entries per word
+            Command::SetScale => {
+                self.scale[index * 2] = val as i16;
+                self.scale[index * 2 + 1] = (val >> 16) as i16;
+            }
+            Command::None => {}
+        }
+    }
+
+    // Data port at 0x1F801820. Empty reads return zero
+    pub fn data_read(&mut self) -> u32 {
+        self.output.pop_front().unwrap_or(0)
+    }
+
+    // Control register at 0x1F801824
+    pub fn control_write(&mut self, val: u32) {
+        // Bit 31 aborts the current command
+        if val & 0x80000000 > 0 {
+            self.command = Command::None;
+            self.remaining = 0;
+            self.command_bits = 0;
+            self.input.clear();
+            self.output.clear();
+        }
+        self.in_enabled = val & 0x40000000 > 0;
+        self.out_enabled = val & 0x20000000 > 0;
+    }
+
+    pub fn status(&self) -> u32 {
+        let mut status = 0;
+        if self.output.is_empty() {
+            status |= 0x80000000;
+        }
+        if self.remaining > 0 {
+            status |= 0x20000000;
+        }
+        if self.data_in_request() {
+            status |= 0x10000000;
+        }
+        if self.data_out_request() {
+            status |= 0x08000000;
+        }
+        status |= self.command_bits << 23;
+        // Whole macroblocks are decoded at once, so the next block is always Cr, or Y for
+        // monochrome
+        status |= 4 << 16;
+        status | (self.remaining.wrapping_sub(1) & 0xFFFF)
+    }
+
+    // Macroblocks are decoded as soon as they arrive, so input is always wanted
+    pub fn data_in_request(&self) -> bool {
+        self.in_enabled
+    }
+
+    pub fn data_out_request(&self) -> bool {
+        self.out_enabled && !self.output.is_empty()
+    }
+
+    pub fn output_words(&self) -> usize {
+        self.output.len()
+    }
+
+    // Decodes every complete macroblock waiting in the input
+    fn decode_input(&mut self) {
+        loop {
+            let decoded = match self.depth {
+                Depth::Four | Depth::Eight => self.decode_mono(),
+                Depth::TwentyFour | Depth::Fifteen => self.decode_color(),
+            };
+            match decoded {
+                Some(used) => {
+                    self.input.drain(..used);
+                }
+                None => break,
+            }
+        }
+    }
+
+    // One 8x8 block of luminance. Returns the halfwords used
+    fn decode_mono(&mut self) -> Option<usize> {
+        let (block, used) = self.decode_block(&self.input, &self.quant_y)?;
+        let pixels = block.map(|y| {
+            // Clipped to 9 bits, then saturated to 8
+            let y = ((y << 23) >> 23).clamp(-128, 127) as u8;
+            if self.signed { y } else { y ^ 0x80 }
+        });
+
+        if self.depth == Depth::Eight {
+            for bytes in pixels.chunks_exact(4) {
+                self.output
+                    .push_back(u32::from_le_bytes(bytes.try_into().unwrap()));
+            }
+        } else {
+            // Eight pixels per word, first pixel in the low nibble
+            for nibbles in pixels.chunks_exact(8) {
+                let word = nibbles
+                    .iter()
+                    .enumerate()
+                    .fold(0, |word, (i, y)| word | ((*y as u32 >> 4) << (i * 4)));
+                self.output.push_back(word);
+            }
+        }
+        Some(used)
+    }
+
+    // A 16x16 macroblock sent as Cr, Cb and then the four Y blocks. Returns the halfwords used
+    fn decode_color(&mut self) -> Option<usize> {
+        let mut used = 0;
+        let (cr, n) = self.decode_block(&self.input[used..], &self.quant_uv)?;
+        used += n;
+        let (cb, n) = self.decode_block(&self.input[used..], &self.quant_uv)?;
+        used += n;
+        let mut luma = [[0; 64]; 4];
+        for block in &mut luma {
+            let (y, n) = self.decode_block(&self.input[used..], &self.quant_y)?;
+            *block = y;
+            used += n;
+        }
+
+        let mut pixels = [[0u8; 3]; 256];
+        for (i, y_block) in luma.iter().enumerate() {
+            let (xx, yy) = ((i & 1) * 8, (i >> 1) * 8);
+            for y in 0..8 {
+                for x in 0..8 {
+                    // Each chroma sample covers 2x2 pixels
+                    let c = (x + xx) / 2 + (y + yy) / 2 * 8;
+                    let (r, g, b) = yuv_to_rgb(y_block[x + y * 8], cb[c], cr[c]);
+                    let flip = if self.signed { 0 } else { 0x80 };
+                    pixels[(x + xx) + (y + yy) * 16] =
+                        [r as u8 ^ flip, g as u8 ^ flip, b as u8 ^ flip];
+                }
+            }
+        }
+
+        if self.depth == Depth::TwentyFour {
+            let bytes = pixels.as_flattened();
+            for word in bytes.chunks_exact(4) {
+                self.output
+                    .push_back(u32::from_le_bytes(word.try_into().unwrap()));
+            }
+        } else {
+            let bit15 = if self.set_bit15 { 0x8000 } else { 0 };
+            let colors = pixels.map(|[r, g, b]| {
+                (r as u32 >> 3) | ((g as u32 >> 3) << 5) | ((b as u32 >> 3) << 10) | bit15
+            });
+            for pair in colors.chunks_exact(2) {
+                self.output.push_back(pair[0] | (pair[1] << 16));
+            }
+        }
+        Some(used)
+    }
+
+    // Run length decodes and dequantizes one block, then applies the IDCT. Returns None until
+    // the whole block has arrived
+    fn decode_block(&self, input: &[u16], table: &[u8; 64]) -> Option<([i32; 64], usize)> {
+        let mut halfwords = input.iter().copied().enumerate();
+        let mut n = halfwords.find(|(_, n)| *n != PADDING)?.1;
+
+        // The DC value gives the scale of the rest of the block
+        let q_scale = ((n >> 10) & 0x3F) as i32;
+        let mut k = 0;
+        let mut val = signed_10bit(n) * table[0] as i32;
+        let mut block = [0; 64];
+        loop {
+            if q_scale == 0 {
+                val = signed_10bit(n) * 2;
+            }
+            val = val.clamp(-0x400, 0x3FF);
+            // Without a scale coefficients are stored in the order they arrive
+            if q_scale > 0 {
+                block[ZAGZIG[k]] = val;
+            } else {
+                block[k] = val;
+            }
+
+            let (i, next) = halfwords.next()?;
+            n = next;
+            // Top 6 bits are the count of zero coefficients skipped
+            k += ((n >> 10) & 0x3F) as usize + 1;
+            if k > 63 {
+                return Some((self.idct(block), i + 1));
+            }
+            val = (signed_10bit(n) * table[k] as i32 * q_scale + 4) / 8;
+        }
+    }
+
+    // Two passes of a 1D IDCT through the uploaded scale table. Each pass transposes the block
+    fn idct(&self, block: [i32; 64]) -> [i32; 64] {
+        let mut src = block;
+        for _ in 0..2 {
+            let mut dst = [0; 64];
+            for x in 0..8 {
+                for y in 0..8 {
+                    let sum: i64 = (0..8)
+                        .map(|z| src[y + z * 8] as i64 * (self.scale[x + z * 8] as i64 >> 3))
+                        .sum();
+                    dst[x + y * 8] = ((sum + 0xFFF) >> 13) as i32;
+                }
+            }
+            src = dst;
+        }
+        src
+    }
+}
+
+fn signed_10bit(n: u16) -> i32 {
+    ((n as i32) << 22) >> 22
+}
+
+// Signed 8 bit color from signed luminance and chrominance
+fn yuv_to_rgb(y: i32, cb: i32, cr: i32) -> (i8, i8, i8) {
+    // 1.402 Cr, -0.3437 Cb - 0.7143 Cr and 1.772 Cb in 8 bit fixed point
+    let r = y + ((359 * cr) >> 8);
+    let g = y + ((-88 * cb - 183 * cr) >> 8);
+    let b = y + ((454 * cb) >> 8);
+    (
+        r.clamp(-128, 127) as i8,
+        g.clamp(-128, 127) as i8,
+        b.clamp(-128, 127) as i8,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::*;
+
+    fn fixture(name: &str) -> Vec<u8> {
+        fs::read(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("testdata/mdec")
+                .join(name),
+        )
+        .unwrap()
+    }
+
+    fn words(bytes: &[u8]) -> Vec<u32> {
+        bytes
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect()
+    }
+
+    // Sends the quantization tables and IDCT scale table of the fixtures
+    fn upload_tables(mdec: &mut Mdec) {
+        let tables = words(&fixture("tables.bin"));
+        mdec.command_write(0x40000001);
+        for &word in &tables[..32] {
+            mdec.command_write(word);
+        }
+        mdec.command_write(0x60000000);
+        for &word in &tables[32..] {
+            mdec.command_write(word);
+        }
+    }
+
+    // Decodes the stream with the command's depth and sign bits, returning every word output
+    fn decode(mdec: &mut Mdec, command: u32, stream: &[u32]) -> Vec<u32> {
+        mdec.command_write(0x20000000 | command | stream.len() as u32);
+        for &word in stream {
+            mdec.command_write(word);
+        }
+        let output = (0..mdec.output_words()).map(|_| mdec.data_read()).collect();
+        assert_eq!(mdec.output_words(), 0);
+        output
+    }
+
+    fn reference_mdec() -> (Mdec, Vec<u32>) {
+        let mut mdec = Mdec::new();
+        upload_tables(&mut mdec);
+        (mdec, words(&fixture("macroblock.bin")))
+    }
+
+    // DC only block of flat luminance 12, then a second block of the same
+    const FLAT: [u32; 2] = [0xFE00_0832, 0xFE00_0832];
+
+    #[test]
+    fn reference_macroblock_decodes_to_the_reference_picture() {
+        let (mut mdec, stream) = reference_mdec();
+        let output = decode(&mut mdec, 0x10000000, &stream);
+        assert_eq!(output.len(), 16 * 16 * 3 / 4);
+        let rgb: Vec<u8> = output.iter().flat_map(|word| word.to_le_bytes()).collect();
+        // The reference is decoded with an exact IDCT and color conversion, which the fixed
+        // point steps round away from by a little
+        let reference = fixture("macroblock.rgb");
+        let errors: Vec<u8> = rgb
+            .iter()
+            .zip(&reference)
+            .map(|(a, b)| a.abs_diff(*b))
+            .collect();
+        assert!(errors.iter().all(|&error| error <= 3));
+        let total: u32 = errors.iter().map(|&error| error as u32).sum();
+        assert!(total < rgb.len() as u32);
+    }
+
+    #[test]
+    fn color_depths_pack_the_same_pixels() {
+        let (mut mdec, stream) = reference_mdec();
+        let rgb: Vec<u8> = decode(&mut mdec, 0x10000000, &stream)
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect();
+        // Signed output flips the top bit of each component
+        let signed: Vec<u8> = decode(&mut mdec, 0x14000000, &stream)
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect();
+        assert!(rgb.iter().zip(&signed).all(|(a, b)| a ^ 0x80 == *b));
+
+        // 15 bit pixels keep the top 5 bits of each component, two to a word
+        for (command, bit15) in [(0x18000000, 0), (0x1A000000, 0x8000)] {
+            let colors: Vec<u32> = decode(&mut mdec, command, &stream)
+                .iter()
+                .flat_map(|word| [word & 0xFFFF, word >> 16])
+                .collect();
+            let expected: Vec<u32> = rgb
+                .chunks_exact(3)
+                .map(|c| {
+                    (c[0] as u32 >> 3) | (c[1] as u32 >> 3) << 5 | (c[2] as u32 >> 3) << 10 | bit15
+                })
+                .collect();
+            assert_eq!(colors, expected);
+        }
+    }
+
+    #[test]
+    fn monochrome_depths_send_luminance() {
+        let (mut mdec, _) = reference_mdec();
+        // 8 bit pixels four to a word and 4 bit pixels eight to a word, unsigned unless asked
+        assert_eq!(decode(&mut mdec, 0x08000000, &FLAT), [0x8C8C8C8C; 32]);
+        assert_eq!(decode(&mut mdec, 0x0C000000, &FLAT), [0x0C0C0C0C; 32]);
+        assert_eq!(decode(&mut mdec, 0x00000000, &FLAT), [0x88888888; 16]);
+
+        // Each block of the reference stream decodes to 8x8 pixels
+        let (mut mdec, stream) = reference_mdec();
+        let eight = decode(&mut mdec, 0x08000000, &stream);
+        let four = decode(&mut mdec, 0x00000000, &stream);
+        assert_eq!(eight.len(), 6 * 16);
+        let nibbles: Vec<u32> = eight
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect::<Vec<_>>()
+            .chunks_exact(8)
+            .map(|pixels| {
+                pixels
+                    .iter()
+                    .enumerate()
+                    .fold(0, |word, (i, y)| word | (*y as u32 >> 4) << (i * 4))
+            })
+            .collect();
+        assert_eq!(four, nibbles);
+    }
+
+    #[test]
+    fn yuv_converts_with_the_documented_weights() {
+        assert_eq!(yuv_to_rgb(0, 0, 0), (0, 0, 0));
+        assert_eq!(yuv_to_rgb(10, 50, 0), (10, -8, 98));
+        assert_eq!(yuv_to_rgb(-20, 0, 40), (36, -49, -20));
+        // Components saturate
+        assert_eq!(yuv_to_rgb(0, 0, 100), (127, -72, 0));
+        assert_eq!(yuv_to_rgb(-100, -100, 0), (-100, -66, -128));
+    }
+
+    #[test]
+    fn tables_are_packed_into_words() {
+        let mut mdec = Mdec::new();
+        // Luminance and color, four entries to a word with the first in the low byte
+        mdec.command_write(0x40000001);
+        assert_eq!(mdec.status() & 0x2000FFFF, 0x2000001F);
+        for i in 0..32u32 {
+            mdec.command_write(u32::from_le_bytes([0, 1, 2, 3].map(|j| (i * 4 + j) as u8)));
+        }
+        assert_eq!(mdec.status() & 0x2000FFFF, 0xFFFF);
+        assert_eq!(mdec.quant_y, std::array::from_fn(|i| i as u8));
+        assert_eq!(mdec.quant_uv, std::array::from_fn(|i| (i + 64) as u8));
+
+        // Without bit 0 only luminance is sent
+        mdec.command_write(0x40000000);
+        assert_eq!(mdec.status() & 0xFFFF, 15);
+        for _ in 0..16 {
+            mdec.command_write(0x01010101);
+        }
+        assert_eq!(mdec.quant_y, [1; 64]);
+        assert_eq!(mdec.quant_uv, std::array::from_fn(|i| (i + 64) as u8));
+
+        // Two signed entries to a word
+        mdec.command_write(0x60000000);
+        assert_eq!(mdec.status() & 0xFFFF, 31);
+        for i in 0..32 {
+            mdec.command_write(0x8000_0000 | i);
+        }
+        assert_eq!(
+            mdec.scale,
+            std::array::from_fn(|i| match i % 2 {
+                0 => (i / 2) as i16,
+                _ => i16::MIN,
+            })
+        );
+
+        // Words past the count are commands, and this one is ignored
+        mdec.command_write(0x00000005);
+        assert_eq!(mdec.status() & 0x2000FFFF, 0xFFFF);
+        assert_eq!(mdec.quant_y, [1; 64]);
+    }
+
+    #[test]
+    fn decoding_uses_the_tables_uploaded() {
+        let (mut mdec, stream) = reference_mdec();
+        let reference = decode(&mut mdec, 0x10000000, &stream);
+
+        // Doubling the color table leaves the luminance of a grey block alone but not the
+        // colors of the macroblock
+        let tables = fixture("tables.bin");
+        mdec.command_write(0x40000001);
+        for word in words(&tables[..64]) {
+            mdec.command_write(word);
+        }
+        let doubled: Vec<u8> = tables[64..128].iter().map(|q| q * 2).collect();
+        for word in words(&doubled) {
+            mdec.command_write(word);
+        }
+        assert_ne!(decode(&mut mdec, 0x10000000, &stream), reference);
+        assert_eq!(decode(&mut mdec, 0x08000000, &FLAT), [0x8C8C8C8C; 32]);
+
+        // A flat DC block is scaled by the first luminance entry
+        mdec.command_write(0x40000000);
+        mdec.command_write(0x00000004);
+        for _ in 1..16 {
+            mdec.command_write(0);
+        }
+        assert_eq!(decode(&mut mdec, 0x08000000, &FLAT), [0x99999999; 32]);
+
+        upload_tables(&mut mdec);
+        assert_eq!(decode(&mut mdec, 0x10000000, &stream), reference);
+    }
 }
@@ -0,0 +1,387 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::sio::SioDevice;
+
+// 128 KB made of 1024 sectors of 128 bytes
+pub const CARD_SIZE: usize = 0x20000;
+const SECTOR_SIZE: usize = 128;
+const LAST_SECTOR: u16 = 0x3FF;
+// Step of a finished transfer. The card stays silent until deselected
+const TRANSFER_DONE: usize = usize::MAX;
+// FLAG bit 3 is set from power on until the first successful write
+const FLAG_FRESH: u8 = 0x08;
+
+// Ending bytes of read and write commands
+const END_GOOD: u8 = 0x47;
+const END_BAD_CHECKSUM: u8 = 0x4E;
+const END_BAD_SECTOR: u8 = 0xFF;
+
+// Offsets of each step within the read and write commands
+const READ_DATA: usize = 10;
+const WRITE_DATA: usize = 6;
+
+// SCPH-1020 memory card backed by a raw .mcd image
+#[derive(Clone)]
+pub struct MemoryCard {
+    data: Box<[u8; CARD_SIZE]>,
+    path: PathBuf,
+    // Set when the image differs from the file
+    dirty: bool,
+    flag: u8,
+    command: u8,
+    // Bytes of the current transfer exchanged so far
+    step: usize,
+    // Byte received in the previous step, echoed back during writes
+    previous: u8,
+    sector: u16,
+    checksum: u8,
+    checksum_ok: bool,
+    buffer: [u8; SECTOR_SIZE],
+}
+
+impl MemoryCard {
+    // Loads the image at the path. A missing file gives a freshly formatted card, saved on
+    // the first flush
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let (data, dirty) = match fs::read(path) {
+            Ok(image) => {
+                let data: Box<[u8; CARD_SIZE]> =
+                    image
+                        .into_boxed_slice()
+                        .try_into()
+                        .map_err(|image: Box<[u8]>| {
+                            io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                format!(
+                                    "memory card image is {} bytes, expected {CARD_SIZE}",
+                                    image.len()
+                                ),
+                            )
+                        })?;
+                (data, false)
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (format(), true),
+            Err(err) => return Err(err),
+        };
+
+        Ok(Self {
+            data,
+            path: path.to_path_buf(),
+            dirty,
+            flag: FLAG_FRESH,
+            command: 0,
+            step: 0,
+            previous: 0,
+            sector: 0,
+            checksum: 0,
+            checksum_ok: false,
+            buffer: [0; SECTOR_SIZE],
+        })
+    }
+
+    fn sector_valid(&self) -> bool {
+        self.sector <= LAST_SECTOR
+    }
+
+    fn sector_offset(&self) -> usize {
+        self.sector as usize * SECTOR_SIZE
+    }
+
+    // Read command 0x52. Returns the sector, its number first, followed by a checksum
+    fn read_step(&mut self, step: usize, byte: u8) -> (u8, bool) {
+        match step {
+            4 => {
+                self.sector = (byte as u16) << 8;
+                (0x00, true)
+            }
+            5 => {
+                self.sector |= byte as u16;
+                (self.previous, true)
+            }
+            6 => (0x5C, true),
+            7 => (0x5D, true),
+            // The card answers FFFFh and stops for sectors past the end
+            8 if !self.sector_valid() => (0xFF, false),
+            8 => {
+                let msb = (self.sector >> 8) as u8;
+                self.checksum = msb;
+                (msb, true)
+            }
+            9 => {
+                let lsb = self.sector as u8;
+                self.checksum ^= lsb;
+                (lsb, true)
+            }
+            READ_DATA..138 => {
+                let data = self.data[self.sector_offset() + step - READ_DATA];
+                self.checksum ^= data;
+                (data, true)
+            }
+            138 => (self.checksum, true),
+            139 => (END_GOOD, false),
+            _ => (0xFF, false),
+        }
+    }
+
+    // Write command 0x57. The sector is only written if the checksum matches
+    fn write_step(&mut self, step: usize, byte: u8) -> (u8, bool) {
+        match step {
+            4 => {
+                self.sector = (byte as u16) << 8;
+                self.checksum = byte;
+                (0x00, true)
+            }
+            5 => {
+                self.sector |= byte as u16;
+                self.checksum ^= byte;
+                (self.previous, true)
+            }
+            WRITE_DATA..134 => {
+                self.buffer[step - WRITE_DATA] = byte;
+                self.checksum ^= byte;
+                (self.previous, true)
+            }
+            134 => {
+                self.checksum_ok = byte == self.checksum;
+                (self.previous, true)
+            }
+            135 => (0x5C, true),
+            136 => (0x5D, true),
+            137 => {
+                let end = if !self.sector_valid() {
+                    END_BAD_SECTOR
+                } else if !self.checksum_ok {
+                    END_BAD_CHECKSUM
+                } else {
+                    let offset = self.sector_offset();
+                    self.data[offset..offset + SECTOR_SIZE].copy_from_slice(&self.buffer);
+                    self.dirty = true;
+                    self.flag &= !FLAG_FRESH;
+                    END_GOOD
+                };
+                (end, false)
+            }
+            _ => (0xFF, false),
+        }
+    }
+
+    // ID command 0x53. Reports the card size
+    fn id_step(&self, step: usize) -> (u8, bool) {
+        match step {
+            4 => (0x5C, true),
+            5 => (0x5D, true),
+            6 => (0x04, true),
+            7 => (0x00, true),
+            8 => (0x00, true),
+            9 => (0x80, false),
+            _ => (0xFF, false),
+        }
+    }
+}
+
+impl SioDevice for MemoryCard {
+    fn exchange(&mut self, byte: u8) -> (u8, bool) {
+        let reply = match (self.step, byte) {
+            // Address byte 0x81 selects the card, 0x01 would be the controller
+            (0, 0x81) => (0xFF, true),
+            (0, _) => (0xFF, false),
+            (1, 0x52 | 0x57 | 0x53) => {
+                self.command = byte;
+                (self.flag, true)
+            }
+            (1, _) => (0xFF, false),
+            // Card ID 0x5D5A
+            (2, _) => (0x5A, true),
+            (3, _) => (0x5D, true),
+            (TRANSFER_DONE, _) => (0xFF, false),
+            (step, _) => match self.command {
+                0x52 => self.read_step(step, byte),
+                0x57 => self.write_step(step, byte),
+                _ => self.id_step(step),
+            },
+        };
+        self.previous = byte;
+        self.step = if reply.1 {
+            self.step + 1
+        } else {
+            TRANSFER_DONE
+        };
+        reply
+    }
+
+    fn deselect(&mut self) {
+        self.step = 0;
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&self.path, self.data.as_slice())?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    fn unsaved(&self) -> bool {
+        self.dirty
+    }
+
+    fn clone_box(&self) -> Box<dyn SioDevice> {
+        Box::new(self.clone())
+    }
+}
+
+// Blank card as formatted by the BIOS. Every frame in the header block ends in the XOR of
+// its other bytes
+pub fn format() -> Box<[u8; CARD_SIZE]> {
+    let mut data = Box::new([0; CARD_SIZE]);
+    for (i, frame) in data[..64 * SECTOR_SIZE]
+        .chunks_exact_mut(SECTOR_SIZE)
+        .enumerate()
+    {
+        match i {
+            // Header and the write test frame
+            0 | 63 => frame[..2].copy_from_slice(b"MC"),
+            // Directory entries of the 15 free blocks
+            1..=15 => {
+                frame[0] = 0xA0;
+                frame[8..10].fill(0xFF);
+            }
+            // Broken sector list, all unused
+            16..=35 => {
+                frame[..4].fill(0xFF);
+                frame[8..10].fill(0xFF);
+            }
+            // Replacement sectors
+            _ => frame.fill(0xFF),
+        }
+        frame[SECTOR_SIZE - 1] = frame[..SECTOR_SIZE - 1].iter().fold(0, |acc, b| acc ^ b);
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sio::transfer;
+
+    fn card_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("ps1_emulator_{name}_{}.mcd", std::process::id()))
+    }
+
+    // Write command for a sector, with the checksum adjusted by `corrupt`
+    fn write_command(sector: u16, data: &[u8; SECTOR_SIZE], corrupt: u8) -> Vec<u8> {
+        let [msb, lsb] = sector.to_be_bytes();
+        let checksum = data.iter().fold(msb ^ lsb, |acc, b| acc ^ b) ^ corrupt;
+        let mut bytes = vec![0x81, 0x57, 0x00, 0x00, msb, lsb];
+        bytes.extend(data);
+        bytes.extend([checksum, 0x00, 0x00, 0x00]);
+        bytes
+    }
+
+    fn read_command(sector: u16) -> Vec<u8> {
+        let [msb, lsb] = sector.to_be_bytes();
+        let mut bytes = vec![0x81, 0x52, 0x00, 0x00, msb, lsb];
+        bytes.resize(140, 0x00);
+        bytes
+    }
+
+    #[test]
+    fn blank_card_is_formatted() {
+        let data = format();
+        assert_eq!(data[..2], *b"MC");
+        assert_eq!(data[63 * SECTOR_SIZE..63 * SECTOR_SIZE + 2], *b"MC");
+        // Free directory entries and unused broken sector entries
+        assert_eq!(data[SECTOR_SIZE], 0xA0);
+        assert_eq!(data[16 * SECTOR_SIZE..16 * SECTOR_SIZE + 4], [0xFF; 4]);
+        for frame in data[..64 * SECTOR_SIZE].chunks_exact(SECTOR_SIZE) {
+            assert_eq!(frame.iter().fold(0, |acc, b| acc ^ b), 0);
+        }
+        // The save blocks are empty
+        assert!(data[64 * SECTOR_SIZE..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn written_sectors_reach_the_file() {
+        let path = card_path("memcard_write");
+        let _ = fs::remove_file(&path);
+        let mut card = MemoryCard::open(&path).unwrap();
+        // A missing file is a new card that needs saving
+        assert!(card.unsaved());
+
+        let data: [u8; SECTOR_SIZE] = std::array::from_fn(|i| (i * 3) as u8);
+        let reply = transfer(&mut card, &write_command(0x123, &data, 0));
+        // The fresh card flag, the card ID and the previous byte echoed, ending well
+        assert_eq!(reply[..6], [0xFF, 0x08, 0x5A, 0x5D, 0x00, 0x01]);
+        assert_eq!(reply[134..], [data[127], 0x5C, 0x5D, END_GOOD]);
+        card.flush().unwrap();
+        assert!(!card.unsaved());
+
+        let image = fs::read(&path).unwrap();
+        assert_eq!(image.len(), CARD_SIZE);
+        assert_eq!(image[0x123 * SECTOR_SIZE..0x124 * SECTOR_SIZE], data);
+        assert_eq!(image[..64 * SECTOR_SIZE], format()[..64 * SECTOR_SIZE]);
+
+        // Reopened, the sector reads back with its number and checksum
+        let mut card = MemoryCard::open(&path).unwrap();
+        let reply = transfer(&mut card, &read_command(0x123));
+        assert_eq!(reply[6..10], [0x5C, 0x5D, 0x01, 0x23]);
+        assert_eq!(reply[READ_DATA..138], data);
+        let checksum = data.iter().fold(0x01 ^ 0x23, |acc, b| acc ^ b);
+        assert_eq!(reply[138..], [checksum, END_GOOD]);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn bad_writes_leave_the_card_alone() {
+        let path = card_path("memcard_bad_write");
+        let _ = fs::remove_file(&path);
+        let mut card = MemoryCard::open(&path).unwrap();
+        card.flush().unwrap();
+
+        let data = [0x55; SECTOR_SIZE];
+        let reply = transfer(&mut card, &write_command(0x40, &data, 0x01));
+        assert_eq!(reply[137], END_BAD_CHECKSUM);
+        let reply = transfer(&mut card, &write_command(0x400, &data, 0));
+        assert_eq!(reply[137], END_BAD_SECTOR);
+        assert!(!card.unsaved());
+        // Still a fresh card
+        assert_eq!(transfer(&mut card, &read_command(0x40))[1], FLAG_FRESH);
+        assert_eq!(fs::read(&path).unwrap(), format().as_slice());
+
+        // Reads past the end stop after the sector number
+        let reply = transfer(&mut card, &read_command(0x400));
+        assert_eq!(reply[8..], [0xFF]);
+
+        // A good write clears the fresh flag
+        transfer(&mut card, &write_command(0x40, &data, 0));
+        assert_eq!(transfer(&mut card, &read_command(0x40))[1], 0x00);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn id_command_reports_the_size() {
+        let mut card = MemoryCard::open(&card_path("memcard_id")).unwrap();
+        assert_eq!(
+            transfer(&mut card, &[0x81, 0x53, 0, 0, 0, 0, 0, 0, 0, 0]),
+            [0xFF, 0x08, 0x5A, 0x5D, 0x5C, 0x5D, 0x04, 0x00, 0x00, 0x80]
+        );
+        // Other addresses and commands get no /ACK
+        assert_eq!(transfer(&mut card, &[0x01, 0x42]), [0xFF]);
+        assert_eq!(transfer(&mut card, &[0x81, 0x42]), [0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn images_of_the_wrong_size_are_rejected() {
+        let path = card_path("memcard_short");
+        fs::write(&path, [0; 100]).unwrap();
+        let err = MemoryCard::open(&path).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let _ = fs::remove_file(&path);
+    }
+}
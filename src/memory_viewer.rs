@@ -0,0 +1,213 @@
+use eframe::egui::{self, Color32, RichText};
+
+use crate::bus::Bus;
+use crate::cpu::Cpu;
+
+// Shortcuts to the start of each memory area, with how much of it the grid scrolls through
+const REGIONS: [(&str, u32, u32); 5] = [
+    ("RAM", 0x80000000, 0x200000),
+    ("Scratchpad", 0x1F800000, 0x400),
+    ("BIOS", 0xBFC00000, 0x80000),
+    ("I/O", 0x1F801000, 0x2000),
+    ("Expansion 1", 0x1F000000, 0x10000),
+];
+// Addresses outside the regions are shown 64KB at a time
+const WINDOW_SIZE: u32 = 0x10000;
+
+fn parse_hex(text: &str) -> Option<u32> {
+    let text = text.trim();
+    let text = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .unwrap_or(text);
+    u32::from_str_radix(text, 16).ok()
+}
+
+// Debug window with a hex dump of the guest address space. Bytes can be edited while paused
+pub struct MemoryViewer {
+    pub open: bool,
+    address_text: String,
+    // First address and length of the area the grid scrolls through
+    start: u32,
+    len: u32,
+    // Address last jumped to, highlighted in the grid
+    target: Option<u32>,
+    scroll_to: Option<u32>,
+    // Byte being edited and the text typed so far
+    editing: Option<(u32, String)>,
+    error: Option<String>,
+}
+
+impl MemoryViewer {
+    pub fn new() -> Self {
+        let (_, start, len) = REGIONS[0];
+        Self {
+            open: false,
+            address_text: format!("{start:08X}"),
+            start,
+            len,
+            target: None,
+            scroll_to: None,
+            editing: None,
+            error: None,
+        }
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context, cpu: &mut Cpu, paused: bool) {
+        if !paused {
+            self.editing = None;
+        }
+
+        let mut open = self.open;
+        egui::Window::new("Memory")
+            .open(&mut open)
+            .default_size([620.0, 400.0])
+            .show(ctx, |ui| self.contents(ui, cpu, paused));
+        self.open = open;
+    }
+
+    fn goto(&mut self, addr: u32) {
+        (self.start, self.len) = REGIONS
+            .iter()
+            .find(|(_, start, len)| addr.wrapping_sub(*start) < *len)
+            .map(|(_, start, len)| (*start, *len))
+            .unwrap_or((addr & !(WINDOW_SIZE - 1), WINDOW_SIZE));
+        self.address_text = format!("{addr:08X}");
+        self.target = Some(addr);
+        self.scroll_to = Some(addr);
+        self.error = None;
+    }
+
+    fn contents(&mut self, ui: &mut egui::Ui, cpu: &mut Cpu, paused: bool) {
+        ui.horizontal(|ui| {
+            ui.label("Address");
+            let response = ui.add(
+                egui::TextEdit::singleline(&mut self.address_text)
+                    .desired_width(80.0)
+                    .font(egui::TextStyle::Monospace),
+            );
+            if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                match parse_hex(&self.address_text) {
+                    Some(addr) => self.goto(addr),
+                    None => self.error = Some(format!("{} is not an address", self.address_text)),
+                }
+            }
+            if ui.button("Go to PC").clicked() {
+                self.goto(cpu.registers.program_counter);
+            }
+            if ui.button("Go to SP").clicked() {
+                self.goto(cpu.registers.registers[29]);
+            }
+        });
+        ui.horizontal(|ui| {
+            for (name, start, _) in REGIONS {
+                if ui.button(name).clicked() {
+                    self.goto(start);
+                }
+            }
+        });
+        if let Some(error) = &self.error {
+            ui.colored_label(Color32::RED, error);
+        } else if paused {
+            ui.label("Double click a byte to edit it");
+        }
+        ui.separator();
+
+        let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+        let mut scroll = egui::ScrollArea::vertical().auto_shrink(false);
+        if let Some(addr) = self.scroll_to.take() {
+            let row = addr.wrapping_sub(self.start) / 16;
+            let spacing = ui.spacing().item_spacing.y;
+            scroll = scroll.vertical_scroll_offset(row as f32 * (row_height + spacing));
+        }
+
+        let mut edited = None;
+        scroll.show_rows(ui, row_height, (self.len / 16) as usize, |ui, rows| {
+            for row in rows {
+                let addr = self.start.wrapping_add(row as u32 * 16);
+                ui.horizontal(|ui| {
+                    ui.spacing_mut().item_spacing.x = 4.0;
+                    edited = edited.or(self.row(ui, &cpu.bus, addr, paused));
+                });
+            }
+        });
+
+        // Going through the bus keeps mirrors and cache isolation behaving as the CPU sees them
+        if let Some((addr, val)) = edited
+            && cpu.bus.mem_write_byte(addr, val).is_err()
+        {
+            self.error = Some(format!("Nothing to write to at {addr:08X}"));
+        }
+    }
+
+    // Shows 16 bytes from an address and their ASCII. Returns a byte typed in for one of them
+    fn row(&mut self, ui: &mut egui::Ui, bus: &Bus, addr: u32, paused: bool) -> Option<(u32, u8)> {
+        ui.monospace(format!("{addr:08X}"));
+        ui.add_space(4.0);
+        let bytes: Vec<_> = (0..16)
+            .map(|i| (addr.wrapping_add(i), bus.peek_byte(addr.wrapping_add(i))))
+            .collect();
+
+        let mut edited = None;
+        for &(addr, byte) in &bytes {
+            edited = edited.or(self.byte(ui, addr, byte, paused));
+        }
+
+        let ascii: String = bytes
+            .iter()
+            .map(|(_, byte)| match byte {
+                Some(byte @ 0x20..=0x7E) => *byte as char,
+                _ => '.',
+            })
+            .collect();
+        ui.add_space(4.0);
+        ui.monospace(ascii);
+        edited
+    }
+
+    fn byte(
+        &mut self,
+        ui: &mut egui::Ui,
+        addr: u32,
+        byte: Option<u8>,
+        paused: bool,
+    ) -> Option<(u32, u8)> {
+        if let Some((editing, text)) = &mut self.editing
+            && *editing == addr
+        {
+            let response = ui.add(
+                egui::TextEdit::singleline(text)
+                    .desired_width(16.0)
+                    .char_limit(2)
+                    .font(egui::TextStyle::Monospace),
+            );
+            response.request_focus();
+            if response.lost_focus() {
+                let typed = u8::from_str_radix(text.trim(), 16);
+                let escaped = ui.input(|i| i.key_pressed(egui::Key::Escape));
+                self.editing = None;
+                if let Ok(val) = typed
+                    && !escaped
+                {
+                    return Some((addr, val));
+                }
+            }
+            return None;
+        }
+
+        // Unmapped space and registers that can't be read safely
+        let Some(val) = byte else {
+            ui.monospace("??");
+            return None;
+        };
+        let mut text = RichText::new(format!("{val:02X}")).monospace();
+        if self.target == Some(addr) {
+            text = text.color(Color32::YELLOW);
+        }
+        let response = ui.add(egui::Label::new(text).sense(egui::Sense::click()));
+        if paused && response.double_clicked() {
+            self.editing = Some((addr, format!("{val:02X}")));
+        }
+        None
+    }
+}
@@ -0,0 +1,143 @@
+use crate::sio::SioDevice;
+
+// Step of a finished transfer. The mouse stays silent until deselected
+const TRANSFER_DONE: usize = usize::MAX;
+
+// SCPH-1090 mouse. Answers the read command 0x42 with its buttons and the motion since the
+// last read
+#[derive(Clone)]
+pub struct PsMouse {
+    // Motion not yet sent. Anything past the -128 to 127 a read can carry waits for the next
+    dx: i32,
+    dy: i32,
+    left: bool,
+    right: bool,
+    // Motion sent by the current read
+    sent: (i8, i8),
+    // Bytes of the current transfer exchanged so far
+    step: usize,
+}
+
+impl PsMouse {
+    pub fn new() -> Self {
+        Self {
+            dx: 0,
+            dy: 0,
+            left: false,
+            right: false,
+            sent: (0, 0),
+            step: 0,
+        }
+    }
+}
+
+impl SioDevice for PsMouse {
+    fn exchange(&mut self, byte: u8) -> (u8, bool) {
+        let reply = match (self.step, byte) {
+            (0, 0x01) => (0xFF, true),
+            // ID 0x5A12 is a mouse
+            (1, 0x42) => (0x12, true),
+            (2, _) => (0x5A, true),
+            (3, _) => (0xFF, true),
+            // Buttons are active low. Bits 0-1 read 0 and bits 4-7 read 1
+            (4, _) => {
+                let mut buttons = 0xFC;
+                if self.left {
+                    buttons &= !0x08;
+                }
+                if self.right {
+                    buttons &= !0x04;
+                }
+                // The motion is latched with the buttons
+                self.sent = (
+                    self.dx.clamp(i8::MIN as i32, i8::MAX as i32) as i8,
+                    self.dy.clamp(i8::MIN as i32, i8::MAX as i32) as i8,
+                );
+                self.dx -= self.sent.0 as i32;
+                self.dy -= self.sent.1 as i32;
+                (buttons, true)
+            }
+            (5, _) => (self.sent.0 as u8, true),
+            // No /ACK after the last byte
+            (6, _) => (self.sent.1 as u8, false),
+            _ => (0xFF, false),
+        };
+        self.step = if reply.1 {
+            self.step + 1
+        } else {
+            TRANSFER_DONE
+        };
+        reply
+    }
+
+    fn deselect(&mut self) {
+        self.step = 0;
+    }
+
+    fn move_mouse(&mut self, dx: i32, dy: i32, left: bool, right: bool) {
+        self.dx = self.dx.saturating_add(dx);
+        self.dy = self.dy.saturating_add(dy);
+        self.left = left;
+        self.right = right;
+    }
+
+    fn clone_box(&self) -> Box<dyn SioDevice> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sio::transfer;
+
+    const READ: [u8; 7] = [0x01, 0x42, 0x00, 0x00, 0x00, 0x00, 0x00];
+
+    #[test]
+    fn read_sends_the_buttons_and_motion() {
+        let mut mouse = PsMouse::new();
+        assert_eq!(
+            transfer(&mut mouse, &READ),
+            [0xFF, 0x12, 0x5A, 0xFF, 0xFC, 0x00, 0x00]
+        );
+        mouse.move_mouse(5, -3, true, false);
+        assert_eq!(
+            transfer(&mut mouse, &READ),
+            [0xFF, 0x12, 0x5A, 0xFF, 0xF4, 0x05, 0xFD]
+        );
+        // Motion is only sent once and the buttons stay held
+        mouse.move_mouse(0, 0, true, true);
+        assert_eq!(transfer(&mut mouse, &READ)[4..], [0xF0, 0x00, 0x00]);
+
+        // Other commands end the transfer
+        assert_eq!(transfer(&mut mouse, &[0x01, 0x43, 0x00]), [0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn motion_past_a_byte_carries_over() {
+        let mut mouse = PsMouse::new();
+        // Moves between reads add up
+        mouse.move_mouse(100, -100, false, false);
+        mouse.move_mouse(200, -100, false, false);
+        let motion: Vec<(i8, i8)> = (0..4)
+            .map(|_| {
+                let reply = transfer(&mut mouse, &READ);
+                (reply[5] as i8, reply[6] as i8)
+            })
+            .collect();
+        assert_eq!(motion, [(127, -128), (127, -72), (46, 0), (0, 0)]);
+    }
+
+    #[test]
+    fn motion_is_latched_with_the_buttons() {
+        let mut mouse = PsMouse::new();
+        mouse.move_mouse(10, 10, false, false);
+        let mut reply: Vec<u8> = READ[..5].iter().map(|&b| mouse.exchange(b).0).collect();
+        // Moving part way through a read waits for the next one
+        mouse.move_mouse(20, 0, false, false);
+        reply.extend(READ[5..].iter().map(|&b| mouse.exchange(b).0));
+        mouse.deselect();
+        assert_eq!(reply[5..], [10, 10]);
+        assert_eq!(transfer(&mut mouse, &READ)[5..], [20, 0]);
+    }
+}
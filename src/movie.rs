@@ -0,0 +1,101 @@
+use std::io;
+
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+
+use crate::cpu::Cpu;
+use crate::emulator::Input;
+
+// Movie files start with these bytes and the format version
+const MAGIC: &[u8; 8] = b"PS1MOVIE";
+const VERSION: u32 = 3;
+const HEADER_SIZE: usize = MAGIC.len() + 4;
+
+// What a movie was recorded against, checked before it is played back
+#[derive(Serialize, Deserialize)]
+pub struct MovieHeader {
+    pub emulator_version: String,
+    pub bios_hash: [u8; 20],
+    // None when no disc was in the drive
+    pub disc_hash: Option<u32>,
+    // Save state the movie starts from. None starts from power on
+    pub start_state: Option<Vec<u8>>,
+    // States loaded while recording
+    pub rerecords: u32,
+}
+
+impl MovieHeader {
+    // Describes the machine as it is now
+    pub fn new(cpu: &mut Cpu, start_state: Option<Vec<u8>>) -> Self {
+        let hashes = cpu.host_hashes();
+        Self {
+            emulator_version: env!("CARGO_PKG_VERSION").to_string(),
+            bios_hash: hashes.bios,
+            disc_hash: hashes.disc,
+            start_state,
+            rerecords: 0,
+        }
+    }
+
+    // Why the movie may not play back the same on this machine, if it may not
+    pub fn mismatch(&self, current: &MovieHeader) -> Option<String> {
+        if self.bios_hash != current.bios_hash {
+            Some("recorded with a different BIOS".to_string())
+        } else if self.disc_hash != current.disc_hash {
+            Some("recorded with a different disc".to_string())
+        } else if self.emulator_version != current.emulator_version {
+            Some(format!("recorded by version {}", self.emulator_version))
+        } else {
+            None
+        }
+    }
+}
+
+// What the loop took at one input poll. Polls happen once per frame, after vblank starts
+#[derive(Serialize, Deserialize)]
+pub enum MovieFrame {
+    Input(Input),
+    // Loaded while recording, and loaded again before the next poll on playback
+    LoadState(Vec<u8>),
+}
+
+// Input of each frame since a known start, which plays back the same game
+#[derive(Serialize, Deserialize)]
+pub struct Movie {
+    pub header: MovieHeader,
+    pub frames: Vec<MovieFrame>,
+}
+
+impl Movie {
+    pub fn new(header: MovieHeader) -> Self {
+        Self {
+            header,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        bincode::DefaultOptions::new()
+            .serialize_into(&mut out, self)
+            .expect("movie serializes");
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> io::Result<Self> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        if bytes.len() < HEADER_SIZE || !bytes.starts_with(MAGIC) {
+            return Err(invalid("not a movie".to_string()));
+        }
+        let version = u32::from_le_bytes(bytes[MAGIC.len()..HEADER_SIZE].try_into().unwrap());
+        if version != VERSION {
+            return Err(invalid(format!(
+                "movie is version {version} but this build reads version {VERSION}"
+            )));
+        }
+        bincode::DefaultOptions::new()
+            .deserialize(&bytes[HEADER_SIZE..])
+            .map_err(|err| invalid(format!("movie is corrupt: {err}")))
+    }
+}
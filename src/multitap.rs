@@ -0,0 +1,199 @@
+use crate::pad::{Buttons, DigitalPad};
+use crate::sio::SioDevice;
+
+// Step of a finished transfer. The tap stays silent until deselected
+const TRANSFER_DONE: usize = usize::MAX;
+// Bytes each controller takes in the combined response
+const SLOT_BYTES: usize = 8;
+
+// SCPH-1070 multitap with four controller slots. Address bytes 0x01-0x04 talk to one slot
+// directly. Once a 0x42 read has sent 0x01 as its third byte, the next read addressed to
+// slot A returns all four controllers at once
+#[derive(Clone)]
+pub struct Multitap {
+    slots: [Option<Box<dyn SioDevice>>; 4],
+    // Slot taking part in a direct transfer
+    slot: Option<usize>,
+    // Set by the third byte of a read, applies to the next transfer
+    multi_next: bool,
+    multi: bool,
+    // Bytes of the current transfer exchanged so far
+    step: usize,
+    // Previous byte sent, used to spot a 0x42 read command
+    previous: u8,
+    // Responses of the four slots, read at the start of a combined transfer
+    response: [u8; 4 * SLOT_BYTES],
+}
+
+impl Multitap {
+    pub fn new() -> Self {
+        Self {
+            slots: std::array::from_fn(|_| Some(Box::new(DigitalPad::new()) as _)),
+            slot: None,
+            multi_next: false,
+            multi: false,
+            step: 0,
+            previous: 0,
+            response: [0xFF; 4 * SLOT_BYTES],
+        }
+    }
+
+    // Reads every slot with a 0x42 command. Empty slots and missing bytes read 0xFF
+    fn read_slots(&mut self) {
+        self.response = [0xFF; 4 * SLOT_BYTES];
+        for (slot, response) in self
+            .slots
+            .iter_mut()
+            .zip(self.response.chunks_exact_mut(SLOT_BYTES))
+        {
+            let Some(device) = slot else {
+                continue;
+            };
+            device.deselect();
+            if device.exchange(0x01).1 {
+                let mut command = 0x42;
+                for byte in response.iter_mut() {
+                    let (rx, ack) = device.exchange(command);
+                    *byte = rx;
+                    command = 0x00;
+                    if !ack {
+                        break;
+                    }
+                }
+            }
+            device.deselect();
+        }
+    }
+
+    fn exchange_multi(&mut self, byte: u8) -> (u8, bool) {
+        match (self.step, byte) {
+            (1, 0x42) => {
+                self.read_slots();
+                (0x80, true)
+            }
+            (2, _) => {
+                self.multi_next = byte == 0x01;
+                (0x5A, true)
+            }
+            (step, _) if (3..3 + 4 * SLOT_BYTES).contains(&step) => {
+                let last = step == 2 + 4 * SLOT_BYTES;
+                (self.response[step - 3], !last)
+            }
+            _ => (0xFF, false),
+        }
+    }
+}
+
+impl SioDevice for Multitap {
+    fn exchange(&mut self, byte: u8) -> (u8, bool) {
+        let reply = match (self.step, byte) {
+            (0, 0x01) if self.multi_next => {
+                self.multi = true;
+                (0xFF, true)
+            }
+            // Slots are addressed as controllers 0x01-0x04
+            (0, 0x01..=0x04) => {
+                self.multi = false;
+                let slot = (byte - 1) as usize;
+                match &mut self.slots[slot] {
+                    Some(device) => {
+                        self.slot = Some(slot);
+                        device.exchange(0x01)
+                    }
+                    None => (0xFF, false),
+                }
+            }
+            (TRANSFER_DONE, _) => (0xFF, false),
+            _ if self.multi => self.exchange_multi(byte),
+            (step, _) => {
+                // The tap watches the third byte of a read for the mode of the next transfer
+                if step == 2 && self.previous == 0x42 {
+                    self.multi_next = byte == 0x01;
+                }
+                match self.slot.and_then(|slot| self.slots[slot].as_mut()) {
+                    Some(device) => device.exchange(byte),
+                    None => (0xFF, false),
+                }
+            }
+        };
+        self.previous = byte;
+        self.step = if reply.1 {
+            self.step + 1
+        } else {
+            TRANSFER_DONE
+        };
+        reply
+    }
+
+    fn deselect(&mut self) {
+        if let Some(device) = self.slot.and_then(|slot| self.slots[slot].as_mut()) {
+            device.deselect();
+        }
+        self.slot = None;
+        self.multi = false;
+        self.step = 0;
+    }
+
+    fn set_buttons(&mut self, slot: usize, buttons: Buttons) {
+        if let Some(device) = &mut self.slots[slot] {
+            device.set_buttons(0, buttons);
+        }
+    }
+
+    fn clone_box(&self) -> Box<dyn SioDevice> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sio::transfer;
+
+    fn tap() -> Multitap {
+        let mut tap = Multitap::new();
+        tap.slots[2] = None;
+        for slot in [0, 1, 3] {
+            tap.set_buttons(slot, Buttons(1 << slot));
+        }
+        tap
+    }
+
+    #[test]
+    fn slots_can_be_read_directly() {
+        let mut tap = tap();
+        assert_eq!(
+            transfer(&mut tap, &[0x02, 0x42, 0, 0, 0]),
+            [0xFF, 0x41, 0x5A, 0xFD, 0xFF]
+        );
+        // Nothing answers for an empty slot
+        assert_eq!(transfer(&mut tap, &[0x03, 0x42]), [0xFF]);
+    }
+
+    #[test]
+    fn multitap_read_sends_an_8_byte_frame_per_slot() {
+        let mut tap = tap();
+        // Sending 0x01 as the third byte of a read asks for all four slots next time
+        transfer(&mut tap, &[0x01, 0x42, 0x01, 0, 0]);
+
+        let mut bytes = vec![0x01, 0x42, 0x01];
+        bytes.resize(3 + 4 * SLOT_BYTES + 1, 0);
+        let received = transfer(&mut tap, &bytes);
+        assert_eq!(received[..3], [0xFF, 0x80, 0x5A]);
+        assert_eq!(received.len(), 3 + 4 * SLOT_BYTES);
+
+        let frames: Vec<_> = received[3..].chunks(SLOT_BYTES).collect();
+        // Digital pads leave the last 3 bytes of their frame as 0xFF
+        assert_eq!(frames[0], [0x41, 0x5A, 0xFE, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(frames[1], [0x41, 0x5A, 0xFD, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(frames[2], [0xFF; SLOT_BYTES]);
+        assert_eq!(frames[3], [0x41, 0x5A, 0xF7, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn combined_read_only_follows_a_request_for_one() {
+        let mut tap = tap();
+        transfer(&mut tap, &[0x01, 0x42, 0x00, 0, 0]);
+        assert_eq!(transfer(&mut tap, &[0x01, 0x42, 0, 0, 0])[1], 0x41);
+    }
+}
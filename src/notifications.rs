@@ -0,0 +1,167 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use eframe::egui;
+
+// How long a notification stays up, fading out over the last part of it
+const DURATION: Duration = Duration::from_secs(3);
+const FADE: Duration = Duration::from_millis(500);
+// The oldest is dropped once more than this many are up
+const MAX_SHOWN: usize = 5;
+
+// Short messages stacked in the bottom left corner of the window, such as "Saved state to
+// slot 3"
+pub struct Notifications {
+    // Oldest first, with when each was raised
+    messages: VecDeque<(String, Instant)>,
+}
+
+impl Notifications {
+    pub fn new() -> Self {
+        Self {
+            messages: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, message: String) {
+        self.push_at(message, Instant::now());
+    }
+
+    // A message that is already up moves to the newest place instead of showing twice
+    fn push_at(&mut self, message: String, now: Instant) {
+        self.messages.retain(|(shown, _)| *shown != message);
+        self.messages.push_back((message, now));
+        if self.messages.len() > MAX_SHOWN {
+            self.messages.pop_front();
+        }
+    }
+
+    // Drops the messages that have been up for DURATION. They were raised in order, so they
+    // expire in order too
+    fn expire(&mut self, now: Instant) {
+        while self
+            .messages
+            .front()
+            .is_some_and(|(_, raised)| now.duration_since(*raised) >= DURATION)
+        {
+            self.messages.pop_front();
+        }
+    }
+
+    // Messages still up, oldest first, with how opaque each is
+    fn visible(&self, now: Instant) -> impl DoubleEndedIterator<Item = (&str, f32)> {
+        self.messages.iter().map(move |(message, raised)| {
+            let left = DURATION.saturating_sub(now.duration_since(*raised));
+            let opacity = (left.as_secs_f32() / FADE.as_secs_f32()).min(1.0);
+            (message.as_str(), opacity)
+        })
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context) {
+        let now = Instant::now();
+        self.expire(now);
+        let Some((_, oldest)) = self.messages.front() else {
+            return;
+        };
+        // Redrawn smoothly while fading, otherwise once the oldest starts to fade
+        let fade_start = (*oldest + DURATION - FADE).saturating_duration_since(now);
+        if fade_start.is_zero() {
+            ctx.request_repaint();
+        } else {
+            ctx.request_repaint_after(fade_start);
+        }
+
+        let corner = ctx.content_rect().left_bottom() + egui::vec2(8.0, -8.0);
+        let layer = egui::LayerId::new(egui::Order::Foreground, egui::Id::new("notifications"));
+        let painter = ctx.layer_painter(layer);
+        let mut bottom = corner.y;
+        // Newest at the bottom, older ones pushed up above it
+        for (message, opacity) in self.visible(now).rev() {
+            let color = egui::Color32::WHITE.gamma_multiply(opacity);
+            let galley =
+                painter.layout_no_wrap(message.to_string(), egui::FontId::monospace(14.0), color);
+            let pos = egui::pos2(corner.x, bottom);
+            let rect = egui::Align2::LEFT_BOTTOM.anchor_size(pos, galley.size());
+            let background = egui::Color32::from_black_alpha(160).gamma_multiply(opacity);
+            painter.rect_filled(rect.expand(4.0), 4.0, background);
+            painter.galley(rect.min, galley, color);
+            bottom = rect.top() - 12.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shown(notifications: &Notifications, now: Instant) -> Vec<(&str, f32)> {
+        notifications.visible(now).collect()
+    }
+
+    #[test]
+    fn messages_expire_oldest_first() {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut notifications = Notifications::new();
+        notifications.push_at("Saved state to slot 1".to_string(), at(0));
+        notifications.push_at("Memory card written".to_string(), at(1000));
+        notifications.push_at("Saved state to slot 2".to_string(), at(2000));
+
+        notifications.expire(at(2999));
+        assert_eq!(notifications.messages.len(), 3);
+        notifications.expire(at(3000));
+        assert_eq!(
+            shown(&notifications, at(3000)),
+            [("Memory card written", 1.0), ("Saved state to slot 2", 1.0)]
+        );
+        // Fading out over the last half second
+        assert_eq!(
+            shown(&notifications, at(3750)),
+            [("Memory card written", 0.5), ("Saved state to slot 2", 1.0)]
+        );
+        notifications.expire(at(5000));
+        assert!(notifications.messages.is_empty());
+    }
+
+    #[test]
+    fn repeated_messages_move_to_the_newest_place() {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut notifications = Notifications::new();
+        notifications.push_at("Save slot 2".to_string(), at(0));
+        notifications.push_at("BIOS not found".to_string(), at(100));
+        notifications.push_at("Save slot 2".to_string(), at(2000));
+
+        let messages: Vec<_> = shown(&notifications, at(2000))
+            .into_iter()
+            .map(|(message, _)| message)
+            .collect();
+        assert_eq!(messages, ["BIOS not found", "Save slot 2"]);
+        // Up for its full time again
+        notifications.expire(at(4000));
+        assert_eq!(shown(&notifications, at(4000)), [("Save slot 2", 1.0)]);
+    }
+
+    #[test]
+    fn only_the_newest_messages_are_kept() {
+        let start = Instant::now();
+        let mut notifications = Notifications::new();
+        for slot in 1..=MAX_SHOWN + 2 {
+            notifications.push_at(format!("Save slot {slot}"), start);
+        }
+        let messages: Vec<_> = shown(&notifications, start)
+            .into_iter()
+            .map(|(message, _)| message)
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "Save slot 3",
+                "Save slot 4",
+                "Save slot 5",
+                "Save slot 6",
+                "Save slot 7"
+            ]
+        );
+    }
+}
@@ -0,0 +1,452 @@
+use serde::{Deserialize, Serialize};
+
+use crate::sio::SioDevice;
+
+// Step of a finished transfer. The pad stays silent until deselected
+const TRANSFER_DONE: usize = usize::MAX;
+
+// Button bits in the order the pad sends them. The pad sends them active low
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+pub struct Buttons(pub u16);
+
+impl Buttons {
+    pub const SELECT: u16 = 0x0001;
+    pub const START: u16 = 0x0008;
+    pub const UP: u16 = 0x0010;
+    pub const RIGHT: u16 = 0x0020;
+    pub const DOWN: u16 = 0x0040;
+    pub const LEFT: u16 = 0x0080;
+    pub const L2: u16 = 0x0100;
+    pub const R2: u16 = 0x0200;
+    pub const L1: u16 = 0x0400;
+    pub const R1: u16 = 0x0800;
+    pub const TRIANGLE: u16 = 0x1000;
+    pub const CIRCLE: u16 = 0x2000;
+    pub const CROSS: u16 = 0x4000;
+    pub const SQUARE: u16 = 0x8000;
+
+    pub fn set(&mut self, button: u16, held: bool) {
+        if held {
+            self.0 |= button;
+        } else {
+            self.0 &= !button;
+        }
+    }
+}
+
+// SCPH-1080 digital pad. Answers the read command 0x42 with its ID and the buttons held
+#[derive(Clone)]
+pub struct DigitalPad {
+    buttons: Buttons,
+    // Bytes of the current transfer exchanged so far
+    step: usize,
+}
+
+impl DigitalPad {
+    pub fn new() -> Self {
+        Self {
+            buttons: Buttons::default(),
+            step: 0,
+        }
+    }
+}
+
+impl SioDevice for DigitalPad {
+    fn exchange(&mut self, byte: u8) -> (u8, bool) {
+        let reply = match (self.step, byte) {
+            // Address byte 0x01 selects the pad, 0x81 would be the memory card
+            (0, 0x01) => (0xFF, true),
+            // ID 0x5A41 is a digital pad
+            (1, 0x42) => (0x41, true),
+            (2, _) => (0x5A, true),
+            (3, _) => (!self.buttons.0 as u8, true),
+            // No /ACK after the last byte
+            (4, _) => (!(self.buttons.0 >> 8) as u8, false),
+            // Anything else ends the transfer
+            _ => (0xFF, false),
+        };
+        self.step = if reply.1 {
+            self.step + 1
+        } else {
+            TRANSFER_DONE
+        };
+        reply
+    }
+
+    fn deselect(&mut self) {
+        self.step = 0;
+    }
+
+    fn set_buttons(&mut self, slot: usize, buttons: Buttons) {
+        if slot == 0 {
+            self.buttons = buttons;
+        }
+    }
+
+    fn clone_box(&self) -> Box<dyn SioDevice> {
+        Box::new(self.clone())
+    }
+}
+
+// SCPH-1200 DualShock. In analog mode it answers 0x42 with ID 0x73 and the stick positions
+// after the buttons. Pressing its Analog button switches it to digital mode, where it answers
+// with ID 0x41 like a digital pad. Games set it up with 0x43 config mode, where the ID is
+// 0xF3 and 0x44 picks the mode and 0x4D picks which bytes of a read drive the motors
+#[derive(Clone)]
+pub struct AnalogPad {
+    buttons: Buttons,
+    // Right stick X and Y, then left stick X and Y. 0x00 is left or up and 0x80 is centred
+    axes: [u8; 4],
+    analog: bool,
+    config: bool,
+    // Set by 0x44 so the Analog button does nothing
+    locked: bool,
+    // Motor each of bytes 3-8 of a read drives. 0x00 is the small motor, 0x01 the large one
+    // and 0xFF neither
+    rumble_map: [u8; 6],
+    // The small motor is either off or on, the large one runs at any speed
+    small_motor: bool,
+    large_motor: u8,
+    command: u8,
+    // Bytes of the current transfer exchanged so far
+    step: usize,
+    // Bytes the current command sends after its ID and 0x5A
+    response: Vec<u8>,
+}
+
+impl AnalogPad {
+    pub fn new() -> Self {
+        Self {
+            buttons: Buttons::default(),
+            axes: [0x80; 4],
+            analog: true,
+            config: false,
+            locked: false,
+            rumble_map: [0xFF; 6],
+            small_motor: false,
+            large_motor: 0,
+            command: 0,
+            step: 0,
+            response: Vec::new(),
+        }
+    }
+
+    fn id(&self) -> u8 {
+        match (self.config, self.analog) {
+            (true, _) => 0xF3,
+            (false, true) => 0x73,
+            (false, false) => 0x41,
+        }
+    }
+
+    // Bytes sent after the ID by each command. None for commands the pad doesn't know
+    fn response(&self, command: u8) -> Option<Vec<u8>> {
+        let response = match command {
+            // 0x43 reads the pad too, unless already in config mode
+            0x42 | 0x43 if !self.config || command == 0x42 => {
+                let [low, high] = (!self.buttons.0).to_le_bytes();
+                let mut response = vec![low, high];
+                if self.analog || self.config {
+                    response.extend(self.axes);
+                }
+                response
+            }
+            _ if !self.config => return None,
+            // Mode and lock
+            0x43 | 0x44 | 0x4F => vec![0x00; 6],
+            // Status, with whether the pad is in analog mode
+            0x45 => vec![0x01, 0x02, self.analog as u8, 0x02, 0x01, 0x00],
+            // The last bytes of 0x46 and 0x4C depend on the byte after 0x5A
+            0x46 => vec![0x00, 0x00, 0x01, 0x02, 0x00, 0x0A],
+            0x47 => vec![0x00, 0x00, 0x02, 0x00, 0x01, 0x00],
+            0x4C => vec![0x00, 0x00, 0x00, 0x04, 0x00, 0x00],
+            0x4D => self.rumble_map.to_vec(),
+            _ => return None,
+        };
+        Some(response)
+    }
+
+    // Acts on a byte received after 0x5A, counting from 0
+    fn receive(&mut self, index: usize, byte: u8) {
+        match (self.command, index) {
+            (0x42, _) => match self.rumble_map[index] {
+                0x00 => self.small_motor = byte & 0x01 > 0,
+                0x01 => self.large_motor = byte,
+                _ => {}
+            },
+            (0x43, 0) => self.config = byte == 0x01,
+            (0x44, 0) => self.analog = byte == 0x01,
+            (0x44, 1) => self.locked = byte == 0x03,
+            (0x46, 0) if byte == 0x01 => self.response[3..].copy_from_slice(&[0x01, 0x01, 0x14]),
+            (0x4C, 0) if byte == 0x01 => self.response[3] = 0x07,
+            (0x4D, _) => {
+                self.rumble_map[index] = byte;
+                // Motors with nothing driving them stop
+                if !self.rumble_map.contains(&0x00) {
+                    self.small_motor = false;
+                }
+                if !self.rumble_map.contains(&0x01) {
+                    self.large_motor = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl SioDevice for AnalogPad {
+    fn exchange(&mut self, byte: u8) -> (u8, bool) {
+        let reply = match (self.step, byte) {
+            (0, 0x01) => (0xFF, true),
+            (1, _) => match self.response(byte) {
+                Some(response) => {
+                    let id = self.id();
+                    self.command = byte;
+                    self.response = response;
+                    (id, true)
+                }
+                // Anything else ends the transfer
+                None => (0xFF, false),
+            },
+            (2, _) => (0x5A, true),
+            // No /ACK after the last byte
+            (step, _) if step >= 3 && step - 3 < self.response.len() => {
+                let index = step - 3;
+                let reply = (self.response[index], index + 1 < self.response.len());
+                self.receive(index, byte);
+                reply
+            }
+            _ => (0xFF, false),
+        };
+        self.step = if reply.1 {
+            self.step + 1
+        } else {
+            TRANSFER_DONE
+        };
+        reply
+    }
+
+    fn deselect(&mut self) {
+        self.step = 0;
+    }
+
+    fn set_buttons(&mut self, slot: usize, buttons: Buttons) {
+        if slot == 0 {
+            self.buttons = buttons;
+        }
+    }
+
+    fn set_axes(&mut self, slot: usize, axes: [u8; 4]) {
+        if slot == 0 {
+            self.axes = axes;
+        }
+    }
+
+    fn press_analog(&mut self) {
+        if !self.locked {
+            self.analog = !self.analog;
+        }
+    }
+
+    fn motors(&self) -> (u8, u8) {
+        (if self.small_motor { 0xFF } else { 0x00 }, self.large_motor)
+    }
+
+    fn clone_box(&self) -> Box<dyn SioDevice> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sio::{Rumble, Sio0, transfer};
+
+    // Motor speeds forwarded, by port
+    #[derive(Default)]
+    struct MockRumble(Vec<(usize, u8, u8)>);
+
+    impl Rumble for MockRumble {
+        fn set_motors(&mut self, port: usize, small: u8, large: u8) {
+            self.0.push((port, small, large));
+        }
+    }
+
+    fn config(pad: &mut AnalogPad, command: u8, params: [u8; 6]) -> Vec<u8> {
+        let mut bytes = vec![0x01, command, 0x00];
+        bytes.extend(params);
+        transfer(pad, &bytes)
+    }
+
+    // Exchanges a byte through the SIO0 registers, returning the byte received and whether
+    // /ACK followed
+    fn exchange(sio: &mut Sio0, byte: u8) -> (u8, bool) {
+        sio.write(0x0, byte as u16);
+        let mut ack = false;
+        for _ in 0..1000 {
+            sio.tick(8);
+            ack |= sio.read(0x4) & 0x80 > 0;
+        }
+        (sio.read(0x0) as u8, ack)
+    }
+
+    #[test]
+    fn digital_pad_sends_the_buttons_held_active_low() {
+        let mut pad = DigitalPad::new();
+        let read = [0x01, 0x42, 0x00, 0x00, 0x00];
+        for (buttons, low, high) in [
+            (0, 0xFF, 0xFF),
+            (Buttons::START | Buttons::SELECT, 0xF6, 0xFF),
+            (Buttons::UP | Buttons::LEFT, 0x6F, 0xFF),
+            (Buttons::CROSS | Buttons::SQUARE | Buttons::L1, 0xFF, 0x3B),
+            (0xFFFF, 0x00, 0x00),
+        ] {
+            pad.set_buttons(0, Buttons(buttons));
+            assert_eq!(transfer(&mut pad, &read), [0xFF, 0x41, 0x5A, low, high]);
+        }
+        // Only slot 0 has a pad
+        pad.set_buttons(1, Buttons(Buttons::CROSS));
+        assert_eq!(transfer(&mut pad, &read)[3..], [0x00, 0x00]);
+    }
+
+    #[test]
+    fn digital_pad_ends_the_transfer_on_unknown_bytes() {
+        let mut pad = DigitalPad::new();
+        // The memory card address and unsupported commands get no /ACK
+        assert_eq!(pad.exchange(0x81), (0xFF, false));
+        pad.deselect();
+        assert_eq!(pad.exchange(0x01), (0xFF, true));
+        assert_eq!(pad.exchange(0x43), (0xFF, false));
+        // Silent until deselected
+        assert_eq!(pad.exchange(0x42), (0xFF, false));
+        pad.deselect();
+        assert_eq!(transfer(&mut pad, &[0x01, 0x42, 0, 0, 0]).len(), 5);
+    }
+
+    #[test]
+    fn digital_pad_answers_only_on_its_port() {
+        let mut sio = Sio0::new();
+        sio.set_buttons(0, 0, Buttons(Buttons::CIRCLE));
+        sio.write(0xE, 0x88);
+        sio.write(0x8, 0x0D);
+        // Port 1 is empty
+        sio.write(0xA, 0x2003);
+        assert_eq!(exchange(&mut sio, 0x01), (0xFF, false));
+        assert_eq!(exchange(&mut sio, 0x42), (0xFF, false));
+
+        sio.write(0xA, 0x0003);
+        let bytes = [0x01, 0x42, 0x00, 0x00, 0x00].map(|byte| exchange(&mut sio, byte));
+        assert_eq!(
+            bytes,
+            [
+                (0xFF, true),
+                (0x41, true),
+                (0x5A, true),
+                (0xFF, true),
+                (0xDF, false)
+            ]
+        );
+    }
+
+    #[test]
+    fn analog_pad_sends_sticks_after_the_buttons() {
+        let mut pad = AnalogPad::new();
+        pad.set_buttons(0, Buttons(Buttons::CROSS | Buttons::START));
+        assert_eq!(
+            transfer(&mut pad, &[0x01, 0x42, 0, 0, 0, 0, 0, 0, 0, 0]),
+            [0xFF, 0x73, 0x5A, 0xF7, 0xBF, 0x80, 0x80, 0x80, 0x80]
+        );
+
+        // Host sticks reach the pad through the port
+        let mut sio = Sio0::new();
+        sio.connect_controller(1, Some(Box::new(pad)));
+        sio.set_axes(1, 0, [0xFF, 0x00, 0x12, 0x80]);
+        let pad = sio.ports[1].controller.as_mut().unwrap();
+        assert_eq!(
+            transfer(pad.as_mut(), &[0x01, 0x42, 0, 0, 0, 0, 0, 0, 0])[5..],
+            [0xFF, 0x00, 0x12, 0x80]
+        );
+    }
+
+    #[test]
+    fn analog_button_falls_back_to_a_digital_pad() {
+        let mut pad = AnalogPad::new();
+        pad.set_buttons(0, Buttons(Buttons::CROSS | Buttons::START));
+        pad.press_analog();
+        let mut digital = DigitalPad::new();
+        digital.set_buttons(0, Buttons(Buttons::CROSS | Buttons::START));
+        let bytes = [0x01, 0x42, 0, 0, 0, 0, 0];
+        assert_eq!(transfer(&mut pad, &bytes), [0xFF, 0x41, 0x5A, 0xF7, 0xBF]);
+        assert_eq!(transfer(&mut pad, &bytes), transfer(&mut digital, &bytes));
+
+        pad.press_analog();
+        assert_eq!(transfer(&mut pad, &bytes)[1], 0x73);
+    }
+
+    #[test]
+    fn config_mode_maps_read_bytes_to_the_motors() {
+        let mut pad = AnalogPad::new();
+        // Until mapped, bytes sent with a read do nothing
+        transfer(&mut pad, &[0x01, 0x42, 0x00, 0xFF, 0xFF, 0, 0, 0, 0]);
+        assert_eq!(pad.motors(), (0, 0));
+
+        // 0x43 reads the pad while switching to config mode
+        assert_eq!(config(&mut pad, 0x43, [0x01, 0, 0, 0, 0, 0]).len(), 9);
+        // The old map comes back while the new one goes in
+        assert_eq!(
+            config(&mut pad, 0x4D, [0x00, 0x01, 0xFF, 0xFF, 0xFF, 0xFF]),
+            [0xFF, 0xF3, 0x5A, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]
+        );
+        config(&mut pad, 0x43, [0x00; 6]);
+
+        transfer(&mut pad, &[0x01, 0x42, 0x00, 0x01, 0xC0, 0, 0, 0, 0]);
+        assert_eq!(pad.motors(), (0xFF, 0xC0));
+        let mut sio = Sio0::new();
+        sio.connect_controller(0, Some(Box::new(pad.clone())));
+        sio.connect_controller(1, Some(Box::new(DigitalPad::new())));
+        let mut rumble = MockRumble::default();
+        sio.forward_rumble(&mut rumble);
+        assert_eq!(rumble.0, [(0, 0xFF, 0xC0), (1, 0, 0)]);
+
+        // Only bit 0 turns the small motor on
+        transfer(&mut pad, &[0x01, 0x42, 0x00, 0xFE, 0x00, 0, 0, 0, 0]);
+        assert_eq!(pad.motors(), (0, 0));
+
+        // Unmapping a motor stops it
+        transfer(&mut pad, &[0x01, 0x42, 0x00, 0x01, 0x40, 0, 0, 0, 0]);
+        config(&mut pad, 0x43, [0x01, 0, 0, 0, 0, 0]);
+        config(&mut pad, 0x4D, [0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(pad.motors(), (0, 0x40));
+    }
+
+    #[test]
+    fn config_mode_sets_and_locks_the_mode() {
+        let mut pad = AnalogPad::new();
+        // Commands other than reads need config mode
+        assert_eq!(
+            config(&mut pad, 0x44, [0x00, 0x03, 0, 0, 0, 0]),
+            [0xFF, 0xFF]
+        );
+        config(&mut pad, 0x43, [0x01, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            config(&mut pad, 0x45, [0; 6]),
+            [0xFF, 0xF3, 0x5A, 0x01, 0x02, 0x01, 0x02, 0x01, 0x00]
+        );
+        assert_eq!(
+            config(&mut pad, 0x44, [0x00, 0x03, 0, 0, 0, 0]),
+            [0xFF, 0xF3, 0x5A, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            config(&mut pad, 0x46, [0x01, 0, 0, 0, 0, 0])[6..],
+            [0x01, 0x01, 0x14]
+        );
+        config(&mut pad, 0x43, [0x00; 6]);
+
+        // Locked in digital mode, the Analog button does nothing
+        pad.press_analog();
+        assert_eq!(
+            transfer(&mut pad, &[0x01, 0x42, 0, 0, 0]),
+            [0xFF, 0x41, 0x5A, 0xFF, 0xFF]
+        );
+    }
+}
@@ -0,0 +1,193 @@
+use eframe::egui::{self, Color32, RichText};
+
+use crate::cpu::Cpu;
+use crate::disasm::REGISTER_NAMES;
+
+// Registers after the GPRs in a snapshot
+const PC: usize = 32;
+const HI: usize = 33;
+const LO: usize = 34;
+const SR: usize = 35;
+const CAUSE: usize = 36;
+const EPC: usize = 37;
+const BADVADDR: usize = 38;
+const SNAPSHOT_LEN: usize = 39;
+
+const SPECIAL_NAMES: [(usize, &str); 7] = [
+    (PC, "pc"),
+    (HI, "hi"),
+    (LO, "lo"),
+    (SR, "sr"),
+    (CAUSE, "cause"),
+    (EPC, "epc"),
+    (BADVADDR, "badvaddr"),
+];
+
+const EXCEPTION_NAMES: [&str; 13] = [
+    "Int", "Mod", "TLBL", "TLBS", "AdEL", "AdES", "IBE", "DBE", "Syscall", "Bp", "RI", "CpU", "Ov",
+];
+
+fn snapshot(cpu: &Cpu) -> [u32; SNAPSHOT_LEN] {
+    let mut values = [0; SNAPSHOT_LEN];
+    values[..32].copy_from_slice(&cpu.registers.registers);
+    values[PC] = cpu.registers.program_counter;
+    values[HI] = cpu.registers.hi;
+    values[LO] = cpu.registers.lo;
+    values[SR] = cpu.bus.cop0.sr.bits();
+    values[CAUSE] = cpu.bus.cop0.cause.bits();
+    values[EPC] = cpu.bus.cop0.epc;
+    values[BADVADDR] = cpu.bus.cop0.badvaddr;
+    values
+}
+
+// Debug window with the CPU and COP0 registers. Values that changed in the last step or
+// frame are highlighted, and double clicking one while paused edits it
+pub struct RegisterViewer {
+    pub open: bool,
+    shown: [u32; SNAPSHOT_LEN],
+    previous: [u32; SNAPSHOT_LEN],
+    // Register being edited and the text typed so far
+    editing: Option<(usize, String)>,
+}
+
+impl RegisterViewer {
+    pub fn new() -> Self {
+        Self {
+            open: false,
+            shown: [0; SNAPSHOT_LEN],
+            previous: [0; SNAPSHOT_LEN],
+            editing: None,
+        }
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context, cpu: &mut Cpu, paused: bool) {
+        let values = snapshot(cpu);
+        if values != self.shown {
+            self.previous = self.shown;
+            self.shown = values;
+        }
+        if !paused {
+            self.editing = None;
+        }
+
+        let mut open = self.open;
+        egui::Window::new("Registers")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| self.contents(ui, cpu, paused));
+        self.open = open;
+    }
+
+    fn contents(&mut self, ui: &mut egui::Ui, cpu: &mut Cpu, paused: bool) {
+        let mut edited = None;
+        egui::Grid::new("gprs").show(ui, |ui| {
+            for row in 0..8 {
+                for reg in (row..32).step_by(8) {
+                    ui.monospace(REGISTER_NAMES[reg]);
+                    edited = edited.or(self.value(ui, reg, paused));
+                }
+                ui.end_row();
+            }
+        });
+
+        ui.separator();
+        egui::Grid::new("special_registers").show(ui, |ui| {
+            for (i, (reg, name)) in SPECIAL_NAMES.into_iter().enumerate() {
+                ui.monospace(name);
+                edited = edited.or(self.value(ui, reg, paused));
+                if i % 4 == 3 {
+                    ui.end_row();
+                }
+            }
+        });
+        let branch = match cpu.registers.delayed_branch {
+            Some(target) => format!("{target:08X}"),
+            None => "-".to_string(),
+        };
+        ui.monospace(format!("Pending branch: {branch}"));
+
+        let sr = self.shown[SR];
+        let bit = |n: u32| (sr >> n) & 1;
+        ui.monospace(format!(
+            "SR: IEc {} KUc {} IEp {} KUp {} IEo {} KUo {} IM {:02X} IsC {} BEV {} CU {:04b}",
+            bit(0),
+            bit(1),
+            bit(2),
+            bit(3),
+            bit(4),
+            bit(5),
+            (sr >> 8) & 0xFF,
+            bit(16),
+            bit(22),
+            sr >> 28
+        ));
+        let cause = &cpu.bus.cop0.cause;
+        let code = cause.exception_code() as usize;
+        ui.monospace(format!(
+            "CAUSE: ExcCode {code:02X} ({}) IP {:02X} BD {}",
+            EXCEPTION_NAMES.get(code).unwrap_or(&"?"),
+            cause.interrupt_pending() >> 8,
+            cause.branch_delay() as u8
+        ));
+        if paused {
+            ui.label("Double click a value to edit it");
+        }
+
+        if let Some((reg, val)) = edited {
+            write_register(cpu, reg, val);
+            self.shown = snapshot(cpu);
+        }
+    }
+
+    // Shows one register, or its editor. Returns a value typed in for it
+    fn value(&mut self, ui: &mut egui::Ui, reg: usize, paused: bool) -> Option<(usize, u32)> {
+        if let Some((editing, text)) = &mut self.editing
+            && *editing == reg
+        {
+            let response = ui.add(
+                egui::TextEdit::singleline(text)
+                    .desired_width(64.0)
+                    .font(egui::TextStyle::Monospace),
+            );
+            response.request_focus();
+            if response.lost_focus() {
+                let typed = u32::from_str_radix(text.trim().trim_start_matches("0x"), 16);
+                let escaped = ui.input(|i| i.key_pressed(egui::Key::Escape));
+                self.editing = None;
+                if let Ok(val) = typed
+                    && !escaped
+                {
+                    return Some((reg, val));
+                }
+            }
+            return None;
+        }
+
+        let val = self.shown[reg];
+        let mut text = RichText::new(format!("{val:08X}")).monospace();
+        if val != self.previous[reg] {
+            text = text.color(Color32::YELLOW);
+        }
+        let response = ui.add(egui::Label::new(text).sense(egui::Sense::click()));
+        // r0 always reads zero
+        if paused && reg != 0 && response.double_clicked() {
+            self.editing = Some((reg, format!("{val:08X}")));
+        }
+        None
+    }
+}
+
+// COP0 registers are written as MTC0 would, so read only bits keep their values
+fn write_register(cpu: &mut Cpu, reg: usize, val: u32) {
+    match reg {
+        0..32 => cpu.registers.registers[reg] = val,
+        PC => cpu.registers.program_counter = val,
+        HI => cpu.registers.hi = val,
+        LO => cpu.registers.lo = val,
+        SR => cpu.bus.cop0.sr.write(val),
+        CAUSE => cpu.bus.cop0.cause.write(val),
+        EPC => cpu.bus.cop0.epc = val,
+        BADVADDR => cpu.bus.cop0.badvaddr = val,
+        _ => {}
+    }
+}
@@ -0,0 +1,253 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use eframe::egui;
+
+use crate::gpu::Gpu;
+
+// Named after the game and the UTC time, such as "Crash_2024-03-09_21-05-33.png"
+pub fn file_name(game: Option<&str>, time: SystemTime) -> String {
+    let game: String = game
+        .unwrap_or("bios")
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c => c,
+        })
+        .collect();
+
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (year, month, day) = civil_date((secs / 86400) as i64);
+    let (hour, minute, second) = (secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    format!("{game}_{year:04}-{month:02}-{day:02}_{hour:02}-{minute:02}-{second:02}.png")
+}
+
+// UTC time written like "2024-03-09 21:05:33 UTC"
+pub fn format_time(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (year, month, day) = civil_date((secs / 86400) as i64);
+    let (hour, minute, second) = (secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    format!("{year:04}-{month:02}-{day:02} {hour:02}:{minute:02}:{second:02} UTC")
+}
+
+// Year, month and day of a count of days since 1970-01-01
+fn civil_date(days: i64) -> (i64, u32, u32) {
+    // Counted in 400 year eras starting on 0000-03-01, so leap days end each year
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// RGBA bytes of an image, which egui keeps premultiplied
+pub fn rgba(image: &egui::ColorImage) -> Vec<u8> {
+    image
+        .pixels
+        .iter()
+        .flat_map(|pixel| pixel.to_srgba_unmultiplied())
+        .collect()
+}
+
+// All of VRAM read as 15 bit color, for debugging
+pub fn vram_image(gpu: &Gpu) -> egui::ColorImage {
+    egui::ColorImage::from_rgb([1024, 512], &gpu.vram_rgb())
+}
+
+pub fn save_png(path: &Path, image: &egui::ColorImage) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    write_png(io::BufWriter::new(fs::File::create(path)?), image)
+}
+
+fn write_png(out: impl Write, image: &egui::ColorImage) -> io::Result<()> {
+    let [width, height] = image.size;
+    let mut encoder = png::Encoder::new(out, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&rgba(image)))
+        .map_err(io::Error::other)
+}
+
+// Size of the pictures kept in save states
+const THUMBNAIL_SIZE: [usize; 2] = [160, 120];
+
+// The picture shrunk to THUMBNAIL_SIZE as a PNG, each pixel the average of the ones it covers
+pub fn thumbnail_png([width, height]: [usize; 2], picture: &[egui::Color32]) -> Vec<u8> {
+    let [thumb_width, thumb_height] = THUMBNAIL_SIZE;
+    // Source pixels covered by a thumbnail pixel, at least one when the picture is smaller
+    let span = |i: usize, size: usize, thumb: usize| {
+        let start = i * size / thumb;
+        start..((i + 1) * size / thumb).max(start + 1)
+    };
+    let mut pixels = Vec::with_capacity(thumb_width * thumb_height);
+    for y in 0..thumb_height {
+        let rows = span(y, height, thumb_height);
+        for x in 0..thumb_width {
+            let columns = span(x, width, thumb_width);
+            let mut sum = [0u32; 4];
+            for row in rows.clone() {
+                for pixel in &picture[row * width..][columns.clone()] {
+                    for (total, channel) in sum.iter_mut().zip(pixel.to_array()) {
+                        *total += channel as u32;
+                    }
+                }
+            }
+            let count = (rows.len() * columns.len()) as u32;
+            let [r, g, b, a] = sum.map(|total| (total / count) as u8);
+            pixels.push(egui::Color32::from_rgba_premultiplied(r, g, b, a));
+        }
+    }
+    let mut png = Vec::new();
+    write_png(&mut png, &egui::ColorImage::new(THUMBNAIL_SIZE, pixels))
+        .expect("PNG is written to memory");
+    png
+}
+
+// Thumbnails, and other PNGs written by write_png
+pub fn read_png(png: &[u8]) -> Option<egui::ColorImage> {
+    let mut reader = png::Decoder::new(io::Cursor::new(png)).read_info().ok()?;
+    let mut buf = vec![0; reader.output_buffer_size()?];
+    let info = reader.next_frame(&mut buf).ok()?;
+    if info.color_type != png::ColorType::Rgba || info.bit_depth != png::BitDepth::Eight {
+        return None;
+    }
+    let size = [info.width as usize, info.height as usize];
+    Some(egui::ColorImage::from_rgba_unmultiplied(
+        size,
+        &buf[..info.buffer_size()],
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn file_names_carry_the_game_and_utc_time() {
+        let time = at(1710018333);
+        assert_eq!(
+            file_name(Some("Crash"), time),
+            "Crash_2024-03-09_21-05-33.png"
+        );
+        assert_eq!(file_name(None, time), "bios_2024-03-09_21-05-33.png");
+        assert_eq!(format_time(time), "2024-03-09 21:05:33 UTC");
+
+        // Characters Windows doesn't allow in file names are replaced
+        assert_eq!(
+            file_name(Some("Tomb Raider: Chronicles? <Disc 1>"), at(0)),
+            "Tomb Raider_ Chronicles_ _Disc 1__1970-01-01_00-00-00.png"
+        );
+
+        // Leap days, and the last second of a day
+        assert_eq!(format_time(at(951868799)), "2000-02-29 23:59:59 UTC");
+        assert_eq!(format_time(at(951868800)), "2000-03-01 00:00:00 UTC");
+        assert_eq!(format_time(at(1709251199)), "2024-02-29 23:59:59 UTC");
+    }
+
+    #[test]
+    fn vram_converts_to_rgba() {
+        let mut gpu = Gpu::new();
+        // White, red, green and blue, then red with the mask bit set
+        for (i, pixel) in [0x7FFF, 0x001F, 0x03E0, 0x7C00, 0x801F, 0x0C63]
+            .into_iter()
+            .enumerate()
+        {
+            gpu.gp0.vram.write(1024 * 3 + i, pixel);
+        }
+
+        let image = vram_image(&gpu);
+        assert_eq!(image.size, [1024, 512]);
+        let rgba = rgba(&image);
+        assert_eq!(rgba.len(), 4 * 1024 * 512);
+        let row = &rgba[4 * 1024 * 3..];
+        assert_eq!(
+            row[..24],
+            [
+                255, 255, 255, 255, 255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255, 255, 0, 0, 255,
+                25, 25, 25, 255,
+            ]
+        );
+        assert!(rgba[..4 * 1024 * 3].chunks(4).all(|p| p == [0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn saved_pngs_read_back_the_same() {
+        let mut gpu = Gpu::new();
+        for i in 0..1024 * 512 {
+            gpu.gp0.vram.write(i, ((i * 2654435761) >> 7) as u16);
+        }
+        let image = vram_image(&gpu);
+
+        let path = std::env::temp_dir().join(format!(
+            "ps1_emulator_screenshot_{}.png",
+            std::process::id()
+        ));
+        save_png(&path, &image).unwrap();
+        let saved = read_png(&fs::read(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+        assert_eq!(saved, Some(image));
+    }
+
+    #[test]
+    fn thumbnails_average_the_pixels_they_cover() {
+        // Black and white columns on the left, green on the right
+        let (width, height) = (320, 240);
+        let picture: Vec<_> = (0..width * height)
+            .map(|i| match i % width {
+                x if x >= width / 2 => egui::Color32::from_rgb(0, 255, 0),
+                x if x % 2 == 0 => egui::Color32::BLACK,
+                _ => egui::Color32::WHITE,
+            })
+            .collect();
+        let thumbnail = read_png(&thumbnail_png([width, height], &picture)).unwrap();
+        assert_eq!(thumbnail.size, THUMBNAIL_SIZE);
+        for (i, pixel) in thumbnail.pixels.iter().enumerate() {
+            let expected = if i % 160 < 80 {
+                egui::Color32::from_gray(127)
+            } else {
+                egui::Color32::from_rgb(0, 255, 0)
+            };
+            assert_eq!(*pixel, expected, "pixel {i}");
+        }
+
+        // Odd sizes and pictures smaller than a thumbnail still fill it
+        for size in [[321, 241], [100, 50], [1, 1]] {
+            let picture = vec![egui::Color32::from_rgb(10, 20, 30); size[0] * size[1]];
+            let thumbnail = read_png(&thumbnail_png(size, &picture)).unwrap();
+            assert_eq!(thumbnail.size, THUMBNAIL_SIZE);
+            assert!(
+                thumbnail
+                    .pixels
+                    .iter()
+                    .all(|pixel| *pixel == egui::Color32::from_rgb(10, 20, 30))
+            );
+        }
+    }
+}
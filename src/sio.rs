@@ -0,0 +1,662 @@
+use std::collections::VecDeque;
+use std::io;
+
+use serde::{Deserialize, Serialize};
+use tracing::{Level, event};
+
+use crate::pad::{Buttons, DigitalPad};
+
+// Bytes the receive FIFO holds
+const RX_FIFO_SIZE: usize = 8;
+// CPU cycles between the last bit of a byte and a device pulling /ACK low
+const ACK_DELAY: u32 = 338;
+// CPU cycles /ACK stays low
+const ACK_PULSE: u32 = 100;
+
+// Controllers and memory cards sit on one of the two ports. Every device on the selected port
+// sees the address byte starting a transfer. Only the device that answers it with an /ACK
+// takes part in the rest of the transfer
+pub trait SioDevice: Send {
+    // Returns the byte shifted out at the same time and whether /ACK follows it
+    fn exchange(&mut self, byte: u8) -> (u8, bool);
+    // The port was deselected, ending the transfer
+    fn deselect(&mut self) {}
+    // Buttons held on the controller in a slot. Only a multitap has more than slot 0
+    fn set_buttons(&mut self, _slot: usize, _buttons: Buttons) {}
+    // Stick positions of the pad in a slot, as right X and Y then left X and Y. 0x00 is left
+    // or up and 0x80 is centred
+    fn set_axes(&mut self, _slot: usize, _axes: [u8; 4]) {}
+    // Mouse motion since the last call, positive right and down, and the buttons held
+    fn move_mouse(&mut self, _dx: i32, _dy: i32, _left: bool, _right: bool) {}
+    // The Analog button on the pad was pressed
+    fn press_analog(&mut self) {}
+    // Speeds of the small and large rumble motors, 0 when still
+    fn motors(&self) -> (u8, u8) {
+        (0, 0)
+    }
+    // Saves anything written to the device since the last flush
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+    // Whether there are writes the next flush would save
+    fn unsaved(&self) -> bool {
+        false
+    }
+    // Copy of the device as it is now, so run-ahead can put it back after the frames it runs
+    fn clone_box(&self) -> Box<dyn SioDevice>;
+}
+
+impl Clone for Box<dyn SioDevice> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+// Where the speeds of the rumble motors go, such as a host gamepad
+pub trait Rumble {
+    fn set_motors(&mut self, port: usize, small: u8, large: u8);
+}
+
+// Each port has a controller and a memory card slot sharing the same lines
+#[derive(Clone, Default)]
+pub struct Port {
+    pub controller: Option<Box<dyn SioDevice>>,
+    pub memcard: Option<Box<dyn SioDevice>>,
+}
+
+impl Port {
+    fn devices(&mut self) -> impl Iterator<Item = &mut Box<dyn SioDevice>> {
+        self.controller.iter_mut().chain(self.memcard.iter_mut())
+    }
+}
+
+// Serial port 0 at 0x1F801040-0x1F80104F
+#[derive(Serialize, Deserialize)]
+pub struct Sio0 {
+    #[serde(skip)]
+    pub ports: [Port; 2],
+    // Device on the selected port taking part in the current transfer
+    active: Option<usize>,
+    // Set once the address byte of the current transfer has been sent
+    addressed: bool,
+    rx_fifo: VecDeque<u8>,
+    // Byte waiting for the current byte to finish
+    tx_pending: Option<u8>,
+    // Byte being shifted out and the cycles left until it is done
+    transfer: Option<(u8, u32)>,
+    // Cycles until the device answers with /ACK, then until it lets go
+    ack_delay: Option<u32>,
+    ack_cycles: u32,
+    irq: bool,
+    mode: u16,
+    control: u16,
+    baud: u16,
+}
+
+impl Sio0 {
+    pub fn new() -> Self {
+        Self {
+            ports: [
+                Port {
+                    controller: Some(Box::new(DigitalPad::new())),
+                    memcard: None,
+                },
+                Port::default(),
+            ],
+            active: None,
+            addressed: false,
+            rx_fifo: VecDeque::with_capacity(RX_FIFO_SIZE),
+            tx_pending: None,
+            transfer: None,
+            ack_delay: None,
+            ack_cycles: 0,
+            irq: false,
+            mode: 0,
+            control: 0,
+            baud: 0,
+        }
+    }
+
+    // Moves the controllers and memory cards over from another machine. Any transfer they were
+    // in the middle of ends, so a reset or loaded state starts them the same way every time
+    pub fn take_ports_from(&mut self, old: &mut Sio0) {
+        std::mem::swap(&mut self.ports, &mut old.ports);
+        self.deselect();
+    }
+
+    pub fn set_buttons(&mut self, port: usize, slot: usize, buttons: Buttons) {
+        if let Some(controller) = &mut self.ports[port].controller {
+            controller.set_buttons(slot, buttons);
+        }
+    }
+
+    pub fn set_axes(&mut self, port: usize, slot: usize, axes: [u8; 4]) {
+        if let Some(controller) = &mut self.ports[port].controller {
+            controller.set_axes(slot, axes);
+        }
+    }
+
+    pub fn move_mouse(&mut self, port: usize, dx: i32, dy: i32, left: bool, right: bool) {
+        if let Some(controller) = &mut self.ports[port].controller {
+            controller.move_mouse(dx, dy, left, right);
+        }
+    }
+
+    pub fn press_analog(&mut self, port: usize) {
+        if let Some(controller) = &mut self.ports[port].controller {
+            controller.press_analog();
+        }
+    }
+
+    // Hands the motor speeds of the controllers on each port to the host
+    pub fn forward_rumble(&self, rumble: &mut dyn Rumble) {
+        for (port, device) in self.ports.iter().enumerate() {
+            if let Some(controller) = &device.controller {
+                let (small, large) = controller.motors();
+                rumble.set_motors(port, small, large);
+            }
+        }
+    }
+
+    pub fn connect_controller(&mut self, port: usize, controller: Option<Box<dyn SioDevice>>) {
+        self.deselect();
+        self.ports[port].controller = controller;
+    }
+
+    // Replaces the memory card in a slot, saving the card taken out
+    pub fn insert_memcard(
+        &mut self,
+        slot: usize,
+        card: Option<Box<dyn SioDevice>>,
+    ) -> io::Result<()> {
+        self.deselect();
+        let old = std::mem::replace(&mut self.ports[slot].memcard, card);
+        match old {
+            Some(mut old) => old.flush(),
+            None => Ok(()),
+        }
+    }
+
+    // Saves the memory cards. Stops at the first card that fails
+    pub fn flush(&mut self) -> io::Result<()> {
+        for port in &mut self.ports {
+            for device in port.devices() {
+                device.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    // Whether /JOYn selects a port, so a device may be part way through a transfer
+    pub fn selected(&self) -> bool {
+        self.control & 0x2 > 0
+    }
+
+    // Cards are flushed every moment, so unsaved writes mean a game is probably saving
+    pub fn unsaved_writes(&self) -> bool {
+        self.ports.iter().any(|port| {
+            port.controller
+                .iter()
+                .chain(&port.memcard)
+                .any(|device| device.unsaved())
+        })
+    }
+
+    // Advance the port by the given CPU cycles. Returns true if IRQ
+    pub fn tick(&mut self, cycles: u32) -> bool {
+        let mut irq = false;
+        self.ack_cycles = self.ack_cycles.saturating_sub(cycles);
+        // Only the cycles after the byte finished count towards its /ACK
+        let mut ack_cycles = cycles;
+
+        if let Some((byte, remaining)) = self.transfer {
+            if remaining > cycles {
+                self.transfer = Some((byte, remaining - cycles));
+            } else {
+                self.transfer = None;
+                ack_cycles = cycles - remaining;
+                irq |= self.finish_byte(byte);
+                if let Some(next) = self.tx_pending.take() {
+                    self.start_byte(next);
+                }
+            }
+        }
+
+        if let Some(delay) = self.ack_delay {
+            if delay > ack_cycles {
+                self.ack_delay = Some(delay - ack_cycles);
+            } else {
+                self.ack_delay = None;
+                self.ack_cycles = ACK_PULSE;
+                // CTRL bit 12 enables the IRQ on /ACK
+                if self.control & 0x1000 > 0 {
+                    irq |= self.raise_irq();
+                }
+            }
+        }
+
+        irq
+    }
+
+    // Registers wider than a byte are shifted so the byte at the offset is in the low bits
+    pub fn read(&mut self, offset: u32) -> u32 {
+        match offset {
+            // Reading the data register takes a byte from the receive FIFO
+            0x0 => self.rx_fifo.pop_front().unwrap_or(0xFF) as u32,
+            0x1..=0x3 => 0,
+            0x4..=0x7 => self.status() >> ((offset - 0x4) * 8),
+            0x8 | 0x9 => (self.mode >> ((offset & 1) * 8)) as u32,
+            0xA | 0xB => (self.control >> ((offset & 1) * 8)) as u32,
+            0xE | 0xF => (self.baud >> ((offset & 1) * 8)) as u32,
+            _ => {
+                event!(target: "ps1_emulator::SIO", Level::DEBUG, "Read from unhandled register {:X}", offset);
+                0
+            }
+        }
+    }
+
+    pub fn write(&mut self, offset: u32, val: u16) {
+        match offset {
+            0x0 => self.data_write(val as u8),
+            0x8 => self.mode = val,
+            0xA => self.control_write(val),
+            0xE => self.baud = val,
+            _ => {
+                event!(
+                    target: "ps1_emulator::SIO",
+                    Level::DEBUG,
+                    "Write to unhandled register {:X} with {:04X}",
+                    offset,
+                    val
+                );
+            }
+        }
+    }
+
+    fn data_write(&mut self, val: u8) {
+        // A second byte waits in the transmit FIFO until the first is done
+        if self.transfer.is_some() {
+            self.tx_pending = Some(val);
+        } else {
+            self.start_byte(val);
+        }
+    }
+
+    fn control_write(&mut self, val: u16) {
+        // Reset clears the FIFOs and every register but BAUD
+        if val & 0x40 > 0 {
+            self.rx_fifo.clear();
+            self.tx_pending = None;
+            self.transfer = None;
+            self.ack_delay = None;
+            self.ack_cycles = 0;
+            self.irq = false;
+            self.mode = 0;
+            self.control = 0;
+            self.deselect();
+            return;
+        }
+
+        // Acknowledge clears the IRQ
+        if val & 0x10 > 0 {
+            self.irq = false;
+        }
+
+        // /JOYn going high or switching ports ends the transfer
+        let prev = self.control;
+        self.control = val & !0x50;
+        if val & 0x2 == 0 || (prev ^ val) & 0x2000 > 0 {
+            self.deselect();
+        }
+    }
+
+    fn deselect(&mut self) {
+        self.active = None;
+        self.addressed = false;
+        for port in &mut self.ports {
+            for device in port.devices() {
+                device.deselect();
+            }
+        }
+    }
+
+    // A byte takes eight baud periods. MODE bits 0-1 set the baud multiplier
+    fn start_byte(&mut self, val: u8) {
+        let factor = match self.mode & 0x3 {
+            2 => 16,
+            3 => 64,
+            _ => 1,
+        };
+        let cycles = (self.baud as u32 * factor * 8).max(1);
+        self.transfer = Some((val, cycles));
+        self.ack_delay = None;
+        self.ack_cycles = 0;
+    }
+
+    // Exchanges the byte with the selected port and stores the byte received. Returns true if IRQ
+    fn finish_byte(&mut self, tx: u8) -> bool {
+        let (rx, ack) = if self.control & 0x2 > 0 {
+            let port = &mut self.ports[(self.control >> 13) as usize & 1];
+            match self.active {
+                Some(i) => match port.devices().nth(i) {
+                    Some(device) => device.exchange(tx),
+                    None => (0xFF, false),
+                },
+                // Nothing answered the address byte
+                None if self.addressed => (0xFF, false),
+                None => {
+                    self.addressed = true;
+                    let mut result = (0xFF, false);
+                    for (i, device) in port.devices().enumerate() {
+                        let (rx, ack) = device.exchange(tx);
+                        if ack {
+                            self.active = Some(i);
+                            result = (rx, ack);
+                            break;
+                        }
+                    }
+                    result
+                }
+            }
+        } else {
+            (0xFF, false)
+        };
+
+        // Bytes arriving with the FIFO full overwrite the newest byte
+        if self.rx_fifo.len() == RX_FIFO_SIZE {
+            self.rx_fifo.pop_back();
+        }
+        self.rx_fifo.push_back(rx);
+        if ack {
+            self.ack_delay = Some(ACK_DELAY);
+        }
+
+        // CTRL bit 11 enables the IRQ once bits 8-9 select 1, 2, 4 or 8 bytes received
+        let rx_level = 1 << ((self.control >> 8) & 0x3);
+        let mut irq = false;
+        if self.control & 0x800 > 0 && self.rx_fifo.len() >= rx_level {
+            irq |= self.raise_irq();
+        }
+        // CTRL bit 10 enables the IRQ once the byte has been sent
+        if self.control & 0x400 > 0 {
+            irq |= self.raise_irq();
+        }
+        irq
+    }
+
+    // The IRQ is only raised again after being acknowledged
+    fn raise_irq(&mut self) -> bool {
+        let raised = !self.irq;
+        self.irq = true;
+        raised
+    }
+
+    fn status(&self) -> u32 {
+        let mut status = 0;
+        // TX FIFO not full
+        if self.tx_pending.is_none() {
+            status |= 0x1;
+        }
+        if !self.rx_fifo.is_empty() {
+            status |= 0x2;
+        }
+        // Transfer finished
+        if self.transfer.is_none() && self.tx_pending.is_none() {
+            status |= 0x4;
+        }
+        // /ACK input is low
+        if self.ack_cycles > 0 {
+            status |= 0x80;
+        }
+        if self.irq {
+            status |= 0x200;
+        }
+        status
+    }
+}
+
+// Runs a whole transfer with a device. Returns the bytes received for each byte sent, stopping
+// after the first without an /ACK
+#[cfg(test)]
+pub fn transfer(device: &mut dyn SioDevice, bytes: &[u8]) -> Vec<u8> {
+    let mut received = Vec::new();
+    for &byte in bytes {
+        let (rx, ack) = device.exchange(byte);
+        received.push(rx);
+        if !ack {
+            break;
+        }
+    }
+    device.deselect();
+    received
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    // Answers address byte 0x01 and then sends REPLY, keeping the bytes it receives
+    #[derive(Clone, Default)]
+    struct Echo {
+        received: Arc<Mutex<Vec<u8>>>,
+        step: usize,
+    }
+
+    const REPLY: [u8; 3] = [0x10, 0x20, 0x30];
+
+    impl SioDevice for Echo {
+        fn exchange(&mut self, byte: u8) -> (u8, bool) {
+            if self.step == 0 && byte != 0x01 {
+                return (0xFF, false);
+            }
+            self.received.lock().unwrap().push(byte);
+            let reply = match self.step {
+                0 => 0xFF,
+                step => REPLY[step - 1],
+            };
+            self.step += 1;
+            (reply, self.step <= REPLY.len())
+        }
+
+        fn deselect(&mut self) {
+            self.step = 0;
+        }
+
+        fn clone_box(&self) -> Box<dyn SioDevice> {
+            Box::new(self.clone())
+        }
+    }
+
+    // Port with an Echo as its controller, selecting port 0 or 1
+    fn echo_sio(port: usize) -> (Sio0, Arc<Mutex<Vec<u8>>>) {
+        let mut sio = Sio0::new();
+        let echo = Echo::default();
+        let received = echo.received.clone();
+        sio.connect_controller(0, None);
+        sio.connect_controller(port, Some(Box::new(echo)));
+        sio.write(0xE, 0x88);
+        sio.write(0x8, 0x0D);
+        sio.write(0xA, 0x0003 | (port as u16) << 13);
+        (sio, received)
+    }
+
+    // Sends a byte through the data register and waits for the one received
+    fn exchange(sio: &mut Sio0, byte: u8) -> u8 {
+        sio.write(0x0, byte as u16);
+        assert_eq!(sio.read(0x4) & 0x4, 0);
+        while sio.read(0x4) & 0x4 == 0 {
+            sio.tick(8);
+        }
+        assert_eq!(sio.read(0x4) & 0x2, 0x2);
+        let rx = sio.read(0x0) as u8;
+        assert_eq!(sio.read(0x4) & 0x2, 0);
+        rx
+    }
+
+    #[test]
+    fn registers_exchange_bytes_with_the_selected_port() {
+        for port in 0..2 {
+            let (mut sio, received) = echo_sio(port);
+            let rx: Vec<u8> = [0x01, 0xA1, 0xA2, 0xA3]
+                .into_iter()
+                .map(|byte| exchange(&mut sio, byte))
+                .collect();
+            assert_eq!(rx, [0xFF, 0x10, 0x20, 0x30]);
+            assert_eq!(*received.lock().unwrap(), [0x01, 0xA1, 0xA2, 0xA3]);
+
+            // Deselecting ends the transfer, so the next starts with the address again
+            sio.write(0xA, 0);
+            sio.write(0xA, 0x0003 | (port as u16) << 13);
+            assert_eq!(exchange(&mut sio, 0x01), 0xFF);
+            assert_eq!(exchange(&mut sio, 0xB1), 0x10);
+        }
+
+        // Nothing answers on the other port or with the port deselected
+        let (mut sio, received) = echo_sio(1);
+        sio.write(0xA, 0x0003);
+        assert_eq!(exchange(&mut sio, 0x01), 0xFF);
+        assert_eq!(exchange(&mut sio, 0xA1), 0xFF);
+        sio.write(0xA, 0x2001);
+        assert_eq!(exchange(&mut sio, 0x01), 0xFF);
+        assert!(received.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn second_byte_waits_in_the_transmit_fifo() {
+        let (mut sio, received) = echo_sio(0);
+        sio.write(0x0, 0x01);
+        assert_eq!(sio.read(0x4) & 0x1, 0x1);
+        sio.write(0x0, 0xA1);
+        // TX FIFO full and the transfer still going
+        assert_eq!(sio.read(0x4) & 0x5, 0);
+
+        // A byte takes 8 baud periods
+        sio.tick(0x88 * 8);
+        assert_eq!(sio.read(0x4) & 0x7, 0x3);
+        sio.tick(0x88 * 8);
+        assert_eq!(sio.read(0x4) & 0x7, 0x7);
+        assert_eq!([sio.read(0x0), sio.read(0x0)], [0xFF, 0x10]);
+        assert_eq!(*received.lock().unwrap(), [0x01, 0xA1]);
+
+        // Reset empties the FIFOs and clears the registers
+        sio.write(0x0, 0xA2);
+        sio.tick(0x88 * 8);
+        sio.write(0xA, 0x40);
+        assert_eq!(sio.read(0x4) & 0x2, 0);
+        assert_eq!(sio.read(0xA), 0);
+        assert_eq!(sio.read(0xE), 0x88);
+    }
+
+    // Cycles from writing a byte until the IRQ, if one comes within a while
+    fn cycles_to_irq(sio: &mut Sio0, byte: u8) -> Option<u32> {
+        sio.write(0x0, byte as u16);
+        (1..=5000).find(|_| sio.tick(1))
+    }
+
+    #[test]
+    fn ack_raises_the_irq_after_a_delay() {
+        let (mut sio, _) = echo_sio(0);
+        // /ACK interrupts enabled
+        sio.write(0xA, 0x1003);
+        let byte_cycles = 0x88 * 8;
+        for byte in [0x01, 0xA1, 0xA2] {
+            assert_eq!(cycles_to_irq(&mut sio, byte), Some(byte_cycles + ACK_DELAY));
+            assert_eq!(sio.read(0x4) & 0x280, 0x280);
+            // /ACK goes back up but the IRQ stays until acknowledged
+            sio.tick(ACK_PULSE);
+            assert_eq!(sio.read(0x4) & 0x280, 0x200);
+            sio.write(0xA, 0x1013);
+            assert_eq!(sio.read(0x4) & 0x200, 0);
+        }
+        // The last byte of the transfer has no /ACK
+        assert_eq!(cycles_to_irq(&mut sio, 0xA3), None);
+        assert_eq!(sio.read(0x4) & 0x280, 0);
+    }
+
+    #[test]
+    fn unacknowledged_irq_is_not_raised_again() {
+        let (mut sio, _) = echo_sio(0);
+        sio.write(0xA, 0x1003);
+        assert!(cycles_to_irq(&mut sio, 0x01).is_some());
+        assert_eq!(cycles_to_irq(&mut sio, 0xA1), None);
+        assert_eq!(sio.read(0x4) & 0x200, 0x200);
+
+        // Without the enable /ACK still pulses, but there's no IRQ
+        let (mut sio, _) = echo_sio(0);
+        sio.write(0x0, 0x01);
+        let mut ack = 0;
+        for _ in 0..5000 {
+            assert!(!sio.tick(1));
+            ack += (sio.read(0x4) >> 7) & 1;
+        }
+        assert_eq!(ack, ACK_PULSE);
+    }
+
+    // Memory card that logs its flushes, failing them when asked
+    #[derive(Clone)]
+    struct LoggedCard {
+        name: &'static str,
+        log: Arc<Mutex<Vec<&'static str>>>,
+        unsaved: bool,
+        fail: bool,
+    }
+
+    impl SioDevice for LoggedCard {
+        fn exchange(&mut self, _byte: u8) -> (u8, bool) {
+            (0xFF, false)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.log.lock().unwrap().push(self.name);
+            if self.fail {
+                return Err(io::Error::other("disk full"));
+            }
+            self.unsaved = false;
+            Ok(())
+        }
+
+        fn unsaved(&self) -> bool {
+            self.unsaved
+        }
+
+        fn clone_box(&self) -> Box<dyn SioDevice> {
+            Box::new(self.clone())
+        }
+    }
+
+    #[test]
+    fn flush_saves_each_card_in_order_and_stops_at_a_failure() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let card = |name, unsaved, fail| {
+            Some(Box::new(LoggedCard {
+                name,
+                log: log.clone(),
+                unsaved,
+                fail,
+            }) as Box<dyn SioDevice>)
+        };
+        let mut sio = Sio0::new();
+        sio.connect_controller(0, card("pad 1", false, false));
+        sio.connect_controller(1, None);
+        sio.insert_memcard(0, card("card 1", false, false)).unwrap();
+        sio.insert_memcard(1, card("card 2", true, false)).unwrap();
+        assert!(sio.unsaved_writes());
+
+        sio.flush().unwrap();
+        assert_eq!(*log.lock().unwrap(), ["pad 1", "card 1", "card 2"]);
+        assert!(!sio.unsaved_writes());
+
+        // A card taken out is saved as it goes
+        log.lock().unwrap().clear();
+        sio.insert_memcard(0, card("card 3", true, true)).unwrap();
+        assert_eq!(*log.lock().unwrap(), ["card 1"]);
+
+        log.lock().unwrap().clear();
+        assert!(sio.flush().is_err());
+        assert_eq!(*log.lock().unwrap(), ["pad 1", "card 3"]);
+        assert!(sio.unsaved_writes());
+        assert!(sio.insert_memcard(0, None).is_err());
+    }
+}
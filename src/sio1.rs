@@ -0,0 +1,504 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+use serde::{Deserialize, Serialize};
+use tracing::{Level, event};
+
+// Bytes the receive FIFO holds
+const RX_FIFO_SIZE: usize = 8;
+
+// Whatever is plugged into the serial port. Bytes are sent and received whole, the link
+// handles its own buffering
+pub trait SerialLink: Send {
+    fn send(&mut self, byte: u8);
+    fn receive(&mut self) -> Option<u8>;
+    // DSR and CTS input levels given the DTR and RTS output levels
+    fn handshake(&mut self, dtr: bool, rts: bool) -> (bool, bool);
+}
+
+// Loopback plug. TX is wired to RX, DTR to DSR and RTS to CTS
+#[derive(Default)]
+pub struct Loopback {
+    bytes: VecDeque<u8>,
+}
+
+impl SerialLink for Loopback {
+    fn send(&mut self, byte: u8) {
+        self.bytes.push_back(byte);
+    }
+
+    fn receive(&mut self) -> Option<u8> {
+        self.bytes.pop_front()
+    }
+
+    fn handshake(&mut self, dtr: bool, rts: bool) -> (bool, bool) {
+        (dtr, rts)
+    }
+}
+
+// Link cable to another emulator over TCP. Only the byte stream is carried, the handshake
+// lines read high while connected
+pub struct TcpLink {
+    listener: Option<TcpListener>,
+    stream: Option<TcpStream>,
+    // Bytes sent before the other side connected or while the socket was full
+    outgoing: VecDeque<u8>,
+}
+
+impl TcpLink {
+    // Waits for the other emulator to join
+    pub fn host(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener: Some(listener),
+            stream: None,
+            outgoing: VecDeque::new(),
+        })
+    }
+
+    pub fn join(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            listener: None,
+            stream: Some(stream),
+            outgoing: VecDeque::new(),
+        })
+    }
+
+    // Accepts the other side and sends anything queued. Drops the connection on errors
+    fn poll(&mut self) {
+        if self.stream.is_none()
+            && let Some(listener) = &self.listener
+        {
+            match listener.accept() {
+                Ok((stream, addr)) => {
+                    event!(target: "ps1_emulator::SIO1", Level::INFO, "Link cable connected to {addr}");
+                    if stream
+                        .set_nonblocking(true)
+                        .and_then(|_| stream.set_nodelay(true))
+                        .is_ok()
+                    {
+                        self.stream = Some(stream);
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                Err(err) => {
+                    event!(target: "ps1_emulator::SIO1", Level::WARN, "Link cable accept failed: {err}");
+                }
+            }
+        }
+
+        let Some(stream) = &mut self.stream else {
+            return;
+        };
+        while !self.outgoing.is_empty() {
+            let (front, _) = self.outgoing.as_slices();
+            match stream.write(front) {
+                Ok(0) => break,
+                Ok(written) => {
+                    self.outgoing.drain(..written);
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    event!(target: "ps1_emulator::SIO1", Level::WARN, "Link cable disconnected: {err}");
+                    self.stream = None;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl SerialLink for TcpLink {
+    fn send(&mut self, byte: u8) {
+        self.outgoing.push_back(byte);
+        self.poll();
+    }
+
+    fn receive(&mut self) -> Option<u8> {
+        self.poll();
+        let stream = self.stream.as_mut()?;
+        let mut byte = [0];
+        match stream.read(&mut byte) {
+            Ok(1) => Some(byte[0]),
+            Ok(_) => {
+                event!(target: "ps1_emulator::SIO1", Level::INFO, "Link cable closed");
+                self.stream = None;
+                None
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => None,
+            Err(err) => {
+                event!(target: "ps1_emulator::SIO1", Level::WARN, "Link cable disconnected: {err}");
+                self.stream = None;
+                None
+            }
+        }
+    }
+
+    fn handshake(&mut self, _dtr: bool, _rts: bool) -> (bool, bool) {
+        let connected = self.stream.is_some();
+        (connected, connected)
+    }
+}
+
+// Serial port 1 at 0x1F801050-0x1F80105F
+#[derive(Serialize, Deserialize)]
+pub struct Sio1 {
+    #[serde(skip)]
+    link: Option<Box<dyn SerialLink>>,
+    rx_fifo: VecDeque<u8>,
+    // Byte waiting for the current byte to finish
+    tx_pending: Option<u8>,
+    // Byte being shifted out and the cycles left until it is done
+    transfer: Option<(u8, u32)>,
+    // Cycles until the next received byte can be taken from the link
+    rx_cycles: u32,
+    overrun: bool,
+    dsr: bool,
+    cts: bool,
+    irq: bool,
+    mode: u16,
+    control: u16,
+    baud: u16,
+}
+
+impl Sio1 {
+    pub fn new() -> Self {
+        Self {
+            link: None,
+            rx_fifo: VecDeque::with_capacity(RX_FIFO_SIZE),
+            tx_pending: None,
+            transfer: None,
+            rx_cycles: 0,
+            overrun: false,
+            dsr: false,
+            cts: false,
+            irq: false,
+            mode: 0,
+            control: 0,
+            baud: 0,
+        }
+    }
+
+    pub fn connect(&mut self, link: Option<Box<dyn SerialLink>>) {
+        self.link = link;
+    }
+
+    pub fn take_link(&mut self) -> Option<Box<dyn SerialLink>> {
+        self.link.take()
+    }
+
+    // Advance the port by the given CPU cycles. Returns true if IRQ
+    pub fn tick(&mut self, cycles: u32) -> bool {
+        let mut irq = false;
+
+        if let Some((byte, remaining)) = self.transfer {
+            if remaining > cycles {
+                self.transfer = Some((byte, remaining - cycles));
+            } else {
+                self.transfer = None;
+                if let Some(link) = &mut self.link {
+                    link.send(byte);
+                }
+                if let Some(next) = self.tx_pending.take() {
+                    self.start_byte(next);
+                }
+                // CTRL bit 10 enables the IRQ once the byte has been sent
+                if self.control & 0x400 > 0 {
+                    irq |= self.raise_irq();
+                }
+            }
+        }
+
+        // Bytes arrive no faster than the baud rate allows. RX is enabled by CTRL bit 2
+        self.rx_cycles = self.rx_cycles.saturating_sub(cycles);
+        if self.rx_cycles == 0
+            && self.control & 0x4 > 0
+            && let Some(byte) = self.link.as_mut().and_then(|link| link.receive())
+        {
+            self.rx_cycles = self.byte_cycles();
+            if self.rx_fifo.len() == RX_FIFO_SIZE {
+                self.overrun = true;
+                self.rx_fifo.pop_back();
+            }
+            self.rx_fifo.push_back(byte);
+
+            // CTRL bit 11 enables the IRQ once bits 8-9 select 1, 2, 4 or 8 bytes received
+            let rx_level = 1 << ((self.control >> 8) & 0x3);
+            if self.control & 0x800 > 0 && self.rx_fifo.len() >= rx_level {
+                irq |= self.raise_irq();
+            }
+        }
+
+        let (dsr, cts) = match &mut self.link {
+            Some(link) => link.handshake(self.control & 0x2 > 0, self.control & 0x20 > 0),
+            None => (false, false),
+        };
+        self.dsr = dsr;
+        self.cts = cts;
+        // CTRL bit 12 enables the IRQ while DSR is high
+        if dsr && self.control & 0x1000 > 0 {
+            irq |= self.raise_irq();
+        }
+
+        irq
+    }
+
+    // Registers wider than a byte are shifted so the byte at the offset is in the low bits
+    pub fn read(&mut self, offset: u32) -> u32 {
+        match offset {
+            // Reading the data register takes a byte from the receive FIFO
+            0x0 => self.rx_fifo.pop_front().unwrap_or(0) as u32,
+            0x1..=0x3 => 0,
+            0x4..=0x7 => self.status() >> ((offset - 0x4) * 8),
+            0x8 | 0x9 => (self.mode >> ((offset & 1) * 8)) as u32,
+            0xA | 0xB => (self.control >> ((offset & 1) * 8)) as u32,
+            0xE | 0xF => (self.baud >> ((offset & 1) * 8)) as u32,
+            _ => {
+                event!(target: "ps1_emulator::SIO1", Level::DEBUG, "Read from unhandled register {:X}", offset);
+                0
+            }
+        }
+    }
+
+    pub fn write(&mut self, offset: u32, val: u16) {
+        match offset {
+            0x0 => self.data_write(val as u8),
+            0x8 => self.mode = val,
+            0xA => self.control_write(val),
+            0xE => self.baud = val,
+            _ => {
+                event!(
+                    target: "ps1_emulator::SIO1",
+                    Level::DEBUG,
+                    "Write to unhandled register {:X} with {:04X}",
+                    offset,
+                    val
+                );
+            }
+        }
+    }
+
+    fn data_write(&mut self, val: u8) {
+        // Bytes are only sent while TX is enabled by CTRL bit 0
+        if self.control & 0x1 == 0 {
+            event!(target: "ps1_emulator::SIO1", Level::DEBUG, "Data write with TX disabled");
+            return;
+        }
+        // A second byte waits in the transmit FIFO until the first is done
+        if self.transfer.is_some() {
+            self.tx_pending = Some(val);
+        } else {
+            self.start_byte(val);
+        }
+    }
+
+    fn control_write(&mut self, val: u16) {
+        // Reset clears the FIFOs and every register but BAUD
+        if val & 0x40 > 0 {
+            self.rx_fifo.clear();
+            self.tx_pending = None;
+            self.transfer = None;
+            self.overrun = false;
+            self.irq = false;
+            self.mode = 0;
+            self.control = 0;
+            return;
+        }
+
+        // Acknowledge clears the IRQ and the error flags
+        if val & 0x10 > 0 {
+            self.irq = false;
+            self.overrun = false;
+        }
+        self.control = val & !0x50;
+    }
+
+    // Start bit, 5 to 8 data bits, optional parity and the stop bits, each one baud period
+    // long. MODE bits 0-1 set the baud multiplier
+    fn byte_cycles(&self) -> u32 {
+        let factor = match self.mode & 0x3 {
+            2 => 16,
+            3 => 64,
+            _ => 1,
+        };
+        let data_bits = 5 + ((self.mode >> 2) & 0x3) as u32;
+        let parity = ((self.mode >> 4) & 0x1) as u32;
+        let stop_bits = match (self.mode >> 6) & 0x3 {
+            3 => 2,
+            _ => 1,
+        };
+        (self.baud as u32 * factor * (1 + data_bits + parity + stop_bits)).max(1)
+    }
+
+    fn start_byte(&mut self, val: u8) {
+        self.transfer = Some((val, self.byte_cycles()));
+    }
+
+    // The IRQ is only raised again after being acknowledged
+    fn raise_irq(&mut self) -> bool {
+        let raised = !self.irq;
+        self.irq = true;
+        raised
+    }
+
+    fn status(&self) -> u32 {
+        let mut status = 0;
+        // TX FIFO not full
+        if self.tx_pending.is_none() {
+            status |= 0x1;
+        }
+        if !self.rx_fifo.is_empty() {
+            status |= 0x2;
+        }
+        // Transfer finished
+        if self.transfer.is_none() && self.tx_pending.is_none() {
+            status |= 0x4;
+        }
+        if self.overrun {
+            status |= 0x10;
+        }
+        // RX input idles high
+        status |= 0x40;
+        if self.dsr {
+            status |= 0x80;
+        }
+        if self.cts {
+            status |= 0x100;
+        }
+        if self.irq {
+            status |= 0x200;
+        }
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 8 data bits and 1 stop bit at 16 cycles a bit, so a byte takes 160 cycles
+    const BYTE_CYCLES: u32 = 160;
+
+    fn loopback_sio1(control: u16) -> Sio1 {
+        let mut sio1 = Sio1::new();
+        sio1.connect(Some(Box::new(Loopback::default())));
+        sio1.write(0x8, 0x4D);
+        sio1.write(0xE, 0x10);
+        sio1.write(0xA, control);
+        sio1
+    }
+
+    #[test]
+    fn loopback_receives_the_bytes_sent() {
+        // TX and RX enabled with the IRQ on each byte received
+        let mut sio1 = loopback_sio1(0x0805);
+        sio1.write(0x0, 0x55);
+        sio1.write(0x0, 0xAA);
+        assert_eq!(sio1.read(0x4) & 0x7, 0);
+
+        assert!(!sio1.tick(BYTE_CYCLES - 1));
+        assert!(sio1.tick(1));
+        assert_eq!(sio1.read(0x4) & 0x203, 0x203);
+        assert_eq!(sio1.read(0x0), 0x55);
+        sio1.write(0xA, 0x0815);
+        assert_eq!(sio1.read(0x4) & 0x200, 0);
+
+        assert!(sio1.tick(BYTE_CYCLES));
+        assert_eq!(sio1.read(0x4) & 0x7, 0x7);
+        assert_eq!(sio1.read(0x0), 0xAA);
+        assert_eq!(sio1.read(0x4) & 0x2, 0);
+    }
+
+    #[test]
+    fn registers_follow_the_control_bits() {
+        let mut sio1 = loopback_sio1(0x0001);
+        assert_eq!(sio1.read(0x8), 0x4D);
+        assert_eq!(sio1.read(0xE), 0x10);
+        // DTR and RTS loop back to DSR and CTS
+        sio1.tick(1);
+        assert_eq!(sio1.read(0x4) & 0x180, 0);
+        sio1.write(0xA, 0x0023);
+        sio1.tick(1);
+        assert_eq!(sio1.read(0x4) & 0x180, 0x180);
+        // The IRQ while DSR is high
+        sio1.write(0xA, 0x1023);
+        assert!(sio1.tick(1));
+
+        // Nothing is sent with TX disabled or received with RX disabled
+        let mut sio1 = loopback_sio1(0x0000);
+        sio1.write(0x0, 0x55);
+        assert!(sio1.transfer.is_none());
+        sio1.write(0xA, 0x0001);
+        sio1.write(0x0, 0x55);
+        sio1.tick(BYTE_CYCLES * 2);
+        assert_eq!(sio1.read(0x4) & 0x2, 0);
+        sio1.write(0xA, 0x0005);
+        sio1.tick(1);
+        assert_eq!(sio1.read(0x0), 0x55);
+    }
+
+    #[test]
+    fn full_fifo_overruns() {
+        let mut sio1 = loopback_sio1(0x0005);
+        for byte in 0..9 {
+            sio1.write(0x0, byte);
+            sio1.tick(BYTE_CYCLES);
+        }
+        assert_eq!(sio1.read(0x4) & 0x10, 0x10);
+        // The last byte replaces the newest one in the FIFO
+        let bytes: Vec<u32> = (0..8).map(|_| sio1.read(0x0)).collect();
+        assert_eq!(bytes, [0, 1, 2, 3, 4, 5, 6, 8]);
+        sio1.write(0xA, 0x0015);
+        assert_eq!(sio1.read(0x4) & 0x10, 0);
+
+        // Reset clears everything but BAUD
+        sio1.write(0x0, 0x55);
+        sio1.write(0xA, 0x40);
+        assert_eq!(sio1.read(0x4) & 0x7, 0x5);
+        assert_eq!(
+            [sio1.read(0x8), sio1.read(0xA), sio1.read(0xE)],
+            [0, 0, 0x10]
+        );
+    }
+
+    #[test]
+    fn tcp_link_carries_bytes_both_ways() {
+        // A port nothing is listening on
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut host = TcpLink::host(("127.0.0.1", port)).unwrap();
+        // Bytes sent before the other side joins are kept
+        host.send(0x12);
+        assert_eq!(host.handshake(true, true), (false, false));
+        let mut guest = TcpLink::join(("127.0.0.1", port)).unwrap();
+        let receive = |link: &mut TcpLink| {
+            (0..1000).find_map(|_| {
+                std::thread::sleep(std::time::Duration::from_millis(1));
+                link.receive()
+            })
+        };
+        // The host accepts and sends what was queued when next polled
+        assert_eq!(host.receive(), None);
+        assert_eq!(host.handshake(false, false), (true, true));
+        assert_eq!(receive(&mut guest), Some(0x12));
+        guest.send(0x34);
+        assert_eq!(receive(&mut host), Some(0x34));
+
+        // Closing one side drops the line on the other
+        drop(guest);
+        let closed = (0..1000).any(|_| {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            host.receive();
+            host.handshake(true, true) == (false, false)
+        });
+        assert!(closed);
+    }
+}
@@ -0,0 +1,1582 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tracing::{Level, event};
+
+// 512 KB of sound RAM
+const RAM_SIZE: usize = 0x80000;
+// Halfwords the transfer FIFO holds before a manual transfer
+const FIFO_SIZE: usize = 32;
+// CPU cycles per 44.1kHz output sample
+const SAMPLE_CYCLES: u32 = 768;
+// Stereo output samples kept until the frontend takes them, about a tenth of a second
+const OUTPUT_CAPACITY: usize = 8820;
+// CPU cycles SPUSTAT reports busy for each halfword transferred
+const TRANSFER_CYCLES: u32 = 16;
+// Samples decoded from each 16 byte ADPCM block
+const BLOCK_SAMPLES: usize = 28;
+// ADPCM filter coefficients. XA-ADPCM on the CDROM only uses the first four
+pub const ADPCM_POS: [i32; 5] = [0, 60, 115, 98, 122];
+pub const ADPCM_NEG: [i32; 5] = [0, 0, -52, -55, -60];
+
+// Reverb register indices. d and m registers are addresses in units of 8 bytes relative to
+// the reverb buffer position, v registers are volumes
+const D_APF1: usize = 0;
+const D_APF2: usize = 1;
+const V_IIR: usize = 2;
+const V_COMB1: usize = 3;
+const V_WALL: usize = 7;
+const V_APF1: usize = 8;
+const V_APF2: usize = 9;
+const M_SAME: usize = 10;
+const M_COMB1: usize = 12;
+const M_COMB2: usize = 14;
+const D_SAME: usize = 16;
+const M_DIFF: usize = 18;
+const M_COMB3: usize = 20;
+const M_COMB4: usize = 22;
+const D_DIFF: usize = 24;
+const M_APF1: usize = 26;
+const M_APF2: usize = 28;
+const V_IN: usize = 30;
+
+#[derive(Clone, Copy, PartialEq, Default, Debug, Serialize, Deserialize)]
+enum AdsrPhase {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    #[default]
+    Off,
+}
+
+// Volume register with fixed or sweep mode. Bit 15 selects sweep
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+struct Volume {
+    register: u16,
+    // Volume applied to the last sample
+    current: i16,
+    // Samples until the next sweep step
+    wait: u32,
+}
+
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+struct Voice {
+    // Left and right volume
+    volume: [Volume; 2],
+    pitch: u16,
+    // Addresses are in units of 8 bytes
+    start_address: u16,
+    adsr: u32,
+    adsr_volume: u16,
+    repeat_address: u16,
+    adsr_phase: AdsrPhase,
+    // Samples until the next envelope step
+    adsr_wait: u32,
+    // Last sample after the envelope. Modulates the pitch of the next voice
+    output: i16,
+    // Byte address of the block being played
+    current_address: u32,
+    // 4.12 fixed point position within the block
+    counter: u32,
+    // The last three samples of the previous block followed by the current block
+    samples: [i16; BLOCK_SAMPLES + 3],
+    // Flags of the current block
+    flags: u8,
+    // Previous two decoded samples for the ADPCM filter
+    history: [i32; 2],
+}
+
+// Save states hold everything but the gaussian table, which is rebuilt, and the output not
+// yet taken by the frontend
+#[derive(Serialize, Deserialize)]
+pub struct Spu {
+    #[serde(serialize_with = "serialize_ram", deserialize_with = "deserialize_ram")]
+    ram: Box<[u8; RAM_SIZE]>,
+    voices: [Voice; 24],
+    main_volume: [Volume; 2],
+    reverb_volume: [u16; 2],
+    cd_volume: [u16; 2],
+    external_volume: [u16; 2],
+    // Voice bit masks. Writing a 1 to key on or key off starts or releases that voice. Both
+    // read back the last value written
+    key_on: u32,
+    key_off: u32,
+    pitch_modulation: u32,
+    noise_mode: u32,
+    reverb_mode: u32,
+    end_flags: u32,
+    reverb_base: u16,
+    irq_address: u16,
+    // SPUSTAT bit 6, set when sound RAM at the IRQ address is accessed
+    irq_flag: bool,
+    irq_pending: bool,
+    transfer_address: u16,
+    // Byte address of the next transfer
+    transfer_pos: u32,
+    transfer_control: u16,
+    fifo: VecDeque<u16>,
+    control: u16,
+    // Reverb configuration from 0x1F801DC0-0x1F801DFF
+    reverb: [u16; 32],
+    // Byte address of the reverb buffer position, which cycles from mBASE to the end of RAM
+    reverb_pos: u32,
+    // Reverb runs at 22.05kHz. The output is held for the odd samples
+    reverb_odd: bool,
+    reverb_out: (i32, i32),
+    // Noise generator shared by voices in noise mode
+    noise_timer: i32,
+    noise_level: u16,
+    #[serde(skip, default = "gauss_table")]
+    gauss: [i32; 512],
+    // Remaining cycles of the last transfer, reported by SPUSTAT bit 10
+    busy_cycles: u32,
+    // Byte offset into each of the four capture buffers at the start of sound RAM
+    capture_pos: u32,
+    sample_cycles: u32,
+    // Interleaved stereo samples at 44.1kHz
+    #[serde(skip)]
+    pub output: Vec<i16>,
+    // CD audio at 44.1kHz from the CDROM
+    pub cd_input: VecDeque<(i16, i16)>,
+}
+
+impl Spu {
+    pub fn new() -> Self {
+        Self {
+            ram: Box::new([0; RAM_SIZE]),
+            voices: [Voice::default(); 24],
+            main_volume: [Volume::default(); 2],
+            reverb_volume: [0; 2],
+            cd_volume: [0; 2],
+            external_volume: [0; 2],
+            key_on: 0,
+            key_off: 0,
+            pitch_modulation: 0,
+            noise_mode: 0,
+            reverb_mode: 0,
+            end_flags: 0,
+            reverb_base: 0,
+            irq_address: 0,
+            irq_flag: false,
+            irq_pending: false,
+            transfer_address: 0,
+            transfer_pos: 0,
+            transfer_control: 0,
+            fifo: VecDeque::with_capacity(FIFO_SIZE),
+            control: 0,
+            reverb: [0; 32],
+            reverb_pos: 0,
+            reverb_odd: false,
+            reverb_out: (0, 0),
+            noise_timer: 0,
+            noise_level: 0,
+            gauss: gauss_table(),
+            busy_cycles: 0,
+            capture_pos: 0,
+            sample_cycles: 0,
+            output: Vec::with_capacity(OUTPUT_CAPACITY),
+            cd_input: VecDeque::new(),
+        }
+    }
+
+    // Generates the samples due in the given CPU cycles. Returns the samples generated
+    pub fn run_cycles(&mut self, cycles: u32) -> &[i16] {
+        // Oldest samples are dropped if the frontend falls behind
+        if self.output.len() > OUTPUT_CAPACITY {
+            let excess = self.output.len() - OUTPUT_CAPACITY;
+            self.output.drain(..excess);
+        }
+        if self.cd_input.len() > OUTPUT_CAPACITY / 2 {
+            let excess = self.cd_input.len() - OUTPUT_CAPACITY / 2;
+            self.cd_input.drain(..excess);
+        }
+
+        self.busy_cycles = self.busy_cycles.saturating_sub(cycles);
+        let start = self.output.len();
+        self.sample_cycles += cycles;
+        while self.sample_cycles >= SAMPLE_CYCLES {
+            self.sample_cycles -= SAMPLE_CYCLES;
+            let (left, right) = self.generate_sample();
+            self.output.push(left);
+            self.output.push(right);
+        }
+        &self.output[start..]
+    }
+
+    fn generate_sample(&mut self) -> (i16, i16) {
+        let mut left = 0;
+        let mut right = 0;
+        let mut reverb_left = 0;
+        let mut reverb_right = 0;
+        self.step_noise();
+        for i in 0..24 {
+            if self.voices[i].adsr_phase == AdsrPhase::Off {
+                self.voices[i].output = 0;
+                continue;
+            }
+
+            // NON replaces the ADPCM samples with the noise generator. The voice keeps
+            // decoding so loops and ENDX behave the same
+            let voice = &mut self.voices[i];
+            let source = if self.noise_mode & (1 << i) > 0 {
+                self.noise_level as i16 as i32
+            } else {
+                voice.interpolate(&self.gauss)
+            };
+            let sample = (source * voice.adsr_volume as i32) >> 15;
+            voice.output = sample as i16;
+            voice.step_envelope();
+            let voice_left = mul(sample, voice.volume[0].step() as i32);
+            let voice_right = mul(sample, voice.volume[1].step() as i32);
+            left += voice_left;
+            right += voice_right;
+            // EON sends the voice to the reverb input
+            if self.reverb_mode & (1 << i) > 0 {
+                reverb_left += voice_left;
+                reverb_right += voice_right;
+            }
+            self.step_voice(i);
+        }
+
+        // CD audio is enabled by SPUCNT bit 0 and sent to reverb by bit 2
+        let (cd_left, cd_right) = self.cd_input.pop_front().unwrap_or((0, 0));
+        let cd_left = mul(cd_left as i32, self.cd_volume[0] as i16 as i32);
+        let cd_right = mul(cd_right as i32, self.cd_volume[1] as i16 as i32);
+        self.capture(cd_left as i16, cd_right as i16);
+        if self.control & 0x1 > 0 {
+            left += cd_left;
+            right += cd_right;
+            if self.control & 0x4 > 0 {
+                reverb_left += cd_left;
+                reverb_right += cd_right;
+            }
+        }
+
+        let (wet_left, wet_right) = self.process_reverb(reverb_left, reverb_right);
+        left += wet_left;
+        right += wet_right;
+
+        // SPUCNT bit 15 enables the SPU and bit 14 unmutes the output
+        if self.control & 0xC000 != 0xC000 {
+            return (0, 0);
+        }
+        left = mul(
+            left.clamp(-0x8000, 0x7FFF),
+            self.main_volume[0].step() as i32,
+        );
+        right = mul(
+            right.clamp(-0x8000, 0x7FFF),
+            self.main_volume[1].step() as i32,
+        );
+        (left as i16, right as i16)
+    }
+
+    // CD left, CD right, voice 1 and voice 3 are written to 0x400 byte ring buffers
+    // sharing one position
+    fn capture(&mut self, cd_left: i16, cd_right: i16) {
+        if self.control & 0x8000 == 0 {
+            return;
+        }
+        let samples = [
+            cd_left,
+            cd_right,
+            self.voices[1].output,
+            self.voices[3].output,
+        ];
+        for (i, sample) in samples.into_iter().enumerate() {
+            let addr = i as u32 * 0x400 + self.capture_pos;
+            self.check_irq(addr);
+            self.write_ram(addr, sample as u16);
+        }
+        self.capture_pos = (self.capture_pos + 2) & 0x3FF;
+    }
+
+    // The noise clock in SPUCNT sets how often a new bit is shifted into the noise level
+    fn step_noise(&mut self) {
+        let step = 4 + ((self.control >> 8) & 0x3) as i32;
+        let shift = (self.control >> 10) & 0xF;
+        let level = self.noise_level;
+        let parity = ((level >> 15) ^ (level >> 12) ^ (level >> 11) ^ (level >> 10) ^ 1) & 1;
+
+        self.noise_timer -= step;
+        if self.noise_timer < 0 {
+            self.noise_level = (level << 1) | parity;
+            self.noise_timer += 0x20000 >> shift;
+            if self.noise_timer < 0 {
+                self.noise_timer += 0x20000 >> shift;
+            }
+        }
+    }
+
+    // Runs the reverb network on every other sample and returns the reverb output
+    fn process_reverb(&mut self, left: i32, right: i32) -> (i32, i32) {
+        self.reverb_odd = !self.reverb_odd;
+        if !self.reverb_odd {
+            return self.reverb_out;
+        }
+
+        let left_in = mul(left.clamp(-0x8000, 0x7FFF), self.reverb_register(V_IN));
+        let right_in = mul(right.clamp(-0x8000, 0x7FFF), self.reverb_register(V_IN + 1));
+        let v_iir = self.reverb_register(V_IIR);
+        let v_wall = self.reverb_register(V_WALL);
+
+        // Same side and cross side reflections through the IIR filter
+        for (side, input) in [(0, left_in), (1, right_in)] {
+            let same = mul(self.reverb_read(D_SAME + side, 0), v_wall);
+            let prev = self.reverb_read(M_SAME + side, -2);
+            self.reverb_write(M_SAME + side, mul(input + same - prev, v_iir) + prev);
+
+            let diff = mul(self.reverb_read(D_DIFF + (side ^ 1), 0), v_wall);
+            let prev = self.reverb_read(M_DIFF + side, -2);
+            self.reverb_write(M_DIFF + side, mul(input + diff - prev, v_iir) + prev);
+        }
+
+        let mut out = [0; 2];
+        for (side, out) in out.iter_mut().enumerate() {
+            // Comb filters
+            let mut acc = 0;
+            for (i, comb) in [M_COMB1, M_COMB2, M_COMB3, M_COMB4].into_iter().enumerate() {
+                acc += mul(
+                    self.reverb_read(comb + side, 0),
+                    self.reverb_register(V_COMB1 + i),
+                );
+            }
+
+            // Two all pass filters
+            for (apf, delay, volume) in [(M_APF1, D_APF1, V_APF1), (M_APF2, D_APF2, V_APF2)] {
+                let offset = -(self.reverb[delay] as i32 * 8);
+                let delayed = self.reverb_read(apf + side, offset);
+                let volume = self.reverb_register(volume);
+                acc = (acc - mul(volume, delayed)).clamp(-0x8000, 0x7FFF);
+                self.reverb_write(apf + side, acc);
+                acc = mul(acc, volume) + delayed;
+            }
+            *out = acc.clamp(-0x8000, 0x7FFF);
+        }
+
+        let base = self.reverb_base as u32 * 8;
+        self.reverb_pos = ((self.reverb_pos + 2) & (RAM_SIZE as u32 - 2)).max(base);
+        self.reverb_out = (
+            mul(out[0], self.reverb_volume[0] as i16 as i32),
+            mul(out[1], self.reverb_volume[1] as i16 as i32),
+        );
+        self.reverb_out
+    }
+
+    fn reverb_register(&self, reg: usize) -> i32 {
+        self.reverb[reg] as i16 as i32
+    }
+
+    // Byte address of a reverb register's address plus an adjustment, wrapped to the buffer
+    fn reverb_address(&self, reg: usize, adjust: i32) -> usize {
+        let base = self.reverb_base as i64 * 8;
+        let size = RAM_SIZE as i64 - base;
+        let offset = self.reverb_pos as i64 - base + self.reverb[reg] as i64 * 8 + adjust as i64;
+        (base + offset.rem_euclid(size)) as usize & (RAM_SIZE - 2)
+    }
+
+    fn reverb_read(&mut self, reg: usize, adjust: i32) -> i32 {
+        let addr = self.reverb_address(reg, adjust);
+        self.check_irq(addr as u32);
+        i16::from_le_bytes([self.ram[addr], self.ram[addr + 1]]) as i32
+    }
+
+    // SPUCNT bit 7 enables writes to the reverb buffer
+    fn reverb_write(&mut self, reg: usize, val: i32) {
+        if self.control & 0x80 == 0 {
+            return;
+        }
+        let addr = self.reverb_address(reg, 0);
+        self.check_irq(addr as u32);
+        self.write_ram(addr as u32, val.clamp(-0x8000, 0x7FFF) as u16);
+    }
+
+    // Advances the pitch counter, moving to the next block at the end of the current one
+    fn step_voice(&mut self, i: usize) {
+        // PMON scales the pitch by the previous voice's output, from 0 to almost 2 times.
+        // Pitches above 0x7FFF are sign extended and the result truncated as on hardware
+        let mut step = self.voices[i].pitch as u32;
+        if i > 0 && self.pitch_modulation & (1 << i) > 0 {
+            let factor = self.voices[i - 1].output as i32 + 0x8000;
+            step = ((step as i16 as i32 * factor) >> 15) as u32 & 0xFFFF;
+        }
+
+        let voice = &mut self.voices[i];
+        voice.counter += step.min(0x4000);
+        if voice.counter < (BLOCK_SAMPLES as u32) << 12 {
+            return;
+        }
+        voice.counter -= (BLOCK_SAMPLES as u32) << 12;
+
+        // Loop end jumps to the repeat address. Without loop repeat the voice stops
+        if voice.flags & 0x1 > 0 {
+            self.end_flags |= 1 << i;
+            voice.current_address = voice.repeat_address as u32 * 8;
+            if voice.flags & 0x2 == 0 {
+                voice.adsr_phase = AdsrPhase::Off;
+                voice.adsr_volume = 0;
+            }
+        } else {
+            voice.current_address = (voice.current_address + 16) & (RAM_SIZE as u32 - 1);
+        }
+        self.decode_block(i);
+    }
+
+    // Restarts the voice from its start address and clears its ENDX bit
+    fn key_on(&mut self, i: usize) {
+        self.end_flags &= !(1 << i);
+        let voice = &mut self.voices[i];
+        voice.adsr_phase = AdsrPhase::Attack;
+        voice.adsr_volume = 0;
+        voice.adsr_wait = 0;
+        voice.current_address = voice.start_address as u32 * 8;
+        voice.counter = 0;
+        voice.samples = [0; BLOCK_SAMPLES + 3];
+        voice.history = [0; 2];
+        self.decode_block(i);
+    }
+
+    fn key_off(&mut self, i: usize) {
+        let voice = &mut self.voices[i];
+        if voice.adsr_phase != AdsrPhase::Off {
+            voice.adsr_phase = AdsrPhase::Release;
+            voice.adsr_wait = 0;
+        }
+    }
+
+    // Decodes the 16 byte ADPCM block at the voice's current address
+    fn decode_block(&mut self, i: usize) {
+        let addr = self.voices[i].current_address as usize;
+        let block: [u8; 16] = std::array::from_fn(|j| self.ram[(addr + j) & (RAM_SIZE - 1)]);
+        // The block covers two 8 byte IRQ address units
+        self.check_irq(addr as u32);
+        self.check_irq(addr as u32 + 8);
+        let voice = &mut self.voices[i];
+
+        let shift = match block[0] & 0xF {
+            shift @ 0..=12 => shift,
+            _ => 9,
+        };
+        let filter = ((block[0] >> 4) & 0x7).min(4) as usize;
+        voice.flags = block[1];
+        // Loop start marks this block as the repeat address
+        if voice.flags & 0x4 > 0 {
+            voice.repeat_address = (addr / 8) as u16;
+        }
+
+        voice.samples.copy_within(BLOCK_SAMPLES.., 0);
+        for (j, sample) in voice.samples[3..].iter_mut().enumerate() {
+            let nibble = (block[2 + j / 2] >> ((j & 1) * 4)) & 0xF;
+            let raw = ((nibble as i16) << 12) >> shift;
+            let [old, older] = voice.history;
+            let prediction = (old * ADPCM_POS[filter] + older * ADPCM_NEG[filter] + 32) >> 6;
+            let decoded = (raw as i32 + prediction).clamp(-0x8000, 0x7FFF);
+            voice.history = [decoded, old];
+            *sample = decoded as i16;
+        }
+    }
+
+    // Reads the halfword register at the offset from 0x1F801C00
+    pub fn read(&mut self, offset: u32) -> u16 {
+        match offset {
+            0x000..=0x17F => {
+                let voice = &self.voices[offset as usize / 16];
+                match offset & 0xF {
+                    0x0 => voice.volume[0].register,
+                    0x2 => voice.volume[1].register,
+                    0x4 => voice.pitch,
+                    0x6 => voice.start_address,
+                    0x8 => voice.adsr as u16,
+                    0xA => (voice.adsr >> 16) as u16,
+                    0xC => voice.adsr_volume,
+                    _ => voice.repeat_address,
+                }
+            }
+            0x180 => self.main_volume[0].register,
+            0x182 => self.main_volume[1].register,
+            0x184 => self.reverb_volume[0],
+            0x186 => self.reverb_volume[1],
+            0x188 => self.key_on as u16,
+            0x18A => (self.key_on >> 16) as u16,
+            0x18C => self.key_off as u16,
+            0x18E => (self.key_off >> 16) as u16,
+            0x190 => self.pitch_modulation as u16,
+            0x192 => (self.pitch_modulation >> 16) as u16,
+            0x194 => self.noise_mode as u16,
+            0x196 => (self.noise_mode >> 16) as u16,
+            0x198 => self.reverb_mode as u16,
+            0x19A => (self.reverb_mode >> 16) as u16,
+            0x19C => self.end_flags as u16,
+            0x19E => (self.end_flags >> 16) as u16,
+            0x1A2 => self.reverb_base,
+            0x1A4 => self.irq_address,
+            0x1A6 => self.transfer_address,
+            0x1AA => self.control,
+            0x1AC => self.transfer_control,
+            0x1AE => self.status(),
+            0x1B0 => self.cd_volume[0],
+            0x1B2 => self.cd_volume[1],
+            0x1B4 => self.external_volume[0],
+            0x1B6 => self.external_volume[1],
+            // Current main volume
+            0x1B8 => self.main_volume[0].current as u16,
+            0x1BA => self.main_volume[1].current as u16,
+            0x1C0..=0x1FF => self.reverb[(offset as usize - 0x1C0) / 2],
+            // Current left and right volume of each voice
+            0x200..=0x25F => {
+                let voice = &self.voices[(offset as usize - 0x200) / 4];
+                voice.volume[(offset as usize / 2) & 1].current as u16
+            }
+            _ => {
+                event!(
+                    target: "ps1_emulator::SPU",
+                    Level::DEBUG,
+                    "Read from unhandled register {:03X}",
+                    offset
+                );
+                0
+            }
+        }
+    }
+
+    // Writes the halfword register at the offset from 0x1F801C00
+    pub fn write(&mut self, offset: u32, val: u16) {
+        match offset {
+            0x000..=0x17F => {
+                let voice = &mut self.voices[offset as usize / 16];
+                match offset & 0xF {
+                    0x0 => voice.volume[0].write(val),
+                    0x2 => voice.volume[1].write(val),
+                    0x4 => voice.pitch = val,
+                    0x6 => voice.start_address = val,
+                    0x8 => voice.adsr = (voice.adsr & 0xFFFF0000) | val as u32,
+                    0xA => voice.adsr = (voice.adsr & 0xFFFF) | ((val as u32) << 16),
+                    0xC => voice.adsr_volume = val,
+                    _ => voice.repeat_address = val,
+                }
+            }
+            0x180 => self.main_volume[0].write(val),
+            0x182 => self.main_volume[1].write(val),
+            0x184 => self.reverb_volume[0] = val,
+            0x186 => self.reverb_volume[1] = val,
+            0x188 => {
+                self.key_on = set_low(self.key_on, val);
+                self.key_voices(val as u32, true);
+            }
+            0x18A => {
+                self.key_on = set_high(self.key_on, val);
+                self.key_voices((val as u32) << 16, true);
+            }
+            0x18C => {
+                self.key_off = set_low(self.key_off, val);
+                self.key_voices(val as u32, false);
+            }
+            0x18E => {
+                self.key_off = set_high(self.key_off, val);
+                self.key_voices((val as u32) << 16, false);
+            }
+            // Voice 0 cannot be pitch modulated
+            0x190 => self.pitch_modulation = set_low(self.pitch_modulation, val & !1),
+            0x192 => self.pitch_modulation = set_high(self.pitch_modulation, val),
+            0x194 => self.noise_mode = set_low(self.noise_mode, val),
+            0x196 => self.noise_mode = set_high(self.noise_mode, val),
+            0x198 => self.reverb_mode = set_low(self.reverb_mode, val),
+            0x19A => self.reverb_mode = set_high(self.reverb_mode, val),
+            // ENDX is read only
+            0x19C | 0x19E => {}
+            0x1A2 => {
+                self.reverb_base = val;
+                self.reverb_pos = val as u32 * 8;
+            }
+            0x1A4 => self.irq_address = val,
+            0x1A6 => {
+                self.transfer_address = val;
+                self.transfer_pos = val as u32 * 8;
+            }
+            0x1A8 => {
+                if self.fifo.len() < FIFO_SIZE {
+                    self.fifo.push_back(val);
+                }
+            }
+            0x1AA => self.control_write(val),
+            0x1AC => self.transfer_control = val,
+            0x1B0 => self.cd_volume[0] = val,
+            0x1B2 => self.cd_volume[1] = val,
+            0x1B4 => self.external_volume[0] = val,
+            0x1B6 => self.external_volume[1] = val,
+            0x1C0..=0x1FF => self.reverb[(offset as usize - 0x1C0) / 2] = val,
+            // Current voice volumes are read only
+            0x200..=0x25F => {}
+            _ => {
+                event!(
+                    target: "ps1_emulator::SPU",
+                    Level::DEBUG,
+                    "Write to unhandled register {:03X} with {:04X}",
+                    offset,
+                    val
+                );
+            }
+        }
+    }
+
+    // Starts or stops the voices with their bit set
+    fn key_voices(&mut self, bits: u32, on: bool) {
+        for i in (0..24).filter(|i| bits & (1 << i) > 0) {
+            if on {
+                self.key_on(i);
+            } else {
+                self.key_off(i);
+            }
+        }
+    }
+
+    fn control_write(&mut self, val: u16) {
+        self.control = val;
+        // Clearing the IRQ enable acknowledges the IRQ
+        if val & 0x40 == 0 {
+            self.irq_flag = false;
+        }
+
+        // Manual write mode empties the FIFO into sound RAM
+        if (val >> 4) & 0x3 == 1 {
+            while let Some(data) = self.fifo.pop_front() {
+                self.transfer_write(data);
+            }
+        }
+    }
+
+    // DMA channel 4 in SPU DMA write mode. Anything left in the FIFO is written first
+    pub fn dma_write(&mut self, val: u32) {
+        if (self.control >> 4) & 0x3 != 2 {
+            event!(target: "ps1_emulator::SPU", Level::WARN, "DMA write while not in DMA write mode");
+            return;
+        }
+        while let Some(data) = self.fifo.pop_front() {
+            self.transfer_write(data);
+        }
+        self.transfer_write(val as u16);
+        self.transfer_write((val >> 16) as u16);
+    }
+
+    // DMA channel 4 in SPU DMA read mode
+    pub fn dma_read(&mut self) -> u32 {
+        if (self.control >> 4) & 0x3 != 3 {
+            event!(target: "ps1_emulator::SPU", Level::WARN, "DMA read while not in DMA read mode");
+            return 0;
+        }
+        let lo = self.transfer_read() as u32;
+        let hi = self.transfer_read() as u32;
+        lo | (hi << 16)
+    }
+
+    // Sound RAM accesses at the transfer address keep the SPU busy for a few cycles each
+    fn transfer_write(&mut self, data: u16) {
+        self.check_irq(self.transfer_pos);
+        self.write_ram(self.transfer_pos, data);
+        self.transfer_pos = (self.transfer_pos + 2) & (RAM_SIZE as u32 - 1);
+        self.busy_cycles += TRANSFER_CYCLES;
+    }
+
+    fn transfer_read(&mut self) -> u16 {
+        self.check_irq(self.transfer_pos);
+        let addr = self.transfer_pos as usize & (RAM_SIZE - 2);
+        let data = u16::from_le_bytes([self.ram[addr], self.ram[addr + 1]]);
+        self.transfer_pos = (self.transfer_pos + 2) & (RAM_SIZE as u32 - 1);
+        self.busy_cycles += TRANSFER_CYCLES;
+        data
+    }
+
+    // Mode bits mirror SPUCNT
+    fn status(&self) -> u16 {
+        let mut status = self.control & 0x3F;
+        if self.irq_flag {
+            status |= 0x40;
+        }
+        if self.busy_cycles > 0 {
+            status |= 0x400;
+        }
+        match (self.control >> 4) & 0x3 {
+            // DMA write request
+            2 => status |= 0x80 | 0x100,
+            // DMA read request
+            3 => status |= 0x80 | 0x200,
+            _ => {}
+        }
+        // Set while writing to the second half of the capture buffers
+        if self.capture_pos >= 0x200 {
+            status |= 0x800;
+        }
+        status
+    }
+
+    // Returns true once for each IRQ raised since the last call
+    pub fn take_irq(&mut self) -> bool {
+        std::mem::take(&mut self.irq_pending)
+    }
+
+    // Raises the IRQ if enabled in SPUCNT and the access falls in the IRQ address unit
+    fn check_irq(&mut self, addr: u32) {
+        if self.control & 0x40 > 0
+            && !self.irq_flag
+            && (addr & (RAM_SIZE as u32 - 1)) / 8 == self.irq_address as u32
+        {
+            self.irq_flag = true;
+            self.irq_pending = true;
+        }
+    }
+
+    fn write_ram(&mut self, addr: u32, val: u16) {
+        let addr = addr as usize & (RAM_SIZE - 2);
+        self.ram[addr..addr + 2].copy_from_slice(&val.to_le_bytes());
+    }
+}
+
+impl Voice {
+    // Envelope parameters for the current phase: exponential, decreasing, shift and step
+    fn adsr_rate(&self) -> (bool, bool, u32, i32) {
+        let adsr = self.adsr;
+        match self.adsr_phase {
+            AdsrPhase::Attack => (
+                adsr & 0x8000 > 0,
+                false,
+                (adsr >> 10) & 0x1F,
+                7 - ((adsr >> 8) & 0x3) as i32,
+            ),
+            // Decay is always exponential with a step of -8
+            AdsrPhase::Decay => (true, true, (adsr >> 4) & 0xF, -8),
+            AdsrPhase::Sustain => {
+                let decrease = adsr & 0x40000000 > 0;
+                let step = (adsr >> 22) & 0x3;
+                let step = if decrease {
+                    -8 + step as i32
+                } else {
+                    7 - step as i32
+                };
+                (adsr & 0x80000000 > 0, decrease, (adsr >> 24) & 0x1F, step)
+            }
+            AdsrPhase::Release | AdsrPhase::Off => {
+                (adsr & 0x200000 > 0, true, (adsr >> 16) & 0x1F, -8)
+            }
+        }
+    }
+
+    // Steps the envelope once its wait has elapsed, then moves to the next phase when the
+    // target level is reached
+    fn step_envelope(&mut self) {
+        if self.adsr_wait > 1 {
+            self.adsr_wait -= 1;
+            return;
+        }
+
+        let (exponential, decrease, shift, step) = self.adsr_rate();
+        let (level, wait) =
+            envelope_step(self.adsr_volume as i32, exponential, decrease, shift, step);
+        self.adsr_wait = wait;
+        self.adsr_volume = level as u16;
+
+        // Sustain level is (N+1)*0x800
+        let sustain_level = (((self.adsr & 0xF) + 1) * 0x800).min(0x7FFF) as i32;
+        match self.adsr_phase {
+            AdsrPhase::Attack if level == 0x7FFF => self.adsr_phase = AdsrPhase::Decay,
+            AdsrPhase::Decay if level <= sustain_level => self.adsr_phase = AdsrPhase::Sustain,
+            AdsrPhase::Release if level == 0 => self.adsr_phase = AdsrPhase::Off,
+            _ => {}
+        }
+    }
+
+    // 4 point gaussian interpolation around the current sample
+    fn interpolate(&self, gauss: &[i32; 512]) -> i32 {
+        let pos = (self.counter >> 12) as usize;
+        let i = ((self.counter >> 4) & 0xFF) as usize;
+        let [oldest, older, old, new] = [0, 1, 2, 3].map(|j| self.samples[pos + j] as i32);
+        (gauss[0xFF - i] * oldest
+            + gauss[0x1FF - i] * older
+            + gauss[0x100 + i] * old
+            + gauss[i] * new)
+            >> 15
+    }
+}
+
+impl Volume {
+    fn write(&mut self, val: u16) {
+        self.register = val;
+        self.wait = 0;
+        if val & 0x8000 == 0 {
+            self.current = fixed_volume(val) as i16;
+        }
+    }
+
+    // Advances a sweep by one sample and returns the volume to apply
+    fn step(&mut self) -> i16 {
+        let val = self.register;
+        if val & 0x8000 == 0 {
+            return self.current;
+        }
+        if self.wait > 1 {
+            self.wait -= 1;
+            return self.current;
+        }
+
+        // Bit 14 selects exponential, bit 13 decreasing and bit 12 a negative volume
+        let exponential = val & 0x4000 > 0;
+        let decrease = val & 0x2000 > 0;
+        let negative = val & 0x1000 > 0;
+        let shift = ((val >> 2) & 0x1F) as u32;
+        let step = (val & 0x3) as i32;
+        let step = if decrease { -8 + step } else { 7 - step };
+
+        let magnitude = if negative {
+            -(self.current as i32)
+        } else {
+            self.current as i32
+        };
+        let (magnitude, wait) = envelope_step(magnitude.max(0), exponential, decrease, shift, step);
+        self.wait = wait;
+        self.current = if negative { -magnitude } else { magnitude } as i16;
+        self.current
+    }
+}
+
+// Steps an envelope level shared by ADSR and volume sweeps. Returns the new level and the
+// samples to wait before the next step
+fn envelope_step(
+    level: i32,
+    exponential: bool,
+    decrease: bool,
+    shift: u32,
+    step: i32,
+) -> (i32, u32) {
+    let mut wait = 1 << shift.saturating_sub(11);
+    let mut step = step << 11u32.saturating_sub(shift);
+    if exponential && !decrease && level > 0x6000 {
+        wait *= 4;
+    }
+    if exponential && decrease {
+        step = (step * level) >> 15;
+    }
+    ((level + step).clamp(0, 0x7FFF), wait)
+}
+
+// Multiplies by a signed 1.15 fixed point volume
+fn mul(val: i32, volume: i32) -> i32 {
+    (val * volume) >> 15
+}
+
+// Fixed volumes hold half the signed 16 bit volume in bits 0-14
+fn fixed_volume(val: u16) -> i32 {
+    ((val << 1) as i16) as i32
+}
+
+// Interpolation weights for sample offsets of 2 down to 1/256 samples. The curve is a
+// gaussian fitted to the hardware table's peak rather than a copy of the hardware table
+fn gauss_table() -> [i32; 512] {
+    let mut table = [0; 512];
+    for (i, weight) in table.iter_mut().enumerate() {
+        let distance = (512 - i) as f64 / 256.0;
+        *weight = (0x59B3 as f64 * (-1.547 * distance * distance).exp()).round() as i32;
+    }
+    table
+}
+
+fn set_low(reg: u32, val: u16) -> u32 {
+    (reg & 0xFFFF0000) | val as u32
+}
+
+fn set_high(reg: u32, val: u16) -> u32 {
+    (reg & 0xFFFF) | ((val as u32) << 16)
+}
+
+// Sound RAM is stored as a single length prefixed block of bytes
+fn serialize_ram<S: Serializer>(ram: &[u8; RAM_SIZE], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_bytes(ram)
+}
+
+fn deserialize_ram<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Box<[u8; RAM_SIZE]>, D::Error> {
+    let ram = Vec::<u8>::deserialize(deserializer)?;
+    let len = ram.len();
+    ram.into_boxed_slice()
+        .try_into()
+        .map_err(|_| serde::de::Error::invalid_length(len, &"512 KB of sound RAM"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Writes halfwords to sound RAM through the FIFO in manual write mode
+    fn upload(spu: &mut Spu, address: u16, data: &[u16]) {
+        let control = spu.control & !0x30;
+        spu.write(0x1A6, address);
+        for chunk in data.chunks(FIFO_SIZE) {
+            for &halfword in chunk {
+                spu.write(0x1A8, halfword);
+            }
+            spu.write(0x1AA, control | 0x10);
+            spu.write(0x1AA, control);
+        }
+    }
+
+    // Writes bytes to sound RAM at the address in units of 8 bytes
+    fn upload_bytes(spu: &mut Spu, address: u16, bytes: &[u8]) {
+        let halfwords: Vec<u16> = bytes
+            .chunks(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        upload(spu, address, &halfwords);
+    }
+
+    // ADPCM blocks played in a row and the PCM they decode to, worked out by hand from the
+    // documented filters. The first is a loop start, the second jumps back to it and the
+    // third uses filter 4
+    const BLOCKS: [[u8; 16]; 3] = [
+        [
+            0x00, 0x04, 0x83, 0x2D, 0xC7, 0x61, 0x0B, 0xA5, 0x4F, 0xE9, 0x83, 0x2D, 0xC7, 0x61,
+            0x0B, 0xA5,
+        ],
+        [
+            0x23, 0x03, 0x11, 0x48, 0x7F, 0xB6, 0xED, 0x24, 0x5B, 0x92, 0xC9, 0x00, 0x37, 0x6E,
+            0xA5, 0xDC,
+        ],
+        [
+            0x4C, 0x00, 0x80, 0xD9, 0x32, 0x8B, 0xE4, 0x3D, 0x96, 0xEF, 0x48, 0xA1, 0xFA, 0x53,
+            0xAC, 0x05,
+        ],
+    ];
+    const PCM: [[i16; 28]; 3] = [
+        [
+            12288, -32768, -12288, 8192, 28672, -16384, 4096, 24576, -20480, 0, 20480, -24576,
+            -4096, 16384, -28672, -8192, 12288, -32768, -12288, 8192, 28672, -16384, 4096, 24576,
+            -20480, 0, 20480, -24576,
+        ],
+        [
+            -32768, -32768, -32768, -30208, -28168, -22486, -14446, -10248, -8213, -7455, -4675,
+            -1319, -1132, 1598, 4815, 3770, -722, -6408, -10928, -14430, -13466, -10936, -9733,
+            -5531, 530, 2374, 1787, -254,
+        ],
+        [
+            -2159, -3885, -5389, -6634, -7592, -8250, -8614, -8694, -8493, -8041, -7369, -6506,
+            -5488, -4369, -3184, -1976, -790, 351, 1411, 2355, 3160, 3815, 4313, 4650, 4817, 4817,
+            4671, 4388,
+        ],
+    ];
+
+    // Enabled and unmuted at full main volume
+    fn enabled_spu() -> Spu {
+        let mut spu = Spu::new();
+        spu.write(0x1AA, 0xC000);
+        spu.write(0x180, 0x3FFF);
+        spu.write(0x182, 0x3FFF);
+        spu
+    }
+
+    // Generates one output sample
+    fn sample(spu: &mut Spu) -> (i16, i16) {
+        let out = spu.run_cycles(SAMPLE_CYCLES);
+        (out[0], out[1])
+    }
+
+    #[test]
+    fn bios_init_sequence() {
+        let mut spu = Spu::new();
+        // Off, then everything silenced and the voices keyed off
+        spu.write(0x1AA, 0x0000);
+        assert_eq!(spu.read(0x1AE), 0x0000);
+        for (offset, val) in [
+            (0x180, 0x0000),
+            (0x182, 0x0000),
+            (0x184, 0x0000),
+            (0x186, 0x0000),
+            (0x18C, 0xFFFF),
+            (0x18E, 0x00FF),
+            (0x190, 0x0000),
+            (0x192, 0x0000),
+            (0x194, 0x0000),
+            (0x196, 0x0000),
+            (0x198, 0x0000),
+            (0x19A, 0x0000),
+            (0x1AC, 0x0004),
+            (0x1B0, 0x0000),
+            (0x1B2, 0x0000),
+            (0x1B4, 0x0000),
+            (0x1B6, 0x0000),
+        ] {
+            spu.write(offset, val);
+            assert_eq!(spu.read(offset), val, "register {offset:03X}");
+        }
+
+        // Enabled and unmuted with CD audio on. SPUSTAT mirrors the low bits
+        spu.write(0x1AA, 0xC001);
+        assert_eq!(spu.read(0x1AA), 0xC001);
+        assert_eq!(spu.read(0x1AE), 0x0001);
+
+        // Voice registers read back as written
+        for (offset, val) in [
+            (0x10, 0x3FFF),
+            (0x12, 0x1234),
+            (0x14, 0x1000),
+            (0x16, 0x0200),
+        ] {
+            spu.write(offset, val);
+            assert_eq!(spu.read(offset), val);
+        }
+        spu.write(0x18, 0x80FF);
+        spu.write(0x1A, 0x1FC0);
+        assert_eq!(spu.voices[1].adsr, 0x1FC0_80FF);
+
+        // Voice 0 can't be pitch modulated and ENDX is read only
+        spu.write(0x190, 0xFFFF);
+        assert_eq!(spu.read(0x190), 0xFFFE);
+        spu.write(0x19C, 0xFFFF);
+        assert_eq!(spu.read(0x19C), 0x0000);
+    }
+
+    #[test]
+    fn manual_upload_reads_back() {
+        let mut spu = Spu::new();
+        spu.write(0x1AA, 0xC000);
+        let data: Vec<u16> = (0..80).map(|i| i * 0x0301 + 7).collect();
+        upload(&mut spu, 0x200, &data);
+        for (i, &halfword) in data.iter().enumerate() {
+            let addr = 0x1000 + i * 2;
+            assert_eq!(spu.ram[addr..addr + 2], halfword.to_le_bytes());
+        }
+
+        // SPUSTAT reports busy while the halfwords are written
+        assert_eq!(spu.read(0x1AE) & 0x400, 0x400);
+        spu.run_cycles(80 * TRANSFER_CYCLES - 1);
+        assert_eq!(spu.read(0x1AE) & 0x400, 0x400);
+        spu.run_cycles(1);
+        assert_eq!(spu.read(0x1AE) & 0x400, 0);
+
+        spu.write(0x1A6, 0x200);
+        spu.write(0x1AA, 0xC030);
+        let words: Vec<u32> = (0..40).map(|_| spu.dma_read()).collect();
+        let halfwords: Vec<u16> = words
+            .iter()
+            .flat_map(|word| [*word as u16, (word >> 16) as u16])
+            .collect();
+        assert_eq!(halfwords, data);
+    }
+
+    #[test]
+    fn adpcm_blocks_decode_to_the_reference_pcm() {
+        let mut spu = enabled_spu();
+        upload_bytes(&mut spu, 0x200, BLOCKS.as_flattened());
+        spu.write(0x06, 0x200);
+        spu.write(0x188, 0x1);
+        for (i, pcm) in PCM.iter().enumerate() {
+            if i > 0 {
+                spu.voices[0].current_address += 16;
+                spu.decode_block(0);
+            }
+            assert_eq!(spu.voices[0].samples[3..], *pcm, "block {i}");
+        }
+    }
+
+    #[test]
+    fn voice_loops_back_to_the_loop_start() {
+        let mut spu = enabled_spu();
+        upload_bytes(&mut spu, 0x200, BLOCKS.as_flattened());
+        // One sample per output sample at full volume
+        spu.write(0x00, 0x3FFF);
+        spu.write(0x02, 0x3FFF);
+        spu.write(0x04, 0x1000);
+        spu.write(0x06, 0x200);
+        spu.write(0x188, 0x1);
+
+        for _ in 0..28 {
+            sample(&mut spu);
+        }
+        assert_eq!(spu.voices[0].current_address, 0x1010);
+        assert_eq!(spu.read(0x19C), 0);
+        // The second block ends with loop repeat, back to the first
+        for _ in 0..28 {
+            sample(&mut spu);
+        }
+        assert_eq!(spu.voices[0].current_address, 0x1000);
+        assert_eq!(spu.voices[0].samples[3..], PCM[0]);
+        assert_eq!(spu.read(0x19C), 0x1);
+        assert_ne!(spu.voices[0].adsr_phase, AdsrPhase::Off);
+
+        // Output keeps coming in stereo pairs, silent while the SPU is muted
+        assert_eq!(spu.run_cycles(SAMPLE_CYCLES * 10).len(), 20);
+        spu.write(0x1AA, 0x8000);
+        assert!(spu.run_cycles(SAMPLE_CYCLES * 10).iter().all(|&s| s == 0));
+    }
+
+    // Envelope level after each sample from key on
+    fn envelope(spu: &mut Spu, samples: usize) -> Vec<u16> {
+        (0..samples)
+            .map(|_| {
+                sample(spu);
+                spu.read(0x0C)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn envelope_steps_through_attack_decay_sustain_and_release() {
+        let mut spu = enabled_spu();
+        spu.write(0x04, 0x1000);
+        // Fastest linear attack and exponential decay to a sustain level of 0x2000, then a
+        // slow linear sustain increase of 7 every 16 samples and the fastest release
+        spu.write(0x08, 0x0003);
+        spu.write(0x0A, 0x0F00);
+        spu.write(0x188, 0x1);
+
+        let levels = envelope(&mut spu, 22);
+        assert_eq!(
+            levels[..6],
+            [0x3800, 0x7000, 0x7FFF, 0x3FFF, 0x1FFF, 0x2006]
+        );
+        assert!(levels[6..21].iter().all(|&level| level == 0x2006));
+        assert_eq!(levels[21], 0x200D);
+        assert_eq!(spu.voices[0].adsr_phase, AdsrPhase::Sustain);
+
+        spu.write(0x18C, 0x1);
+        assert_eq!(envelope(&mut spu, 1), [0]);
+        assert_eq!(spu.voices[0].adsr_phase, AdsrPhase::Off);
+    }
+
+    #[test]
+    fn exponential_attack_slows_above_0x6000() {
+        let mut spu = enabled_spu();
+        spu.write(0x04, 0x1000);
+        // Exponential attack with a shift of 4, adding 896 a sample
+        spu.write(0x08, 0x9000);
+        spu.write(0x188, 0x1);
+
+        let levels = envelope(&mut spu, 33);
+        assert_eq!(levels[27], 25088);
+        assert_eq!(levels[28..32], [25984; 4]);
+        assert_eq!(levels[32], 26880);
+    }
+
+    #[test]
+    fn end_flag_sets_endx_until_key_on() {
+        let mut spu = enabled_spu();
+        // A single block ending without loop repeat
+        let mut block = BLOCKS[0];
+        block[1] = 0x01;
+        upload_bytes(&mut spu, 0x200, &block);
+        for voice in [5, 20] {
+            spu.write(voice * 0x10 + 0x04, 0x1000);
+            spu.write(voice * 0x10 + 0x06, 0x200);
+        }
+        spu.write(0x188, 1 << 5);
+        spu.write(0x18A, 1 << 4);
+        assert_eq!(spu.read(0x188), 1 << 5);
+        assert_eq!(spu.read(0x18A), 1 << 4);
+        // The start address is latched at key on
+        spu.write(0x56, 0x300);
+
+        for _ in 0..27 {
+            sample(&mut spu);
+        }
+        assert_eq!((spu.read(0x19C), spu.read(0x19E)), (0, 0));
+        sample(&mut spu);
+        assert_eq!((spu.read(0x19C), spu.read(0x19E)), (1 << 5, 1 << 4));
+        assert_eq!(spu.voices[5].adsr_phase, AdsrPhase::Off);
+        assert_eq!(spu.read(0x5C), 0);
+
+        // Key on clears ENDX and restarts from the new start address
+        spu.write(0x188, 1 << 5);
+        assert_eq!((spu.read(0x19C), spu.read(0x19E)), (0, 1 << 4));
+        assert_eq!(spu.voices[5].current_address, 0x1800);
+        // Key off reads back too and writing 0 to either changes nothing
+        spu.write(0x18E, 1 << 4);
+        spu.write(0x188, 0);
+        assert_eq!(spu.read(0x18E), 1 << 4);
+        assert_eq!(spu.voices[5].adsr_phase, AdsrPhase::Attack);
+    }
+
+    #[test]
+    fn reverb_reflections_arrive_at_their_delays() {
+        let mut spu = enabled_spu();
+        // CD audio on, sent to reverb with reverb writes enabled
+        spu.write(0x1AA, 0xC085);
+        spu.write(0x1B0, 0x7FFF);
+        spu.write(0x184, 0x7FFF);
+        // Buffer in the top 8KB of sound RAM
+        spu.write(0x1A2, 0xFC00);
+        // Left input goes through the IIR filter into mSAME at 0x100. Two combs read it 10
+        // and 20 units behind and the all pass filters only delay by 2 and 3 units. A unit
+        // is 4 reverb steps of two samples each
+        let preset = [
+            (D_APF1, 2),
+            (D_APF2, 3),
+            (V_IIR, 0x7FFF),
+            (V_COMB1, 0x4000),
+            (V_COMB1 + 1, 0x2000),
+            (M_SAME, 0x100),
+            (M_COMB1, 0xF6),
+            (M_COMB2, 0xEC),
+            (M_APF1, 0x200),
+            (M_APF2, 0x300),
+            (V_IN, 0x7FFF),
+        ];
+        for (reg, val) in preset {
+            spu.write(0x1C0 + reg as u32 * 2, val);
+        }
+
+        spu.cd_input.push_back((0x4000, 0));
+        let left: Vec<i16> = (0..300).map(|_| sample(&mut spu).0).collect();
+        let heard: Vec<_> = left
+            .iter()
+            .enumerate()
+            .filter(|(_, sample)| **sample != 0)
+            .map(|(i, _)| i)
+            .collect();
+        // The dry impulse, then each reflection held for two samples
+        let first = 2 * 4 * (10 + 2 + 3);
+        let second = 2 * 4 * (20 + 2 + 3);
+        assert_eq!(heard, [0, first, first + 1, second, second + 1]);
+        assert!(left[first] > left[second] && left[second] > 0);
+    }
+
+    #[test]
+    fn noise_follows_the_documented_sequence() {
+        let mut spu = enabled_spu();
+        // Fastest clock, a new bit every sample
+        spu.write(0x1AA, 0xFF00);
+        let levels: Vec<u16> = (0..16)
+            .map(|_| {
+                sample(&mut spu);
+                spu.noise_level
+            })
+            .collect();
+        assert_eq!(
+            levels,
+            [
+                0x0001, 0x0003, 0x0007, 0x000F, 0x001F, 0x003F, 0x007F, 0x00FF, 0x01FF, 0x03FF,
+                0x07FF, 0x0FFE, 0x1FFD, 0x3FFA, 0x7FF4, 0xFFE8,
+            ]
+        );
+
+        // Half as fast with a shift of 14 and the smallest step
+        let mut spu = enabled_spu();
+        spu.write(0x1AA, 0xF800);
+        let levels: Vec<u16> = (0..8)
+            .map(|_| {
+                sample(&mut spu);
+                spu.noise_level
+            })
+            .collect();
+        assert_eq!(levels, [0x1, 0x1, 0x3, 0x3, 0x7, 0x7, 0xF, 0xF]);
+    }
+
+    #[test]
+    fn noise_mode_switches_the_source_but_not_the_envelope() {
+        let play = |toggle: bool| {
+            let mut spu = enabled_spu();
+            spu.write(0x1AA, 0xFF00);
+            upload_bytes(&mut spu, 0x200, BLOCKS.as_flattened());
+            spu.write(0x04, 0x1000);
+            spu.write(0x06, 0x200);
+            spu.write(0x08, 0x1F0F);
+            spu.write(0x188, 0x1);
+            let mut levels = Vec::new();
+            for i in 0..30 {
+                if toggle && (i == 10 || i == 20) {
+                    spu.write(0x194, (i == 10) as u16);
+                }
+                let level = spu.voices[0].adsr_volume as i32;
+                sample(&mut spu);
+                if toggle && (10..20).contains(&i) {
+                    // The noise level of this sample times the envelope
+                    let noise = spu.noise_level as i16 as i32;
+                    assert_eq!(spu.voices[0].output as i32, (noise * level) >> 15);
+                }
+                levels.push(spu.voices[0].adsr_volume);
+            }
+            levels
+        };
+        assert_eq!(play(true), play(false));
+    }
+
+    // Sign changes of voice 1 in each window of output samples
+    fn crossings(outputs: &[i16], windows: &[std::ops::Range<usize>]) -> Vec<usize> {
+        windows
+            .iter()
+            .map(|window| {
+                outputs[window.clone()]
+                    .windows(2)
+                    .filter(|pair| (pair[0] < 0) != (pair[1] < 0))
+                    .count()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pitch_modulation_follows_the_previous_voice() {
+        let play = |modulate: bool| {
+            let mut spu = enabled_spu();
+            // A square tone with a period of 28 samples, then a slow square of a positive
+            // and a negative block
+            let mut blocks = [[0; 16]; 3];
+            blocks[0] = [
+                0x00, 0x07, 0x44, 0x44, 0x44, 0x44, 0x44, 0x44, 0x44, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC,
+                0xCC, 0xCC,
+            ];
+            blocks[1][1] = 0x04;
+            blocks[1][2..].fill(0x44);
+            blocks[2][1] = 0x03;
+            blocks[2][2..].fill(0xCC);
+            upload_bytes(&mut spu, 0x200, blocks.as_flattened());
+            for (voice, pitch, start) in [(0, 0x0100, 0x202), (1, 0x1000, 0x200)] {
+                spu.write(voice * 0x10 + 0x04, pitch);
+                spu.write(voice * 0x10 + 0x06, start);
+                // Full volume in a few samples and held there
+                spu.write(voice * 0x10 + 0x08, 0x00FF);
+                spu.write(voice * 0x10 + 0x0A, 0x1F00);
+            }
+            spu.write(0x190, if modulate { 0x2 } else { 0 });
+            spu.write(0x188, 0x3);
+            (0..1000)
+                .map(|_| {
+                    sample(&mut spu);
+                    spu.voices[1].output
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Voice 0 is positive in the first window and negative in the second. A half period
+        // of 14 samples crosses 25 times in 350 samples, at 1.5 times the pitch 37 times and
+        // at half the pitch 13 times
+        let windows = [50..400, 500..850];
+        assert_eq!(crossings(&play(false), &windows), [25, 25]);
+        assert_eq!(crossings(&play(true), &windows), [37, 13]);
+    }
+
+    #[test]
+    fn irq_fires_each_time_the_loop_reaches_its_address() {
+        let mut spu = enabled_spu();
+        upload_bytes(&mut spu, 0x200, BLOCKS[..2].as_flattened());
+        spu.write(0x04, 0x1000);
+        spu.write(0x06, 0x200);
+        // The second block of the loop
+        spu.write(0x1A4, 0x202);
+        spu.write(0x1AA, 0xC040);
+        spu.write(0x188, 0x1);
+
+        let mut irqs = Vec::new();
+        for i in 0..300 {
+            sample(&mut spu);
+            if spu.take_irq() {
+                assert_eq!(spu.read(0x1AE) & 0x40, 0x40);
+                irqs.push(i);
+                // Acknowledged by clearing the enable bit
+                spu.write(0x1AA, 0xC000);
+                assert_eq!(spu.read(0x1AE) & 0x40, 0);
+                spu.write(0x1AA, 0xC040);
+            }
+        }
+        // The block is reached after the first 28 samples and then once per loop
+        assert_eq!(irqs, [27, 83, 139, 195, 251]);
+    }
+
+    #[test]
+    fn irq_fires_on_transfers() {
+        let mut spu = enabled_spu();
+        spu.write(0x1A4, 0x403);
+        spu.write(0x1AA, 0xC040);
+        upload(&mut spu, 0x400, &[0; 12]);
+        assert!(!spu.take_irq());
+        upload(&mut spu, 0x400, &[0; 13]);
+        assert!(spu.take_irq());
+        assert_eq!(spu.read(0x1AE) & 0x40, 0x40);
+    }
+
+    #[test]
+    fn main_volume_sweeps_follow_their_rate() {
+        let mut spu = enabled_spu();
+        // Linear increase from silence with shift 8, adding 7 << 3 every sample
+        spu.write(0x180, 0);
+        spu.write(0x180, 0x8000 | 8 << 2);
+        let levels: Vec<u16> = (0..10)
+            .map(|_| {
+                sample(&mut spu);
+                spu.read(0x1B8)
+            })
+            .collect();
+        assert_eq!(levels, [56, 112, 168, 224, 280, 336, 392, 448, 504, 560]);
+        // The register reads back as written and the other side is left alone
+        assert_eq!(spu.read(0x180), 0x8000 | 8 << 2);
+        assert_eq!(spu.read(0x1BA), 0x7FFE);
+
+        // Shift 13 steps by 7 every fourth sample
+        spu.write(0x180, 0);
+        spu.write(0x180, 0x8000 | 13 << 2);
+        let levels: Vec<u16> = (0..9)
+            .map(|_| {
+                sample(&mut spu);
+                spu.read(0x1B8)
+            })
+            .collect();
+        assert_eq!(levels, [7, 7, 7, 7, 14, 14, 14, 14, 21]);
+
+        // Exponential decrease from full volume slows as the volume falls and reaches silence
+        spu.write(0x182, 0x3FFF);
+        spu.write(0x182, 0xE000 | 8 << 2);
+        let mut levels = vec![0x7FFE];
+        while levels[levels.len() - 1] > 0 {
+            sample(&mut spu);
+            levels.push(spu.read(0x1BA));
+            assert!(levels.len() < 10000);
+        }
+        assert_eq!(levels[1..4], [32702, 32638, 32574]);
+        let drops: Vec<u16> = levels.windows(2).map(|pair| pair[0] - pair[1]).collect();
+        assert!(drops.windows(2).all(|pair| pair[1] <= pair[0]));
+    }
+
+    #[test]
+    fn cd_volume_only_scales_the_cd_input() {
+        let play = |cd_volume: u16, cd: bool, voice: bool| {
+            let mut spu = enabled_spu();
+            spu.write(0x1AA, 0xC001);
+            spu.write(0x1B0, cd_volume);
+            spu.write(0x1B2, cd_volume / 2);
+            if voice {
+                upload_bytes(&mut spu, 0x200, BLOCKS[..2].as_flattened());
+                spu.write(0x00, 0x3FFF);
+                spu.write(0x02, 0x3FFF);
+                spu.write(0x04, 0x1000);
+                spu.write(0x06, 0x200);
+                spu.write(0x08, 0x00FF);
+                spu.write(0x0A, 0x1F00);
+                spu.write(0x188, 0x1);
+            }
+            (0..100)
+                .map(|_| {
+                    if cd {
+                        spu.cd_input.push_back((0x4000, -0x4000));
+                    }
+                    sample(&mut spu)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Half volume on the left and a quarter on the right, then the main volume
+        assert!(
+            play(0x4000, true, false)
+                .iter()
+                .all(|&out| out == (8191, -4096))
+        );
+        // Muted CD input leaves the voice as it is, and the CD volume does nothing to it
+        let voice = play(0x4000, false, true);
+        assert!(voice.iter().any(|&out| out != (0, 0)));
+        assert_eq!(play(0, true, true), voice);
+        assert_eq!(play(0x7FFF, false, true), voice);
+    }
+
+    #[test]
+    fn capture_buffers_record_the_cd_and_voice_1() {
+        let mut spu = enabled_spu();
+        upload_bytes(&mut spu, 0x200, BLOCKS[..2].as_flattened());
+        spu.write(0x14, 0x1000);
+        spu.write(0x16, 0x200);
+        spu.write(0x18, 0x00FF);
+        spu.write(0x1A, 0x1F00);
+        spu.write(0x1B0, 0x4000);
+        spu.write(0x1B2, 0x4000);
+        // Voice 1 reaches the fifth unit of its buffer on sample 20
+        spu.write(0x1A4, 0x105);
+        spu.write(0x1AA, 0xC041);
+        spu.write(0x188, 0x2);
+
+        let mut voice = Vec::new();
+        let mut irq = None;
+        for i in 0..300 {
+            spu.cd_input.push_back((i * 10, -i * 10));
+            sample(&mut spu);
+            voice.push(spu.voices[1].output);
+            if spu.take_irq() && irq.is_none() {
+                irq = Some(i);
+            }
+        }
+        assert_eq!(irq, Some(20));
+        assert!(voice.iter().any(|&sample| sample != 0));
+        // The second half of the buffers is being written
+        assert_eq!(spu.read(0x1AE) & 0x800, 0x800);
+
+        // Read the four buffers back by DMA
+        spu.write(0x1AA, 0xC031);
+        spu.write(0x1A6, 0);
+        let mut captured = Vec::new();
+        for _ in 0..0x400 {
+            let word = spu.dma_read();
+            captured.extend([word as i16, (word >> 16) as i16]);
+        }
+        let buffers: Vec<&[i16]> = captured
+            .chunks(0x200)
+            .map(|buffer| &buffer[..300])
+            .collect();
+        let cd: Vec<i16> = (0..300).map(|i| i * 5).collect();
+        assert_eq!(buffers[0], cd);
+        assert_eq!(
+            buffers[1],
+            cd.iter().map(|sample| -sample).collect::<Vec<_>>()
+        );
+        assert_eq!(buffers[2], voice);
+        assert!(buffers[3].iter().all(|&sample| sample == 0));
+    }
+
+    #[test]
+    fn saved_state_continues_with_the_same_samples() {
+        let mut spu = enabled_spu();
+        upload_bytes(&mut spu, 0x200, BLOCKS.as_flattened());
+        // Voice 0 loops, voice 1 plays on to the filter 4 block modulated by voice 0, voice
+        // 2 is noise and every voice goes through reverb
+        for (voice, pitch, start) in [(0, 0x0C00, 0x200), (1, 0x0E00, 0x202), (2, 0x1000, 0x200)] {
+            spu.write(voice * 0x10, 0x3000);
+            spu.write(voice * 0x10 + 0x02, 0x8000 | 10 << 2);
+            spu.write(voice * 0x10 + 0x04, pitch);
+            spu.write(voice * 0x10 + 0x06, start);
+            spu.write(voice * 0x10 + 0x08, 0x30F5);
+            spu.write(voice * 0x10 + 0x0A, 0x1F8A);
+        }
+        spu.write(0x190, 0x2);
+        spu.write(0x194, 0x4);
+        spu.write(0x198, 0x7);
+        spu.write(0x1AA, 0xC081);
+        spu.write(0x184, 0x3000);
+        spu.write(0x186, 0x3000);
+        spu.write(0x1A2, 0xF000);
+        for (reg, val) in [
+            (D_APF1, 0x20),
+            (D_APF2, 0x10),
+            (V_IIR, 0x6000),
+            (V_COMB1, 0x5000),
+            (M_SAME, 0x300),
+            (M_COMB1, 0x200),
+            (M_APF1, 0x180),
+            (M_APF2, 0x100),
+            (V_IN, 0x6000),
+        ] {
+            spu.write(0x1C0 + reg as u32 * 2, val);
+        }
+        spu.write(0x188, 0x7);
+        for _ in 0..500 {
+            spu.cd_input.push_back((0x1000, -0x1000));
+            sample(&mut spu);
+        }
+        // Key off part way through the release
+        spu.write(0x18C, 0x1);
+        for _ in 0..20 {
+            sample(&mut spu);
+        }
+
+        let state = bincode::serialize(&spu).unwrap();
+        // Sound RAM is stored once as a block
+        assert!(state.len() < RAM_SIZE + 0x1000);
+        let mut loaded: Spu = bincode::deserialize(&state).unwrap();
+        let original: Vec<_> = (0..1000).map(|_| sample(&mut spu)).collect();
+        let restored: Vec<_> = (0..1000).map(|_| sample(&mut loaded)).collect();
+        assert!(original.iter().any(|&out| out != (0, 0)));
+        assert_eq!(original, restored);
+        assert_eq!(spu.ram, loaded.ram);
+    }
+}
@@ -0,0 +1,429 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bincode::Options;
+use serde::de::{self, Deserialize, DeserializeOwned, Deserializer, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeTuple, Serializer};
+
+// Save states start with these bytes, the format version and a CRC of everything after it.
+// The StateHeader follows, then from version 4 on a length prefixed PNG thumbnail, which is
+// empty when there is none, then the machine
+const MAGIC: &[u8; 8] = b"PS1STATE";
+// Bumped whenever the layout of any saved struct changes
+pub const VERSION: u32 = 4;
+// States from this version on are brought up to date by MIGRATIONS, older ones are refused
+const OLDEST_VERSION: u32 = 3;
+const CRC_OFFSET: usize = MAGIC.len() + 4;
+const HEADER_SIZE: usize = CRC_OFFSET + 4;
+// First version with a thumbnail chunk
+const THUMBNAIL_VERSION: u32 = 4;
+
+// Upgrades a payload from each version to the next, starting at OLDEST_VERSION. A field added
+// in a new version is filled in by the step before it, so older states keep loading
+type Migration = fn(&[u8]) -> Result<Vec<u8>, StateError>;
+const MIGRATIONS: [Migration; (VERSION - OLDEST_VERSION) as usize] = [
+    // Version 4 only added the thumbnail chunk, which comes before the machine
+    |payload| Ok(payload.to_vec()),
+];
+
+#[derive(Debug)]
+pub enum StateError {
+    NotAState,
+    // Written by a version of the emulator too old to be migrated
+    VersionTooOld(u32),
+    // Written by a newer version of the emulator
+    VersionTooNew(u32),
+    // The file was cut short or damaged
+    BadChecksum,
+    WrongBios,
+    // Saved with a different disc inserted, or with one inserted or not when the other is
+    WrongDisc,
+    Corrupt(String),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::NotAState => write!(f, "not a save state"),
+            StateError::VersionTooOld(version) => write!(
+                f,
+                "save state is version {version}, older than this build can read"
+            ),
+            StateError::VersionTooNew(version) => write!(
+                f,
+                "save state is version {version}, made by a newer build that writes version \
+                 {VERSION}"
+            ),
+            StateError::BadChecksum => write!(f, "save state is damaged or incomplete"),
+            StateError::WrongBios => write!(f, "save state was made with a different BIOS"),
+            StateError::WrongDisc => write!(f, "save state was made with a different disc"),
+            StateError::Corrupt(err) => write!(f, "save state is corrupt: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+// The BIOS and the start of the disc, which come from the host instead of the state
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq)]
+pub struct HostHashes {
+    // SHA-1 of the whole image
+    pub bios: [u8; 20],
+    pub disc: Option<u32>,
+}
+
+impl HostHashes {
+    // Whether a state saved with these can be loaded into a machine with the current ones
+    pub fn check(&self, current: &HostHashes) -> Result<(), StateError> {
+        if self.bios != current.bios {
+            Err(StateError::WrongBios)
+        } else if self.disc != current.disc {
+            Err(StateError::WrongDisc)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+// Follows the CRC. Kept the same in every version so any state can be described
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct StateHeader {
+    pub emulator_version: String,
+    pub hashes: HostHashes,
+    // Seconds since the Unix epoch
+    pub created: u64,
+}
+
+impl StateHeader {
+    pub fn new(hashes: HostHashes) -> Self {
+        Self {
+            emulator_version: env!("CARGO_PKG_VERSION").to_string(),
+            hashes,
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        }
+    }
+}
+
+// The CRC is left blank until seal is called once the machine is written after the header
+pub fn write_header(out: &mut Vec<u8>, header: &StateHeader, thumbnail: Option<&[u8]>) {
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    out.extend_from_slice(&[0; 4]);
+    encode(header, out);
+    let thumbnail = thumbnail.unwrap_or_default();
+    out.extend_from_slice(&(thumbnail.len() as u32).to_le_bytes());
+    out.extend_from_slice(thumbnail);
+}
+
+pub fn seal(state: &mut [u8]) {
+    let mut crc = flate2::Crc::new();
+    crc.update(&state[HEADER_SIZE..]);
+    state[CRC_OFFSET..HEADER_SIZE].copy_from_slice(&crc.sum().to_le_bytes());
+}
+
+// Checks the header and returns it with the serialized machine after it, upgraded to this
+// version's layout
+pub fn read_header(state: &[u8]) -> Result<(StateHeader, Cow<'_, [u8]>), StateError> {
+    let version = version(state)?;
+    let mut crc = flate2::Crc::new();
+    crc.update(&state[HEADER_SIZE..]);
+    if crc.sum() != word(state, CRC_OFFSET) {
+        return Err(StateError::BadChecksum);
+    }
+
+    let Parts {
+        header, payload, ..
+    } = split(state, version)?;
+    let mut payload = Cow::Borrowed(payload);
+    for migrate in &MIGRATIONS[(version - OLDEST_VERSION) as usize..] {
+        payload = Cow::Owned(migrate(&payload)?);
+    }
+    Ok((header, payload))
+}
+
+// PNG of the picture when the state was saved, if it has one. The CRC isn't checked, so slots
+// can be listed without going over the whole machine
+pub fn thumbnail(state: &[u8]) -> Option<&[u8]> {
+    let version = version(state).ok()?;
+    split(state, version).ok()?.thumbnail
+}
+
+fn word(state: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(state[offset..offset + 4].try_into().unwrap())
+}
+
+// Version of a state this build can read
+fn version(state: &[u8]) -> Result<u32, StateError> {
+    if state.len() < HEADER_SIZE || !state.starts_with(MAGIC) {
+        return Err(StateError::NotAState);
+    }
+    let version = word(state, MAGIC.len());
+    if version > VERSION {
+        return Err(StateError::VersionTooNew(version));
+    }
+    if version < OLDEST_VERSION {
+        return Err(StateError::VersionTooOld(version));
+    }
+    Ok(version)
+}
+
+// What follows the CRC
+struct Parts<'a> {
+    header: StateHeader,
+    thumbnail: Option<&'a [u8]>,
+    // The serialized machine, in the layout of the state's version
+    payload: &'a [u8],
+}
+
+fn split(state: &[u8], version: u32) -> Result<Parts<'_>, StateError> {
+    let mut rest = &state[HEADER_SIZE..];
+    let header = options()
+        .deserialize_from(&mut rest)
+        .map_err(|err| StateError::Corrupt(err.to_string()))?;
+    if version < THUMBNAIL_VERSION {
+        return Ok(Parts {
+            header,
+            thumbnail: None,
+            payload: rest,
+        });
+    }
+    let cut_short = || StateError::Corrupt("thumbnail is cut short".to_string());
+    let len = rest.get(..4).ok_or_else(cut_short)?;
+    let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+    let thumbnail = rest.get(4..4 + len).ok_or_else(cut_short)?;
+    Ok(Parts {
+        header,
+        thumbnail: (len > 0).then_some(thumbnail),
+        payload: &rest[4 + len..],
+    })
+}
+
+// Corrupt length prefixes would otherwise allocate without bound
+const MAX_STATE_SIZE: u64 = 64 * 1024 * 1024;
+
+fn options() -> impl bincode::Options {
+    bincode::DefaultOptions::new().with_limit(MAX_STATE_SIZE)
+}
+
+pub fn encode<T: Serialize>(value: &T, out: &mut Vec<u8>) {
+    options()
+        .serialize_into(out, value)
+        .expect("machine state serializes");
+}
+
+pub fn decode<T: DeserializeOwned>(payload: &[u8]) -> Result<T, StateError> {
+    options()
+        .deserialize(payload)
+        .map_err(|err| StateError::Corrupt(err.to_string()))
+}
+
+// Byte arrays too large for serde's derives, such as RAM, stored as one length prefixed block
+pub mod bytes {
+    use super::*;
+    use std::borrow::Borrow;
+
+    // Takes boxed arrays as well as arrays
+    pub fn serialize<S: Serializer, B: Borrow<[u8; N]>, const N: usize>(
+        bytes: &B,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(bytes.borrow())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, T: TryFrom<Vec<u8>>>(
+        deserializer: D,
+    ) -> Result<T, D::Error> {
+        let bytes = deserializer.deserialize_byte_buf(BytesVisitor)?;
+        let len = bytes.len();
+        bytes
+            .try_into()
+            .map_err(|_| de::Error::invalid_length(len, &"a block of the saved size"))
+    }
+
+    struct BytesVisitor;
+
+    impl<'de> Visitor<'de> for BytesVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a block of bytes")
+        }
+
+        fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Vec<u8>, E> {
+            Ok(bytes.to_vec())
+        }
+
+        fn visit_byte_buf<E: de::Error>(self, bytes: Vec<u8>) -> Result<Vec<u8>, E> {
+            Ok(bytes)
+        }
+    }
+}
+
+// Other arrays longer than the 32 elements serde's derives handle
+pub mod array {
+    use super::*;
+    use std::marker::PhantomData;
+
+    pub fn serialize<S: Serializer, T: Serialize, const N: usize>(
+        array: &[T; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut tuple = serializer.serialize_tuple(N)?;
+        for element in array {
+            tuple.serialize_element(element)?;
+        }
+        tuple.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, T: Deserialize<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<[T; N], D::Error> {
+        deserializer.deserialize_tuple(N, ArrayVisitor(PhantomData))
+    }
+
+    struct ArrayVisitor<T, const N: usize>(PhantomData<T>);
+
+    impl<'de, T: Deserialize<'de>, const N: usize> Visitor<'de> for ArrayVisitor<T, N> {
+        type Value = [T; N];
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "an array of {N} elements")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<[T; N], A::Error> {
+            let mut elements = Vec::with_capacity(N);
+            while let Some(element) = seq.next_element()? {
+                elements.push(element);
+            }
+            let len = elements.len();
+            elements
+                .try_into()
+                .map_err(|_| de::Error::invalid_length(len, &self))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASHES: HostHashes = HostHashes {
+        bios: [7; 20],
+        disc: Some(0x1234),
+    };
+
+    // Sealed state holding a stand-in for the machine
+    fn state(thumbnail: Option<&[u8]>) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_header(&mut out, &StateHeader::new(HASHES), thumbnail);
+        encode(&(0..1000u32).collect::<Vec<_>>(), &mut out);
+        seal(&mut out);
+        out
+    }
+
+    fn set_version(state: &mut [u8], version: u32) {
+        state[MAGIC.len()..CRC_OFFSET].copy_from_slice(&version.to_le_bytes());
+    }
+
+    #[test]
+    fn round_trip() {
+        let saved = state(Some(b"png"));
+        let (header, payload) = read_header(&saved).unwrap();
+        assert!(header.hashes == HASHES);
+        assert_eq!(header.emulator_version, env!("CARGO_PKG_VERSION"));
+        let machine: Vec<u32> = decode(&payload).unwrap();
+        assert_eq!(machine, (0..1000).collect::<Vec<_>>());
+        assert_eq!(thumbnail(&saved), Some(&b"png"[..]));
+        assert_eq!(thumbnail(&state(None)), None);
+    }
+
+    #[test]
+    fn version_without_a_thumbnail_is_migrated() {
+        let mut old = Vec::new();
+        old.extend_from_slice(MAGIC);
+        old.extend_from_slice(&OLDEST_VERSION.to_le_bytes());
+        old.extend_from_slice(&[0; 4]);
+        encode(&StateHeader::new(HASHES), &mut old);
+        encode(&vec![1u32, 2, 3], &mut old);
+        seal(&mut old);
+        let (_, payload) = read_header(&old).unwrap();
+        assert_eq!(decode::<Vec<u32>>(&payload).unwrap(), [1, 2, 3]);
+        assert_eq!(thumbnail(&old), None);
+    }
+
+    #[test]
+    fn truncated_state_is_rejected() {
+        let state = state(None);
+        for len in [state.len() - 1, state.len() / 2, HEADER_SIZE + 1] {
+            assert!(matches!(
+                read_header(&state[..len]),
+                Err(StateError::BadChecksum)
+            ));
+        }
+        for len in [0, MAGIC.len(), HEADER_SIZE - 1] {
+            assert!(matches!(
+                read_header(&state[..len]),
+                Err(StateError::NotAState)
+            ));
+        }
+    }
+
+    #[test]
+    fn flipped_bit_is_rejected() {
+        let state = state(Some(b"png"));
+        for offset in [HEADER_SIZE, state.len() / 2, state.len() - 1] {
+            let mut damaged = state.clone();
+            damaged[offset] ^= 0x10;
+            assert!(matches!(
+                read_header(&damaged),
+                Err(StateError::BadChecksum)
+            ));
+        }
+        let mut damaged = state.clone();
+        damaged[CRC_OFFSET] ^= 0x01;
+        assert!(matches!(
+            read_header(&damaged),
+            Err(StateError::BadChecksum)
+        ));
+        let mut damaged = state;
+        damaged[0] ^= 0x01;
+        assert!(matches!(read_header(&damaged), Err(StateError::NotAState)));
+    }
+
+    #[test]
+    fn versions_outside_the_readable_range_are_rejected() {
+        let mut state = state(None);
+        set_version(&mut state, VERSION + 1);
+        assert!(matches!(
+            read_header(&state),
+            Err(StateError::VersionTooNew(version)) if version == VERSION + 1
+        ));
+        set_version(&mut state, OLDEST_VERSION - 1);
+        assert!(matches!(
+            read_header(&state),
+            Err(StateError::VersionTooOld(version)) if version == OLDEST_VERSION - 1
+        ));
+    }
+
+    #[test]
+    fn host_hashes_must_match() {
+        let other_bios = HostHashes {
+            bios: [8; 20],
+            ..HASHES
+        };
+        let no_disc = HostHashes {
+            disc: None,
+            ..HASHES
+        };
+        assert!(HASHES.check(&HASHES).is_ok());
+        assert!(matches!(
+            HASHES.check(&other_bios),
+            Err(StateError::WrongBios)
+        ));
+        assert!(matches!(HASHES.check(&no_disc), Err(StateError::WrongDisc)));
+    }
+}
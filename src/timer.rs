@@ -1,3 +1,8 @@
+use serde::{Deserialize, Serialize};
+
+use crate::gpu::TickEvents;
+
+#[derive(Serialize, Deserialize)]
 pub struct Timer {
     id: u8,
     counter_mode: CounterMode,
@@ -7,6 +12,7 @@ pub struct Timer {
     allow_irq: bool,
     sync_mode: u8,
     sync_enabled: bool,
+    eighth_remainder: u32,
 }
 
 impl Timer {
@@ -20,12 +26,64 @@ impl Timer {
             allow_irq: true,
             sync_mode: 0,
             sync_enabled: false,
+            eighth_remainder: 0,
         }
     }
 
-    // Tick timer once. Returns true if IRQ
-    pub fn tick(&mut self, dotclocks: u16, hblanks: u16) -> bool {
-        self.increment_counter(dotclocks, hblanks);
+    // Advance the timer by the given CPU cycles and video events. Returns true if IRQ
+    pub fn tick(&mut self, cycles: u32, events: &TickEvents) -> bool {
+        if self.sync_enabled && self.apply_sync(events) {
+            return false;
+        }
+
+        let mut irq = false;
+        for _ in 0..self.increments(cycles, events) {
+            irq |= self.increment_counter();
+        }
+
+        irq
+    }
+
+    // Handles the sync mode. Returns true if the counter is paused
+    fn apply_sync(&mut self, events: &TickEvents) -> bool {
+        let (edge, in_blank) = match self.id {
+            0 => (events.hblanks > 0, events.in_hblank),
+            1 => (events.vblank_start, events.in_vblank),
+            // Timer 2 has no blank signal. Sync modes 0 and 3 stop the counter
+            _ => return self.sync_mode == 0 || self.sync_mode == 3,
+        };
+
+        match self.sync_mode {
+            // Pause counter during blank
+            0 => in_blank,
+            // Reset counter to 0 at blank
+            1 => {
+                if edge {
+                    self.counter = 0;
+                }
+                false
+            }
+            // Reset counter to 0 at blank and pause outside of blank
+            2 => {
+                if edge {
+                    self.counter = 0;
+                }
+                !in_blank
+            }
+            // Pause until blank occurs once, then switch to free run
+            3 => {
+                if edge {
+                    self.sync_enabled = false;
+                }
+                !edge
+            }
+            _ => panic!("Impossible"),
+        }
+    }
+
+    // Increment counter once. Returns true if IRQ
+    fn increment_counter(&mut self) -> bool {
+        self.counter = self.counter.wrapping_add(1);
 
         if self.reset_after_target() && (self.counter == self.target_value.wrapping_add(1)) {
             self.counter = 0;
@@ -65,9 +123,9 @@ impl Timer {
     pub fn write_mode(&mut self, val: u16) {
         self.counter = 0;
         self.allow_irq = true;
-        self.mode |= 0x400;
-        self.mode = val & 0x3FF;
+        self.mode = (val & 0x3FF) | 0x400;
         self.sync_enabled = val & 1 > 0;
+        self.sync_mode = ((val >> 1) & 0b11) as u8;
 
         match (val >> 8) & 0b11 {
             0 => self.counter_mode = CounterMode::SystemClock,
@@ -110,19 +168,17 @@ impl Timer {
         self.mode
     }
 
-    fn increment_counter(&mut self, dotclocks: u16, hblanks: u16) {
+    // Number of times the counter increments for this tick
+    fn increments(&mut self, cycles: u32, events: &TickEvents) -> u32 {
         match self.counter_mode {
-            CounterMode::SystemClock => {
-                self.counter = self.counter.wrapping_add(1);
-            }
-            CounterMode::Dotclock => {
-                self.counter = dotclocks;
-            }
-            CounterMode::Hblank => {
-                self.counter = hblanks;
-            }
+            CounterMode::SystemClock => cycles,
+            CounterMode::Dotclock => events.dotclocks,
+            CounterMode::Hblank => events.hblanks,
             CounterMode::SystemClockEighth => {
-                todo!()
+                self.eighth_remainder += cycles;
+                let increments = self.eighth_remainder / 8;
+                self.eighth_remainder %= 8;
+                increments
             }
         }
     }
@@ -159,7 +215,7 @@ impl Timer {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 enum CounterMode {
     SystemClock,
     Dotclock,
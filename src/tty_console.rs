@@ -0,0 +1,233 @@
+use std::collections::VecDeque;
+
+use eframe::egui::{self, RichText};
+
+// Older lines are dropped past this many
+const MAX_LINES: usize = 10000;
+
+struct Line {
+    text: String,
+    // Emulated seconds since power on when the line started
+    time: f64,
+    // Address of the putchar call that started the line, or of the logged BIOS call
+    pc: u32,
+    // Logged BIOS call rather than text the guest printed
+    bios_call: bool,
+}
+
+impl Line {
+    fn format(&self, timestamps: bool, show_pc: bool) -> String {
+        let mut prefix = String::new();
+        if timestamps {
+            prefix.push_str(&format!("[{:9.3}] ", self.time));
+        }
+        if show_pc {
+            prefix.push_str(&format!("{:08X}: ", self.pc));
+        }
+        format!("{prefix}{}", self.text)
+    }
+}
+
+// What the bus hands the console
+pub enum TtyOutput {
+    // Given to one of the BIOS putchar calls
+    Char(char),
+    // Line logged for a call into the BIOS, see bios_calls
+    BiosCall(String),
+}
+
+// Debug window with the text the guest printed through the BIOS. Test ROMs report their
+// results this way
+pub struct TtyConsole {
+    pub open: bool,
+    lines: VecDeque<Line>,
+    // Line still being printed
+    partial: Option<Line>,
+    follow: bool,
+    timestamps: bool,
+    show_pc: bool,
+}
+
+impl TtyConsole {
+    pub fn new() -> Self {
+        Self {
+            open: false,
+            lines: VecDeque::new(),
+            partial: None,
+            follow: true,
+            timestamps: false,
+            show_pc: false,
+        }
+    }
+
+    // Adds output from the bus, printed by emulated time in seconds
+    pub fn push(&mut self, output: &[(TtyOutput, u32)], time: f64) {
+        for (output, return_addr) in output {
+            // Calls return past their delay slot
+            let pc = return_addr.wrapping_sub(8);
+            let ch = match output {
+                TtyOutput::Char(ch) => *ch,
+                // Kept on a line of its own, ahead of any line still being printed
+                TtyOutput::BiosCall(text) => {
+                    self.push_line(Line {
+                        text: text.clone(),
+                        time,
+                        pc,
+                        bios_call: true,
+                    });
+                    continue;
+                }
+            };
+            let line = self.partial.get_or_insert_with(|| Line {
+                text: String::new(),
+                time,
+                pc,
+                bios_call: false,
+            });
+            match ch {
+                '\n' => {
+                    if let Some(line) = self.partial.take() {
+                        self.push_line(line);
+                    }
+                }
+                '\r' => {}
+                ch => line.text.push(ch),
+            }
+        }
+    }
+
+    fn push_line(&mut self, line: Line) {
+        self.lines.push_back(line);
+        if self.lines.len() > MAX_LINES {
+            self.lines.pop_front();
+        }
+    }
+
+    fn clear(&mut self) {
+        self.lines.clear();
+        self.partial = None;
+    }
+
+    fn text(&self) -> String {
+        self.lines
+            .iter()
+            .chain(&self.partial)
+            .map(|line| line.format(self.timestamps, self.show_pc))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context) {
+        let mut open = self.open;
+        egui::Window::new("TTY")
+            .open(&mut open)
+            .default_size([520.0, 320.0])
+            .show(ctx, |ui| self.contents(ui));
+        self.open = open;
+    }
+
+    fn contents(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.follow, "Follow");
+            ui.checkbox(&mut self.timestamps, "Timestamps");
+            ui.checkbox(&mut self.show_pc, "Caller PC");
+            if ui.button("Copy").clicked() {
+                ui.ctx().copy_text(self.text());
+            }
+            if ui.button("Clear").clicked() {
+                self.clear();
+            }
+        });
+        ui.separator();
+
+        let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+        let rows = self.lines.len() + self.partial.is_some() as usize;
+        egui::ScrollArea::both()
+            .auto_shrink(false)
+            .stick_to_bottom(self.follow)
+            .show_rows(ui, row_height, rows, |ui, rows| {
+                for line in self
+                    .lines
+                    .iter()
+                    .chain(&self.partial)
+                    .skip(rows.start)
+                    .take(rows.len())
+                {
+                    let text = RichText::new(line.format(self.timestamps, self.show_pc));
+                    if line.bios_call {
+                        ui.label(text.monospace().weak());
+                    } else {
+                        ui.label(text.monospace());
+                    }
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(text: &str, return_addr: u32) -> Vec<(TtyOutput, u32)> {
+        text.chars()
+            .map(|ch| (TtyOutput::Char(ch), return_addr))
+            .collect()
+    }
+
+    #[test]
+    fn output_is_split_into_lines() {
+        let mut console = TtyConsole::new();
+        console.push(&chars("PS-X Realtime Kernel\r\n\nTest ", 0x80001008), 1.5);
+        console.push(&chars("passed", 0x80002008), 2.0);
+        assert_eq!(console.text(), "PS-X Realtime Kernel\n\nTest passed");
+
+        // Lines are annotated with when and where they started
+        console.timestamps = true;
+        console.show_pc = true;
+        assert_eq!(
+            console.text(),
+            "[    1.500] 80001000: PS-X Realtime Kernel\n\
+             [    1.500] 80001000: \n\
+             [    1.500] 80001000: Test passed"
+        );
+        console.timestamps = false;
+        assert_eq!(
+            console.text(),
+            "80001000: PS-X Realtime Kernel\n80001000: \n80001000: Test passed"
+        );
+
+        console.clear();
+        assert_eq!(console.text(), "");
+    }
+
+    #[test]
+    fn bios_calls_get_a_line_of_their_own() {
+        let mut console = TtyConsole::new();
+        let mut output = chars("Loading", 0x80001008);
+        output.push((TtyOutput::BiosCall("A(3Fh) printf".to_string()), 0x80003008));
+        output.extend(chars("...\n", 0x80001008));
+        console.push(&output, 0.0);
+
+        console.show_pc = true;
+        assert_eq!(
+            console.text(),
+            "80003000: A(3Fh) printf\n80001000: Loading..."
+        );
+        assert!(console.lines[0].bios_call);
+        assert!(!console.lines[1].bios_call);
+    }
+
+    #[test]
+    fn scrollback_keeps_the_newest_lines() {
+        let mut console = TtyConsole::new();
+        for i in 0..MAX_LINES + 5 {
+            console.push(&chars(&format!("{i}\n"), 0), 0.0);
+        }
+        assert_eq!(console.lines.len(), MAX_LINES);
+        assert_eq!(console.lines.front().unwrap().text, "5");
+        assert_eq!(
+            console.lines.back().unwrap().text,
+            (MAX_LINES + 4).to_string()
+        );
+    }
+}
@@ -0,0 +1,136 @@
+use eframe::egui::{self, Color32};
+
+use crate::gpu::Gpu;
+
+// How VRAM is shown: as 15 bit pixels, or as a texture page read through a CLUT
+#[derive(Clone, Copy, PartialEq)]
+enum ViewMode {
+    Direct,
+    Clut4,
+    Clut8,
+}
+
+// Debug window showing the whole of VRAM with the display and drawing areas outlined
+pub struct VramViewer {
+    pub open: bool,
+    zoom: f32,
+    mode: ViewMode,
+    // Texture page in units of 64 pixels across and 256 lines down
+    page: (usize, usize),
+    // CLUT position in units of 16 pixels across and lines down
+    clut: (usize, usize),
+    texture: Option<egui::TextureHandle>,
+    pixels: Vec<Color32>,
+}
+
+impl VramViewer {
+    pub fn new() -> Self {
+        Self {
+            open: false,
+            zoom: 1.0,
+            mode: ViewMode::Direct,
+            page: (0, 0),
+            clut: (0, 0),
+            texture: None,
+            pixels: Vec::new(),
+        }
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context, gpu: &Gpu) {
+        let mut open = self.open;
+        egui::Window::new("VRAM")
+            .open(&mut open)
+            .default_size([640.0, 400.0])
+            .show(ctx, |ui| self.contents(ui, gpu));
+        self.open = open;
+    }
+
+    fn contents(&mut self, ui: &mut egui::Ui, gpu: &Gpu) {
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut self.mode, ViewMode::Direct, "15 bit");
+            ui.radio_value(&mut self.mode, ViewMode::Clut4, "4 bit CLUT");
+            ui.radio_value(&mut self.mode, ViewMode::Clut8, "8 bit CLUT");
+            ui.add(egui::Slider::new(&mut self.zoom, 0.25..=4.0).text("Zoom"));
+        });
+        if self.mode != ViewMode::Direct {
+            ui.horizontal(|ui| {
+                ui.label("Page");
+                ui.add(egui::DragValue::new(&mut self.page.0).range(0..=15));
+                ui.add(egui::DragValue::new(&mut self.page.1).range(0..=1));
+                ui.label("CLUT");
+                ui.add(egui::DragValue::new(&mut self.clut.0).range(0..=63));
+                ui.add(egui::DragValue::new(&mut self.clut.1).range(0..=511));
+            });
+        }
+
+        let page = (self.page.0 * 64, self.page.1 * 256);
+        let image = match self.mode {
+            ViewMode::Direct => egui::ColorImage::from_rgb([1024, 512], &gpu.vram_rgb()),
+            ViewMode::Clut4 | ViewMode::Clut8 => {
+                let clut = (self.clut.0 * 16, self.clut.1);
+                let eight_bit = self.mode == ViewMode::Clut8;
+                gpu.render_texture_page(page, clut, eight_bit, &mut self.pixels);
+                egui::ColorImage::new([256, 256], self.pixels.clone())
+            }
+        };
+        let size = egui::vec2(image.size[0] as f32, image.size[1] as f32) * self.zoom;
+        let texture = self.texture.get_or_insert_with(|| {
+            ui.ctx()
+                .load_texture("vram_viewer", image.clone(), egui::TextureOptions::NEAREST)
+        });
+        texture.set(image, egui::TextureOptions::NEAREST);
+        let texture = egui::load::SizedTexture::new(texture.id(), size);
+
+        ui.label("Display area in green, drawing area in red");
+        egui::ScrollArea::both().show(ui, |ui| {
+            let response = ui.add(egui::Image::new(texture).sense(egui::Sense::hover()));
+            let origin = response.rect.min;
+            let outline = |x: usize, y: usize, width: usize, height: usize, color: Color32| {
+                let min = origin + egui::vec2(x as f32, y as f32) * self.zoom;
+                let rect = egui::Rect::from_min_size(
+                    min,
+                    egui::vec2(width as f32, height as f32) * self.zoom,
+                );
+                ui.painter().rect_stroke(
+                    rect,
+                    0.0,
+                    egui::Stroke::new(1.0, color),
+                    egui::StrokeKind::Outside,
+                );
+            };
+
+            if self.mode == ViewMode::Direct {
+                let (x, y, width, height) = gpu.display_area();
+                outline(x, y, width, height, Color32::GREEN);
+                let (left, top) = gpu.gp0.draw_area_top_left;
+                let (right, bottom) = gpu.gp0.draw_area_bot_right;
+                outline(
+                    left as usize,
+                    top as usize,
+                    (right + 1).saturating_sub(left) as usize,
+                    (bottom + 1).saturating_sub(top) as usize,
+                    Color32::RED,
+                );
+            }
+
+            let Some(pos) = response.hover_pos() else {
+                return;
+            };
+            let pos = (pos - origin) / self.zoom;
+            let (x, y) = (pos.x as usize, pos.y as usize);
+            // Texels map back to the halfword in VRAM holding them
+            let readout = match self.mode {
+                ViewMode::Direct => format!("X {x} Y {y}: {:04X}", gpu.vram_pixel(x, y)),
+                mode => {
+                    let per_halfword = if mode == ViewMode::Clut8 { 2 } else { 4 };
+                    let (vram_x, vram_y) = (page.0 + x / per_halfword, page.1 + y);
+                    format!(
+                        "U {x} V {y} at X {vram_x} Y {vram_y}: {:04X}",
+                        gpu.vram_pixel(vram_x, vram_y)
+                    )
+                }
+            };
+            response.on_hover_text_at_pointer(readout);
+        });
+    }
+}
@@ -0,0 +1,413 @@
+use eframe::egui::{self, Color32, RichText};
+use serde::{Deserialize, Serialize};
+
+// Accesses a watchpoint stops at
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    fn label(self) -> &'static str {
+        match self {
+            Access::Read => "Read",
+            Access::Write => "Write",
+            Access::ReadWrite => "Read/Write",
+        }
+    }
+
+    fn matches(self, write: bool) -> bool {
+        match self {
+            Access::Read => !write,
+            Access::Write => write,
+            Access::ReadWrite => true,
+        }
+    }
+}
+
+// Data watchpoint over an inclusive range of addresses. Disabled ones are kept in the list but
+// never stop the CPU
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Watchpoint {
+    pub start: u32,
+    pub end: u32,
+    pub access: Access,
+    pub enabled: bool,
+}
+
+// Load, store or DMA transfer that touched a watched address
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct WatchHit {
+    // Instruction that made the access, or that was running when DMA made it
+    pub pc: u32,
+    pub addr: u32,
+    // In bytes
+    pub size: u32,
+    pub val: u32,
+    pub write: bool,
+    pub dma: bool,
+}
+
+impl WatchHit {
+    // Such as "Word write of 0000002A to 80012340 at PC 80010040"
+    pub fn describe(&self) -> String {
+        let width = match self.size {
+            1 => "Byte",
+            2 => "Halfword",
+            _ => "Word",
+        };
+        let (kind, dir) = if self.write {
+            ("write", "to")
+        } else {
+            ("read", "from")
+        };
+        let val = match self.size {
+            1 => format!("{:02X}", self.val),
+            2 => format!("{:04X}", self.val),
+            _ => format!("{:08X}", self.val),
+        };
+        let source = if self.dma { " by DMA" } else { "" };
+        format!(
+            "{width} {kind} of {val} {dir} {:08X}{source} at PC {:08X}",
+            self.addr, self.pc
+        )
+    }
+}
+
+// The segments mirror the same physical space, so watchpoints match whichever is used
+fn physical(addr: u32) -> u32 {
+    addr & 0x1FFFFFFF
+}
+
+// Enabled watchpoints as the bus checks them. Belongs to the host like breakpoints, so save
+// states leave it alone
+#[derive(Default)]
+pub struct Watcher {
+    // Physical ranges, inclusive
+    ranges: Vec<(u32, u32, Access)>,
+    // Whether DMA transfers are checked as well as the CPU's loads and stores
+    dma: bool,
+    // Instruction being executed. Only kept up to date while any watchpoint is set
+    pub pc: u32,
+    // First access to hit since the last take_hit
+    hit: Option<WatchHit>,
+}
+
+impl Watcher {
+    pub fn new(watchpoints: &[Watchpoint], dma: bool) -> Self {
+        let ranges = watchpoints
+            .iter()
+            .filter(|watchpoint| watchpoint.enabled)
+            .map(|watchpoint| {
+                let (start, end) = (physical(watchpoint.start), physical(watchpoint.end));
+                (start, end, watchpoint.access)
+            })
+            .collect();
+        Self {
+            ranges,
+            dma,
+            ..Self::default()
+        }
+    }
+
+    // Checked before each access, so nothing else is done while there are no watchpoints
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    pub fn check(&mut self, addr: u32, size: u32, val: u32, write: bool, dma: bool) {
+        if self.hit.is_some() || (dma && !self.dma) {
+            return;
+        }
+        let first = physical(addr);
+        let last = first + size - 1;
+        let hit = self
+            .ranges
+            .iter()
+            .any(|&(start, end, access)| first <= end && last >= start && access.matches(write));
+        if hit {
+            self.hit = Some(WatchHit {
+                pc: self.pc,
+                addr,
+                size,
+                val,
+                write,
+                dma,
+            });
+        }
+    }
+
+    pub fn take_hit(&mut self) -> Option<WatchHit> {
+        self.hit.take()
+    }
+}
+
+// Reads "80010000" or "0x80010000"
+fn parse_addr(text: &str) -> Result<u32, String> {
+    let text = text.trim();
+    let hex = text.strip_prefix("0x").unwrap_or(text);
+    u32::from_str_radix(hex, 16).map_err(|_| format!("{text} is not an address"))
+}
+
+// Debug window listing the current game's watchpoints
+pub struct WatchpointWindow {
+    pub open: bool,
+    start_text: String,
+    // Empty watches the start address alone
+    end_text: String,
+    access: Access,
+    error: Option<String>,
+}
+
+impl WatchpointWindow {
+    pub fn new() -> Self {
+        Self {
+            open: false,
+            start_text: String::new(),
+            end_text: String::new(),
+            access: Access::Write,
+            error: None,
+        }
+    }
+
+    // Returns whether the list or DMA setting changed. Watchpoints the last hit touched are
+    // highlighted
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        watchpoints: &mut Vec<Watchpoint>,
+        dma: &mut bool,
+        hit: Option<WatchHit>,
+    ) -> bool {
+        let mut changed = false;
+        let mut open = self.open;
+        egui::Window::new("Watchpoints")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| changed = self.contents(ui, watchpoints, dma, hit));
+        self.open = open;
+        changed
+    }
+
+    fn contents(
+        &mut self,
+        ui: &mut egui::Ui,
+        watchpoints: &mut Vec<Watchpoint>,
+        dma: &mut bool,
+        hit: Option<WatchHit>,
+    ) -> bool {
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            ui.label("From");
+            ui.add(
+                egui::TextEdit::singleline(&mut self.start_text)
+                    .desired_width(80.0)
+                    .font(egui::TextStyle::Monospace),
+            );
+            ui.label("to");
+            ui.add(
+                egui::TextEdit::singleline(&mut self.end_text)
+                    .desired_width(80.0)
+                    .hint_text("optional")
+                    .font(egui::TextStyle::Monospace),
+            );
+        });
+        ui.horizontal(|ui| {
+            for access in [Access::Read, Access::Write, Access::ReadWrite] {
+                ui.radio_value(&mut self.access, access, access.label());
+            }
+            if ui.button("Add").clicked() {
+                match self.parse_range() {
+                    Ok((start, end)) => {
+                        watchpoints.push(Watchpoint {
+                            start,
+                            end,
+                            access: self.access,
+                            enabled: true,
+                        });
+                        self.start_text.clear();
+                        self.end_text.clear();
+                        self.error = None;
+                        changed = true;
+                    }
+                    Err(err) => self.error = Some(err),
+                }
+            }
+        });
+        if let Some(error) = &self.error {
+            ui.colored_label(Color32::RED, error);
+        }
+        changed |= ui.checkbox(dma, "Include DMA transfers").changed();
+
+        ui.separator();
+        if let Some(hit) = hit {
+            ui.label(RichText::new(hit.describe()).color(Color32::YELLOW));
+        }
+        if watchpoints.is_empty() {
+            ui.label("No watchpoints");
+        }
+        let mut remove = None;
+        egui::Grid::new("watchpoints").show(ui, |ui| {
+            for (i, watchpoint) in watchpoints.iter_mut().enumerate() {
+                changed |= ui.checkbox(&mut watchpoint.enabled, "").changed();
+                let range = if watchpoint.start == watchpoint.end {
+                    format!("{:08X}", watchpoint.start)
+                } else {
+                    format!("{:08X}-{:08X}", watchpoint.start, watchpoint.end)
+                };
+                let mut text = RichText::new(range).monospace();
+                if hit.is_some_and(|hit| {
+                    let first = physical(hit.addr);
+                    let last = first + hit.size - 1;
+                    first <= physical(watchpoint.end) && last >= physical(watchpoint.start)
+                }) {
+                    text = text.color(Color32::YELLOW);
+                }
+                ui.label(text);
+                ui.label(watchpoint.access.label());
+                if ui.button("Remove").clicked() {
+                    remove = Some(i);
+                }
+                ui.end_row();
+            }
+        });
+        if let Some(i) = remove {
+            watchpoints.remove(i);
+            changed = true;
+        }
+        changed
+    }
+
+    fn parse_range(&self) -> Result<(u32, u32), String> {
+        let start = parse_addr(&self.start_text)?;
+        let end = match self.end_text.trim() {
+            "" => start,
+            text => parse_addr(text)?,
+        };
+        if end < start {
+            return Err(format!("{end:08X} comes before {start:08X}"));
+        }
+        Ok((start, end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn watchpoint(start: u32, end: u32, access: Access) -> Watchpoint {
+        Watchpoint {
+            start,
+            end,
+            access,
+            enabled: true,
+        }
+    }
+
+    #[test]
+    fn accesses_overlapping_the_range_hit() {
+        let mut watcher = Watcher::new(&[watchpoint(0x80010004, 0x80010007, Access::Write)], false);
+        watcher.pc = 0x80020000;
+        watcher.check(0x80010000, 4, 0, true, false);
+        watcher.check(0x80010008, 2, 0, true, false);
+        assert_eq!(watcher.take_hit(), None);
+        // The word at 80010002 ends inside the range
+        watcher.check(0x80010002, 4, 0x1234, true, false);
+        assert_eq!(
+            watcher.take_hit(),
+            Some(WatchHit {
+                pc: 0x80020000,
+                addr: 0x80010002,
+                size: 4,
+                val: 0x1234,
+                write: true,
+                dma: false,
+            })
+        );
+        assert_eq!(watcher.take_hit(), None);
+    }
+
+    #[test]
+    fn access_kind_must_match() {
+        let watchpoints = [
+            watchpoint(0x100, 0x100, Access::Read),
+            watchpoint(0x200, 0x200, Access::Write),
+            watchpoint(0x300, 0x300, Access::ReadWrite),
+        ];
+        let mut watcher = Watcher::new(&watchpoints, false);
+        for (addr, write, hit) in [
+            (0x100, false, true),
+            (0x100, true, false),
+            (0x200, false, false),
+            (0x200, true, true),
+            (0x300, false, true),
+            (0x300, true, true),
+        ] {
+            watcher.check(addr, 1, 0, write, false);
+            assert_eq!(watcher.take_hit().is_some(), hit, "{addr:X} write {write}");
+        }
+    }
+
+    #[test]
+    fn segments_share_physical_addresses() {
+        let mut watcher = Watcher::new(&[watchpoint(0x00010000, 0x00010000, Access::Read)], false);
+        for addr in [0x00010000, 0x80010000, 0xA0010000] {
+            watcher.check(addr, 1, 0, false, false);
+            assert_eq!(watcher.take_hit().map(|hit| hit.addr), Some(addr));
+        }
+    }
+
+    #[test]
+    fn first_hit_is_kept() {
+        let mut watcher = Watcher::new(&[watchpoint(0x100, 0x1FF, Access::ReadWrite)], false);
+        watcher.check(0x100, 1, 1, true, false);
+        watcher.check(0x104, 1, 2, true, false);
+        assert_eq!(watcher.take_hit().map(|hit| hit.val), Some(1));
+    }
+
+    #[test]
+    fn dma_and_disabled_watchpoints_are_left_out() {
+        let disabled = Watchpoint {
+            enabled: false,
+            ..watchpoint(0x200, 0x200, Access::ReadWrite)
+        };
+        let watchpoints = [watchpoint(0x100, 0x100, Access::ReadWrite), disabled];
+        let mut watcher = Watcher::new(&watchpoints, false);
+        watcher.check(0x100, 4, 0, true, true);
+        watcher.check(0x200, 4, 0, true, false);
+        assert_eq!(watcher.take_hit(), None);
+
+        let mut watcher = Watcher::new(&watchpoints, true);
+        watcher.check(0x100, 4, 0, true, true);
+        assert!(watcher.take_hit().is_some_and(|hit| hit.dma));
+        assert!(Watcher::new(&[disabled], true).is_empty());
+    }
+
+    #[test]
+    fn describe() {
+        let hit = WatchHit {
+            pc: 0x80010040,
+            addr: 0x80012340,
+            size: 4,
+            val: 0x2A,
+            write: true,
+            dma: false,
+        };
+        assert_eq!(
+            hit.describe(),
+            "Word write of 0000002A to 80012340 at PC 80010040"
+        );
+        let hit = WatchHit {
+            size: 1,
+            write: false,
+            dma: true,
+            ..hit
+        };
+        assert_eq!(
+            hit.describe(),
+            "Byte read of 2A from 80012340 by DMA at PC 80010040"
+        );
+    }
+}
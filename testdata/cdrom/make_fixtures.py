@@ -0,0 +1,230 @@
+#!/usr/bin/env python3
+# Writes the disc image fixtures used by the CD-ROM tests. Run from this folder
+
+import math
+import random
+import struct
+import zlib
+
+ISO_SECTOR = 2048
+SECTOR = 2352
+
+
+def iso_sector(data=b""):
+    return data.ljust(ISO_SECTOR, b"\0")
+
+
+def dir_record(name, extent, size, flags=0):
+    # Both byte orders of the extent and size, the date, flags and volume sequence number
+    record = bytearray(2) + struct.pack("<I", extent) + struct.pack(">I", extent)
+    record += struct.pack("<I", size) + struct.pack(">I", size)
+    record += bytes(7) + bytes([flags, 0, 0]) + struct.pack("<H", 1) + struct.pack(">H", 1)
+    record += bytes([len(name)]) + name
+    if len(record) % 2:
+        record += b"\0"
+    record[0] = len(record)
+    return bytes(record)
+
+
+# Licensed European disc with a root directory holding SYSTEM.CNF
+def game_iso():
+    cnf = b"BOOT = cdrom:\\SLES_123.45;1\r\nTCB = 4\r\nEVENT = 10\r\nSTACK = 801FFFF0\r\n"
+    license = b"          Licensed  by          Sony Computer Entertainment Euro pe   "
+
+    root = dir_record(b"\0", 18, ISO_SECTOR, 2) + dir_record(b"\1", 18, ISO_SECTOR, 2)
+    root += dir_record(b"SYSTEM.CNF;1", 19, len(cnf))
+
+    pvd = bytearray(iso_sector(b"\1CD001\1"))
+    pvd[156:156 + 34] = dir_record(b"\0", 18, ISO_SECTOR, 2)
+
+    sectors = [iso_sector() for _ in range(20)]
+    sectors[4] = iso_sector(license)
+    sectors[16] = bytes(pvd)
+    sectors[17] = iso_sector(b"\xffCD001\1")
+    sectors[18] = iso_sector(root)
+    sectors[19] = iso_sector(cnf)
+    return b"".join(sectors)
+
+
+def bcd(val):
+    return (val // 10) << 4 | val % 10
+
+
+def edc(data):
+    crc = 0
+    for byte in data:
+        crc ^= byte
+        for _ in range(8):
+            crc = (crc >> 1) ^ (0xD8018001 if crc & 1 else 0)
+    return crc
+
+
+# Raw sector with sync, header MSF, mode 2 and a data subheader around 2048 bytes
+def data_sector(lba, data):
+    frames = lba + 150
+    header = bytes([bcd(frames // 4500), bcd(frames // 75 % 60), bcd(frames % 75), 2])
+    subheader = bytes([0, 0, 0x08, 0]) * 2
+    body = subheader + data
+    return b"\0" + b"\xff" * 10 + b"\0" + header + body + struct.pack("<I", edc(body)) + bytes(276)
+
+
+# Stereo 16 bit samples, the right channel inverted
+def sine_sectors(count, frequency, amplitude):
+    samples = bytearray()
+    for i in range(count * SECTOR // 4):
+        left = round(amplitude * math.sin(2 * math.pi * frequency * i / 44100))
+        samples += struct.pack("<hh", left, -left)
+    return bytes(samples)
+
+
+# Data track of 6 sectors followed by two audio tracks of 3 sectors, the last with a pregap
+# that isn't stored
+def mixed_bin():
+    data = b"".join(
+        data_sector(lba, bytes((lba * 7 + i) & 0xFF for i in range(ISO_SECTOR)))
+        for lba in range(6)
+    )
+    return data + sine_sectors(3, 441, 8000) + sine_sectors(3, 882, 4000)
+
+
+MIXED_CUE = """FILE "mixed.bin" BINARY
+  TRACK 01 MODE2/2352
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    INDEX 01 00:00:06
+  TRACK 03 AUDIO
+    PREGAP 00:02:00
+    INDEX 01 00:00:09
+"""
+
+
+def deflate(data):
+    compressor = zlib.compressobj(9, zlib.DEFLATED, -15)
+    return compressor.compress(data) + compressor.flush()
+
+
+# The tracks of mixed.bin in a V5 CHD with cdzl hunks of 8 frames. Tracks are padded to 4
+# frames and audio samples are stored big endian
+def mixed_chd():
+    raw = mixed_bin()
+    tracks = [(1, "MODE2_RAW", 0, 6, 0), (2, "AUDIO", 6, 3, 0), (3, "AUDIO", 9, 3, 150)]
+    frames = []
+    metadata = []
+    for number, kind, start, count, pregap in tracks:
+        for lba in range(start, start + count):
+            sector = raw[lba * SECTOR:(lba + 1) * SECTOR]
+            if kind == "AUDIO":
+                sector = b"".join(sector[i + 1:i + 2] + sector[i:i + 1] for i in range(0, SECTOR, 2))
+            frames.append(sector)
+        frames += [bytes(SECTOR)] * (-count % 4)
+        metadata.append(
+            f"TRACK:{number} TYPE:{kind} SUBTYPE:NONE FRAMES:{count} PREGAP:{pregap} "
+            f"PGTYPE:{kind} PGSUB:NONE POSTGAP:0\0".encode()
+        )
+
+    hunk_frames = 8
+    hunk_bytes = hunk_frames * (SECTOR + 96)
+    hunks = []
+    for i in range(0, len(frames), hunk_frames):
+        sectors = deflate(b"".join(frames[i:i + hunk_frames]))
+        # No frames need their ECC regenerated
+        hunks.append(b"\0" + struct.pack(">H", len(sectors)) + sectors + deflate(bytes(96 * hunk_frames)))
+
+    # Metadata entries follow the header, linked by their offsets
+    offset = 124
+    meta = b""
+    for i, data in enumerate(metadata):
+        next_offset = offset + 16 + len(data) if i + 1 < len(metadata) else 0
+        meta += b"CHT2" + struct.pack(">IQ", len(data), next_offset) + data
+        offset += 16 + len(data)
+    hunk_offset = offset
+    map_offset = hunk_offset + sum(len(hunk) for hunk in hunks)
+
+    # The map codes every entry type with 4 bits. All hunks are codec 0, followed by their
+    # 20 bit lengths and unchecked CRCs
+    bits = "0100" * 16 + "0000" * len(hunks)
+    bits += "".join(format(len(hunk), "020b") + "0" * 16 for hunk in hunks)
+    bits += "0" * (-len(bits) % 8)
+    compressed_map = int(bits, 2).to_bytes(len(bits) // 8, "big")
+    map_header = struct.pack(">I", len(compressed_map)) + hunk_offset.to_bytes(6, "big")
+    map_header += bytes([0, 0, 20, 0, 0, 0])
+
+    header = b"MComprHD" + struct.pack(">II", 124, 5) + b"cdzl" + bytes(12)
+    header += struct.pack(">QQQII", len(hunks) * hunk_bytes, map_offset, 124, hunk_bytes, SECTOR + 96)
+    header += bytes(60)
+    return header + meta + b"".join(hunks) + map_header + compressed_map
+
+
+# Libcrypt style subchannel Q for mixed.bin. LBA 3 has all its Q data replaced and LBA 5 only
+# its absolute position, each with a bit flipped in the frame
+def libcrypt_sbi():
+    sbi = b"SBI\0"
+    sbi += bytes([0x00, 0x02, 0x03, 1, 0x41, 0x01, 0x01, 0x00, 0x00, 0x13, 0x00, 0x00, 0x02, 0x03])
+    sbi += bytes([0x00, 0x02, 0x05, 3, 0x00, 0x02, 0x25])
+    return sbi
+
+
+# Stereo 37.8kHz 4 bit XA-ADPCM sector of file 1 channel 0 with every filter and a range of
+# shifts
+def xa_sector():
+    rng = random.Random(1645)
+    groups = bytearray()
+    for group in range(18):
+        headers = bytes(((unit + group) % 4) << 4 | (unit * 3 + group) % 13 for unit in range(8))
+        groups += headers[:4] + headers + headers[4:]
+        groups += bytes(rng.randrange(256) for _ in range(112))
+
+    subheader = bytes([1, 0, 0x64, 0x01]) * 2
+    body = subheader + groups + bytes(20)
+    return b"\0" + b"\xff" * 10 + b"\0" + bytes([0, 2, 0, 2]) + body + struct.pack("<I", edc(body))
+
+
+# Decodes an XA sector as the hardware documentation describes and holds each sample for as
+# many 44.1kHz output samples as it covers
+def xa_pcm(sector):
+    pos_table = [0, 60, 115, 98]
+    neg_table = [0, 0, -52, -55]
+    history = [[0, 0], [0, 0]]
+    channels = [[], []]
+    for g in range(18):
+        group = sector[24 + g * 128:24 + (g + 1) * 128]
+        for unit in range(8):
+            shift = group[4 + unit] & 0xF
+            shift = shift if shift <= 12 else 9
+            pos, neg = pos_table[group[4 + unit] >> 4 & 3], neg_table[group[4 + unit] >> 4 & 3]
+            channel = unit & 1
+            old, older = history[channel]
+            for i in range(28):
+                nibble = group[16 + i * 4 + unit // 2] >> (unit & 1) * 4 & 0xF
+                sample = (nibble - 16 if nibble >= 8 else nibble) << 12 >> shift
+                sample += (old * pos + older * neg + 32) >> 6
+                sample = max(-0x8000, min(0x7FFF, sample))
+                old, older = sample, old
+                channels[channel].append(sample)
+            history[channel] = [old, older]
+
+    pcm = bytearray()
+    phase = 0
+    for left, right in zip(*channels):
+        phase += 44100
+        while phase >= 37800:
+            phase -= 37800
+            pcm += struct.pack("<hh", left, right)
+    return bytes(pcm)
+
+
+if __name__ == "__main__":
+    with open("game.iso", "wb") as f:
+        f.write(game_iso())
+    with open("mixed.bin", "wb") as f:
+        f.write(mixed_bin())
+    with open("mixed.cue", "w") as f:
+        f.write(MIXED_CUE)
+    with open("mixed.chd", "wb") as f:
+        f.write(mixed_chd())
+    with open("libcrypt.sbi", "wb") as f:
+        f.write(libcrypt_sbi())
+    with open("xa.bin", "wb") as f:
+        f.write(xa_sector())
+    with open("xa.pcm", "wb") as f:
+        f.write(xa_pcm(xa_sector()))
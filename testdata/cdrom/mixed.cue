@@ -0,0 +1,8 @@
+FILE "mixed.bin" BINARY
+  TRACK 01 MODE2/2352
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    INDEX 01 00:00:06
+  TRACK 03 AUDIO
+    PREGAP 00:02:00
+    INDEX 01 00:00:09
@@ -0,0 +1,155 @@
+#!/usr/bin/env python3
+# Writes the macroblock fixtures used by the MDEC tests. Run from this folder
+
+import math
+import struct
+
+# Quantization table of the standard PlayStation FMV encoder, in the order coefficients are
+# sent. Used for both luminance and color
+QUANT = bytes([
+    0x02, 0x10, 0x10, 0x13, 0x10, 0x13, 0x16, 0x16, 0x16, 0x16, 0x16, 0x16, 0x1A, 0x18, 0x1A,
+    0x1B, 0x1B, 0x1B, 0x1A, 0x1A, 0x1A, 0x1A, 0x1B, 0x1B, 0x1B, 0x1D, 0x1D, 0x1D, 0x22, 0x22,
+    0x22, 0x1D, 0x1D, 0x1D, 0x1B, 0x1B, 0x1D, 0x1D, 0x20, 0x20, 0x22, 0x22, 0x25, 0x26, 0x25,
+    0x23, 0x23, 0x22, 0x23, 0x26, 0x26, 0x28, 0x28, 0x28, 0x30, 0x30, 0x2E, 0x2E, 0x38, 0x38,
+    0x3A, 0x45, 0x45, 0x53,
+])
+Q_SCALE = 2
+
+
+def c(u):
+    return 1 / math.sqrt(2) if u == 0 else 1
+
+
+# IDCT basis in 1.15 fixed point rounded down, frequency by row
+SCALE = [
+    min(0x7FFF, math.floor(0x8000 * c(u) * math.cos((2 * x + 1) * u * math.pi / 16)))
+    for u in range(8)
+    for x in range(8)
+]
+
+# Order coefficients are sent in, by row then column
+ZIGZAG = sorted(range(64), key=lambda i: (i // 8 + i % 8, (i // 8 if (i // 8 + i % 8) % 2 else i % 8)))
+
+
+def fdct(block):
+    out = []
+    for v in range(8):
+        for u in range(8):
+            total = sum(
+                block[y * 8 + x]
+                * math.cos((2 * y + 1) * v * math.pi / 16)
+                * math.cos((2 * x + 1) * u * math.pi / 16)
+                for y in range(8)
+                for x in range(8)
+            )
+            out.append(c(u) * c(v) * total / 4)
+    return out
+
+
+def idct(coefficients):
+    out = []
+    for y in range(8):
+        for x in range(8):
+            total = sum(
+                c(u) * c(v) * coefficients[v * 8 + u]
+                * math.cos((2 * y + 1) * v * math.pi / 16)
+                * math.cos((2 * x + 1) * u * math.pi / 16)
+                for v in range(8)
+                for u in range(8)
+            )
+            out.append(total / 4)
+    return out
+
+
+def trunc_div(a, b):
+    return -(-a // b) if a < 0 else a // b
+
+
+# Run length codes a block of signed samples. Returns the halfwords and the coefficients the
+# MDEC dequantizes them to
+def encode_block(samples):
+    coefficients = fdct(samples)
+    halfwords = []
+    decoded = [0] * 64
+    dc = max(-512, min(511, round(coefficients[0] / QUANT[0])))
+    halfwords.append(Q_SCALE << 10 | dc & 0x3FF)
+    decoded[0] = dc * QUANT[0]
+    run = 0
+    for k in range(1, 64):
+        pos = ZIGZAG[k]
+        level = round(coefficients[pos] * 8 / (QUANT[k] * Q_SCALE))
+        level = max(-512, min(511, level))
+        if level == 0:
+            run += 1
+            continue
+        halfwords.append(run << 10 | level & 0x3FF)
+        decoded[pos] = max(-0x400, min(0x3FF, trunc_div(level * QUANT[k] * Q_SCALE + 4, 8)))
+        run = 0
+    halfwords.append(0xFE00)
+    return halfwords, decoded
+
+
+# A 16x16 picture of gradients and a ripple, as unsigned RGB
+def picture():
+    pixels = []
+    for y in range(16):
+        for x in range(16):
+            r = 50 + 9 * x
+            g = 60 + 8 * y
+            b = 128 + 60 * math.sin(x / 3) * math.cos(y / 4)
+            pixels.append((r, g, b))
+    return pixels
+
+
+# Macroblock of the picture as Cr, Cb and four luminance blocks, then the RGB the MDEC should
+# decode it to with an exact IDCT and color conversion
+def macroblock():
+    pixels = picture()
+    luma = [0.299 * r + 0.587 * g + 0.114 * b - 128 for r, g, b in pixels]
+    cb = [(-0.1687 * r - 0.3313 * g + 0.5 * b) for r, g, b in pixels]
+    cr = [(0.5 * r - 0.4187 * g - 0.0813 * b) for r, g, b in pixels]
+
+    def chroma(plane):
+        return [
+            sum(plane[(2 * y + dy) * 16 + 2 * x + dx] for dy in range(2) for dx in range(2)) / 4
+            for y in range(8)
+            for x in range(8)
+        ]
+
+    def luma_block(i):
+        xx, yy = (i & 1) * 8, (i >> 1) * 8
+        return [luma[(y + yy) * 16 + x + xx] for y in range(8) for x in range(8)]
+
+    halfwords = []
+    decoded = []
+    for block in [chroma(cr), chroma(cb)] + [luma_block(i) for i in range(4)]:
+        words, coefficients = encode_block(block)
+        halfwords += words
+        decoded.append(idct(coefficients))
+    if len(halfwords) % 2:
+        halfwords.append(0xFE00)
+
+    cr, cb = decoded[0], decoded[1]
+    rgb = bytearray(16 * 16 * 3)
+    for i in range(4):
+        xx, yy = (i & 1) * 8, (i >> 1) * 8
+        for y in range(8):
+            for x in range(8):
+                lum = decoded[2 + i][y * 8 + x]
+                chroma_index = (x + xx) // 2 + (y + yy) // 2 * 8
+                u, v = cb[chroma_index], cr[chroma_index]
+                color = (lum + 1.402 * v, lum - 0.3437 * u - 0.7143 * v, lum + 1.772 * u)
+                offset = ((y + yy) * 16 + x + xx) * 3
+                for j, value in enumerate(color):
+                    rgb[offset + j] = max(-128, min(127, round(value))) + 128
+    return struct.pack(f"<{len(halfwords)}H", *halfwords), bytes(rgb)
+
+
+if __name__ == "__main__":
+    stream, rgb = macroblock()
+    with open("tables.bin", "wb") as f:
+        f.write(QUANT + QUANT + struct.pack("<64h", *SCALE))
+    with open("macroblock.bin", "wb") as f:
+        f.write(stream)
+    with open("macroblock.rgb", "wb") as f:
+        f.write(rgb)